@@ -0,0 +1,229 @@
+//! Allocator benchmarks: system allocator vs the `pool-alloc` pool
+//!
+//! Replays the allocation pattern of circuit builds and of a bulk transfer
+//! against each allocator, through a wrapper that counts the bytes each one
+//! holds from the underlying system allocator. Run with
+//!
+//! ```text
+//! cargo bench --bench allocator
+//! ```
+//!
+//! This runs natively, so timings only compare the two allocators with
+//! each other; the footprint numbers carry over to wasm32.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tor_wasm::allocator::{PoolAllocator, PoolCounters};
+
+const CELL: usize = 514;
+const CELL_PAYLOAD: usize = 509;
+const RELAY_DATA: usize = 498;
+
+/// Bytes held from `System`, current and peak
+struct Footprint {
+    held: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Footprint {
+    const fn new() -> Self {
+        Self {
+            held: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// System allocator that records its footprint
+struct Measured(&'static Footprint);
+
+unsafe impl GlobalAlloc for Measured {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let held = self.0.held.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        self.0.peak.fetch_max(held, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.held.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// One live allocation
+struct Block {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+fn alloc(a: &dyn GlobalAlloc, size: usize) -> Block {
+    let layout = Layout::from_size_align(size, 1).unwrap();
+    let ptr = unsafe { a.alloc(layout) };
+    assert!(!ptr.is_null());
+    // Touch the memory like real code would
+    unsafe { ptr.write_bytes(0x5a, size) };
+    Block { ptr, layout }
+}
+
+fn free(a: &dyn GlobalAlloc, block: Block) {
+    unsafe { a.dealloc(block.ptr, block.layout) };
+}
+
+/// Growable buffer like a response `Vec<u8>`
+struct Buffer {
+    block: Block,
+    len: usize,
+}
+
+impl Buffer {
+    fn new(a: &dyn GlobalAlloc) -> Self {
+        Self {
+            block: alloc(a, 64),
+            len: 0,
+        }
+    }
+
+    fn extend(&mut self, a: &dyn GlobalAlloc, n: usize) {
+        if self.len + n > self.block.layout.size() {
+            let new_size = (self.len + n).max(self.block.layout.size() * 2);
+            let ptr = unsafe { a.realloc(self.block.ptr, self.block.layout, new_size) };
+            assert!(!ptr.is_null());
+            self.block = Block {
+                ptr,
+                layout: Layout::from_size_align(new_size, 1).unwrap(),
+            };
+        }
+        self.len += n;
+    }
+}
+
+/// Build `circuits` three-hop circuits, keeping the last 8 alive
+fn circuit_builds(a: &dyn GlobalAlloc, circuits: usize) {
+    let mut alive = Vec::new();
+    for _ in 0..circuits {
+        let mut circuit = vec![alloc(a, 1024)];
+        for _hop in 0..3 {
+            // Handshake: onion skin, CREATE2/EXTEND2 cell, reply, key material
+            let temps = [
+                alloc(a, 84),
+                alloc(a, CELL_PAYLOAD),
+                alloc(a, CELL),
+                alloc(a, CELL),
+                alloc(a, 72),
+            ];
+            // Per-hop state: ciphers and running digests
+            circuit.extend([alloc(a, 176), alloc(a, 176), alloc(a, 112), alloc(a, 112)]);
+            for t in temps {
+                free(a, t);
+            }
+        }
+        alive.push(circuit);
+        if alive.len() > 8 {
+            for block in alive.remove(0) {
+                free(a, block);
+            }
+        }
+    }
+    for block in alive.into_iter().flatten() {
+        free(a, block);
+    }
+}
+
+/// Receive `bytes` as RELAY_DATA cells into one response buffer, then
+/// allocate a response-sized buffer after the transfer
+fn bulk_transfer(a: &dyn GlobalAlloc, bytes: usize) {
+    let mut response = Buffer::new(a);
+    let mut received = 0;
+    while received < bytes {
+        let raw = alloc(a, CELL);
+        let payload = alloc(a, CELL_PAYLOAD);
+        let data = alloc(a, RELAY_DATA);
+        response.extend(a, RELAY_DATA);
+        free(a, raw);
+        free(a, payload);
+        free(a, data);
+        received += RELAY_DATA;
+    }
+    free(a, response.block);
+
+    // The next large response: can freed memory be reused for it?
+    let next = alloc(a, bytes);
+    free(a, next);
+}
+
+struct Run {
+    name: &'static str,
+    workload: &'static str,
+    millis: f64,
+    peak: usize,
+    held_after: usize,
+}
+
+fn measure(
+    name: &'static str,
+    workload: &'static str,
+    make: impl Fn(&'static Measured) -> Box<dyn GlobalAlloc>,
+    run: impl Fn(&dyn GlobalAlloc),
+) -> Run {
+    let footprint: &'static Footprint = Box::leak(Box::new(Footprint::new()));
+    let measured: &'static Measured = Box::leak(Box::new(Measured(footprint)));
+    let allocator = make(measured);
+    let start = Instant::now();
+    run(allocator.as_ref());
+    Run {
+        name,
+        workload,
+        millis: start.elapsed().as_secs_f64() * 1000.0,
+        peak: footprint.peak.load(Ordering::Relaxed),
+        held_after: footprint.held.load(Ordering::Relaxed),
+    }
+}
+
+/// Named allocation trace
+type Workload = (&'static str, fn(&dyn GlobalAlloc));
+
+/// `&'static Measured` as a plain allocator
+struct Forward(&'static Measured);
+
+unsafe impl GlobalAlloc for Forward {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
+
+fn main() {
+    let workloads: [Workload; 2] = [
+        ("circuit_build x2000", |a| circuit_builds(a, 2_000)),
+        ("bulk_transfer 16 MiB", |a| bulk_transfer(a, 16 << 20)),
+    ];
+
+    let mut runs = Vec::new();
+    for (workload, run) in workloads {
+        runs.push(measure("system", workload, |m| Box::new(Forward(m)), run));
+        runs.push(measure(
+            "pool",
+            workload,
+            |m| {
+                let counters: &'static PoolCounters = Box::leak(Box::new(PoolCounters::new()));
+                Box::new(PoolAllocator::new(Forward(m), counters))
+            },
+            run,
+        ));
+    }
+
+    println!(
+        "{:<8} {:<22} {:>10} {:>14} {:>14}",
+        "alloc", "workload", "ms", "peak bytes", "held after"
+    );
+    for r in runs {
+        println!(
+            "{:<8} {:<22} {:>10.2} {:>14} {:>14}",
+            r.name, r.workload, r.millis, r.peak, r.held_after
+        );
+    }
+}
@@ -0,0 +1,418 @@
+/**
+ * Bridge Framing Protocol
+ *
+ * Server side of the framed bridge protocol (client side:
+ * src/transport/framing.rs). A WebSocket opened with `?framing=1` carries
+ * records instead of one relay's raw bytes, so a single socket can hold
+ * several OR connections and either side can add padding.
+ *
+ * Record: type u8 | channel u16 BE | length u16 BE | payload
+ *
+ *   1 HELLO    client: supported versions; bridge: the one it picked (channel 0)
+ *   2 OPEN     target, `addr=HOST:PORT` or `dest=<blinded blob>`
+ *   3 DATA     bytes for the channel's OR connection
+ *   4 CLOSE    optional UTF-8 reason; either side may send it
+ *   5 PADDING  ignored (channel 0)
+ *   6 SESSION  bridge: resume token (16 bytes) | grace seconds u16 (channel 0)
+ *   7 RESUME   resume token | (channel u16, bytes received u64)* (channel 0)
+ *   8 ACK      bytes of the channel received so far, u64
+ *
+ * Version 2 sessions survive the client's socket dropping. After HELLO the
+ * bridge sends SESSION; if the socket then closes, the relay connections
+ * are paused and held for GRACE_SECS. A new socket whose first record
+ * after HELLO is RESUME with the token takes them over (its own fresh
+ * token is dropped): the bridge answers with a RESUME
+ * of its own counts (or CLOSE on channel 0 if the token is unknown or
+ * expired) and both sides replay the DATA the other missed. Each side ACKs
+ * every ACK_INTERVAL bytes so the other can drop what it keeps for replay.
+ *
+ * Usage:
+ *   const { FramedSession } = require('./framing');
+ *   new FramedSession(ws, id, { openTarget: (channel, target) => socket });
+ */
+
+const crypto = require('crypto');
+
+const SUPPORTED_VERSIONS = [1, 2];
+const RESUMABLE_VERSION = 2;
+const HEADER_LEN = 5;
+const MAX_PAYLOAD = 0xffff;
+const CONTROL_CHANNEL = 0;
+const TOKEN_LEN = 16;
+const GRACE_SECS = parseInt(process.env.FRAMING_RESUME_GRACE_SECS || '30');
+const ACK_INTERVAL = 32 * 1024;
+
+const RECORD = {
+  HELLO: 1,
+  OPEN: 2,
+  DATA: 3,
+  CLOSE: 4,
+  PADDING: 5,
+  SESSION: 6,
+  RESUME: 7,
+  ACK: 8,
+};
+
+/** Sessions whose socket dropped, by hex token, waiting to be resumed */
+const suspended = new Map();
+
+/** Encode one record; DATA longer than MAX_PAYLOAD is split */
+function encodeRecord(type, channel, payload = Buffer.alloc(0)) {
+  const parts = [];
+  let offset = 0;
+  do {
+    const chunk = payload.subarray(offset, offset + MAX_PAYLOAD);
+    const header = Buffer.alloc(HEADER_LEN);
+    header.writeUInt8(type, 0);
+    header.writeUInt16BE(channel, 1);
+    header.writeUInt16BE(chunk.length, 3);
+    parts.push(header, chunk);
+    offset += chunk.length;
+  } while (type === RECORD.DATA && offset < payload.length);
+  return Buffer.concat(parts);
+}
+
+/** Reassembles records from arbitrarily split WebSocket messages */
+class RecordDecoder {
+  constructor() {
+    this.buf = Buffer.alloc(0);
+  }
+
+  push(data) {
+    this.buf = Buffer.concat([this.buf, data]);
+  }
+
+  /** Next complete record, or null; throws on an unknown record type */
+  next() {
+    if (this.buf.length < HEADER_LEN) return null;
+    const type = this.buf.readUInt8(0);
+    if (!Object.values(RECORD).includes(type)) {
+      throw new Error(`Unknown framing record type ${type}`);
+    }
+    const channel = this.buf.readUInt16BE(1);
+    const length = this.buf.readUInt16BE(3);
+    if (this.buf.length < HEADER_LEN + length) return null;
+    const payload = this.buf.subarray(HEADER_LEN, HEADER_LEN + length);
+    this.buf = this.buf.subarray(HEADER_LEN + length);
+    return { type, channel, payload };
+  }
+}
+
+/** Sent channel bytes the client hasn't acknowledged, kept for replay */
+class SendLog {
+  constructor() {
+    this.start = 0;
+    this.chunks = [];
+    this.held = 0;
+  }
+
+  push(data) {
+    this.chunks.push(data);
+    this.held += data.length;
+  }
+
+  sent() {
+    return this.start + this.held;
+  }
+
+  /** The client has the first `offset` bytes */
+  ack(offset) {
+    if (offset > this.sent()) {
+      throw new Error(`Client acknowledged ${offset} bytes of ${this.sent()} sent`);
+    }
+    while (this.start < offset) {
+      const chunk = this.chunks[0];
+      const drop = Math.min(chunk.length, offset - this.start);
+      if (drop === chunk.length) this.chunks.shift();
+      else this.chunks[0] = chunk.subarray(drop);
+      this.start += drop;
+      this.held -= drop;
+    }
+  }
+
+  /** Everything after the first `from` bytes */
+  replayFrom(from) {
+    if (from < this.start) {
+      throw new Error(`Cannot replay from byte ${from}, the log starts at ${this.start}`);
+    }
+    this.ack(from);
+    return Buffer.concat(this.chunks);
+  }
+}
+
+/** RESUME payload: token, then (channel, received) pairs */
+function encodeResume(token, entries) {
+  const payload = Buffer.alloc(TOKEN_LEN + entries.length * 10);
+  token.copy(payload, 0);
+  entries.forEach(([channel, received], i) => {
+    payload.writeUInt16BE(channel, TOKEN_LEN + i * 10);
+    payload.writeBigUInt64BE(BigInt(received), TOKEN_LEN + i * 10 + 2);
+  });
+  return payload;
+}
+
+function decodeResume(payload) {
+  if (payload.length < TOKEN_LEN || (payload.length - TOKEN_LEN) % 10 !== 0) {
+    throw new Error('Malformed RESUME');
+  }
+  const entries = [];
+  for (let at = TOKEN_LEN; at < payload.length; at += 10) {
+    entries.push([payload.readUInt16BE(at), Number(payload.readBigUInt64BE(at + 2))]);
+  }
+  return { token: payload.subarray(0, TOKEN_LEN), entries };
+}
+
+function encodeOffset(offset) {
+  const payload = Buffer.alloc(8);
+  payload.writeBigUInt64BE(BigInt(offset));
+  return payload;
+}
+
+/** Highest version both sides speak, or null */
+function pickVersion(offered) {
+  const common = [...offered].filter(v => SUPPORTED_VERSIONS.includes(v));
+  return common.length ? Math.max(...common) : null;
+}
+
+/**
+ * One framed client WebSocket and its channels.
+ *
+ * `openTarget(channel, target)` returns a duplex socket for an OPEN target
+ * (or throws to refuse it); the session relays DATA both ways and sends
+ * CLOSE when the socket ends.
+ */
+class FramedSession {
+  constructor(ws, id, { openTarget, monitor = null }) {
+    this.ws = ws;
+    this.id = id;
+    this.openTarget = openTarget;
+    this.monitor = monitor;
+    this.decoder = new RecordDecoder();
+    this.version = null;
+    this.token = null;
+    this.started = false;
+    this.graceTimer = null;
+    // channel → { socket, log, received, acked }
+    this.channels = new Map();
+
+    ws.on('message', (data) => this.onMessage(Buffer.from(data)));
+    ws.on('close', () => this.onSocketClosed());
+  }
+
+  onMessage(data) {
+    if (this.monitor) this.monitor.recordFrame(this.id, data.length, 'up');
+    this.decoder.push(data);
+    try {
+      let record;
+      while ((record = this.decoder.next())) {
+        this.onRecord(record);
+      }
+    } catch (err) {
+      console.log(`[${this.id}] ❌ Framing error: ${err.message}`);
+      this.ws.close(1002, err.message);
+    }
+  }
+
+  onRecord({ type, channel, payload }) {
+    if (this.version === null) {
+      if (type !== RECORD.HELLO) throw new Error('Expected HELLO');
+      const version = pickVersion(payload);
+      if (version === null) {
+        throw new Error(`No common framing version (client offered ${[...payload]})`);
+      }
+      this.version = version;
+      this.send(RECORD.HELLO, CONTROL_CHANNEL, Buffer.from([version]));
+      if (version >= RESUMABLE_VERSION) this.issueToken();
+      console.log(`[${this.id}] 🧵 Framing version ${version}`);
+      return;
+    }
+
+    // A resuming client sends RESUME straight after HELLO
+    if (type === RECORD.RESUME && this.token && !this.started) {
+      this.started = true;
+      return this.resume(payload);
+    }
+    this.started = true;
+
+    switch (type) {
+      case RECORD.OPEN:
+        return this.open(channel, payload.toString('utf8'));
+      case RECORD.DATA: {
+        const entry = this.channels.get(channel);
+        if (entry) {
+          entry.socket.write(payload);
+          entry.received += payload.length;
+          if (this.token && entry.received - entry.acked >= ACK_INTERVAL) {
+            entry.acked = entry.received;
+            this.send(RECORD.ACK, channel, encodeOffset(entry.received));
+          }
+        }
+        return;
+      }
+      case RECORD.CLOSE: {
+        const entry = this.channels.get(channel);
+        if (entry) {
+          this.channels.delete(channel);
+          entry.socket.destroy();
+          console.log(`[${this.id}] 🔌 Channel ${channel} closed by client`);
+        }
+        return;
+      }
+      case RECORD.ACK: {
+        const entry = this.channels.get(channel);
+        if (payload.length !== 8) throw new Error('Malformed ACK');
+        if (entry) entry.log.ack(Number(payload.readBigUInt64BE(0)));
+        return;
+      }
+      case RECORD.PADDING:
+        return;
+      default:
+        throw new Error(`Unexpected record type ${type}`);
+    }
+  }
+
+  /** Name the session so its client can resume it after a drop */
+  issueToken() {
+    this.token = crypto.randomBytes(TOKEN_LEN);
+    const payload = Buffer.alloc(TOKEN_LEN + 2);
+    this.token.copy(payload, 0);
+    payload.writeUInt16BE(GRACE_SECS, TOKEN_LEN);
+    this.send(RECORD.SESSION, CONTROL_CHANNEL, payload);
+  }
+
+  /** Take over the channels of the suspended session named in RESUME */
+  resume(payload) {
+    const { token, entries } = decodeResume(payload);
+    const old = suspended.get(token.toString('hex'));
+    if (!old) {
+      console.log(`[${this.id}] ❌ Resume refused: unknown or expired token`);
+      this.send(RECORD.CLOSE, CONTROL_CHANNEL, Buffer.from('Unknown or expired resume token'));
+      this.ws.close(1008, 'Unknown or expired resume token');
+      return;
+    }
+    suspended.delete(token.toString('hex'));
+    clearTimeout(old.graceTimer);
+    this.token = old.token;
+
+    // Channels the client no longer lists were closed on its side
+    const clientReceived = new Map(entries);
+    for (const [channel, entry] of old.channels) {
+      if (!clientReceived.has(channel)) entry.socket.destroy();
+    }
+    const replays = [];
+    for (const [channel, received] of clientReceived) {
+      const entry = old.channels.get(channel);
+      if (!entry) continue;
+      replays.push([channel, entry.log.replayFrom(received)]);
+      this.adopt(channel, entry);
+    }
+    old.channels.clear();
+
+    this.send(
+      RECORD.RESUME,
+      CONTROL_CHANNEL,
+      encodeResume(this.token, [...this.channels].map(([channel, e]) => [channel, e.received]))
+    );
+    for (const [channel, missing] of replays) {
+      if (missing.length) this.send(RECORD.DATA, channel, missing);
+      const entry = this.channels.get(channel);
+      if (entry) {
+        entry.acked = entry.received;
+        entry.socket.resume();
+      }
+    }
+    console.log(`[${this.id}] 🧵 Resumed session [${old.id}] with ${this.channels.size} channels`);
+  }
+
+  open(channel, target) {
+    if (channel === CONTROL_CHANNEL || this.channels.has(channel)) {
+      throw new Error(`Bad OPEN for channel ${channel}`);
+    }
+    let socket;
+    try {
+      socket = this.openTarget(channel, target);
+    } catch (err) {
+      console.log(`[${this.id}] ❌ Channel ${channel} refused: ${err.message}`);
+      this.send(RECORD.CLOSE, channel, Buffer.from(err.message));
+      return;
+    }
+    this.adopt(channel, { socket, log: new SendLog(), received: 0, acked: 0 });
+  }
+
+  /** Relay a channel's socket through this session */
+  adopt(channel, entry) {
+    const { socket } = entry;
+    this.channels.set(channel, entry);
+    // Listeners of a session this one resumed go quiet once it lets go
+    socket.on('data', (data) => {
+      if (this.channels.get(channel) !== entry) return;
+      if (this.token) entry.log.push(data);
+      this.send(RECORD.DATA, channel, data);
+    });
+    socket.on('error', (err) => {
+      if (this.channels.get(channel) !== entry) return;
+      console.log(`[${this.id}] ❌ Channel ${channel} error: ${err.message}`);
+      this.endChannel(channel, entry, err.message);
+    });
+    socket.on('close', () => this.endChannel(channel, entry, ''));
+  }
+
+  /** The target side of a channel ended: tell the client once */
+  endChannel(channel, entry, reason) {
+    if (this.channels.get(channel) !== entry) return;
+    this.channels.delete(channel);
+    this.send(RECORD.CLOSE, channel, Buffer.from(reason));
+    console.log(`[${this.id}] 🔌 Channel ${channel} closed${reason ? ': ' + reason : ''}`);
+  }
+
+  /** Send `length` bytes of padding to the client */
+  sendPadding(length) {
+    this.send(RECORD.PADDING, CONTROL_CHANNEL, Buffer.alloc(Math.min(length, MAX_PAYLOAD)));
+  }
+
+  send(type, channel, payload) {
+    if (this.ws.readyState !== this.ws.OPEN) return;
+    const frame = encodeRecord(type, channel, payload);
+    if (this.monitor) this.monitor.recordFrame(this.id, frame.length, 'down');
+    this.ws.send(frame);
+  }
+
+  /** Hold a resumable session's channels for the grace window */
+  onSocketClosed() {
+    if (!this.token || this.channels.size === 0) {
+      this.closeAll();
+      return;
+    }
+    for (const { socket } of this.channels.values()) {
+      socket.pause();
+    }
+    const key = this.token.toString('hex');
+    suspended.set(key, this);
+    this.graceTimer = setTimeout(() => {
+      if (suspended.get(key) !== this) return;
+      suspended.delete(key);
+      console.log(`[${this.id}] ⌛ Session not resumed within ${GRACE_SECS}s`);
+      this.closeAll();
+    }, GRACE_SECS * 1000);
+    console.log(`[${this.id}] ⏸️  Holding ${this.channels.size} channels for ${GRACE_SECS}s`);
+  }
+
+  closeAll() {
+    for (const { socket } of this.channels.values()) {
+      socket.destroy();
+    }
+    this.channels.clear();
+  }
+}
+
+module.exports = {
+  FramedSession,
+  RecordDecoder,
+  SendLog,
+  encodeRecord,
+  encodeResume,
+  decodeResume,
+  pickVersion,
+  RECORD,
+  SUPPORTED_VERSIONS,
+};
@@ -8,6 +8,9 @@
  * 
  * Usage:
  *   node server-collector.js [--port PORT]
+ *
+ * Set TOR_DIRPORT=host:port to serve the consensus of a local test network
+ * (e.g. chutney) from one of its authorities instead.
  */
 
 const WebSocket = require('ws');
@@ -180,6 +183,27 @@ async function fetchDescriptors(filename) {
   });
 }
 
+/**
+ * Fetch a directory document from a directory authority's DirPort
+ * (TOR_DIRPORT=host:port, used for chutney-style local test networks)
+ */
+async function fetchFromDirPort(dirPort, path) {
+  return new Promise((resolve, reject) => {
+    console.log(`📡 Fetching http://${dirPort}${path}...`);
+    http.get(`http://${dirPort}${path}`, (res) => {
+      if (res.statusCode !== 200) {
+        reject(new Error(`DirPort returned HTTP ${res.statusCode} for ${path}`));
+        res.resume();
+        return;
+      }
+      let data = '';
+      res.on('data', (chunk) => { data += chunk; });
+      res.on('end', () => resolve(data));
+      res.on('error', reject);
+    }).on('error', reject);
+  });
+}
+
 /**
  * Parse consensus to extract relay information
  */
@@ -282,32 +306,41 @@ function parseDescriptors(descriptorData) {
  * Fetch and cache consensus + descriptors from Tor Collector
  */
 async function fetchAndCacheConsensus() {
-  console.log('\n🔄 Fetching fresh Tor consensus from Collector...\n');
+  const dirPort = process.env.TOR_DIRPORT;
+  console.log(`\n🔄 Fetching fresh Tor consensus from ${dirPort ? `DirPort ${dirPort}` : 'Collector'}...\n`);
   
   try {
-    // Fetch latest consensus
-    const consensusFilename = await fetchLatestConsensusFilename();
-    const consensusData = await fetchConsensus(consensusFilename);
-    
-    // Parse consensus to get relay list
-    const relays = parseConsensus(consensusData);
-    
-    // Fetch MULTIPLE server descriptor files to get more ntor keys
-    const descriptorFilenames = await fetchLatestDescriptorsFilenames(15);
-    console.log(`📥 Fetching ${descriptorFilenames.length} descriptor files...`);
-    
-    // Merge all ntor keys from all descriptor files
+    let consensusData;
     const ntorKeys = {};
-    for (const filename of descriptorFilenames) {
-      try {
-        const descriptorData = await fetchDescriptors(filename);
-        const keys = parseDescriptors(descriptorData);
-        Object.assign(ntorKeys, keys);
-        console.log(`   ✓ ${filename}: ${Object.keys(keys).length} keys`);
-      } catch (err) {
-        console.warn(`   ⚠️ ${filename}: ${err.message}`);
+
+    if (dirPort) {
+      // Local test network: one authority has everything
+      consensusData = await fetchFromDirPort(dirPort, '/tor/status-vote/current/consensus');
+      Object.assign(ntorKeys, parseDescriptors(await fetchFromDirPort(dirPort, '/tor/server/all')));
+    } else {
+      // Fetch latest consensus
+      const consensusFilename = await fetchLatestConsensusFilename();
+      consensusData = await fetchConsensus(consensusFilename);
+
+      // Fetch MULTIPLE server descriptor files to get more ntor keys
+      const descriptorFilenames = await fetchLatestDescriptorsFilenames(15);
+      console.log(`📥 Fetching ${descriptorFilenames.length} descriptor files...`);
+
+      // Merge all ntor keys from all descriptor files
+      for (const filename of descriptorFilenames) {
+        try {
+          const descriptorData = await fetchDescriptors(filename);
+          const keys = parseDescriptors(descriptorData);
+          Object.assign(ntorKeys, keys);
+          console.log(`   ✓ ${filename}: ${Object.keys(keys).length} keys`);
+        } catch (err) {
+          console.warn(`   ⚠️ ${filename}: ${err.message}`);
+        }
       }
     }
+
+    // Parse consensus to get relay list
+    const relays = parseConsensus(consensusData);
     
     console.log(`\n🔑 Total unique ntor keys collected: ${Object.keys(ntorKeys).length}`);
     
@@ -347,7 +380,7 @@ async function fetchAndCacheConsensus() {
       consensus,
       timestamp: Date.now(),
       cacheAge: 0,
-      source: 'collector',
+      source: dirPort ? 'dirport' : 'collector',
     };
     consensusCacheTime = Date.now();
     
@@ -601,6 +634,13 @@ server.on('request', (req, res) => {
       });
     }
 
+    // Local test network: plain JSON, nothing to hide from a censor
+    if (process.env.TOR_DIRPORT) {
+      res.writeHead(200, { 'Content-Type': 'application/json' });
+      res.end(JSON.stringify(consensusCache));
+      return;
+    }
+
     // Obfuscated response — compress + base64
     const zlib = require('zlib');
     const raw = JSON.stringify(consensusCache);
@@ -109,6 +109,7 @@ function convertOnionooToConsensus(onionooData) {
         ntor_onion_key: null, // Onionoo doesn't provide this, will use mock
         bandwidth: r.observed_bandwidth || 0,
         country: r.country || null,
+        as: r.as || null,
       };
     });
   
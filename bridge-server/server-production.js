@@ -22,6 +22,7 @@ const { serveCoverSite, setStandardHeaders } = require('./cover-site');
 const { handleHealth, startManagementServer } = require('./health-auth');
 const logger = require('./logger');
 const { TrafficMonitor } = require('./traffic-monitor');
+const { FramedSession } = require('./framing');
 
 // Static file root (parent directory of bridge-server/)
 const STATIC_ROOT = path.resolve(__dirname, '..');
@@ -544,6 +545,29 @@ server.on('request', (req, res) => {
   });
 });
 
+/**
+ * Open the TLS connection for a framed channel's `addr=HOST:PORT` target.
+ * Blinded `dest=` targets need a Bridge A and are refused here.
+ */
+function openFramedTarget(id) {
+  return (channel, target) => {
+    const match = target.match(/^addr=([^:]+):(\d+)$/);
+    if (!match) {
+      throw new Error(`Unsupported target (expected addr=HOST:PORT)`);
+    }
+    const [, host, portStr] = match;
+    console.log(`[${id}] 🎯 Channel ${channel} → ${host}:${portStr}`);
+    // tls.connect buffers writes until the handshake completes
+    const socket = tls.connect({
+      host,
+      port: parseInt(portStr),
+      rejectUnauthorized: false, // Tor relays use self-signed certificates
+    });
+    socket.setTimeout(300000, () => socket.destroy(new Error('Timeout')));
+    return socket;
+  };
+}
+
 wss.on('connection', (ws, req) => {
   const id = ++connectionId;
   const clientIp = req.socket.remoteAddress;
@@ -553,6 +577,15 @@ wss.on('connection', (ws, req) => {
 
   // Parse target address from query string
   const params = url.parse(req.url, true).query;
+
+  // Framed session: relay addresses arrive in OPEN records, many per socket
+  if (params.framing) {
+    console.log(`[${id}] 🧵 Framed session`);
+    new FramedSession(ws, id, { openTarget: openFramedTarget(id), monitor: trafficMonitor });
+    ws.on('close', () => trafficMonitor.closeConnection(id));
+    return;
+  }
+
   const targetAddr = params.addr;
 
   if (!targetAddr) {
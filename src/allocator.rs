@@ -0,0 +1,292 @@
+//! Allocator selection
+//!
+//! By default the crate uses the system allocator (dlmalloc on wasm32).
+//! The `pool-alloc` feature swaps in [`PoolAllocator`], which serves small
+//! allocations (up to one cell, 514 bytes, rounded to 528) from per-size
+//! free lists carved out of 64 KiB slabs, and passes everything larger to
+//! the system allocator.
+//!
+//! `benches/allocator.rs` replays the allocation pattern of circuit builds
+//! and a bulk transfer against both and reports the time taken and the
+//! bytes each holds from the system (`cargo bench --bench allocator`).
+//! Response buffers, not cells, dominate the peak footprint, and the pool
+//! never returns its slabs, so dlmalloc stays the default. `wee_alloc` is
+//! no longer maintained and is not offered.
+//!
+//! With `memory-tracking`, the tracking allocator wraps whichever one is
+//! selected here.
+
+use serde::Serialize;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::UnsafeCell;
+use std::ptr::{self, null_mut};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Block sizes served from the pool; the largest fits a 514-byte cell
+pub const POOL_SIZE_CLASSES: [usize; 5] = [32, 64, 128, 256, 528];
+
+/// Largest alignment served from the pool
+pub const POOL_MAX_ALIGN: usize = 16;
+
+/// Bytes requested from the inner allocator per slab
+pub const SLAB_BYTES: usize = 64 * 1024;
+
+/// Name of the selected allocator, for metrics
+pub const ALLOCATOR_NAME: &str = if cfg!(feature = "pool-alloc") {
+    "pool"
+} else {
+    "system"
+};
+
+/// Pool counters
+#[derive(Debug)]
+pub struct PoolCounters {
+    slabs: AtomicUsize,
+    reused: AtomicUsize,
+    carved: AtomicUsize,
+    passed_through: AtomicUsize,
+}
+
+impl Default for PoolCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PoolCounters {
+    pub const fn new() -> Self {
+        Self {
+            slabs: AtomicUsize::new(0),
+            reused: AtomicUsize::new(0),
+            carved: AtomicUsize::new(0),
+            passed_through: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            slab_bytes: self.slabs.load(Ordering::Relaxed) * SLAB_BYTES,
+            reused_blocks: self.reused.load(Ordering::Relaxed),
+            carved_blocks: self.carved.load(Ordering::Relaxed),
+            passed_through: self.passed_through.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Pool allocator statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    /// Memory held in slabs (never returned)
+    pub slab_bytes: usize,
+    /// Allocations served from a free list
+    pub reused_blocks: usize,
+    /// Allocations carved fresh from a slab
+    pub carved_blocks: usize,
+    /// Allocations too large or too aligned for the pool
+    pub passed_through: usize,
+}
+
+struct PoolState {
+    /// Free list head per size class; a free block stores the next pointer
+    free: [*mut u8; POOL_SIZE_CLASSES.len()],
+    /// Unused remainder of the current slab
+    bump: *mut u8,
+    bump_end: *mut u8,
+}
+
+/// Free-list pool for cell-sized objects in front of another allocator
+pub struct PoolAllocator<A> {
+    inner: A,
+    counters: &'static PoolCounters,
+    locked: AtomicBool,
+    state: UnsafeCell<PoolState>,
+}
+
+// SAFETY: `state` is only accessed while `locked` is held
+unsafe impl<A: Sync> Sync for PoolAllocator<A> {}
+
+impl<A> PoolAllocator<A> {
+    pub const fn new(inner: A, counters: &'static PoolCounters) -> Self {
+        Self {
+            inner,
+            counters,
+            locked: AtomicBool::new(false),
+            state: UnsafeCell::new(PoolState {
+                free: [null_mut(); POOL_SIZE_CLASSES.len()],
+                bump: null_mut(),
+                bump_end: null_mut(),
+            }),
+        }
+    }
+
+    /// Size class index for `layout`, if the pool serves it
+    fn class_of(layout: Layout) -> Option<usize> {
+        if layout.align() > POOL_MAX_ALIGN {
+            return None;
+        }
+        POOL_SIZE_CLASSES.iter().position(|&c| layout.size() <= c)
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut PoolState) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+        // SAFETY: the lock is held
+        let result = f(unsafe { &mut *self.state.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for PoolAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = Self::class_of(layout) else {
+            self.counters.passed_through.fetch_add(1, Ordering::Relaxed);
+            return self.inner.alloc(layout);
+        };
+        let size = POOL_SIZE_CLASSES[class];
+        self.with_state(|state| {
+            let head = state.free[class];
+            if !head.is_null() {
+                state.free[class] = head.cast::<*mut u8>().read();
+                self.counters.reused.fetch_add(1, Ordering::Relaxed);
+                return head;
+            }
+            if (state.bump_end as usize) - (state.bump as usize) < size {
+                let slab = self.inner.alloc(Layout::from_size_align_unchecked(
+                    SLAB_BYTES,
+                    POOL_MAX_ALIGN,
+                ));
+                if slab.is_null() {
+                    return slab;
+                }
+                self.counters.slabs.fetch_add(1, Ordering::Relaxed);
+                state.bump = slab;
+                state.bump_end = slab.add(SLAB_BYTES);
+            }
+            let block = state.bump;
+            state.bump = block.add(size);
+            self.counters.carved.fetch_add(1, Ordering::Relaxed);
+            block
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(class) = Self::class_of(layout) else {
+            self.inner.dealloc(ptr, layout);
+            return;
+        };
+        self.with_state(|state| {
+            ptr.cast::<*mut u8>().write(state.free[class]);
+            state.free[class] = ptr;
+        });
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (Self::class_of(layout), Self::class_of(new_layout)) {
+            // Large to large: let the inner allocator grow in place
+            (None, None) => self.inner.realloc(ptr, layout, new_size),
+            (Some(old), Some(new)) if old == new => ptr,
+            _ => {
+                let new_ptr = self.alloc(new_layout);
+                if !new_ptr.is_null() {
+                    ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+                new_ptr
+            }
+        }
+    }
+}
+
+/// Counters of the global pool
+pub static POOL_COUNTERS: PoolCounters = PoolCounters::new();
+
+/// The allocator selected by features
+#[cfg(feature = "pool-alloc")]
+pub type BaseAllocator = PoolAllocator<System>;
+#[cfg(not(feature = "pool-alloc"))]
+pub type BaseAllocator = System;
+
+/// A new instance of the selected allocator
+#[cfg(feature = "pool-alloc")]
+pub const fn base_allocator() -> BaseAllocator {
+    PoolAllocator::new(System, &POOL_COUNTERS)
+}
+#[cfg(not(feature = "pool-alloc"))]
+pub const fn base_allocator() -> BaseAllocator {
+    System
+}
+
+#[cfg(all(feature = "pool-alloc", not(feature = "memory-tracking")))]
+#[global_allocator]
+static ALLOCATOR: BaseAllocator = base_allocator();
+
+/// Pool statistics, if the pool allocator is selected
+pub fn pool_stats() -> Option<PoolStats> {
+    cfg!(feature = "pool-alloc").then(|| POOL_COUNTERS.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_reuses_blocks() {
+        static COUNTERS: PoolCounters = PoolCounters::new();
+        let pool = PoolAllocator::new(System, &COUNTERS);
+        let cell = Layout::from_size_align(514, 1).unwrap();
+        let large = Layout::from_size_align(4096, 8).unwrap();
+
+        unsafe {
+            let a = pool.alloc(cell);
+            let b = pool.alloc(cell);
+            assert_eq!(b as usize - a as usize, 528);
+            assert_eq!(a as usize % POOL_MAX_ALIGN, 0);
+            a.write_bytes(0xAA, 514);
+            pool.dealloc(a, cell);
+
+            // The freed block comes back for the next cell
+            let c = pool.alloc(cell);
+            assert_eq!(c, a);
+
+            let big = pool.alloc(large);
+            pool.dealloc(big, large);
+            pool.dealloc(b, cell);
+            pool.dealloc(c, cell);
+        }
+
+        let stats = COUNTERS.stats();
+        assert_eq!(stats.slab_bytes, SLAB_BYTES);
+        assert_eq!(stats.carved_blocks, 2);
+        assert_eq!(stats.reused_blocks, 1);
+        assert_eq!(stats.passed_through, 1);
+    }
+
+    #[test]
+    fn test_realloc_across_classes() {
+        static COUNTERS: PoolCounters = PoolCounters::new();
+        let pool = PoolAllocator::new(System, &COUNTERS);
+        let small = Layout::from_size_align(20, 1).unwrap();
+
+        unsafe {
+            let p = pool.alloc(small);
+            p.copy_from(b"cells and more cells".as_ptr(), 20);
+
+            // Same class: no move
+            assert_eq!(pool.realloc(p, small, 30), p);
+
+            // Into another class, then out of the pool entirely
+            let p = pool.realloc(p, Layout::from_size_align(30, 1).unwrap(), 200);
+            let p = pool.realloc(p, Layout::from_size_align(200, 1).unwrap(), 10_000);
+            assert_eq!(std::slice::from_raw_parts(p, 20), b"cells and more cells");
+            pool.dealloc(p, Layout::from_size_align(10_000, 1).unwrap());
+        }
+        assert_eq!(COUNTERS.stats().passed_through, 1);
+    }
+}
@@ -1,89 +1,125 @@
 //! Arti trait implementations for WasmRuntime
 //!
-//! This module implements all tor-rtcompat traits needed for Arti integration
+//! With the `arti` feature, [`WasmRuntime`] implements `tor_rtcompat::Runtime`
+//! so upstream arti crates can drive protocol logic over our transports.
+//! Sleeping and spawning go through the browser event loop, TCP connects
+//! through the bridge, and TLS through the same rustls session our own
+//! channels use.
+//!
+//! Not everything arti expects exists in a browser: there are no listeners,
+//! UDP, Unix sockets or blocking threads, and `SleepProvider::now()` still
+//! reads `std::time::Instant`, which wasm32-unknown-unknown cannot provide.
+//! Only the arti code paths that avoid those are usable here.
 
+use async_trait::async_trait;
+use futures::future::{self, Ready};
+use futures::Future;
 use std::io::{self, Result as IoResult};
 use std::net::SocketAddr;
-use std::pin::Pin;
-use futures::Future;
-use futures::io::{AsyncRead, AsyncWrite};
-use async_trait::async_trait;
-use tor_rtcompat::{NetStreamProvider, UdpProvider, TlsProvider, StreamOps};
+use std::time::{Duration, SystemTime};
+use tor_general_addr::unix;
+use tor_rtcompat::{
+    Blocking, CoarseInstant, CoarseTimeProvider, NetStreamProvider, RealCoarseTimeProvider,
+    SleepProvider, TlsProvider, UdpProvider,
+};
+
+use crate::runtime::traits_impl::{ArtiStream, ArtiTlsConnector, ArtiTlsStream, SendFuture};
+use crate::runtime::{WasmRuntime, WasmSleep, WasmTcpListener, WasmUdpSocket};
+use crate::transport::{BridgeConfig, TransportStream, WasmMeekStream};
+
+impl SleepProvider for WasmRuntime {
+    type SleepFuture = SendFuture<WasmSleep>;
+
+    fn sleep(&self, duration: Duration) -> Self::SleepFuture {
+        SendFuture(WasmSleep::new(duration))
+    }
+
+    fn wallclock(&self) -> SystemTime {
+        // std's SystemTime::now() panics on wasm32; build it from Date.now()
+        let since_epoch = web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .unwrap_or_default();
+        SystemTime::UNIX_EPOCH + since_epoch
+    }
+}
 
-use crate::runtime::WasmRuntime;
-use crate::transport::{WasmTcpStream, WasmMeekStream, WasmWebTunnelStream, TransportStream};
+impl CoarseTimeProvider for WasmRuntime {
+    fn now_coarse(&self) -> CoarseInstant {
+        // coarsetime reads performance.now() on wasm32
+        RealCoarseTimeProvider::new().now_coarse()
+    }
+}
+
+// No threads in a browser: "blocking" work runs inline on the event loop.
+impl Blocking for WasmRuntime {
+    type ThreadHandle<T: Send + 'static> = Ready<T>;
+
+    fn spawn_blocking<F, T>(&self, f: F) -> Self::ThreadHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        future::ready(f())
+    }
+
+    fn reenter_block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future,
+        F::Output: Send + 'static,
+    {
+        // Only completes futures that don't wait on the browser event loop
+        futures::executor::block_on(future)
+    }
+}
 
-// Implementation 1: NetStreamProvider for regular TCP (SocketAddr)
-// Use ?Send since WASM is single-threaded and WebSocket is !Send
-//
 // Transport fallback chain:
-//   1. WebSocket (fastest, default)
-//   2. WebTunnel (HTTPS disguised as normal website — if configured)
-//   3. meek via CDN (if WebSocket/WebTunnel fail and meek_url is configured)
-//
-// This ensures connectivity even when censors block WebSocket/ECH.
-#[async_trait(?Send)]
+//   1. WebSocket through the bridge (fastest, default)
+//   2. meek via CDN (if WebSocket fails and meek_url is configured)
+#[async_trait]
 impl NetStreamProvider<SocketAddr> for WasmRuntime {
-    type Stream = TransportStream;
-    type Listener = crate::runtime::tcp::WasmTcpListener;
-
-    async fn connect(
-        &self,
-        addr: &SocketAddr,
-    ) -> IoResult<Self::Stream> {
-        // Try WebSocket first (fast path)
-        let config = crate::transport::BridgeConfig::new(self.bridge_url().to_string());
-        let url = config.build_url(addr);
-
-        match WasmTcpStream::connect(&url).await {
-            Ok(stream) => {
-                log::debug!("WebSocket connected to {}", addr);
-                return Ok(TransportStream::WebSocket(stream));
-            }
-            Err(ws_err) => {
-                log::warn!("WebSocket connect failed ({})", ws_err);
-
-                // Try WebTunnel if configured (looks like normal HTTPS)
-                if let (Some(wt_url), Some(wt_path)) = (self.webtunnel_url(), self.webtunnel_path()) {
-                    log::info!("Trying WebTunnel transport...");
-                    match WasmWebTunnelStream::connect(wt_url, wt_path).await {
-                        Ok(wt_stream) => {
-                            log::info!("WebTunnel connected to {}", addr);
-                            return Ok(TransportStream::WebTunnel(wt_stream));
-                        }
-                        Err(wt_err) => {
-                            log::warn!("WebTunnel failed: {}", wt_err);
-                        }
-                    }
+    type Stream = ArtiStream;
+    type Listener = WasmTcpListener;
+
+    async fn connect(&self, addr: &SocketAddr) -> IoResult<Self::Stream> {
+        let bridge_url = self.bridge_url().to_string();
+        let meek_url = self.meek_url().map(str::to_string);
+        let addr = *addr;
+
+        SendFuture(async move {
+            let ws_err = match BridgeConfig::new(bridge_url).connect(&addr).await {
+                Ok(stream) => {
+                    log::debug!("WebSocket connected to {}", addr);
+                    return Ok(ArtiStream(stream));
                 }
+                Err(e) => e,
+            };
+            log::warn!("WebSocket connect failed ({})", ws_err);
 
-                // Try meek fallback (HTTP POST through CDN)
-                if let Some(meek_url) = self.meek_url() {
-                    log::info!("Trying meek transport...");
-                    let target = format!("{}", addr);
-                    match WasmMeekStream::connect(meek_url, &target).await {
-                        Ok(meek_stream) => {
-                            log::info!("meek transport connected to {}", addr);
-                            return Ok(TransportStream::Meek(meek_stream));
-                        }
-                        Err(meek_err) => {
-                            log::error!(
-                                "meek fallback also failed: {}. All transports exhausted.",
-                                meek_err
-                            );
-                        }
+            if let Some(meek_url) = meek_url {
+                log::info!("Trying meek transport...");
+                match WasmMeekStream::connect(&meek_url, &addr.to_string()).await {
+                    Ok(stream) => {
+                        log::info!("meek transport connected to {}", addr);
+                        return Ok(ArtiStream(TransportStream::Meek(stream)));
+                    }
+                    Err(e) => {
+                        log::error!(
+                            "meek fallback also failed: {}. All transports exhausted.",
+                            e
+                        );
                     }
                 }
-
-                Err(io::Error::new(io::ErrorKind::ConnectionRefused, ws_err.to_string()))
             }
-        }
+
+            Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                ws_err.to_string(),
+            ))
+        })
+        .await
     }
-    
-    async fn listen(
-        &self,
-        _addr: &SocketAddr,
-    ) -> IoResult<Self::Listener> {
+
+    async fn listen(&self, _addr: &SocketAddr) -> IoResult<Self::Listener> {
         Err(io::Error::new(
             io::ErrorKind::Unsupported,
             "TCP listeners not supported in WASM (clients only)",
@@ -91,53 +127,67 @@ impl NetStreamProvider<SocketAddr> for WasmRuntime {
     }
 }
 
-// Implementation 2: NetStreamProvider for Unix sockets (stub)
-#[cfg(unix)]
-#[async_trait(?Send)]
-impl NetStreamProvider<std::os::unix::net::SocketAddr> for WasmRuntime {
-    type Stream = crate::runtime::compat::unix::UnixStream;
-    type Listener = crate::runtime::compat::unix::UnixStream; // Dummy
-    
-    async fn connect(
-        &self,
-        _addr: &std::os::unix::net::SocketAddr,
-    ) -> IoResult<Self::Stream> {
-        crate::runtime::compat::unix::UnixStream::unsupported()
+#[async_trait]
+impl NetStreamProvider<unix::SocketAddr> for WasmRuntime {
+    type Stream = ArtiStream;
+    type Listener = WasmTcpListener;
+
+    async fn connect(&self, _addr: &unix::SocketAddr) -> IoResult<Self::Stream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix domain sockets are not supported in WASM/browser environment",
+        ))
     }
-    
-    async fn listen(
-        &self,
-        _addr: &std::os::unix::net::SocketAddr,
-    ) -> IoResult<Self::Listener> {
-        crate::runtime::compat::unix::UnixStream::unsupported()
+
+    async fn listen(&self, _addr: &unix::SocketAddr) -> IoResult<Self::Listener> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix domain sockets are not supported in WASM/browser environment",
+        ))
     }
 }
 
-// Implementation 3: TlsProvider (passthrough - browser handles TLS)
-impl<S> TlsProvider<S> for WasmRuntime
-where
-    S: AsyncRead + AsyncWrite + StreamOps + Send + Sync + Unpin + 'static,
-{
-    type Connector = crate::runtime::compat::WasmTlsConnector;
-    type TlsStream = S; // No wrapping needed, browser does TLS
-    
+impl TlsProvider<ArtiStream> for WasmRuntime {
+    type Connector = ArtiTlsConnector;
+    type TlsStream = ArtiTlsStream;
+
     fn tls_connector(&self) -> Self::Connector {
-        crate::runtime::compat::WasmTlsConnector::new()
+        ArtiTlsConnector
     }
-    
+
     fn supports_keying_material_export(&self) -> bool {
-        // WASM/browser TLS doesn't expose keying material
-        false
+        true
     }
 }
 
-// Implementation 4: UdpProvider (stub - not needed for Tor clients)
-#[async_trait(?Send)]
+#[async_trait]
 impl UdpProvider for WasmRuntime {
-    type UdpSocket = crate::runtime::compat::WasmUdpSocket;
-    
+    type UdpSocket = WasmUdpSocket;
+
     async fn bind(&self, _addr: &SocketAddr) -> IoResult<Self::UdpSocket> {
-        crate::runtime::compat::WasmUdpSocket::unsupported()
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "UDP sockets are not supported in WASM environment",
+        ))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_runtime<R: tor_rtcompat::Runtime>(_runtime: &R) {}
+
+    #[test]
+    fn test_wasm_runtime_is_arti_runtime() {
+        assert_runtime(&WasmRuntime::new());
+    }
+
+    #[test]
+    fn test_blocking_runs_inline() {
+        let runtime = WasmRuntime::new();
+        let handle = runtime.spawn_blocking(|| 6 * 7);
+        assert_eq!(futures::executor::block_on(handle), 42);
+        assert_eq!(runtime.reenter_block_on(async { "done" }), "done");
+    }
+}
@@ -0,0 +1,406 @@
+//! Bandwidth quotas
+//!
+//! Metered mobile users and shared deployments want a ceiling on how much
+//! a client transfers. [`BandwidthQuota`] counts the HTTP bytes of each
+//! request (request plus response, not Tor cell overhead or directory
+//! traffic) against three limits:
+//!
+//! - **session**: since the client was created
+//! - **daily**: per UTC day, kept in `localStorage` across page loads
+//! - **request**: one request's transfer (overridable per fetch)
+//!
+//! Crossing `warn_percent` of the session or daily quota raises a warning
+//! event, and using it up raises an exhausted event; each fires once per
+//! session (or day). What happens then is [`QuotaAction`]: refuse new
+//! requests, pace them through the rate limiter, or only notify.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, TorError};
+
+/// `localStorage` key of the daily counter
+pub const DAILY_USAGE_KEY: &str = "tor_bandwidth_daily";
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// What to do once a session or daily quota is used up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Refuse new requests until the quota resets
+    #[default]
+    Block,
+    /// Keep serving, paced to `throttle_bytes_per_second`
+    Throttle,
+    /// Only raise the event
+    Notify,
+}
+
+/// Bandwidth quota settings; a limit of 0 means unlimited
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthQuotaConfig {
+    /// Bytes per session (default: unlimited)
+    pub session_bytes: u64,
+    /// Bytes per UTC day (default: unlimited)
+    pub daily_bytes: u64,
+    /// Bytes per request (default: unlimited)
+    pub request_bytes: u64,
+    /// Percentage of a quota that raises a warning (default: 80)
+    pub warn_percent: u8,
+    /// What happens once the session or daily quota is used up
+    pub on_exhausted: QuotaAction,
+    /// Pace while throttled (default: 50 KB/s)
+    pub throttle_bytes_per_second: u64,
+}
+
+impl Default for BandwidthQuotaConfig {
+    fn default() -> Self {
+        Self {
+            session_bytes: 0,
+            daily_bytes: 0,
+            request_bytes: 0,
+            warn_percent: 80,
+            on_exhausted: QuotaAction::Block,
+            throttle_bytes_per_second: 50_000,
+        }
+    }
+}
+
+/// Which limit an event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaScope {
+    Session,
+    Daily,
+    Request,
+}
+
+/// How far a limit has been reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaLevel {
+    Warning,
+    Exhausted,
+}
+
+/// A `bandwidth_quota` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaEvent {
+    pub scope: QuotaScope,
+    pub level: QuotaLevel,
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+}
+
+/// Usage as reported by `get_metrics()`
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    pub session_bytes: u64,
+    pub daily_bytes: u64,
+    pub requests: u64,
+    pub session_limit: u64,
+    pub daily_limit: u64,
+    pub request_limit: u64,
+    /// Requests refused because a quota was used up or too small
+    pub refused_requests: u64,
+    /// Whether requests are currently being paced
+    pub throttled: bool,
+}
+
+/// Usage counters and limits; see the module docs
+#[derive(Debug)]
+pub struct BandwidthQuota {
+    config: BandwidthQuotaConfig,
+    session_bytes: u64,
+    /// UTC day (days since the epoch) `daily_bytes` counts
+    day: u64,
+    daily_bytes: u64,
+    requests: u64,
+    refused_requests: u64,
+    /// Highest level already announced, per scope
+    session_raised: Option<QuotaLevel>,
+    daily_raised: Option<QuotaLevel>,
+}
+
+impl BandwidthQuota {
+    pub fn new(config: BandwidthQuotaConfig) -> Self {
+        Self {
+            config,
+            session_bytes: 0,
+            day: 0,
+            daily_bytes: 0,
+            requests: 0,
+            refused_requests: 0,
+            session_raised: None,
+            daily_raised: None,
+        }
+    }
+
+    pub fn config(&self) -> &BandwidthQuotaConfig {
+        &self.config
+    }
+
+    /// Use new limits, keeping the usage so far
+    ///
+    /// Events are re-armed, so a lowered limit that is already crossed is
+    /// announced on the next request.
+    pub fn set_config(&mut self, config: BandwidthQuotaConfig) {
+        self.config = config;
+        self.session_raised = None;
+        self.daily_raised = None;
+    }
+
+    /// Continue the daily count saved by an earlier session
+    pub fn restore_daily(&mut self, day: u64, bytes: u64) {
+        self.day = day;
+        self.daily_bytes = bytes;
+    }
+
+    /// `(day, bytes)` to save for the next session
+    pub fn daily(&self) -> (u64, u64) {
+        (self.day, self.daily_bytes)
+    }
+
+    /// Start a new day's count if `now_secs` is past the current one
+    fn roll_day(&mut self, now_secs: u64) {
+        let today = now_secs / SECS_PER_DAY;
+        if today != self.day {
+            self.day = today;
+            self.daily_bytes = 0;
+            self.daily_raised = None;
+        }
+    }
+
+    fn session_exhausted(&self) -> bool {
+        self.config.session_bytes > 0 && self.session_bytes >= self.config.session_bytes
+    }
+
+    fn daily_exhausted(&self) -> bool {
+        self.config.daily_bytes > 0 && self.daily_bytes >= self.config.daily_bytes
+    }
+
+    /// Whether the session or today's quota is used up
+    pub fn is_exhausted(&mut self, now_secs: u64) -> bool {
+        self.roll_day(now_secs);
+        self.session_exhausted() || self.daily_exhausted()
+    }
+
+    /// Whether requests should be paced right now
+    pub fn is_throttled(&mut self, now_secs: u64) -> bool {
+        self.config.on_exhausted == QuotaAction::Throttle && self.is_exhausted(now_secs)
+    }
+
+    /// Check that a new request may start
+    pub fn admit(&mut self, now_secs: u64) -> Result<()> {
+        if self.config.on_exhausted == QuotaAction::Block && self.is_exhausted(now_secs) {
+            self.refused_requests += 1;
+            let scope = if self.session_exhausted() {
+                "session"
+            } else {
+                "daily"
+            };
+            return Err(TorError::ResourceExhausted(format!(
+                "{} bandwidth quota used up",
+                scope
+            )));
+        }
+        Ok(())
+    }
+
+    /// Count a finished request's `bytes`, returning the events it raised
+    ///
+    /// `request_limit` overrides the configured per-request limit. The
+    /// bytes are counted either way; if they exceed that limit the request
+    /// fails.
+    pub fn record(
+        &mut self,
+        bytes: u64,
+        request_limit: Option<u64>,
+        now_secs: u64,
+    ) -> (Vec<QuotaEvent>, Result<()>) {
+        self.roll_day(now_secs);
+        self.requests += 1;
+        self.session_bytes += bytes;
+        self.daily_bytes += bytes;
+
+        let mut events = Vec::new();
+        let warn = self.config.warn_percent;
+        let (used, limit) = (self.session_bytes, self.config.session_bytes);
+        if let Some(event) = crossed(
+            QuotaScope::Session,
+            used,
+            limit,
+            warn,
+            &mut self.session_raised,
+        ) {
+            events.push(event);
+        }
+        let (used, limit) = (self.daily_bytes, self.config.daily_bytes);
+        if let Some(event) = crossed(QuotaScope::Daily, used, limit, warn, &mut self.daily_raised) {
+            events.push(event);
+        }
+
+        let limit = request_limit.unwrap_or(self.config.request_bytes);
+        if limit > 0 && bytes > limit {
+            self.refused_requests += 1;
+            events.push(QuotaEvent {
+                scope: QuotaScope::Request,
+                level: QuotaLevel::Exhausted,
+                used_bytes: bytes,
+                limit_bytes: limit,
+            });
+            let error = TorError::ResourceExhausted(format!(
+                "request transferred {} bytes, over its {} byte quota",
+                bytes, limit
+            ));
+            return (events, Err(error));
+        }
+        (events, Ok(()))
+    }
+
+    pub fn usage(&self, now_secs: u64) -> QuotaUsage {
+        // A count from an earlier day no longer applies
+        let daily_bytes = if now_secs / SECS_PER_DAY == self.day {
+            self.daily_bytes
+        } else {
+            0
+        };
+        let daily_exhausted = self.config.daily_bytes > 0 && daily_bytes >= self.config.daily_bytes;
+        let throttled = self.config.on_exhausted == QuotaAction::Throttle
+            && (self.session_exhausted() || daily_exhausted);
+        QuotaUsage {
+            session_bytes: self.session_bytes,
+            daily_bytes,
+            requests: self.requests,
+            session_limit: self.config.session_bytes,
+            daily_limit: self.config.daily_bytes,
+            request_limit: self.config.request_bytes,
+            refused_requests: self.refused_requests,
+            throttled,
+        }
+    }
+}
+
+/// The event for `used` reaching a new level of `limit`, if any
+fn crossed(
+    scope: QuotaScope,
+    used: u64,
+    limit: u64,
+    warn_percent: u8,
+    raised: &mut Option<QuotaLevel>,
+) -> Option<QuotaEvent> {
+    if limit == 0 {
+        return None;
+    }
+    let level = if used >= limit {
+        QuotaLevel::Exhausted
+    } else if used.saturating_mul(100) >= limit.saturating_mul(warn_percent as u64) {
+        QuotaLevel::Warning
+    } else {
+        return None;
+    };
+    if raised.is_some_and(|r| r >= level) {
+        return None;
+    }
+    *raised = Some(level);
+    Some(QuotaEvent {
+        scope,
+        level,
+        used_bytes: used,
+        limit_bytes: limit,
+    })
+}
+
+/// The daily counter an earlier session saved under `key` (normally
+/// [`DAILY_USAGE_KEY`]), as `(day, bytes)`
+pub fn load_daily(key: &str) -> Option<(u64, u64)> {
+    let storage = web_sys::window()?.local_storage().ok()??;
+    let json = storage.get_item(key).ok()??;
+    serde_json::from_str(&json).ok()
+}
+
+/// Save the daily counter under `key` for later sessions
+pub fn save_daily(key: &str, daily: (u64, u64)) {
+    let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) else {
+        return;
+    };
+    if let Ok(json) = serde_json::to_string(&daily) {
+        if storage.set_item(key, &json).is_err() {
+            log::warn!("📊 Failed to save daily bandwidth usage");
+        }
+    }
+}
+
+/// Forget the daily counter saved under `key`
+pub fn clear_daily(key: &str) {
+    if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+        let _ = storage.remove_item(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECS_PER_DAY;
+
+    #[test]
+    fn test_events_fire_once_per_level() {
+        let mut quota = BandwidthQuota::new(BandwidthQuotaConfig {
+            session_bytes: 1_000,
+            ..BandwidthQuotaConfig::default()
+        });
+
+        let (events, result) = quota.record(500, None, DAY);
+        assert!(events.is_empty() && result.is_ok());
+
+        let (events, _) = quota.record(300, None, DAY);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            (events[0].scope, events[0].level),
+            (QuotaScope::Session, QuotaLevel::Warning)
+        );
+        assert!(quota.record(10, None, DAY).0.is_empty());
+        assert!(quota.admit(DAY).is_ok());
+
+        let (events, _) = quota.record(200, None, DAY);
+        assert_eq!(events[0].level, QuotaLevel::Exhausted);
+        assert!(quota.admit(DAY).is_err());
+        assert_eq!(quota.usage(DAY).refused_requests, 1);
+    }
+
+    #[test]
+    fn test_daily_quota_resets_and_throttles() {
+        let mut quota = BandwidthQuota::new(BandwidthQuotaConfig {
+            daily_bytes: 100,
+            on_exhausted: QuotaAction::Throttle,
+            ..BandwidthQuotaConfig::default()
+        });
+        quota.restore_daily(1, 90);
+
+        let (events, _) = quota.record(20, None, DAY + 5);
+        assert_eq!(events[0].level, QuotaLevel::Exhausted);
+        // Throttled, not blocked
+        assert!(quota.admit(DAY + 5).is_ok());
+        assert!(quota.is_throttled(DAY + 5));
+
+        // Next UTC day starts from zero
+        assert!(!quota.is_throttled(2 * DAY));
+        assert_eq!(quota.daily(), (2, 0));
+    }
+
+    #[test]
+    fn test_request_limit_override() {
+        let mut quota = BandwidthQuota::new(BandwidthQuotaConfig {
+            request_bytes: 1_000,
+            ..BandwidthQuotaConfig::default()
+        });
+        assert!(quota.record(800, None, 0).1.is_ok());
+        let (events, result) = quota.record(800, Some(500), 0);
+        assert!(result.is_err());
+        assert_eq!(events[0].scope, QuotaScope::Request);
+        // Counted even though it failed
+        assert_eq!(quota.usage(0).session_bytes, 1_600);
+    }
+}
@@ -0,0 +1,514 @@
+//! Operator relay blocklist
+//!
+//! Deployments that must never route through certain relays (known-bad,
+//! or legally off limits where they run) can publish a signed blocklist
+//! and point the client at it. Blocked relays are excluded from selection
+//! for every position, and the circuit builder refuses them again at
+//! build time, so custom paths and cannibalized circuits can't reach them
+//! either.
+//!
+//! The list is a small text document signed with an operator ed25519 key:
+//!
+//! ```text
+//! blocklist-version 1
+//! published 1760000000
+//! fingerprint 0123456789ABCDEF0123456789ABCDEF01234567
+//! range 192.0.2.0/24
+//! range 2001:db8::/32
+//! signature <base64 ed25519 signature>
+//! ```
+//!
+//! The signature covers every byte before the `signature` line. `#` lines
+//! are comments. A list is only replaced by one with a later `published`
+//! time, so a replayed old list can't unblock relays.
+//!
+//! The list is fetched directly with `fetch()`, not through Tor: relays
+//! can't be chosen safely before it is known. The signature, not the
+//! transport, is what makes it trustworthy.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::rc::Rc;
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, RequestMode, Response};
+
+use crate::error::{Result, TorError};
+use crate::protocol::Relay;
+use crate::runtime::timer::now_ms;
+use crate::runtime::LocalCell;
+
+/// Format version this client understands
+pub const BLOCKLIST_VERSION: u32 = 1;
+
+/// Largest blocklist document accepted
+const MAX_BLOCKLIST_BYTES: usize = 1024 * 1024;
+
+/// Where to fetch the blocklist and the key it must be signed with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlocklistConfig {
+    /// HTTPS URL of the signed blocklist (default: "", no blocklist)
+    pub url: String,
+    /// Hex ed25519 public key the list must be signed with
+    pub public_key: String,
+    /// Refetch at most this often, in seconds (default: 21600)
+    pub refresh_secs: u64,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            public_key: String::new(),
+            refresh_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+impl BlocklistConfig {
+    /// Whether a blocklist is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.url.is_empty()
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        if !self.url.starts_with("https://") {
+            return Err(TorError::ParseError(
+                "blocklist.url must be an https:// URL".into(),
+            ));
+        }
+        if self.refresh_secs == 0 {
+            return Err(TorError::ParseError(
+                "blocklist.refresh_secs must be greater than 0".into(),
+            ));
+        }
+        self.verifying_key().map(|_| ())
+    }
+
+    fn verifying_key(&self) -> Result<VerifyingKey> {
+        let bytes: [u8; 32] = hex::decode(self.public_key.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| {
+                TorError::ParseError("blocklist.public_key must be 64 hex digits".into())
+            })?;
+        VerifyingKey::from_bytes(&bytes)
+            .map_err(|e| TorError::ParseError(format!("Invalid blocklist.public_key: {}", e)))
+    }
+}
+
+/// An IPv4 or IPv6 network in CIDR form
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// Parse `addr/prefix`, or a bare address as a single host
+    pub fn parse(s: &str) -> Result<Self> {
+        let bad = || TorError::ParseError(format!("Invalid IP range: {}", s));
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr.parse().map_err(|_| bad())?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|&p| p <= max).ok_or_else(bad)?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    /// Whether `addr` lies in this range
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(net), IpAddr::V4(a)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(a) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(a)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(a) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Relays the operator has blocked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    published: u64,
+    fingerprints: HashSet<String>,
+    ranges: Vec<IpRange>,
+}
+
+/// Blocklist shared between the client, which refreshes it, and the
+/// circuit builders, which check every hop against it
+pub type SharedBlocklist = Rc<LocalCell<Blocklist>>;
+
+/// A fresh, empty shared blocklist
+pub fn new_shared_blocklist() -> SharedBlocklist {
+    Rc::new(LocalCell::new(Blocklist::default()))
+}
+
+impl Blocklist {
+    /// Parse a signed blocklist document, checking its signature with `key`
+    pub fn parse_signed(text: &str, key: &VerifyingKey) -> Result<Self> {
+        let bad = |m: &str| TorError::ParseError(format!("Invalid blocklist: {}", m));
+
+        let sig_start = text
+            .find("\nsignature ")
+            .map(|i| i + 1)
+            .ok_or_else(|| bad("missing signature"))?;
+        let (signed, sig_line) = text.split_at(sig_start);
+        let signature = general_purpose::STANDARD
+            .decode(sig_line["signature ".len()..].trim())
+            .ok()
+            .and_then(|b| Signature::from_slice(&b).ok())
+            .ok_or_else(|| bad("malformed signature"))?;
+        key.verify(signed.as_bytes(), &signature)
+            .map_err(|_| bad("signature does not match the configured key"))?;
+
+        Self::parse_body(signed)
+    }
+
+    fn parse_body(body: &str) -> Result<Self> {
+        let bad = |m: String| TorError::ParseError(format!("Invalid blocklist: {}", m));
+
+        let mut lines = body
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'));
+        match lines
+            .next()
+            .and_then(|l| l.strip_prefix("blocklist-version "))
+        {
+            Some(v) if v.trim() == BLOCKLIST_VERSION.to_string() => {}
+            Some(v) => return Err(bad(format!("unsupported version {}", v))),
+            None => return Err(bad("missing blocklist-version".into())),
+        }
+
+        let mut list = Self::default();
+        let mut published = None;
+        for line in lines {
+            let (keyword, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.split_whitespace().next().unwrap_or("");
+            match keyword {
+                "published" => {
+                    published = Some(
+                        value
+                            .parse()
+                            .map_err(|_| bad(format!("bad time {}", value)))?,
+                    )
+                }
+                "fingerprint" => {
+                    if value.len() != 40 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(bad(format!("bad fingerprint {}", value)));
+                    }
+                    list.fingerprints.insert(value.to_ascii_uppercase());
+                }
+                "range" => list.ranges.push(IpRange::parse(value)?),
+                // Unknown keywords are left for later versions
+                _ => log::debug!("Ignoring blocklist line: {}", keyword),
+            }
+        }
+        list.published = published.ok_or_else(|| bad("missing published".into()))?;
+        Ok(list)
+    }
+
+    /// When the list was published (Unix seconds)
+    pub fn published(&self) -> u64 {
+        self.published
+    }
+
+    /// Whether nothing is blocked
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty() && self.ranges.is_empty()
+    }
+
+    /// Whether `relay` is blocked, by fingerprint or address
+    pub fn blocks(&self, relay: &Relay) -> bool {
+        self.fingerprints
+            .contains(&relay.fingerprint.to_ascii_uppercase())
+            || self.ranges.iter().any(|r| r.contains(relay.address))
+    }
+
+    /// Fingerprints of the blocked relays among `relays`
+    pub fn blocked_in(&self, relays: &[Relay]) -> Vec<String> {
+        relays
+            .iter()
+            .filter(|r| self.blocks(r))
+            .map(|r| r.fingerprint.clone())
+            .collect()
+    }
+}
+
+/// Blocklist state for `blocklist_status()`
+#[derive(Debug, Clone, Serialize)]
+pub struct BlocklistStatus {
+    pub enabled: bool,
+    /// `published` of the list in use (0 = none loaded)
+    pub published: u64,
+    pub fingerprints: usize,
+    pub ranges: usize,
+    /// When the list was last fetched successfully (ms)
+    pub fetched_at_ms: Option<u64>,
+    /// Why the last fetch failed, if it did
+    pub last_error: Option<String>,
+}
+
+/// Keeps the shared blocklist up to date from the configured URL
+pub struct BlocklistUpdater {
+    config: BlocklistConfig,
+    list: SharedBlocklist,
+    fetched_at_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl BlocklistUpdater {
+    pub fn new(config: BlocklistConfig) -> Self {
+        Self {
+            config,
+            list: new_shared_blocklist(),
+            fetched_at_ms: None,
+            last_error: None,
+        }
+    }
+
+    pub fn config(&self) -> &BlocklistConfig {
+        &self.config
+    }
+
+    /// Switch to `config`
+    ///
+    /// The list in use stays until one from the new source has loaded, so
+    /// nothing is unblocked in between; it is dropped only when the
+    /// blocklist is turned off.
+    pub fn set_config(&mut self, config: BlocklistConfig) {
+        if !config.is_enabled() {
+            self.list.with(|l| *l = Blocklist::default());
+        }
+        self.config = config;
+        self.fetched_at_ms = None;
+        self.last_error = None;
+    }
+
+    /// Handle to the list for circuit builders
+    pub fn shared(&self) -> SharedBlocklist {
+        Rc::clone(&self.list)
+    }
+
+    /// Whether the list should be fetched again
+    pub fn is_due(&self) -> bool {
+        self.config.is_enabled()
+            && self
+                .fetched_at_ms
+                .is_none_or(|at| now_ms().saturating_sub(at) >= self.config.refresh_secs * 1000)
+    }
+
+    /// Fetch, verify and install the list; true if it replaced the old one
+    pub async fn refresh(&mut self) -> Result<bool> {
+        let result = self.fetch().await.and_then(|list| self.install(list));
+        match &result {
+            Ok(_) => {
+                self.fetched_at_ms = Some(now_ms());
+                self.last_error = None;
+            }
+            Err(e) => {
+                log::warn!("🚫 Blocklist refresh failed: {}", e);
+                self.last_error = Some(e.to_string());
+            }
+        }
+        result
+    }
+
+    /// Use `list` unless it is older than the one in use
+    fn install(&mut self, list: Blocklist) -> Result<bool> {
+        let current = self.list.with(|l| l.published);
+        if list.published < current {
+            return Err(TorError::ParseError(format!(
+                "Blocklist published {} is older than the one in use ({})",
+                list.published, current
+            )));
+        }
+        if list.published == current && current != 0 {
+            return Ok(false);
+        }
+        log::info!(
+            "🚫 Blocklist published {}: {} fingerprints, {} ranges",
+            list.published,
+            list.fingerprints.len(),
+            list.ranges.len()
+        );
+        self.list.with(|l| *l = list);
+        Ok(true)
+    }
+
+    async fn fetch(&self) -> Result<Blocklist> {
+        let key = self.config.verifying_key()?;
+        let url = &self.config.url;
+        let network = |e: JsValue| TorError::Network(format!("{}: {:?}", url, e));
+
+        let opts = RequestInit::new();
+        opts.set_method("GET");
+        opts.set_mode(RequestMode::Cors);
+        let request = Request::new_with_str_and_init(url, &opts).map_err(network)?;
+
+        let window = web_sys::window().ok_or_else(|| TorError::Network("No window".into()))?;
+        let response: Response = JsFuture::from(window.fetch_with_request(&request))
+            .await
+            .map_err(network)?
+            .dyn_into()
+            .map_err(network)?;
+        if !response.ok() {
+            return Err(TorError::Network(format!(
+                "HTTP {} from {}",
+                response.status(),
+                url
+            )));
+        }
+        let text = JsFuture::from(response.text().map_err(network)?)
+            .await
+            .map_err(network)?
+            .as_string()
+            .unwrap_or_default();
+        if text.len() > MAX_BLOCKLIST_BYTES {
+            return Err(TorError::ParseError(format!(
+                "Blocklist is larger than {} bytes",
+                MAX_BLOCKLIST_BYTES
+            )));
+        }
+        Blocklist::parse_signed(&text, &key)
+    }
+
+    pub fn status(&self) -> BlocklistStatus {
+        let (published, fingerprints, ranges) = self
+            .list
+            .with(|l| (l.published, l.fingerprints.len(), l.ranges.len()));
+        BlocklistStatus {
+            enabled: self.config.is_enabled(),
+            published,
+            fingerprints,
+            ranges,
+            fetched_at_ms: self.fetched_at_ms,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::TestRelay;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn sign(body: &str, key: &SigningKey) -> String {
+        let signature = key.sign(body.as_bytes());
+        format!(
+            "{}signature {}\n",
+            body,
+            general_purpose::STANDARD.encode(signature.to_bytes())
+        )
+    }
+
+    fn relay(fingerprint: &str, address: &str) -> Relay {
+        TestRelay::new(fingerprint)
+            .fingerprint(fingerprint)
+            .address(address)
+            .or_port(443)
+            .build()
+    }
+
+    const BODY: &str = "blocklist-version 1\n\
+        published 1760000000\n\
+        # known-bad\n\
+        fingerprint 0123456789abcdef0123456789abcdef01234567\n\
+        range 192.0.2.0/24\n\
+        range 2001:db8::/32\n";
+
+    #[test]
+    fn test_signed_blocklist_blocks_fingerprints_and_ranges() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let list = Blocklist::parse_signed(&sign(BODY, &key), &key.verifying_key()).unwrap();
+        assert_eq!(list.published(), 1_760_000_000);
+
+        let fp = "0123456789ABCDEF0123456789ABCDEF01234567";
+        assert!(list.blocks(&relay(fp, "203.0.113.1")));
+        assert!(list.blocks(&relay("OTHER", "192.0.2.200")));
+        assert!(list.blocks(&relay("OTHER", "2001:db8::1")));
+        assert!(!list.blocks(&relay("OTHER", "192.0.3.1")));
+        assert!(!list.blocks(&relay("OTHER", "2001:db9::1")));
+    }
+
+    #[test]
+    fn test_blocklist_rejects_bad_signatures() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signed = sign(BODY, &key);
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(Blocklist::parse_signed(&signed, &other).is_err());
+
+        let tampered = signed.replace("192.0.2.0/24", "192.0.2.0/32");
+        assert!(Blocklist::parse_signed(&tampered, &key.verifying_key()).is_err());
+        assert!(Blocklist::parse_signed(BODY, &key.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_ip_range_parsing() {
+        let host = IpRange::parse("198.51.100.7").unwrap();
+        assert!(host.contains("198.51.100.7".parse().unwrap()));
+        assert!(!host.contains("198.51.100.8".parse().unwrap()));
+        assert!(IpRange::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!(IpRange::parse("10.0.0.0/33").is_err());
+        assert!(IpRange::parse("not-an-ip/8").is_err());
+    }
+
+    #[test]
+    fn test_older_blocklist_is_refused() {
+        let mut updater = BlocklistUpdater::new(BlocklistConfig::default());
+        let newer = Blocklist::parse_body(BODY).unwrap();
+        assert!(updater.install(newer.clone()).unwrap());
+        assert!(!updater.install(newer).unwrap());
+
+        let older = Blocklist::parse_body(&BODY.replace("1760000000", "1750000000")).unwrap();
+        assert!(updater.install(older).is_err());
+        assert_eq!(updater.status().published, 1_760_000_000);
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(BlocklistConfig::default().validate().is_ok());
+        let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let config = BlocklistConfig {
+            url: "https://example.com/blocklist".into(),
+            public_key: hex::encode(key.to_bytes()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        assert!(BlocklistConfig {
+            url: "http://example.com/blocklist".into(),
+            ..config.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(BlocklistConfig {
+            public_key: "abcd".into(),
+            ..config
+        }
+        .validate()
+        .is_err());
+    }
+}
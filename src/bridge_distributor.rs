@@ -0,0 +1,491 @@
+//! Bridge distribution client
+//!
+//! Lets censored users obtain bridges in-app from a Moat-style HTTPS
+//! distributor (the JSON:API protocol rdsys serves to Tor Browser):
+//!
+//!   1. `POST {endpoint}/fetch` with the transports we support → CAPTCHA
+//!      challenge (image + opaque challenge string)
+//!   2. `POST {endpoint}/check` with the challenge and the user's solution →
+//!      bridge lines
+//!
+//! The solution is passed through untouched, so a distributor may equally
+//! hand out an image CAPTCHA or accept a token from a third-party widget.
+//!
+//! Bridge lines use Tor's `<transport> <addr> <fingerprint> key=value...`
+//! form; only the browser-capable transports (WebSocket, meek, WebTunnel,
+//! WebRTC) are kept. Accepted lines are persisted in the `state` store and
+//! re-parsed on load.
+//!
+//! The endpoint is contacted directly with `fetch()`, so it must be
+//! reachable without Tor (e.g. behind a CDN).
+
+use crate::error::{Result, TorError};
+use crate::runtime::timer::now_ms;
+use crate::storage::WasmStorage;
+use crate::transport::{BridgeConfig, TransportMode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, RequestMode, Response};
+
+/// Moat protocol version we speak
+pub const MOAT_VERSION: &str = "0.1.0";
+
+/// Transports requested from the distributor, in order of preference
+pub const SUPPORTED_TRANSPORTS: [&str; 4] = ["webtunnel", "websocket", "meek", "webrtc"];
+
+const MOAT_CONTENT_TYPE: &str = "application/vnd.api+json";
+const STORE_NAME: &str = "state";
+const STORAGE_KEY: &str = "distributed_bridges";
+
+/// CAPTCHA challenge returned by `/fetch`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeChallenge {
+    /// Transport the distributor chose for this challenge
+    pub transport: String,
+    /// Base64-encoded CAPTCHA image (empty for token-based CAPTCHAs)
+    pub image: String,
+    /// Opaque challenge string, echoed back in `/check`
+    pub challenge: String,
+}
+
+/// A bridge line that parsed into a usable configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct DistributedBridge {
+    pub line: String,
+    pub transport: &'static str,
+    pub url: String,
+}
+
+/// Bridge lines persisted across sessions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoredBridges {
+    pub endpoint: String,
+    /// When the lines were fetched (ms since epoch)
+    pub fetched_at: u64,
+    pub lines: Vec<String>,
+}
+
+impl StoredBridges {
+    /// Parsed bridges, skipping lines that no longer parse
+    pub fn bridges(&self) -> Vec<DistributedBridge> {
+        self.lines
+            .iter()
+            .filter_map(|line| {
+                let config = parse_bridge_line(line).ok()?;
+                Some(DistributedBridge {
+                    line: line.clone(),
+                    transport: config.transport.as_str(),
+                    url: config.bridge_url,
+                })
+            })
+            .collect()
+    }
+
+    /// Transport configurations for every stored bridge
+    pub fn configs(&self) -> Vec<BridgeConfig> {
+        self.lines
+            .iter()
+            .filter_map(|line| parse_bridge_line(line).ok())
+            .collect()
+    }
+}
+
+/// Parse one bridge line into a transport configuration
+///
+/// Accepts Tor's `<transport> <addr:port> <fingerprint> key=value...` form
+/// with a `url=` argument, or a bare `wss://`/`https://` URL. Recognized
+/// arguments: `url`, `broker` (WebRTC), `bridge_b_pubkey` (hex, enables
+/// blinded mode).
+pub fn parse_bridge_line(line: &str) -> Result<BridgeConfig> {
+    let line = line.trim();
+    let line = line.strip_prefix("Bridge ").unwrap_or(line).trim();
+    if line.is_empty() {
+        return Err(TorError::ParseError("Empty bridge line".into()));
+    }
+
+    if line.starts_with("wss://") || line.starts_with("ws://") {
+        return Ok(BridgeConfig::new(line.to_string()));
+    }
+    if line.starts_with("https://") || line.starts_with("http://") {
+        return Ok(BridgeConfig::meek(line.to_string()));
+    }
+
+    let mut parts = line.split_whitespace();
+    let transport = parts.next().unwrap_or_default().to_ascii_lowercase();
+    let mut url = None;
+    let mut broker = None;
+    let mut pubkey = None;
+    for arg in parts {
+        match arg.split_once('=') {
+            Some(("url", v)) => url = Some(v.to_string()),
+            Some(("broker", v)) => broker = Some(v.to_string()),
+            Some(("bridge_b_pubkey", v)) => pubkey = Some(v.to_string()),
+            // Address, fingerprint and transport args we don't use
+            _ => {}
+        }
+    }
+    let url = url.ok_or_else(|| {
+        TorError::ParseError(format!("Bridge line has no url= argument: {}", line))
+    })?;
+
+    let mut config = match transport.as_str() {
+        "websocket" => BridgeConfig::new(url),
+        "meek" | "meek_lite" => BridgeConfig::meek(url),
+        "webtunnel" => {
+            let (origin, path) = split_url_path(&url);
+            BridgeConfig::webtunnel(origin.to_string(), path.to_string())
+        }
+        "webrtc" | "snowflake" => {
+            let broker = broker.ok_or_else(|| {
+                TorError::ParseError("WebRTC bridge line needs a broker= argument".into())
+            })?;
+            BridgeConfig::peer_bridge(broker, url, None)
+        }
+        other => {
+            return Err(TorError::ParseError(format!(
+                "Unsupported bridge transport: {}",
+                other
+            )))
+        }
+    };
+
+    if let Some(hex_key) = pubkey {
+        let key: [u8; 32] = hex::decode(&hex_key)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| TorError::ParseError("bridge_b_pubkey must be 32 hex bytes".into()))?;
+        config.bridge_b_pubkey = Some(key);
+    }
+
+    // meek needs an http(s) URL, every other transport a ws(s) one
+    let is_ws = config.bridge_url.starts_with("wss://") || config.bridge_url.starts_with("ws://");
+    if (config.transport == TransportMode::Meek) == is_ws {
+        return Err(TorError::InvalidUrl(format!(
+            "URL scheme does not match {} transport: {}",
+            config.transport.as_str(),
+            config.bridge_url
+        )));
+    }
+
+    Ok(config)
+}
+
+/// Split `wss://host/secret` into (`wss://host`, `/secret`)
+fn split_url_path(url: &str) -> (&str, &str) {
+    let host_start = url.find("://").map(|i| i + 3).unwrap_or(0);
+    match url[host_start..].find('/') {
+        Some(i) => url.split_at(host_start + i),
+        None => (url, "/"),
+    }
+}
+
+/// Turn a JSON:API `errors` array into an error
+fn moat_error(response: &Value) -> Option<TorError> {
+    let error = response.get("errors")?.as_array()?.first()?;
+    let code = error.get("code").and_then(Value::as_u64).unwrap_or(0);
+    let detail = error
+        .get("detail")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown error");
+    Some(TorError::Directory(format!(
+        "Bridge distributor error {}: {}",
+        code, detail
+    )))
+}
+
+/// The first `data` entry, checked against the expected type
+fn moat_data<'a>(response: &'a Value, expected: &str) -> Result<&'a Value> {
+    if let Some(e) = moat_error(response) {
+        return Err(e);
+    }
+    let data = response
+        .get("data")
+        .and_then(Value::as_array)
+        .and_then(|d| d.first())
+        .ok_or_else(|| TorError::ParseError("Distributor response has no data".into()))?;
+    match data.get("type").and_then(Value::as_str) {
+        Some(t) if t == expected => Ok(data),
+        other => Err(TorError::ParseError(format!(
+            "Expected {} from distributor, got {:?}",
+            expected, other
+        ))),
+    }
+}
+
+/// Parse a `/fetch` response
+pub fn parse_challenge(response: &Value) -> Result<BridgeChallenge> {
+    let data = moat_data(response, "moat-challenge")?;
+    let field = |name: &str| {
+        data.get(name)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let challenge = field("challenge");
+    if challenge.is_empty() {
+        return Err(TorError::ParseError(
+            "Challenge missing from response".into(),
+        ));
+    }
+    Ok(BridgeChallenge {
+        transport: field("transport"),
+        image: field("image"),
+        challenge,
+    })
+}
+
+/// Parse a `/check` response into raw bridge lines
+pub fn parse_bridge_lines(response: &Value) -> Result<Vec<String>> {
+    let data = moat_data(response, "moat-bridges")?;
+    let lines = data
+        .get("bridges")
+        .and_then(Value::as_array)
+        .ok_or_else(|| TorError::ParseError("Bridges missing from response".into()))?;
+    Ok(lines
+        .iter()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect())
+}
+
+/// Client for a Moat-style bridge distributor
+pub struct BridgeDistributor {
+    endpoint: String,
+    transports: Vec<String>,
+    storage: Arc<WasmStorage>,
+}
+
+impl BridgeDistributor {
+    /// Create a distributor client for `endpoint` (e.g. `https://bridges.example/moat`)
+    pub fn new(endpoint: &str, storage: Arc<WasmStorage>) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            transports: SUPPORTED_TRANSPORTS.iter().map(|t| t.to_string()).collect(),
+            storage,
+        }
+    }
+
+    /// Ask for specific transports instead of every supported one
+    pub fn with_transports(mut self, transports: Vec<String>) -> Self {
+        self.transports = transports;
+        self
+    }
+
+    /// Request a CAPTCHA challenge
+    pub async fn request_challenge(&self) -> Result<BridgeChallenge> {
+        let body = serde_json::json!({
+            "data": [{
+                "version": MOAT_VERSION,
+                "type": "client-transports",
+                "supported": self.transports,
+            }]
+        });
+        let response = self.post("fetch", &body).await?;
+        parse_challenge(&response)
+    }
+
+    /// Submit the CAPTCHA solution and fetch bridges
+    ///
+    /// Lines for unsupported transports are dropped. If at least one line is
+    /// usable, the set replaces any previously stored bridges.
+    pub async fn fetch_bridges(
+        &self,
+        challenge: &BridgeChallenge,
+        solution: &str,
+    ) -> Result<Vec<DistributedBridge>> {
+        let body = serde_json::json!({
+            "data": [{
+                "id": "2",
+                "version": MOAT_VERSION,
+                "type": "moat-solution",
+                "transport": challenge.transport,
+                "challenge": challenge.challenge,
+                "solution": solution,
+                "qrcode": "false",
+            }]
+        });
+        let response = self.post("check", &body).await?;
+        let lines = parse_bridge_lines(&response)?;
+
+        let stored = StoredBridges {
+            endpoint: self.endpoint.clone(),
+            fetched_at: now_ms(),
+            lines: lines
+                .into_iter()
+                .filter(|line| match parse_bridge_line(line) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        log::debug!("Skipping bridge line: {}", e);
+                        false
+                    }
+                })
+                .collect(),
+        };
+        if stored.lines.is_empty() {
+            return Err(TorError::NoRelaysAvailable(
+                "Distributor returned no usable bridges".into(),
+            ));
+        }
+
+        log::info!(
+            "🌉 Received {} bridges from {}",
+            stored.lines.len(),
+            self.endpoint
+        );
+        self.save(&stored).await?;
+        Ok(stored.bridges())
+    }
+
+    /// Bridges persisted by a previous fetch
+    pub async fn load(storage: &WasmStorage) -> Result<Option<StoredBridges>> {
+        match storage.get(STORE_NAME, STORAGE_KEY).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| TorError::Storage(format!("Corrupt stored bridges: {}", e))),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, stored: &StoredBridges) -> Result<()> {
+        let bytes = serde_json::to_vec(stored)
+            .map_err(|e| TorError::Internal(format!("Serialize bridges: {}", e)))?;
+        self.storage.set(STORE_NAME, STORAGE_KEY, &bytes).await
+    }
+
+    /// POST a JSON:API document and parse the response
+    async fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        let url = format!("{}/{}", self.endpoint, path);
+        let network = |e: JsValue| TorError::Network(format!("{}: {:?}", url, e));
+
+        let opts = RequestInit::new();
+        opts.set_method("POST");
+        opts.set_mode(RequestMode::Cors);
+        opts.set_body(&JsValue::from_str(&body.to_string()));
+
+        let request = Request::new_with_str_and_init(&url, &opts).map_err(network)?;
+        request
+            .headers()
+            .set("Content-Type", MOAT_CONTENT_TYPE)
+            .map_err(network)?;
+
+        let window = web_sys::window().ok_or_else(|| TorError::Network("No window".into()))?;
+        let response: Response = JsFuture::from(window.fetch_with_request(&request))
+            .await
+            .map_err(network)?
+            .dyn_into()
+            .map_err(network)?;
+        let text = JsFuture::from(response.text().map_err(network)?)
+            .await
+            .map_err(network)?
+            .as_string()
+            .unwrap_or_default();
+
+        // Moat reports errors as JSON:API documents, sometimes with a non-2xx status
+        match serde_json::from_str::<Value>(&text) {
+            Ok(json) => Ok(json),
+            Err(_) if !response.ok() => Err(TorError::Network(format!(
+                "HTTP {} from {}",
+                response.status(),
+                url
+            ))),
+            Err(e) => Err(TorError::ParseError(format!(
+                "Invalid distributor response: {}",
+                e
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bridge_lines() {
+        let config = parse_bridge_line(
+            "webtunnel 192.0.2.3:443 2852A1B8FD3D5B2EB3BF3ED1E0F8B3D8B5A2D2F1 url=wss://blog.example/ws-a1b2 ver=0.0.1",
+        )
+        .unwrap();
+        assert_eq!(config.transport, TransportMode::WebTunnel);
+        assert_eq!(config.bridge_url, "wss://blog.example");
+        assert_eq!(config.webtunnel_path.as_deref(), Some("/ws-a1b2"));
+
+        let config = parse_bridge_line(&format!(
+            "Bridge websocket 192.0.2.4:1 url=wss://bridge.example/tor bridge_b_pubkey={}",
+            "01".repeat(32)
+        ))
+        .unwrap();
+        assert_eq!(config.transport, TransportMode::WebSocket);
+        assert_eq!(config.bridge_b_pubkey, Some([1u8; 32]));
+
+        let config = parse_bridge_line(
+            "snowflake 192.0.2.5:80 url=wss://proxy.example broker=https://broker.example",
+        )
+        .unwrap();
+        assert_eq!(config.transport, TransportMode::WebRtc);
+        assert_eq!(config.broker_url.as_deref(), Some("https://broker.example"));
+
+        assert_eq!(
+            parse_bridge_line("https://cdn.example/meek")
+                .unwrap()
+                .transport,
+            TransportMode::Meek
+        );
+
+        for bad in [
+            "",
+            "obfs4 192.0.2.6:443 FP cert=abc iat-mode=0",
+            "webtunnel 192.0.2.7:443 FP",
+            "meek 192.0.2.8:443 url=wss://not-http.example",
+            "snowflake 192.0.2.9:80 url=wss://proxy.example",
+            "websocket 192.0.2.1:1 url=wss://b.example bridge_b_pubkey=zz",
+        ] {
+            assert!(parse_bridge_line(bad).is_err(), "accepted {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_moat_responses() {
+        let challenge = parse_challenge(&serde_json::json!({
+            "data": [{
+                "id": "1", "type": "moat-challenge", "version": "0.1.0",
+                "transport": "webtunnel", "image": "iVBORw0KGgo=", "challenge": "c2VjcmV0"
+            }]
+        }))
+        .unwrap();
+        assert_eq!(challenge.transport, "webtunnel");
+        assert_eq!(challenge.challenge, "c2VjcmV0");
+
+        let lines = parse_bridge_lines(&serde_json::json!({
+            "data": [{
+                "id": "3", "type": "moat-bridges", "version": "0.1.0",
+                "bridges": ["webtunnel 192.0.2.3:443 FP url=wss://a.example/x", "obfs4 192.0.2.4:1 FP"]
+            }]
+        }))
+        .unwrap();
+        assert_eq!(lines.len(), 2);
+
+        let stored = StoredBridges {
+            lines,
+            ..Default::default()
+        };
+        assert_eq!(stored.bridges().len(), 1);
+        assert_eq!(stored.bridges()[0].transport, "webtunnel");
+
+        let err = parse_bridge_lines(&serde_json::json!({
+            "errors": [{"code": 419, "status": "Not Acceptable", "detail": "The CAPTCHA solution was incorrect."}]
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("419"));
+
+        // A challenge where bridges were expected is an error, not an empty list
+        assert!(parse_bridge_lines(&serde_json::json!({
+            "data": [{"type": "moat-challenge", "challenge": "x"}]
+        }))
+        .is_err());
+    }
+}
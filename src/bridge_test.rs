@@ -0,0 +1,344 @@
+//! Bridge reachability testing
+//!
+//! Backs `TorClient::test_bridge`, which "paste your bridge line" UIs use to
+//! check a candidate bridge before switching to it. The test runs the same
+//! transport chain as a real circuit build (transport connect, TLS, link
+//! VERSIONS/CERTS/NETINFO) on a throwaway connection, then closes it. Nothing
+//! is shared with the client's own provider, pools or guard state.
+//!
+//! ```javascript
+//! const report = await client.test_bridge(JSON.stringify({
+//!     transport: "webtunnel",
+//!     url: "wss://innocent-blog.example",
+//!     path: "/ws-a1b2c3d4",
+//! }));
+//! // { ok: false, failed_stage: "tls", error: "...", connect_ms: 412, ... }
+//! ```
+
+use crate::error::{Result, TorError};
+use crate::network::WasmTlsConnector;
+use crate::protocol::{CircuitBuilder, Relay};
+use crate::runtime::timer::now_ms;
+use crate::transport::BridgeConfig;
+use futures::future::FutureExt;
+use futures::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::SocketAddr;
+
+/// Default time budget for the whole test
+pub const DEFAULT_TEST_TIMEOUT_MS: u32 = 30_000;
+
+/// Upper bound on the time budget
+pub const MAX_TEST_TIMEOUT_MS: u32 = 120_000;
+
+/// Candidate bridge as pasted by the user
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BridgeTestConfig {
+    /// `"websocket"` (default), `"meek"`, `"webtunnel"` or `"webrtc"`
+    pub transport: Option<String>,
+
+    /// Bridge URL (`ws(s)://` for WebSocket/WebTunnel/WebRTC, `http(s)://` for meek)
+    pub url: String,
+
+    /// WebTunnel secret path
+    pub path: Option<String>,
+
+    /// WebRTC signaling broker
+    pub broker_url: Option<String>,
+
+    /// Hex-encoded Bridge B public key (enables blinded mode)
+    pub bridge_b_pubkey: Option<String>,
+
+    /// Fingerprint of the relay to reach through the bridge. When
+    /// `relay_address` is unset it is looked up in the consensus.
+    pub relay: Option<String>,
+
+    /// Explicit `ip:port` of the relay to reach (usable before bootstrap)
+    pub relay_address: Option<String>,
+
+    /// Time budget for the whole test (default 30s, capped at 120s)
+    pub timeout_ms: Option<u32>,
+}
+
+impl BridgeTestConfig {
+    /// Parse the JSON passed from JavaScript
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| TorError::ParseError(format!("Invalid bridge config: {}", e)))
+    }
+
+    /// Build the transport configuration this candidate describes
+    pub fn bridge_config(&self) -> Result<BridgeConfig> {
+        let url = self.url.trim();
+        if url.is_empty() {
+            return Err(TorError::InvalidUrl("Bridge URL is required".into()));
+        }
+        let is_ws = url.starts_with("ws://") || url.starts_with("wss://");
+        let is_http = url.starts_with("http://") || url.starts_with("https://");
+
+        let transport = self.transport.as_deref().unwrap_or("websocket");
+        let mut config = match transport.to_ascii_lowercase().as_str() {
+            "websocket" | "ws" if is_ws => BridgeConfig::new(url.to_string()),
+            "meek" if is_http => BridgeConfig::meek(url.to_string()),
+            "webtunnel" if is_ws => {
+                let path = self.path.clone().ok_or_else(|| {
+                    TorError::ParseError("WebTunnel bridge needs a secret path".into())
+                })?;
+                BridgeConfig::webtunnel(url.to_string(), path)
+            }
+            "webrtc" if is_ws => {
+                let broker = self.broker_url.clone().ok_or_else(|| {
+                    TorError::ParseError("WebRTC bridge needs a broker_url".into())
+                })?;
+                BridgeConfig::peer_bridge(broker, url.to_string(), None)
+            }
+            "websocket" | "ws" | "meek" | "webtunnel" | "webrtc" => {
+                return Err(TorError::InvalidUrl(format!(
+                    "URL scheme does not match {} transport: {}",
+                    transport, url
+                )))
+            }
+            other => {
+                return Err(TorError::ParseError(format!(
+                    "Unknown transport: {}",
+                    other
+                )))
+            }
+        };
+
+        if let Some(ref hex_key) = self.bridge_b_pubkey {
+            let bytes = hex::decode(hex_key.trim())
+                .map_err(|e| TorError::ParseError(format!("Invalid bridge_b_pubkey: {}", e)))?;
+            let key: [u8; 32] = bytes
+                .try_into()
+                .map_err(|_| TorError::ParseError("bridge_b_pubkey must be 32 bytes".into()))?;
+            config.bridge_b_pubkey = Some(key);
+        }
+
+        Ok(config)
+    }
+
+    /// Explicit relay address, if one was given
+    pub fn relay_address(&self) -> Result<Option<SocketAddr>> {
+        self.relay_address
+            .as_deref()
+            .map(|addr| {
+                addr.trim()
+                    .parse()
+                    .map_err(|_| TorError::ParseError(format!("Invalid relay_address: {}", addr)))
+            })
+            .transpose()
+    }
+
+    /// Time budget, clamped to the allowed range
+    pub fn timeout_ms(&self) -> u32 {
+        self.timeout_ms
+            .unwrap_or(DEFAULT_TEST_TIMEOUT_MS)
+            .clamp(1_000, MAX_TEST_TIMEOUT_MS)
+    }
+}
+
+/// The relay a bridge test tries to reach
+#[derive(Debug, Clone)]
+pub struct BridgeTestTarget {
+    pub address: SocketAddr,
+    /// Used as the TLS server name and in the report
+    pub name: String,
+    /// Identity to verify in the CERTS cell (skipped when unknown)
+    pub fingerprint: Option<String>,
+}
+
+impl From<&Relay> for BridgeTestTarget {
+    fn from(relay: &Relay) -> Self {
+        Self {
+            address: relay.socket_addr(),
+            name: relay.nickname.clone(),
+            fingerprint: Some(relay.fingerprint.clone()),
+        }
+    }
+}
+
+/// Step of the transport chain that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgeTestStage {
+    /// Transport connection to the bridge (and through it to the relay)
+    Connect,
+    /// TLS handshake with the relay
+    Tls,
+    /// Link protocol handshake (VERSIONS, CERTS, NETINFO)
+    Handshake,
+}
+
+/// Outcome of a bridge test
+#[derive(Debug, Clone, Serialize)]
+pub struct BridgeTestReport {
+    pub ok: bool,
+    pub transport: &'static str,
+    pub relay: String,
+    /// First stage that failed (`None` on success)
+    pub failed_stage: Option<BridgeTestStage>,
+    pub error: Option<String>,
+    pub connect_ms: Option<u64>,
+    pub tls_ms: Option<u64>,
+    pub handshake_ms: Option<u64>,
+    pub total_ms: u64,
+}
+
+impl BridgeTestReport {
+    fn new(config: &BridgeConfig, target: &BridgeTestTarget) -> Self {
+        Self {
+            ok: false,
+            transport: config.transport.as_str(),
+            relay: format!("{} ({})", target.name, target.address),
+            failed_stage: None,
+            error: None,
+            connect_ms: None,
+            tls_ms: None,
+            handshake_ms: None,
+            total_ms: 0,
+        }
+    }
+
+    fn fail(mut self, stage: BridgeTestStage, error: TorError, started: u64) -> Self {
+        log::warn!("⚠️ Bridge test failed at {:?}: {}", stage, error);
+        self.failed_stage = Some(stage);
+        self.error = Some(error.to_string());
+        self.total_ms = now_ms().saturating_sub(started);
+        self
+    }
+}
+
+/// Run `future` unless the deadline passes first
+async fn before_deadline<T>(future: impl Future<Output = Result<T>>, deadline: u64) -> Result<T> {
+    let remaining = deadline.saturating_sub(now_ms()) as u32;
+    futures::select_biased! {
+        result = future.fuse() => result,
+        _ = gloo_timers::future::TimeoutFuture::new(remaining).fuse() => Err(TorError::Timeout),
+    }
+}
+
+/// Connect to `target` through the candidate bridge, complete TLS and the
+/// link handshake, then close the connection.
+pub async fn run_bridge_test(
+    config: &BridgeConfig,
+    target: &BridgeTestTarget,
+    timeout_ms: u32,
+) -> BridgeTestReport {
+    let started = now_ms();
+    let deadline = started + timeout_ms as u64;
+    let mut report = BridgeTestReport::new(config, target);
+
+    log::info!(
+        "🧪 Testing {} bridge {} → {}",
+        report.transport,
+        config.bridge_url,
+        report.relay
+    );
+
+    let stage_start = now_ms();
+    let connect = async {
+        config
+            .connect(&target.address)
+            .await
+            .map_err(|e| TorError::ConnectionFailed(e.to_string()))
+    };
+    let stream = match before_deadline(connect, deadline).await {
+        Ok(stream) => stream,
+        Err(e) => return report.fail(BridgeTestStage::Connect, e, started),
+    };
+    report.connect_ms = Some(now_ms().saturating_sub(stage_start));
+
+    let stage_start = now_ms();
+    let tls = async {
+        WasmTlsConnector::new()
+            .connect(stream, Some(&target.name), Some(target.address))
+            .await
+            .map_err(|e| TorError::HandshakeFailed(format!("TLS handshake failed: {}", e)))
+    };
+    let mut tls_stream = match before_deadline(tls, deadline).await {
+        Ok(stream) => stream,
+        Err(e) => return report.fail(BridgeTestStage::Tls, e, started),
+    };
+    report.tls_ms = Some(now_ms().saturating_sub(stage_start));
+
+    let stage_start = now_ms();
+    let handshake = CircuitBuilder::protocol_handshake(
+        &mut tls_stream,
+        target.fingerprint.as_deref(),
+        None,
+        None,
+    );
+    let result = before_deadline(handshake, deadline).await;
+    let _ = tls_stream.close().await;
+    if let Err(e) = result {
+        return report.fail(BridgeTestStage::Handshake, e, started);
+    }
+    report.handshake_ms = Some(now_ms().saturating_sub(stage_start));
+
+    report.ok = true;
+    report.total_ms = now_ms().saturating_sub(started);
+    log::info!("✅ Bridge reachable ({}ms)", report.total_ms);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportMode;
+
+    #[test]
+    fn test_parses_each_transport() {
+        let config = BridgeTestConfig::from_json(r#"{"url": "wss://bridge.example"}"#).unwrap();
+        let bridge = config.bridge_config().unwrap();
+        assert_eq!(bridge.transport, TransportMode::WebSocket);
+        assert_eq!(config.timeout_ms(), DEFAULT_TEST_TIMEOUT_MS);
+
+        let config = BridgeTestConfig::from_json(
+            r#"{"transport": "WebTunnel", "url": "wss://blog.example", "path": "/ws-1"}"#,
+        )
+        .unwrap();
+        let bridge = config.bridge_config().unwrap();
+        assert_eq!(bridge.transport, TransportMode::WebTunnel);
+        assert_eq!(bridge.webtunnel_path.as_deref(), Some("/ws-1"));
+
+        let config = BridgeTestConfig::from_json(&format!(
+            r#"{{"transport": "meek", "url": "https://cdn.example/meek",
+                "bridge_b_pubkey": "{}", "relay_address": "192.0.2.7:443",
+                "timeout_ms": 999999}}"#,
+            "ab".repeat(32)
+        ))
+        .unwrap();
+        let bridge = config.bridge_config().unwrap();
+        assert_eq!(bridge.transport, TransportMode::Meek);
+        assert_eq!(bridge.bridge_b_pubkey, Some([0xab; 32]));
+        assert_eq!(
+            config.relay_address().unwrap(),
+            Some("192.0.2.7:443".parse().unwrap())
+        );
+        assert_eq!(config.timeout_ms(), MAX_TEST_TIMEOUT_MS);
+    }
+
+    #[test]
+    fn test_rejects_inconsistent_config() {
+        let bad = [
+            r#"{"url": ""}"#,
+            r#"{"transport": "meek", "url": "wss://bridge.example"}"#,
+            r#"{"transport": "webtunnel", "url": "wss://blog.example"}"#,
+            r#"{"transport": "webrtc", "url": "wss://bridge.example"}"#,
+            r#"{"transport": "obfs4", "url": "wss://bridge.example"}"#,
+            r#"{"url": "wss://bridge.example", "bridge_b_pubkey": "abcd"}"#,
+        ];
+        for json in bad {
+            let config = BridgeTestConfig::from_json(json).unwrap();
+            assert!(config.bridge_config().is_err(), "accepted {}", json);
+        }
+
+        let config =
+            BridgeTestConfig::from_json(r#"{"url": "wss://b", "relay_address": "nope"}"#).unwrap();
+        assert!(config.relay_address().is_err());
+        assert!(BridgeTestConfig::from_json("not json").is_err());
+    }
+}
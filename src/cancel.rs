@@ -0,0 +1,161 @@
+//! Request cancellation
+//!
+//! A [`CancelToken`] is shared by everything working on one request. It is
+//! usually tied to a JS `AbortSignal` (the `signal` fetch option), and is
+//! checked at points where stopping leaves no protocol state behind:
+//!
+//! - the circuit builder checks it between hops, and destroys a partly
+//!   built circuit with DESTROY ([`CircuitBuilder::with_cancel`])
+//! - the cooperative scheduler ends the request's streams with RELAY_END
+//!   and fails their queued work ([`CooperativeCircuit::with_cancel`])
+//!
+//! Nothing is dropped halfway through reading a cell, as that would leave
+//! the link to the guard out of step.
+//!
+//! [`CircuitBuilder::with_cancel`]: crate::protocol::CircuitBuilder::with_cancel
+//! [`CooperativeCircuit::with_cancel`]: crate::cooperative::CooperativeCircuit::with_cancel
+
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{Result, TorError};
+
+type AbortListener = (web_sys::AbortSignal, Closure<dyn FnMut()>);
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: Cell<bool>,
+    wakers: RefCell<Vec<Waker>>,
+    /// `abort` listener on the signal, removed when the token goes away
+    listener: RefCell<Option<AbortListener>>,
+}
+
+impl Drop for CancelState {
+    fn drop(&mut self) {
+        if let Some((signal, closure)) = self.listener.get_mut().take() {
+            let _ = signal
+                .remove_event_listener_with_callback("abort", closure.as_ref().unchecked_ref());
+        }
+    }
+}
+
+/// Cancellation flag shared by the parts of one request
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    state: Rc<CancelState>,
+}
+
+impl std::fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl CancelToken {
+    /// A token that is cancelled only by [`CancelToken::cancel`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token cancelled when `signal` aborts (or already cancelled, if it
+    /// has)
+    pub fn from_signal(signal: &web_sys::AbortSignal) -> Self {
+        let token = Self::new();
+        if signal.aborted() {
+            token.cancel();
+            return token;
+        }
+        let weak: Weak<CancelState> = Rc::downgrade(&token.state);
+        let closure = Closure::<dyn FnMut()>::new(move || {
+            if let Some(state) = weak.upgrade() {
+                CancelToken { state }.cancel();
+            }
+        });
+        if signal
+            .add_event_listener_with_callback("abort", closure.as_ref().unchecked_ref())
+            .is_ok()
+        {
+            *token.state.listener.borrow_mut() = Some((signal.clone(), closure));
+        }
+        token
+    }
+
+    /// Cancel the request
+    pub fn cancel(&self) {
+        if self.state.cancelled.replace(true) {
+            return;
+        }
+        log::info!("🛑 Request cancelled");
+        for waker in self.state.wakers.borrow_mut().drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Whether the request was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.get()
+    }
+
+    /// `Err(TorError::Cancelled)` once the request was cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(TorError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Resolves once the request is cancelled
+    ///
+    /// Only race this against futures that are safe to drop, such as timers.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            token: self.clone(),
+        }
+    }
+}
+
+/// Future returned by [`CancelToken::cancelled`]
+pub struct Cancelled {
+    token: CancelToken,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.token.state.wakers.borrow_mut();
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn test_cancel_wakes_waiters() {
+        let token = CancelToken::new();
+        let mut cancelled = token.clone().cancelled();
+        assert!(token.check().is_ok());
+        assert!((&mut cancelled).now_or_never().is_none());
+
+        token.clone().cancel();
+        assert!(matches!(token.check(), Err(TorError::Cancelled)));
+        assert!(cancelled.now_or_never().is_some());
+    }
+}
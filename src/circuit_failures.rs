@@ -0,0 +1,301 @@
+//! Circuit build failure analytics
+//!
+//! Counts failed circuit build attempts by the stage that failed and by the
+//! DESTROY reason a relay gave, so operators can tell "bridge down" from
+//! "guards blocked" from "consensus stale" in the field. Only counters are
+//! kept: no relay fingerprints, nicknames or addresses, and no timestamps
+//! finer than the order of recent outcomes.
+//!
+//! The last few builds are also kept as [`BuildReport`]s for diagnostics
+//! bundles. Their error text (and path explanation, when enabled) may name
+//! relays, so they are only exported through
+//! `TorClient::export_diagnostics()`, which redacts it.
+
+use crate::error::TorError;
+use crate::path_explain::SelectionExplanation;
+use crate::runtime::LocalCell;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+/// Number of recent failures the diagnosis looks at
+pub const RECENT_FAILURE_WINDOW: usize = 16;
+
+/// Number of build reports kept
+pub const MAX_BUILD_REPORTS: usize = 20;
+
+/// Stage of a circuit build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildStage {
+    /// No usable guard/middle/exit in the consensus
+    PathSelection,
+    /// Transport connection through the bridge to the guard
+    Connect,
+    /// TLS with the guard
+    Tls,
+    /// VERSIONS / CERTS / NETINFO
+    LinkHandshake,
+    /// CREATE2 with the guard
+    Create,
+    /// EXTEND2 to the middle (or an inner hop of a custom path)
+    ExtendMiddle,
+    /// EXTEND2 to the exit
+    ExtendExit,
+    /// The attempt ran out of time
+    Timeout,
+}
+
+impl BuildStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStage::PathSelection => "path_selection",
+            BuildStage::Connect => "connect",
+            BuildStage::Tls => "tls",
+            BuildStage::LinkHandshake => "link_handshake",
+            BuildStage::Create => "create",
+            BuildStage::ExtendMiddle => "extend_middle",
+            BuildStage::ExtendExit => "extend_exit",
+            BuildStage::Timeout => "timeout",
+        }
+    }
+}
+
+/// Most likely cause of recent failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCause {
+    /// Nothing is failing (the last build succeeded, or none failed)
+    None,
+    /// Transport connections fail: bridge unreachable or down
+    BridgeUnreachable,
+    /// Bridge works but TLS/link handshakes with guards fail
+    GuardsBlocked,
+    /// Relays reject our handshakes (stale onion keys) or the consensus
+    /// has expired
+    ConsensusStale,
+    /// The consensus has no usable relays for a path
+    NoUsableRelays,
+    /// Attempts time out
+    NetworkSlow,
+    /// No single stage dominates
+    Mixed,
+}
+
+/// Failure counters for `get_metrics()`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CircuitFailureReport {
+    pub builds_succeeded: u64,
+    pub attempts_failed: u64,
+    /// Failed attempts by stage
+    pub by_stage: BTreeMap<&'static str, u64>,
+    /// DESTROY cells received during builds, by reason name
+    pub destroy_reasons: BTreeMap<String, u64>,
+}
+
+/// Outcome of one complete build (all of its attempts)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildReport {
+    pub ok: bool,
+    /// Built through a caller-chosen path rather than by path selection
+    pub custom_path: bool,
+    /// Guards tried
+    pub attempts: u32,
+    pub duration_ms: u64,
+    /// Final error, if the build failed
+    pub error: Option<String>,
+    /// Why each relay was chosen, while `explain_paths` is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<SelectionExplanation>,
+}
+
+/// Aggregated circuit build failures
+#[derive(Debug, Default)]
+pub struct CircuitFailureStats {
+    builds_succeeded: u64,
+    by_stage: BTreeMap<BuildStage, u64>,
+    destroy_reasons: BTreeMap<String, u64>,
+    /// Failures since the last successful build (newest last)
+    recent: VecDeque<(BuildStage, Option<u8>)>,
+    /// Most recent builds (newest last)
+    builds: VecDeque<BuildReport>,
+}
+
+/// Failure stats shared between the client and its circuit builder
+pub type SharedFailureStats = Rc<LocalCell<CircuitFailureStats>>;
+
+/// Create an empty shared stats handle
+pub fn new_shared_failure_stats() -> SharedFailureStats {
+    Rc::new(LocalCell::new(CircuitFailureStats::default()))
+}
+
+impl CircuitFailureStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a failed build attempt
+    pub fn record_failure(&mut self, stage: BuildStage, error: &TorError) {
+        *self.by_stage.entry(stage).or_default() += 1;
+
+        let reason = match error {
+            TorError::CircuitDestroyed {
+                reason,
+                reason_name,
+            } => {
+                *self.destroy_reasons.entry(reason_name.clone()).or_default() += 1;
+                Some(*reason)
+            }
+            _ => None,
+        };
+
+        if self.recent.len() == RECENT_FAILURE_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((stage, reason));
+    }
+
+    /// Record a completed build
+    pub fn record_success(&mut self) {
+        self.builds_succeeded += 1;
+        self.recent.clear();
+    }
+
+    /// Record the outcome of a complete build
+    pub fn record_build(&mut self, report: BuildReport) {
+        if self.builds.len() == MAX_BUILD_REPORTS {
+            self.builds.pop_front();
+        }
+        self.builds.push_back(report);
+    }
+
+    /// The most recent build reports, oldest first
+    pub fn recent_builds(&self) -> Vec<BuildReport> {
+        self.builds.iter().cloned().collect()
+    }
+
+    /// Guess why builds are failing from the failures since the last
+    /// success. `consensus_fresh` is whether the client's consensus is
+    /// still fresh.
+    pub fn diagnose(&self, consensus_fresh: bool) -> FailureCause {
+        if self.recent.is_empty() {
+            return FailureCause::None;
+        }
+        if !consensus_fresh {
+            return FailureCause::ConsensusStale;
+        }
+
+        let mut tally: BTreeMap<FailureCause, usize> = BTreeMap::new();
+        for &(stage, reason) in &self.recent {
+            let cause = match (stage, reason) {
+                (BuildStage::Connect, _) => FailureCause::BridgeUnreachable,
+                (BuildStage::Tls | BuildStage::LinkHandshake, _) => FailureCause::GuardsBlocked,
+                // PROTOCOL / OR_IDENTITY: our view of the relay's keys is wrong
+                (_, Some(1 | 7)) => FailureCause::ConsensusStale,
+                (BuildStage::PathSelection, _) => FailureCause::NoUsableRelays,
+                (BuildStage::Timeout, _) => FailureCause::NetworkSlow,
+                _ => FailureCause::Mixed,
+            };
+            *tally.entry(cause).or_default() += 1;
+        }
+
+        // A cause wins if it accounts for more than half the recent failures
+        tally
+            .into_iter()
+            .find(|&(_, n)| n * 2 > self.recent.len())
+            .map(|(cause, _)| cause)
+            .unwrap_or(FailureCause::Mixed)
+    }
+
+    /// Current counters
+    pub fn report(&self) -> CircuitFailureReport {
+        CircuitFailureReport {
+            builds_succeeded: self.builds_succeeded,
+            attempts_failed: self.by_stage.values().sum(),
+            by_stage: self
+                .by_stage
+                .iter()
+                .map(|(stage, n)| (stage.as_str(), *n))
+                .collect(),
+            destroy_reasons: self.destroy_reasons.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(stats: &mut CircuitFailureStats, stage: BuildStage, n: usize) {
+        for _ in 0..n {
+            stats.record_failure(stage, &TorError::ConnectionFailed("x".into()));
+        }
+    }
+
+    #[test]
+    fn test_counts_by_stage_and_reason() {
+        let mut stats = CircuitFailureStats::new();
+        failed(&mut stats, BuildStage::Connect, 2);
+        stats.record_failure(BuildStage::Create, &TorError::circuit_destroyed(1));
+        stats.record_failure(BuildStage::ExtendExit, &TorError::circuit_destroyed(6));
+        stats.record_success();
+
+        let report = stats.report();
+        assert_eq!(report.builds_succeeded, 1);
+        assert_eq!(report.attempts_failed, 4);
+        assert_eq!(report.by_stage["connect"], 2);
+        assert_eq!(report.by_stage["extend_exit"], 1);
+        assert_eq!(report.destroy_reasons["PROTOCOL"], 1);
+        assert_eq!(report.destroy_reasons["CONNECTFAILED"], 1);
+    }
+
+    #[test]
+    fn test_diagnosis() {
+        let mut stats = CircuitFailureStats::new();
+        assert_eq!(stats.diagnose(true), FailureCause::None);
+
+        failed(&mut stats, BuildStage::Connect, 3);
+        assert_eq!(stats.diagnose(true), FailureCause::BridgeUnreachable);
+        assert_eq!(stats.diagnose(false), FailureCause::ConsensusStale);
+
+        stats.record_success();
+        failed(&mut stats, BuildStage::Tls, 2);
+        failed(&mut stats, BuildStage::LinkHandshake, 1);
+        assert_eq!(stats.diagnose(true), FailureCause::GuardsBlocked);
+
+        stats.record_success();
+        for _ in 0..3 {
+            stats.record_failure(BuildStage::Create, &TorError::circuit_destroyed(1));
+        }
+        failed(&mut stats, BuildStage::Timeout, 1);
+        assert_eq!(stats.diagnose(true), FailureCause::ConsensusStale);
+
+        failed(&mut stats, BuildStage::Timeout, 2);
+        assert_eq!(stats.diagnose(true), FailureCause::Mixed);
+
+        failed(&mut stats, BuildStage::Connect, RECENT_FAILURE_WINDOW);
+        assert_eq!(stats.diagnose(true), FailureCause::BridgeUnreachable);
+    }
+
+    #[test]
+    fn test_build_reports_are_bounded() {
+        let mut stats = CircuitFailureStats::new();
+        for i in 0..MAX_BUILD_REPORTS as u64 + 5 {
+            stats.record_build(BuildReport {
+                ok: i % 2 == 0,
+                custom_path: false,
+                attempts: 1,
+                duration_ms: i,
+                error: None,
+                explanation: None,
+            });
+        }
+        let builds = stats.recent_builds();
+        assert_eq!(builds.len(), MAX_BUILD_REPORTS);
+        assert_eq!(builds[0].duration_ms, 5);
+        assert_eq!(
+            builds.last().unwrap().duration_ms,
+            MAX_BUILD_REPORTS as u64 + 4
+        );
+    }
+}
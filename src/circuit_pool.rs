@@ -5,12 +5,24 @@
 //! Security considerations:
 //! - Limited pool size (prevents fingerprinting)
 //! - Circuit expiration (stale circuits are suspicious)
-//! - No destination-specific prebuilding (reveals intent)
+//! - No destination-specific prebuilding (reveals intent): circuits are
+//!   only warmed per coarse port class, sized by how many isolation keys
+//!   recently needed a new circuit of that class
+//! - Usage history is short-lived, bounded and dropped with `clear()`
+//! - Only circuits that never carried a stream are cannibalized (reshaped
+//!   for a specific exit or an extra hop), so a reshaped circuit can't link
+//!   its new use to earlier traffic
 
-use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
 
-use crate::error::Result;
-use crate::protocol::{Circuit, CircuitBuilder, RelaySelector};
+use crate::error::{Result, TorError};
+use crate::protocol::{
+    same_ipv4_slash16, Circuit, CircuitBuilder, Relay, RelaySelector, StreamLifetime,
+};
+
+/// Most recent circuit demands remembered for prediction
+const MAX_DEMAND_SAMPLES: usize = 64;
 
 /// Timestamp in milliseconds (WASM-compatible)
 fn now_ms() -> u64 {
@@ -29,7 +41,8 @@ fn now_ms() -> u64 {
 }
 
 /// Configuration for circuit pool
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CircuitPoolConfig {
     /// Maximum number of prebuilt circuits
     pub max_prebuilt: usize,
@@ -39,32 +52,138 @@ pub struct CircuitPoolConfig {
     pub min_circuits: usize,
     /// How often to check for maintenance (ms)
     pub maintenance_interval_ms: u64,
+    /// How far back circuit demand counts towards warm-up targets (ms)
+    pub prediction_window_ms: u64,
 }
 
 impl Default for CircuitPoolConfig {
     fn default() -> Self {
         Self {
-            max_prebuilt: 3,                 // Security: limit to prevent fingerprinting
-            max_age_ms: 10 * 60 * 1000,      // 10 minutes
-            min_circuits: 1,                 // Keep at least 1 ready
-            maintenance_interval_ms: 30_000, // Check every 30s
+            max_prebuilt: 3,                     // Security: limit to prevent fingerprinting
+            max_age_ms: 10 * 60 * 1000,          // 10 minutes
+            min_circuits: 1,                     // Keep at least 1 ready
+            maintenance_interval_ms: 30_000,     // Check every 30s
+            prediction_window_ms: 5 * 60 * 1000, // 5 minutes
         }
     }
 }
 
+/// Kind of stream a prebuilt circuit is warmed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortClass {
+    /// Web (80/443) and other request/response traffic
+    General,
+    /// Streams to long-lived ports (SSH, IMAP, chat); Stable relays only
+    LongLived,
+    /// Onion service introduction; never prebuilt, since introduction and
+    /// HSDir circuits are cannibalized from general ones
+    OnionIntro,
+}
+
+impl PortClass {
+    /// Every class, in warm-up priority order
+    pub const ALL: [PortClass; 3] = [
+        PortClass::General,
+        PortClass::LongLived,
+        PortClass::OnionIntro,
+    ];
+
+    /// Class of circuit a stream of `lifetime` needs
+    pub fn for_lifetime(lifetime: StreamLifetime) -> Self {
+        match lifetime {
+            StreamLifetime::Short => PortClass::General,
+            StreamLifetime::LongLived => PortClass::LongLived,
+        }
+    }
+
+    /// Lifetime to select relays for when building a circuit of this class
+    pub fn lifetime(self) -> StreamLifetime {
+        match self {
+            PortClass::LongLived => StreamLifetime::LongLived,
+            PortClass::General | PortClass::OnionIntro => StreamLifetime::Short,
+        }
+    }
+
+    /// Whether circuits of this class can be built ahead of time
+    pub fn is_prebuildable(self) -> bool {
+        self != PortClass::OnionIntro
+    }
+
+    /// Name used in stats (`"general"`, `"long_lived"`, `"onion_intro"`)
+    pub fn name(self) -> &'static str {
+        match self {
+            PortClass::General => "general",
+            PortClass::LongLived => "long_lived",
+            PortClass::OnionIntro => "onion_intro",
+        }
+    }
+}
+
+/// A circuit shape plain prebuilt circuits don't have
+#[derive(Debug, Clone)]
+pub enum CircuitTarget {
+    /// A three-hop circuit ending at this exit
+    Exit(Relay),
+    /// A four-hop circuit: a normal path plus this relay (a rendezvous
+    /// point, say), which needn't allow exits
+    ExtraHop(Relay),
+}
+
+impl CircuitTarget {
+    /// The relay the circuit must end at
+    pub fn relay(&self) -> &Relay {
+        match self {
+            CircuitTarget::Exit(relay) | CircuitTarget::ExtraHop(relay) => relay,
+        }
+    }
+
+    /// Whether a three-hop circuit through `path` can be reshaped into
+    /// this target: none of the hops kept may be the target relay, in its
+    /// family or in its /16
+    pub fn fits(&self, path: &[Relay]) -> bool {
+        if path.len() != 3 {
+            return false;
+        }
+        let kept = match self {
+            CircuitTarget::Exit(_) => &path[..2],
+            CircuitTarget::ExtraHop(_) => path,
+        };
+        let relay = self.relay();
+        kept.iter().all(|hop| {
+            hop.fingerprint != relay.fingerprint
+                && !CircuitBuilder::relays_share_family(hop, relay)
+                && !same_ipv4_slash16(hop, relay)
+        })
+    }
+}
+
+/// An isolation key needed a new circuit of some class
+struct Demand {
+    at: u64,
+    class: PortClass,
+    key: String,
+}
+
 /// A prebuilt circuit ready for use
 struct PrebuiltCircuit {
     /// The circuit itself (owned)
     circuit: Circuit,
+    /// Kind of stream it was built for
+    class: PortClass,
     /// When it was created
     created_at: u64,
+    /// Never handed out (prebuilt, not returned after use)
+    fresh: bool,
 }
 
 impl PrebuiltCircuit {
-    fn new(circuit: Circuit) -> Self {
+    fn new(circuit: Circuit, class: PortClass, fresh: bool) -> Self {
         Self {
             circuit,
+            class,
             created_at: now_ms(),
+            fresh,
         }
     }
 
@@ -85,6 +204,8 @@ pub struct PrebuiltCircuitPool {
     config: CircuitPoolConfig,
     /// Last maintenance time
     last_maintenance: u64,
+    /// Recent circuit demand, oldest first
+    demand: VecDeque<Demand>,
     /// Statistics
     stats: CircuitPoolStats,
 }
@@ -100,6 +221,8 @@ pub struct CircuitPoolStats {
     pub pool_misses: u64,
     /// Circuits expired (too old)
     pub circuits_expired: u64,
+    /// Prebuilt circuits reshaped for a [`CircuitTarget`]
+    pub circuits_cannibalized: u64,
     /// Current pool size
     pub current_pool_size: usize,
 }
@@ -116,50 +239,244 @@ impl PrebuiltCircuitPool {
             available: VecDeque::new(),
             config,
             last_maintenance: now_ms(),
+            demand: VecDeque::new(),
             stats: CircuitPoolStats::default(),
         }
     }
 
-    /// Get a circuit from the pool, or build a new one
+    /// Get a circuit of `class` from the pool, or build a new one
     ///
     /// This is the main entry point - returns a ready-to-use circuit.
+    /// `selector` is narrowed to the class's relay requirements here.
     pub async fn get_circuit(
         &mut self,
         builder: &CircuitBuilder,
         selector: &RelaySelector,
+        class: PortClass,
+    ) -> Result<Circuit> {
+        if let Some(circuit) = self.take(class) {
+            return Ok(circuit);
+        }
+
+        // Build new circuit
+        log::info!("Building new {} circuit (pool empty)", class.name());
+        let selector = selector.clone().for_stream(class.lifetime());
+        let circuit = builder.build_circuit(&selector).await?;
+        self.stats.circuits_built += 1;
+
+        Ok(circuit)
+    }
+
+    /// Get a circuit shaped for `target`, cannibalizing a prebuilt one when
+    /// possible
+    ///
+    /// A fresh general circuit whose kept hops fit the target is truncated
+    /// and/or extended in place, saving the guard and middle handshakes.
+    /// If none fits or reshaping fails, a circuit is built cold.
+    pub async fn get_circuit_for(
+        &mut self,
+        builder: &CircuitBuilder,
+        selector: &RelaySelector,
+        target: &CircuitTarget,
     ) -> Result<Circuit> {
+        builder.check_blocklist(target.relay())?;
+        if let Some(mut circuit) = self.take_for(target) {
+            let reshaped = match target {
+                CircuitTarget::Exit(exit) => circuit.replace_exit(exit).await,
+                CircuitTarget::ExtraHop(relay) => circuit.extend_to(relay).await,
+            };
+            match reshaped {
+                Ok(()) => {
+                    log::info!(
+                        "🍴 Cannibalized circuit {} to end at {}",
+                        circuit.id,
+                        target.relay().nickname
+                    );
+                    self.stats.circuits_cannibalized += 1;
+                    return Ok(circuit);
+                }
+                Err(e) => {
+                    log::warn!("Cannibalizing circuit {} failed: {}", circuit.id, e);
+                    let _ = circuit.destroy().await;
+                }
+            }
+        }
+
+        log::info!(
+            "Building new circuit to {} (nothing to cannibalize)",
+            target.relay().nickname
+        );
+        let circuit = match target {
+            CircuitTarget::Exit(exit) => {
+                let path = Self::path_to_exit(selector, exit)?;
+                builder.build_circuit_through(&path).await?
+            }
+            CircuitTarget::ExtraHop(relay) => {
+                let mut circuit = builder.build_circuit(selector).await?;
+                if !target.fits(&circuit.relays) {
+                    let _ = circuit.destroy().await;
+                    return Err(TorError::CircuitBuildFailed(format!(
+                        "Built path conflicts with {}",
+                        relay.nickname
+                    )));
+                }
+                circuit.extend_to(relay).await?;
+                circuit
+            }
+        };
+        self.stats.circuits_built += 1;
+        Ok(circuit)
+    }
+
+    /// Guard → middle → `exit`, passing the usual path checks
+    fn path_to_exit(selector: &RelaySelector, exit: &Relay) -> Result<Vec<Relay>> {
+        let no_path =
+            || TorError::CircuitBuildFailed(format!("No usable path to exit {}", exit.nickname));
+        let guard = selector.select_guard().ok_or_else(no_path)?;
+        selector
+            .select_middles(5, &[&guard.fingerprint, &exit.fingerprint])
+            .into_iter()
+            .map(|middle| vec![guard.clone(), middle.clone(), exit.clone()])
+            .find(|path| CircuitBuilder::validate_path(path, true).is_ok())
+            .ok_or_else(no_path)
+    }
+
+    /// Take a fresh, connected general circuit that can be reshaped into
+    /// `target`
+    fn take_for(&mut self, target: &CircuitTarget) -> Option<Circuit> {
+        self.maybe_expire_old_circuits();
+        let index = self.available.iter().position(|p| {
+            p.fresh
+                && p.class == PortClass::General
+                && p.circuit.is_connected()
+                && target.fits(&p.circuit.relays)
+        })?;
+        let taken = self.available.remove(index).map(|p| p.circuit);
+        self.stats.current_pool_size = self.available.len();
+        taken
+    }
+
+    /// Take a healthy prebuilt circuit of `class`, if there is one
+    ///
+    /// Counts a pool hit or miss either way.
+    pub fn take(&mut self, class: PortClass) -> Option<Circuit> {
         // Run maintenance if needed
         self.maybe_expire_old_circuits();
 
-        // Try to get from pool (check health before handing out)
-        while let Some(prebuilt) = self.available.pop_front() {
-            if prebuilt.circuit.is_connected() {
+        // Drop dead circuits of this class before handing one out
+        let before = self.available.len();
+        self.available
+            .retain(|p| p.class != class || p.circuit.is_connected());
+        if self.available.len() < before {
+            log::debug!("Skipping disconnected circuits in pool");
+        }
+
+        let index = self.available.iter().position(|p| p.class == class);
+        let taken = index.and_then(|i| self.available.remove(i));
+        self.stats.current_pool_size = self.available.len();
+        match taken {
+            Some(prebuilt) => {
                 log::info!(
-                    "Using prebuilt circuit (age: {}ms, pool remaining: {})",
+                    "Using prebuilt {} circuit (age: {}ms, pool remaining: {})",
+                    class.name(),
                     prebuilt.age_ms(),
                     self.available.len()
                 );
                 self.stats.pool_hits += 1;
-                self.stats.current_pool_size = self.available.len();
-                return Ok(prebuilt.circuit);
+                Some(prebuilt.circuit)
+            }
+            None => {
+                self.stats.pool_misses += 1;
+                None
             }
-            log::debug!("Skipping disconnected circuit in pool");
         }
+    }
 
-        // Build new circuit
-        log::info!("Building new circuit (pool empty)");
-        self.stats.pool_misses += 1;
+    /// Note that isolation key `key` needed a new circuit of `class`
+    ///
+    /// Drives how many circuits of each class `warm_up` keeps ready.
+    pub fn record_demand(&mut self, key: &str, class: PortClass) {
+        let now = now_ms();
+        self.forget_old_demand(now);
+        if self.demand.len() >= MAX_DEMAND_SAMPLES {
+            self.demand.pop_front();
+        }
+        self.demand.push_back(Demand {
+            at: now,
+            class,
+            key: key.to_string(),
+        });
+    }
 
-        let circuit = builder.build_circuit(selector).await?;
-        self.stats.circuits_built += 1;
+    /// Drop demand older than the prediction window
+    fn forget_old_demand(&mut self, now: u64) {
+        let window = self.config.prediction_window_ms;
+        while self
+            .demand
+            .front()
+            .is_some_and(|d| now.saturating_sub(d.at) > window)
+        {
+            self.demand.pop_front();
+        }
+    }
 
-        Ok(circuit)
+    /// Distinct isolation keys that needed a circuit of `class` within the
+    /// prediction window
+    fn predicted_demand(&self, class: PortClass, now: u64) -> usize {
+        let window = self.config.prediction_window_ms;
+        self.demand
+            .iter()
+            .filter(|d| d.class == class && now.saturating_sub(d.at) <= window)
+            .map(|d| d.key.as_str())
+            .collect::<HashSet<_>>()
+            .len()
     }
 
-    /// Return a circuit to the pool for reuse
+    /// How many circuits of each class to keep ready
+    ///
+    /// General circuits are kept at `min_circuits` at least; every class
+    /// wants one circuit per isolation key that recently needed one. Slots
+    /// up to `max_prebuilt` are handed out one per class in turn, so busy
+    /// web browsing can't starve the occasional SSH session.
+    pub fn targets(&self) -> Vec<(PortClass, usize)> {
+        let now = now_ms();
+        let wanted: Vec<usize> = PortClass::ALL
+            .iter()
+            .map(|&class| {
+                if !class.is_prebuildable() {
+                    return 0;
+                }
+                let demand = self.predicted_demand(class, now);
+                if class == PortClass::General {
+                    demand.max(self.config.min_circuits)
+                } else {
+                    demand
+                }
+            })
+            .collect();
+
+        let mut targets = vec![0; PortClass::ALL.len()];
+        let mut slots = self.config.max_prebuilt;
+        while slots > 0 {
+            let mut handed_out = false;
+            for (target, want) in targets.iter_mut().zip(&wanted) {
+                if slots > 0 && *target < *want {
+                    *target += 1;
+                    slots -= 1;
+                    handed_out = true;
+                }
+            }
+            if !handed_out {
+                break;
+            }
+        }
+        PortClass::ALL.into_iter().zip(targets).collect()
+    }
+
+    /// Return a circuit built for `class` to the pool for reuse
     ///
     /// Circuit will be kept if pool has room and circuit is healthy.
-    pub fn return_circuit(&mut self, circuit: Circuit) {
+    pub fn return_circuit(&mut self, circuit: Circuit, class: PortClass) {
         // Don't return if pool is full
         if self.available.len() >= self.config.max_prebuilt {
             log::debug!("Pool full, dropping circuit");
@@ -172,40 +489,48 @@ impl PrebuiltCircuitPool {
             return;
         }
 
-        self.available.push_back(PrebuiltCircuit {
-            circuit,
-            created_at: now_ms(),
-        });
+        self.available
+            .push_back(PrebuiltCircuit::new(circuit, class, false));
         self.stats.current_pool_size = self.available.len();
         log::info!("Circuit returned to pool (size: {})", self.available.len());
     }
 
-    /// Prebuild circuits up to the minimum
+    /// Prebuild circuits of each class up to its target (see [`targets`])
+    ///
+    /// Call this after bootstrap to have circuits ready, and periodically
+    /// to refill what requests took.
     ///
-    /// Call this after bootstrap to have circuits ready.
+    /// [`targets`]: PrebuiltCircuitPool::targets
     pub async fn warm_up(
         &mut self,
         builder: &CircuitBuilder,
         selector: &RelaySelector,
     ) -> Result<usize> {
+        self.expire_old_circuits();
         let mut built = 0;
 
-        while self.available.len() < self.config.min_circuits {
-            log::info!(
-                "🔥 Warming up circuit pool ({}/{})",
-                self.available.len(),
-                self.config.min_circuits
-            );
-
-            match builder.build_circuit(selector).await {
-                Ok(circuit) => {
-                    self.available.push_back(PrebuiltCircuit::new(circuit));
-                    self.stats.circuits_built += 1;
-                    built += 1;
-                }
-                Err(e) => {
-                    log::warn!("Failed to prebuild circuit: {}", e);
-                    break;
+        for (class, target) in self.targets() {
+            let class_selector = selector.clone().for_stream(class.lifetime());
+            while self.size_of(class) < target {
+                log::info!(
+                    "🔥 Warming up {} circuits ({}/{})",
+                    class.name(),
+                    self.size_of(class),
+                    target
+                );
+
+                match builder.build_circuit(&class_selector).await {
+                    Ok(circuit) => {
+                        self.available
+                            .push_back(PrebuiltCircuit::new(circuit, class, true));
+                        self.stats.circuits_built += 1;
+                        built += 1;
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to prebuild {} circuit: {}", class.name(), e);
+                        self.stats.current_pool_size = self.available.len();
+                        return Ok(built);
+                    }
                 }
             }
         }
@@ -260,22 +585,58 @@ impl PrebuiltCircuitPool {
         }
     }
 
+    /// Current pool configuration
+    pub fn config(&self) -> &CircuitPoolConfig {
+        &self.config
+    }
+
+    /// Use a new configuration; circuits beyond the new `max_prebuilt` are
+    /// dropped, the rest age out under the new limits
+    pub fn set_config(&mut self, config: CircuitPoolConfig) {
+        self.available.truncate(config.max_prebuilt);
+        self.stats.current_pool_size = self.available.len();
+        self.config = config;
+    }
+
     /// Get current pool size
     pub fn size(&self) -> usize {
         self.available.len()
     }
 
+    /// Number of pooled circuits of `class`
+    pub fn size_of(&self, class: PortClass) -> usize {
+        self.available.iter().filter(|p| p.class == class).count()
+    }
+
+    /// Pooled circuits, next to be handed out first
+    pub fn circuits(&self) -> impl Iterator<Item = &Circuit> {
+        self.available.iter().map(|p| &p.circuit)
+    }
+
+    /// Keep only the pooled circuits for which `keep` returns true
+    pub fn retain(&mut self, mut keep: impl FnMut(&Circuit) -> bool) {
+        self.available.retain(|p| keep(&p.circuit));
+        self.stats.current_pool_size = self.available.len();
+    }
+
     /// Check if pool has available circuits
     pub fn has_available(&self) -> bool {
         !self.available.is_empty()
     }
 
-    /// Clear all circuits from pool
+    /// Clear all circuits and usage history from pool
     pub fn clear(&mut self) {
         self.available.clear();
+        self.demand.clear();
         self.stats.current_pool_size = 0;
         log::info!("Circuit pool cleared");
     }
+
+    /// Remove and return all pooled circuits (for explicit teardown)
+    pub fn drain(&mut self) -> Vec<Circuit> {
+        self.stats.current_pool_size = 0;
+        self.available.drain(..).map(|p| p.circuit).collect()
+    }
 }
 
 impl Default for PrebuiltCircuitPool {
@@ -287,6 +648,7 @@ impl Default for PrebuiltCircuitPool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::TestRelay;
 
     #[test]
     fn test_pool_config_defaults() {
@@ -303,4 +665,78 @@ mod tests {
         assert_eq!(stats.pool_hits, 0);
         assert_eq!(stats.pool_misses, 0);
     }
+
+    fn target(pool: &PrebuiltCircuitPool, class: PortClass) -> usize {
+        pool.targets()
+            .into_iter()
+            .find(|(c, _)| *c == class)
+            .map(|(_, n)| n)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_targets_follow_recent_demand() {
+        let mut pool = PrebuiltCircuitPool::new();
+        assert_eq!(target(&pool, PortClass::General), 1);
+        assert_eq!(target(&pool, PortClass::LongLived), 0);
+
+        // Repeat demand from one key counts once
+        pool.record_demand("a.example", PortClass::General);
+        pool.record_demand("b.example", PortClass::General);
+        pool.record_demand("b.example", PortClass::General);
+        pool.record_demand("mail.example", PortClass::LongLived);
+        pool.record_demand("intro", PortClass::OnionIntro);
+        assert_eq!(target(&pool, PortClass::General), 2);
+        assert_eq!(target(&pool, PortClass::LongLived), 1);
+        assert_eq!(target(&pool, PortClass::OnionIntro), 0);
+
+        pool.clear();
+        assert_eq!(target(&pool, PortClass::LongLived), 0);
+    }
+
+    #[test]
+    fn test_targets_share_max_prebuilt() {
+        let mut pool = PrebuiltCircuitPool::with_config(CircuitPoolConfig {
+            max_prebuilt: 2,
+            ..Default::default()
+        });
+        for key in ["a", "b", "c", "d"] {
+            pool.record_demand(key, PortClass::General);
+        }
+        pool.record_demand("ssh", PortClass::LongLived);
+        assert_eq!(target(&pool, PortClass::General), 1);
+        assert_eq!(target(&pool, PortClass::LongLived), 1);
+        assert!(pool.take(PortClass::LongLived).is_none());
+        assert_eq!(pool.get_stats().pool_misses, 1);
+    }
+
+    fn relay(nickname: &str, address: &str) -> Relay {
+        TestRelay::new(nickname)
+            .fingerprint(&format!("{:0>40}", nickname.len()))
+            .address(address)
+            .ntor_onion_key(Some("AAAA"))
+            .build()
+    }
+
+    #[test]
+    fn test_cannibalize_target_fits() {
+        let path = vec![
+            relay("g", "10.1.0.1"),
+            relay("mm", "10.2.0.1"),
+            relay("eee", "10.3.0.1"),
+        ];
+        let exit = relay("xxxx", "10.4.0.1");
+        assert!(CircuitTarget::Exit(exit.clone()).fits(&path));
+        assert!(CircuitTarget::ExtraHop(exit).fits(&path));
+
+        // The exit being replaced may share a /16 with the new one; kept hops may not
+        let near_exit = relay("xxxx", "10.3.9.9");
+        assert!(CircuitTarget::Exit(near_exit.clone()).fits(&path));
+        assert!(!CircuitTarget::ExtraHop(near_exit).fits(&path));
+        assert!(!CircuitTarget::Exit(relay("xxxx", "10.2.9.9")).fits(&path));
+
+        // Relays already on the path, and paths of other lengths, never fit
+        assert!(!CircuitTarget::Exit(path[1].clone()).fits(&path));
+        assert!(!CircuitTarget::Exit(relay("xxxx", "10.4.0.1")).fits(&path[..2]));
+    }
 }
@@ -0,0 +1,627 @@
+//! Unified client configuration
+//!
+//! [`TorClientConfig`] gathers the settings that are otherwise made through
+//! separate calls (bridge and network, isolation, circuit pool, keepalive,
+//! HTTP padding, relay requirements, rate limits, redirects, body
+//! decoding and the operator blocklist) into one struct that
+//! JavaScript passes as JSON. Every section and field may be omitted and
+//! takes its default; unknown sections are rejected so typos don't silently
+//! fall back to defaults.
+//!
+//! A running client can be reconfigured with a new config:
+//! [`TorClientConfig::diff`] sorts the changes into those applied right away
+//! and those held back until the next new identity, because they would
+//! otherwise mix old and new connections or guards under one identity.
+
+use serde::{Deserialize, Serialize};
+
+use crate::bandwidth_quota::{BandwidthQuotaConfig, QuotaAction};
+use crate::blocklist::BlocklistConfig;
+use crate::circuit_pool::CircuitPoolConfig;
+use crate::error::{Result, TorError};
+use crate::guards::{MAX_GUARDS, MIN_GUARDS};
+use crate::http_client::HttpClientConfig;
+use crate::http_padding::HttpPaddingConfig;
+use crate::http_policy::HttpSecurityConfig;
+use crate::isolation::{IsolationConfig, IsolationType};
+use crate::keepalive::KeepaliveConfig;
+use crate::log_ring::{self, DEFAULT_LOG_CAPACITY, MAX_LOG_CAPACITY};
+use crate::network::NetworkConfig;
+use crate::protocol::{debug, RelayRequirements};
+use crate::rate_limiter::RateLimiterConfig;
+use crate::traffic_shaping::RequestJitterConfig;
+
+/// Complete configuration of a `TorClient`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TorClientConfig {
+    /// Bridge URL, timeouts, retries and bridge framing
+    pub network: NetworkConfig,
+    /// Circuit isolation policy and circuit retirement limits
+    pub isolation: IsolationConfig,
+    /// Prebuilt circuit pool
+    pub circuit_pool: CircuitPoolConfig,
+    /// Idle circuit probing
+    pub keepalive: KeepaliveConfig,
+    /// Default HTTP request padding (per-URL overrides are set separately)
+    pub http_padding: HttpPaddingConfig,
+    /// Default random delay before requests (per-URL overrides are set
+    /// separately)
+    pub request_jitter: RequestJitterConfig,
+    /// Minimum bandwidth / flags for relays on new circuits
+    pub relay_requirements: RelayRequirements,
+    /// Abuse-prevention limits
+    pub rate_limit: RateLimiterConfig,
+    /// In-memory log ring and protocol dumps
+    pub logging: LoggingConfig,
+    /// Entry guard selection
+    pub guards: GuardConfig,
+    /// Plain-HTTP policy
+    pub http: HttpSecurityConfig,
+    /// Redirects and content decoding for `fetch_json()` / `fetch_bytes()`
+    pub http_client: HttpClientConfig,
+    /// Session, daily and per-request byte quotas
+    pub bandwidth_quota: BandwidthQuotaConfig,
+    /// Signed operator blocklist of relays never to use
+    pub blocklist: BlocklistConfig,
+    /// Where persistent state is kept (fixed for the client's lifetime)
+    pub storage: StorageConfig,
+}
+
+/// Logging settings (process-wide, shared by every client)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Records kept for `get_recent_logs()` (default: 500, 0 = off)
+    pub log_capacity: usize,
+    /// Log byte-level protocol dumps (default: false)
+    pub debug_protocol: bool,
+    /// Explain relay choices in circuit build reports (default: false)
+    pub explain_paths: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            log_capacity: DEFAULT_LOG_CAPACITY,
+            debug_protocol: false,
+            explain_paths: false,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// The settings currently in effect
+    pub fn current() -> Self {
+        Self {
+            log_capacity: log_ring::log_capacity(),
+            debug_protocol: debug::debug_protocol(),
+            explain_paths: debug::explain_paths(),
+        }
+    }
+
+    /// Make these the process-wide settings
+    pub fn apply(&self) {
+        log_ring::set_log_capacity(self.log_capacity);
+        debug::set_debug_protocol(self.debug_protocol);
+        debug::set_explain_paths(self.explain_paths);
+    }
+}
+
+/// Entry guard settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuardConfig {
+    /// Guards chosen at each selection (default: 5)
+    pub count: usize,
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        Self { count: MAX_GUARDS }
+    }
+}
+
+/// Longest storage namespace accepted
+pub const MAX_NAMESPACE_LEN: usize = 64;
+
+/// Persistent storage settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Suffix keeping this client's guards, quotas and IndexedDB apart from
+    /// other clients on the same origin (default: "", the shared keys).
+    /// The first namespaced database created takes over the records of the
+    /// shared one.
+    pub namespace: String,
+}
+
+impl StorageConfig {
+    /// `base` (a `localStorage` key or IndexedDB name) in this namespace
+    pub fn key(&self, base: &str) -> String {
+        if self.namespace.is_empty() {
+            base.to_string()
+        } else {
+            format!("{}.{}", base, self.namespace)
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let ns = &self.namespace;
+        if ns.len() > MAX_NAMESPACE_LEN
+            || !ns
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(invalid(format!(
+                "storage.namespace must be at most {} letters, digits, '-' or '_', got {:?}",
+                MAX_NAMESPACE_LEN, ns
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What a reconfiguration changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConfigChange {
+    /// Sections and fields that took effect immediately
+    pub applied: Vec<&'static str>,
+    /// Sections and fields held back until the next new identity
+    pub deferred: Vec<&'static str>,
+}
+
+impl ConfigChange {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.deferred.is_empty()
+    }
+}
+
+impl TorClientConfig {
+    /// Start building a configuration from the defaults
+    pub fn builder() -> TorClientConfigBuilder {
+        TorClientConfigBuilder::default()
+    }
+
+    /// Parse and validate the JSON passed from JavaScript
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: Self = serde_json::from_str(json)
+            .map_err(|e| TorError::ParseError(format!("Invalid client config: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that every section holds usable values
+    pub fn validate(&self) -> Result<()> {
+        let url = &self.network.bridge_url;
+        let has_scheme = ["ws://", "wss://", "http://", "https://"]
+            .iter()
+            .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme));
+        if !has_scheme {
+            return Err(invalid(format!(
+                "network.bridge_url must be a ws(s):// or http(s):// URL, got {:?}",
+                url
+            )));
+        }
+
+        if let Some(key) = &self.network.blinding_key {
+            crate::network::decode_key(key)
+                .map_err(|e| invalid(format!("network.blinding_key: {}", e)))?;
+        }
+
+        let nonzero = [
+            ("network.connect_timeout", self.network.connect_timeout),
+            (
+                "network.max_connections",
+                self.network.max_connections as u64,
+            ),
+            (
+                "isolation.max_circuit_age_secs",
+                self.isolation.max_circuit_age.as_secs(),
+            ),
+            (
+                "isolation.max_requests_per_circuit",
+                self.isolation.max_requests_per_circuit as u64,
+            ),
+            (
+                "isolation.max_cached_circuits",
+                self.isolation.max_cached_circuits as u64,
+            ),
+            (
+                "isolation.max_streams_per_circuit",
+                self.isolation.max_streams_per_circuit as u64,
+            ),
+            (
+                "isolation.max_circuits_per_key",
+                self.isolation.max_circuits_per_key as u64,
+            ),
+            (
+                "circuit_pool.maintenance_interval_ms",
+                self.circuit_pool.maintenance_interval_ms,
+            ),
+            (
+                "keepalive.probe_interval_ms",
+                self.keepalive.probe_interval_ms,
+            ),
+            ("keepalive.idle_after_ms", self.keepalive.idle_after_ms),
+            (
+                "keepalive.send_timeout_ms",
+                self.keepalive.send_timeout_ms as u64,
+            ),
+            (
+                "rate_limit.circuits_per_minute",
+                self.rate_limit.circuits_per_minute as u64,
+            ),
+            (
+                "rate_limit.streams_per_circuit",
+                self.rate_limit.streams_per_circuit as u64,
+            ),
+            ("rate_limit.key_burst", self.rate_limit.key_burst as u64),
+            (
+                "rate_limit.key_circuits_per_minute",
+                self.rate_limit.key_circuits_per_minute as u64,
+            ),
+            ("rate_limit.window_ms", self.rate_limit.window_ms),
+            (
+                "http_client.max_body_bytes",
+                self.http_client.max_body_bytes,
+            ),
+        ];
+        if let Some((field, _)) = nonzero.iter().find(|(_, value)| *value == 0) {
+            return Err(invalid(format!("{} must be greater than 0", field)));
+        }
+
+        if self.logging.log_capacity > MAX_LOG_CAPACITY {
+            return Err(invalid(format!(
+                "logging.log_capacity is limited to {}",
+                MAX_LOG_CAPACITY
+            )));
+        }
+        if !(MIN_GUARDS..=MAX_GUARDS).contains(&self.guards.count) {
+            return Err(invalid(format!(
+                "guards.count must be between {} and {}",
+                MIN_GUARDS, MAX_GUARDS
+            )));
+        }
+
+        if self.circuit_pool.min_circuits > self.circuit_pool.max_prebuilt {
+            return Err(invalid(format!(
+                "circuit_pool.min_circuits ({}) exceeds max_prebuilt ({})",
+                self.circuit_pool.min_circuits, self.circuit_pool.max_prebuilt
+            )));
+        }
+
+        if self.bandwidth_quota.warn_percent > 100 {
+            return Err(invalid(
+                "bandwidth_quota.warn_percent must be at most 100".to_string(),
+            ));
+        }
+        if self.bandwidth_quota.on_exhausted == QuotaAction::Throttle
+            && self.bandwidth_quota.throttle_bytes_per_second == 0
+        {
+            return Err(invalid(
+                "bandwidth_quota.throttle_bytes_per_second must be greater than 0 to throttle"
+                    .to_string(),
+            ));
+        }
+
+        self.storage.validate()?;
+        self.isolation.validate()?;
+        self.blocklist.validate()?;
+        self.request_jitter.validate()?;
+        self.http_padding.validate()
+    }
+
+    /// Compare with `new`, sorting what differs into changes that apply
+    /// immediately and changes that wait for a new identity
+    ///
+    /// Held back: the bridge and connection pool (a new network provider)
+    /// and the guard count (a new guard set). Everything else only affects
+    /// what happens next and applies right away.
+    pub fn diff(&self, new: &Self) -> ConfigChange {
+        let (old_net, new_net) = (&self.network, &new.network);
+        let live = [
+            (
+                "network.connect_timeout",
+                old_net.connect_timeout != new_net.connect_timeout,
+            ),
+            (
+                "network.retry_on_failure",
+                old_net.retry_on_failure != new_net.retry_on_failure,
+            ),
+            (
+                "network.max_retries",
+                old_net.max_retries != new_net.max_retries,
+            ),
+            ("network.framing", old_net.framing != new_net.framing),
+            ("isolation", self.isolation != new.isolation),
+            ("circuit_pool", self.circuit_pool != new.circuit_pool),
+            ("keepalive", self.keepalive != new.keepalive),
+            ("http_padding", self.http_padding != new.http_padding),
+            ("request_jitter", self.request_jitter != new.request_jitter),
+            (
+                "relay_requirements",
+                self.relay_requirements != new.relay_requirements,
+            ),
+            ("rate_limit", self.rate_limit != new.rate_limit),
+            ("logging", self.logging != new.logging),
+            ("http", self.http != new.http),
+            ("http_client", self.http_client != new.http_client),
+            (
+                "bandwidth_quota",
+                self.bandwidth_quota != new.bandwidth_quota,
+            ),
+            ("blocklist", self.blocklist != new.blocklist),
+        ];
+        let deferred = [
+            (
+                "network.bridge_url",
+                old_net.bridge_url != new_net.bridge_url,
+            ),
+            (
+                "network.max_connections",
+                old_net.max_connections != new_net.max_connections,
+            ),
+            (
+                "network.enable_pooling",
+                old_net.enable_pooling != new_net.enable_pooling,
+            ),
+            (
+                "network.blinding_key",
+                old_net.blinding_key != new_net.blinding_key,
+            ),
+            ("guards", self.guards != new.guards),
+        ];
+        let changed = |fields: &[(&'static str, bool)]| {
+            fields
+                .iter()
+                .filter_map(|&(field, changed)| changed.then_some(field))
+                .collect()
+        };
+        ConfigChange {
+            applied: changed(&live),
+            deferred: changed(&deferred),
+        }
+    }
+}
+
+fn invalid(message: String) -> TorError {
+    TorError::ParseError(format!("Invalid client config: {}", message))
+}
+
+/// Builder for [`TorClientConfig`]; `build()` validates the result
+#[derive(Debug, Clone, Default)]
+pub struct TorClientConfigBuilder {
+    config: TorClientConfig,
+}
+
+impl TorClientConfigBuilder {
+    /// Bridge to reach relays through (WebSocket, or http(s) for meek)
+    pub fn bridge_url(mut self, bridge_url: impl Into<String>) -> Self {
+        self.config.network.bridge_url = bridge_url.into();
+        self
+    }
+
+    /// Share one framed bridge WebSocket between relay connections
+    pub fn framing(mut self, enabled: bool) -> Self {
+        self.config.network.framing = enabled;
+        self
+    }
+
+    /// Replace the whole network section
+    pub fn network(mut self, network: NetworkConfig) -> Self {
+        self.config.network = network;
+        self
+    }
+
+    /// Circuit isolation policy, keeping the other isolation limits
+    pub fn isolation_policy(mut self, policy: IsolationType) -> Self {
+        self.config.isolation.policy = policy;
+        self
+    }
+
+    /// Replace the whole isolation section
+    pub fn isolation(mut self, isolation: IsolationConfig) -> Self {
+        self.config.isolation = isolation;
+        self
+    }
+
+    /// Replace the circuit pool section
+    pub fn circuit_pool(mut self, circuit_pool: CircuitPoolConfig) -> Self {
+        self.config.circuit_pool = circuit_pool;
+        self
+    }
+
+    /// Replace the keepalive section
+    pub fn keepalive(mut self, keepalive: KeepaliveConfig) -> Self {
+        self.config.keepalive = keepalive;
+        self
+    }
+
+    /// Replace the default HTTP padding section
+    pub fn http_padding(mut self, http_padding: HttpPaddingConfig) -> Self {
+        self.config.http_padding = http_padding;
+        self
+    }
+
+    /// Replace the default request jitter section
+    pub fn request_jitter(mut self, request_jitter: RequestJitterConfig) -> Self {
+        self.config.request_jitter = request_jitter;
+        self
+    }
+
+    /// Replace the relay requirements section
+    pub fn relay_requirements(mut self, requirements: RelayRequirements) -> Self {
+        self.config.relay_requirements = requirements;
+        self
+    }
+
+    /// Replace the rate limit section
+    pub fn rate_limit(mut self, rate_limit: RateLimiterConfig) -> Self {
+        self.config.rate_limit = rate_limit;
+        self
+    }
+
+    /// Replace the logging section
+    pub fn logging(mut self, logging: LoggingConfig) -> Self {
+        self.config.logging = logging;
+        self
+    }
+
+    /// Number of entry guards to select
+    pub fn guard_count(mut self, count: usize) -> Self {
+        self.config.guards.count = count;
+        self
+    }
+
+    /// Replace the plain-HTTP policy section
+    pub fn http(mut self, http: HttpSecurityConfig) -> Self {
+        self.config.http = http;
+        self
+    }
+
+    /// Replace the redirect and decoding section
+    pub fn http_client(mut self, http_client: HttpClientConfig) -> Self {
+        self.config.http_client = http_client;
+        self
+    }
+
+    /// Replace the bandwidth quota section
+    pub fn bandwidth_quota(mut self, bandwidth_quota: BandwidthQuotaConfig) -> Self {
+        self.config.bandwidth_quota = bandwidth_quota;
+        self
+    }
+
+    /// Replace the blocklist section
+    pub fn blocklist(mut self, blocklist: BlocklistConfig) -> Self {
+        self.config.blocklist = blocklist;
+        self
+    }
+
+    /// Keep persistent state under `namespace` (see [`StorageConfig`])
+    pub fn storage_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.config.storage.namespace = namespace.into();
+        self
+    }
+
+    /// Validate and return the configuration
+    pub fn build(self) -> Result<TorClientConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_partial_json_takes_defaults() {
+        let config = TorClientConfig::from_json(
+            r#"{
+                "network": { "bridge_url": "wss://bridge.example", "framing": false },
+                "isolation": { "policy": "per_request", "max_circuit_age_secs": 120 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.network.bridge_url, "wss://bridge.example");
+        assert!(!config.network.framing);
+        assert_eq!(
+            config.network.max_retries,
+            NetworkConfig::default().max_retries
+        );
+        assert_eq!(config.isolation.policy, IsolationType::PerRequest);
+        assert_eq!(config.isolation.max_circuit_age, Duration::from_secs(120));
+        assert_eq!(config.circuit_pool, CircuitPoolConfig::default());
+
+        // Round-trips through its own JSON
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(TorClientConfig::from_json(&json).unwrap(), config);
+
+        assert_eq!(
+            TorClientConfig::from_json("{}").unwrap(),
+            TorClientConfig::default()
+        );
+        assert!(TorClientConfig::from_json(r#"{ "isolaton": {} }"#).is_err());
+    }
+
+    #[test]
+    fn test_builder_validates() {
+        let config = TorClientConfig::builder()
+            .bridge_url("wss://bridge.example")
+            .isolation_policy(IsolationType::PerDestination)
+            .build()
+            .unwrap();
+        assert_eq!(config.isolation.policy, IsolationType::PerDestination);
+
+        assert!(TorClientConfig::builder()
+            .bridge_url("bridge.example")
+            .build()
+            .is_err());
+        assert!(TorClientConfig::builder()
+            .circuit_pool(CircuitPoolConfig {
+                min_circuits: 5,
+                max_prebuilt: 2,
+                ..CircuitPoolConfig::default()
+            })
+            .build()
+            .is_err());
+        assert!(TorClientConfig::builder()
+            .rate_limit(RateLimiterConfig {
+                window_ms: 0,
+                ..RateLimiterConfig::default()
+            })
+            .build()
+            .is_err());
+        assert!(TorClientConfig::builder()
+            .network(NetworkConfig {
+                blinding_key: Some("abcd".to_string()),
+                ..NetworkConfig::default()
+            })
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_diff_defers_bridge_and_guards() {
+        let current = TorClientConfig::default();
+        assert!(current.diff(&current).is_empty());
+
+        let mut new = TorClientConfig::builder()
+            .framing(false)
+            .isolation_policy(IsolationType::None)
+            .guard_count(3)
+            .build()
+            .unwrap();
+        new.network.connect_timeout = 30;
+        new.network.bridge_url = "wss://other.example".to_string();
+
+        let change = current.diff(&new);
+        assert_eq!(
+            change.applied,
+            vec!["network.connect_timeout", "network.framing", "isolation"]
+        );
+        assert_eq!(change.deferred, vec!["network.bridge_url", "guards"]);
+
+        assert!(TorClientConfig::builder().guard_count(9).build().is_err());
+    }
+
+    #[test]
+    fn test_storage_namespace_keys() {
+        let shared = StorageConfig::default();
+        assert_eq!(shared.key("tor_guard_state"), "tor_guard_state");
+
+        let config = TorClientConfig::builder()
+            .storage_namespace("work-1")
+            .build()
+            .unwrap();
+        assert_eq!(config.storage.key("tor-storage"), "tor-storage.work-1");
+
+        assert!(TorClientConfig::builder()
+            .storage_namespace("a/b")
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Several client identities in one page
+//!
+//! Apps that offer user-selectable personas need identities that share
+//! nothing an observer could link: [`TorClientManager`] hosts one
+//! [`TorClient`] per identity, each with its own storage namespace (see
+//! [`StorageConfig`](crate::StorageConfig)), and so its own guards,
+//! circuits, bandwidth counts and IndexedDB database.
+//!
+//! Only the consensus is shared. It is public, identical for every client,
+//! and read-only once fetched, so the first identity to bootstrap fetches
+//! it and the others reuse it until it stops being fresh.
+//!
+//! Process-wide settings and reports (logging, clock skew, security
+//! posture) stay shared, as they describe the page rather than an identity.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::client_config::TorClientConfig;
+use crate::error::{Result, TorError};
+use crate::protocol::Consensus;
+use crate::TorClient;
+
+/// Identity names are used as storage namespaces
+fn identity_config(base: &TorClientConfig, name: &str) -> Result<TorClientConfig> {
+    if name.is_empty() {
+        return Err(TorError::InvalidState("Identity name is empty".into()));
+    }
+    let mut config = base.clone();
+    config.storage.namespace = name.to_string();
+    config.validate()?;
+    Ok(config)
+}
+
+/// Several independent `TorClient` identities; see the module docs
+#[wasm_bindgen]
+pub struct TorClientManager {
+    /// Configuration each identity starts from
+    base: TorClientConfig,
+    identities: BTreeMap<String, TorClient>,
+    active: Option<String>,
+    /// Consensus the identities bootstrap with
+    consensus: Option<Arc<Consensus>>,
+}
+
+#[wasm_bindgen]
+impl TorClientManager {
+    /// Create a manager whose identities use `config_json` (as for
+    /// `TorClient.with_config()`), or the defaults
+    #[wasm_bindgen(constructor)]
+    pub fn new(config_json: Option<String>) -> std::result::Result<TorClientManager, JsValue> {
+        let base = match config_json {
+            Some(json) => TorClientConfig::from_json(&json)?,
+            None => TorClientConfig::default(),
+        };
+        Ok(Self::with_config(base))
+    }
+
+    /// Create and bootstrap the identity `name`
+    ///
+    /// `name` (letters, digits, `-`, `_`) is also its storage namespace, so
+    /// an identity created again under the same name keeps its guards. The
+    /// first identity becomes the active one.
+    #[wasm_bindgen]
+    pub async fn create_identity(&mut self, name: String) -> std::result::Result<(), JsValue> {
+        if self.identities.contains_key(&name) {
+            return Err(JsValue::from_str(&format!(
+                "Identity {} already exists",
+                name
+            )));
+        }
+        let config = identity_config(&self.base, &name)?;
+        let mut client = TorClient::from_config(config).await?;
+
+        match self.consensus.clone().filter(|c| c.is_fresh()) {
+            Some(consensus) => client.bootstrap_with_consensus(consensus).await?,
+            None => {
+                client.bootstrap().await?;
+                self.consensus = client.consensus();
+            }
+        }
+
+        log::info!("🎭 Identity {} ready", name);
+        self.identities.insert(name.clone(), client);
+        if self.active.is_none() {
+            self.active = Some(name);
+        }
+        Ok(())
+    }
+
+    /// Shut the identity `name` down, deleting what it persisted if `wipe`
+    ///
+    /// Returns false if there is no such identity. If it was active, no
+    /// identity is active afterwards.
+    #[wasm_bindgen]
+    pub async fn destroy_identity(
+        &mut self,
+        name: String,
+        wipe: Option<bool>,
+    ) -> std::result::Result<bool, JsValue> {
+        let Some(mut client) = self.identities.remove(&name) else {
+            return Ok(false);
+        };
+        if self.active.as_deref() == Some(name.as_str()) {
+            self.active = None;
+        }
+        client.shutdown().await?;
+        if wipe.unwrap_or(false) {
+            client.clear_persistent_state().await?;
+            log::info!("🎭 Identity {} destroyed and its storage wiped", name);
+        } else {
+            log::info!("🎭 Identity {} destroyed", name);
+        }
+        Ok(true)
+    }
+
+    /// Make `name` the identity `fetch()` uses
+    #[wasm_bindgen]
+    pub fn switch_identity(&mut self, name: String) -> std::result::Result<(), JsValue> {
+        if !self.identities.contains_key(&name) {
+            return Err(JsValue::from_str(&format!("No identity {}", name)));
+        }
+        self.active = Some(name);
+        Ok(())
+    }
+
+    /// Name of the active identity
+    #[wasm_bindgen]
+    pub fn active_identity(&self) -> Option<String> {
+        self.active.clone()
+    }
+
+    /// Names of all identities, sorted
+    #[wasm_bindgen]
+    pub fn list_identities(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.names()).unwrap_or(JsValue::NULL)
+    }
+
+    /// `fetch()` through the active identity
+    #[wasm_bindgen]
+    pub async fn fetch(
+        &mut self,
+        url: String,
+        options: JsValue,
+    ) -> std::result::Result<String, JsValue> {
+        self.active_client()?.fetch(url, None, options).await
+    }
+
+    /// `get_status()` of the identity `name`, or of the active one
+    #[wasm_bindgen]
+    pub fn get_identity_status(&self, name: Option<String>) -> JsValue {
+        let name = name.or_else(|| self.active.clone());
+        name.and_then(|n| self.identities.get(&n))
+            .map(TorClient::get_status)
+            .unwrap_or(JsValue::NULL)
+    }
+}
+
+impl TorClientManager {
+    /// Create a manager whose identities start from `base`
+    pub fn with_config(base: TorClientConfig) -> Self {
+        Self {
+            base,
+            identities: BTreeMap::new(),
+            active: None,
+            consensus: None,
+        }
+    }
+
+    /// Names of all identities, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.identities.keys().cloned().collect()
+    }
+
+    /// The identity `name`
+    pub fn identity(&mut self, name: &str) -> Option<&mut TorClient> {
+        self.identities.get_mut(name)
+    }
+
+    fn active_client(&mut self) -> Result<&mut TorClient> {
+        let name = self
+            .active
+            .as_ref()
+            .ok_or_else(|| TorError::InvalidState("No active identity".into()))?;
+        self.identities
+            .get_mut(name)
+            .ok_or_else(|| TorError::InvalidState(format!("No identity {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_config_namespaces_storage() {
+        let base = TorClientConfig::builder().guard_count(3).build().unwrap();
+        let config = identity_config(&base, "work").unwrap();
+        assert_eq!(config.storage.namespace, "work");
+        assert_eq!(config.guards.count, 3);
+
+        assert!(identity_config(&base, "").is_err());
+        assert!(identity_config(&base, "../home").is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Local clock skew estimation
+//!
+//! A browser whose clock is off by hours judges every consensus stale (or
+//! keeps one long past expiry). Two outside references tell us how far off
+//! it is:
+//!
+//! - A freshly downloaded consensus was valid when we fetched it, so the true
+//!   time lies in `[valid_after, valid_until]`. That bounds the skew.
+//! - Each guard's NETINFO cell carries the relay's idea of the current time.
+//!   One relay can be wrong; the median of recent samples is used.
+//!
+//! The estimate is the NETINFO median clamped into the consensus bounds (or
+//! zero clamped into them, before enough NETINFO samples have arrived).
+//! Consensus freshness checks read [`now_secs`], which applies it. When the
+//! skew exceeds [`SKEW_WARNING_SECS`], a `clock_skew` warning goes to the
+//! listener registered with [`set_listener`].
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Skew beyond which a warning is raised (10 minutes)
+pub const SKEW_WARNING_SECS: i64 = 600;
+
+/// NETINFO samples kept
+const MAX_NETINFO_SAMPLES: usize = 15;
+
+/// NETINFO samples needed before they are trusted over the consensus alone
+const MIN_NETINFO_SAMPLES: usize = 3;
+
+/// What produced a skew estimate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkewSource {
+    Consensus,
+    Netinfo,
+}
+
+/// A `clock_skew` warning
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClockSkewWarning {
+    /// Seconds to add to local time to get true time
+    pub skew_secs: i64,
+    /// The observation that pushed the estimate over the threshold
+    pub source: SkewSource,
+}
+
+/// Current estimate and the evidence behind it
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockSkewReport {
+    pub skew_secs: i64,
+    pub netinfo_samples: usize,
+    /// Skew range allowed by the last consensus, as (min, max)
+    pub consensus_bounds: Option<(i64, i64)>,
+    pub warning: bool,
+}
+
+/// Skew estimator; see the module docs
+#[derive(Debug, Default)]
+pub struct SkewEstimator {
+    /// Relay time minus local time, most recent last
+    netinfo: VecDeque<i64>,
+    consensus_bounds: Option<(i64, i64)>,
+    /// Estimate when the last warning was raised
+    warned_at: Option<i64>,
+}
+
+impl SkewEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a relay's NETINFO timestamp, received at `local_secs`
+    pub fn observe_netinfo(
+        &mut self,
+        relay_secs: u64,
+        local_secs: u64,
+    ) -> Option<ClockSkewWarning> {
+        // Clients and some relays send zero to avoid revealing their clock
+        if relay_secs == 0 {
+            return None;
+        }
+        if self.netinfo.len() == MAX_NETINFO_SAMPLES {
+            self.netinfo.pop_front();
+        }
+        self.netinfo
+            .push_back(relay_secs as i64 - local_secs as i64);
+        self.check_warning(SkewSource::Netinfo)
+    }
+
+    /// Record the validity window of a consensus just downloaded at `local_secs`
+    pub fn observe_consensus(
+        &mut self,
+        valid_after: u64,
+        valid_until: u64,
+        local_secs: u64,
+    ) -> Option<ClockSkewWarning> {
+        if valid_after == 0 || valid_until < valid_after {
+            return None;
+        }
+        let local = local_secs as i64;
+        self.consensus_bounds = Some((valid_after as i64 - local, valid_until as i64 - local));
+        self.check_warning(SkewSource::Consensus)
+    }
+
+    /// Seconds to add to local time to get true time
+    pub fn skew_secs(&self) -> i64 {
+        let netinfo = (self.netinfo.len() >= MIN_NETINFO_SAMPLES).then(|| {
+            let mut sorted: Vec<i64> = self.netinfo.iter().copied().collect();
+            sorted.sort_unstable();
+            sorted[sorted.len() / 2]
+        });
+        let estimate = netinfo.unwrap_or(0);
+        match self.consensus_bounds {
+            Some((min, max)) => estimate.clamp(min, max),
+            None => estimate,
+        }
+    }
+
+    pub fn report(&self) -> ClockSkewReport {
+        ClockSkewReport {
+            skew_secs: self.skew_secs(),
+            netinfo_samples: self.netinfo.len(),
+            consensus_bounds: self.consensus_bounds,
+            warning: self.skew_secs().abs() >= SKEW_WARNING_SECS,
+        }
+    }
+
+    /// Warn when the estimate first crosses the threshold, and again if it
+    /// moves by more than half the threshold since the last warning
+    fn check_warning(&mut self, source: SkewSource) -> Option<ClockSkewWarning> {
+        let skew = self.skew_secs();
+        if skew.abs() < SKEW_WARNING_SECS {
+            self.warned_at = None;
+            return None;
+        }
+        if let Some(previous) = self.warned_at {
+            if (skew - previous).abs() <= SKEW_WARNING_SECS / 2 {
+                return None;
+            }
+        }
+        self.warned_at = Some(skew);
+        Some(ClockSkewWarning {
+            skew_secs: skew,
+            source,
+        })
+    }
+}
+
+type Listener = Rc<dyn Fn(&ClockSkewWarning)>;
+
+thread_local! {
+    static ESTIMATOR: RefCell<SkewEstimator> = RefCell::new(SkewEstimator::new());
+    static LISTENER: RefCell<Option<Listener>> = const { RefCell::new(None) };
+}
+
+fn local_secs() -> u64 {
+    crate::runtime::timer::now_ms() / 1000
+}
+
+fn raise(warning: Option<ClockSkewWarning>) {
+    let Some(warning) = warning else {
+        return;
+    };
+    log::warn!(
+        "⏰ clock_skew: local clock is {}s {} true time (from {:?})",
+        warning.skew_secs.abs(),
+        if warning.skew_secs > 0 {
+            "behind"
+        } else {
+            "ahead of"
+        },
+        warning.source
+    );
+    if let Some(listener) = LISTENER.with(|l| l.borrow().clone()) {
+        listener(&warning);
+    }
+}
+
+/// Register a callback for `clock_skew` warnings, replacing any earlier one
+pub fn set_listener(listener: impl Fn(&ClockSkewWarning) + 'static) {
+    LISTENER.with(|l| *l.borrow_mut() = Some(Rc::new(listener)));
+}
+
+/// Record a guard's NETINFO timestamp
+pub fn observe_netinfo(relay_secs: u64) {
+    raise(ESTIMATOR.with(|e| e.borrow_mut().observe_netinfo(relay_secs, local_secs())));
+}
+
+/// Record the validity window of a freshly downloaded consensus
+pub fn observe_consensus(valid_after: u64, valid_until: u64) {
+    raise(ESTIMATOR.with(|e| {
+        e.borrow_mut()
+            .observe_consensus(valid_after, valid_until, local_secs())
+    }));
+}
+
+/// Current skew estimate in seconds
+pub fn skew_secs() -> i64 {
+    ESTIMATOR.with(|e| e.borrow().skew_secs())
+}
+
+pub fn report() -> ClockSkewReport {
+    ESTIMATOR.with(|e| e.borrow().report())
+}
+
+/// Local time corrected by the skew estimate (Unix seconds)
+pub fn now_secs() -> u64 {
+    (local_secs() as i64 + skew_secs()).max(0) as u64
+}
+
+/// Parse a UTC timestamp, `YYYY-MM-DD HH:MM:SS` (consensus) or ISO 8601
+/// `YYYY-MM-DDTHH:MM:SS[.fff]Z`, into Unix seconds
+pub fn parse_utc(text: &str) -> Option<u64> {
+    let text = text.trim().trim_end_matches('Z');
+    let (date, time) = text.split_once([' ', 'T'])?;
+    let time = time.split('.').next()?;
+
+    let mut date = date.split('-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.split(':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    // Days since 1970-01-01 (Howard Hinnant's days_from_civil)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(secs).ok()
+}
+
+/// Format Unix seconds as ISO 8601 UTC, `YYYY-MM-DDTHH:MM:SSZ`
+pub fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's civil_from_days)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_utc() {
+        assert_eq!(parse_utc("1970-01-01 00:00:00"), Some(0));
+        assert_eq!(parse_utc("2024-02-29 12:34:56"), Some(1_709_210_096));
+        assert_eq!(parse_utc("2026-10-16T08:00:00.123Z"), Some(1_792_137_600));
+        assert_eq!(parse_utc("2026-13-01 00:00:00"), None);
+        assert_eq!(parse_utc("yesterday"), None);
+        for secs in [0, 951_782_400, 1_709_210_096, 1_792_137_600] {
+            assert_eq!(parse_utc(&format_utc(secs)), Some(secs));
+        }
+        assert_eq!(format_utc(1_709_210_096), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn test_netinfo_median_within_consensus_bounds() {
+        let mut skew = SkewEstimator::new();
+        let local = 1_000_000;
+
+        // Too few NETINFO samples: the consensus alone moves us just enough
+        assert!(skew.observe_netinfo(local + 7200, local).is_none());
+        assert_eq!(skew.skew_secs(), 0);
+        let warning = skew.observe_consensus(local + 3600, local + 3 * 3600, local);
+        assert_eq!(
+            warning,
+            Some(ClockSkewWarning {
+                skew_secs: 3600,
+                source: SkewSource::Consensus
+            })
+        );
+
+        // Once there are enough samples, one wild relay doesn't move the median
+        skew.observe_netinfo(local + 7300, local);
+        assert_eq!(skew.skew_secs(), 3600);
+        skew.observe_netinfo(local + 999_999, local);
+        assert_eq!(skew.skew_secs(), 7300);
+        assert!(skew.report().warning);
+
+        // Zero timestamps carry no information
+        skew.observe_netinfo(0, local);
+        assert_eq!(skew.report().netinfo_samples, 3);
+    }
+
+    #[test]
+    fn test_warning_not_repeated_for_small_changes() {
+        let mut skew = SkewEstimator::new();
+        let local = 1_000_000;
+        assert!(skew
+            .observe_consensus(local - 300, local + 3600, local)
+            .is_none());
+
+        for relay in [local - 1200, local - 1200, local - 1200] {
+            skew.observe_netinfo(relay, local);
+        }
+        assert_eq!(skew.skew_secs(), -300);
+        // Consensus bound still holds us at -300: under the threshold
+        assert!(!skew.report().warning);
+
+        assert!(skew
+            .observe_consensus(local - 7200, local + 3600, local)
+            .is_some());
+        assert_eq!(skew.skew_secs(), -1200);
+        assert!(skew.observe_netinfo(local - 1300, local).is_none());
+    }
+}
@@ -0,0 +1,259 @@
+//! HTTP CONNECT proxy emulation
+//!
+//! Lets JS HTTP libraries that only know how to talk to a "proxy" use Tor
+//! unmodified: the library's proxy socket is backed by [`ProxyTunnel`]. The
+//! bytes it sends first (`CONNECT host:port HTTP/1.1 ...`) go to
+//! `TorClient::proxy_connect()`, which opens a Tor stream to the target and
+//! returns the tunnel together with the proxy's reply. After a `200` reply
+//! the tunnel carries the library's bytes (usually its own TLS) verbatim.
+//!
+//! Only CONNECT is supported; absolute-form requests (`GET http://...`) are
+//! answered with `405` so plain-HTTP proxying never happens at the exit in
+//! the clear by accident.
+
+use crate::protocol::TorStream;
+use wasm_bindgen::prelude::*;
+
+/// Largest CONNECT header accepted
+pub const MAX_CONNECT_HEADER: usize = 8 * 1024;
+
+/// Bytes read per `ProxyTunnel::read()` call
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Target of a CONNECT request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectRequest {
+    pub host: String,
+    pub port: u16,
+    /// Length of the request header including the blank line; anything after
+    /// it is early tunnel data
+    pub header_len: usize,
+}
+
+/// Result of parsing the start of a proxy dialogue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectParse {
+    /// The header is not complete yet
+    Incomplete,
+    Request(ConnectRequest),
+    /// The request can't be served; reply with this status
+    Reject(u16),
+}
+
+/// Reason phrase for the statuses the shim sends
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "Connection established",
+        400 => "Bad Request",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        502 => "Bad Gateway",
+        _ => "Error",
+    }
+}
+
+/// Proxy reply bytes for `status`
+pub fn proxy_response(status: u16) -> Vec<u8> {
+    if status == 200 {
+        format!("HTTP/1.1 200 {}\r\n\r\n", reason(200)).into_bytes()
+    } else {
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status,
+            reason(status)
+        )
+        .into_bytes()
+    }
+}
+
+/// Split a CONNECT authority (`host:port` or `[v6]:port`)
+///
+/// IPv6 hosts keep their brackets, as RELAY_BEGIN expects them.
+fn parse_authority(authority: &str) -> Option<(String, u16)> {
+    let (host, port) = if authority.starts_with('[') {
+        let (host, port) = authority.split_once("]:")?;
+        host[1..].parse::<std::net::Ipv6Addr>().ok()?;
+        (&authority[..host.len() + 1], port)
+    } else {
+        authority.rsplit_once(':')?
+    };
+    let port: u16 = port.parse().ok()?;
+    if host.is_empty() || port == 0 {
+        return None;
+    }
+    Some((host.to_string(), port))
+}
+
+/// Parse the first bytes a client sends to its proxy
+pub fn parse_connect(data: &[u8]) -> ConnectParse {
+    let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return if data.len() > MAX_CONNECT_HEADER {
+            ConnectParse::Reject(431)
+        } else {
+            ConnectParse::Incomplete
+        };
+    };
+    let header_len = end + 4;
+    if header_len > MAX_CONNECT_HEADER {
+        return ConnectParse::Reject(431);
+    }
+
+    let Ok(head) = std::str::from_utf8(&data[..end]) else {
+        return ConnectParse::Reject(400);
+    };
+    let request_line = head.split("\r\n").next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(authority), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return ConnectParse::Reject(400);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return ConnectParse::Reject(400);
+    }
+    if method != "CONNECT" {
+        return ConnectParse::Reject(405);
+    }
+
+    match parse_authority(authority) {
+        Some((host, port)) => ConnectParse::Request(ConnectRequest {
+            host,
+            port,
+            header_len,
+        }),
+        None => ConnectParse::Reject(400),
+    }
+}
+
+/// One proxied connection, as seen by a JS library's proxy socket
+///
+/// Always send `response()` back to the library first. If `is_open()` is
+/// false the request was refused and the socket should be closed after the
+/// reply; otherwise pump bytes with `write()` and `read()`.
+#[wasm_bindgen]
+pub struct ProxyTunnel {
+    response: Vec<u8>,
+    target: Option<String>,
+    stream: Option<TorStream>,
+}
+
+impl ProxyTunnel {
+    /// Tunnel that refuses the request with `status`
+    pub fn rejected(status: u16) -> Self {
+        Self {
+            response: proxy_response(status),
+            target: None,
+            stream: None,
+        }
+    }
+
+    /// Established tunnel over `stream`
+    pub fn open(request: &ConnectRequest, stream: TorStream) -> Self {
+        Self {
+            response: proxy_response(200),
+            target: Some(format!("{}:{}", request.host, request.port)),
+            stream: Some(stream),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl ProxyTunnel {
+    /// Whether `data` holds a complete CONNECT header (or is too large to
+    /// ever be one), i.e. whether it is time to call `proxy_connect()`
+    pub fn header_complete(data: &[u8]) -> bool {
+        parse_connect(data) != ConnectParse::Incomplete
+    }
+
+    /// Reply to send to the library
+    pub fn response(&self) -> Vec<u8> {
+        self.response.clone()
+    }
+
+    /// Whether the tunnel is connected
+    pub fn is_open(&self) -> bool {
+        self.stream.as_ref().is_some_and(|s| !s.is_closed())
+    }
+
+    /// `host:port` the tunnel connects to (undefined if refused)
+    pub fn target(&self) -> Option<String> {
+        self.target.clone()
+    }
+
+    /// Send bytes from the library through the tunnel
+    pub async fn write(&mut self, data: Vec<u8>) -> Result<(), JsValue> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| JsValue::from_str("Proxy tunnel is not open"))?;
+        stream.write_all(&data).await?;
+        Ok(())
+    }
+
+    /// Next bytes from the target; empty once the tunnel has closed
+    pub async fn read(&mut self) -> Result<Vec<u8>, JsValue> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(Vec::new());
+        };
+        let mut buf = vec![0u8; READ_CHUNK];
+        let n = stream.read_some(&mut buf).await?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Close the tunnel (RELAY_END to the exit)
+    pub async fn close(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = stream.close().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_connect_request() {
+        let request =
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n\x16\x03\x01";
+        let ConnectParse::Request(req) = parse_connect(request) else {
+            panic!("expected request");
+        };
+        assert_eq!((req.host.as_str(), req.port), ("example.com", 443));
+        assert_eq!(&request[req.header_len..], b"\x16\x03\x01");
+
+        let v6 = parse_connect(b"CONNECT [2001:db8::1]:8443 HTTP/1.0\r\n\r\n");
+        assert!(
+            matches!(v6, ConnectParse::Request(r) if r.host == "[2001:db8::1]" && r.port == 8443)
+        );
+
+        assert_eq!(
+            parse_connect(b"CONNECT example.com:443 HTTP/1.1\r\nHost: ex"),
+            ConnectParse::Incomplete
+        );
+    }
+
+    #[test]
+    fn test_rejects_unsupported_requests() {
+        assert_eq!(
+            parse_connect(b"GET http://example.com/ HTTP/1.1\r\n\r\n"),
+            ConnectParse::Reject(405)
+        );
+        for bad in [
+            &b"CONNECT example.com HTTP/1.1\r\n\r\n"[..],
+            b"CONNECT example.com:0 HTTP/1.1\r\n\r\n",
+            b"CONNECT [::1:443 HTTP/1.1\r\n\r\n",
+            b"CONNECT example.com:443\r\n\r\n",
+            b"CONNECT example.com:443 SPDY/3\r\n\r\n",
+        ] {
+            assert_eq!(parse_connect(bad), ConnectParse::Reject(400));
+        }
+        let huge = vec![b'a'; MAX_CONNECT_HEADER + 1];
+        assert_eq!(parse_connect(&huge), ConnectParse::Reject(431));
+
+        let reply = String::from_utf8(proxy_response(502)).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+        assert!(reply.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,371 @@
+//! Control-port style commands over JSON
+//!
+//! `TorClient::command` accepts a subset of Tor's control protocol
+//! (control-spec.txt) as JSON, so tooling written against the control port
+//! can be ported to browser deployments:
+//!
+//! ```text
+//! {"command": "GETINFO", "keys": ["circuit-status", "stream-status"]}
+//! {"command": "SIGNAL", "signal": "NEWNYM"}
+//! {"command": "SETCONF", "options": {"IsolationPolicy": "per_request"}}
+//! ```
+//!
+//! Replies use the control port's status codes: `{ status: 250, message:
+//! "OK", values }` on success, otherwise e.g. `{ status: 552, message:
+//! "Unrecognized key \"foo\"" }`. Commands, keys, signals and option names
+//! are case-insensitive, as on the control port, and SETCONF validates every
+//! option before applying any.
+//!
+//! Streams only live inside a request method, which holds the client until
+//! it returns, so `stream-status` is empty whenever a command can run.
+
+use crate::http_padding::HttpPaddingConfig;
+use crate::isolation::IsolationType;
+use crate::protocol::{Circuit, RelayRequirements};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Command succeeded
+pub const STATUS_OK: u16 = 250;
+/// Unrecognized command
+pub const STATUS_UNRECOGNIZED_COMMAND: u16 = 510;
+/// Syntax error in command argument
+pub const STATUS_SYNTAX_ERROR: u16 = 512;
+/// Command not allowed in the client's current state
+pub const STATUS_UNAVAILABLE: u16 = 551;
+/// Unrecognized key, signal or option
+pub const STATUS_UNRECOGNIZED_ENTITY: u16 = 552;
+/// Invalid configuration value
+pub const STATUS_INVALID_VALUE: u16 = 553;
+
+/// GETINFO keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoKey {
+    CircuitStatus,
+    StreamStatus,
+    Version,
+}
+
+impl InfoKey {
+    fn parse(key: &str) -> Option<Self> {
+        match key.to_ascii_lowercase().as_str() {
+            "circuit-status" => Some(InfoKey::CircuitStatus),
+            "stream-status" => Some(InfoKey::StreamStatus),
+            "version" => Some(InfoKey::Version),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InfoKey::CircuitStatus => "circuit-status",
+            InfoKey::StreamStatus => "stream-status",
+            InfoKey::Version => "version",
+        }
+    }
+}
+
+/// SIGNAL names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Fresh circuits for all later requests (`new_identity()`)
+    NewNym,
+    /// Forget cached exit DNS answers
+    ClearDnsCache,
+}
+
+/// One validated SETCONF option
+#[derive(Debug, Clone)]
+pub enum ConfChange {
+    /// `IsolationPolicy`: same names as `set_isolation_policy()`
+    IsolationPolicy(IsolationType),
+    /// `HttpPadding`: the default padding config, as for `set_http_padding()`
+    HttpPadding(HttpPaddingConfig),
+    /// `MemoryBudget`: bytes, 0 = none
+    MemoryBudget(usize),
+    /// `RelayRequirements`: as for `set_relay_requirements()`
+    RelayRequirements(RelayRequirements),
+}
+
+/// A parsed command
+#[derive(Debug, Clone)]
+pub enum ControlCommand {
+    GetInfo(Vec<InfoKey>),
+    Signal(Signal),
+    SetConf(Vec<ConfChange>),
+}
+
+/// Reply to a command
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControlReply {
+    pub status: u16,
+    pub message: String,
+    /// GETINFO answers by key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values: Option<Value>,
+}
+
+impl ControlReply {
+    pub fn ok() -> Self {
+        Self {
+            status: STATUS_OK,
+            message: "OK".into(),
+            values: None,
+        }
+    }
+
+    pub fn with_values(values: Map<String, Value>) -> Self {
+        Self {
+            values: Some(Value::Object(values)),
+            ..Self::ok()
+        }
+    }
+
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            values: None,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == STATUS_OK
+    }
+}
+
+impl ControlCommand {
+    /// Parse a command, or the error reply for it
+    pub fn parse(json: &str) -> Result<Self, ControlReply> {
+        let request: Value = serde_json::from_str(json).map_err(|e| {
+            ControlReply::error(STATUS_SYNTAX_ERROR, format!("Invalid JSON: {}", e))
+        })?;
+        let command = request
+            .get("command")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ControlReply::error(STATUS_SYNTAX_ERROR, "Missing \"command\""))?;
+
+        match command.to_ascii_uppercase().as_str() {
+            "GETINFO" => parse_getinfo(&request),
+            "SIGNAL" => parse_signal(&request),
+            "SETCONF" => parse_setconf(&request),
+            _ => Err(ControlReply::error(
+                STATUS_UNRECOGNIZED_COMMAND,
+                format!("Unrecognized command \"{}\"", command),
+            )),
+        }
+    }
+}
+
+fn parse_getinfo(request: &Value) -> Result<ControlCommand, ControlReply> {
+    let keys = request
+        .get("keys")
+        .and_then(Value::as_array)
+        .filter(|keys| !keys.is_empty())
+        .ok_or_else(|| {
+            ControlReply::error(
+                STATUS_SYNTAX_ERROR,
+                "GETINFO needs a non-empty \"keys\" array",
+            )
+        })?;
+    keys.iter()
+        .map(|key| {
+            let key = key.as_str().ok_or_else(|| {
+                ControlReply::error(STATUS_SYNTAX_ERROR, "GETINFO keys are strings")
+            })?;
+            InfoKey::parse(key).ok_or_else(|| {
+                ControlReply::error(
+                    STATUS_UNRECOGNIZED_ENTITY,
+                    format!("Unrecognized key \"{}\"", key),
+                )
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(ControlCommand::GetInfo)
+}
+
+fn parse_signal(request: &Value) -> Result<ControlCommand, ControlReply> {
+    let signal = request
+        .get("signal")
+        .and_then(Value::as_str)
+        .ok_or_else(|| ControlReply::error(STATUS_SYNTAX_ERROR, "SIGNAL needs \"signal\""))?;
+    match signal.to_ascii_uppercase().as_str() {
+        "NEWNYM" => Ok(ControlCommand::Signal(Signal::NewNym)),
+        "CLEARDNSCACHE" => Ok(ControlCommand::Signal(Signal::ClearDnsCache)),
+        _ => Err(ControlReply::error(
+            STATUS_UNRECOGNIZED_ENTITY,
+            format!("Unrecognized signal \"{}\"", signal),
+        )),
+    }
+}
+
+fn parse_setconf(request: &Value) -> Result<ControlCommand, ControlReply> {
+    let options = request
+        .get("options")
+        .and_then(Value::as_object)
+        .ok_or_else(|| {
+            ControlReply::error(STATUS_SYNTAX_ERROR, "SETCONF needs an \"options\" object")
+        })?;
+    options
+        .iter()
+        .map(|(name, value)| parse_option(name, value))
+        .collect::<Result<_, _>>()
+        .map(ControlCommand::SetConf)
+}
+
+fn parse_option(name: &str, value: &Value) -> Result<ConfChange, ControlReply> {
+    let invalid = |why: String| {
+        ControlReply::error(
+            STATUS_INVALID_VALUE,
+            format!("Invalid value for {}: {}", name, why),
+        )
+    };
+    match name.to_ascii_lowercase().as_str() {
+        "isolationpolicy" => {
+            let policy = value.as_str().unwrap_or_default();
+            IsolationType::from_name(policy)
+                .map(ConfChange::IsolationPolicy)
+                .ok_or_else(|| invalid(format!("unknown policy {}", value)))
+        }
+        "httppadding" => HttpPaddingConfig::from_json(&value.to_string())
+            .map(ConfChange::HttpPadding)
+            .map_err(|e| invalid(e.to_string())),
+        "memorybudget" => value
+            .as_u64()
+            .and_then(|bytes| usize::try_from(bytes).ok())
+            .map(ConfChange::MemoryBudget)
+            .ok_or_else(|| invalid(format!("expected a byte count, got {}", value))),
+        "relayrequirements" => RelayRequirements::from_json(&value.to_string())
+            .map(ConfChange::RelayRequirements)
+            .map_err(|e| invalid(e.to_string())),
+        _ => Err(ControlReply::error(
+            STATUS_UNRECOGNIZED_ENTITY,
+            format!("Unrecognized option \"{}\"", name),
+        )),
+    }
+}
+
+/// One `circuit-status` entry
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub id: u32,
+    /// `BUILT`, or `CLOSED` once its link has gone away
+    pub status: &'static str,
+    /// `$FINGERPRINT~nickname` per hop, guard first
+    pub path: Vec<String>,
+    /// `GENERAL` (cached for an isolation key), `PREBUILT` (pooled) or
+    /// `CUSTOM` (from `build_custom_circuit()`)
+    pub purpose: &'static str,
+    pub isolation_key: Option<String>,
+    pub age_secs: u64,
+}
+
+impl CircuitStatus {
+    pub fn new(circuit: &Circuit, purpose: &'static str, isolation_key: Option<String>) -> Self {
+        Self {
+            id: circuit.id,
+            status: if circuit.is_connected() {
+                "BUILT"
+            } else {
+                "CLOSED"
+            },
+            path: circuit
+                .relays
+                .iter()
+                .map(|r| format!("${}~{}", r.fingerprint, r.nickname))
+                .collect(),
+            purpose,
+            isolation_key,
+            age_secs: circuit.age(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(json: &str) -> ControlReply {
+        ControlCommand::parse(json).unwrap_err()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let command =
+            ControlCommand::parse(r#"{"command":"getinfo","keys":["Circuit-Status","version"]}"#)
+                .unwrap();
+        assert!(matches!(
+            command,
+            ControlCommand::GetInfo(keys) if keys == [InfoKey::CircuitStatus, InfoKey::Version]
+        ));
+
+        let command = ControlCommand::parse(r#"{"command":"SIGNAL","signal":"newnym"}"#).unwrap();
+        assert!(matches!(command, ControlCommand::Signal(Signal::NewNym)));
+
+        let command = ControlCommand::parse(
+            r#"{"command":"SETCONF","options":{"ISOLATIONPOLICY":"per_request","MemoryBudget":1048576,"RelayRequirements":{"min_bandwidth":500}}}"#,
+        )
+        .unwrap();
+        let ControlCommand::SetConf(changes) = command else {
+            panic!("expected SETCONF");
+        };
+        assert!(matches!(
+            &changes[..],
+            [
+                ConfChange::IsolationPolicy(IsolationType::PerRequest),
+                ConfChange::MemoryBudget(1_048_576),
+                ConfChange::RelayRequirements(RelayRequirements {
+                    min_bandwidth: 500,
+                    require_fast: true,
+                    ..
+                })
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(parse_err("GETINFO version").status, STATUS_SYNTAX_ERROR);
+        assert_eq!(parse_err(r#"{"keys":[]}"#).status, STATUS_SYNTAX_ERROR);
+        assert_eq!(
+            parse_err(r#"{"command":"MAPADDRESS"}"#).status,
+            STATUS_UNRECOGNIZED_COMMAND
+        );
+        assert_eq!(
+            parse_err(r#"{"command":"GETINFO","keys":[]}"#).status,
+            STATUS_SYNTAX_ERROR
+        );
+        let reply = parse_err(r#"{"command":"GETINFO","keys":["version","ns/all"]}"#);
+        assert_eq!(reply.status, STATUS_UNRECOGNIZED_ENTITY);
+        assert!(reply.message.contains("ns/all"));
+        assert_eq!(
+            parse_err(r#"{"command":"SIGNAL","signal":"HUP"}"#).status,
+            STATUS_UNRECOGNIZED_ENTITY
+        );
+    }
+
+    #[test]
+    fn test_setconf_rejects_whole_command() {
+        // One bad option and nothing is applied
+        let reply = parse_err(
+            r#"{"command":"SETCONF","options":{"IsolationPolicy":"per_request","HttpPadding":{"header":"bad header"}}}"#,
+        );
+        assert_eq!(reply.status, STATUS_INVALID_VALUE);
+        assert!(reply.message.contains("HttpPadding"));
+
+        assert_eq!(
+            parse_err(r#"{"command":"SETCONF","options":{"IsolationPolicy":"sometimes"}}"#).status,
+            STATUS_INVALID_VALUE
+        );
+        assert_eq!(
+            parse_err(r#"{"command":"SETCONF","options":{"SocksPort":9050}}"#).status,
+            STATUS_UNRECOGNIZED_ENTITY
+        );
+
+        let reply = ControlReply::error(STATUS_UNAVAILABLE, "nope");
+        assert_eq!(
+            serde_json::to_value(&reply).unwrap(),
+            serde_json::json!({ "status": 551, "message": "nope" })
+        );
+    }
+}
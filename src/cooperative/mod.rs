@@ -55,6 +55,9 @@ mod scheduler;
 mod stream;
 mod tls;
 
+#[cfg(test)]
+mod soak_tests;
+
 pub use scheduler::{
     // The critical functions that avoid borrow-across-await
     drive_scheduler,
@@ -63,6 +66,7 @@ pub use scheduler::{
     PendingWork,
     SchedulerDriver,
     SchedulerError,
+    SchedulerEvent,
     SchedulerStats,
     StreamHandle,
     WorkResult,
@@ -72,8 +76,9 @@ pub use tls::CooperativeTlsStream;
 
 // Configuration constants - exposed for documentation/testing
 pub use scheduler::{
-    DEFAULT_RECEIVE_TIMEOUT_MS, DEFAULT_SEND_TIMEOUT_MS, MAX_CELLS_PER_STREAM, MAX_INCOMING_BUFFER,
-    MAX_STREAMS_PER_CIRCUIT, MAX_TOTAL_QUEUED_CELLS,
+    DEFAULT_RECEIVE_TIMEOUT_MS, DEFAULT_SEND_TIMEOUT_MS, DEFAULT_STREAM_IDLE_TIMEOUT_MS,
+    MAX_CELLS_PER_STREAM, MAX_INCOMING_BUFFER, MAX_STREAMS_PER_CIRCUIT, MAX_TOTAL_QUEUED_CELLS,
+    ORPHAN_TIMEOUT_MS,
 };
 
 /// Helper function to open a stream using the cooperative pattern
@@ -83,13 +88,18 @@ pub use scheduler::{
 /// 2. Sending RELAY_BEGIN
 /// 3. Waiting for RELAY_CONNECTED
 /// 4. Returning a ready-to-use CooperativeStream
+///
+/// `flags` set which address families the exit may connect over.
 pub async fn open_cooperative_stream(
     scheduler: &std::rc::Rc<std::cell::RefCell<CooperativeCircuit>>,
     host: &str,
     port: u16,
+    flags: crate::protocol::BeginFlags,
 ) -> crate::error::Result<CooperativeStream> {
     use crate::error::TorError;
-    use crate::protocol::{RelayCell, RelayCommand};
+    use crate::protocol::{begin_payload, parse_connected, RelayCell, RelayCommand};
+
+    let payload = begin_payload(host, port, flags)?;
 
     // Check if we can open a stream (brief borrow)
     let stream_id = {
@@ -106,8 +116,7 @@ pub async fn open_cooperative_stream(
     log::info!("📡 Opening stream {} to {}:{}", stream_id, host, port);
 
     // Create RELAY_BEGIN cell
-    let target = format!("{}:{}\0", host, port);
-    let begin_cell = RelayCell::new(RelayCommand::Begin, stream_id, target.as_bytes().to_vec());
+    let begin_cell = RelayCell::new(RelayCommand::Begin, stream_id, payload);
 
     // Queue the send (brief borrow)
     let send_rx = {
@@ -142,10 +151,10 @@ pub async fn open_cooperative_stream(
                 s.mark_stream_open(stream_id);
             }
 
-            Ok(CooperativeStream::new(
-                StreamHandle { stream_id },
-                std::rc::Rc::clone(scheduler),
-            ))
+            Ok(
+                CooperativeStream::new(StreamHandle { stream_id }, std::rc::Rc::clone(scheduler))
+                    .with_connected_address(parse_connected(&cell.data)),
+            )
         }
         RelayCommand::End => {
             // Clean up failed stream (brief borrow)
@@ -25,8 +25,11 @@ use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
 
+use crate::cancel::CancelToken;
 use crate::error::{Result, TorError};
-use crate::protocol::{Circuit, RelayCell};
+use crate::events::{self, TorEvent};
+use crate::protocol::{Circuit, RelayCell, RelayCommand, StreamFlowControl};
+use crate::runtime::{LocalCell, TimerId, TimerService};
 
 // ============================================================================
 // CONFIGURATION CONSTANTS
@@ -50,25 +53,72 @@ pub const DEFAULT_RECEIVE_TIMEOUT_MS: u32 = 30_000; // 30 seconds
 /// Default timeout for send operations (milliseconds)
 pub const DEFAULT_SEND_TIMEOUT_MS: u32 = 10_000; // 10 seconds
 
+/// How long cells for unknown streams are kept (milliseconds)
+pub const ORPHAN_TIMEOUT_MS: u64 = 10_000;
+
+/// Default idle period after which a stream nobody sends on or reads from
+/// is reaped (milliseconds)
+pub const DEFAULT_STREAM_IDLE_TIMEOUT_MS: u64 = 120_000;
+
+/// RELAY_END reason sent when reaping a stream (REASON_DONE)
+const END_REASON_DONE: u8 = 6;
+
 // ============================================================================
 // QUEUED OPERATIONS
 // ============================================================================
 
+/// Delivery slot shared between a waiter and its timeout timer.
+/// Whichever side takes the sender first wins.
+type DeliverySlot<T> = Rc<LocalCell<Option<oneshot::Sender<Result<T>>>>>;
+
 /// A cell operation queued for sending
 struct QueuedSend {
     cell: RelayCell,
-    /// Channel to notify when send completes
-    completion: oneshot::Sender<Result<()>>,
-    /// Deadline (js_sys::Date::now() timestamp)
-    deadline: f64,
+    /// Channel to notify when send completes (empty once timed out)
+    completion: DeliverySlot<()>,
+    /// Timeout registered with the timer service
+    timer: TimerId,
+}
+
+impl QueuedSend {
+    /// Whether the sender is still waiting for the cell to go out
+    fn is_pending(&self) -> bool {
+        self.completion.with(|slot| slot.is_some())
+    }
+
+    /// Cancel the timeout and take the sender (if it has not fired)
+    fn claim(&self, timers: &TimerService) -> Option<oneshot::Sender<Result<()>>> {
+        timers.cancel(self.timer);
+        self.completion.replace(None)
+    }
+
+    /// Fail the sender with `error` (if it has not timed out)
+    fn fail(self, timers: &TimerService, error: TorError) {
+        if let Some(completion) = self.claim(timers) {
+            let _ = completion.send(Err(error));
+        }
+    }
 }
 
 /// A pending receive operation
 struct PendingReceive {
-    /// Channel to deliver the received cell
-    delivery: oneshot::Sender<Result<RelayCell>>,
-    /// Deadline (js_sys::Date::now() timestamp)
-    deadline: f64,
+    /// Channel to deliver the received cell (empty once timed out)
+    delivery: DeliverySlot<RelayCell>,
+    /// Timeout registered with the timer service
+    timer: TimerId,
+}
+
+impl PendingReceive {
+    /// Whether the waiter can still receive a cell
+    fn is_pending(&self) -> bool {
+        self.delivery.with(|slot| slot.is_some())
+    }
+
+    /// Cancel the timeout and take the sender (if it has not fired)
+    fn claim(self, timers: &TimerService) -> Option<oneshot::Sender<Result<RelayCell>>> {
+        timers.cancel(self.timer);
+        self.delivery.replace(None)
+    }
 }
 
 /// Metadata about an active stream
@@ -77,12 +127,17 @@ struct StreamInfo {
     host: String,
     port: u16,
     state: StreamState,
-    send_window: u16,
-    recv_window: u16,
+    /// Stream-level SENDME windows
+    flow: StreamFlowControl,
     /// Per-stream send queue for fair scheduling
     send_queue: VecDeque<QueuedSend>,
     /// Cells received but not yet read by stream
     recv_buffer: VecDeque<RelayCell>,
+    /// Last send or receive the stream's owner asked for (incoming cells
+    /// don't count: they arrive whether or not anyone is reading)
+    last_activity: u64,
+    /// Why the stream ended, once known (reported in `stream_ended`)
+    end_reason: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -166,6 +221,29 @@ impl From<SchedulerError> for TorError {
     }
 }
 
+/// Something the scheduler did on its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchedulerEvent {
+    /// An idle stream was closed with RELAY_END and its state freed
+    StreamReaped {
+        stream_id: u16,
+        host: String,
+        port: u16,
+        idle_ms: u64,
+    },
+}
+
+impl SchedulerEvent {
+    /// Event name (`stream_reaped`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchedulerEvent::StreamReaped { .. } => "stream_reaped",
+        }
+    }
+}
+
+type EventListener = Rc<dyn Fn(&SchedulerEvent)>;
+
 // ============================================================================
 // PENDING WORK - What needs to be done outside the borrow
 // ============================================================================
@@ -228,19 +306,44 @@ pub struct CooperativeCircuit {
     /// Order of stream IDs for round-robin
     stream_order: Vec<u16>,
 
-    /// Incoming cells for streams that haven't registered to receive yet
-    orphan_buffer: VecDeque<(u16, RelayCell)>,
+    /// Incoming cells for streams that haven't registered to receive yet,
+    /// with their arrival time
+    orphan_buffer: VecDeque<(u16, u64, RelayCell)>,
 
     /// Circuit death reason (if dead)
     death_reason: Option<String>,
 
     /// Total cells currently queued across all streams
     total_queued_cells: usize,
+
+    /// Timer service for operation timeouts
+    timers: TimerService,
+
+    /// Idle period after which a stream is reaped (None = never)
+    stream_idle_timeout_ms: Option<u64>,
+
+    /// Cells owned by no stream (END for reaped streams), sent before
+    /// any stream's queue
+    control_queue: VecDeque<RelayCell>,
+
+    /// Streams reaped for inactivity
+    reaped_streams: u64,
+
+    /// Called for every [`SchedulerEvent`]
+    listener: Option<EventListener>,
+
+    /// Cancellation of the request using this circuit
+    cancel: Option<CancelToken>,
 }
 
 impl CooperativeCircuit {
     /// Create a new cooperative circuit scheduler
     pub fn new(circuit: Circuit) -> Self {
+        Self::with_timers(circuit, TimerService::global())
+    }
+
+    /// Create a scheduler whose timeouts use the given timer service
+    pub fn with_timers(circuit: Circuit, timers: TimerService) -> Self {
         let circuit_id = circuit.id;
         log::info!("🎛️ Creating CooperativeCircuit for circuit {}", circuit_id);
 
@@ -255,9 +358,36 @@ impl CooperativeCircuit {
             orphan_buffer: VecDeque::new(),
             death_reason: None,
             total_queued_cells: 0,
+            timers,
+            stream_idle_timeout_ms: Some(DEFAULT_STREAM_IDLE_TIMEOUT_MS),
+            control_queue: VecDeque::new(),
+            reaped_streams: 0,
+            listener: None,
+            cancel: None,
         }
     }
 
+    /// Reap streams idle for `timeout_ms` (None turns reaping off)
+    pub fn with_stream_idle_timeout(mut self, timeout_ms: Option<u64>) -> Self {
+        self.stream_idle_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// End every stream with RELAY_END, failing its queued work, once
+    /// `cancel` is cancelled
+    pub fn with_cancel(mut self, cancel: Option<CancelToken>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Register a callback invoked for every scheduler event
+    ///
+    /// It runs while the scheduler is borrowed, so it must not touch the
+    /// scheduler itself.
+    pub fn set_listener(&mut self, listener: impl Fn(&SchedulerEvent) + 'static) {
+        self.listener = Some(Rc::new(listener));
+    }
+
     /// Get the circuit ID
     pub fn id(&self) -> u32 {
         self.circuit_id
@@ -342,14 +472,27 @@ impl CooperativeCircuit {
             });
         }
 
+        // The timer fails the sender even if nobody ticks; the next tick
+        // drops the expired cell from the queue
         let (tx, rx) = oneshot::channel();
         let timeout = timeout_ms.unwrap_or(DEFAULT_SEND_TIMEOUT_MS);
-        let deadline = js_sys::Date::now() + timeout as f64;
+        let completion: DeliverySlot<()> = Rc::new(LocalCell::new(Some(tx)));
+        let slot = completion.clone();
+        let timer = self.timers.schedule(
+            std::time::Duration::from_millis(timeout as u64),
+            move || {
+                if let Some(tx) = slot.replace(None) {
+                    log::warn!("⏰ Send timeout for stream {}", stream_id);
+                    let _ = tx.send(Err(TorError::Timeout));
+                }
+            },
+        );
+        stream.last_activity = self.timers.now_ms();
 
         stream.send_queue.push_back(QueuedSend {
             cell,
-            completion: tx,
-            deadline,
+            completion,
+            timer,
         });
         self.total_queued_cells += 1;
 
@@ -379,6 +522,7 @@ impl CooperativeCircuit {
             .streams
             .get_mut(&stream_id)
             .ok_or(SchedulerError::StreamNotFound { stream_id })?;
+        stream.last_activity = self.timers.now_ms();
 
         let (tx, rx) = oneshot::channel();
 
@@ -389,30 +533,32 @@ impl CooperativeCircuit {
             return Ok(rx);
         }
 
-        // Check orphan buffer
-        if let Some(idx) = self
-            .orphan_buffer
-            .iter()
-            .position(|(sid, _)| *sid == stream_id)
-        {
-            let (_, cell) = self.orphan_buffer.remove(idx).unwrap();
-            log::trace!("📥 Returning orphan cell for stream {}", stream_id);
-            let _ = tx.send(Ok(cell));
-            return Ok(rx);
-        }
-
-        // Register to wait
+        // Register to wait; the timer fails the waiter even if nobody ticks
         let timeout = timeout_ms.unwrap_or(DEFAULT_RECEIVE_TIMEOUT_MS);
-        let deadline = js_sys::Date::now() + timeout as f64;
-
-        self.recv_waiters.insert(
-            stream_id,
-            PendingReceive {
-                delivery: tx,
-                deadline,
+        let delivery: DeliverySlot<RelayCell> = Rc::new(LocalCell::new(Some(tx)));
+        let slot = delivery.clone();
+        let timer = self.timers.schedule(
+            std::time::Duration::from_millis(timeout as u64),
+            move || {
+                if let Some(tx) = slot.replace(None) {
+                    log::warn!("⏰ Receive timeout for stream {}", stream_id);
+                    let _ = tx.send(Err(TorError::Timeout));
+                }
             },
         );
 
+        if let Some(previous) = self
+            .recv_waiters
+            .insert(stream_id, PendingReceive { delivery, timer })
+        {
+            if let Some(delivery) = previous.claim(&self.timers) {
+                let _ = delivery.send(Err(TorError::Stream(format!(
+                    "Receive on stream {} superseded",
+                    stream_id
+                ))));
+            }
+        }
+
         log::trace!(
             "📥 Registered receive waiter for stream {} (timeout: {}ms)",
             stream_id,
@@ -440,43 +586,73 @@ impl CooperativeCircuit {
 
         // Expire timed-out operations
         self.expire_timed_out_operations();
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            self.cancel_streams();
+        }
 
         // Get next send (round-robin)
         if let Some(work) = self.take_next_send() {
             return work;
         }
 
-        // If anyone is waiting to receive, indicate we should check
-        if !self.recv_waiters.is_empty() {
+        // If anyone is waiting to receive, indicate we should check; sends
+        // held back by a closed window also wait for a SENDME to arrive
+        if !self.recv_waiters.is_empty() || self.total_queued_cells > 0 {
             return PendingWork::Receive;
         }
 
         PendingWork::Idle
     }
 
-    /// Take the next cell to send (round-robin across streams)
+    /// Take the next cell to send (control cells first, then round-robin
+    /// across streams)
     fn take_next_send(&mut self) -> Option<PendingWork> {
+        if let Some(cell) = self.control_queue.pop_front() {
+            // Nobody waits on a control cell
+            let (completion, _) = oneshot::channel();
+            return Some(PendingWork::Send {
+                stream_id: cell.stream_id,
+                cell,
+                completion,
+            });
+        }
+
         if self.stream_order.is_empty() {
             return None;
         }
 
-        // Try each stream in round-robin order
+        // Try each stream in round-robin order, passing over DATA cells that
+        // a closed circuit or stream window holds back
+        let circuit_open = self.circuit.as_ref().is_none_or(Circuit::can_package);
         let start_index = self.round_robin_index;
         loop {
             let stream_id = self.stream_order[self.round_robin_index];
             self.round_robin_index = (self.round_robin_index + 1) % self.stream_order.len();
 
             if let Some(stream) = self.streams.get_mut(&stream_id) {
-                if let Some(queued) = stream.send_queue.pop_front() {
+                let stalled = stream.send_queue.front().is_some_and(|q| {
+                    q.cell.command == RelayCommand::Data
+                        && !(circuit_open && stream.flow.can_send())
+                });
+                if stalled {
+                    log::trace!("⏸️ Stream {} waiting for SENDME", stream_id);
+                } else if let Some(queued) = stream.send_queue.pop_front() {
                     self.total_queued_cells = self.total_queued_cells.saturating_sub(1);
 
-                    log::trace!("📤 Taking cell for stream {} (round-robin)", stream_id);
+                    // A send that timed out since the last sweep is dropped
+                    if let Some(completion) = queued.claim(&self.timers) {
+                        if queued.cell.command == RelayCommand::Data {
+                            let _ = stream.flow.on_send();
+                        }
 
-                    return Some(PendingWork::Send {
-                        stream_id,
-                        cell: queued.cell,
-                        completion: queued.completion,
-                    });
+                        log::trace!("📤 Taking cell for stream {} (round-robin)", stream_id);
+
+                        return Some(PendingWork::Send {
+                            stream_id,
+                            cell: queued.cell,
+                            completion,
+                        });
+                    }
                 }
             }
 
@@ -495,6 +671,10 @@ impl CooperativeCircuit {
             return false;
         }
 
+        if !self.control_queue.is_empty() {
+            return true;
+        }
+
         // Check if any stream has queued sends
         for stream in self.streams.values() {
             if !stream.send_queue.is_empty() {
@@ -503,26 +683,19 @@ impl CooperativeCircuit {
         }
 
         // Check if any receives are waiting
-        !self.recv_waiters.is_empty()
+        self.recv_waiters.values().any(PendingReceive::is_pending)
     }
 
     /// Expire timed-out send and receive operations
     fn expire_timed_out_operations(&mut self) {
-        let now = js_sys::Date::now();
+        let now = self.timers.now_ms();
 
-        // Expire send operations
+        // Send timeouts also fire from the timer service; drop their cells
         for stream in self.streams.values_mut() {
-            let mut expired_count = 0;
-            while let Some(front) = stream.send_queue.front() {
-                if now > front.deadline {
-                    let queued = stream.send_queue.pop_front().unwrap();
-                    let _ = queued.completion.send(Err(TorError::Timeout));
-                    expired_count += 1;
-                    self.total_queued_cells = self.total_queued_cells.saturating_sub(1);
-                } else {
-                    break; // Queue is ordered by insertion time
-                }
-            }
+            let queued = stream.send_queue.len();
+            stream.send_queue.retain(QueuedSend::is_pending);
+            let expired_count = queued - stream.send_queue.len();
+            self.total_queued_cells = self.total_queued_cells.saturating_sub(expired_count);
             if expired_count > 0 {
                 log::warn!(
                     "⏰ Expired {} send operations for stream {}",
@@ -532,19 +705,91 @@ impl CooperativeCircuit {
             }
         }
 
-        // Expire receive operations
-        let expired_receives: Vec<u16> = self
-            .recv_waiters
-            .iter()
-            .filter(|(_, waiter)| now > waiter.deadline)
-            .map(|(stream_id, _)| *stream_id)
+        // Receive timeouts fire from the timer service; drop their waiters
+        self.recv_waiters.retain(|_, waiter| waiter.is_pending());
+
+        // Nobody claimed these in time
+        while let Some((_, arrived, _)) = self.orphan_buffer.front() {
+            if now <= arrived + ORPHAN_TIMEOUT_MS {
+                break;
+            }
+            self.orphan_buffer.pop_front();
+        }
+
+        self.reap_idle_streams(now);
+    }
+
+    /// Close streams whose owner stopped sending and reading (a JS consumer
+    /// that went away): send RELAY_END, free their state and report them
+    fn reap_idle_streams(&mut self, now: u64) {
+        let Some(timeout) = self.stream_idle_timeout_ms else {
+            return;
+        };
+        let idle: Vec<u16> = self
+            .streams
+            .values()
+            .filter(|stream| {
+                stream.send_queue.is_empty()
+                    && !self.recv_waiters.contains_key(&stream.stream_id)
+                    && now.saturating_sub(stream.last_activity) > timeout
+            })
+            .map(|stream| stream.stream_id)
             .collect();
 
-        for stream_id in expired_receives {
-            if let Some(waiter) = self.recv_waiters.remove(&stream_id) {
-                log::warn!("⏰ Receive timeout for stream {}", stream_id);
-                let _ = waiter.delivery.send(Err(TorError::Timeout));
+        for stream_id in idle {
+            let Some(stream) = self.streams.get(&stream_id) else {
+                continue;
+            };
+            let idle_ms = now.saturating_sub(stream.last_activity);
+            log::warn!(
+                "🧟 Reaping stream {} to {}:{} (idle {}s)",
+                stream_id,
+                stream.host,
+                stream.port,
+                idle_ms / 1000
+            );
+            // A half-closed stream already sent its END
+            if matches!(stream.state, StreamState::Opening | StreamState::Open) {
+                self.control_queue.push_back(RelayCell::new(
+                    RelayCommand::End,
+                    stream_id,
+                    vec![END_REASON_DONE],
+                ));
+            }
+            let event = SchedulerEvent::StreamReaped {
+                stream_id,
+                host: stream.host.clone(),
+                port: stream.port,
+                idle_ms,
+            };
+            self.set_end_reason(stream_id, format!("idle for {}s", idle_ms / 1000));
+            self.remove_stream(stream_id);
+            self.reaped_streams += 1;
+            if let Some(listener) = &self.listener {
+                listener(&event);
+            }
+        }
+    }
+
+    /// End all streams of a cancelled request
+    fn cancel_streams(&mut self) {
+        let stream_ids: Vec<u16> = self.streams.keys().copied().collect();
+        for stream_id in stream_ids {
+            // A half-closed stream already sent its END
+            if self
+                .streams
+                .get(&stream_id)
+                .is_some_and(|s| matches!(s.state, StreamState::Opening | StreamState::Open))
+            {
+                self.control_queue.push_back(RelayCell::new(
+                    RelayCommand::End,
+                    stream_id,
+                    vec![END_REASON_DONE],
+                ));
             }
+            log::info!("🛑 Ending stream {} of a cancelled request", stream_id);
+            self.set_end_reason(stream_id, "cancelled".into());
+            self.fail_stream(stream_id, TorError::Cancelled);
         }
     }
 
@@ -561,9 +806,46 @@ impl CooperativeCircuit {
             cell.command
         );
 
-        // Route to waiting stream
-        if let Some(waiter) = self.recv_waiters.remove(&stream_id) {
-            let _ = waiter.delivery.send(Ok(cell));
+        // Stream 0 carries circuit-level cells (RELAY_DROP padding, circuit
+        // SENDMEs) that no stream will ever claim. TRUNCATED means the exit
+        // is gone: every stream went with it, and new ones must not be sent
+        // to whichever hop is now last.
+        if stream_id == 0 {
+            if cell.command == RelayCommand::Truncated {
+                let reason = cell.data.first().copied().unwrap_or(0);
+                self.mark_circuit_dead(format!("Circuit truncated by relay (reason: {})", reason));
+            }
+            return;
+        }
+
+        // Stream-level flow control: SENDMEs from the exit reopen our window
+        // and aren't passed on; every 50th DATA cell is acknowledged
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            match cell.command {
+                RelayCommand::Sendme => {
+                    stream.flow.on_sendme_received();
+                    return;
+                }
+                RelayCommand::Data if stream.flow.on_receive_data() => {
+                    self.queue_stream_sendme(stream_id);
+                }
+                RelayCommand::End => {
+                    let reason = cell.data.first().copied().unwrap_or(0);
+                    stream
+                        .end_reason
+                        .get_or_insert_with(|| format!("ended by exit (reason: {})", reason));
+                }
+                _ => {}
+            }
+        }
+
+        // Route to waiting stream (unless its timeout already fired)
+        let waiter = self
+            .recv_waiters
+            .remove(&stream_id)
+            .and_then(|waiter| waiter.claim(&self.timers));
+        if let Some(delivery) = waiter {
+            let _ = delivery.send(Ok(cell));
         }
         // Or buffer in stream's recv_buffer
         else if let Some(stream) = self.streams.get_mut(&stream_id) {
@@ -573,12 +855,22 @@ impl CooperativeCircuit {
                 log::warn!("⚠️ Stream {} recv buffer full, dropping cell", stream_id);
             }
         }
+        // An END for a stream we don't know needs no answer (and a closed
+        // stream's END must not be answered with another)
+        else if cell.command == RelayCommand::End {
+            log::debug!(
+                "🔚 END for unknown stream {} (reason: {})",
+                stream_id,
+                cell.data.first().copied().unwrap_or(0)
+            );
+        }
         // Or buffer as orphan (stream might register soon)
         else {
-            self.orphan_buffer.push_back((stream_id, cell));
+            let now = self.timers.now_ms();
+            self.orphan_buffer.push_back((stream_id, now, cell));
             // Clean up orphan buffer if too large
             while self.orphan_buffer.len() > MAX_INCOMING_BUFFER {
-                let (old_stream_id, _) = self.orphan_buffer.pop_front().unwrap();
+                let (old_stream_id, _, _) = self.orphan_buffer.pop_front().unwrap();
                 log::warn!(
                     "⚠️ Evicting orphan cell for stream {} (buffer full)",
                     old_stream_id
@@ -587,30 +879,51 @@ impl CooperativeCircuit {
         }
     }
 
+    /// Acknowledge a stream's DATA cells ahead of any queued data
+    fn queue_stream_sendme(&mut self, stream_id: u16) {
+        log::trace!("📤 Queueing SENDME for stream {}", stream_id);
+        self.control_queue
+            .push_back(RelayCell::new(RelayCommand::Sendme, stream_id, vec![]));
+    }
+
     /// Mark the circuit as dead and notify all waiters
     pub fn mark_circuit_dead(&mut self, reason: String) {
         log::error!("💀 Circuit {} dead: {}", self.circuit_id, reason);
 
         self.death_reason = Some(reason.clone());
+        if let Some(circuit) = self.circuit.as_mut() {
+            circuit.set_close_reason(reason.clone());
+        }
         self.circuit = None; // Drop the circuit
+        for stream in self.streams.values_mut() {
+            stream
+                .end_reason
+                .get_or_insert_with(|| format!("circuit closed: {}", reason));
+        }
 
         let error = TorError::CircuitClosed(reason);
 
         // Notify all receive waiters
         for (stream_id, waiter) in self.recv_waiters.drain() {
-            log::debug!("  Notifying recv waiter for stream {}", stream_id);
-            let _ = waiter.delivery.send(Err(error.clone()));
+            if let Some(delivery) = waiter.claim(&self.timers) {
+                log::debug!("  Notifying recv waiter for stream {}", stream_id);
+                let _ = delivery.send(Err(error.clone()));
+            }
         }
 
         // Notify all send waiters
         for stream in self.streams.values_mut() {
             for queued in stream.send_queue.drain(..) {
                 log::debug!("  Notifying send waiter for stream {}", stream.stream_id);
-                let _ = queued.completion.send(Err(error.clone()));
+                queued.fail(&self.timers, error.clone());
             }
         }
 
         self.total_queued_cells = 0;
+        self.control_queue.clear();
+
+        // No stream will register on a dead circuit
+        self.orphan_buffer.clear();
     }
 
     // ========================================================================
@@ -618,7 +931,42 @@ impl CooperativeCircuit {
     // ========================================================================
 
     /// Register a new stream (internal use during open_stream)
+    ///
+    /// Call this before sending BEGIN: from here on the stream's cells are
+    /// buffered for it instead of competing for orphan buffer space, and
+    /// any that arrived early are claimed now.
     pub fn register_stream(&mut self, stream_id: u16, host: &str, port: u16) {
+        let (claimed, orphans): (VecDeque<_>, VecDeque<_>) = self
+            .orphan_buffer
+            .drain(..)
+            .partition(|(id, _, _)| *id == stream_id);
+        self.orphan_buffer = orphans;
+        if claimed.len() > MAX_CELLS_PER_STREAM {
+            log::warn!(
+                "⚠️ Dropping {} early cells for stream {} (recv buffer full)",
+                claimed.len() - MAX_CELLS_PER_STREAM,
+                stream_id
+            );
+        }
+        let recv_buffer: VecDeque<RelayCell> = claimed
+            .into_iter()
+            .take(MAX_CELLS_PER_STREAM)
+            .map(|(_, _, cell)| cell)
+            .collect();
+        let mut flow = StreamFlowControl::new(stream_id);
+        for cell in &recv_buffer {
+            if cell.command == RelayCommand::Data && flow.on_receive_data() {
+                self.queue_stream_sendme(stream_id);
+            }
+        }
+        if !recv_buffer.is_empty() {
+            log::trace!(
+                "📥 Stream {} claimed {} orphan cells",
+                stream_id,
+                recv_buffer.len()
+            );
+        }
+
         self.streams.insert(
             stream_id,
             StreamInfo {
@@ -626,10 +974,11 @@ impl CooperativeCircuit {
                 host: host.to_string(),
                 port,
                 state: StreamState::Opening,
-                send_window: 500,
-                recv_window: 500,
+                flow,
                 send_queue: VecDeque::new(),
-                recv_buffer: VecDeque::new(),
+                recv_buffer,
+                last_activity: self.timers.now_ms(),
+                end_reason: None,
             },
         );
         self.stream_order.push(stream_id);
@@ -638,14 +987,61 @@ impl CooperativeCircuit {
     /// Mark stream as open
     pub fn mark_stream_open(&mut self, stream_id: u16) {
         if let Some(info) = self.streams.get_mut(&stream_id) {
+            if info.state == StreamState::Opening {
+                events::post(TorEvent::StreamOpened {
+                    circuit_id: self.circuit_id,
+                    stream_id,
+                    host: info.host.clone(),
+                    port: info.port,
+                });
+            }
             info.state = StreamState::Open;
         }
     }
 
-    /// Remove a stream
+    /// Record why a stream is ending, unless a reason is already known
+    fn set_end_reason(&mut self, stream_id: u16, reason: String) {
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.end_reason.get_or_insert(reason);
+        }
+    }
+
+    /// Remove a stream, failing its queued sends and pending receive
     pub fn remove_stream(&mut self, stream_id: u16) {
-        self.streams.remove(&stream_id);
+        let error = TorError::Stream(format!("Stream {} closed", stream_id));
+        self.fail_stream(stream_id, error);
+    }
+
+    /// Remove a stream, failing its queued sends and pending receive with
+    /// `error`
+    fn fail_stream(&mut self, stream_id: u16, error: TorError) {
+        if let Some(stream) = self.streams.remove(&stream_id) {
+            self.total_queued_cells = self
+                .total_queued_cells
+                .saturating_sub(stream.send_queue.len());
+            for queued in stream.send_queue {
+                queued.fail(&self.timers, error.clone());
+            }
+            // Only streams the exit accepted were announced
+            if stream.state != StreamState::Opening {
+                events::post(TorEvent::StreamEnded {
+                    circuit_id: self.circuit_id,
+                    stream_id,
+                    reason: stream.end_reason.unwrap_or_else(|| "closed".into()),
+                });
+            }
+        }
+        if let Some(delivery) = self
+            .recv_waiters
+            .remove(&stream_id)
+            .and_then(|waiter| waiter.claim(&self.timers))
+        {
+            let _ = delivery.send(Err(error));
+        }
         self.stream_order.retain(|&id| id != stream_id);
+        if self.round_robin_index >= self.stream_order.len() {
+            self.round_robin_index = 0;
+        }
     }
 
     /// Get next stream ID
@@ -677,6 +1073,8 @@ impl CooperativeCircuit {
             total_queued_sends: self.total_queued_cells,
             pending_receives: self.recv_waiters.len(),
             orphan_buffer_size: self.orphan_buffer.len(),
+            scheduled_streams: self.stream_order.len(),
+            reaped_streams: self.reaped_streams,
         }
     }
 }
@@ -690,6 +1088,10 @@ pub struct SchedulerStats {
     pub total_queued_sends: usize,
     pub pending_receives: usize,
     pub orphan_buffer_size: usize,
+    /// Streams in the round-robin order (should equal `stream_count`)
+    pub scheduled_streams: usize,
+    /// Streams closed for inactivity
+    pub reaped_streams: u64,
 }
 
 /// A handle to a stream on a cooperative circuit
@@ -887,4 +1289,426 @@ mod tests {
         let handle = StreamHandle { stream_id: 42 };
         assert_eq!(handle.stream_id(), 42);
     }
+
+    // ===== Randomized interleavings =====
+
+    use crate::protocol::CircuitKeys;
+    use crate::runtime::timer::MockClock;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    /// Interleavings tried, and operations per interleaving
+    const CASES: u64 = 200;
+    const OPS_PER_CASE: usize = 1_000;
+
+    fn scheduler(clock: &MockClock) -> CooperativeCircuit {
+        let keys = CircuitKeys::derive_from_secret(&[1; 32]).unwrap();
+        CooperativeCircuit::with_timers(
+            Circuit::new(1, Vec::new(), keys),
+            TimerService::with_clock(Rc::new(clock.clone())),
+        )
+    }
+
+    /// DATA cell carrying a unique tag
+    fn tagged(stream_id: u16, tag: u32) -> RelayCell {
+        RelayCell::new(RelayCommand::Data, stream_id, tag.to_be_bytes().to_vec())
+    }
+
+    fn tag_of(cell: &RelayCell) -> u32 {
+        u32::from_be_bytes(cell.data[..4].try_into().unwrap())
+    }
+
+    impl CooperativeCircuit {
+        fn check_invariants(&self) {
+            let queued: usize = self.streams.values().map(|s| s.send_queue.len()).sum();
+            assert_eq!(self.total_queued_cells, queued, "queued cell count drifted");
+            assert!(queued <= MAX_TOTAL_QUEUED_CELLS);
+            for stream in self.streams.values() {
+                assert!(stream.send_queue.len() <= MAX_CELLS_PER_STREAM);
+                assert!(stream.recv_buffer.len() <= MAX_CELLS_PER_STREAM);
+            }
+            assert!(self.orphan_buffer.len() <= MAX_INCOMING_BUFFER);
+            assert!(
+                self.orphan_buffer
+                    .iter()
+                    .all(|(id, _, _)| !self.streams.contains_key(id)),
+                "orphan cell for a registered stream"
+            );
+
+            let mut order = self.stream_order.clone();
+            order.sort_unstable();
+            let mut ids: Vec<u16> = self.streams.keys().copied().collect();
+            ids.sort_unstable();
+            assert_eq!(order, ids, "stream_order out of sync with streams");
+            assert!(self.stream_order.is_empty() || self.round_robin_index < order.len());
+        }
+    }
+
+    /// Apply random operations, checking invariants after each, then tear
+    /// down and check every operation completed exactly once
+    fn run_interleaving(seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let clock = MockClock::new(1_000);
+        let mut s = scheduler(&clock);
+        let mut sends = Vec::new();
+        let mut receives = Vec::new();
+        let mut sent_tags = HashSet::new();
+        let mut next_tag = 0u32;
+
+        for _ in 0..OPS_PER_CASE {
+            let stream_id = match s.stream_order.choose(&mut rng) {
+                Some(&id) if rng.gen_bool(0.8) => id,
+                _ => rng.gen_range(0..=30),
+            };
+            next_tag += 1;
+
+            match rng.gen_range(0..100) {
+                0..=7 => {
+                    if s.can_open_stream() {
+                        let id = s.next_stream_id();
+                        s.register_stream(id, "example.com", 80);
+                    }
+                }
+                8..=11 => s.remove_stream(stream_id),
+                12..=41 => {
+                    let timeout = rng.gen_range(1..2_000);
+                    match s.queue_send(stream_id, tagged(stream_id, next_tag), Some(timeout)) {
+                        Ok(rx) => sends.push((next_tag, rx)),
+                        Err(SchedulerError::SendQueueFull { .. }) => assert!(
+                            s.total_queued_cells >= MAX_TOTAL_QUEUED_CELLS
+                                || s.streams[&stream_id].send_queue.len() >= MAX_CELLS_PER_STREAM
+                        ),
+                        Err(SchedulerError::StreamNotFound { .. }) => {
+                            assert!(!s.streams.contains_key(&stream_id))
+                        }
+                        Err(SchedulerError::CircuitDead { .. }) => assert!(!s.is_alive()),
+                        Err(e) => panic!("unexpected queue_send error: {}", e),
+                    }
+                }
+                42..=56 => {
+                    let timeout = rng.gen_range(1..2_000);
+                    if let Ok(rx) = s.register_receive(stream_id, Some(timeout)) {
+                        receives.push((stream_id, rx));
+                    }
+                }
+                57..=71 => s.deliver_received(tagged(stream_id, next_tag)),
+                72..=89 => {
+                    if let PendingWork::Send {
+                        stream_id,
+                        cell,
+                        completion,
+                    } = s.tick_sync()
+                    {
+                        assert_eq!(cell.stream_id, stream_id);
+                        if cell.command == RelayCommand::End {
+                            // Reaped for inactivity
+                            assert!(!s.streams.contains_key(&stream_id));
+                        } else {
+                            assert!(sent_tags.insert(tag_of(&cell)), "cell sent twice");
+                        }
+                        let _ = completion.send(Ok(()));
+                    }
+                }
+                90..=98 => {
+                    clock.advance(rng.gen_range(0..1_500));
+                    s.timers.run_expired();
+                }
+                _ => s.mark_circuit_dead("random failure".into()),
+            }
+            s.check_invariants();
+        }
+
+        // Let every timeout fire, then fail whatever is left
+        clock.advance(10_000);
+        s.timers.run_expired();
+        s.tick_sync();
+        s.mark_circuit_dead("teardown".into());
+        s.check_invariants();
+
+        for (tag, mut rx) in sends {
+            match rx.try_recv() {
+                Ok(Some(Ok(()))) => assert!(sent_tags.contains(&tag)),
+                Ok(Some(Err(_))) => assert!(!sent_tags.contains(&tag)),
+                _ => panic!("send {} never completed", tag),
+            }
+        }
+        let mut delivered = HashSet::new();
+        for (stream_id, mut rx) in receives {
+            match rx.try_recv() {
+                Ok(Some(Ok(cell))) => {
+                    assert_eq!(cell.stream_id, stream_id);
+                    assert!(delivered.insert(tag_of(&cell)), "cell delivered twice");
+                }
+                Ok(Some(Err(_))) => {}
+                _ => panic!("receive on stream {} never completed", stream_id),
+            }
+        }
+        for (_, _, cell) in &s.orphan_buffer {
+            assert!(
+                delivered.insert(tag_of(cell)),
+                "delivered cell still buffered"
+            );
+        }
+        for cell in s.streams.values().flat_map(|st| &st.recv_buffer) {
+            assert!(
+                delivered.insert(tag_of(cell)),
+                "delivered cell still buffered"
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_interleavings_keep_invariants() {
+        for seed in 0..CASES {
+            if std::panic::catch_unwind(|| run_interleaving(seed)).is_err() {
+                panic!("scheduler invariant violated with seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_stream_fails_its_operations() {
+        let clock = MockClock::new(0);
+        let mut s = scheduler(&clock);
+        s.register_stream(1, "example.com", 80);
+        s.register_stream(2, "example.com", 80);
+        let mut send = s.queue_send(1, tagged(1, 1), None).unwrap();
+        let mut recv = s.register_receive(1, None).unwrap();
+        s.round_robin_index = 1;
+
+        s.remove_stream(2);
+        s.remove_stream(1);
+        s.check_invariants();
+        assert!(matches!(
+            send.try_recv(),
+            Ok(Some(Err(TorError::Stream(_))))
+        ));
+        assert!(matches!(
+            recv.try_recv(),
+            Ok(Some(Err(TorError::Stream(_))))
+        ));
+        assert_eq!(s.timers.pending(), 0);
+
+        // Nothing left behind to trip the next round-robin pass
+        s.register_stream(3, "example.com", 80);
+        let _send = s.queue_send(3, tagged(3, 2), None).unwrap();
+        assert!(matches!(
+            s.tick_sync(),
+            PendingWork::Send { stream_id: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn test_cancel_ends_streams() {
+        let clock = MockClock::new(0);
+        let cancel = CancelToken::new();
+        let mut s = scheduler(&clock).with_cancel(Some(cancel.clone()));
+        s.register_stream(1, "example.com", 443);
+        s.mark_stream_open(1);
+        let mut send = s.queue_send(1, tagged(1, 1), None).unwrap();
+        let mut recv = s.register_receive(1, None).unwrap();
+
+        cancel.cancel();
+        match s.tick_sync() {
+            PendingWork::Send {
+                stream_id, cell, ..
+            } => {
+                assert_eq!(stream_id, 1);
+                assert_eq!(cell.command, RelayCommand::End);
+            }
+            _ => panic!("expected the stream's END"),
+        }
+        s.check_invariants();
+        assert!(matches!(
+            send.try_recv(),
+            Ok(Some(Err(TorError::Cancelled)))
+        ));
+        assert!(matches!(
+            recv.try_recv(),
+            Ok(Some(Err(TorError::Cancelled)))
+        ));
+        assert_eq!(s.stream_count(), 0);
+        assert!(matches!(s.tick_sync(), PendingWork::Idle));
+    }
+
+    #[test]
+    fn test_register_stream_claims_early_cells() {
+        let clock = MockClock::new(0);
+        let mut s = scheduler(&clock);
+
+        // CONNECTED and DATA beat the registration
+        s.deliver_received(RelayCell::new(RelayCommand::Connected, 5, vec![]));
+        s.deliver_received(tagged(5, 1));
+        s.deliver_received(tagged(6, 2));
+        // END for a stream nobody knows is not kept
+        s.deliver_received(RelayCell::new(RelayCommand::End, 7, vec![6]));
+        assert_eq!(s.orphan_buffer.len(), 3);
+
+        s.register_stream(5, "example.com", 80);
+        s.check_invariants();
+        assert_eq!(s.orphan_buffer.len(), 1);
+        let mut first = s.register_receive(5, None).unwrap();
+        let mut second = s.register_receive(5, None).unwrap();
+        assert!(matches!(
+            first.try_recv(),
+            Ok(Some(Ok(RelayCell {
+                command: RelayCommand::Connected,
+                ..
+            })))
+        ));
+        assert_eq!(tag_of(&second.try_recv().unwrap().unwrap().unwrap()), 1);
+
+        // Once registered, its cells never touch the orphan buffer
+        for tag in 0..(MAX_INCOMING_BUFFER as u32 + 10) {
+            s.deliver_received(tagged(5, tag));
+        }
+        assert_eq!(s.orphan_buffer.len(), 1);
+
+        s.mark_circuit_dead("destroyed".into());
+        assert!(s.orphan_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_idle_streams_are_reaped() {
+        let clock = MockClock::new(0);
+        let mut s = scheduler(&clock).with_stream_idle_timeout(Some(1_000));
+        let reaped = Rc::new(RefCell::new(Vec::new()));
+        let events = Rc::clone(&reaped);
+        s.set_listener(move |event| events.borrow_mut().push(event.clone()));
+
+        s.register_stream(1, "idle.example", 80);
+        s.register_stream(2, "busy.example", 443);
+        s.mark_stream_open(1);
+
+        // Stream 2 keeps reading; stream 1's owner is gone
+        clock.advance(800);
+        let _recv = s.register_receive(2, Some(60_000)).unwrap();
+        clock.advance(800);
+        match s.tick_sync() {
+            PendingWork::Send {
+                stream_id: 1, cell, ..
+            } => {
+                assert_eq!(cell.command, RelayCommand::End);
+                assert_eq!(cell.data, vec![END_REASON_DONE]);
+            }
+            other => panic!("expected END for stream 1, got {:?}", other),
+        }
+        s.check_invariants();
+        assert_eq!(s.stream_count(), 1);
+        assert_eq!(s.stats().reaped_streams, 1);
+        assert_eq!(
+            reaped.borrow().as_slice(),
+            &[SchedulerEvent::StreamReaped {
+                stream_id: 1,
+                host: "idle.example".into(),
+                port: 80,
+                idle_ms: 1_600,
+            }]
+        );
+        assert_eq!(reaped.borrow()[0].name(), "stream_reaped");
+
+        // An END from the exit for the reaped stream is not answered
+        s.deliver_received(RelayCell::new(RelayCommand::End, 1, vec![6]));
+        assert!(s.orphan_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_reaping_can_be_disabled() {
+        let clock = MockClock::new(0);
+        let mut s = scheduler(&clock).with_stream_idle_timeout(None);
+        s.register_stream(1, "example.com", 80);
+        clock.advance(DEFAULT_STREAM_IDLE_TIMEOUT_MS * 10);
+        assert!(matches!(s.tick_sync(), PendingWork::Idle));
+        assert_eq!(s.stream_count(), 1);
+    }
+
+    #[test]
+    fn test_truncated_kills_circuit() {
+        let clock = MockClock::new(0);
+        let mut s = scheduler(&clock);
+        s.register_stream(1, "example.com", 80);
+        let mut recv = s.register_receive(1, None).unwrap();
+
+        // Circuit-level SENDMEs and padding are still ignored
+        s.deliver_received(RelayCell::new(RelayCommand::Drop, 0, vec![]));
+        assert!(s.death_reason.is_none());
+
+        s.deliver_received(RelayCell::new(RelayCommand::Truncated, 0, vec![8]));
+        assert!(s.death_reason.as_deref().unwrap().contains("truncated"));
+        assert!(matches!(
+            recv.try_recv(),
+            Ok(Some(Err(TorError::CircuitClosed(_))))
+        ));
+    }
+
+    #[test]
+    fn test_stream_lifecycle_events() {
+        use crate::events::{self, EventType, TorEvent};
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let subs: Vec<u32> = [EventType::StreamOpened, EventType::StreamEnded]
+            .into_iter()
+            .map(|kind| {
+                let seen = Rc::clone(&seen);
+                events::subscribe(kind, move |e| seen.borrow_mut().push(e.clone()))
+            })
+            .collect();
+
+        let clock = MockClock::new(0);
+        let mut s = scheduler(&clock);
+        s.register_stream(1, "example.com", 443);
+        s.register_stream(2, "example.org", 80);
+        s.mark_stream_open(1);
+        s.mark_stream_open(1);
+        s.deliver_received(RelayCell::new(RelayCommand::End, 1, vec![6]));
+        s.remove_stream(1);
+        // Never opened, so never announced
+        s.remove_stream(2);
+
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 2);
+        assert!(matches!(
+            &seen[0],
+            TorEvent::StreamOpened { stream_id: 1, host, port: 443, .. } if host == "example.com"
+        ));
+        assert!(matches!(
+            &seen[1],
+            TorEvent::StreamEnded { stream_id: 1, reason, .. } if reason == "ended by exit (reason: 6)"
+        ));
+        for id in subs {
+            events::unsubscribe(id);
+        }
+    }
+
+    #[test]
+    fn test_stream_sendmes_acknowledge_and_reopen() {
+        let clock = MockClock::new(0);
+        let mut s = scheduler(&clock);
+        s.register_stream(5, "example.com", 443);
+        s.mark_stream_open(5);
+
+        // Every 50th DATA cell is acknowledged with a stream SENDME
+        for tag in 0..50 {
+            s.deliver_received(tagged(5, tag));
+        }
+        assert_eq!(s.control_queue.len(), 1);
+        assert_eq!(s.control_queue[0].command, RelayCommand::Sendme);
+        assert_eq!(s.control_queue[0].stream_id, 5);
+        s.control_queue.clear();
+
+        // A closed send window holds DATA back until a SENDME arrives
+        s.streams.get_mut(&5).unwrap().flow.send_window = 0;
+        let _pending = s.queue_send(5, tagged(5, 99), None).unwrap();
+        assert!(matches!(s.tick_sync(), PendingWork::Receive));
+
+        s.deliver_received(RelayCell::new(RelayCommand::Sendme, 5, vec![]));
+        assert!(s.streams[&5].recv_buffer.len() == 50);
+        assert!(matches!(
+            s.tick_sync(),
+            PendingWork::Send { stream_id: 5, .. }
+        ));
+        assert_eq!(s.streams[&5].flow.send_window, 49);
+    }
 }
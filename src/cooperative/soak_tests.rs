@@ -0,0 +1,450 @@
+//! Soak test for long-lived circuits
+//!
+//! Runs a [`CooperativeCircuit`] against a simulated exit for hours of
+//! mock-clock time: thousands of streams open, download under stream
+//! SENDME flow control and close, while some BEGINs go unanswered and time
+//! out, the tab stops ticking long enough for sends to expire, and the
+//! exit mixes RELAY_DROP padding and circuit SENDMEs in on stream 0.
+//!
+//! Every simulated minute the scheduler's per-stream state must be within
+//! its limits; once the load stops, it must all drain back to empty.
+//!
+//! The default test covers 20 simulated minutes. The four-hour run is
+//! ignored by default:
+//!
+//! ```text
+//! cargo test --release soak -- --ignored
+//! ```
+
+use super::scheduler::{
+    CooperativeCircuit, PendingWork, MAX_INCOMING_BUFFER, MAX_STREAMS_PER_CIRCUIT,
+    ORPHAN_TIMEOUT_MS,
+};
+use crate::error::{Result, TorError};
+use crate::padding::{PaddingConfig, PaddingScheduler};
+use crate::protocol::{Circuit, CircuitKeys, RelayCell, RelayCommand, StreamFlowControl};
+use crate::runtime::{MockClock, TimerService};
+use futures::channel::oneshot;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+/// Simulated time per step
+const STEP_MS: u64 = 50;
+
+/// Streams the client tries to keep open
+const TARGET_STREAMS: usize = 12;
+
+/// Cells the exit can put on the wire per step
+const EXIT_CELLS_PER_STEP: usize = 40;
+
+/// Receive timeout used by the client streams
+const RECV_TIMEOUT_MS: u32 = 5_000;
+
+/// BEGINs the exit never answers
+const BLACKHOLE_RATE: f64 = 0.03;
+
+/// BEGINs the exit refuses with END
+const REFUSE_RATE: f64 = 0.02;
+
+/// Chance per step that the tab is throttled for `THROTTLE_MS`
+const THROTTLE_RATE: f64 = 0.000_5;
+const THROTTLE_MS: u64 = 15_000;
+
+/// Exit sends a circuit SENDME per this many client DATA/SENDME cells
+const CIRCUIT_SENDME_EVERY: u64 = 100;
+
+/// Exit side of one stream
+struct ExitStream {
+    /// DATA cells left in the response
+    remaining: u32,
+    /// Exit's send window, refilled by the client's SENDMEs
+    flow: StreamFlowControl,
+}
+
+/// Simulated exit relay: answers BEGINs, streams responses within the
+/// SENDME window, and sends stream-0 padding and circuit SENDMEs
+struct MockExit {
+    rng: StdRng,
+    streams: HashMap<u16, ExitStream>,
+    /// Cells on their way to the client
+    outbound: VecDeque<RelayCell>,
+    padding: PaddingScheduler,
+    client_cells: u64,
+    padding_sent: u64,
+}
+
+impl MockExit {
+    fn new(seed: u64, timers: TimerService) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            streams: HashMap::new(),
+            outbound: VecDeque::new(),
+            padding: PaddingScheduler::with_timers(PaddingConfig::default(), timers),
+            client_cells: 0,
+            padding_sent: 0,
+        }
+    }
+
+    fn on_cell(&mut self, cell: RelayCell) {
+        let stream_id = cell.stream_id;
+        match cell.command {
+            RelayCommand::Begin => {
+                if self.rng.gen_bool(BLACKHOLE_RATE) {
+                    return;
+                }
+                if self.rng.gen_bool(REFUSE_RATE) {
+                    self.outbound
+                        .push_back(RelayCell::new(RelayCommand::End, stream_id, vec![4]));
+                    return;
+                }
+                self.streams.insert(
+                    stream_id,
+                    ExitStream {
+                        remaining: self.rng.gen_range(1..1_200),
+                        flow: StreamFlowControl::new(stream_id),
+                    },
+                );
+                self.outbound
+                    .push_back(RelayCell::new(RelayCommand::Connected, stream_id, vec![]));
+            }
+            RelayCommand::Sendme => {
+                if let Some(stream) = self.streams.get_mut(&stream_id) {
+                    stream.flow.on_sendme_received();
+                }
+            }
+            RelayCommand::End => {
+                self.streams.remove(&stream_id);
+            }
+            _ => {}
+        }
+        self.client_cells += 1;
+        if self.client_cells.is_multiple_of(CIRCUIT_SENDME_EVERY) {
+            self.outbound
+                .push_back(RelayCell::new(RelayCommand::Sendme, 0, vec![]));
+        }
+    }
+
+    /// Queue response data within each stream's window, and padding
+    fn step(&mut self) {
+        // Share the link fairly between streams
+        let budget = EXIT_CELLS_PER_STEP.saturating_sub(self.outbound.len());
+        let per_stream = (budget / self.streams.len().max(1)).max(1);
+        let mut finished = Vec::new();
+        for (&stream_id, stream) in self.streams.iter_mut() {
+            let mut sent = 0;
+            while sent < per_stream && stream.remaining > 0 && stream.flow.can_send() {
+                stream.flow.on_send().unwrap();
+                stream.remaining -= 1;
+                sent += 1;
+                self.outbound.push_back(RelayCell::new(
+                    RelayCommand::Data,
+                    stream_id,
+                    vec![0; 498],
+                ));
+            }
+            if stream.remaining == 0 {
+                finished.push(stream_id);
+            }
+        }
+        for stream_id in finished {
+            self.streams.remove(&stream_id);
+            self.outbound
+                .push_back(RelayCell::new(RelayCommand::End, stream_id, vec![6]));
+        }
+
+        if self.outbound.is_empty() {
+            if self.padding.should_send_padding() {
+                self.padding.on_padding_sent();
+                self.padding_sent += 1;
+                self.outbound
+                    .push_back(RelayCell::new(RelayCommand::Drop, 0, vec![]));
+            }
+        } else {
+            self.padding.on_cell_activity();
+        }
+    }
+}
+
+/// Client side of one stream
+struct ClientStream {
+    flow: StreamFlowControl,
+    recv: oneshot::Receiver<Result<RelayCell>>,
+    /// Our END, once closing; the stream is removed when it has been sent
+    closing: Option<oneshot::Receiver<Result<()>>>,
+}
+
+#[derive(Debug, Default)]
+struct SoakStats {
+    opened: u64,
+    completed: u64,
+    refused: u64,
+    timed_out: u64,
+    sendmes_sent: u64,
+    sends_expired: u64,
+}
+
+struct Soak {
+    clock: MockClock,
+    timers: TimerService,
+    scheduler: CooperativeCircuit,
+    exit: MockExit,
+    rng: StdRng,
+    streams: HashMap<u16, ClientStream>,
+    sends: Vec<oneshot::Receiver<Result<()>>>,
+    throttled_until: u64,
+    stats: SoakStats,
+}
+
+impl Soak {
+    fn new(seed: u64) -> Self {
+        let clock = MockClock::new(1_000);
+        let timers = TimerService::with_clock(Rc::new(clock.clone()));
+        let keys = CircuitKeys::derive_from_secret(&[7; 32]).unwrap();
+        let scheduler =
+            CooperativeCircuit::with_timers(Circuit::new(1, Vec::new(), keys), timers.clone());
+        let exit = MockExit::new(seed, timers.clone());
+        Self {
+            clock,
+            timers,
+            scheduler,
+            exit,
+            rng: StdRng::seed_from_u64(seed ^ 0x5eed),
+            streams: HashMap::new(),
+            sends: Vec::new(),
+            throttled_until: 0,
+            stats: SoakStats::default(),
+        }
+    }
+
+    fn send(&mut self, stream_id: u16, cell: RelayCell) {
+        match self.scheduler.queue_send(stream_id, cell, None) {
+            Ok(rx) => self.sends.push(rx),
+            Err(e) => panic!("queue_send on stream {}: {}", stream_id, e),
+        }
+    }
+
+    fn open_stream(&mut self) {
+        let stream_id = self.scheduler.next_stream_id();
+        self.scheduler
+            .register_stream(stream_id, "example.com", 443);
+        let begin = RelayCell::new(
+            RelayCommand::Begin,
+            stream_id,
+            b"example.com:443\0".to_vec(),
+        );
+        self.send(stream_id, begin);
+        let recv = self
+            .scheduler
+            .register_receive(stream_id, Some(RECV_TIMEOUT_MS))
+            .unwrap();
+        self.streams.insert(
+            stream_id,
+            ClientStream {
+                flow: StreamFlowControl::new(stream_id),
+                recv,
+                closing: None,
+            },
+        );
+        self.stats.opened += 1;
+    }
+
+    fn close_stream(&mut self, stream_id: u16) {
+        self.streams.remove(&stream_id);
+        self.scheduler.remove_stream(stream_id);
+    }
+
+    /// Send END and remove the stream once it is out, like
+    /// `CooperativeStream::close`
+    fn begin_close(&mut self, stream_id: u16) {
+        let end = RelayCell::new(RelayCommand::End, stream_id, vec![6]);
+        match self.scheduler.queue_send(stream_id, end, Some(5_000)) {
+            Ok(rx) => self.streams.get_mut(&stream_id).unwrap().closing = Some(rx),
+            Err(_) => self.close_stream(stream_id),
+        }
+    }
+
+    /// Handle everything delivered to one stream
+    fn poll_stream(&mut self, stream_id: u16) {
+        let stream = self.streams.get_mut(&stream_id).unwrap();
+        if let Some(end) = stream.closing.as_mut() {
+            match end.try_recv() {
+                // Best effort, sent or not
+                Ok(Some(_)) => self.close_stream(stream_id),
+                Ok(None) => {}
+                Err(_) => panic!("END on stream {} was dropped", stream_id),
+            }
+            return;
+        }
+        loop {
+            let result = match self.streams.get_mut(&stream_id).unwrap().recv.try_recv() {
+                Ok(Some(result)) => result,
+                Ok(None) => return,
+                Err(_) => panic!("receive on stream {} was dropped", stream_id),
+            };
+            let cell = match result {
+                Ok(cell) => cell,
+                Err(TorError::Timeout) => {
+                    self.stats.timed_out += 1;
+                    self.begin_close(stream_id);
+                    return;
+                }
+                Err(e) => panic!("receive on stream {} failed: {}", stream_id, e),
+            };
+            match cell.command {
+                RelayCommand::Connected => {
+                    self.scheduler.mark_stream_open(stream_id);
+                    let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec();
+                    self.send(
+                        stream_id,
+                        RelayCell::new(RelayCommand::Data, stream_id, request),
+                    );
+                }
+                RelayCommand::Data => {
+                    let stream = self.streams.get_mut(&stream_id).unwrap();
+                    if stream.flow.on_receive_data() {
+                        self.stats.sendmes_sent += 1;
+                        self.send(
+                            stream_id,
+                            RelayCell::new(RelayCommand::Sendme, stream_id, vec![]),
+                        );
+                    }
+                }
+                RelayCommand::End => {
+                    if cell.data.first() == Some(&4) {
+                        self.stats.refused += 1;
+                    } else {
+                        self.stats.completed += 1;
+                    }
+                    self.close_stream(stream_id);
+                    return;
+                }
+                other => panic!("unexpected {:?} on stream {}", other, stream_id),
+            }
+            let recv = self
+                .scheduler
+                .register_receive(stream_id, Some(RECV_TIMEOUT_MS))
+                .unwrap();
+            self.streams.get_mut(&stream_id).unwrap().recv = recv;
+        }
+    }
+
+    fn step(&mut self, open_streams: bool) {
+        let now = self.timers.now_ms();
+
+        if open_streams {
+            while self.streams.len() < TARGET_STREAMS && self.scheduler.can_open_stream() {
+                self.open_stream();
+            }
+        }
+
+        // The exit's cells arrive whether or not the tab is ticking
+        self.exit.step();
+        while let Some(cell) = self.exit.outbound.pop_front() {
+            self.scheduler.deliver_received(cell);
+        }
+
+        let mut ids: Vec<u16> = self.streams.keys().copied().collect();
+        ids.sort_unstable();
+        for stream_id in ids {
+            self.poll_stream(stream_id);
+        }
+
+        if now >= self.throttled_until {
+            if self.rng.gen_bool(THROTTLE_RATE) {
+                self.throttled_until = now + THROTTLE_MS;
+            }
+            while let PendingWork::Send {
+                cell, completion, ..
+            } = self.scheduler.tick_sync()
+            {
+                self.exit.on_cell(cell);
+                let _ = completion.send(Ok(()));
+            }
+        }
+
+        let mut stats = std::mem::take(&mut self.stats);
+        self.sends.retain_mut(|rx| match rx.try_recv() {
+            Ok(Some(Ok(()))) => false,
+            Ok(Some(Err(TorError::Timeout))) => {
+                stats.sends_expired += 1;
+                false
+            }
+            Ok(Some(Err(_))) => false,
+            Ok(None) => true,
+            Err(_) => panic!("send completion was dropped"),
+        });
+        self.stats = stats;
+
+        self.clock.advance(STEP_MS);
+        self.timers.run_expired();
+    }
+
+    fn check_bounded(&self) {
+        let stats = self.scheduler.stats();
+        assert!(stats.stream_count <= MAX_STREAMS_PER_CIRCUIT);
+        assert_eq!(stats.stream_count, self.streams.len(), "streams leaked");
+        assert_eq!(
+            stats.scheduled_streams, stats.stream_count,
+            "stream_order leaked"
+        );
+        assert!(stats.pending_receives <= stats.stream_count);
+        assert!(stats.orphan_buffer_size <= MAX_INCOMING_BUFFER);
+        // One receive timeout per stream plus one per queued send, and the
+        // exit's padding deadline
+        assert!(
+            self.timers.pending() <= stats.stream_count + stats.total_queued_sends + 1,
+            "timers leaked"
+        );
+    }
+
+    /// Run `minutes` of load, then drain and check everything is released
+    fn run(&mut self, minutes: u64) {
+        let steps_per_minute = 60_000 / STEP_MS;
+        for _ in 0..minutes {
+            for _ in 0..steps_per_minute {
+                self.step(true);
+            }
+            self.check_bounded();
+        }
+
+        let drain_steps = (THROTTLE_MS + RECV_TIMEOUT_MS as u64 + ORPHAN_TIMEOUT_MS) / STEP_MS * 2;
+        for _ in 0..drain_steps {
+            self.step(false);
+        }
+        self.check_bounded();
+
+        let stats = self.scheduler.stats();
+        assert!(self.streams.is_empty(), "streams never finished");
+        assert_eq!(stats.stream_count, 0);
+        assert_eq!(stats.scheduled_streams, 0);
+        assert_eq!(stats.total_queued_sends, 0);
+        assert_eq!(stats.pending_receives, 0);
+        assert_eq!(stats.orphan_buffer_size, 0);
+        assert_eq!(self.timers.pending(), 0);
+        assert!(self.sends.is_empty());
+    }
+}
+
+fn soak(minutes: u64, seed: u64) -> SoakStats {
+    let mut soak = Soak::new(seed);
+    soak.run(minutes);
+    let stats = soak.stats;
+    assert!(stats.opened >= minutes * 30, "too few streams: {:?}", stats);
+    assert!(stats.completed > 0 && stats.timed_out > 0 && stats.refused > 0);
+    assert!(stats.sendmes_sent > 0 && soak.exit.padding_sent > 0);
+    stats
+}
+
+#[test]
+fn test_soak_twenty_minutes() {
+    let stats = soak(20, 1);
+    assert!(stats.sends_expired > 0, "{:?}", stats);
+}
+
+#[test]
+#[ignore = "four hours of simulated time; run with --ignored"]
+fn test_soak_four_hours() {
+    soak(4 * 60, 2);
+}
@@ -17,7 +17,7 @@
 
 use super::scheduler::{drive_until_complete, CooperativeCircuit, StreamHandle};
 use crate::error::{Result, TorError};
-use crate::protocol::{RelayCell, RelayCommand};
+use crate::protocol::{DnsAnswer, RelayCell, RelayCommand};
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -33,6 +33,12 @@ pub struct CooperativeStream {
     /// Whether stream is closed
     closed: bool,
 
+    /// Reason carried by the END that closed the stream, if the exit sent one
+    end_reason: Option<u8>,
+
+    /// Address the exit connected to, if it reported one in RELAY_CONNECTED
+    connected_address: Option<DnsAnswer>,
+
     /// Custom send timeout (None = use default)
     send_timeout_ms: Option<u32>,
 
@@ -51,11 +57,19 @@ impl CooperativeStream {
             handle,
             scheduler,
             closed: false,
+            end_reason: None,
+            connected_address: None,
             send_timeout_ms: None,
             recv_timeout_ms: None,
         }
     }
 
+    /// Record the address the exit reported in RELAY_CONNECTED
+    pub fn with_connected_address(mut self, address: Option<DnsAnswer>) -> Self {
+        self.connected_address = address;
+        self
+    }
+
     /// Set custom send timeout
     pub fn with_send_timeout(mut self, timeout_ms: u32) -> Self {
         self.send_timeout_ms = Some(timeout_ms);
@@ -68,6 +82,11 @@ impl CooperativeStream {
         self
     }
 
+    /// Change the receive timeout (None = use default)
+    pub fn set_recv_timeout(&mut self, timeout_ms: Option<u32>) {
+        self.recv_timeout_ms = timeout_ms;
+    }
+
     /// Get stream ID
     pub fn stream_id(&self) -> u16 {
         self.handle.stream_id()
@@ -78,6 +97,16 @@ impl CooperativeStream {
         self.closed
     }
 
+    /// RELAY_END reason the exit closed the stream with, if it did
+    pub fn end_reason(&self) -> Option<u8> {
+        self.end_reason
+    }
+
+    /// Address (and DNS TTL) the exit reported in RELAY_CONNECTED
+    pub fn connected_address(&self) -> Option<DnsAnswer> {
+        self.connected_address
+    }
+
     /// Write data to the stream
     ///
     /// Returns error if:
@@ -166,6 +195,7 @@ impl CooperativeStream {
                 RelayCommand::End => {
                     log::info!("📥 Stream {} received END", self.handle.stream_id());
                     self.closed = true;
+                    self.end_reason = Some(cell.data.first().copied().unwrap_or(0));
                     return Ok(0);
                 }
                 RelayCommand::Sendme => {
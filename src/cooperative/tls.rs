@@ -5,6 +5,7 @@
 
 use super::stream::CooperativeStream;
 use crate::error::{Result, TorError};
+use crate::protocol::leaf_certificate_digest;
 use rustls::pki_types::ServerName;
 use rustls::{ClientConfig, ClientConnection, RootCertStore};
 use std::io::{Read, Write};
@@ -264,6 +265,16 @@ impl CooperativeTlsStream {
         Ok(result)
     }
 
+    /// Hex SHA-256 of the server's leaf certificate
+    pub fn certificate_digest(&self) -> Option<String> {
+        leaf_certificate_digest(&self.tls)
+    }
+
+    /// RELAY_END reason the underlying stream was closed with, if any
+    pub fn end_reason(&self) -> Option<u8> {
+        self.stream.end_reason()
+    }
+
     /// Close the TLS connection
     pub async fn close(&mut self) -> Result<()> {
         log::debug!("  🔒 Closing TLS connection");
@@ -0,0 +1,34 @@
+/**
+ * tor-wasm crypto offload worker
+ *
+ * Runs onion encryption/decryption of relay cells off the main thread.
+ * Copy it next to the wasm-pack output (`pkg/`) and start it from the page:
+ *
+ *   import init, { enable_crypto_worker } from './pkg/tor_wasm.js';
+ *   await init();
+ *   enable_crypto_worker(new URL('./pkg/crypto-worker.js', import.meta.url).href);
+ *
+ * Each message is a job `{ id, op, layers, payload }`; the reply carries
+ * the processed `payload` back (transferred unless it is shared memory),
+ * or `{ id, error }`. The worker holds no keys between jobs.
+ *
+ * License: MIT / Apache-2.0
+ */
+
+import init, { crypto_worker_handle } from './tor_wasm.js';
+
+const ready = init();
+
+self.onmessage = async (event) => {
+  const job = event.data;
+  try {
+    await ready;
+    const reply = crypto_worker_handle(job);
+    const buffer = reply.payload.buffer;
+    const shared =
+      typeof SharedArrayBuffer !== 'undefined' && buffer instanceof SharedArrayBuffer;
+    self.postMessage(reply, shared ? [] : [buffer]);
+  } catch (e) {
+    self.postMessage({ id: job && job.id, error: String(e) });
+  }
+};
@@ -0,0 +1,463 @@
+//! Onion crypto offload to a dedicated Web Worker
+//!
+//! Relay cells are onion encrypted and decrypted (AES-128-CTR, one layer
+//! per hop) on the main thread by default. During a bulk transfer on a
+//! low-end device that work competes with the page for the main thread.
+//! `enable_crypto_worker(url)` starts a module Worker running
+//! `crypto-worker.js`, which loads this same WASM module and answers each
+//! job with [`crypto_worker_handle`]; circuits then hand the layers of
+//! every RELAY cell they send or receive to it and await the result.
+//!
+//! The worker keeps no state: a job carries each hop's key, IV and
+//! keystream offset, so circuits keep their own ciphers and only seek them
+//! past the keystream the worker used. A job that fails or isn't answered
+//! within [`JOB_TIMEOUT_MS`] is done locally instead, so offload never
+//! breaks a circuit. Each cell costs a `postMessage` round trip, which
+//! trades latency for main-thread time; leave it off on fast devices.
+//!
+//! Cell buffers live in a `SharedArrayBuffer` when the page is
+//! cross-origin isolated; otherwise their `ArrayBuffer` is transferred to
+//! the worker and back rather than copied.
+//!
+//! Handshake cells (CREATE2/EXTEND2) are always processed locally.
+
+use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::protocol::CircuitKeys;
+use crate::runtime::TaskSupervisor;
+use crate::transport::shared_ring::shared_memory_available;
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+/// How long a job may take before the cell is processed locally
+pub const JOB_TIMEOUT_MS: u32 = 2_000;
+
+/// One hop's onion layer: its AES key and IV, and how far into the
+/// keystream the cell starts
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct OnionLayer {
+    pub key: [u8; 16],
+    pub iv: [u8; 16],
+    pub offset: u64,
+}
+
+impl OnionLayer {
+    fn cipher(&self) -> Aes128Ctr {
+        let mut cipher = Aes128Ctr::new((&self.key).into(), (&self.iv).into());
+        cipher.seek(self.offset);
+        cipher
+    }
+}
+
+/// Which way a job's cell is travelling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnionOp {
+    /// Outgoing: apply every layer, innermost hop first
+    Encrypt,
+    /// Incoming: peel layers from the guard inwards until a hop
+    /// recognizes the cell
+    Decrypt,
+}
+
+/// Apply outgoing onion layers (`layers` in hop order, guard first)
+pub fn encrypt_layers(layers: &[OnionLayer], payload: &mut [u8]) {
+    for layer in layers.iter().rev() {
+        layer.cipher().apply_keystream(payload);
+    }
+}
+
+/// Peel incoming onion layers one at a time (tor-spec §5.5.2)
+///
+/// Returns the hop whose layer left `recognized` zero, or `None` once every
+/// layer is peeled without a match.
+pub fn decrypt_layers(layers: &[OnionLayer], payload: &mut [u8]) -> Option<usize> {
+    for (hop, layer) in layers.iter().enumerate() {
+        layer.cipher().apply_keystream(payload);
+        if payload.get(1..3) == Some(&[0, 0]) {
+            return Some(hop);
+        }
+    }
+    None
+}
+
+/// Layers for `op` on hops with `keys`, starting where each of `ciphers`
+/// is now (forward ciphers for encryption, backward for decryption)
+pub(crate) fn layers_for(
+    op: OnionOp,
+    keys: &[CircuitKeys],
+    ciphers: &[Aes128Ctr],
+) -> Vec<OnionLayer> {
+    keys.iter()
+        .zip(ciphers)
+        .map(|(keys, cipher)| {
+            let (key, iv) = match op {
+                OnionOp::Encrypt => (keys.forward_key, keys.forward_iv),
+                OnionOp::Decrypt => (keys.backward_key, keys.backward_iv),
+            };
+            OnionLayer {
+                key,
+                iv,
+                offset: cipher.current_pos(),
+            }
+        })
+        .collect()
+}
+
+/// Move `ciphers` past `len` bytes of keystream used elsewhere
+pub(crate) fn advance(ciphers: &mut [Aes128Ctr], len: usize) {
+    for cipher in ciphers {
+        let pos: u64 = cipher.current_pos();
+        cipher.seek(pos + len as u64);
+    }
+}
+
+/// Offload counters, for `crypto_worker_stats()`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CryptoWorkerStats {
+    pub enabled: bool,
+    pub shared_memory: bool,
+    /// Cells processed by the worker
+    pub jobs: u64,
+    /// Cells processed locally after the worker failed or timed out
+    pub fallbacks: u64,
+}
+
+type Reply = std::result::Result<(Vec<u8>, Option<usize>), String>;
+
+/// Main-thread handle to the crypto worker
+struct CryptoWorker {
+    worker: web_sys::Worker,
+    pending: Rc<RefCell<HashMap<u32, oneshot::Sender<Reply>>>>,
+    next_id: Cell<u32>,
+    shared_memory: bool,
+    _onmessage: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    _onerror: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Drop for CryptoWorker {
+    fn drop(&mut self) {
+        self.worker.terminate();
+    }
+}
+
+thread_local! {
+    static WORKER: RefCell<Option<Rc<CryptoWorker>>> = const { RefCell::new(None) };
+    static STATS: RefCell<CryptoWorkerStats> = RefCell::new(CryptoWorkerStats::default());
+}
+
+/// Whether cells are being offloaded
+pub fn enabled() -> bool {
+    WORKER.with(|w| w.borrow().is_some())
+}
+
+/// Current offload counters
+pub fn stats() -> CryptoWorkerStats {
+    let mut stats = STATS.with(|s| s.borrow().clone());
+    stats.enabled = enabled();
+    stats.shared_memory = WORKER.with(|w| w.borrow().as_ref().is_some_and(|w| w.shared_memory));
+    stats
+}
+
+fn count(fallback: bool) {
+    STATS.with(|s| {
+        let mut stats = s.borrow_mut();
+        if fallback {
+            stats.fallbacks += 1;
+        } else {
+            stats.jobs += 1;
+        }
+    });
+}
+
+/// Field `name` of a job or reply object
+fn field(object: &JsValue, name: &str) -> JsValue {
+    js_sys::Reflect::get(object, &name.into()).unwrap_or(JsValue::UNDEFINED)
+}
+
+/// Start offloading onion crypto to a module Worker loaded from
+/// `script_url` (normally `crypto-worker.js` next to the WASM bundle)
+///
+/// Replaces any worker already running.
+#[wasm_bindgen]
+pub fn enable_crypto_worker(script_url: String) -> std::result::Result<(), JsValue> {
+    let options = web_sys::WorkerOptions::new();
+    options.set_type(web_sys::WorkerType::Module);
+    let worker = web_sys::Worker::new_with_options(&script_url, &options)?;
+
+    let pending: Rc<RefCell<HashMap<u32, oneshot::Sender<Reply>>>> = Rc::default();
+
+    let replies = Rc::clone(&pending);
+    let onmessage = Closure::wrap(Box::new(move |event: web_sys::MessageEvent| {
+        let data = event.data();
+        let Some(id) = field(&data, "id").as_f64() else {
+            return;
+        };
+        let Some(sender) = replies.borrow_mut().remove(&(id as u32)) else {
+            return; // Timed out already
+        };
+        let error = field(&data, "error");
+        let reply = if let Some(error) = error.as_string() {
+            Err(error)
+        } else {
+            let payload = js_sys::Uint8Array::new(&field(&data, "payload")).to_vec();
+            let hop = field(&data, "hop").as_f64().map(|h| h as usize);
+            Ok((payload, hop))
+        };
+        let _ = sender.send(reply);
+    }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+    worker.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+
+    // A worker that fails to load or crashes takes its jobs with it; they
+    // fall back to local processing and offload is switched off
+    let failed = Rc::clone(&pending);
+    let onerror = Closure::wrap(Box::new(move |_event: JsValue| {
+        log::warn!("⚠️ Crypto worker failed, processing cells locally");
+        failed.borrow_mut().clear();
+        TaskSupervisor::global().spawn("crypto-worker-teardown", async {
+            disable_crypto_worker();
+            Ok(())
+        });
+    }) as Box<dyn FnMut(JsValue)>);
+    worker.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+
+    let shared_memory = shared_memory_available();
+    log::info!(
+        "🧵 Crypto worker started ({})",
+        if shared_memory {
+            "shared memory"
+        } else {
+            "transferred buffers"
+        }
+    );
+    let handle = CryptoWorker {
+        worker,
+        pending,
+        next_id: Cell::new(0),
+        shared_memory,
+        _onmessage: onmessage,
+        _onerror: onerror,
+    };
+    WORKER.with(|w| *w.borrow_mut() = Some(Rc::new(handle)));
+    Ok(())
+}
+
+/// Stop offloading; cells are processed on the main thread again
+#[wasm_bindgen]
+pub fn disable_crypto_worker() {
+    if WORKER.with(|w| w.borrow_mut().take()).is_some() {
+        log::info!("🧵 Crypto worker stopped");
+    }
+}
+
+/// Offload counters: `{ enabled, shared_memory, jobs, fallbacks }`
+#[wasm_bindgen]
+pub fn crypto_worker_stats() -> JsValue {
+    serde_wasm_bindgen::to_value(&stats()).unwrap_or(JsValue::NULL)
+}
+
+/// Worker side: run one job posted by the main thread
+///
+/// `job` is `{ id, op, layers, payload }`; `payload` (a `Uint8Array`) is
+/// processed in place. Returns `{ id, payload, hop }`, where `hop` is the
+/// recognizing hop of a decrypted cell (or null).
+#[wasm_bindgen]
+pub fn crypto_worker_handle(job: JsValue) -> std::result::Result<JsValue, JsValue> {
+    let op: OnionOp = serde_wasm_bindgen::from_value(field(&job, "op"))?;
+    let layers: Vec<OnionLayer> = serde_wasm_bindgen::from_value(field(&job, "layers"))?;
+    let buffer = js_sys::Uint8Array::new(&field(&job, "payload"));
+    let mut payload = buffer.to_vec();
+
+    let hop = match op {
+        OnionOp::Encrypt => {
+            encrypt_layers(&layers, &mut payload);
+            None
+        }
+        OnionOp::Decrypt => decrypt_layers(&layers, &mut payload),
+    };
+    buffer.copy_from(&payload);
+
+    let reply = js_sys::Object::new();
+    js_sys::Reflect::set(&reply, &"id".into(), &field(&job, "id"))?;
+    js_sys::Reflect::set(&reply, &"payload".into(), &buffer)?;
+    js_sys::Reflect::set(
+        &reply,
+        &"hop".into(),
+        &hop.map_or(JsValue::NULL, |h| JsValue::from(h as u32)),
+    )?;
+    Ok(reply.into())
+}
+
+/// Run `op` over `payload` in the worker
+///
+/// Returns `None` when offload is off or the job failed, in which case
+/// `payload` is untouched and the caller processes it locally; otherwise
+/// the recognizing hop (always `None` for encryption).
+pub(crate) async fn run(
+    op: OnionOp,
+    layers: &[OnionLayer],
+    payload: &mut [u8],
+) -> Option<Option<usize>> {
+    use futures::future::FutureExt;
+
+    let worker = WORKER.with(|w| w.borrow().clone())?;
+    match send_job(&worker, op, layers, payload) {
+        Ok((id, reply)) => {
+            let reply = futures::select_biased! {
+                reply = reply.fuse() => reply.unwrap_or_else(|_| Err("worker stopped".into())),
+                _ = gloo_timers::future::TimeoutFuture::new(JOB_TIMEOUT_MS).fuse() => {
+                    worker.pending.borrow_mut().remove(&id);
+                    Err("timed out".into())
+                }
+            };
+            match reply {
+                Ok((data, hop)) if data.len() == payload.len() => {
+                    payload.copy_from_slice(&data);
+                    count(false);
+                    Some(hop)
+                }
+                Ok(_) => {
+                    log::warn!("⚠️ Crypto worker returned a resized cell");
+                    count(true);
+                    None
+                }
+                Err(e) => {
+                    log::warn!("⚠️ Crypto worker job failed: {}", e);
+                    count(true);
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            log::warn!("⚠️ Could not post crypto job: {:?}", e);
+            count(true);
+            None
+        }
+    }
+}
+
+/// Post one job, returning its ID and the channel its reply arrives on
+fn send_job(
+    worker: &CryptoWorker,
+    op: OnionOp,
+    layers: &[OnionLayer],
+    payload: &[u8],
+) -> std::result::Result<(u32, oneshot::Receiver<Reply>), JsValue> {
+    let id = worker.next_id.get().wrapping_add(1);
+    worker.next_id.set(id);
+
+    let buffer: JsValue = if worker.shared_memory {
+        js_sys::SharedArrayBuffer::new(payload.len() as u32).into()
+    } else {
+        js_sys::ArrayBuffer::new(payload.len() as u32).into()
+    };
+    let view = js_sys::Uint8Array::new(&buffer);
+    view.copy_from(payload);
+
+    let job = js_sys::Object::new();
+    js_sys::Reflect::set(&job, &"id".into(), &JsValue::from(id))?;
+    js_sys::Reflect::set(&job, &"op".into(), &serde_wasm_bindgen::to_value(&op)?)?;
+    js_sys::Reflect::set(
+        &job,
+        &"layers".into(),
+        &serde_wasm_bindgen::to_value(layers)?,
+    )?;
+    js_sys::Reflect::set(&job, &"payload".into(), &view)?;
+
+    let (sender, receiver) = oneshot::channel();
+    worker.pending.borrow_mut().insert(id, sender);
+    let posted = if worker.shared_memory {
+        worker.worker.post_message(&job)
+    } else {
+        let transfer = js_sys::Array::of1(&buffer);
+        worker.worker.post_message_with_transfer(&job, &transfer)
+    };
+    if let Err(e) = posted {
+        worker.pending.borrow_mut().remove(&id);
+        return Err(e);
+    }
+    Ok((id, receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(seed: u8, offset: u64) -> OnionLayer {
+        OnionLayer {
+            key: [seed; 16],
+            iv: [seed.wrapping_add(1); 16],
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_layers_match_persistent_ciphers() {
+        // Ciphers that already processed one cell, as on a live circuit
+        let keys: Vec<CircuitKeys> = (1..=3)
+            .map(|seed| CircuitKeys {
+                forward_key: [seed; 16],
+                backward_key: [0; 16],
+                forward_iv: [seed + 1; 16],
+                backward_iv: [0; 16],
+                forward_digest: [0; 20],
+                backward_digest: [0; 20],
+            })
+            .collect();
+        let mut ciphers: Vec<Aes128Ctr> = keys
+            .iter()
+            .map(|k| Aes128Ctr::new((&k.forward_key).into(), (&k.forward_iv).into()))
+            .collect();
+        for cipher in &mut ciphers {
+            cipher.apply_keystream(&mut [0u8; 509]);
+        }
+        let layers = layers_for(OnionOp::Encrypt, &keys, &ciphers);
+        assert!(layers.iter().all(|l| l.offset == 509));
+
+        let cell: Vec<u8> = (0..509).map(|i| i as u8).collect();
+        let mut local = cell.clone();
+        for cipher in ciphers.iter_mut().rev() {
+            cipher.apply_keystream(&mut local);
+        }
+        let mut offloaded = cell;
+        encrypt_layers(&layers, &mut offloaded);
+        assert_eq!(local, offloaded);
+
+        // Skipping the keystream the worker used lands where the local
+        // ciphers ended up
+        let mut skipped: Vec<Aes128Ctr> = layers.iter().map(OnionLayer::cipher).collect();
+        advance(&mut skipped, 509);
+        for (a, b) in skipped.iter_mut().zip(&mut ciphers) {
+            let mut x = [0u8; 16];
+            let mut y = [0u8; 16];
+            a.apply_keystream(&mut x);
+            b.apply_keystream(&mut y);
+            assert_eq!(x, y);
+        }
+    }
+
+    #[test]
+    fn test_decrypt_finds_recognizing_hop() {
+        let layers = vec![layer(1, 0), layer(2, 509), layer(3, 0)];
+
+        // A cell from the middle hop: recognized zero under two layers
+        let mut cell = vec![0x02u8; 509];
+        cell[1..3].fill(0);
+        let mut wire = cell.clone();
+        encrypt_layers(&layers[..2], &mut wire);
+
+        let mut peeled = wire;
+        assert_eq!(decrypt_layers(&layers, &mut peeled), Some(1));
+        assert_eq!(peeled, cell);
+
+        assert_eq!(decrypt_layers(&[], &mut peeled), None);
+    }
+}
@@ -0,0 +1,361 @@
+//! Diagnostics bundle redaction
+//!
+//! `TorClient::export_diagnostics()` gathers recent logs, metrics, config
+//! and circuit build reports into one JSON document for bug reports. Before
+//! it leaves the client, every string in it (keys included) goes through a
+//! [`Redactor`], which removes what could identify the user's bridge or the
+//! relays they used:
+//!
+//! - URL hosts, paths and queries (`wss://bridge.example/x?k=v` →
+//!   `wss://[host]/[path]`)
+//! - host names and `host:port` pairs (`example.com:443` → `[host]`), and
+//!   the values logged after `Host:` and `Path:`
+//! - absolute paths (`GET /search?q=tor` → `GET [path]`)
+//! - IPv4 and IPv6 addresses, with or without a port
+//! - hex strings of 32+ digits (relay fingerprints, key material)
+//! - long base64-looking tokens (ed25519 identities, ntor keys)
+//! - relay nicknames known from the consensus (`guard moria1` →
+//!   `guard [relay]`)
+//!
+//! Redaction is deliberately over-eager: a bug report with a few extra
+//! `[hex]` markers, or a word that happens to be some relay's nickname, is
+//! fine; a leaked bridge address is not.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Version of the bundle layout
+pub const DIAGNOSTICS_FORMAT: u32 = 1;
+
+/// Log records included in a bundle
+pub const DIAGNOSTICS_LOG_RECORDS: usize = 200;
+
+/// Shortest hex run treated as a fingerprint or key
+const MIN_HEX_LEN: usize = 32;
+
+/// Shortest base64 run treated as a key (a 20-byte RSA identity)
+const MIN_BASE64_LEN: usize = 27;
+
+/// Log labels whose value names a site visited, with its replacement
+const VISIT_LABELS: [(&str, &str); 2] = [("Host: ", "[host]"), ("Path: ", "[path]")];
+
+/// Whether `c` ends a path or a labelled value
+fn ends_value(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | '"' | '\'' | ')')
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '[' | ']' | '-' | '_' | '+' | '/')
+}
+
+/// Replacement for one address- or key-like token, if it is one
+fn redact_token(token: &str) -> Option<&'static str> {
+    // `[v6]` or `[v6]:port`
+    let unbracketed = match token.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(rest, |(inner, _)| inner),
+        None => token,
+    };
+    if (unbracketed.contains("::") || unbracketed.matches(':').count() >= 2)
+        && unbracketed.parse::<Ipv6Addr>().is_ok()
+    {
+        return Some("[ip]");
+    }
+
+    let host = match token.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => token,
+    };
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return Some("[ip]");
+    }
+    // `example.com`, `name.onion:80`, `localhost:8080`
+    let has_port = host.len() < token.len();
+    if is_host_name(host) || (has_port && host.bytes().any(|b| b.is_ascii_alphabetic())) {
+        return Some("[host]");
+    }
+
+    if token.len() >= MIN_HEX_LEN && token.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Some(if token.len() == 40 {
+            "[fingerprint]"
+        } else {
+            "[hex]"
+        });
+    }
+
+    let is_base64 = token
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/');
+    if is_base64
+        && token.len() >= MIN_BASE64_LEN
+        && token.bytes().any(|b| b.is_ascii_digit())
+        && token.bytes().any(|b| b.is_ascii_uppercase())
+        && token.bytes().any(|b| b.is_ascii_lowercase())
+    {
+        return Some("[key]");
+    }
+    None
+}
+
+/// Dotted name whose last label is alphabetic (`example.com`, not `v1.2.3`)
+fn is_host_name(token: &str) -> bool {
+    let labels: Vec<&str> = token.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.len() >= 2 && tld.bytes().all(|b| b.is_ascii_alphabetic()))
+}
+
+/// Replace the value after each of `VISIT_LABELS`
+fn redact_labelled(text: &str) -> String {
+    let mut text = text.to_string();
+    for (label, marker) in VISIT_LABELS {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(pos) = rest.find(label) {
+            let (before, after) = rest.split_at(pos + label.len());
+            out.push_str(before);
+            let len = after.find(ends_value).unwrap_or(after.len());
+            if len > 0 {
+                out.push_str(marker);
+            }
+            rest = &after[len..];
+        }
+        out.push_str(rest);
+        text = out;
+    }
+    text
+}
+
+/// Replace URL hosts with `[host]` and what follows them with `/[path]`
+fn redact_urls(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find("://") {
+        let (before, after) = rest.split_at(pos + 3);
+        out.push_str(before);
+        let host_len = after
+            .find(|c: char| matches!(c, '/' | '?' | '#') || c.is_whitespace() || c == '"')
+            .unwrap_or(after.len());
+        if host_len > 0 {
+            out.push_str("[host]");
+        }
+        let after = &after[host_len..];
+        let path_len = after.find(ends_value).unwrap_or(after.len());
+        match &after[..path_len] {
+            "" | "/" => out.push_str(&after[..path_len]),
+            _ => out.push_str("/[path]"),
+        }
+        rest = &after[path_len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Replace absolute paths (with their query) starting a word with `[path]`
+fn redact_paths(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut word_start = true;
+    while let Some(c) = rest.chars().next() {
+        let len = if c == '/' && word_start {
+            let path = &rest[1..];
+            1 + path.find(ends_value).unwrap_or(path.len())
+        } else {
+            0
+        };
+        // A lone `/` is not a path
+        if len > 1 {
+            out.push_str("[path]");
+            rest = &rest[len..];
+            word_start = false;
+            continue;
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+        word_start = c.is_whitespace() || matches!(c, '"' | '\'' | '(' | '=');
+    }
+    out
+}
+
+/// Redacts bundle strings, knowing which words are relay nicknames
+#[derive(Debug, Default)]
+pub struct Redactor {
+    nicknames: HashSet<String>,
+}
+
+impl Redactor {
+    /// Redactor that also replaces `nicknames` with `[relay]`
+    pub fn new<S: Into<String>>(nicknames: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            nicknames: nicknames.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Redact addresses, hosts, paths, fingerprints, keys and nicknames in
+    /// free text
+    pub fn redact(&self, text: &str) -> String {
+        let text = redact_paths(&redact_urls(&redact_labelled(text)));
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while !rest.is_empty() {
+            let start = rest.find(is_token_char).unwrap_or(rest.len());
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c| !is_token_char(c)).unwrap_or(rest.len());
+            let (token, tail) = rest.split_at(end);
+            rest = tail;
+
+            // Sentence punctuation after a token is not part of it
+            let trimmed = token.trim_end_matches(['.', ':']);
+            let marker = redact_token(trimmed)
+                .or_else(|| self.nicknames.contains(trimmed).then_some("[relay]"));
+            match marker {
+                Some(marker) => {
+                    out.push_str(marker);
+                    out.push_str(&token[trimmed.len()..]);
+                }
+                None => out.push_str(token),
+            }
+        }
+        out
+    }
+
+    /// Redact every string (and object key) in a JSON value in place
+    pub fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => *s = self.redact(s),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_value(item)),
+            Value::Object(map) => {
+                let entries = std::mem::take(map);
+                for (key, mut item) in entries {
+                    self.redact_value(&mut item);
+                    map.insert(self.redact(&key), item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(text: &str) -> String {
+        Redactor::new(["moria1"]).redact(text)
+    }
+
+    #[test]
+    fn test_redacts_addresses_and_keys() {
+        let fp = "9695DFC35FFEB861329B9F1AB04C46397020CE31";
+        let line = format!(
+            "Trying guard moria1 at 128.31.0.34:9101 (${}), v6 [2001:db8::7]:443.",
+            fp
+        );
+        assert_eq!(
+            redact(&line),
+            "Trying guard [relay] at [ip] ($[fingerprint]), v6 [ip]."
+        );
+        assert_eq!(
+            redact("ntor key 3mUJd8yb8VA8Kk0/B+q0oOmZfAPG6fQ8g7TX0q2fdlw= ok"),
+            "ntor key [key]= ok"
+        );
+        assert_eq!(
+            redact("digest 0123456789abcdef0123456789abcdef01"),
+            "digest [hex]"
+        );
+        assert_eq!(
+            redact("bridge wss://bridge.example.org:8443/ws?key=abc failed"),
+            "bridge wss://[host]/[path] failed"
+        );
+
+        // Ordinary text survives
+        let plain = "Circuit built successfully on attempt 2 at 12:30:45 (v1.2.3)";
+        assert_eq!(redact(plain), plain);
+    }
+
+    #[test]
+    fn test_redacts_json_keys_and_values() {
+        let mut value = serde_json::json!({
+            "bridge": "https://203.0.113.9/",
+            "errors": ["connect to 198.51.100.4:9001 failed"],
+            "by_address": { "192.0.2.1": 3 },
+            "count": 7,
+        });
+        Redactor::default().redact_value(&mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "bridge": "https://[host]/",
+                "errors": ["connect to [ip] failed"],
+                "by_address": { "[ip]": 3 },
+                "count": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn test_redacts_sites_visited() {
+        // The request logging in lib.rs, verbatim
+        let cases = [
+            (
+                format!("🌐 Connecting to {}:{} via Tor...", "example.com", 443),
+                "🌐 Connecting to [host] via Tor...",
+            ),
+            (
+                format!("🌐 Connecting to {}:{} via Tor...", "localhost", 8080),
+                "🌐 Connecting to [host] via Tor...",
+            ),
+            (
+                format!(
+                    "  Host: {}, Port: {}, Path: {}",
+                    "intranet", 80, "/search?q=private+thing"
+                ),
+                "  Host: [host], Port: 80, Path: [path]",
+            ),
+            (
+                format!(
+                    "  Host: {}, Port: {}, Path: {}, HTTPS: {}",
+                    "news.example.org", 443, "/article/42", true
+                ),
+                "  Host: [host], Port: 443, Path: [path], HTTPS: true",
+            ),
+            (
+                format!(
+                    "🌐 [COOP] GET {} via Tor ({})...",
+                    "https://example.com/a?b=c", "HTTPS"
+                ),
+                "🌐 [COOP] GET https://[host]/[path] via Tor (HTTPS)...",
+            ),
+            (
+                format!("  ♻️ Reusing existing circuit for '{}'", "duckduckgo.com"),
+                "  ♻️ Reusing existing circuit for '[host]'",
+            ),
+            (
+                "GET /inbox HTTP/1.1 to the root / and back".to_string(),
+                "GET [path] HTTP/1.1 to the root / and back",
+            ),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(redact(&line), expected);
+        }
+    }
+
+    #[test]
+    fn test_redacts_known_nicknames() {
+        assert_eq!(
+            redact("Extending to moria1: middle relay."),
+            "Extending to [relay]: middle relay."
+        );
+
+        // Only whole tokens: a nickname inside a longer one stays
+        assert_eq!(redact("moria10 timed out"), "moria10 timed out");
+    }
+}
@@ -0,0 +1,241 @@
+//! Exit-side DNS result caching
+//!
+//! Caches the addresses exits report (RELAY_RESOLVED answers and the
+//! address in RELAY_CONNECTED) so repeated connections to the same host can
+//! send RELAY_BEGIN to the IP and skip the exit's DNS lookup.
+//!
+//! Entries are keyed by (isolation key, hostname): an answer learned for one
+//! isolation key is never used for another, and the whole cache is dropped
+//! with the circuits on new identity. TTLs are clamped so an exit can neither
+//! pin an answer for hours nor defeat the cache with a zero TTL.
+
+use crate::isolation::IsolationKey;
+use crate::protocol::DnsAnswer;
+use crate::runtime::timer::{system_clock, SharedClock};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Shortest TTL honoured, in seconds
+pub const MIN_DNS_TTL_SECS: u32 = 60;
+
+/// Longest TTL honoured, in seconds
+pub const MAX_DNS_TTL_SECS: u32 = 30 * 60;
+
+/// Maximum number of cached hostnames
+pub const MAX_DNS_ENTRIES: usize = 256;
+
+struct CachedAnswer {
+    addresses: Vec<IpAddr>,
+    expires_at_ms: u64,
+}
+
+/// DNS cache statistics
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DnsCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Per-isolation-key cache of exit DNS answers
+pub struct DnsCache {
+    clock: SharedClock,
+    entries: HashMap<(String, String), CachedAnswer>,
+    hits: u64,
+    misses: u64,
+}
+
+/// Normalize a hostname for use as a cache key
+fn host_key(host: &str) -> String {
+    host.trim_end_matches('.').to_lowercase()
+}
+
+/// Format an address as the host part of a RELAY_BEGIN target
+/// (IPv6 in brackets, as tor-spec requires)
+pub fn begin_host(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => format!("[{}]", v6),
+    }
+}
+
+impl DnsCache {
+    /// Cache using the system clock
+    pub fn new() -> Self {
+        Self::with_clock(system_clock())
+    }
+
+    /// Cache using a custom clock (tests)
+    pub fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            clock,
+            entries: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Cached addresses for `host` under `key`, if still fresh
+    pub fn lookup(&mut self, key: &IsolationKey, host: &str) -> Option<Vec<IpAddr>> {
+        if host.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+        let now = self.clock.now_ms();
+        let cache_key = (key.as_str().to_string(), host_key(host));
+
+        match self.entries.get(&cache_key) {
+            Some(entry) if entry.expires_at_ms > now => {
+                self.hits += 1;
+                Some(entry.addresses.clone())
+            }
+            Some(_) => {
+                self.entries.remove(&cache_key);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Remember the exit's answers for `host` under `key`
+    ///
+    /// The entry lives for the smallest TTL among the answers, clamped to
+    /// [`MIN_DNS_TTL_SECS`, `MAX_DNS_TTL_SECS`].
+    pub fn insert(&mut self, key: &IsolationKey, host: &str, answers: &[DnsAnswer]) {
+        if answers.is_empty() || host.parse::<IpAddr>().is_ok() {
+            return;
+        }
+        let ttl = answers
+            .iter()
+            .map(|a| a.ttl)
+            .min()
+            .unwrap_or(0)
+            .clamp(MIN_DNS_TTL_SECS, MAX_DNS_TTL_SECS);
+        let now = self.clock.now_ms();
+
+        if self.entries.len() >= MAX_DNS_ENTRIES {
+            self.entries.retain(|_, e| e.expires_at_ms > now);
+        }
+        if self.entries.len() >= MAX_DNS_ENTRIES {
+            // Still full: evict the entry closest to expiry
+            if let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.expires_at_ms)
+                .map(|(k, _)| k.clone())
+            {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(
+            (key.as_str().to_string(), host_key(host)),
+            CachedAnswer {
+                addresses: answers.iter().map(|a| a.address).collect(),
+                expires_at_ms: now + ttl as u64 * 1000,
+            },
+        );
+    }
+
+    /// Drop every cached answer
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of cached hostnames (including expired ones not yet evicted)
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Current statistics
+    pub fn stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            entries: self.entries.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isolation::IsolationType;
+    use crate::runtime::MockClock;
+    use std::rc::Rc;
+
+    fn answer(ip: &str, ttl: u32) -> DnsAnswer {
+        DnsAnswer {
+            address: ip.parse().unwrap(),
+            ttl,
+        }
+    }
+
+    fn key(host: &str) -> IsolationKey {
+        IsolationKey::for_destination(host, 443, IsolationType::PerDomain)
+    }
+
+    #[test]
+    fn test_entries_are_isolated_and_expire() {
+        let clock = MockClock::new(1_000);
+        let mut cache = DnsCache::with_clock(Rc::new(clock.clone()));
+        let a = key("example.com");
+        let b = key("other.org");
+
+        cache.insert(&a, "Example.COM.", &[answer("93.184.216.34", 600)]);
+        assert_eq!(
+            cache.lookup(&a, "example.com"),
+            Some(vec!["93.184.216.34".parse().unwrap()])
+        );
+        // Same hostname under another isolation key is a miss
+        assert_eq!(cache.lookup(&b, "example.com"), None);
+
+        clock.advance(600 * 1000);
+        assert_eq!(cache.lookup(&a, "example.com"), None);
+        assert!(cache.is_empty());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+    }
+
+    #[test]
+    fn test_ttl_is_clamped() {
+        let clock = MockClock::new(0);
+        let mut cache = DnsCache::with_clock(Rc::new(clock.clone()));
+        let k = key("example.com");
+
+        cache.insert(&k, "zero.example", &[answer("192.0.2.1", 0)]);
+        cache.insert(&k, "huge.example", &[answer("192.0.2.2", u32::MAX)]);
+        cache.insert(&k, "192.0.2.3", &[answer("192.0.2.3", 600)]);
+        assert_eq!(cache.len(), 2);
+
+        clock.advance((MIN_DNS_TTL_SECS as u64 - 1) * 1000);
+        assert!(cache.lookup(&k, "zero.example").is_some());
+        clock.advance(1000);
+        assert!(cache.lookup(&k, "zero.example").is_none());
+
+        clock.set(MAX_DNS_TTL_SECS as u64 * 1000);
+        assert!(cache.lookup(&k, "huge.example").is_none());
+    }
+
+    #[test]
+    fn test_begin_host_brackets_ipv6() {
+        assert_eq!(begin_host("192.0.2.1".parse().unwrap()), "192.0.2.1");
+        assert_eq!(begin_host("::1".parse().unwrap()), "[::1]");
+    }
+}
@@ -0,0 +1,292 @@
+//! Dormant mode for backgrounded apps
+//!
+//! A PWA sent to the background has no use for warm circuits, and every
+//! keepalive probe, pool refill or heartbeat it keeps sending is traffic an
+//! observer can time. [`TorClient::set_dormant`](crate::TorClient::set_dormant)
+//! tears the circuits down and turns the periodic maintenance calls into
+//! no-ops, while the consensus, guards and verified link handshakes stay, so
+//! waking up costs a circuit build rather than a bootstrap.
+//!
+//! The client wakes by itself once it is needed again: on the next request,
+//! when the page becomes visible, or when a periodic background sync tagged
+//! [`PERIODIC_SYNC_TAG`] fires. A client running in a service worker gets
+//! the `periodicsync` event directly; one running in a page relies on its
+//! service worker forwarding it with `client.postMessage("tor-wasm-wake")`.
+
+use crate::runtime::LocalCell;
+use serde::Serialize;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Tag of the periodic background sync that wakes the client
+pub const PERIODIC_SYNC_TAG: &str = "tor-wasm-wake";
+
+/// Default minimum interval asked of periodic background sync
+pub const DEFAULT_WAKE_INTERVAL_SECS: u32 = 12 * 60 * 60;
+
+/// Why a dormant client woke up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WakeReason {
+    /// `set_dormant(false)`
+    Explicit,
+    /// A request (or bootstrap) needed the network
+    Request,
+    /// The page became visible
+    Visible,
+    /// A periodic background sync fired
+    PeriodicSync,
+}
+
+/// Dormancy state and counters, as reported by `dormant_status()`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DormantStatus {
+    pub dormant: bool,
+    /// When the client last went dormant (ms since epoch)
+    pub since_ms: Option<u64>,
+    pub times_dormant: u64,
+    pub wakes: u64,
+    pub last_wake: Option<WakeReason>,
+    /// Background calls skipped while dormant
+    pub suppressed: u64,
+}
+
+/// Shared handle to a client's dormancy, also held by the page hooks
+#[derive(Clone, Default)]
+pub struct Dormancy {
+    state: Rc<LocalCell<DormantStatus>>,
+    hooks_installed: Rc<Cell<bool>>,
+}
+
+impl Dormancy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_dormant(&self) -> bool {
+        self.state.with(|s| s.dormant)
+    }
+
+    pub fn status(&self) -> DormantStatus {
+        self.state.with(|s| s.clone())
+    }
+
+    /// Go dormant at `now_ms`; false if already dormant
+    pub fn enter(&self, now_ms: u64) -> bool {
+        self.state.with(|s| {
+            if s.dormant {
+                return false;
+            }
+            s.dormant = true;
+            s.since_ms = Some(now_ms);
+            s.times_dormant += 1;
+            true
+        })
+    }
+
+    /// Wake up for `reason`; false if already awake
+    pub fn wake(&self, reason: WakeReason) -> bool {
+        wake(&self.state, reason)
+    }
+
+    /// Whether the background call `what` should be skipped, counting it
+    /// if so
+    pub fn suppress(&self, what: &str) -> bool {
+        let dormant = self.state.with(|s| {
+            if s.dormant {
+                s.suppressed += 1;
+            }
+            s.dormant
+        });
+        if dormant {
+            log::debug!("💤 Dormant: skipping {}", what);
+        }
+        dormant
+    }
+
+    /// Wake on page visibility and periodic background sync
+    ///
+    /// Installed once per client; the listeners hold the state weakly, so
+    /// they go quiet once the client is dropped.
+    pub fn install_page_hooks(&self, wake_interval_secs: u32) {
+        if self.hooks_installed.replace(true) {
+            return;
+        }
+        hooks::install(Rc::downgrade(&self.state), wake_interval_secs);
+    }
+}
+
+fn wake(state: &LocalCell<DormantStatus>, reason: WakeReason) -> bool {
+    let woke = state.with(|s| {
+        if !s.dormant {
+            return false;
+        }
+        s.dormant = false;
+        s.wakes += 1;
+        s.last_wake = Some(reason);
+        true
+    });
+    if woke {
+        log::info!("⏰ Waking from dormant mode ({:?})", reason);
+    }
+    woke
+}
+
+mod hooks {
+    use super::{wake, DormantStatus, WakeReason, PERIODIC_SYNC_TAG};
+    use crate::runtime::{LocalCell, TaskSupervisor};
+    use js_sys::Reflect;
+    use std::rc::Weak;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen::JsCast;
+
+    type State = Weak<LocalCell<DormantStatus>>;
+
+    pub(super) fn install(state: State, wake_interval_secs: u32) {
+        let global = js_sys::global();
+
+        // A page coming back to the foreground
+        if let Some(document) = property(&global, "document") {
+            let doc = document.clone();
+            let state = state.clone();
+            listen(&document, "visibilitychange", move |_| {
+                let visible = property(&doc, "visibilityState")
+                    .and_then(|v| v.as_string())
+                    .is_some_and(|v| v == "visible");
+                if visible {
+                    wake_weak(&state, WakeReason::Visible);
+                }
+            });
+        }
+
+        // Periodic sync in a service worker (never fires elsewhere)
+        {
+            let state = state.clone();
+            listen(&global, "periodicsync", move |event| {
+                if property(&event, "tag")
+                    .and_then(|t| t.as_string())
+                    .as_deref()
+                    == Some(PERIODIC_SYNC_TAG)
+                {
+                    wake_weak(&state, WakeReason::PeriodicSync);
+                }
+            });
+        }
+
+        // A page's service worker forwarding the sync, and registering it
+        let container = property(&global, "navigator").and_then(|n| property(&n, "serviceWorker"));
+        if let Some(container) = container {
+            let forwarded = state.clone();
+            listen(&container, "message", move |event| {
+                if property(&event, "data")
+                    .and_then(|d| d.as_string())
+                    .as_deref()
+                    == Some(PERIODIC_SYNC_TAG)
+                {
+                    wake_weak(&forwarded, WakeReason::PeriodicSync);
+                }
+            });
+            let register = register_periodic_sync(container, wake_interval_secs);
+            TaskSupervisor::global().spawn("periodic-sync-register", async move {
+                register.await;
+                Ok(())
+            });
+        }
+    }
+
+    fn wake_weak(state: &State, reason: WakeReason) {
+        if let Some(state) = state.upgrade() {
+            wake(&state, reason);
+        }
+    }
+
+    fn property(target: &JsValue, name: &str) -> Option<JsValue> {
+        Reflect::get(target, &JsValue::from_str(name))
+            .ok()
+            .filter(|v| !v.is_undefined() && !v.is_null())
+    }
+
+    fn listen(target: &JsValue, event: &str, handler: impl FnMut(JsValue) + 'static) {
+        let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(JsValue)>);
+        let added = target
+            .unchecked_ref::<web_sys::EventTarget>()
+            .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref());
+        if added.is_ok() {
+            closure.forget(); // Lives as long as the page
+        }
+    }
+
+    /// Ask for a periodic background sync once the service worker is ready
+    ///
+    /// Best effort: most browsers lack the API or only grant it to
+    /// installed apps.
+    async fn register_periodic_sync(container: JsValue, wake_interval_secs: u32) {
+        let result = async {
+            let ready = property(&container, "ready").ok_or("no service worker")?;
+            let registration =
+                wasm_bindgen_futures::JsFuture::from(ready.unchecked_into::<js_sys::Promise>())
+                    .await
+                    .map_err(|_| "service worker not ready")?;
+            let sync = property(&registration, "periodicSync").ok_or("unsupported")?;
+            let register: js_sys::Function = property(&sync, "register")
+                .ok_or("unsupported")?
+                .unchecked_into();
+            let options = js_sys::Object::new();
+            let _ = Reflect::set(
+                &options,
+                &JsValue::from_str("minInterval"),
+                &JsValue::from_f64(f64::from(wake_interval_secs) * 1000.0),
+            );
+            let pending = register
+                .call2(&sync, &JsValue::from_str(PERIODIC_SYNC_TAG), &options)
+                .map_err(|_| "register failed")?;
+            wasm_bindgen_futures::JsFuture::from(pending.unchecked_into::<js_sys::Promise>())
+                .await
+                .map_err(|_| "permission denied")?;
+            Ok::<(), &str>(())
+        };
+        match result.await {
+            Ok(()) => log::info!(
+                "⏰ Periodic sync '{}' registered (every {}s at most)",
+                PERIODIC_SYNC_TAG,
+                wake_interval_secs
+            ),
+            Err(why) => log::debug!("⏰ No periodic background sync: {}", why),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dormancy_suppresses_until_woken() {
+        let dormancy = Dormancy::new();
+        assert!(!dormancy.suppress("refill"));
+        assert!(!dormancy.wake(WakeReason::Request));
+
+        assert!(dormancy.enter(1_000));
+        assert!(!dormancy.enter(2_000));
+        assert!(dormancy.is_dormant());
+        assert!(dormancy.suppress("refill"));
+        assert!(dormancy.suppress("heartbeat"));
+
+        // Shared with the page hooks
+        assert!(dormancy.clone().wake(WakeReason::Visible));
+        assert!(!dormancy.wake(WakeReason::Request));
+        assert!(!dormancy.suppress("refill"));
+
+        assert_eq!(
+            dormancy.status(),
+            DormantStatus {
+                dormant: false,
+                since_ms: Some(1_000),
+                times_dormant: 1,
+                wakes: 1,
+                last_wake: Some(WakeReason::Visible),
+                suppressed: 2,
+            }
+        );
+    }
+}
@@ -20,18 +20,21 @@ pub enum ErrorCode {
     ConnectionFailed = 100,
     ConnectionTimeout = 101,
     ConnectionRefused = 102,
+    RequestCancelled = 103,
 
     // Protocol errors (2xx)
     ProtocolViolation = 200,
     UnexpectedCell = 201,
     DigestMismatch = 202,
     HandshakeFailed = 203,
+    IntegrityMismatch = 204,
 
     // Circuit errors (3xx)
     CircuitBuildFailed = 300,
     CircuitDestroyed = 301,
     AllRelaysFailed = 302,
     StreamFailed = 303,
+    OnionServiceFailed = 304,
 
     // Security errors (4xx) - FATAL
     CertificateError = 400,
@@ -74,6 +77,9 @@ pub enum TorError {
     #[error("Connection refused: {0}")]
     ConnectionRefused(String),
 
+    #[error("Request cancelled")]
+    Cancelled,
+
     // ===== Protocol Errors =====
     #[error("Protocol error: {0}")]
     ProtocolError(String),
@@ -87,6 +93,9 @@ pub enum TorError {
     #[error("Handshake failed: {0}")]
     HandshakeFailed(String),
 
+    #[error("Integrity check failed: {0}")]
+    IntegrityMismatch(String),
+
     // ===== Circuit Errors =====
     #[error("Circuit build failed: {0}")]
     CircuitBuildFailed(String),
@@ -103,6 +112,9 @@ pub enum TorError {
     #[error("Stream error: {0}")]
     Stream(String),
 
+    #[error("Onion service error: {0}")]
+    OnionService(String),
+
     // ===== Security Errors (FATAL) =====
     #[error("Certificate verification failed: {0}")]
     CertificateError(String),
@@ -177,12 +189,14 @@ impl TorError {
             TorError::ConnectionFailed(_) => ErrorCode::ConnectionFailed,
             TorError::Timeout => ErrorCode::ConnectionTimeout,
             TorError::ConnectionRefused(_) => ErrorCode::ConnectionRefused,
+            TorError::Cancelled => ErrorCode::RequestCancelled,
 
             // Protocol
             TorError::ProtocolError(_) => ErrorCode::ProtocolViolation,
             TorError::UnexpectedCell { .. } => ErrorCode::UnexpectedCell,
             TorError::DigestMismatch => ErrorCode::DigestMismatch,
             TorError::HandshakeFailed(_) => ErrorCode::HandshakeFailed,
+            TorError::IntegrityMismatch(_) => ErrorCode::IntegrityMismatch,
 
             // Circuit
             TorError::CircuitBuildFailed(_) => ErrorCode::CircuitBuildFailed,
@@ -190,6 +204,7 @@ impl TorError {
             TorError::AllRelaysFailed => ErrorCode::AllRelaysFailed,
             TorError::CircuitClosed(_) => ErrorCode::CircuitDestroyed,
             TorError::Stream(_) => ErrorCode::StreamFailed,
+            TorError::OnionService(_) => ErrorCode::OnionServiceFailed,
 
             // Security (fatal)
             TorError::CertificateError(_) => ErrorCode::CertificateError,
@@ -257,6 +272,7 @@ impl TorError {
                 | TorError::Network(_)
                 | TorError::HandshakeFailed(_)
                 | TorError::Stream(_)
+                | TorError::OnionService(_)
         )
     }
 
@@ -285,6 +301,7 @@ impl TorError {
             TorError::ConnectionRefused(_) => {
                 "Connection was refused. The relay may be offline.".into()
             }
+            TorError::Cancelled => "The request was cancelled.".into(),
 
             // Protocol
             TorError::ProtocolError(_) => "A protocol error occurred. Please try again.".into(),
@@ -295,6 +312,9 @@ impl TorError {
             TorError::HandshakeFailed(_) => {
                 "Failed to establish secure connection. Please try again.".into()
             }
+            TorError::IntegrityMismatch(_) => {
+                "The downloaded content does not match its expected hash.".into()
+            }
 
             // Circuit
             TorError::CircuitBuildFailed(_) => {
@@ -309,6 +329,9 @@ impl TorError {
             }
             TorError::CircuitClosed(_) => "Your circuit was closed. Please try again.".into(),
             TorError::Stream(_) => "Data transfer failed. Please try again.".into(),
+            TorError::OnionService(_) => {
+                "Couldn't reach the onion service. It may be offline; please try again.".into()
+            }
 
             // Security (fatal)
             TorError::CertificateError(_) => {
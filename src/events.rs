@@ -0,0 +1,278 @@
+//! Circuit and stream lifecycle events
+//!
+//! The circuit builder, the cooperative scheduler, the guard state and the
+//! client post [`TorEvent`]s here; `TorClient::subscribe()` registers JS
+//! callbacks for them. Subscriptions are kept per [`EventType`] and are
+//! process-wide, like the clock skew and security posture listeners.
+//!
+//! Events are posted from deep inside code that holds borrows of shared
+//! state (a guard state cell, a scheduler). In the browser they are
+//! therefore queued and delivered from a microtask once the poster has
+//! returned, so a callback may call back into the client. Nothing is
+//! queued while no one is subscribed.
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Something that happened to a circuit, stream, guard or the consensus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TorEvent {
+    /// A circuit finished building
+    CircuitBuilt {
+        circuit_id: u32,
+        /// Nicknames from guard to exit
+        path: Vec<String>,
+    },
+    /// A built circuit went away
+    CircuitClosed { circuit_id: u32, reason: String },
+    /// An exit accepted a stream (RELAY_CONNECTED)
+    StreamOpened {
+        circuit_id: u32,
+        stream_id: u16,
+        host: String,
+        port: u16,
+    },
+    /// An opened stream was closed, by either side
+    StreamEnded {
+        circuit_id: u32,
+        stream_id: u16,
+        reason: String,
+    },
+    /// A guard failed often enough to be set aside
+    GuardDown {
+        fingerprint: String,
+        /// Unix time until which it is not used
+        until: u64,
+        last_error: String,
+    },
+    /// A new consensus was installed
+    ConsensusRefreshed {
+        relays: usize,
+        valid_after: u64,
+        valid_until: u64,
+    },
+}
+
+/// Kind of [`TorEvent`] a subscription is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    CircuitBuilt,
+    CircuitClosed,
+    StreamOpened,
+    StreamEnded,
+    GuardDown,
+    ConsensusRefreshed,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 6] = [
+        EventType::CircuitBuilt,
+        EventType::CircuitClosed,
+        EventType::StreamOpened,
+        EventType::StreamEnded,
+        EventType::GuardDown,
+        EventType::ConsensusRefreshed,
+    ];
+
+    /// Name used by `subscribe()` and in the event's `event` field
+    pub fn name(self) -> &'static str {
+        match self {
+            EventType::CircuitBuilt => "circuit_built",
+            EventType::CircuitClosed => "circuit_closed",
+            EventType::StreamOpened => "stream_opened",
+            EventType::StreamEnded => "stream_ended",
+            EventType::GuardDown => "guard_down",
+            EventType::ConsensusRefreshed => "consensus_refreshed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+impl TorEvent {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            TorEvent::CircuitBuilt { .. } => EventType::CircuitBuilt,
+            TorEvent::CircuitClosed { .. } => EventType::CircuitClosed,
+            TorEvent::StreamOpened { .. } => EventType::StreamOpened,
+            TorEvent::StreamEnded { .. } => EventType::StreamEnded,
+            TorEvent::GuardDown { .. } => EventType::GuardDown,
+            TorEvent::ConsensusRefreshed { .. } => EventType::ConsensusRefreshed,
+        }
+    }
+}
+
+type Listener = Rc<dyn Fn(&TorEvent)>;
+
+/// Subscriptions and events not yet delivered
+#[derive(Default)]
+pub struct EventBus {
+    next_id: u32,
+    subscribers: Vec<(u32, EventType, Listener)>,
+    queue: VecDeque<TorEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a listener for `event_type`, returning its subscription ID
+    pub fn subscribe(
+        &mut self,
+        event_type: EventType,
+        listener: impl Fn(&TorEvent) + 'static,
+    ) -> u32 {
+        self.next_id += 1;
+        self.subscribers
+            .push((self.next_id, event_type, Rc::new(listener)));
+        self.next_id
+    }
+
+    /// Remove a subscription, returning whether it existed
+    pub fn unsubscribe(&mut self, id: u32) -> bool {
+        let before = self.subscribers.len();
+        self.subscribers.retain(|(sub, _, _)| *sub != id);
+        self.subscribers.len() != before
+    }
+
+    /// Queue `event` if anyone listens for it; returns whether the queue
+    /// was empty before (and a delivery must be scheduled)
+    pub fn enqueue(&mut self, event: TorEvent) -> bool {
+        let event_type = event.event_type();
+        if !self.subscribers.iter().any(|(_, t, _)| *t == event_type) {
+            return false;
+        }
+        self.queue.push_back(event);
+        self.queue.len() == 1
+    }
+
+    /// Next queued event with the listeners subscribed to it
+    pub fn take_next(&mut self) -> Option<(TorEvent, Vec<Listener>)> {
+        let event = self.queue.pop_front()?;
+        let event_type = event.event_type();
+        let listeners = self
+            .subscribers
+            .iter()
+            .filter(|(_, t, _)| *t == event_type)
+            .map(|(_, _, l)| Rc::clone(l))
+            .collect();
+        Some((event, listeners))
+    }
+}
+
+thread_local! {
+    static BUS: RefCell<EventBus> = RefCell::new(EventBus::new());
+}
+
+/// Register `listener` for `event_type`, returning the subscription ID
+pub fn subscribe(event_type: EventType, listener: impl Fn(&TorEvent) + 'static) -> u32 {
+    BUS.with(|b| b.borrow_mut().subscribe(event_type, listener))
+}
+
+/// Cancel a subscription, returning whether it existed
+pub fn unsubscribe(id: u32) -> bool {
+    BUS.with(|b| b.borrow_mut().unsubscribe(id))
+}
+
+/// Post an event to its subscribers
+pub fn post(event: TorEvent) {
+    if BUS.with(|b| b.borrow_mut().enqueue(event)) {
+        schedule_delivery();
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn schedule_delivery() {
+    crate::runtime::TaskSupervisor::global().spawn("event-delivery", async {
+        deliver();
+        Ok(())
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn schedule_delivery() {
+    deliver();
+}
+
+/// Hand every queued event to its listeners, outside the bus borrow so a
+/// listener may subscribe, unsubscribe or post
+fn deliver() {
+    while let Some((event, listeners)) = BUS.with(|b| b.borrow_mut().take_next()) {
+        for listener in listeners {
+            listener(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_reach_only_their_subscribers() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let circuits = subscribe(EventType::CircuitBuilt, {
+            let seen = Rc::clone(&seen);
+            move |e| seen.borrow_mut().push(e.clone())
+        });
+        let guards = subscribe(EventType::GuardDown, {
+            let seen = Rc::clone(&seen);
+            move |e| seen.borrow_mut().push(e.clone())
+        });
+
+        post(TorEvent::CircuitBuilt {
+            circuit_id: 1,
+            path: vec!["g".into(), "m".into(), "e".into()],
+        });
+        post(TorEvent::StreamOpened {
+            circuit_id: 1,
+            stream_id: 1,
+            host: "example.com".into(),
+            port: 443,
+        });
+        assert_eq!(seen.borrow().len(), 1);
+        assert_eq!(seen.borrow()[0].event_type(), EventType::CircuitBuilt);
+
+        assert!(unsubscribe(circuits));
+        assert!(!unsubscribe(circuits));
+        post(TorEvent::CircuitBuilt {
+            circuit_id: 2,
+            path: Vec::new(),
+        });
+        assert_eq!(seen.borrow().len(), 1);
+        unsubscribe(guards);
+    }
+
+    #[test]
+    fn test_bus_queues_only_subscribed_events() {
+        let mut bus = EventBus::new();
+        let closed = TorEvent::CircuitClosed {
+            circuit_id: 3,
+            reason: "destroyed by client".into(),
+        };
+        assert!(!bus.enqueue(closed.clone()));
+        assert!(bus.take_next().is_none());
+
+        bus.subscribe(EventType::CircuitClosed, |_| {});
+        assert!(bus.enqueue(closed.clone()));
+        assert!(!bus.enqueue(closed.clone()));
+        let (event, listeners) = bus.take_next().unwrap();
+        assert_eq!(event, closed);
+        assert_eq!(listeners.len(), 1);
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "circuit_closed");
+        assert_eq!(
+            EventType::from_name("circuit_closed"),
+            Some(EventType::CircuitClosed)
+        );
+        assert_eq!(EventType::from_name("nope"), None);
+    }
+}
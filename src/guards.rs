@@ -18,9 +18,12 @@
 //! - Guard state should be stored persistently (we use IndexedDB)
 
 use crate::error::{Result, TorError};
+use crate::events::{self, TorEvent};
 use crate::protocol::Relay;
+use crate::runtime::LocalCell;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::rc::Rc;
 
 /// How long guards should be kept before rotation (in seconds)
 /// Tor spec says 2-3 months, we use 60 days (conservative)
@@ -71,6 +74,19 @@ pub struct GuardState {
 
     /// Version of the guard state format (for future migrations)
     pub version: u32,
+
+    /// Failures or successes recorded since the state was last saved
+    #[serde(skip)]
+    pub(crate) unsaved: bool,
+}
+
+/// Guard state shared between the client and its circuit builder, which
+/// records each attempt's outcome
+pub type SharedGuardState = Rc<LocalCell<GuardState>>;
+
+/// Wrap `state` in a shared handle
+pub fn new_shared_guard_state(state: GuardState) -> SharedGuardState {
+    Rc::new(LocalCell::new(state))
 }
 
 impl Default for GuardState {
@@ -82,6 +98,7 @@ impl Default for GuardState {
             failed_guards: HashMap::new(),
             bad_guards: HashMap::new(),
             version: 1,
+            unsaved: false,
         }
     }
 }
@@ -139,6 +156,11 @@ impl GuardState {
 
     /// Select new guards from the consensus
     pub fn select_guards(&mut self, relays: &[Relay]) -> Result<()> {
+        self.select_guard_count(relays, MAX_GUARDS)
+    }
+
+    /// Select up to `count` new guards from the consensus
+    pub fn select_guard_count(&mut self, relays: &[Relay], count: usize) -> Result<()> {
         log::info!("🛡️ Selecting new guard nodes...");
 
         // Filter for guard-eligible relays
@@ -169,7 +191,7 @@ impl GuardState {
         let mut rng_state = current_time_secs();
 
         // Select guards with bandwidth-weighted probability
-        while selected.len() < MAX_GUARDS && !guard_candidates.is_empty() {
+        while selected.len() < count && !guard_candidates.is_empty() {
             // Simple weighted selection: pick from top 20% with some randomness
             let top_count = (guard_candidates.len() / 5).max(1);
             let idx = simple_random(&mut rng_state) as usize % top_count;
@@ -245,6 +267,7 @@ impl GuardState {
         failure.consecutive_failures += 1;
         failure.last_failure_time = now;
         failure.last_error = error.to_string();
+        self.unsaved = true;
 
         log::warn!(
             "⚠️ Guard {} failed ({} times): {}",
@@ -255,20 +278,35 @@ impl GuardState {
 
         // Mark as bad if too many failures
         if failure.consecutive_failures >= MAX_FAILURES_BEFORE_BAD {
-            self.mark_bad(fingerprint);
+            self.mark_bad(fingerprint, error);
         }
     }
 
     /// Record a guard success (clears failure count)
     pub fn record_success(&mut self, fingerprint: &str) {
-        self.failed_guards.remove(fingerprint);
-        self.bad_guards.remove(fingerprint);
+        let cleared = self.failed_guards.remove(fingerprint).is_some();
+        let unbanned = self.bad_guards.remove(fingerprint).is_some();
+        self.unsaved |= cleared || unbanned;
+    }
+
+    /// Whether outcomes were recorded since the last call; clears the flag
+    pub fn take_unsaved(&mut self) -> bool {
+        std::mem::take(&mut self.unsaved)
     }
 
-    /// Mark a guard as bad (temporarily unusable)
-    fn mark_bad(&mut self, fingerprint: &str) {
+    /// Mark a guard as bad (temporarily unusable), posting `guard_down`
+    /// if it wasn't already
+    fn mark_bad(&mut self, fingerprint: &str, error: &str) {
+        let was_bad = self.is_bad_guard(fingerprint);
         let bad_until = current_time_secs() + BAD_GUARD_TIMEOUT_SECS;
         self.bad_guards.insert(fingerprint.to_string(), bad_until);
+        if !was_bad {
+            events::post(TorEvent::GuardDown {
+                fingerprint: fingerprint.to_string(),
+                until: bad_until,
+                last_error: error.to_string(),
+            });
+        }
 
         log::warn!(
             "🚫 Guard {} marked as bad until {}",
@@ -278,7 +316,7 @@ impl GuardState {
     }
 
     /// Check if a guard is currently bad
-    fn is_bad_guard(&self, fingerprint: &str) -> bool {
+    pub fn is_bad_guard(&self, fingerprint: &str) -> bool {
         if let Some(&bad_until) = self.bad_guards.get(fingerprint) {
             current_time_secs() < bad_until
         } else {
@@ -343,9 +381,13 @@ fn format_timestamp(ts: u64) -> String {
     }
 }
 
+/// `localStorage` key of the guard state
+pub const GUARD_STATE_KEY: &str = "tor_guard_state";
+
 /// Guard persistence manager
 ///
 /// Handles loading and saving guard state to IndexedDB
+#[derive(Debug, Clone)]
 pub struct GuardPersistence {
     /// Storage key for guard state
     storage_key: String,
@@ -354,8 +396,13 @@ pub struct GuardPersistence {
 impl GuardPersistence {
     /// Create a new guard persistence manager
     pub fn new() -> Self {
+        Self::with_key(GUARD_STATE_KEY)
+    }
+
+    /// Persist under `storage_key` instead of the shared key
+    pub fn with_key(storage_key: impl Into<String>) -> Self {
         Self {
-            storage_key: "tor_guard_state".to_string(),
+            storage_key: storage_key.into(),
         }
     }
 
@@ -476,6 +523,11 @@ mod tests {
         state.record_success("TEST_GUARD_FP");
 
         assert!(!state.failed_guards.contains_key("TEST_GUARD_FP"));
+        assert!(state.take_unsaved());
+
+        // A success that changes nothing leaves nothing to save
+        state.record_success("TEST_GUARD_FP");
+        assert!(!state.take_unsaved());
     }
 
     #[test]
@@ -0,0 +1,31 @@
+/**
+ * tor-wasm handshake worker
+ *
+ * Generates ntor keypairs for candidate circuit paths off the main thread.
+ * Needs a cross-origin isolated page. Copy it next to the wasm-pack output
+ * (`pkg/`) and start the pool from the page:
+ *
+ *   import init, { enable_handshake_pool } from './pkg/tor_wasm.js';
+ *   await init();
+ *   enable_handshake_pool(new URL('./pkg/handshake-worker.js', import.meta.url).href);
+ *
+ * Each message is a job `{ id, count }`; the reply is `{ id, keypairs }`
+ * (transferred) or `{ id, error }`. The worker keeps nothing between jobs.
+ *
+ * License: MIT / Apache-2.0
+ */
+
+import init, { handshake_worker_handle } from './tor_wasm.js';
+
+const ready = init();
+
+self.onmessage = async (event) => {
+  const job = event.data;
+  try {
+    await ready;
+    const reply = handshake_worker_handle(job);
+    self.postMessage(reply, [reply.keypairs.buffer]);
+  } catch (e) {
+    self.postMessage({ id: job && job.id, error: String(e) });
+  }
+};
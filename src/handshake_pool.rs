@@ -0,0 +1,447 @@
+//! ntor keypair precomputation on a pool of Web Workers
+//!
+//! The client half of every ntor handshake starts with a fresh X25519
+//! keypair. [`ParallelCircuitBuilder`](crate::ParallelCircuitBuilder)
+//! picks several candidate paths at once; [`prepare_paths`] makes the
+//! keypairs for all of their hops ahead of the build, and the circuit
+//! builder takes a prepared one ([`handshake_for`]) instead of generating
+//! it while the relay waits.
+//!
+//! When the page is cross-origin isolated, `enable_handshake_pool(url)`
+//! starts several module Workers running `handshake-worker.js`, each with
+//! its own instance of this WASM module, and each candidate path becomes
+//! one job, so paths are prepared concurrently on separate threads.
+//! Without the pool, or for a job that fails or isn't answered within
+//! [`JOB_TIMEOUT_MS`], paths are prepared on the main thread one at a time,
+//! yielding to the event loop between them.
+//!
+//! A prepared keypair serves one handshake at most and is dropped unused
+//! after [`PREPARED_TTL_MS`].
+
+use futures::channel::oneshot;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use zeroize::Zeroize;
+
+use crate::protocol::NtorHandshake;
+use crate::runtime::timer::now_ms;
+use crate::runtime::TaskSupervisor;
+use crate::transport::shared_ring::shared_memory_available;
+
+/// How long a job may take before its path is prepared locally
+pub const JOB_TIMEOUT_MS: u32 = 2_000;
+
+/// How long a prepared keypair waits for a handshake
+pub const PREPARED_TTL_MS: u64 = 10 * 60 * 1000;
+
+/// Prepared keypairs kept per relay at most
+pub const MAX_PREPARED_PER_RELAY: usize = 4;
+
+/// Workers started when the page doesn't say how many cores it has
+const DEFAULT_WORKERS: usize = 2;
+
+/// Workers started at most
+const MAX_WORKERS: usize = 4;
+
+/// Bytes per keypair in a reply: secret, then public key
+const KEYPAIR_LEN: usize = 64;
+
+/// Pool counters, for `handshake_pool_stats()`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HandshakePoolStats {
+    pub enabled: bool,
+    pub workers: usize,
+    /// Paths prepared by a worker
+    pub jobs: u64,
+    /// Paths prepared on the main thread
+    pub local: u64,
+    /// Jobs that failed or timed out and were redone locally
+    pub fallbacks: u64,
+    /// Keypairs waiting for a handshake
+    pub prepared: usize,
+    /// Handshakes that used a prepared keypair
+    pub used: u64,
+}
+
+struct Prepared {
+    handshake: NtorHandshake,
+    at: u64,
+}
+
+type Reply = std::result::Result<Vec<u8>, String>;
+
+/// One worker and its outstanding jobs
+struct PoolWorker {
+    worker: web_sys::Worker,
+    pending: Rc<RefCell<HashMap<u32, oneshot::Sender<Reply>>>>,
+    _onmessage: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    _onerror: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Drop for PoolWorker {
+    fn drop(&mut self) {
+        self.worker.terminate();
+    }
+}
+
+/// Main-thread handle to the workers
+struct HandshakePool {
+    workers: Vec<PoolWorker>,
+    next_id: Cell<u32>,
+    /// Worker the next job goes to
+    next_worker: Cell<usize>,
+}
+
+thread_local! {
+    static POOL: RefCell<Option<Rc<HandshakePool>>> = const { RefCell::new(None) };
+    static PREPARED: RefCell<HashMap<String, Vec<Prepared>>> = RefCell::new(HashMap::new());
+    static STATS: RefCell<HandshakePoolStats> = RefCell::new(HandshakePoolStats::default());
+}
+
+/// Whether paths are prepared on worker threads
+pub fn enabled() -> bool {
+    POOL.with(|p| p.borrow().is_some())
+}
+
+/// Current pool counters
+pub fn stats() -> HandshakePoolStats {
+    let mut stats = STATS.with(|s| s.borrow().clone());
+    stats.enabled = enabled();
+    stats.workers = POOL.with(|p| p.borrow().as_ref().map_or(0, |p| p.workers.len()));
+    stats.prepared = PREPARED.with(|p| p.borrow().values().map(Vec::len).sum());
+    stats
+}
+
+fn count(update: impl FnOnce(&mut HandshakePoolStats)) {
+    STATS.with(|s| update(&mut s.borrow_mut()));
+}
+
+/// A handshake for the relay with `fingerprint`: a prepared keypair if
+/// one is waiting, otherwise a fresh one
+pub fn handshake_for(fingerprint: &str) -> NtorHandshake {
+    take(fingerprint).unwrap_or_default()
+}
+
+/// The oldest live prepared keypair for `fingerprint`, removing it
+pub fn take(fingerprint: &str) -> Option<NtorHandshake> {
+    let now = now_ms();
+    let handshake = PREPARED.with(|p| {
+        let mut prepared = p.borrow_mut();
+        let entries = prepared.get_mut(&fingerprint.to_uppercase())?;
+        entries.retain(|e| now.saturating_sub(e.at) < PREPARED_TTL_MS);
+        let handshake = (!entries.is_empty()).then(|| entries.remove(0).handshake);
+        if entries.is_empty() {
+            prepared.remove(&fingerprint.to_uppercase());
+        }
+        handshake
+    })?;
+    count(|s| s.used += 1);
+    Some(handshake)
+}
+
+/// Keep `handshakes` for the hops of one path (guard first)
+fn store(path: &[String], handshakes: Vec<NtorHandshake>) {
+    let now = now_ms();
+    PREPARED.with(|p| {
+        let mut prepared = p.borrow_mut();
+        for (fingerprint, handshake) in path.iter().zip(handshakes) {
+            let entries = prepared.entry(fingerprint.to_uppercase()).or_default();
+            entries.retain(|e| now.saturating_sub(e.at) < PREPARED_TTL_MS);
+            if entries.len() < MAX_PREPARED_PER_RELAY {
+                entries.push(Prepared { handshake, at: now });
+            }
+        }
+    });
+}
+
+/// Drop every prepared keypair
+pub fn clear() {
+    PREPARED.with(|p| p.borrow_mut().clear());
+}
+
+/// Prepare keypairs for every hop of `paths` (relay fingerprints, guard
+/// first), on the workers when the pool runs
+pub async fn prepare_paths(paths: &[Vec<String>]) {
+    let pool = POOL.with(|p| p.borrow().clone());
+    let Some(pool) = pool else {
+        for (i, path) in paths.iter().enumerate() {
+            if i > 0 {
+                yield_now().await;
+            }
+            prepare_locally(path);
+        }
+        return;
+    };
+
+    let jobs = paths.iter().map(|path| {
+        let pool = Rc::clone(&pool);
+        async move {
+            match run_job(&pool, path.len()).await {
+                Ok(keypairs) => {
+                    store(path, keypairs);
+                    count(|s| s.jobs += 1);
+                }
+                Err(e) => {
+                    log::warn!("⚠️ Handshake job failed ({}), preparing locally", e);
+                    count(|s| s.fallbacks += 1);
+                    prepare_locally(path);
+                }
+            }
+        }
+    });
+    futures::future::join_all(jobs).await;
+}
+
+fn prepare_locally(path: &[String]) {
+    store(path, path.iter().map(|_| NtorHandshake::new()).collect());
+    count(|s| s.local += 1);
+}
+
+/// Let other tasks run between locally prepared paths
+async fn yield_now() {
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::TimeoutFuture::new(0).await;
+}
+
+/// Keypairs packed as `secret | public`, as a worker replies
+fn unpack(mut bytes: Vec<u8>) -> std::result::Result<Vec<NtorHandshake>, String> {
+    if !bytes.len().is_multiple_of(KEYPAIR_LEN) {
+        bytes.zeroize();
+        return Err("malformed reply".into());
+    }
+    let handshakes = bytes
+        .chunks_exact(KEYPAIR_LEN)
+        .map(|pair| {
+            let mut secret = [0u8; 32];
+            let mut public = [0u8; 32];
+            secret.copy_from_slice(&pair[..32]);
+            public.copy_from_slice(&pair[32..]);
+            let handshake = NtorHandshake::from_parts(secret, public);
+            secret.zeroize();
+            handshake
+        })
+        .collect();
+    bytes.zeroize();
+    Ok(handshakes)
+}
+
+/// Field `name` of a job or reply object
+fn field(object: &JsValue, name: &str) -> JsValue {
+    js_sys::Reflect::get(object, &name.into()).unwrap_or(JsValue::UNDEFINED)
+}
+
+/// Ask the next worker for `count` keypairs
+async fn run_job(
+    pool: &HandshakePool,
+    count: usize,
+) -> std::result::Result<Vec<NtorHandshake>, String> {
+    use futures::future::FutureExt;
+
+    let index = pool.next_worker.get() % pool.workers.len();
+    pool.next_worker.set(index + 1);
+    let worker = &pool.workers[index];
+
+    let id = pool.next_id.get().wrapping_add(1);
+    pool.next_id.set(id);
+    let job = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&job, &"id".into(), &JsValue::from(id));
+    let _ = js_sys::Reflect::set(&job, &"count".into(), &JsValue::from(count as u32));
+
+    let (sender, receiver) = oneshot::channel();
+    worker.pending.borrow_mut().insert(id, sender);
+    if let Err(e) = worker.worker.post_message(&job) {
+        worker.pending.borrow_mut().remove(&id);
+        return Err(format!("{:?}", e));
+    }
+
+    let reply = futures::select_biased! {
+        reply = receiver.fuse() => reply.unwrap_or_else(|_| Err("worker stopped".into())),
+        _ = gloo_timers::future::TimeoutFuture::new(JOB_TIMEOUT_MS).fuse() => {
+            worker.pending.borrow_mut().remove(&id);
+            Err("timed out".into())
+        }
+    };
+    let handshakes = unpack(reply?)?;
+    if handshakes.len() != count {
+        return Err("wrong number of keypairs".into());
+    }
+    Ok(handshakes)
+}
+
+/// Start `size` handshake workers (default: one per spare core, up to 4)
+/// loaded from `script_url` (normally `handshake-worker.js` next to the
+/// WASM bundle)
+///
+/// Needs a cross-origin isolated page. Replaces any pool already running.
+#[wasm_bindgen]
+pub fn enable_handshake_pool(
+    script_url: String,
+    size: Option<u32>,
+) -> std::result::Result<(), JsValue> {
+    if !shared_memory_available() {
+        return Err(JsValue::from_str(
+            "handshake pool needs a cross-origin isolated page",
+        ));
+    }
+    let cores = web_sys::window()
+        .map(|w| w.navigator().hardware_concurrency() as usize)
+        .filter(|&n| n > 1);
+    let size = size
+        .map(|n| n as usize)
+        .or(cores.map(|n| n - 1))
+        .unwrap_or(DEFAULT_WORKERS)
+        .clamp(1, MAX_WORKERS);
+
+    let workers = (0..size)
+        .map(|_| start_worker(&script_url))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    log::info!("🧵 Handshake pool started ({} workers)", workers.len());
+    let pool = HandshakePool {
+        workers,
+        next_id: Cell::new(0),
+        next_worker: Cell::new(0),
+    };
+    POOL.with(|p| *p.borrow_mut() = Some(Rc::new(pool)));
+    Ok(())
+}
+
+fn start_worker(script_url: &str) -> std::result::Result<PoolWorker, JsValue> {
+    let options = web_sys::WorkerOptions::new();
+    options.set_type(web_sys::WorkerType::Module);
+    let worker = web_sys::Worker::new_with_options(script_url, &options)?;
+    let pending: Rc<RefCell<HashMap<u32, oneshot::Sender<Reply>>>> = Rc::default();
+
+    let replies = Rc::clone(&pending);
+    let onmessage = Closure::wrap(Box::new(move |event: web_sys::MessageEvent| {
+        let data = event.data();
+        let Some(id) = field(&data, "id").as_f64() else {
+            return;
+        };
+        let Some(sender) = replies.borrow_mut().remove(&(id as u32)) else {
+            return; // Timed out already
+        };
+        let reply = match field(&data, "error").as_string() {
+            Some(error) => Err(error),
+            None => Ok(js_sys::Uint8Array::new(&field(&data, "keypairs")).to_vec()),
+        };
+        let _ = sender.send(reply);
+    }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+    worker.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+
+    // A worker that fails takes the pool with it; paths are prepared
+    // locally from then on
+    let failed = Rc::clone(&pending);
+    let onerror = Closure::wrap(Box::new(move |_event: JsValue| {
+        log::warn!("⚠️ Handshake worker failed, preparing paths locally");
+        failed.borrow_mut().clear();
+        TaskSupervisor::global().spawn("handshake-pool-teardown", async {
+            disable_handshake_pool();
+            Ok(())
+        });
+    }) as Box<dyn FnMut(JsValue)>);
+    worker.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+
+    Ok(PoolWorker {
+        worker,
+        pending,
+        _onmessage: onmessage,
+        _onerror: onerror,
+    })
+}
+
+/// Stop the workers; paths are prepared on the main thread again
+#[wasm_bindgen]
+pub fn disable_handshake_pool() {
+    if POOL.with(|p| p.borrow_mut().take()).is_some() {
+        log::info!("🧵 Handshake pool stopped");
+    }
+}
+
+/// Pool counters: `{ enabled, workers, jobs, local, fallbacks, prepared, used }`
+#[wasm_bindgen]
+pub fn handshake_pool_stats() -> JsValue {
+    serde_wasm_bindgen::to_value(&stats()).unwrap_or(JsValue::NULL)
+}
+
+/// Worker side: generate the keypairs one job asks for
+///
+/// `job` is `{ id, count }`. Returns `{ id, keypairs }`, `keypairs` being
+/// a `Uint8Array` of `count` 64-byte `secret | public` entries.
+#[wasm_bindgen]
+pub fn handshake_worker_handle(job: JsValue) -> std::result::Result<JsValue, JsValue> {
+    let count = field(&job, "count")
+        .as_f64()
+        .filter(|n| (1.0..=16.0).contains(n))
+        .ok_or_else(|| JsValue::from_str("invalid keypair count"))? as usize;
+    let mut keypairs = generate(count);
+    let buffer = js_sys::Uint8Array::from(keypairs.as_slice());
+    keypairs.zeroize();
+
+    let reply = js_sys::Object::new();
+    js_sys::Reflect::set(&reply, &"id".into(), &field(&job, "id"))?;
+    js_sys::Reflect::set(&reply, &"keypairs".into(), &buffer)?;
+    Ok(reply.into())
+}
+
+/// `count` fresh keypairs, packed as a worker replies
+fn generate(count: usize) -> Vec<u8> {
+    use rand::rngs::OsRng;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    let mut packed = Vec::with_capacity(count * KEYPAIR_LEN);
+    for _ in 0..count {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        packed.extend_from_slice(secret.as_bytes());
+        packed.extend_from_slice(PublicKey::from(&secret).as_bytes());
+    }
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_prepared_keypairs_are_used_once() {
+        clear();
+        let paths = vec![path(&["g1", "m1", "e1"]), path(&["g1", "m2", "e2"])];
+        futures::executor::block_on(prepare_paths(&paths));
+        assert_eq!(stats().prepared, 6);
+        assert_eq!(stats().local, 2);
+
+        let first = take("G1").unwrap();
+        let second = take("g1").unwrap();
+        assert_ne!(
+            first.client_public_key().as_bytes(),
+            second.client_public_key().as_bytes()
+        );
+        assert!(take("g1").is_none());
+        assert_eq!(stats().prepared, 4);
+
+        // Without one waiting a fresh keypair is made
+        let _ = handshake_for("unknown");
+        clear();
+        assert_eq!(stats().prepared, 0);
+    }
+
+    #[test]
+    fn test_worker_reply_round_trip() {
+        let packed = generate(3);
+        assert_eq!(packed.len(), 3 * KEYPAIR_LEN);
+        let handshakes = unpack(packed.clone()).unwrap();
+        assert_eq!(handshakes.len(), 3);
+        assert_eq!(
+            handshakes[1].client_public_key().as_bytes(),
+            &packed[KEYPAIR_LEN + 32..2 * KEYPAIR_LEN]
+        );
+        assert!(unpack(vec![1; 10]).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Redirects and content codings for the body-returning fetch methods
+//!
+//! `fetch_json()` and `fetch_bytes()` behave like a small HTTP client on
+//! top of the cooperative GET: they offer `Accept-Encoding: gzip, deflate`,
+//! follow 301/302/303/307/308 redirects and hand back the body with the
+//! chunked framing and content coding removed.
+//!
+//! Every hop of a redirect is a request of its own: it goes through the
+//! plain-HTTP policy again (so an `https://` page can't redirect to
+//! `http://` unless that is allowed) and gets the circuit its host's
+//! isolation key selects. Limits on hops and on the decoded body size keep
+//! redirect loops and compression bombs in check. As in browsers, the
+//! `auth` credentials are dropped once a redirect leaves the origin (scheme,
+//! host and port) first asked for, and stay dropped for the later hops.
+
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, TorError};
+use crate::integrity::FetchOptions;
+use crate::response::HttpResponse;
+
+/// Content codings offered in `Accept-Encoding`
+pub const ACCEPT_ENCODING: &str = "gzip, deflate";
+
+/// Redirect and decoding settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    /// Follow redirects (default: true)
+    pub follow_redirects: bool,
+    /// Redirects followed before giving up (default: 5)
+    pub max_redirects: u32,
+    /// Ask for and decode gzip/deflate bodies (default: true)
+    pub decompress: bool,
+    /// Largest body accepted after decoding (default: 32 MiB)
+    pub max_body_bytes: u64,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            follow_redirects: true,
+            max_redirects: 5,
+            decompress: true,
+            max_body_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+impl HttpClientConfig {
+    /// Where `response` to a request for `url` redirects, if it is a
+    /// redirect this client follows
+    pub fn redirect(&self, response: &HttpResponse, url: &str) -> Result<Option<String>> {
+        if !self.follow_redirects || !matches!(response.status, 301 | 302 | 303 | 307 | 308) {
+            return Ok(None);
+        }
+        let Some(location) = response.header("location") else {
+            return Ok(None);
+        };
+        resolve_location(url, location).map(Some)
+    }
+
+    /// Remove the content coding of `response`'s body, enforcing
+    /// `max_body_bytes`
+    pub fn decode(&self, response: &mut HttpResponse) -> Result<()> {
+        let codings: Vec<String> = response
+            .header("content-encoding")
+            .unwrap_or_default()
+            .split(',')
+            .map(|c| c.trim().to_ascii_lowercase())
+            .filter(|c| !c.is_empty() && c != "identity")
+            .collect();
+        if !codings.is_empty() && !self.decompress {
+            return Err(TorError::ProtocolError(format!(
+                "Response is {}-encoded and decompression is off",
+                codings.join(", ")
+            )));
+        }
+        // Codings are listed in the order they were applied
+        for coding in codings.iter().rev() {
+            response.body = decompress(&response.body, coding, self.max_body_bytes)?;
+        }
+        if response.body.len() as u64 > self.max_body_bytes {
+            return Err(body_too_large(self.max_body_bytes));
+        }
+        response
+            .headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("content-encoding"));
+        Ok(())
+    }
+}
+
+/// Prepare `options` for the redirect hop to `next` of a request first made
+/// for `original`, dropping the credentials if `next` is another origin
+pub fn follow_with(options: &mut FetchOptions, original: &str, next: &str) {
+    if options.auth.is_some() && !same_origin(original, next) {
+        log::info!("🔑 Redirect leaves the origin; dropping Authorization");
+        options.auth = None;
+    }
+}
+
+/// Headers a GET adds for `options` (CRLF-terminated lines)
+pub fn request_headers(options: &FetchOptions) -> String {
+    let mut headers = options
+        .accept_encoding
+        .map(|codings| format!("Accept-Encoding: {}\r\n", codings))
+        .unwrap_or_default();
+    headers.push_str(&options.auth_header());
+    headers
+}
+
+/// Whether `a` and `b` share scheme, host and port
+fn same_origin(a: &str, b: &str) -> bool {
+    match (crate::parse_url(a), crate::parse_url(b)) {
+        (Ok((host_a, port_a, _, https_a)), Ok((host_b, port_b, _, https_b))) => {
+            https_a == https_b && port_a == port_b && host_a.eq_ignore_ascii_case(&host_b)
+        }
+        _ => false,
+    }
+}
+
+fn body_too_large(limit: u64) -> TorError {
+    TorError::ProtocolError(format!("Response body exceeds {} bytes", limit))
+}
+
+/// Decode one content coding, reading at most `limit` bytes of output
+fn decompress(body: &[u8], coding: &str, limit: u64) -> Result<Vec<u8>> {
+    let read_limited = |reader: &mut dyn Read| -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        reader.take(limit + 1).read_to_end(&mut out)?;
+        Ok(out)
+    };
+    let decoded = match coding {
+        "gzip" | "x-gzip" => read_limited(&mut GzDecoder::new(body)),
+        // "deflate" means zlib-wrapped, but some servers send it raw
+        "deflate" => read_limited(&mut ZlibDecoder::new(body))
+            .or_else(|_| read_limited(&mut DeflateDecoder::new(body))),
+        other => {
+            return Err(TorError::ProtocolError(format!(
+                "Unsupported content coding {}",
+                other
+            )))
+        }
+    }
+    .map_err(|e| TorError::ProtocolError(format!("Bad {} body: {}", coding, e)))?;
+    if decoded.len() as u64 > limit {
+        return Err(body_too_large(limit));
+    }
+    Ok(decoded)
+}
+
+/// Resolve a `Location` value against the URL it was received for
+pub fn resolve_location(base: &str, location: &str) -> Result<String> {
+    let location = location.trim();
+    let location = location.split('#').next().unwrap_or_default();
+    if location.is_empty() {
+        return Err(TorError::InvalidUrl("Empty redirect location".into()));
+    }
+    if let Some((scheme, _)) = location.split_once("://") {
+        if scheme.eq_ignore_ascii_case("https") || scheme.eq_ignore_ascii_case("http") {
+            return Ok(location.to_string());
+        }
+        return Err(TorError::InvalidUrl(format!(
+            "Refusing redirect to {}",
+            location
+        )));
+    }
+
+    let (scheme, rest) = base
+        .split_once("://")
+        .ok_or_else(|| TorError::InvalidUrl(format!("Not an absolute URL: {}", base)))?;
+    if let Some(authority) = location.strip_prefix("//") {
+        return Ok(format!("{}://{}", scheme, authority));
+    }
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    if location.starts_with('/') {
+        return Ok(format!("{}://{}{}", scheme, authority, location));
+    }
+    let path = path.split(['?', '#']).next().unwrap_or("/");
+    let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+    let dir = if dir.is_empty() { "/" } else { dir };
+    if location.starts_with('?') {
+        return Ok(format!("{}://{}{}{}", scheme, authority, path, location));
+    }
+    Ok(format!("{}://{}{}{}", scheme, authority, dir, location))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_request::HttpAuth;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: headers
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_redirect_targets() {
+        let config = HttpClientConfig::default();
+        let base = "https://example.com/a/b.html?q=1";
+        let cases = [
+            ("https://other.org/x", "https://other.org/x"),
+            ("//cdn.example.com/y", "https://cdn.example.com/y"),
+            ("/root#frag", "https://example.com/root"),
+            ("c.html", "https://example.com/a/c.html"),
+            ("?page=2", "https://example.com/a/b.html?page=2"),
+        ];
+        for (location, expected) in cases {
+            let moved = response(302, &[("Location", location)], b"");
+            assert_eq!(
+                config.redirect(&moved, base).unwrap().as_deref(),
+                Some(expected)
+            );
+        }
+        assert_eq!(resolve_location("http://h", "x").unwrap(), "http://h/x");
+        assert!(resolve_location(base, "javascript://alert(1)").is_err());
+        assert_eq!(
+            resolve_location(base, "HTTPS://other.org/").unwrap(),
+            "HTTPS://other.org/"
+        );
+
+        let ok = response(200, &[("Location", "/elsewhere")], b"");
+        assert!(config.redirect(&ok, base).unwrap().is_none());
+        let off = HttpClientConfig {
+            follow_redirects: false,
+            ..Default::default()
+        };
+        let moved = response(301, &[("Location", "/elsewhere")], b"");
+        assert!(off.redirect(&moved, base).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_credentials_stay_with_the_origin() {
+        let config = HttpClientConfig::default();
+        let original = "https://example.com/login";
+        let mut options = FetchOptions {
+            auth: Some(HttpAuth::Bearer {
+                token: "secret".into(),
+            }),
+            ..Default::default()
+        };
+        assert!(request_headers(&options).contains("Authorization: Bearer secret\r\n"));
+
+        // Same origin, however it is spelled
+        for location in ["/home", "https://EXAMPLE.com:443/home"] {
+            let moved = response(302, &[("Location", location)], b"");
+            let next = config.redirect(&moved, original).unwrap().unwrap();
+            follow_with(&mut options, original, &next);
+            assert!(request_headers(&options).contains("Authorization"));
+        }
+
+        let moved = response(302, &[("Location", "https://evil.example/steal")], b"");
+        let next = config.redirect(&moved, original).unwrap().unwrap();
+        follow_with(&mut options, original, &next);
+        assert!(!request_headers(&options).contains("Authorization"));
+
+        // Dropped for good, even back at the original origin
+        follow_with(&mut options, original, original);
+        assert!(options.auth.is_none());
+
+        // Another scheme or port is another origin
+        for next in ["http://example.com/login", "https://example.com:8443/login"] {
+            let mut options = FetchOptions {
+                auth: Some(HttpAuth::Basic {
+                    username: "user".into(),
+                    password: "pass".into(),
+                }),
+                ..Default::default()
+            };
+            follow_with(&mut options, original, next);
+            assert!(request_headers(&options).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_decode_gzip_with_limit() {
+        let text = b"{\"hello\":\"world\"}".repeat(100);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&text).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let config = HttpClientConfig::default();
+        let mut gz = response(200, &[("Content-Encoding", "gzip")], &gzipped);
+        config.decode(&mut gz).unwrap();
+        assert_eq!(gz.body, text);
+        assert!(gz.header("content-encoding").is_none());
+
+        let small = HttpClientConfig {
+            max_body_bytes: 100,
+            ..Default::default()
+        };
+        let mut gz = response(200, &[("Content-Encoding", "gzip")], &gzipped);
+        assert!(small.decode(&mut gz).is_err());
+
+        let mut raw = response(200, &[("Content-Encoding", "br")], b"??");
+        assert!(config.decode(&mut raw).is_err());
+        let mut plain = response(200, &[], b"plain");
+        config.decode(&mut plain).unwrap();
+        assert_eq!(plain.body, b"plain");
+    }
+}
@@ -0,0 +1,299 @@
+//! HTTP-level request padding
+//!
+//! Cell padding hides when a circuit is busy, but the number of cells an
+//! exchange takes still leaks the request size, which for API calls often
+//! identifies the request. With padding enabled, each request gets a random
+//! padding header that rounds the whole request (head and body) up to a
+//! size bucket plus some random slack.
+//!
+//! Cooperating servers may pad responses the same way; the padding header
+//! is stripped from responses before they reach the caller. Configuration
+//! is per isolation key, with a default for keys that have no override, so
+//! padding can be turned on only for the sites that tolerate it.
+
+use crate::error::{Result, TorError};
+use crate::isolation::IsolationKey;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Default padding header
+pub const DEFAULT_PADDING_HEADER: &str = "X-Padding";
+
+/// Largest bucket or random slack accepted, in bytes
+pub const MAX_PADDING_BYTES: usize = 64 * 1024;
+
+/// HTTP padding settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpPaddingConfig {
+    /// Whether requests are padded and response padding is stripped
+    pub enabled: bool,
+
+    /// Round padded requests up to a multiple of this many bytes (0 = off)
+    pub bucket_bytes: usize,
+
+    /// Random slack added before rounding, up to this many bytes
+    pub max_random_bytes: usize,
+
+    /// Header carrying the padding
+    pub header: String,
+}
+
+impl Default for HttpPaddingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bucket_bytes: 512,
+            max_random_bytes: 128,
+            header: DEFAULT_PADDING_HEADER.to_string(),
+        }
+    }
+}
+
+impl HttpPaddingConfig {
+    /// Parse and validate the JSON passed from JavaScript
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: Self = serde_json::from_str(json)
+            .map_err(|e| TorError::ParseError(format!("Invalid padding config: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        let is_token = !self.header.is_empty()
+            && self
+                .header
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        if !is_token {
+            return Err(TorError::ParseError(format!(
+                "Invalid padding header name: {:?}",
+                self.header
+            )));
+        }
+        if self.bucket_bytes > MAX_PADDING_BYTES || self.max_random_bytes > MAX_PADDING_BYTES {
+            return Err(TorError::ParseError(format!(
+                "Padding sizes are limited to {} bytes",
+                MAX_PADDING_BYTES
+            )));
+        }
+        Ok(())
+    }
+
+    /// Length of the padding value to add to a request of `len` bytes
+    fn padding_len(&self, len: usize, random_extra: usize) -> usize {
+        // "\r\n" + header + ": " + at least one byte of padding
+        let overhead = self.header.len() + 4;
+        let mut target = len + overhead + 1 + random_extra;
+        if self.bucket_bytes > 0 {
+            target = target.div_ceil(self.bucket_bytes) * self.bucket_bytes;
+        }
+        target - len - overhead
+    }
+
+    /// Add the padding header to a complete HTTP/1.1 request
+    pub fn pad(&self, request: String) -> String {
+        self.pad_head(request, 0)
+    }
+
+    /// Add the padding header to a request head whose `body_len`-byte body
+    /// is sent after it
+    pub fn pad_head(&self, request: String, body_len: usize) -> String {
+        let Some(head_end) = request.find("\r\n\r\n") else {
+            return request;
+        };
+        let mut rng = rand::thread_rng();
+        let extra = rng.gen_range(0..=self.max_random_bytes);
+        let padding: String = (&mut rng)
+            .sample_iter(Alphanumeric)
+            .take(self.padding_len(request.len() + body_len, extra))
+            .map(char::from)
+            .collect();
+
+        let mut padded = String::with_capacity(request.len() + padding.len() + 16);
+        padded.push_str(&request[..head_end]);
+        padded.push_str("\r\n");
+        padded.push_str(&self.header);
+        padded.push_str(": ");
+        padded.push_str(&padding);
+        padded.push_str(&request[head_end..]);
+        padded
+    }
+
+    /// Remove padding header lines from a raw response
+    pub fn strip(&self, response: Vec<u8>) -> Vec<u8> {
+        let Some(head_end) = response.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return response;
+        };
+        let prefix = format!("{}:", self.header.to_ascii_lowercase());
+        let head = &response[..head_end];
+        let is_padding = |line: &[u8]| {
+            line.len() >= prefix.len()
+                && line[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+        };
+        if !head.split(|&b| b == b'\n').any(&is_padding) {
+            return response;
+        }
+
+        let mut stripped = Vec::with_capacity(response.len());
+        for (i, line) in head.split(|&b| b == b'\n').enumerate() {
+            if i > 0 && is_padding(line) {
+                continue;
+            }
+            if i > 0 {
+                stripped.push(b'\n');
+            }
+            stripped.extend_from_slice(line);
+        }
+        stripped.extend_from_slice(&response[head_end..]);
+        stripped
+    }
+}
+
+/// Padding settings per isolation key
+#[derive(Debug, Clone, Default)]
+pub struct HttpPaddingPolicy {
+    default: HttpPaddingConfig,
+    overrides: HashMap<String, HttpPaddingConfig>,
+}
+
+impl HttpPaddingPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Settings for keys without an override
+    pub fn set_default(&mut self, config: HttpPaddingConfig) {
+        self.default = config;
+    }
+
+    /// Settings for keys without an override
+    pub fn default_config(&self) -> &HttpPaddingConfig {
+        &self.default
+    }
+
+    /// Settings for one isolation key
+    pub fn set_for(&mut self, key: &IsolationKey, config: HttpPaddingConfig) {
+        self.overrides.insert(key.as_str().to_string(), config);
+    }
+
+    /// Drop every per-key override
+    pub fn clear_overrides(&mut self) {
+        self.overrides.clear();
+    }
+
+    /// Settings in effect for `key`
+    pub fn config_for(&self, key: &IsolationKey) -> &HttpPaddingConfig {
+        self.overrides.get(key.as_str()).unwrap_or(&self.default)
+    }
+
+    /// Pad `request` if padding is enabled for `key`
+    pub fn pad_request(&self, key: &IsolationKey, request: String) -> String {
+        let config = self.config_for(key);
+        if config.enabled {
+            config.pad(request)
+        } else {
+            request
+        }
+    }
+
+    /// Pad a request head (see [`HttpPaddingConfig::pad_head`]) if padding
+    /// is enabled for `key`
+    pub fn pad_request_head(&self, key: &IsolationKey, head: String, body_len: usize) -> String {
+        let config = self.config_for(key);
+        if config.enabled {
+            config.pad_head(head, body_len)
+        } else {
+            head
+        }
+    }
+
+    /// Strip response padding if padding is enabled for `key`
+    pub fn strip_response(&self, key: &IsolationKey, response: Vec<u8>) -> Vec<u8> {
+        let config = self.config_for(key);
+        if config.enabled {
+            config.strip(response)
+        } else {
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isolation::IsolationType;
+
+    fn enabled() -> HttpPaddingConfig {
+        HttpPaddingConfig {
+            enabled: true,
+            ..HttpPaddingConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_requests_pad_to_buckets() {
+        let config = enabled();
+        for body_len in [0, 100, 400, 1000, 5000] {
+            let request = format!(
+                "POST /v1/x HTTP/1.1\r\nHost: api.example\r\nContent-Length: {}\r\n\r\n{}",
+                body_len,
+                "b".repeat(body_len)
+            );
+            let padded = config.pad(request.clone());
+            assert_eq!(padded.len() % config.bucket_bytes, 0);
+            assert!(padded.len() > request.len());
+
+            // Head gains exactly one header; the body is untouched
+            let (head, body) = padded.split_once("\r\n\r\n").unwrap();
+            assert!(head.lines().last().unwrap().starts_with("X-Padding: "));
+            assert_eq!(body.len(), body_len);
+        }
+
+        let no_bucket = HttpPaddingConfig {
+            bucket_bytes: 0,
+            max_random_bytes: 0,
+            ..enabled()
+        };
+        let padded = no_bucket.pad("GET / HTTP/1.1\r\n\r\n".into());
+        assert_eq!(
+            padded,
+            format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", &padded[27..28])
+        );
+    }
+
+    #[test]
+    fn test_strip_and_per_key_policy() {
+        let response =
+            b"HTTP/1.1 200 OK\r\nx-padding: abcdef\r\nContent-Length: 2\r\n\r\nok".to_vec();
+        assert_eq!(
+            enabled().strip(response.clone()),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_vec()
+        );
+
+        let mut policy = HttpPaddingPolicy::new();
+        let padded_site = IsolationKey::for_destination("a.example", 443, IsolationType::PerDomain);
+        let other = IsolationKey::for_destination("b.example", 443, IsolationType::PerDomain);
+        policy.set_for(&padded_site, enabled());
+
+        assert_eq!(policy.strip_response(&other, response.clone()), response);
+        assert_ne!(
+            policy.strip_response(&padded_site, response.clone()),
+            response
+        );
+        let request = "GET / HTTP/1.1\r\n\r\n".to_string();
+        assert_eq!(policy.pad_request(&other, request.clone()), request);
+        assert!(policy
+            .pad_request(&padded_site, request)
+            .contains("X-Padding"));
+
+        assert!(HttpPaddingConfig::from_json(r#"{"enabled":true,"header":"Bad Header"}"#).is_err());
+        assert!(HttpPaddingConfig::from_json(r#"{"bucket_bytes":1000000}"#).is_err());
+        assert_eq!(
+            HttpPaddingConfig::from_json(r#"{"enabled":true}"#).unwrap(),
+            enabled()
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Plain-HTTP policy
+//!
+//! Over plain HTTP the exit relay sees, and can rewrite, the whole request
+//! and response. `fetch("http://…")` is therefore refused unless the caller
+//! opts in, either for the client (`http.allow_insecure_http` in the
+//! config) or for one request (the same field in the fetch options).
+//!
+//! With `upgrade_to_https` an `http://` URL is first tried as `https://`
+//! (default port 80 becomes 443; an explicit port is kept). If that attempt
+//! fails, GET requests fall back to the original URL only when insecure
+//! HTTP is allowed.
+//!
+//! `.onion` URLs are exempt: traffic to an onion service never leaves Tor
+//! and is end-to-end encrypted, so plain HTTP is allowed and not upgraded.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, TorError};
+
+/// Plain-HTTP settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpSecurityConfig {
+    /// Allow requests to `http://` URLs (default: false)
+    pub allow_insecure_http: bool,
+    /// Try `http://` URLs as `https://` first (default: false)
+    pub upgrade_to_https: bool,
+}
+
+impl HttpSecurityConfig {
+    /// These settings with a request's overrides applied
+    pub fn with_overrides(
+        &self,
+        allow_insecure_http: Option<bool>,
+        upgrade_to_https: Option<bool>,
+    ) -> Self {
+        Self {
+            allow_insecure_http: allow_insecure_http.unwrap_or(self.allow_insecure_http),
+            upgrade_to_https: upgrade_to_https.unwrap_or(self.upgrade_to_https),
+        }
+    }
+
+    /// Decide how to fetch `url`
+    ///
+    /// Schemes are case-insensitive; the planned URLs spell them in
+    /// lowercase.
+    pub fn plan(&self, url: &str) -> Result<HttpPlan> {
+        let url = url.trim();
+        // URLs without a scheme are fetched as plain HTTP
+        let (url, rest) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => {
+                return Ok(HttpPlan::direct(&format!("https://{}", rest)));
+            }
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => {
+                (format!("http://{}", rest), rest)
+            }
+            _ => (url.to_string(), url),
+        };
+        let url = url.as_str();
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+        let host = authority.rsplit_once(':').map_or(authority, |(h, _)| h);
+        if crate::protocol::is_onion_host(host) {
+            return Ok(HttpPlan::direct(url));
+        }
+
+        if self.upgrade_to_https {
+            return Ok(HttpPlan {
+                url: format!("https://{}", upgrade_port(rest)),
+                fallback: self.allow_insecure_http.then(|| format!("http://{}", rest)),
+            });
+        }
+        if self.allow_insecure_http {
+            return Ok(HttpPlan::direct(url));
+        }
+        Err(TorError::InvalidUrl(format!(
+            "refusing plain HTTP to {}: the exit relay could read and modify it; \
+             use https://, set upgrade_to_https, or opt in with allow_insecure_http",
+            url
+        )))
+    }
+}
+
+/// How a URL will be fetched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpPlan {
+    /// URL to request
+    pub url: String,
+    /// Plain-HTTP URL to try if the upgraded request fails
+    pub fallback: Option<String>,
+}
+
+impl HttpPlan {
+    fn direct(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            fallback: None,
+        }
+    }
+}
+
+/// Replace an explicit `:80` on the authority with the HTTPS default
+fn upgrade_port(rest: &str) -> String {
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, tail) = rest.split_at(end);
+    match authority.strip_suffix(":80") {
+        Some(host) => format!("{}{}", host, tail),
+        None => rest.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_http_blocked_unless_allowed() {
+        let strict = HttpSecurityConfig::default();
+        assert!(strict.plan("http://example.com/").is_err());
+        assert!(strict.plan("example.com/").is_err());
+        assert_eq!(
+            strict.plan("https://example.com/").unwrap(),
+            HttpPlan::direct("https://example.com/")
+        );
+
+        let allowed = strict.with_overrides(Some(true), None);
+        assert_eq!(
+            allowed.plan("http://example.com/").unwrap(),
+            HttpPlan::direct("http://example.com/")
+        );
+    }
+
+    #[test]
+    fn test_scheme_is_case_insensitive() {
+        let strict = HttpSecurityConfig::default();
+        assert_eq!(
+            strict.plan("HTTPS://example.com/Path").unwrap(),
+            HttpPlan::direct("https://example.com/Path")
+        );
+        assert!(strict.plan("Http://example.com/").is_err());
+
+        let allowed = strict.with_overrides(Some(true), None);
+        assert_eq!(
+            allowed.plan("HTTP://example.com/").unwrap(),
+            HttpPlan::direct("http://example.com/")
+        );
+        let upgrade = strict.with_overrides(None, Some(true));
+        assert_eq!(
+            upgrade.plan("HTTP://example.com:80/").unwrap().url,
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn test_onion_urls_stay_plain_http() {
+        let upgrade = HttpSecurityConfig {
+            allow_insecure_http: false,
+            upgrade_to_https: true,
+        };
+        let url = "http://duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion:80/";
+        assert_eq!(upgrade.plan(url).unwrap(), HttpPlan::direct(url));
+        assert!(upgrade
+            .plan("http://onion.example.com/")
+            .unwrap()
+            .url
+            .starts_with("https://"));
+    }
+
+    #[test]
+    fn test_upgrade_with_optional_fallback() {
+        let upgrade = HttpSecurityConfig {
+            allow_insecure_http: false,
+            upgrade_to_https: true,
+        };
+        assert_eq!(
+            upgrade.plan("http://example.com:80/a?b").unwrap(),
+            HttpPlan {
+                url: "https://example.com/a?b".to_string(),
+                fallback: None,
+            }
+        );
+        assert_eq!(
+            upgrade
+                .with_overrides(Some(true), None)
+                .plan("http://example.com:8080/")
+                .unwrap(),
+            HttpPlan {
+                url: "https://example.com:8080/".to_string(),
+                fallback: Some("http://example.com:8080/".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! Subresource-integrity checks on fetched bodies
+//!
+//! The GET fetch methods take `{ integrity: "sha256-..." }` in the format of
+//! the HTML `integrity` attribute: space-separated `alg-base64` hashes, of
+//! which sha256, sha384 and sha512 are understood and anything else is
+//! ignored. As in SRI, only the hashes of the strongest algorithm listed
+//! count, and the body passes if it matches any of them.
+//!
+//! The hash covers the body after transfer decoding (chunked framing
+//! removed). A plain-HTTP body that fails the check was most likely
+//! rewritten by the exit, which sees that traffic in the clear; over HTTPS
+//! the exit can't alter it, so a mismatch means the resource changed.
+
+use crate::cancel::CancelToken;
+use crate::error::{Result, TorError};
+use crate::sse::{decode_chunked, ChunkState};
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// Per-request options accepted by the GET fetch methods
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FetchOptions {
+    /// SRI metadata the response body must match
+    #[serde(default)]
+    pub integrity: Option<String>,
+    /// Address families the exit may connect over (RELAY_BEGIN flags)
+    #[serde(default)]
+    pub begin_flags: crate::protocol::BeginFlags,
+    /// Allow this request over plain HTTP, overriding the client setting
+    #[serde(default)]
+    pub allow_insecure_http: Option<bool>,
+    /// Try an `http://` URL as `https://` first, overriding the client setting
+    #[serde(default)]
+    pub upgrade_to_https: Option<bool>,
+    /// Per-request bandwidth quota, overriding the client setting
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// `fetch_to_file()` only: continue a partial file rather than replace it
+    #[serde(default)]
+    pub resume: Option<bool>,
+    /// Cancellation tied to the `signal` option, an `AbortSignal`
+    #[serde(default, rename = "signal", deserialize_with = "abort_signal")]
+    pub cancel: Option<CancelToken>,
+    /// Credentials for the `Authorization` header
+    #[serde(default)]
+    pub auth: Option<crate::http_request::HttpAuth>,
+    /// Content codings to offer, set by the methods that decode them
+    #[serde(skip)]
+    pub accept_encoding: Option<&'static str>,
+}
+
+fn abort_signal<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<CancelToken>, D::Error> {
+    use wasm_bindgen::JsCast;
+    let value: wasm_bindgen::JsValue = serde_wasm_bindgen::preserve::deserialize(deserializer)?;
+    if value.is_undefined() || value.is_null() {
+        return Ok(None);
+    }
+    value
+        .dyn_into::<web_sys::AbortSignal>()
+        .map(|signal| Some(CancelToken::from_signal(&signal)))
+        .map_err(|_| serde::de::Error::custom("signal must be an AbortSignal"))
+}
+
+impl FetchOptions {
+    /// Parsed `integrity`, if one was given
+    pub fn integrity(&self) -> Result<Option<Integrity>> {
+        self.integrity.as_deref().map(Integrity::parse).transpose()
+    }
+
+    /// `Authorization` header line (CRLF-terminated) for `auth`, or nothing
+    pub fn auth_header(&self) -> String {
+        self.auth
+            .as_ref()
+            .map(|auth| format!("Authorization: {}\r\n", auth.header_value()))
+            .unwrap_or_default()
+    }
+}
+
+/// Hash algorithms, weakest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" => Some(Algorithm::Sha256),
+            "sha384" => Some(Algorithm::Sha384),
+            "sha512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn digest(&self, body: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Sha256 => Sha256::digest(body).to_vec(),
+            Algorithm::Sha384 => Sha384::digest(body).to_vec(),
+            Algorithm::Sha512 => Sha512::digest(body).to_vec(),
+        }
+    }
+}
+
+/// Parsed integrity metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Integrity {
+    /// Acceptable digests, all of the strongest algorithm given
+    algorithm: Algorithm,
+    digests: Vec<Vec<u8>>,
+}
+
+impl Integrity {
+    /// Parse SRI metadata; fails if it names no usable hash
+    pub fn parse(metadata: &str) -> Result<Self> {
+        let hashes: Vec<(Algorithm, Vec<u8>)> = metadata
+            .split_ascii_whitespace()
+            .filter_map(|token| {
+                // Options after `?` are reserved and ignored
+                let token = token.split('?').next()?;
+                let (algorithm, hash) = token.split_once('-')?;
+                let algorithm = Algorithm::parse(algorithm)?;
+                let digest = base64::engine::general_purpose::STANDARD
+                    .decode(hash)
+                    .ok()?;
+                Some((algorithm, digest))
+            })
+            .collect();
+
+        let algorithm = hashes
+            .iter()
+            .map(|(algorithm, _)| *algorithm)
+            .max()
+            .ok_or_else(|| {
+                TorError::ParseError(format!("No usable integrity hash in {:?}", metadata))
+            })?;
+        Ok(Self {
+            algorithm,
+            digests: hashes
+                .into_iter()
+                .filter(|(a, _)| *a == algorithm)
+                .map(|(_, digest)| digest)
+                .collect(),
+        })
+    }
+
+    /// Check a body against the metadata
+    pub fn verify(&self, body: &[u8]) -> Result<()> {
+        let digest = self.algorithm.digest(body);
+        if self.digests.contains(&digest) {
+            return Ok(());
+        }
+        Err(TorError::IntegrityMismatch(format!(
+            "{:?} of {}-byte body is {}",
+            self.algorithm,
+            body.len(),
+            base64::engine::general_purpose::STANDARD.encode(digest)
+        )))
+    }
+
+    /// Check the body of a raw HTTP/1.1 response
+    pub fn verify_response(&self, response: &[u8]) -> Result<()> {
+        self.verify(&response_body(response)?)
+    }
+}
+
+/// Body of a raw HTTP/1.1 response, with chunked framing removed
+///
+/// Otherwise a `Content-Length` bounds the body: bytes past it are dropped,
+/// and a body cut short fails. An empty body is accepted whatever the
+/// length says, as HEAD and 304 responses have none.
+pub fn response_body(response: &[u8]) -> Result<Vec<u8>> {
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| TorError::ProtocolError("Incomplete response header".into()))?;
+    let (head, body) = (&response[..end], &response[end + 4..]);
+
+    let head = String::from_utf8_lossy(head);
+    let header = |wanted: &str| {
+        head.split("\r\n").skip(1).find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case(wanted)
+                .then(|| value.trim().to_ascii_lowercase())
+        })
+    };
+    let chunked = header("transfer-encoding").is_some_and(|v| v.contains("chunked"));
+    if !chunked {
+        let length = header("content-length").and_then(|v| v.parse::<usize>().ok());
+        return match length {
+            Some(length) if !body.is_empty() && body.len() < length => {
+                Err(TorError::ProtocolError(format!(
+                    "Truncated body ({} of {} bytes)",
+                    body.len(),
+                    length
+                )))
+            }
+            Some(length) => Ok(body[..length.min(body.len())].to_vec()),
+            None => Ok(body.to_vec()),
+        };
+    }
+
+    let mut state = ChunkState::Size(Vec::new());
+    let mut decoded = Vec::with_capacity(body.len());
+    decode_chunked(&mut state, body, &mut decoded)?;
+    if !matches!(state, ChunkState::Done) {
+        return Err(TorError::ProtocolError("Truncated chunked body".into()));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sri(algorithm: &str, body: &[u8]) -> String {
+        let digest = Algorithm::parse(algorithm).unwrap().digest(body);
+        format!(
+            "{}-{}",
+            algorithm,
+            base64::engine::general_purpose::STANDARD.encode(digest)
+        )
+    }
+
+    #[test]
+    fn test_strongest_algorithm_decides() {
+        let body = b"console.log('hello')";
+        let integrity = Integrity::parse(&sri("sha256", body)).unwrap();
+        assert!(integrity.verify(body).is_ok());
+        assert!(matches!(
+            integrity.verify(b"console.log('pwned')"),
+            Err(TorError::IntegrityMismatch(_))
+        ));
+
+        // A matching sha256 doesn't help when a sha512 is also given
+        let metadata = format!(
+            "{} {}?opt md5-AAAA",
+            sri("sha256", body),
+            sri("sha512", b"other")
+        );
+        assert!(Integrity::parse(&metadata).unwrap().verify(body).is_err());
+
+        // Any hash of the strongest algorithm will do
+        let metadata = format!("{} {}", sri("sha384", b"v1"), sri("sha384", body));
+        assert!(Integrity::parse(&metadata).unwrap().verify(body).is_ok());
+
+        assert!(Integrity::parse("md5-AAAA sha256-not*base64").is_err());
+        assert!(Integrity::parse("").is_err());
+    }
+
+    #[test]
+    fn test_response_body_decodes_chunks() {
+        let body = b"Wikipedia in\r\n\r\nchunks.";
+        let integrity = Integrity::parse(&sri("sha256", body)).unwrap();
+
+        let plain = [
+            b"HTTP/1.1 200 OK\r\nContent-Length: 23\r\n\r\n".as_slice(),
+            body,
+        ]
+        .concat();
+        assert!(integrity.verify_response(&plain).is_ok());
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n13\r\npedia in\r\n\r\nchunks.\r\n0\r\n\r\n";
+        assert_eq!(response_body(chunked).unwrap(), body);
+        assert!(integrity.verify_response(chunked).is_ok());
+
+        let truncated = &chunked[..chunked.len() - 5];
+        assert!(matches!(
+            integrity.verify_response(truncated),
+            Err(TorError::ProtocolError(_))
+        ));
+    }
+}
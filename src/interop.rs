@@ -0,0 +1,78 @@
+//! Interop test hooks (`test-interop` feature)
+//!
+//! Lets integration tests point the client at a chutney-style local Tor
+//! network through a test bridge. Such networks run every relay on one
+//! host (usually 127.0.0.1) on arbitrary ports, so two path rules that
+//! protect users on the real network would reject every path:
+//!
+//! - relays are only used on well-known OR ports
+//! - no two relays on a circuit may share an IPv4 /16
+//!
+//! While local network mode is on, both are lifted, and bootstrap fails
+//! instead of falling back to the built-in public relay list when the
+//! bridge has no consensus. Everything else
+//! (ntor handshakes, digests, flow control) runs exactly as in production,
+//! which is the point of testing against real relays. The consensus comes
+//! from the bridge; run `bridge-server/server-collector.js` with
+//! `TOR_DIRPORT` set to a test authority's DirPort so it serves the local
+//! network's consensus (as plain JSON) instead of the public one.
+//!
+//! Never enable this feature in a release build.
+
+use std::cell::Cell;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static LOCAL_NETWORK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether path rules are relaxed for a local test network
+pub fn local_network() -> bool {
+    LOCAL_NETWORK.with(Cell::get)
+}
+
+/// Relax path rules for a local test network (off by default)
+pub fn set_local_network(enabled: bool) {
+    if enabled {
+        log::warn!("🧪 Local test network mode: OR port and /16 path rules are off");
+    }
+    LOCAL_NETWORK.with(|flag| flag.set(enabled));
+}
+
+/// Accept relays on any OR port and in the same /16, as a chutney-style
+/// local test network needs (builds with the `test-interop` feature only)
+#[wasm_bindgen]
+pub fn set_local_test_network(enabled: bool) {
+    set_local_network(enabled);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{same_ipv4_slash16, Relay, RelayFlags, RelaySelector, TestRelay};
+
+    fn local_relay(nickname: &str, or_port: u16) -> Relay {
+        TestRelay::new(nickname)
+            .fingerprint(&format!("{:0>40}", or_port))
+            .address("127.0.0.1")
+            .or_port(or_port)
+            .flags(RelayFlags::from_string("Guard Exit Fast Stable Running"))
+            .ntor_onion_key(Some("AAAA"))
+            .build()
+    }
+
+    #[test]
+    fn test_local_network_relaxes_path_rules() {
+        let relays = vec![local_relay("test000a", 5000), local_relay("test001a", 5001)];
+        let selector = RelaySelector::new(relays.clone());
+        assert!(selector.select_guard().is_none());
+        assert!(same_ipv4_slash16(&relays[0], &relays[1]));
+
+        set_local_network(true);
+        assert!(selector.select_guard().is_some());
+        assert!(!same_ipv4_slash16(&relays[0], &relays[1]));
+
+        set_local_network(false);
+        assert!(!local_network());
+    }
+}
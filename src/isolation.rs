@@ -10,16 +10,41 @@
 //! circuit (and thus the same exit node at the same time).
 //!
 //! With isolation, each domain gets its own circuit, preventing this attack.
+//!
+//! A key whose circuit already carries `max_streams_per_circuit` open
+//! streams spills over onto another circuit under the same key (up to
+//! `max_circuits_per_key`), and new streams go to the least busy of them.
+//! Spillover circuits are retired and cleared with the key's first one.
+//!
+//! A host can be given its own [`RotationPolicy`] (e.g. a new circuit for
+//! `api.example.com` every 15 minutes or every 50 requests). Once 80% of
+//! its budget is used, [`CircuitCache::due_replacements`] names the key so
+//! the client can build a standby circuit in the background; at rotation
+//! the key's circuits are retired together and the standby takes over, so
+//! the request that triggers rotation doesn't wait for a build. Streams
+//! already open on a retired circuit keep it until they finish.
+//!
+//! Keys starting with [`RESERVED_PREFIX`] belong to the client's own traffic
+//! (directory fetches) and can't be produced from a destination, so a user
+//! request never lands on a directory circuit or the other way around.
 
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+use crate::cooperative::MAX_STREAMS_PER_CIRCUIT;
+use crate::error::{Result, TorError};
 use crate::protocol::Circuit;
+use crate::runtime::LocalCell;
+
+/// Share of a rotation budget after which a standby circuit is built
+const STANDBY_AT: f64 = 0.8;
 
 /// How circuits should be isolated
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum IsolationType {
     /// One circuit per domain (e.g., example.com)
     /// This is the default and recommended setting
@@ -39,20 +64,87 @@ pub enum IsolationType {
     None,
 }
 
+impl IsolationType {
+    /// Parse a policy name as accepted by `set_isolation_policy()`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "per_domain" | "domain" => Some(IsolationType::PerDomain),
+            "per_destination" | "destination" => Some(IsolationType::PerDestination),
+            "per_request" | "request" | "paranoid" => Some(IsolationType::PerRequest),
+            "none" | "global" | "off" => Some(IsolationType::None),
+            _ => None,
+        }
+    }
+}
+
 /// Configuration for circuit isolation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct IsolationConfig {
     /// The isolation policy to use
     pub policy: IsolationType,
 
     /// Maximum age of a circuit before forced rotation (default: 10 minutes)
+    #[serde(rename = "max_circuit_age_secs", with = "duration_secs")]
     pub max_circuit_age: Duration,
 
     /// Maximum number of requests per circuit before rotation (default: 100)
     pub max_requests_per_circuit: u32,
 
-    /// Maximum number of cached circuits (default: 10)
+    /// Maximum number of isolation keys with cached circuits (default: 10)
     pub max_cached_circuits: usize,
+
+    /// Open streams a circuit carries before new streams under its key
+    /// spill over onto another circuit (default: `MAX_STREAMS_PER_CIRCUIT`)
+    pub max_streams_per_circuit: usize,
+
+    /// Maximum circuits per isolation key, spillover included (default: 3).
+    /// Once reached, streams share the least busy circuit.
+    pub max_circuits_per_key: usize,
+
+    /// Rotation schedules by host (`global` under the `none` policy);
+    /// these replace the age and request limits for that host's circuits
+    pub rotation: BTreeMap<String, RotationPolicy>,
+}
+
+/// When a host's circuits are rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RotationPolicy {
+    /// Rotate after this many seconds
+    pub every_secs: Option<u64>,
+    /// Rotate after this many requests
+    pub every_requests: Option<u32>,
+}
+
+impl RotationPolicy {
+    fn validate(&self, host: &str) -> Result<()> {
+        if self.every_secs.is_none() && self.every_requests.is_none() {
+            return Err(TorError::ParseError(format!(
+                "isolation.rotation.{}: set every_secs or every_requests",
+                host
+            )));
+        }
+        if self.every_secs == Some(0) || self.every_requests == Some(0) {
+            return Err(TorError::ParseError(format!(
+                "isolation.rotation.{}: limits must be greater than 0",
+                host
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fraction of the budget a circuit of `age` that served `requests`
+    /// has used (1.0 or more means rotate)
+    fn used(&self, age: Duration, requests: u32) -> f64 {
+        let by_age = self
+            .every_secs
+            .map_or(0.0, |secs| age.as_secs_f64() / secs as f64);
+        let by_requests = self
+            .every_requests
+            .map_or(0.0, |n| requests as f64 / n as f64);
+        by_age.max(by_requests)
+    }
 }
 
 impl Default for IsolationConfig {
@@ -62,6 +154,9 @@ impl Default for IsolationConfig {
             max_circuit_age: Duration::from_secs(10 * 60), // 10 minutes
             max_requests_per_circuit: 100,
             max_cached_circuits: 10,
+            max_streams_per_circuit: MAX_STREAMS_PER_CIRCUIT,
+            max_circuits_per_key: 3,
+            rotation: BTreeMap::new(),
         }
     }
 }
@@ -83,10 +178,27 @@ impl IsolationConfig {
             max_circuit_age: Duration::from_secs(30 * 60), // 30 minutes
             max_requests_per_circuit: 1000,
             max_cached_circuits: 1,
+            ..Default::default()
         }
     }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        self.rotation
+            .iter()
+            .try_for_each(|(host, policy)| policy.validate(host))
+    }
+
+    /// Rotation schedule for circuits cached under `key`
+    pub fn rotation_for(&self, key: &IsolationKey) -> Option<&RotationPolicy> {
+        self.rotation
+            .get(key.as_str())
+            .or_else(|| self.rotation.get(key.host()?))
+    }
 }
 
+/// First character of keys reserved for the client's own traffic
+pub const RESERVED_PREFIX: char = '#';
+
 /// Key for circuit isolation - determines which circuit to use
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct IsolationKey {
@@ -110,19 +222,48 @@ impl IsolationKey {
                 // Unique key for each request
                 format!("{}:{}:{}", host, port, uuid_v4())
             }
+            IsolationType::None if crate::protocol::is_onion_host(host) => {
+                // A rendezvous circuit reaches only its own service
+                format!("global {}", host.trim_end_matches('.').to_lowercase())
+            }
             IsolationType::None => {
                 // Single key for all
                 "global".to_string()
             }
         };
 
+        // Keep destinations out of the reserved namespace
+        let key = if key.starts_with(RESERVED_PREFIX) {
+            format!("%{}", key)
+        } else {
+            key
+        };
+
         Self { key }
     }
 
+    /// Key for directory fetches, shared by no destination
+    pub fn directory() -> Self {
+        Self {
+            key: format!("{}directory", RESERVED_PREFIX),
+        }
+    }
+
+    /// Whether this key is in the reserved (non-user) namespace
+    pub fn is_reserved(&self) -> bool {
+        self.key.starts_with(RESERVED_PREFIX)
+    }
+
     /// Get the key string
     pub fn as_str(&self) -> &str {
         &self.key
     }
+
+    /// Host part of a `host:port` key
+    fn host(&self) -> Option<&str> {
+        let (host, port) = self.key.rsplit_once(':')?;
+        (!host.contains(':') && port.parse::<u16>().is_ok()).then_some(host)
+    }
 }
 
 /// Metadata about a cached circuit
@@ -130,6 +271,9 @@ struct CachedCircuit {
     /// The circuit itself (wrapped for shared access)
     circuit: Rc<RefCell<Circuit>>,
 
+    /// ID of the circuit, readable while it is borrowed
+    circuit_id: u32,
+
     /// When this circuit was created
     created_at: Instant,
 
@@ -143,6 +287,7 @@ struct CachedCircuit {
 impl CachedCircuit {
     fn new(circuit: Circuit, key: IsolationKey) -> Self {
         Self {
+            circuit_id: circuit.id,
             circuit: Rc::new(RefCell::new(circuit)),
             created_at: Instant::now(),
             request_count: 0,
@@ -152,6 +297,10 @@ impl CachedCircuit {
 
     /// Check if this circuit should be retired
     fn should_retire(&self, config: &IsolationConfig) -> bool {
+        if let Some(policy) = config.rotation_for(&self.isolation_key) {
+            return policy.used(self.created_at.elapsed(), self.request_count) >= 1.0;
+        }
+
         // Check age
         if self.created_at.elapsed() > config.max_circuit_age {
             log::info!(
@@ -177,6 +326,20 @@ impl CachedCircuit {
     fn increment_requests(&mut self) {
         self.request_count += 1;
     }
+
+    /// Streams open on this circuit: every reference beyond the cache's own
+    /// is held by a stream (or a request about to open one)
+    fn active_streams(&self) -> usize {
+        Rc::strong_count(&self.circuit) - 1
+    }
+}
+
+/// Circuit cache shared between the client and its keepalive task
+pub type SharedCircuitCache = Rc<LocalCell<CircuitCache>>;
+
+/// Create an empty shared cache with `config`
+pub fn new_shared_circuit_cache(config: IsolationConfig) -> SharedCircuitCache {
+    Rc::new(LocalCell::new(CircuitCache::new(config)))
 }
 
 /// Circuit cache with isolation support
@@ -184,11 +347,15 @@ pub struct CircuitCache {
     /// Configuration
     config: IsolationConfig,
 
-    /// Cached circuits by isolation key
-    circuits: HashMap<String, CachedCircuit>,
+    /// Cached circuits by isolation key, oldest first; all but the first
+    /// are spillover circuits
+    circuits: HashMap<String, Vec<CachedCircuit>>,
 
     /// Order of circuit insertion (for LRU eviction)
     insertion_order: Vec<String>,
+
+    /// Circuits built ahead of a key's rotation, waiting to take over
+    standby: HashMap<String, CachedCircuit>,
 }
 
 impl CircuitCache {
@@ -198,6 +365,7 @@ impl CircuitCache {
             config,
             circuits: HashMap::new(),
             insertion_order: Vec::new(),
+            standby: HashMap::new(),
         }
     }
 
@@ -206,90 +374,266 @@ impl CircuitCache {
         self.config.policy
     }
 
+    /// Get the full isolation configuration
+    pub fn config(&self) -> &IsolationConfig {
+        &self.config
+    }
+
     /// Create an isolation key for a destination
     pub fn isolation_key(&self, host: &str, port: u16) -> IsolationKey {
         IsolationKey::for_destination(host, port, self.config.policy)
     }
 
+    /// Rotate circuits for `host` on `policy` from now on
+    pub fn set_rotation_policy(&mut self, host: &str, policy: RotationPolicy) -> Result<()> {
+        let host = host.trim_end_matches('.').to_lowercase();
+        policy.validate(&host)?;
+        self.config.rotation.insert(host, policy);
+        Ok(())
+    }
+
+    /// Drop the rotation schedule for `host`, returning whether it had one
+    pub fn clear_rotation_policy(&mut self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        let removed = self.config.rotation.remove(&host).is_some();
+        let config = &self.config;
+        self.standby
+            .retain(|_, cached| config.rotation_for(&cached.isolation_key).is_some());
+        removed
+    }
+
+    /// Keys with a rotation schedule whose current circuit has used most
+    /// of its budget and that have no standby circuit yet
+    pub fn due_replacements(&self) -> Vec<IsolationKey> {
+        self.insertion_order
+            .iter()
+            .filter(|key| !self.standby.contains_key(*key))
+            .filter_map(|key| self.circuits.get(key)?.first())
+            .filter(|primary| {
+                self.config
+                    .rotation_for(&primary.isolation_key)
+                    .is_some_and(|policy| {
+                        policy.used(primary.created_at.elapsed(), primary.request_count)
+                            >= STANDBY_AT
+                    })
+            })
+            .map(|primary| primary.isolation_key.clone())
+            .collect()
+    }
+
+    /// The first circuit cached under `key`, without counting a request
+    pub fn primary(&self, key: &IsolationKey) -> Option<Rc<RefCell<Circuit>>> {
+        let cached = self.circuits.get(key.as_str())?.first()?;
+        Some(Rc::clone(&cached.circuit))
+    }
+
+    /// Keep `circuit` ready to replace the circuits under `key` at their
+    /// rotation; returns false (dropping it) if the key is no longer cached
+    pub fn store_standby(&mut self, key: IsolationKey, circuit: Circuit) -> bool {
+        if !self.circuits.contains_key(key.as_str()) {
+            return false;
+        }
+        log::info!(
+            "  🧊 Standby circuit {} ready for '{}'",
+            circuit.id,
+            key.as_str()
+        );
+        self.standby
+            .insert(key.as_str().to_string(), CachedCircuit::new(circuit, key));
+        true
+    }
+
+    /// Retire every circuit under `key` now, handing over to its standby
+    /// circuit if one is ready; returns whether the key had circuits
+    pub fn rotate(&mut self, key: &IsolationKey) -> bool {
+        let key_str = key.as_str();
+        let Some(lanes) = self.circuits.get_mut(key_str) else {
+            return false;
+        };
+        match self.standby.remove(key_str) {
+            Some(mut standby) => {
+                // Its budget starts when it takes over
+                standby.created_at = Instant::now();
+                log::info!(
+                    "  🔁 Rotating '{}' to standby circuit {}",
+                    key_str,
+                    standby.circuit_id
+                );
+                *lanes = vec![standby];
+            }
+            None => {
+                log::info!("  🔁 Rotating '{}' (no standby circuit)", key_str);
+                self.remove(key);
+            }
+        }
+        true
+    }
+
     /// Get a circuit for the given isolation key, if one exists and is valid
+    ///
+    /// Picks the key's least busy circuit, passing over any borrowed
+    /// elsewhere (mid keepalive probe). Returns `None` when every one of
+    /// them is at `max_streams_per_circuit` and the key may have another,
+    /// or all are borrowed: the caller builds a circuit and `store`s it.
     pub fn get(&mut self, key: &IsolationKey) -> Option<Rc<RefCell<Circuit>>> {
         let key_str = key.as_str();
+        let lanes = self.circuits.get_mut(key_str)?;
 
-        // Check if we have a circuit for this key
-        if let Some(cached) = self.circuits.get_mut(key_str) {
-            // Check if it should be retired
-            if cached.should_retire(&self.config) {
-                log::info!("  ♻️ Retiring old circuit for '{}'", key_str);
-                self.remove(key);
-                return None;
-            }
+        // A key on a rotation schedule retires its circuits together
+        let config = &self.config;
+        if config.rotation_for(key).is_some() && lanes[0].should_retire(config) {
+            self.rotate(key);
+            return self.get(key);
+        }
 
-            // Increment request count
-            cached.increment_requests();
+        // Retire circuits past their age or request limit
+        let lanes = self.circuits.get_mut(key_str)?;
+        lanes.retain(|cached| {
+            let retire = cached.should_retire(config);
+            if retire {
+                log::info!(
+                    "  ♻️ Retiring old circuit {} for '{}'",
+                    cached.circuit_id,
+                    key_str
+                );
+            }
+            !retire
+        });
+        if lanes.is_empty() {
+            self.remove(key);
+            return None;
+        }
 
+        let can_spill = lanes.len() < config.max_circuits_per_key;
+        let cached = lanes
+            .iter_mut()
+            .filter(|c| c.circuit.try_borrow().is_ok())
+            .min_by_key(|c| c.active_streams())?;
+        if can_spill && cached.active_streams() >= config.max_streams_per_circuit {
             log::info!(
-                "  ✅ Reusing circuit for '{}' (request #{})",
+                "  🔀 Circuits for '{}' are at {} streams; spilling over",
                 key_str,
-                cached.request_count
+                config.max_streams_per_circuit
             );
-
-            return Some(Rc::clone(&cached.circuit));
+            return None;
         }
 
-        None
+        // Increment request count
+        cached.increment_requests();
+
+        log::info!(
+            "  ✅ Reusing circuit {} for '{}' (request #{})",
+            cached.circuit_id,
+            key_str,
+            cached.request_count
+        );
+
+        Some(Rc::clone(&cached.circuit))
     }
 
     /// Store a circuit for the given isolation key
+    ///
+    /// A key that already has circuits gets this one alongside them as a
+    /// spillover circuit.
     pub fn store(&mut self, key: IsolationKey, circuit: Circuit) -> Rc<RefCell<Circuit>> {
         let key_str = key.as_str().to_string();
 
         // Evict old circuits if at capacity
-        while self.circuits.len() >= self.config.max_cached_circuits {
-            self.evict_oldest();
+        if !self.circuits.contains_key(&key_str) {
+            while self.circuits.len() >= self.config.max_cached_circuits {
+                self.evict_oldest();
+            }
+            self.insertion_order.push(key_str.clone());
         }
 
         // Store the circuit
         let cached = CachedCircuit::new(circuit, key.clone());
         let circuit_rc = Rc::clone(&cached.circuit);
 
-        self.circuits.insert(key_str.clone(), cached);
-        self.insertion_order.push(key_str.clone());
+        let lanes = self.circuits.entry(key_str.clone()).or_default();
+        lanes.push(cached);
 
         log::info!(
-            "  📦 Cached circuit for '{}' (total: {})",
+            "  📦 Cached circuit for '{}' ({} for this key, total: {})",
             key_str,
-            self.circuits.len()
+            lanes.len(),
+            self.len()
         );
 
         circuit_rc
     }
 
-    /// Remove a circuit by isolation key
+    /// Remove every circuit cached under an isolation key
     pub fn remove(&mut self, key: &IsolationKey) {
         let key_str = key.as_str();
         self.circuits.remove(key_str);
+        self.standby.remove(key_str);
         self.insertion_order.retain(|k| k != key_str);
     }
 
+    /// Remove one circuit cached under an isolation key, leaving the key's
+    /// others in place
+    pub fn remove_circuit(&mut self, key: &IsolationKey, circuit_id: u32) {
+        if self
+            .standby
+            .get(key.as_str())
+            .is_some_and(|standby| standby.circuit_id == circuit_id)
+        {
+            self.standby.remove(key.as_str());
+            return;
+        }
+        let Some(lanes) = self.circuits.get_mut(key.as_str()) else {
+            return;
+        };
+        lanes.retain(|cached| cached.circuit_id != circuit_id);
+        if lanes.is_empty() {
+            self.remove(key);
+        }
+    }
+
     /// Evict the oldest circuit
     fn evict_oldest(&mut self) {
         if let Some(oldest_key) = self.insertion_order.first().cloned() {
             log::info!("  🗑️ Evicting oldest circuit '{}'", oldest_key);
             self.circuits.remove(&oldest_key);
+            self.standby.remove(&oldest_key);
             self.insertion_order.remove(0);
         }
     }
 
     /// Clear all cached circuits
     pub fn clear(&mut self) {
-        log::info!("  🗑️ Clearing all {} cached circuits", self.circuits.len());
+        log::info!("  🗑️ Clearing all {} cached circuits", self.len());
         self.circuits.clear();
+        self.standby.clear();
         self.insertion_order.clear();
     }
 
-    /// Get the number of cached circuits
+    /// Remove and return all cached circuits (for explicit teardown)
+    pub fn drain(&mut self) -> Vec<Rc<RefCell<Circuit>>> {
+        self.insertion_order.clear();
+        self.circuits
+            .drain()
+            .flat_map(|(_, lanes)| lanes)
+            .chain(self.standby.drain().map(|(_, standby)| standby))
+            .map(|c| c.circuit)
+            .collect()
+    }
+
+    /// Cached circuits with their isolation keys, oldest key first, then
+    /// standby circuits
+    pub fn circuits(&self) -> impl Iterator<Item = (&IsolationKey, &Rc<RefCell<Circuit>>)> {
+        self.insertion_order
+            .iter()
+            .filter_map(|key| self.circuits.get(key))
+            .flatten()
+            .chain(self.standby.values())
+            .map(|cached| (&cached.isolation_key, &cached.circuit))
+    }
+
+    /// Get the number of cached circuits, spillover included
     pub fn len(&self) -> usize {
-        self.circuits.len()
+        self.circuits.values().map(Vec::len).sum()
     }
 
     /// Check if cache is empty
@@ -299,17 +643,32 @@ impl CircuitCache {
 
     /// Get statistics about the cache
     pub fn stats(&self) -> CircuitCacheStats {
-        let total_requests: u32 = self.circuits.values().map(|c| c.request_count).sum();
+        let total_requests: u32 = self
+            .circuits
+            .values()
+            .flatten()
+            .map(|c| c.request_count)
+            .sum();
 
         let oldest_age = self
             .circuits
             .values()
+            .flatten()
             .map(|c| c.created_at.elapsed())
             .max()
             .unwrap_or(Duration::ZERO);
 
+        let cached_circuits = self.len();
         CircuitCacheStats {
-            cached_circuits: self.circuits.len(),
+            cached_circuits,
+            spillover_circuits: cached_circuits - self.circuits.len(),
+            active_streams: self
+                .circuits
+                .values()
+                .flatten()
+                .map(CachedCircuit::active_streams)
+                .sum(),
+            standby_circuits: self.standby.len(),
             total_requests,
             oldest_circuit_age_secs: oldest_age.as_secs(),
             policy: self.config.policy,
@@ -321,6 +680,9 @@ impl CircuitCache {
 #[derive(Debug, Clone)]
 pub struct CircuitCacheStats {
     pub cached_circuits: usize,
+    pub spillover_circuits: usize,
+    pub standby_circuits: usize,
+    pub active_streams: usize,
     pub total_requests: u32,
     pub oldest_circuit_age_secs: u64,
     pub policy: IsolationType,
@@ -348,6 +710,20 @@ fn uuid_v4() -> String {
     )
 }
 
+/// Serialize a `Duration` as whole seconds (as JavaScript callers write it)
+mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -384,6 +760,10 @@ mod tests {
         // All destinations should have same key
         assert_eq!(key1.as_str(), key2.as_str());
         assert_eq!(key1.as_str(), "global");
+
+        // Except onion services, which each need their own circuit
+        let onion = IsolationKey::for_destination("abc.onion", 80, IsolationType::None);
+        assert_ne!(onion.as_str(), "global");
     }
 
     #[test]
@@ -394,4 +774,172 @@ mod tests {
         // Each request should have unique key
         assert_ne!(key1.as_str(), key2.as_str());
     }
+
+    #[test]
+    fn test_destinations_stay_out_of_reserved_namespace() {
+        let directory = IsolationKey::directory();
+        assert!(directory.is_reserved());
+
+        for policy in [
+            IsolationType::PerDomain,
+            IsolationType::PerDestination,
+            IsolationType::PerRequest,
+            IsolationType::None,
+        ] {
+            for host in ["example.com", "#directory", "#DIRECTORY."] {
+                let key = IsolationKey::for_destination(host, 80, policy);
+                assert!(!key.is_reserved(), "{:?} {}", policy, host);
+                assert_ne!(key, directory);
+            }
+        }
+    }
+
+    fn circuit(id: u32) -> Circuit {
+        use crate::protocol::CircuitKeys;
+
+        Circuit::new(
+            id,
+            vec![],
+            CircuitKeys {
+                forward_key: [1u8; 16],
+                backward_key: [2u8; 16],
+                forward_iv: [3u8; 16],
+                backward_iv: [4u8; 16],
+                forward_digest: [5u8; 20],
+                backward_digest: [6u8; 20],
+            },
+        )
+    }
+
+    #[test]
+    fn test_busy_key_spills_over_onto_another_circuit() {
+        let mut cache = CircuitCache::new(IsolationConfig {
+            max_streams_per_circuit: 2,
+            max_circuits_per_key: 2,
+            ..Default::default()
+        });
+        let key = cache.isolation_key("example.com", 443);
+        let first = cache.store(key.clone(), circuit(1));
+        // Two streams hold the first circuit
+        let streams = vec![Rc::clone(&first), Rc::clone(&first)];
+        drop(first);
+
+        assert!(cache.get(&key).is_none());
+        let second = cache.store(key.clone(), circuit(2));
+        assert_eq!(second.borrow().id, 2);
+        drop(second);
+
+        // New streams go to the idle spillover circuit
+        let next = cache.get(&key).expect("spillover circuit");
+        assert_eq!(next.borrow().id, 2);
+        let stats = cache.stats();
+        assert_eq!(stats.cached_circuits, 2);
+        assert_eq!(stats.spillover_circuits, 1);
+        assert_eq!(stats.active_streams, 3);
+
+        // At the per-key cap, the least busy circuit is shared even though
+        // it is at the stream limit
+        let more = [Rc::clone(&next), Rc::clone(&next)];
+        assert_eq!(cache.get(&key).expect("shared circuit").borrow().id, 1);
+        drop(streams);
+        drop(more);
+        drop(next);
+        assert_eq!(cache.get(&key).expect("idle circuit").borrow().id, 1);
+
+        cache.remove_circuit(&key, 1);
+        assert_eq!(cache.len(), 1);
+        cache.remove(&key);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_circuit_mid_probe_is_passed_over() {
+        let mut cache = CircuitCache::new(IsolationConfig::default());
+        let key = cache.isolation_key("example.com", 443);
+        let probed = cache.store(key.clone(), circuit(1));
+
+        // A keepalive probe holds the circuit across its send
+        let borrow = probed.borrow_mut();
+        assert!(cache.get(&key).is_none());
+        drop(borrow);
+        assert_eq!(cache.get(&key).expect("probed circuit").borrow().id, 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_rotation_hands_over_to_standby() {
+        let mut cache = CircuitCache::new(IsolationConfig::default());
+        cache
+            .set_rotation_policy(
+                "API.example.com.",
+                RotationPolicy {
+                    every_requests: Some(5),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(cache
+            .set_rotation_policy("other.com", RotationPolicy::default())
+            .is_err());
+
+        let key = cache.isolation_key("api.example.com", 443);
+        let other = cache.isolation_key("other.com", 443);
+        drop(cache.store(key.clone(), circuit(1)));
+        drop(cache.store(other.clone(), circuit(2)));
+
+        for _ in 0..3 {
+            assert_eq!(cache.get(&key).unwrap().borrow().id, 1);
+        }
+        assert!(cache.due_replacements().is_empty());
+        cache.get(&key);
+        assert_eq!(cache.due_replacements(), vec![key.clone()]);
+
+        assert!(cache.store_standby(other.clone(), circuit(9)));
+        assert!(cache.store_standby(key.clone(), circuit(3)));
+        assert!(cache.due_replacements().is_empty());
+        assert_eq!(cache.stats().standby_circuits, 2);
+
+        // A stream still on the old circuit keeps it
+        let open_stream = cache.get(&key).unwrap();
+        assert_eq!(open_stream.borrow().id, 1);
+        assert_eq!(cache.get(&key).unwrap().borrow().id, 3);
+        assert_eq!(open_stream.borrow().id, 1);
+
+        // Without a standby, rotation leaves the key to be rebuilt
+        assert!(cache.rotate(&key));
+        assert!(cache.get(&key).is_none());
+        assert!(!cache.store_standby(key.clone(), circuit(4)));
+
+        assert!(cache.clear_rotation_policy("api.example.com"));
+        assert_eq!(cache.stats().standby_circuits, 0);
+    }
+
+    #[test]
+    fn test_rotation_policy_matches_destination_keys() {
+        let mut config = IsolationConfig {
+            policy: IsolationType::PerDestination,
+            ..Default::default()
+        };
+        config.rotation.insert(
+            "example.com".into(),
+            RotationPolicy {
+                every_secs: Some(60),
+                ..Default::default()
+            },
+        );
+        assert!(config.validate().is_ok());
+        let key = IsolationKey::for_destination("example.com", 8443, config.policy);
+        assert!(config.rotation_for(&key).is_some());
+        let key = IsolationKey::for_destination("example.org", 443, config.policy);
+        assert!(config.rotation_for(&key).is_none());
+
+        config.rotation.insert(
+            "bad.com".into(),
+            RotationPolicy {
+                every_secs: Some(0),
+                ..Default::default()
+            },
+        );
+        assert!(config.validate().is_err());
+    }
 }
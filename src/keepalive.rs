@@ -0,0 +1,314 @@
+//! Keepalive probes for idle cached circuits
+//!
+//! A circuit can die without anyone telling us: the guard drops the link,
+//! or a relay further along closes the circuit and its DESTROY is lost with
+//! the link. A cached circuit in that state is only discovered when a user
+//! request stalls on it. To find out sooner, circuits left idle for
+//! [`KeepaliveConfig::idle_after_ms`] are sent a RELAY_DROP addressed to the
+//! exit, which relays discard without replying.
+//!
+//! DROP gets no answer, so the probe judges the circuit by the traffic
+//! around it: the send must complete within `send_timeout_ms` (a stalled or
+//! closed link fails here), and nothing on the link during the following
+//! `listen_ms` may say the circuit is gone (a DESTROY from a relay that no
+//! longer knows it, or EOF). Circuits that fail are evicted from the cache.
+//! The reported latency is the time the send took, which tracks link
+//! backpressure rather than a true round trip.
+
+use crate::isolation::{IsolationKey, SharedCircuitCache};
+use crate::protocol::{Circuit, RelayCell, RelayCommand};
+use crate::runtime::timer::{system_clock, SharedClock};
+use crate::runtime::LocalCell;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Keepalive timing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeepaliveConfig {
+    /// How often the client looks for idle circuits in the background
+    /// (default: 30s)
+    pub probe_interval_ms: u64,
+    /// Idle time after which a cached circuit is probed (default: 60s)
+    pub idle_after_ms: u64,
+    /// Longest a DROP send may take before the circuit is considered dead
+    /// (default: 5s)
+    pub send_timeout_ms: u32,
+    /// How long to watch the link after sending (default: 1.5s)
+    pub listen_ms: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval_ms: 30_000,
+            idle_after_ms: 60_000,
+            send_timeout_ms: 5_000,
+            listen_ms: 1_500,
+        }
+    }
+}
+
+/// Result of probing one circuit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    /// The DROP went out and the circuit showed no sign of dying
+    Alive { send_ms: u64 },
+    /// The circuit is gone and should be evicted
+    Dead { reason: String },
+}
+
+/// Keepalive counters
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeepaliveStats {
+    pub probes_sent: u64,
+    pub circuits_evicted: u64,
+    /// Send latency of the most recent successful probe
+    pub last_send_ms: Option<u64>,
+    /// Highest send latency seen
+    pub max_send_ms: u64,
+}
+
+/// Keepalive state shared between the client and its background probe task
+pub type SharedKeepalive = Rc<LocalCell<KeepaliveMonitor>>;
+
+/// Create a shared monitor with `config`
+pub fn new_shared_keepalive(config: KeepaliveConfig) -> SharedKeepalive {
+    Rc::new(LocalCell::new(KeepaliveMonitor::new(config)))
+}
+
+/// Tracks when each cached circuit was last active
+#[derive(Debug)]
+pub struct KeepaliveMonitor {
+    config: KeepaliveConfig,
+    /// Isolation key -> last use or successful probe (ms)
+    last_active: HashMap<String, u64>,
+    stats: KeepaliveStats,
+    clock: SharedClock,
+}
+
+impl Default for KeepaliveMonitor {
+    fn default() -> Self {
+        Self::new(KeepaliveConfig::default())
+    }
+}
+
+impl KeepaliveMonitor {
+    pub fn new(config: KeepaliveConfig) -> Self {
+        Self::with_clock(config, system_clock())
+    }
+
+    /// Create a monitor reading time from `clock`
+    pub fn with_clock(config: KeepaliveConfig, clock: SharedClock) -> Self {
+        Self {
+            config,
+            last_active: HashMap::new(),
+            stats: KeepaliveStats::default(),
+            clock,
+        }
+    }
+
+    pub fn config(&self) -> &KeepaliveConfig {
+        &self.config
+    }
+
+    /// Use new timings for the next probe; idle tracking is kept
+    pub fn set_config(&mut self, config: KeepaliveConfig) {
+        self.config = config;
+    }
+
+    /// Note that the circuit under `key` was just used
+    pub fn touch(&mut self, key: &str) {
+        self.last_active
+            .insert(key.to_string(), self.clock.now_ms());
+    }
+
+    /// Keys among `cached` whose circuits are due a probe
+    ///
+    /// Keys not seen before start their idle period now; keys no longer
+    /// cached are forgotten.
+    pub fn due<'a>(&mut self, cached: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let now = self.clock.now_ms();
+        let mut seen = HashMap::new();
+        for key in cached {
+            let last = self.last_active.get(key).copied().unwrap_or(now);
+            seen.insert(key.to_string(), last);
+        }
+        self.last_active = seen;
+
+        let mut due: Vec<String> = self
+            .last_active
+            .iter()
+            .filter(|(_, last)| now.saturating_sub(**last) >= self.config.idle_after_ms)
+            .map(|(key, _)| key.clone())
+            .collect();
+        due.sort();
+        due
+    }
+
+    /// Account for a probe of the circuit under `key`
+    pub fn record(&mut self, key: &str, outcome: &ProbeOutcome) {
+        self.stats.probes_sent += 1;
+        match outcome {
+            ProbeOutcome::Alive { send_ms } => {
+                self.stats.last_send_ms = Some(*send_ms);
+                self.stats.max_send_ms = self.stats.max_send_ms.max(*send_ms);
+                self.touch(key);
+            }
+            ProbeOutcome::Dead { reason } => {
+                log::warn!("💤 Evicting dead circuit for '{}': {}", key, reason);
+                self.stats.circuits_evicted += 1;
+                self.last_active.remove(key);
+            }
+        }
+    }
+
+    /// Forget every circuit (cache cleared)
+    pub fn clear(&mut self) {
+        self.last_active.clear();
+    }
+
+    pub fn stats(&self) -> KeepaliveStats {
+        self.stats.clone()
+    }
+}
+
+/// Probe every cached circuit that is due, evicting the dead ones from
+/// `cache`; returns how many were probed and how many evicted
+///
+/// Circuits with a stream open, or borrowed by a request, are skipped.
+// The circuit stays borrowed across its probe; `CircuitCache::get` passes
+// over it meanwhile
+#[allow(clippy::await_holding_refcell_ref)]
+pub async fn probe_idle(cache: &SharedCircuitCache, monitor: &SharedKeepalive) -> (usize, usize) {
+    let cached: Vec<(IsolationKey, Rc<RefCell<Circuit>>)> = cache.with(|c| {
+        c.circuits()
+            .map(|(key, circuit)| (key.clone(), Rc::clone(circuit)))
+            .collect()
+    });
+    let (due, config) = monitor.with(|m| {
+        let due = m.due(cached.iter().map(|(key, _)| key.as_str()));
+        (due, m.config().clone())
+    });
+
+    let (mut probed, mut evicted) = (0, 0);
+    for (key, circuit) in cached {
+        // One reference is the cache's, one ours; more means a stream
+        if !due.iter().any(|k| k == key.as_str()) || Rc::strong_count(&circuit) > 2 {
+            continue;
+        }
+        let Ok(mut borrowed) = circuit.try_borrow_mut() else {
+            continue;
+        };
+        let outcome = probe(&mut borrowed, &config).await;
+        let circuit_id = borrowed.id;
+        drop(borrowed);
+
+        probed += 1;
+        monitor.with(|m| m.record(key.as_str(), &outcome));
+        if let ProbeOutcome::Dead { .. } = outcome {
+            cache.with(|c| c.remove_circuit(&key, circuit_id));
+            evicted += 1;
+        }
+    }
+    (probed, evicted)
+}
+
+/// Send a DROP to the circuit's exit and watch the link for signs of death
+pub async fn probe(circuit: &mut Circuit, config: &KeepaliveConfig) -> ProbeOutcome {
+    if !circuit.is_connected() {
+        return ProbeOutcome::Dead {
+            reason: "link closed".into(),
+        };
+    }
+
+    let started = crate::runtime::timer::now_ms();
+    let drop_cell = RelayCell::new(RelayCommand::Drop, 0, Vec::new());
+    let sent = futures::select_biased! {
+        result = circuit.send_relay_cell(&drop_cell).fuse() => Some(result),
+        _ = gloo_timers::future::TimeoutFuture::new(config.send_timeout_ms).fuse() => None,
+    };
+    let send_ms = crate::runtime::timer::now_ms().saturating_sub(started);
+    match sent {
+        None => {
+            return ProbeOutcome::Dead {
+                reason: format!("DROP not sent within {}ms", config.send_timeout_ms),
+            }
+        }
+        Some(Err(e)) => {
+            return ProbeOutcome::Dead {
+                reason: format!("DROP send failed: {}", e),
+            }
+        }
+        Some(Ok(())) => {}
+    }
+
+    gloo_timers::future::TimeoutFuture::new(config.listen_ms).await;
+    match circuit.try_receive_relay_cell().await {
+        Err(e) => ProbeOutcome::Dead {
+            reason: e.to_string(),
+        },
+        Ok(Some(cell)) => {
+            // Nothing should arrive on an idle circuit, but traffic means it lives
+            log::debug!("💤 Unexpected {:?} on idle circuit", cell.command);
+            ProbeOutcome::Alive { send_ms }
+        }
+        Ok(None) => ProbeOutcome::Alive { send_ms },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::timer::MockClock;
+
+    fn monitor(clock: &MockClock) -> KeepaliveMonitor {
+        KeepaliveMonitor::with_clock(KeepaliveConfig::default(), Rc::new(clock.clone()))
+    }
+
+    #[test]
+    fn test_idle_circuits_come_due() {
+        let clock = MockClock::new(1_000_000);
+        let mut monitor = monitor(&clock);
+
+        // First sighting starts the idle period
+        assert!(monitor.due(["a.com", "b.com"]).is_empty());
+        clock.advance(30_000);
+        monitor.touch("b.com");
+        clock.advance(30_000);
+        assert_eq!(monitor.due(["a.com", "b.com"]), vec!["a.com"]);
+        clock.advance(30_000);
+        assert_eq!(monitor.due(["a.com", "b.com"]), vec!["a.com", "b.com"]);
+
+        // Evicted from the cache: forgotten
+        assert_eq!(monitor.due(["b.com"]), vec!["b.com"]);
+        assert_eq!(monitor.due(["a.com", "b.com"]), vec!["b.com"]);
+    }
+
+    #[test]
+    fn test_probe_outcomes_update_state() {
+        let clock = MockClock::new(1_000_000);
+        let mut monitor = monitor(&clock);
+        monitor.due(["a.com", "b.com"]);
+        clock.advance(60_000);
+
+        monitor.record("a.com", &ProbeOutcome::Alive { send_ms: 40 });
+        monitor.record(
+            "b.com",
+            &ProbeOutcome::Dead {
+                reason: "Circuit destroyed by relay (reason: 0)".into(),
+            },
+        );
+        // A live probe restarts the idle period
+        assert_eq!(monitor.due(["a.com", "b.com"]), Vec::<String>::new());
+
+        let stats = monitor.stats();
+        assert_eq!(stats.probes_sent, 2);
+        assert_eq!(stats.circuits_evicted, 1);
+        assert_eq!(stats.last_send_ms, Some(40));
+        assert_eq!(stats.max_send_ms, 40);
+    }
+}
@@ -24,87 +24,194 @@
 //! - **Fast**: Compiled Rust, near-native performance
 //! - **Secure**: Uses WebCrypto for all cryptographic operations
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
 // Modules
+pub mod allocator;
+#[cfg(feature = "arti")]
+mod arti_impls;
+pub mod bandwidth_quota;
+pub mod blocklist;
+pub mod bridge_distributor;
+pub mod bridge_test;
+pub mod cancel;
 mod circuit;
+pub mod circuit_failures;
 pub mod circuit_pool;
+pub mod client_config;
+pub mod client_manager;
+pub mod clock_skew;
 pub mod congestion;
+pub mod connect_proxy;
 pub mod connection_pool;
+pub mod control;
 pub mod cooperative;
+pub mod crypto_worker;
+pub mod diagnostics;
+pub mod dns_cache;
+pub mod dormant;
 mod error;
+pub mod events;
 pub mod fingerprint_defense;
 pub mod guards;
+pub mod handshake_pool;
+pub mod http_client;
+pub mod http_padding;
+pub mod http_policy;
+pub mod http_request;
+pub mod integrity;
+#[cfg(feature = "test-interop")]
+pub mod interop;
 pub mod isolation;
+pub mod keepalive;
+pub mod log_ring;
 pub mod lox_client;
+pub mod memory;
+pub mod metrics;
 pub mod network;
+pub mod onion_service;
+pub mod opfs_download;
+pub mod origin_hints;
 pub mod padding;
 pub mod parallel_builder;
+pub mod path_audit;
+pub mod path_explain;
 pub mod protocol;
 pub mod rate_limiter;
+pub mod relay_search;
 pub mod relay_verifier;
+#[cfg(feature = "research")]
+pub mod research;
+pub mod response;
+pub mod resume;
 pub mod runtime;
+pub mod security_posture;
+pub mod sse;
+pub mod standalone;
 pub mod storage;
+pub mod stream_handle;
 pub mod stream_mux;
+pub mod tor_websocket;
 pub mod traffic_shaping;
 pub mod transport;
-// mod arti_impls; // Temporarily disabled until arti dependencies are WASM-ready
+pub mod upload;
 
 // Security tests (only compiled in test mode)
 #[cfg(test)]
 mod security_tests;
 
-pub use circuit_pool::{CircuitPoolConfig, CircuitPoolStats, PrebuiltCircuitPool};
+pub use bandwidth_quota::{
+    BandwidthQuota, BandwidthQuotaConfig, QuotaAction, QuotaEvent, QuotaLevel, QuotaScope,
+    QuotaUsage,
+};
+pub use blocklist::{
+    new_shared_blocklist, Blocklist, BlocklistConfig, BlocklistStatus, BlocklistUpdater, IpRange,
+    SharedBlocklist,
+};
+pub use bridge_distributor::{
+    BridgeChallenge, BridgeDistributor, DistributedBridge, StoredBridges,
+};
+pub use bridge_test::{BridgeTestConfig, BridgeTestReport, BridgeTestStage};
+pub use cancel::CancelToken;
+pub use circuit_failures::{BuildStage, CircuitFailureReport, FailureCause};
+pub use circuit_pool::{
+    CircuitPoolConfig, CircuitPoolStats, CircuitTarget, PortClass, PrebuiltCircuitPool,
+};
+pub use client_config::{
+    ConfigChange, GuardConfig, LoggingConfig, StorageConfig, TorClientConfig,
+    TorClientConfigBuilder,
+};
+pub use client_manager::TorClientManager;
+pub use clock_skew::{ClockSkewReport, ClockSkewWarning, SkewSource};
 pub use congestion::{
     CongestionAlgorithm, CongestionController, CongestionStats, RttEstimator, RttSample, RttStats,
 };
+pub use connect_proxy::ProxyTunnel;
 pub use connection_pool::{
     ConnectionPool, ConnectionPoolConfig, ConnectionPoolStats, PooledConnection,
 };
+pub use control::{CircuitStatus, ControlCommand, ControlReply};
 pub use cooperative::{
     drive_scheduler, drive_until_complete, open_cooperative_stream, CooperativeCircuit,
     CooperativeStream, CooperativeTlsStream, PendingWork, SchedulerDriver, SchedulerError,
-    SchedulerStats, StreamHandle, WorkResult, DEFAULT_RECEIVE_TIMEOUT_MS, DEFAULT_SEND_TIMEOUT_MS,
-    MAX_CELLS_PER_STREAM, MAX_INCOMING_BUFFER, MAX_STREAMS_PER_CIRCUIT, MAX_TOTAL_QUEUED_CELLS,
+    SchedulerEvent, SchedulerStats, StreamHandle, WorkResult, DEFAULT_RECEIVE_TIMEOUT_MS,
+    DEFAULT_SEND_TIMEOUT_MS, DEFAULT_STREAM_IDLE_TIMEOUT_MS, MAX_CELLS_PER_STREAM,
+    MAX_INCOMING_BUFFER, MAX_STREAMS_PER_CIRCUIT, MAX_TOTAL_QUEUED_CELLS,
 };
+pub use dns_cache::{DnsCache, DnsCacheStats};
+pub use dormant::{Dormancy, DormantStatus, WakeReason};
 pub use error::{Result, TorError};
+pub use events::{EventBus, EventType, TorEvent};
 pub use guards::{
-    FailureInfo, GuardPersistence, GuardState, GUARD_LIFETIME_SECS, MAX_GUARDS, MIN_GUARDS,
+    new_shared_guard_state, FailureInfo, GuardPersistence, GuardState, SharedGuardState,
+    GUARD_LIFETIME_SECS, GUARD_STATE_KEY, MAX_GUARDS, MIN_GUARDS,
 };
+pub use http_client::HttpClientConfig;
+pub use http_padding::{HttpPaddingConfig, HttpPaddingPolicy};
+pub use http_policy::{HttpPlan, HttpSecurityConfig};
+pub use http_request::{HttpAuth, HttpRequest};
+pub use integrity::{FetchOptions, Integrity};
 pub use isolation::{
-    CircuitCache, CircuitCacheStats, IsolationConfig, IsolationKey, IsolationType,
+    CircuitCache, CircuitCacheStats, IsolationConfig, IsolationKey, IsolationType, RotationPolicy,
+    SharedCircuitCache,
+};
+pub use keepalive::{
+    KeepaliveConfig, KeepaliveMonitor, KeepaliveStats, ProbeOutcome, SharedKeepalive,
 };
+pub use metrics::{LatencyHistogram, LatencyMetrics, LatencyReport, LatencySummary};
 pub use network::{
     ConnectionManager, NetworkConfig, NetworkStats, WasmTcpProvider, WasmTlsConnector,
 };
+pub use onion_service::OnionServiceClient;
+pub use opfs_download::{FileSink, OpfsFile};
+pub use origin_hints::{AltService, OriginHints};
 pub use padding::{PaddingCommand, PaddingConfig, PaddingScheduler, PaddingState, PaddingStats};
 pub use parallel_builder::{ParallelBuilderConfig, ParallelBuilderStats, ParallelCircuitBuilder};
+pub use path_audit::{PathAuditReport, RelayShare, RoleDistribution};
+pub use path_explain::{HopExplanation, PassedOver, PassedOverLog, SelectionExplanation};
 pub use rate_limiter::{RateLimiter, RateLimiterConfig, RateLimiterStats};
-pub use relay_verifier::{BandwidthObservation, RelayVerifier, RelayVerifierStats, VerifyError};
-pub use runtime::WasmRuntime;
+pub use relay_search::{RelaySearchPage, RelaySearchQuery, RelaySummary};
+pub use relay_verifier::{
+    AnomalyRecord, BandwidthObservation, BannedExit, ExitAnomaly, RelayVerifier,
+    RelayVerifierStats, VerifyError,
+};
+pub use response::HttpResponse;
+pub use resume::ResumableDownload;
+pub use runtime::{TaskEvent, TaskEventKind, TaskSupervisor, WasmRuntime};
+pub use security_posture::{Downgrade, PostureDegraded, PostureReport, SecurityNotice, Severity};
+pub use sse::{SseEvent, SseStream};
 pub use storage::{
     ArtiStateManager, CircuitData, CircuitPool, CircuitState, CircuitStateManager, CircuitStats,
     ClientState, ConsensusData, Guard, GuardManager, GuardSet, RelayData, RelayFlags,
     TorStorageManager, WasmStorage,
 };
+pub use stream_handle::TorStreamHandle;
 pub use stream_mux::{StreamMultiplexer, StreamMuxConfig, StreamMuxStats};
-pub use traffic_shaping::{TrafficShaper, TrafficShapingConfig, TrafficShapingStats};
+pub use tor_websocket::TorWebSocket;
+pub use traffic_shaping::{
+    RequestJitter, RequestJitterConfig, RequestJitterStats, TrafficShaper, TrafficShapingConfig,
+    TrafficShapingStats,
+};
 pub use transport::{BridgeConfig, TransportStream, WasmTcpStream};
+pub use upload::BodySource;
+
+use runtime::timer::now_ms;
 
 /// Parse a URL into (host, port, path, is_https)
 fn parse_url(url: &str) -> std::result::Result<(String, u16, String, bool), String> {
     // Simple URL parser for http:// and https:// URLs
     let url = url.trim();
 
-    // Detect scheme
-    let (without_scheme, is_https) = if url.starts_with("http://") {
-        (&url[7..], false)
-    } else if url.starts_with("https://") {
-        (&url[8..], true)
-    } else {
+    // Detect scheme (case-insensitively)
+    let (without_scheme, is_https) = match url.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (rest, false),
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (rest, true),
         // Assume HTTP if no scheme
-        (url, false)
+        _ => (url, false),
     };
 
     // Split host/path
@@ -114,8 +221,12 @@ fn parse_url(url: &str) -> std::result::Result<(String, u16, String, bool), Stri
         (without_scheme, "/")
     };
 
-    // Split host:port
-    let (host, port) = if let Some(colon_pos) = host_port.rfind(':') {
+    // Split host:port (an IPv6 literal's colons sit inside brackets)
+    let port_colon = match host_port.rfind(']') {
+        Some(bracket) => host_port[bracket..].find(':').map(|i| bracket + i),
+        None => host_port.rfind(':'),
+    };
+    let (host, port) = if let Some(colon_pos) = port_colon {
         let host = &host_port[..colon_pos];
         let port_str = &host_port[colon_pos + 1..];
         let port = port_str
@@ -131,6 +242,41 @@ fn parse_url(url: &str) -> std::result::Result<(String, u16, String, bool), Stri
     Ok((host, port, path.to_string(), is_https))
 }
 
+/// Parse the optional `options` argument of the GET fetch methods
+fn parse_fetch_options(options: JsValue) -> std::result::Result<FetchOptions, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(FetchOptions::default());
+    }
+    let options: FetchOptions = serde_wasm_bindgen::from_value(options)
+        .map_err(|e| JsValue::from_str(&format!("Invalid fetch options: {}", e)))?;
+    if let Some(auth) = &options.auth {
+        auth.validate()?;
+    }
+    Ok(options)
+}
+
+/// `{ status, headers: [[name, value], ...], body: Uint8Array }` for JS
+fn response_object(response: &HttpResponse) -> std::result::Result<JsValue, JsValue> {
+    let value = serde_wasm_bindgen::to_value(&serde_json::json!({
+        "status": response.status,
+        "headers": response.headers,
+    }))
+    .unwrap_or(JsValue::NULL);
+    let body = js_sys::Uint8Array::from(response.body.as_slice());
+    js_sys::Reflect::set(&value, &JsValue::from_str("body"), &body)?;
+    Ok(value)
+}
+
+/// Fingerprint of a circuit's last hop
+fn exit_fingerprint(circuit: &protocol::Circuit) -> Option<String> {
+    // The last relay of an onion service circuit is the rendezvous point,
+    // which only relays end-to-end encrypted cells
+    if circuit.has_service_hop() {
+        return None;
+    }
+    circuit.relays.last().map(|relay| relay.fingerprint.clone())
+}
+
 /// Initialize the Tor WASM client
 ///
 /// This sets up logging and any global state needed.
@@ -140,12 +286,137 @@ pub fn init() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 
-    // Initialize logging
-    console_log::init_with_level(log::Level::Info).unwrap();
+    // Initialize logging (console plus the in-memory ring for diagnostics)
+    log_ring::init_with_level(log::Level::Info).unwrap();
 
     log::info!("Tor WASM client initialized");
 }
 
+/// Get the most recent log records, oldest first
+///
+/// Records are `{ level, module, timestamp_ms, message }`, kept in a bounded
+/// in-memory ring alongside console output, so they are available without
+/// capturing the devtools console. `min_level` (`"error"`, `"warn"`,
+/// `"info"`, `"debug"`, `"trace"`) keeps only records at least that severe.
+/// Messages are not redacted; use `TorClient::export_diagnostics()` for
+/// something to share.
+#[wasm_bindgen]
+pub fn get_recent_logs(n: u32, min_level: Option<String>) -> std::result::Result<JsValue, JsValue> {
+    let level = match min_level {
+        Some(level) => level
+            .parse::<log::Level>()
+            .map_err(|_| JsValue::from_str(&format!("Unknown log level: {}", level)))?,
+        None => log::Level::Trace,
+    };
+    let records = log_ring::recent_logs_at_level(n as usize, level);
+    Ok(serde_wasm_bindgen::to_value(&records).unwrap_or(JsValue::NULL))
+}
+
+/// Set how many log records the in-memory ring keeps (default 500, at most
+/// 10000; 0 disables it)
+#[wasm_bindgen]
+pub fn set_log_capacity(capacity: u32) {
+    log_ring::set_log_capacity(capacity as usize);
+}
+
+/// Log byte-level protocol dumps (cell headers, digests, handshake layout)
+///
+/// Off by default. Key material is never logged unless the crate was built
+/// in debug mode with the `debug-key-material` feature.
+#[wasm_bindgen]
+pub fn set_debug_protocol(enabled: bool) {
+    protocol::debug::set_debug_protocol(enabled);
+}
+
+/// Record why each relay was chosen in circuit build reports
+///
+/// Off by default. While on, each build report in `export_diagnostics()`
+/// carries an `explanation`: per hop, the relay's flags, bandwidth weight
+/// among the eligible relays, how many were eligible and why the others
+/// were not, plus the relays the build passed over and why.
+#[wasm_bindgen]
+pub fn set_explain_paths(enabled: bool) {
+    protocol::debug::set_explain_paths(enabled);
+}
+
+/// Capture relay cell headers into an in-memory trace
+///
+/// Off by default. While on, every relay cell sent or recognized on any
+/// circuit is recorded (headers only, never payloads) into a ring of
+/// `capacity` entries (default 1000, at most 50000). Turning capture off
+/// keeps what was captured; read it with `export_cell_trace()`.
+#[wasm_bindgen]
+pub fn set_cell_trace(enabled: bool, capacity: Option<u32>) {
+    let capacity = match (enabled, capacity) {
+        (false, _) => 0,
+        (true, Some(capacity)) => capacity as usize,
+        (true, None) => protocol::trace::DEFAULT_TRACE_CAPACITY,
+    };
+    protocol::trace::set_trace_capacity(capacity);
+}
+
+/// Captured relay cell headers, oldest first
+///
+/// Returns `{ capturing, dropped, cells }`, where each cell is
+/// `{ timestamp_ms, circuit_id, direction, hop, command, stream_id,
+/// recognized, digest, digest_ok, length }`: `direction` is `"sent"` or
+/// `"received"`, `hop` counts from the guard (0), `digest` is hex and
+/// `digest_ok` is null where the digest wasn't checked. `dropped` counts
+/// cells pushed out of the ring. `clear` empties the trace afterwards.
+#[wasm_bindgen]
+pub fn export_cell_trace(clear: Option<bool>) -> JsValue {
+    let (cells, dropped) = protocol::trace::trace_entries();
+    if clear.unwrap_or(false) {
+        protocol::trace::clear_trace();
+    }
+    serde_wasm_bindgen::to_value(&serde_json::json!({
+        "capturing": protocol::trace::tracing(),
+        "dropped": dropped,
+        "cells": cells,
+    }))
+    .unwrap_or(JsValue::NULL)
+}
+
+/// Set a memory budget in bytes (0 = none)
+///
+/// Only enforced in builds with the `memory-tracking` feature: consensus
+/// parsing, response buffering and storage serialization then fail with a
+/// "Resource exhausted" error instead of growing past the budget.
+#[wasm_bindgen]
+pub fn set_memory_budget(bytes: u32) {
+    memory::set_budget(bytes as usize);
+}
+
+/// Error returned by every `TorClient` method after `shutdown()`
+const CLIENT_SHUT_DOWN: &str = "Client has been shut down";
+
+/// What one cooperative exchange revealed about its exit
+#[derive(Debug, Default)]
+struct ExchangeReport {
+    /// Hex SHA-256 of the server's leaf certificate (HTTPS only)
+    certificate: Option<String>,
+    /// RELAY_END reason the exit closed the stream with
+    end_reason: Option<u8>,
+    /// Address the exit reported in RELAY_CONNECTED
+    connected_address: Option<protocol::DnsAnswer>,
+}
+
+/// How the most recent stream was served, for `last_response()`
+#[derive(Debug, Clone, serde::Serialize)]
+struct ResponseMetadata {
+    host: String,
+    port: u16,
+    /// Fingerprint of the exit
+    exit: Option<String>,
+    /// Address and TTL the exit reported in RELAY_CONNECTED
+    exit_resolved_address: Option<protocol::DnsAnswer>,
+}
+
+/// Circuits built through user-chosen relays, by circuit ID; shared with
+/// the stream handles that borrow them so they can hand them back
+pub(crate) type CustomCircuits =
+    Rc<runtime::LocalCell<HashMap<u32, Rc<RefCell<protocol::Circuit>>>>>;
+
 /// Main Tor client
 #[wasm_bindgen]
 pub struct TorClient {
@@ -158,14 +429,54 @@ pub struct TorClient {
     // Current consensus
     consensus: Option<Arc<protocol::Consensus>>,
 
+    // Health checks of the last consensus fetched by `bootstrap()`
+    consensus_health: Option<protocol::ConsensusHealthReport>,
+
     // Current state
     bootstrapped: bool,
 
     // Circuit cache for isolation
-    circuit_cache: CircuitCache,
+    circuit_cache: SharedCircuitCache,
+
+    // Exit DNS answers, keyed like the circuit cache
+    dns_cache: DnsCache,
+
+    // Onion service descriptors, cleared with the circuits
+    onion_services: OnionServiceClient,
+
+    // Exit and CONNECTED address of the most recent stream
+    last_response: Option<ResponseMetadata>,
+
+    // Idle tracking for keepalive probes of cached circuits
+    keepalive: SharedKeepalive,
+
+    // HTTP-level request padding, per isolation key
+    http_padding: HttpPaddingPolicy,
+
+    // Random delay before requests, per isolation key
+    request_jitter: RequestJitter,
+
+    // End-to-end request latency, per isolation key
+    latency: LatencyMetrics,
+
+    // Circuit build failures by stage / DESTROY reason, shared with the builder
+    build_failures: circuit_failures::SharedFailureStats,
 
-    // Guard node state (persistent across sessions)
-    guard_state: GuardState,
+    // Onion-Location / Alt-Svc seen in HTTPS responses, per origin
+    origin_hints: OriginHints,
+
+    // Family/deny-list checks and relays caught misbehaving
+    relay_verifier: RelayVerifier,
+
+    // Operator blocklist, shared with the circuit builders
+    blocklist: BlocklistUpdater,
+
+    // Guard node state (persistent across sessions), shared with the
+    // circuit builder so it can record guard outcomes
+    guard_state: SharedGuardState,
+
+    // Verified link handshakes per guard, shared with the circuit builders
+    link_cache: protocol::SharedLinkCache,
 
     // Guard persistence manager
     guard_persistence: GuardPersistence,
@@ -176,91 +487,127 @@ pub struct TorClient {
     // Relay selector (cached)
     relay_selector: Option<protocol::RelaySelector>,
 
+    // Minimum bandwidth / flags for selected relays
+    relay_requirements: protocol::RelayRequirements,
+
+    // Whether plain HTTP is allowed or upgraded
+    http_security: HttpSecurityConfig,
+
+    // Redirects and content decoding for fetch_json / fetch_bytes
+    http_client: HttpClientConfig,
+
     // Rate limiter (abuse prevention)
     rate_limiter: RateLimiter,
 
+    // Session and daily byte counts against the configured quotas
+    bandwidth_quota: BandwidthQuota,
+
+    // Called with every `bandwidth_quota` event
+    quota_listener: Option<js_sys::Function>,
+
     // Circuit pool for reuse
     circuit_pool: PrebuiltCircuitPool,
-}
 
-#[wasm_bindgen]
-impl TorClient {
-    /// Create a new Tor client with custom bridge URL
-    #[wasm_bindgen(constructor)]
-    pub async fn new(bridge_url: Option<String>) -> std::result::Result<TorClient, JsValue> {
-        log::info!("Creating new Tor client");
+    // Background tasks (cancelled on drop / new identity)
+    tasks: TaskSupervisor,
 
-        // Initialize storage
-        let storage = Arc::new(
-            WasmStorage::new()
-                .await
-                .map_err(|e| JsValue::from_str(&format!("Storage init failed: {}", e)))?,
-        );
+    // Circuits built through user-chosen relays, by circuit ID
+    custom_circuits: CustomCircuits,
 
-        // Initialize network provider
-        let network_config = if let Some(url) = bridge_url {
-            network::NetworkConfig::with_bridge(url)
-        } else {
-            network::NetworkConfig::default()
-        };
+    // Set once `shutdown()` has run; the client is then permanently unusable
+    shut_down: bool,
 
-        let network = Arc::new(WasmTcpProvider::with_config(network_config));
+    // Dormant mode, shared with the page's visibility / periodic sync hooks
+    dormancy: dormant::Dormancy,
 
-        log::info!("✅ Tor client created");
+    // Guards chosen at each guard selection
+    guard_count: usize,
 
-        // Initialize circuit cache with default isolation (per-domain)
-        let circuit_cache = CircuitCache::new(IsolationConfig::default());
-        log::info!("  🔒 Circuit isolation: {:?}", circuit_cache.policy());
+    // Configuration with changes held back until the next new identity
+    pending_config: Option<TorClientConfig>,
 
-        // Initialize guard persistence
-        let guard_persistence = GuardPersistence::new();
-        let guard_state = match guard_persistence.load().await {
-            Ok(state) => {
-                if state.guards.is_empty() {
-                    log::info!("  🛡️ No saved guards, will select on bootstrap");
-                } else {
-                    log::info!("  🛡️ Loaded {} guards from storage", state.guards.len());
-                }
-                state
-            }
-            Err(e) => {
-                log::warn!("  ⚠️ Failed to load guard state: {}", e);
-                GuardState::new()
-            }
-        };
+    // Called with every `config_changed` event
+    config_listener: Option<js_sys::Function>,
 
-        Ok(Self {
+    // Namespace of this client's persistent state
+    storage_config: StorageConfig,
+}
+
+#[wasm_bindgen]
+impl TorClient {
+    /// Create a new Tor client with custom bridge URL
+    #[wasm_bindgen(constructor)]
+    pub async fn new(bridge_url: Option<String>) -> std::result::Result<TorClient, JsValue> {
+        let network = match bridge_url {
+            Some(url) => network::NetworkConfig::with_bridge(url),
+            None => network::NetworkConfig::default(),
+        };
+        Ok(Self::from_config(TorClientConfig {
             network,
-            storage,
-            consensus: None,
-            bootstrapped: false,
-            circuit_cache,
-            guard_state,
-            guard_persistence,
-            circuit_builder: None,
-            relay_selector: None,
-            rate_limiter: RateLimiter::new(),
-            circuit_pool: PrebuiltCircuitPool::new(),
+            logging: LoggingConfig::current(),
+            ..TorClientConfig::default()
         })
+        .await?)
+    }
+
+    /// Create a Tor client from a complete configuration
+    ///
+    /// `config_json`: `{ network, isolation, circuit_pool, keepalive,
+    /// http_padding, request_jitter, relay_requirements, rate_limit }`, each
+    /// section taking the same fields as the matching setter; omitted
+    /// sections and fields take their defaults. See `get_config()` for the full shape.
+    #[wasm_bindgen]
+    pub async fn with_config(config_json: String) -> std::result::Result<TorClient, JsValue> {
+        let config = TorClientConfig::from_json(&config_json)?;
+        Ok(Self::from_config(config).await?)
     }
 
     /// Bootstrap the Tor client
     ///
-    /// This fetches the network consensus and prepares circuits.
+    /// This fetches the network consensus and prepares circuits. The first
+    /// bootstrap gets the consensus from the bridge; later ones fetch it
+    /// over BEGIN_DIR on a one-hop circuit to a guard, falling back to the
+    /// bridge if that fails.
     #[wasm_bindgen]
     pub async fn bootstrap(&mut self) -> std::result::Result<(), JsValue> {
+        if self.shut_down {
+            return Err(JsValue::from_str(CLIENT_SHUT_DOWN));
+        }
+        self.dormancy.wake(dormant::WakeReason::Request);
+
         log::info!("🔄 Bootstrapping Tor client...");
 
-        // 1. Create directory manager
+        // 1. Learn what the bridge supports
+        match self.network.heartbeat().await {
+            Ok(caps) => log::info!(
+                "🌉 Bridge capabilities: version {}, multiplexing {}, blinded {}",
+                caps.version,
+                caps.multiplexing,
+                caps.blinded
+            ),
+            Err(e) => log::info!(
+                "🌉 Bridge capabilities unavailable ({}); assuming a legacy bridge",
+                e
+            ),
+        }
+
+        // 2. Create directory manager
         let mut dir_mgr =
             protocol::DirectoryManager::new(Arc::clone(&self.network), Arc::clone(&self.storage));
+        let dir_circuit = self.directory_circuit().await;
+        if let Some(circuit) = &dir_circuit {
+            dir_mgr = dir_mgr.with_dir_circuit(Rc::clone(circuit));
+        }
 
-        // 2. Fetch directory consensus
+        // 3. Fetch directory consensus
         log::info!("📡 Fetching directory consensus...");
-        let consensus = dir_mgr
-            .fetch_consensus()
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Consensus fetch failed: {}", e)))?;
+        let fetched = dir_mgr.fetch_consensus().await;
+        drop(dir_mgr);
+        if let Some(Ok(circuit)) = dir_circuit.map(Rc::try_unwrap) {
+            let _ = circuit.into_inner().destroy().await;
+        }
+        let consensus =
+            fetched.map_err(|e| JsValue::from_str(&format!("Consensus fetch failed: {}", e)))?;
 
         log::info!(
             "✅ Fetched consensus with {} relays",
@@ -270,144 +617,120 @@ impl TorClient {
         // Consensus signatures are verified in fetch_from_bridge() before we get here.
         // The verifier checks that 5+ directory authorities signed the raw consensus.
 
-        // Validate relay data looks legitimate
-        let valid_fingerprints = consensus
-            .relays
-            .iter()
-            .filter(|r| {
-                // Check fingerprint is valid hex and correct length
-                r.fingerprint.len() >= 20
-                    && r.fingerprint
-                        .chars()
-                        .all(|c| c.is_ascii_hexdigit() || c == '+' || c == '/' || c == '=')
-            })
-            .count();
-
-        if valid_fingerprints < consensus.relays.len() / 2 {
-            log::error!("❌ Too many invalid relay fingerprints - possible attack!");
-            return Err(JsValue::from_str(
-                "Consensus validation failed: invalid relay fingerprints",
-            ));
-        }
-
-        // Count relay types
-        let guards = consensus.relays.iter().filter(|r| r.is_guard()).count();
-        let exits = consensus.relays.iter().filter(|r| r.is_exit()).count();
-        let running = consensus.relays.iter().filter(|r| r.is_running()).count();
-
+        // Check the consensus as a whole looks like the real network. The
+        // built-in fallback list (and a local test network) is small by
+        // design, so it is only reported on.
+        let health = protocol::ConsensusHealth::default().check(&consensus);
         log::info!("📊 Relay stats:");
-        log::info!("  Total: {}", consensus.relays.len());
-        log::info!("  Valid fingerprints: {}", valid_fingerprints);
-        log::info!("  Running: {}", running);
-        log::info!("  Guards: {}", guards);
-        log::info!("  Exits: {}", exits);
-
-        // Store consensus
-        let consensus_arc = Arc::new(consensus);
-        self.consensus = Some(Arc::clone(&consensus_arc));
-
-        // 3. Update guard selection if needed
-        log::info!("🛡️ Checking guard state...");
-        self.guard_state.cleanup(); // Clean up expired entries
-
-        if self.guard_state.needs_refresh() {
-            log::info!("  🔄 Selecting new guards...");
-            self.guard_state.select_guards(&consensus_arc.relays)?;
-
-            // Save updated guard state
-            if let Err(e) = self.guard_persistence.save(&self.guard_state).await {
-                log::warn!("  ⚠️ Failed to save guard state: {}", e);
+        log::info!("  Total: {}", health.relays);
+        log::info!("  Running: {}", health.running);
+        log::info!("  Guards: {}", health.guards);
+        log::info!("  Exits: {}", health.exits);
+        log::info!("  Bandwidth: {}", health.total_bandwidth);
+        let enforce = !security_posture::is_active(Downgrade::MockConsensus);
+        #[cfg(feature = "test-interop")]
+        let enforce = enforce && !interop::local_network();
+        let failures = health
+            .failures
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ");
+        self.consensus_health = Some(health);
+        if !failures.is_empty() {
+            if enforce {
+                log::error!("❌ Consensus failed health checks: {}", failures);
+                return Err(JsValue::from_str(&format!(
+                    "Consensus validation failed: {}",
+                    failures
+                )));
             }
-        } else {
-            log::info!(
-                "  ✅ Using {} existing guards (valid for {} more days)",
-                self.guard_state.guards.len(),
-                (self.guard_state.rotate_after.saturating_sub(
-                    web_time::SystemTime::now()
-                        .duration_since(web_time::SystemTime::UNIX_EPOCH)
-                        .map(|d| d.as_secs())
-                        .unwrap_or(0)
-                )) / (24 * 60 * 60)
-            );
+            log::warn!("⚠️ Fallback consensus outside healthy bounds: {}", failures);
         }
 
-        // 4. Create relay selector with guard preferences
-        log::info!("🎯 Creating relay selector...");
-        let mut selector = protocol::RelaySelector::new(consensus_arc.relays.clone());
-        selector.set_preferred_guards(
-            self.guard_state
-                .usable_guards()
-                .into_iter()
-                .cloned()
-                .collect(),
-        );
-        self.relay_selector = Some(selector);
-
-        // 5. Create circuit builder
-        log::info!("🔨 Creating circuit builder...");
-        self.circuit_builder = Some(protocol::CircuitBuilder::new(Arc::clone(&self.network)));
-
-        self.bootstrapped = true;
-
-        // 6. Warm up circuit pool (prebuild circuits for fast first requests)
-        log::info!("🔥 Warming up circuit pool...");
-        let pool_builder = self.circuit_builder.as_ref().unwrap().clone();
-        let pool_selector = self.relay_selector.as_ref().unwrap().clone();
-        match self
-            .circuit_pool
-            .warm_up(&pool_builder, &pool_selector)
-            .await
-        {
-            Ok(n) => log::info!("✅ Circuit pool warmed up ({} circuits ready)", n),
-            Err(e) => log::warn!(
-                "⚠️ Circuit pool warm-up failed: {} (will build on demand)",
-                e
-            ),
-        }
-
-        log::info!("✅ Tor client bootstrapped and ready!");
+        self.install_consensus(Arc::new(consensus)).await
+    }
 
-        Ok(())
+    /// Health checks of the consensus fetched by the last `bootstrap()`
+    ///
+    /// Returns `{ relays, guards, exits, running, total_bandwidth,
+    /// publication_spread_secs, known_authorities, failures }` or null,
+    /// where each failure is `{ kind, ... }` with `kind` one of
+    /// `"too_few_relays"`, `"guard_fraction"`, `"exit_fraction"`,
+    /// `"too_little_bandwidth"`, `"bandwidth_concentrated"`,
+    /// `"publication_spread"` or `"too_few_authorities"`.
+    #[wasm_bindgen]
+    pub fn consensus_health(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.consensus_health).unwrap_or(JsValue::NULL)
     }
 
     /// Get client status
     #[wasm_bindgen]
     pub fn get_status(&self) -> JsValue {
-        let cache_stats = self.circuit_cache.stats();
+        let cache_stats = self.circuit_cache.with(|cache| cache.stats());
         let now = web_time::SystemTime::now()
             .duration_since(web_time::SystemTime::UNIX_EPOCH)
             .map(|d| d.as_secs())
             .unwrap_or(0);
 
-        let days_until_guard_rotation = if self.guard_state.rotate_after > now {
-            (self.guard_state.rotate_after - now) / (24 * 60 * 60)
+        let guards = self.guard_state.with(|g| g.clone());
+        let days_until_guard_rotation = if guards.rotate_after > now {
+            (guards.rotate_after - now) / (24 * 60 * 60)
         } else {
             0
         };
 
+        let net = self.network.get_stats();
+        let network_stats = serde_json::json!({
+            "connections_attempted": net.connections_attempted,
+            "connections_successful": net.connections_successful,
+            "connections_failed": net.connections_failed,
+            "live_connections": net.live_transport_connections,
+            "bytes_sent": net.bytes_sent,
+            "bytes_received": net.bytes_received,
+            "frames_sent": net.frames_sent,
+            "frames_received": net.frames_received,
+            "reconnects": net.reconnects,
+            "buffered_amount": net.buffered_amount,
+            "peak_buffered_amount": net.peak_buffered_amount,
+            "ice_state_transitions": net.ice_state_transitions,
+        });
+        let running_tasks = self.tasks.running_tasks();
+
         let status = if let Some(ref consensus) = self.consensus {
             serde_wasm_bindgen::to_value(&serde_json::json!({
                 "bootstrapped": self.bootstrapped,
+                "shut_down": self.shut_down,
                 "consensus_relay_count": consensus.relays.len(),
                 "cached_circuits": cache_stats.cached_circuits,
                 "total_requests": cache_stats.total_requests,
                 "isolation_policy": format!("{:?}", cache_stats.policy),
                 "consensus_valid": consensus.is_valid(),
                 "consensus_fresh": consensus.is_fresh(),
-                "guard_count": self.guard_state.guards.len(),
-                "usable_guards": self.guard_state.usable_guard_count(),
+                "clock_skew_secs": clock_skew::skew_secs(),
+                "guard_count": guards.guards.len(),
+                "usable_guards": guards.usable_guard_count(),
                 "days_until_guard_rotation": days_until_guard_rotation,
                 "pool_size": self.circuit_pool.size(),
                 "pool_hits": self.circuit_pool.get_stats().pool_hits,
+                "network": network_stats,
+                "background_tasks": running_tasks,
+                "dormant": self.dormancy.is_dormant(),
+                "security": security_posture::report(),
             }))
             .unwrap()
         } else {
             serde_wasm_bindgen::to_value(&serde_json::json!({
                 "bootstrapped": self.bootstrapped,
+                "shut_down": self.shut_down,
                 "consensus_relay_count": 0,
                 "cached_circuits": 0,
                 "isolation_policy": format!("{:?}", cache_stats.policy),
-                "guard_count": self.guard_state.guards.len(),
+                "guard_count": guards.guards.len(),
+                "network": network_stats,
+                "background_tasks": running_tasks,
+                "dormant": self.dormancy.is_dormant(),
+                "security": security_posture::report(),
             }))
             .unwrap()
         };
@@ -428,9 +751,9 @@ impl TorClient {
             ));
         }
 
-        if !self.bootstrapped {
-            log::error!("❌ Client not bootstrapped");
-            return Err(JsValue::from_str("Client not bootstrapped"));
+        if let Err(e) = self.ensure_ready() {
+            log::error!("❌ Client not ready for circuit building");
+            return Err(e);
         }
         log::debug!("  ✓ Client is bootstrapped");
 
@@ -461,7 +784,9 @@ impl TorClient {
 
         // Build circuit (now we own the builder and selector, no borrow conflicts)
         log::debug!("  🚀 Calling builder.build_circuit()...");
-        let circuit = builder.build_circuit(&selector).await.map_err(|e| {
+        let result = builder.build_circuit(&selector).await;
+        self.persist_guard_outcomes();
+        let circuit = result.map_err(|e| {
             // Don't add extra "Circuit build failed" - the error already has context
             let error_msg = e.to_string();
             log::error!("❌ {}", error_msg);
@@ -475,229 +800,532 @@ impl TorClient {
         Ok(circuit.id as usize)
     }
 
-    /// Connect to a host through Tor
+    /// Build a circuit through specific relays (research and debugging)
     ///
-    /// Returns a circuit ID that can be used for communication
+    /// `fingerprints` lists the hops in order, guard first and exit last, as
+    /// hex identity fingerprints (a leading `$` is accepted). Path constraints
+    /// (Guard/Exit flags, families, shared /16 subnets) are enforced unless
+    /// `allow_unsafe_path` is true. The circuit stays open until
+    /// `close_custom_circuit()` or `shutdown()`.
+    ///
+    /// Returns `{ circuit_id, path: [{ nickname, fingerprint }] }`.
     #[wasm_bindgen]
-    pub async fn connect(
+    pub async fn build_custom_circuit(
         &mut self,
-        host: String,
-        port: u16,
-    ) -> std::result::Result<usize, JsValue> {
-        if !self.bootstrapped {
-            return Err(JsValue::from_str("Client not bootstrapped"));
-        }
+        fingerprints: Vec<String>,
+        allow_unsafe_path: Option<bool>,
+    ) -> std::result::Result<JsValue, JsValue> {
+        self.ensure_ready()?;
 
-        log::info!("🌐 Connecting to {}:{} via Tor...", host, port);
+        if !self.rate_limiter.can_create_circuit() {
+            return Err(JsValue::from_str(
+                "Rate limited: too many circuit requests. Please wait.",
+            ));
+        }
 
-        // 1. Build a circuit
-        log::info!("  Building circuit for connection...");
+        let path = fingerprints
+            .iter()
+            .map(|fp| self.find_relay(fp))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let enforce = !allow_unsafe_path.unwrap_or(false);
+        protocol::CircuitBuilder::validate_path(&path, enforce)?;
+        if !enforce {
+            log::warn!("⚠️ Building custom circuit with path constraints disabled");
+        }
 
         let builder = self
             .circuit_builder
             .as_ref()
             .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
             .clone();
-
-        let selector = self
-            .relay_selector
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
-            .clone();
-
-        let circuit = builder
-            .build_circuit(&selector)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Circuit build failed: {}", e)))?;
+        let result = builder.build_circuit_through(&path).await;
+        self.persist_guard_outcomes();
+        let circuit =
+            result.map_err(|e| JsValue::from_str(&format!("Circuit build failed: {}", e)))?;
 
         let circuit_id = circuit.id;
-
-        // Record circuit creation for rate limiting
         self.rate_limiter.record_circuit_created(circuit_id);
+        self.custom_circuits
+            .with(|circuits| circuits.insert(circuit_id, Rc::new(RefCell::new(circuit))));
 
-        // 2. Open a stream through the circuit
-        log::info!("  📡 Opening stream to {}:{}...", host, port);
-
-        let circuit_rc = std::rc::Rc::new(std::cell::RefCell::new(circuit));
-        let mut stream_manager = protocol::StreamManager::new(std::rc::Rc::clone(&circuit_rc));
-
-        let _stream = stream_manager
-            .open_stream(&host, port)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
-
-        log::info!(
-            "✅ Connected to {}:{} via Tor circuit {}",
-            host,
-            port,
-            circuit_id
-        );
+        log::info!("✅ Custom circuit {} ready", circuit_id);
 
-        Ok(circuit_id as usize)
+        let hops: Vec<serde_json::Value> = path
+            .iter()
+            .map(|r| serde_json::json!({ "nickname": r.nickname, "fingerprint": r.fingerprint }))
+            .collect();
+        Ok(serde_wasm_bindgen::to_value(&serde_json::json!({
+            "circuit_id": circuit_id,
+            "path": hops,
+        }))
+        .unwrap_or(JsValue::NULL))
     }
 
-    /// Fetch a URL through Tor (HTTP and HTTPS supported)
+    /// Build a circuit that ends at a specific exit
     ///
-    /// Uses circuit isolation to prevent cross-site correlation.
-    /// Different domains use different circuits.
+    /// `fingerprint` is the exit's hex identity fingerprint (a leading `$` is
+    /// accepted); it must have the Exit flag and not be banned. A fresh
+    /// prebuilt circuit is cannibalized when its guard and middle fit
+    /// (TRUNCATE, then EXTEND2 to the exit), otherwise guard and middle are
+    /// chosen as usual and built cold. Like `build_custom_circuit()`, the
+    /// circuit stays open until `close_custom_circuit()` or `shutdown()`.
     ///
-    /// Returns the HTTP response body as a string
+    /// Returns `{ circuit_id, path: [{ nickname, fingerprint }] }`.
     #[wasm_bindgen]
-    pub async fn fetch(&mut self, url: String) -> std::result::Result<String, JsValue> {
-        if !self.bootstrapped {
-            return Err(JsValue::from_str("Client not bootstrapped"));
+    pub async fn build_circuit_to_exit(
+        &mut self,
+        fingerprint: String,
+    ) -> std::result::Result<JsValue, JsValue> {
+        self.ensure_ready()?;
+
+        if !self.rate_limiter.can_create_circuit() {
+            return Err(JsValue::from_str(
+                "Rate limited: too many circuit requests. Please wait.",
+            ));
         }
 
-        // Parse URL (now returns is_https flag)
-        let (host, port, path, is_https) =
-            parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+        let exit = self.find_relay(&fingerprint)?;
+        if !exit.is_exit() {
+            return Err(JsValue::from_str(&format!(
+                "Relay {} is not a usable exit",
+                exit.nickname
+            )));
+        }
+        if self.relay_verifier.is_banned(&exit.fingerprint) {
+            return Err(JsValue::from_str(&format!(
+                "Exit {} is banned",
+                exit.nickname
+            )));
+        }
 
-        let scheme = if is_https { "HTTPS" } else { "HTTP" };
-        log::info!("🌐 Fetching {} via Tor ({})...", url, scheme);
-        log::info!(
-            "  Host: {}, Port: {}, Path: {}, HTTPS: {}",
-            host,
-            port,
-            path,
-            is_https
-        );
-
-        // 1. Get or build a circuit (with isolation)
-        let isolation_key = self.circuit_cache.isolation_key(&host, port);
-        log::info!("  🔒 Isolation key: '{}'", isolation_key.as_str());
-
-        let circuit_rc = if let Some(cached) = self.circuit_cache.get(&isolation_key) {
-            log::info!("  ♻️ Reusing existing circuit for '{}'", host);
-            cached
-        } else {
-            // Rate limiting check for new circuit
-            if !self.rate_limiter.can_create_circuit() {
-                log::error!("❌ Rate limited: too many circuits created recently");
-                return Err(JsValue::from_str(
-                    "Rate limited: too many circuit requests. Please wait.",
-                ));
-            }
+        let builder = self
+            .circuit_builder
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
+            .clone();
+        let selector = self
+            .relay_selector
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
+            .clone();
+        let result = self
+            .circuit_pool
+            .get_circuit_for(&builder, &selector, &CircuitTarget::Exit(exit))
+            .await;
+        self.persist_guard_outcomes();
+        let circuit =
+            result.map_err(|e| JsValue::from_str(&format!("Circuit build failed: {}", e)))?;
 
-            log::info!("  🔨 Building new circuit for '{}'...", host);
+        let circuit_id = circuit.id;
+        self.rate_limiter.record_circuit_created(circuit_id);
+        let hops: Vec<serde_json::Value> = circuit
+            .relays
+            .iter()
+            .map(|r| serde_json::json!({ "nickname": r.nickname, "fingerprint": r.fingerprint }))
+            .collect();
+        self.custom_circuits
+            .with(|circuits| circuits.insert(circuit_id, Rc::new(RefCell::new(circuit))));
 
-            let builder = self
-                .circuit_builder
-                .as_ref()
-                .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
-                .clone();
+        log::info!("✅ Circuit {} to chosen exit ready", circuit_id);
 
-            let selector = self
-                .relay_selector
-                .as_ref()
-                .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
-                .clone();
+        Ok(serde_wasm_bindgen::to_value(&serde_json::json!({
+            "circuit_id": circuit_id,
+            "path": hops,
+        }))
+        .unwrap_or(JsValue::NULL))
+    }
 
-            let circuit = builder
-                .build_circuit(&selector)
-                .await
-                .map_err(|e| JsValue::from_str(&format!("Circuit build failed: {}", e)))?;
+    /// Destroy a circuit created by `build_custom_circuit()`
+    ///
+    /// Returns false if no custom circuit has that ID.
+    #[wasm_bindgen]
+    pub async fn close_custom_circuit(&mut self, circuit_id: u32) -> bool {
+        let circuit = match self
+            .custom_circuits
+            .with(|circuits| circuits.remove(&circuit_id))
+        {
+            Some(c) => c,
+            None => return false,
+        };
+        match Rc::try_unwrap(circuit) {
+            Ok(cell) => {
+                if let Err(e) = cell.into_inner().destroy().await {
+                    log::warn!("⚠️ Failed to destroy circuit {}: {}", circuit_id, e);
+                }
+            }
+            Err(_) => log::warn!("⚠️ Circuit {} still in use, dropping it", circuit_id),
+        }
+        true
+    }
 
-            // Record circuit creation for rate limiting
-            self.rate_limiter.record_circuit_created(circuit.id);
+    /// Connect to a host through Tor
+    ///
+    /// Opens a stream to `host:port` on a circuit of its own (rate limited
+    /// under the host's isolation key) and returns its handle, run by the
+    /// cooperative scheduler. The stream carries raw bytes; no TLS is added.
+    ///
+    /// Pass `circuit_id` (from `build_custom_circuit()`) to open the stream
+    /// on that circuit instead (errors if it is closed or busy). It is handed
+    /// back when the handle is closed, or right away if the stream fails to
+    /// open.
+    #[wasm_bindgen]
+    pub async fn connect(
+        &mut self,
+        host: String,
+        port: u16,
+        circuit_id: Option<u32>,
+    ) -> std::result::Result<TorStreamHandle, JsValue> {
+        self.ensure_ready()?;
 
-            log::info!("  ✅ Circuit {} built", circuit.id);
+        log::info!("🌐 Connecting to {}:{} via Tor...", host, port);
 
-            // Cache the circuit for future requests to this domain
-            self.circuit_cache.store(isolation_key, circuit)
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let lifetime = self.relay_requirements.stream_lifetime(port, false);
+        let class = PortClass::for_lifetime(lifetime);
+
+        // 1. Get a circuit
+        let circuit = match circuit_id {
+            Some(id) => self.detach_circuit(id)?,
+            None => {
+                log::info!("  Building circuit for connection...");
+                self.pooled_circuit(&isolation_key, class, None).await?
+            }
         };
+        let id = circuit.id;
+        let exit = exit_fingerprint(&circuit);
 
         // 2. Open a stream through the circuit
         log::info!("  📡 Opening stream to {}:{}...", host, port);
 
-        let mut stream_manager = protocol::StreamManager::new(circuit_rc);
-
-        let stream = stream_manager
-            .open_stream(&host, port)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
-
-        log::info!("  ✅ Stream opened");
-
-        // 3. For HTTPS, wrap stream with TLS
-        let response_bytes = if is_https {
-            log::info!("  🔐 Establishing TLS connection...");
-
-            let mut tls_stream = protocol::TlsTorStream::new(stream, &host)
-                .await
-                .map_err(|e| JsValue::from_str(&format!("TLS handshake failed: {}", e)))?;
-
-            log::info!("  ✅ TLS established");
-
-            // Send HTTP request over TLS
-            let http_request = format!(
-                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n\r\n",
-                path, host
-            );
-
-            log::info!(
-                "  📤 Sending HTTPS request ({} bytes)...",
-                http_request.len()
-            );
+        let scheduler = Rc::new(RefCell::new(CooperativeCircuit::new(circuit)));
+        let (target, cached) = self.stream_target(&isolation_key, &host);
+        let opened =
+            open_cooperative_stream(&scheduler, &target, port, protocol::BeginFlags::default())
+                .await;
+        let stream = match opened {
+            Ok(stream) => stream,
+            Err(e) => {
+                if circuit_id.is_some() {
+                    self.reclaim_circuit(scheduler, circuit_id, class);
+                }
+                return Err(JsValue::from_str(&format!("Stream open failed: {}", e)));
+            }
+        };
+        self.note_connected(
+            &isolation_key,
+            &host,
+            port,
+            exit,
+            stream.connected_address(),
+            cached,
+        );
 
-            tls_stream
-                .write(http_request.as_bytes())
-                .await
-                .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
+        log::info!("✅ Connected to {}:{} via Tor circuit {}", host, port, id);
 
-            log::info!("  ✅ Request sent");
-            log::info!("  📥 Receiving response...");
+        let handle = TorStreamHandle::new(scheduler, stream, id, format!("{}:{}", host, port));
+        Ok(match circuit_id {
+            Some(_) => handle.returning_to(self.custom_circuits.clone()),
+            None => handle,
+        })
+    }
 
-            // Read response
-            let response = tls_stream
-                .read_to_end()
+    /// Answer the opening of an HTTP CONNECT proxy dialogue
+    ///
+    /// `request` is what a JS library sent to its configured proxy, at least
+    /// up to the blank line ending the CONNECT header (see
+    /// `ProxyTunnel.header_complete()`); bytes after it are forwarded as
+    /// tunnel data. Opens a stream to the target on its isolated circuit and
+    /// returns the tunnel. Refusals and connection failures are not errors:
+    /// they come back as a closed tunnel whose `response()` is the 4xx/502
+    /// reply for the library.
+    #[wasm_bindgen]
+    pub async fn proxy_connect(
+        &mut self,
+        request: Vec<u8>,
+    ) -> std::result::Result<ProxyTunnel, JsValue> {
+        self.ensure_ready()?;
+
+        let target = match connect_proxy::parse_connect(&request) {
+            connect_proxy::ConnectParse::Request(target) => target,
+            connect_proxy::ConnectParse::Incomplete => {
+                return Err(JsValue::from_str("Incomplete CONNECT request"))
+            }
+            connect_proxy::ConnectParse::Reject(status) => {
+                log::warn!("🚪 Refused proxy request ({})", status);
+                return Ok(ProxyTunnel::rejected(status));
+            }
+        };
+        log::info!("🚪 Proxy CONNECT {}:{}", target.host, target.port);
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&target.host, target.port));
+        let lifetime = self.relay_requirements.stream_lifetime(target.port, false);
+        let stream = match self
+            .isolated_circuit(&isolation_key, &target.host, lifetime, None)
+            .await
+        {
+            Ok(circuit) => {
+                self.open_stream_cached(
+                    circuit,
+                    &isolation_key,
+                    &target.host,
+                    target.port,
+                    lifetime,
+                    protocol::BeginFlags::default(),
+                )
                 .await
-                .map_err(|e| JsValue::from_str(&format!("Failed to receive response: {}", e)))?;
-
-            // Close TLS
-            let _ = tls_stream.close().await;
-
-            response
-        } else {
-            // Plain HTTP
-            let mut stream = stream;
-
-            let http_request = format!(
-                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n\r\n",
-                path, host
-            );
-
-            log::info!(
-                "  📤 Sending HTTP request ({} bytes)...",
-                http_request.len()
-            );
+            }
+            Err(e) => Err(e),
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!(
+                    "⚠️ Proxy CONNECT {}:{} failed: {:?}",
+                    target.host,
+                    target.port,
+                    e.as_string()
+                );
+                return Ok(ProxyTunnel::rejected(502));
+            }
+        };
 
-            stream
-                .write_all(http_request.as_bytes())
-                .await
-                .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
+        let mut tunnel = ProxyTunnel::open(&target, stream);
+        let early_data = &request[target.header_len..];
+        if !early_data.is_empty() {
+            tunnel.write(early_data.to_vec()).await?;
+        }
+        Ok(tunnel)
+    }
 
-            log::info!("  ✅ Request sent");
-            log::info!("  📥 Receiving response...");
+    /// Open a WebSocket (ws:// or wss://) through Tor
+    ///
+    /// Opens a stream on the URL's isolated circuit (Stable exits only, as
+    /// for other long-lived streams), does TLS for `wss://` and the HTTP
+    /// Upgrade handshake, and returns the open socket. `ws://` follows the
+    /// plain-HTTP policy of `fetch()`, including any upgrade to `wss://`.
+    ///
+    /// # Arguments
+    /// * `url` - The `ws://` or `wss://` URL
+    /// * `protocols` - Optional comma-separated subprotocols to offer
+    /// * `headers_json` - Optional JSON object of extra handshake headers
+    #[wasm_bindgen]
+    pub async fn open_websocket(
+        &mut self,
+        url: String,
+        protocols: Option<String>,
+        headers_json: Option<String>,
+    ) -> std::result::Result<TorWebSocket, JsValue> {
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let started_ms = now_ms();
+
+        let http_url = tor_websocket::http_url(&url)
+            .ok_or_else(|| JsValue::from_str("WebSocket URL must be ws:// or wss://"))?;
+        let http_url = self.http_plan(&http_url, None)?.url;
+        let (host, port, path, is_https) =
+            parse_url(&http_url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+        let protocols: Vec<String> = protocols
+            .iter()
+            .flat_map(|p| p.split(','))
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        let headers = match headers_json {
+            Some(json) => {
+                let headers: std::collections::HashMap<String, String> =
+                    serde_json::from_str(&json)
+                        .map_err(|e| JsValue::from_str(&format!("Invalid headers JSON: {}", e)))?;
+                http_request::header_block(headers)?
+            }
+            None => String::new(),
+        };
+        log::info!("🔌 WebSocket {} via Tor...", url);
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let lifetime = self.relay_requirements.stream_lifetime(port, true);
+        let circuit_rc = self
+            .isolated_circuit(&isolation_key, &host, lifetime, None)
+            .await?;
+        let stream = self
+            .open_stream_cached(
+                circuit_rc,
+                &isolation_key,
+                &host,
+                port,
+                lifetime,
+                protocol::BeginFlags::default(),
+            )
+            .await?;
+
+        let conn = sse::HttpConnection::open(stream, &host, is_https).await?;
+        // The HTTP policy may have upgraded ws:// to wss://
+        let scheme = if is_https { "wss" } else { "ws" };
+        let rest = http_url
+            .split_once("://")
+            .map_or(http_url.as_str(), |(_, rest)| rest);
+        let socket_url = format!("{}://{}", scheme, rest);
+        let socket = TorWebSocket::connect(
+            conn,
+            &socket_url,
+            &host,
+            port,
+            &path,
+            is_https,
+            &protocols,
+            &headers,
+        )
+        .await?;
+
+        log::info!("✅ WebSocket open to {}", socket_url);
+        self.record_latency(&isolation_key, started_ms);
+        Ok(socket)
+    }
 
-            let response = stream
-                .read_response()
-                .await
-                .map_err(|e| JsValue::from_str(&format!("Failed to receive response: {}", e)))?;
+    /// Resolve a hostname through the exit (RELAY_RESOLVE)
+    ///
+    /// Uses the circuit requests to `https://hostname` would use, and the
+    /// DNS cache for that isolation key. Answers are cached for their
+    /// (clamped) TTL, so a following `fetch` to the host skips exit DNS.
+    /// Returns the addresses as strings.
+    #[wasm_bindgen]
+    pub async fn resolve(&mut self, hostname: String) -> std::result::Result<Vec<String>, JsValue> {
+        self.ensure_ready()?;
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&hostname, 443));
+        if let Some(addresses) = self.dns_cache.lookup(&isolation_key, &hostname) {
+            return Ok(addresses.iter().map(|a| a.to_string()).collect());
+        }
 
-            // Close stream
-            let _ = stream.close().await;
+        log::info!("🔎 Resolving {} via Tor...", hostname);
+        let circuit_rc = self
+            .isolated_circuit(
+                &isolation_key,
+                &hostname,
+                protocol::StreamLifetime::Short,
+                None,
+            )
+            .await?;
+        let answers = protocol::StreamManager::new(circuit_rc)
+            .resolve(&hostname)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Resolve failed: {}", e)))?;
+        if answers.is_empty() {
+            return Err(JsValue::from_str(&format!("No addresses for {}", hostname)));
+        }
 
-            response
-        };
+        self.dns_cache.insert(&isolation_key, &hostname, &answers);
+        Ok(answers.iter().map(|a| a.address.to_string()).collect())
+    }
 
-        log::info!("  ✅ Received {} bytes", response_bytes.len());
+    /// Fetch a URL through Tor (HTTP and HTTPS supported)
+    ///
+    /// Uses circuit isolation to prevent cross-site correlation.
+    /// Different domains use different circuits.
+    ///
+    /// Pass `circuit_id` (from `build_custom_circuit()`) to bypass isolation
+    /// and send the request over that circuit; errors if it is closed.
+    ///
+    /// `options` may set `{ integrity: "sha256-..." }` (SRI format): the
+    /// promise then rejects unless the body matches, and over plain HTTP
+    /// the exit that served it is marked suspicious. `begin_flags`
+    /// (`{ ipv6_ok, ipv4_not_ok, ipv6_preferred }`) lets the exit connect
+    /// over IPv6, which hosts without an IPv4 address need.
+    ///
+    /// Plain `http://` URLs are refused unless `http.allow_insecure_http`
+    /// is set in the client config or `allow_insecure_http: true` in
+    /// `options`. With `upgrade_to_https` (config or options) they are tried
+    /// over HTTPS first, falling back to HTTP only if that is allowed.
+    ///
+    /// v3 onion services (`http://<56 characters>.onion/`) are reached over
+    /// a rendezvous circuit; plain HTTP to them is always allowed, since the
+    /// connection never leaves Tor and is end-to-end encrypted.
+    ///
+    /// `max_bytes` in `options` overrides the per-request bandwidth quota;
+    /// the promise rejects if the response exceeded it.
+    ///
+    /// `auth` in `options` (`{ type: "basic", username, password }` or
+    /// `{ type: "bearer", token }`) sets `Authorization`; credentials are
+    /// never logged.
+    ///
+    /// An `AbortSignal` as `signal` in `options` cancels the request, which
+    /// then rejects with "Request cancelled". A circuit build stops at the
+    /// next hop; once the stream is open, this method can only stop before
+    /// sending the request (the cooperative methods stop at once).
+    ///
+    /// Returns the HTTP response body as a string; use `fetch_response()`
+    /// for the status, headers and an intact binary body
+    #[wasm_bindgen]
+    pub async fn fetch(
+        &mut self,
+        url: String,
+        circuit_id: Option<u32>,
+        options: JsValue,
+    ) -> std::result::Result<String, JsValue> {
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let options = parse_fetch_options(options)?;
+        let plan = self.http_plan(&url, Some(&options))?;
+        match (
+            self.fetch_url(&plan.url, circuit_id, &options).await,
+            plan.fallback,
+        ) {
+            (Err(e), Some(fallback)) => {
+                log::warn!(
+                    "🔓 HTTPS upgrade of {} failed ({:?}), retrying over HTTP",
+                    url,
+                    e
+                );
+                self.fetch_url(&fallback, circuit_id, &options).await
+            }
+            (result, _) => result,
+        }
+    }
 
-        // Convert to string
-        let response_str = String::from_utf8_lossy(&response_bytes).to_string();
+    /// Fetch a directory document (e.g. `/tor/server/fp/<fingerprint>`)
+    /// over BEGIN_DIR from the last relay of a directory circuit
+    ///
+    /// Directory requests get their own circuit under a reserved isolation
+    /// key: they never share a circuit with `fetch()` and friends, and never
+    /// take one from the prebuilt pool. The relay answers from its own
+    /// directory cache through its ORPort, so the request never leaves the
+    /// Tor network in cleartext. Returns the response body.
+    #[wasm_bindgen]
+    pub async fn fetch_directory(&mut self, path: String) -> std::result::Result<String, JsValue> {
+        self.ensure_ready()?;
+        if !path.starts_with("/tor/") {
+            return Err(JsValue::from_str("Directory path must start with /tor/"));
+        }
 
-        log::info!("✅ Fetch complete: {} bytes", response_str.len());
+        let key = IsolationKey::directory();
+        let circuit = self
+            .isolated_circuit(&key, "directory", protocol::StreamLifetime::Short, None)
+            .await?;
+        log::info!(
+            "📂 Fetching {} over BEGIN_DIR from {}",
+            path,
+            circuit
+                .borrow()
+                .relays
+                .last()
+                .map(|r| r.nickname.clone())
+                .unwrap_or_default()
+        );
 
-        Ok(response_str)
+        // Straight to the stream manager: directory answers stay out of the
+        // DNS cache and `last_response()`, which describe user traffic
+        let body = protocol::DirectoryManager::fetch_over_circuit(&circuit, &path, &[])
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Directory fetch failed: {}", e)))?;
+        log::info!("✅ Directory fetch complete: {} bytes", body.len());
+        Ok(String::from_utf8_lossy(&body).to_string())
     }
 
     /// Fetch a URL via POST through the Tor network
@@ -706,9 +1334,11 @@ impl TorClient {
     /// Useful for LLM API calls (Anthropic, OpenAI, Mistral, etc.)
     ///
     /// # Arguments
-    /// * `url` - The URL to fetch (http:// or https://)
+    /// * `url` - The URL to fetch (https://, or http:// if the client config allows it)
     /// * `headers_json` - JSON string of headers, e.g. {"x-api-key": "...", "content-type": "application/json"}
     /// * `body` - The request body (typically JSON)
+    /// * `circuit_id` - Optional ID from `build_custom_circuit()`; sends the
+    ///   request over that circuit instead of a fresh one (errors if it is closed)
     ///
     /// # Returns
     /// The HTTP response body as a string
@@ -718,17 +1348,20 @@ impl TorClient {
         url: String,
         headers_json: String,
         body: String,
+        circuit_id: Option<u32>,
     ) -> std::result::Result<String, JsValue> {
-        if !self.bootstrapped {
-            return Err(JsValue::from_str("Client not bootstrapped"));
-        }
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let started_ms = now_ms();
 
         // Parse headers from JSON
         let headers: std::collections::HashMap<String, String> =
             serde_json::from_str(&headers_json)
                 .map_err(|e| JsValue::from_str(&format!("Invalid headers JSON: {}", e)))?;
+        let headers_str = http_request::header_block(headers)?;
 
-        // Parse URL
+        // Parse URL (upgraded, or refused, if plain HTTP)
+        let url = self.http_plan(&url, None)?.url;
         let (host, port, path, is_https) =
             parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
 
@@ -738,66 +1371,45 @@ impl TorClient {
         log::info!("  Body length: {} bytes", body.len());
 
         // Get or build a circuit
-        let isolation_key = self.circuit_cache.isolation_key(&host, port);
-
-        let circuit_rc = if let Some(cached) = self.circuit_cache.get(&isolation_key) {
-            log::info!("  ♻️ Reusing existing circuit for '{}'", host);
-            cached
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+
+        let lifetime = match circuit_id {
+            // The caller chose the path
+            Some(_) => protocol::StreamLifetime::Short,
+            None => self.relay_requirements.stream_lifetime(port, false),
+        };
+        let circuit_rc = if let Some(id) = circuit_id {
+            log::info!("  📌 Using attached circuit {}", id);
+            self.attached_circuit(id)?
         } else {
-            if !self.rate_limiter.can_create_circuit() {
-                return Err(JsValue::from_str(
-                    "Rate limited: too many circuit requests. Please wait.",
-                ));
-            }
-
-            log::info!("  🔨 Building new circuit for '{}'...", host);
-
-            let builder = self
-                .circuit_builder
-                .as_ref()
-                .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
-                .clone();
-
-            let selector = self
-                .relay_selector
-                .as_ref()
-                .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
-                .clone();
-
-            let circuit = builder
-                .build_circuit(&selector)
-                .await
-                .map_err(|e| JsValue::from_str(&format!("Circuit build failed: {}", e)))?;
-
-            self.rate_limiter.record_circuit_created(circuit.id);
-            log::info!("  ✅ Circuit {} built", circuit.id);
-
-            self.circuit_cache.store(isolation_key, circuit)
+            self.isolated_circuit(&isolation_key, &host, lifetime, None)
+                .await?
         };
 
         // Open a stream
         log::info!("  📡 Opening stream to {}:{}...", host, port);
 
-        let mut stream_manager = protocol::StreamManager::new(circuit_rc);
-
-        let stream = stream_manager
-            .open_stream(&host, port)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
+        let stream = self
+            .open_stream_cached(
+                circuit_rc,
+                &isolation_key,
+                &host,
+                port,
+                lifetime,
+                protocol::BeginFlags::default(),
+            )
+            .await?;
 
         log::info!("  ✅ Stream opened");
 
-        // Build headers string
-        let mut headers_str = String::new();
-        for (key, value) in &headers {
-            headers_str.push_str(&format!("{}: {}\r\n", key, value));
-        }
-
         // Build HTTP POST request
         let http_request = format!(
             "POST {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n{}\r\n{}",
             path, host, body.len(), headers_str, body
         );
+        let http_request = self.http_padding.pad_request(&isolation_key, http_request);
 
         let response_bytes =
             if is_https {
@@ -852,111 +1464,410 @@ impl TorClient {
             };
 
         log::info!("  ✅ Received {} bytes", response_bytes.len());
+        self.note_response(&host, port, is_https, &response_bytes);
+        self.account_transfer(body.len() + response_bytes.len(), None)?;
+        let response_bytes = self
+            .http_padding
+            .strip_response(&isolation_key, response_bytes);
 
         let response_str = String::from_utf8_lossy(&response_bytes).to_string();
 
         log::info!("✅ POST complete: {} bytes", response_str.len());
 
+        self.record_latency(&isolation_key, started_ms);
         Ok(response_str)
     }
 
-    /// Fetch a URL via POST through the Tor network (Cooperative Scheduler)
+    /// Upload a `Blob` or `ReadableStream` body through Tor
     ///
-    /// This version uses the novel cooperative scheduler that avoids the
-    /// RefCell borrow-across-await bug. It's designed for single-threaded
-    /// WASM environments.
+    /// Like `fetch_post()`, but the body is read and sent a chunk at a time
+    /// instead of being passed as one string, so large files and generated
+    /// data can be uploaded. A `Blob` (or `File`) is sent with
+    /// `Content-Length`, a `ReadableStream` of Uint8Array chunks with
+    /// chunked transfer encoding. Sending pauses whenever the stream or
+    /// circuit flow-control window closes and resumes on the relays'
+    /// SENDMEs.
     ///
     /// # Arguments
-    /// * `url` - The URL to fetch (http:// or https://)
-    /// * `headers_json` - JSON string of headers
-    /// * `body` - The request body (typically JSON)
+    /// * `url` - The URL to upload to (https://, or http:// if the client config allows it)
+    /// * `headers_json` - JSON object of headers; `Host`, `Content-Length`,
+    ///   `Connection` and `Transfer-Encoding` are set by the client
+    /// * `body` - A `Blob`, `File` or `ReadableStream`
+    /// * `method` - POST (default), PUT or PATCH
+    /// * `circuit_id` - Optional ID from `build_custom_circuit()`
     ///
     /// # Returns
-    /// The HTTP response body as a string
+    /// The HTTP response as a string, as from `fetch_post()`
     #[wasm_bindgen]
-    pub async fn fetch_post_cooperative(
+    pub async fn fetch_upload(
         &mut self,
         url: String,
         headers_json: String,
-        body: String,
+        body: JsValue,
+        method: Option<String>,
+        circuit_id: Option<u32>,
     ) -> std::result::Result<String, JsValue> {
-        use std::cell::RefCell;
-        use std::rc::Rc;
-
-        if !self.bootstrapped {
-            return Err(JsValue::from_str("Client not bootstrapped"));
-        }
-
-        // Parse headers from JSON
-        let headers: std::collections::HashMap<String, String> =
+        self.ensure_ready()?;
+        let headers: std::collections::BTreeMap<String, String> =
             serde_json::from_str(&headers_json)
                 .map_err(|e| JsValue::from_str(&format!("Invalid headers JSON: {}", e)))?;
+        let mut request = HttpRequest {
+            method: method.unwrap_or_else(|| "POST".into()),
+            url: url.clone(),
+            headers: headers.into_iter().collect(),
+            body: Vec::new(),
+            auth: None,
+            timeout: None,
+        };
+        request.validate()?;
+        if request.is_safe() {
+            return Err(JsValue::from_str(&format!(
+                "{} requests can't have a body",
+                request.method
+            )));
+        }
+        let mut source = BodySource::from_js(&body)?;
+        self.admit_request().await?;
+        let started_ms = now_ms();
 
-        // Parse URL
+        let url = self.http_plan(&url, None)?.url;
         let (host, port, path, is_https) =
             parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+        log::info!(
+            "🌐 {} {} via Tor ({} bytes)...",
+            request.method,
+            url,
+            source
+                .length()
+                .map_or_else(|| "streamed".to_string(), |n| n.to_string())
+        );
 
-        let scheme = if is_https { "HTTPS" } else { "HTTP" };
-        log::info!("🌐 [COOP] POST {} via Tor ({})...", url, scheme);
-        log::info!("  Host: {}, Port: {}, Path: {}", host, port, path);
-        log::info!("  Body length: {} bytes", body.len());
-
-        // Rate limit check
-        if !self.rate_limiter.can_create_circuit() {
-            return Err(JsValue::from_str(
-                "Rate limited: too many circuit requests. Please wait.",
-            ));
-        }
-
-        // Get circuit from pool or build new one
-        log::info!("  🔨 Getting circuit for cooperative scheduler...");
-
-        let builder = self
-            .circuit_builder
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
-            .clone();
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let lifetime = match circuit_id {
+            Some(_) => protocol::StreamLifetime::Short,
+            None => self.relay_requirements.stream_lifetime(port, false),
+        };
+        let circuit_rc = if let Some(id) = circuit_id {
+            self.attached_circuit(id)?
+        } else {
+            self.isolated_circuit(&isolation_key, &host, lifetime, None)
+                .await?
+        };
+        let stream = self
+            .open_stream_cached(
+                circuit_rc,
+                &isolation_key,
+                &host,
+                port,
+                lifetime,
+                protocol::BeginFlags::default(),
+            )
+            .await?;
+
+        let head = request.streaming_head(&host, port, &path, is_https, source.length());
+        let head = self.http_padding.pad_request_head(
+            &isolation_key,
+            head,
+            source.length().unwrap_or(0) as usize,
+        );
+        let chunked = source.length().is_none();
+        let send_error = |e: TorError| JsValue::from_str(&format!("Failed to send request: {}", e));
 
-        let selector = self
-            .relay_selector
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
-            .clone();
+        let result = if is_https {
+            let mut tls_stream = protocol::TlsTorStream::new(stream, &host)
+                .await
+                .map_err(|e| JsValue::from_str(&format!("TLS handshake failed: {}", e)))?;
+            let sent = async {
+                tls_stream.write_all(head.as_bytes()).await?;
+                while let Some(chunk) = source.next_chunk().await? {
+                    if !chunk.is_empty() {
+                        tls_stream
+                            .write_all(&upload::frame(&chunk, chunked))
+                            .await?;
+                    }
+                }
+                if chunked {
+                    tls_stream.write_all(upload::LAST_CHUNK).await?;
+                }
+                tls_stream.read_to_end().await
+            }
+            .await;
+            let _ = tls_stream.close().await;
+            sent
+        } else {
+            let mut stream = stream;
+            let sent = async {
+                stream.write_all(head.as_bytes()).await?;
+                while let Some(chunk) = source.next_chunk().await? {
+                    if !chunk.is_empty() {
+                        stream.write_all(&upload::frame(&chunk, chunked)).await?;
+                    }
+                }
+                if chunked {
+                    stream.write_all(upload::LAST_CHUNK).await?;
+                }
+                stream.read_response().await
+            }
+            .await;
+            let _ = stream.close().await;
+            sent
+        };
+        let response_bytes = result.map_err(|e| {
+            source.cancel();
+            send_error(e)
+        })?;
 
-        let circuit = self
-            .circuit_pool
-            .get_circuit(&builder, &selector)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Circuit failed: {}", e)))?;
+        log::info!(
+            "  ✅ Uploaded {} bytes, received {}",
+            source.bytes_read(),
+            response_bytes.len()
+        );
+        self.note_response(&host, port, is_https, &response_bytes);
+        self.account_transfer(
+            head.len() + source.bytes_read() as usize + response_bytes.len(),
+            None,
+        )?;
+        let response_bytes = self
+            .http_padding
+            .strip_response(&isolation_key, response_bytes);
+        self.record_latency(&isolation_key, started_ms);
+        Ok(String::from_utf8_lossy(&response_bytes).to_string())
+    }
+
+    /// Stream a server-sent events response (LLM streaming APIs)
+    ///
+    /// Sends a POST with `body`, or a GET when `body` is undefined, and calls
+    /// `on_event({ event, data, id, retry })` for every event as soon as the
+    /// bytes completing it arrive off the circuit. Chunked and
+    /// Content-Length bodies are both handled. A non-2xx status fails with
+    /// the status and response body; an exception from `on_event` aborts
+    /// the stream. Event streams count as long-lived, so they only use
+    /// exits with the Stable flag.
+    ///
+    /// # Arguments
+    /// * `url` - The URL to fetch (https://, or http:// if the client config allows it)
+    /// * `headers_json` - JSON string of headers
+    /// * `body` - Optional request body
+    /// * `on_event` - Called once per event
+    /// * `circuit_id` - Optional ID from `build_custom_circuit()`
+    ///
+    /// # Returns
+    /// The number of events delivered
+    #[wasm_bindgen]
+    pub async fn fetch_sse(
+        &mut self,
+        url: String,
+        headers_json: String,
+        body: Option<String>,
+        on_event: js_sys::Function,
+        circuit_id: Option<u32>,
+    ) -> std::result::Result<u32, JsValue> {
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let started_ms = now_ms();
+
+        let headers: std::collections::HashMap<String, String> =
+            serde_json::from_str(&headers_json)
+                .map_err(|e| JsValue::from_str(&format!("Invalid headers JSON: {}", e)))?;
+        let mut headers_str = String::new();
+        if !headers.keys().any(|k| k.eq_ignore_ascii_case("accept")) {
+            headers_str.push_str("Accept: text/event-stream\r\n");
+        }
+        headers_str.push_str(&http_request::header_block(headers)?);
+        let url = self.http_plan(&url, None)?.url;
+        let (host, port, path, is_https) =
+            parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+        log::info!("🌊 SSE {} via Tor...", url);
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let lifetime = match circuit_id {
+            // The caller chose the path
+            Some(_) => protocol::StreamLifetime::Short,
+            None => self.relay_requirements.stream_lifetime(port, true),
+        };
+        let circuit_rc = if let Some(id) = circuit_id {
+            self.attached_circuit(id)?
+        } else {
+            self.isolated_circuit(&isolation_key, &host, lifetime, None)
+                .await?
+        };
+        let stream = self
+            .open_stream_cached(
+                circuit_rc,
+                &isolation_key,
+                &host,
+                port,
+                lifetime,
+                protocol::BeginFlags::default(),
+            )
+            .await?;
+
+        let http_request = match &body {
+            Some(body) => format!(
+                "POST {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n{}\r\n{}",
+                path, host, body.len(), headers_str, body
+            ),
+            None => format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n{}\r\n",
+                path, host, headers_str
+            ),
+        };
+        let http_request = self.http_padding.pad_request(&isolation_key, http_request);
+
+        let mut conn = sse::HttpConnection::open(stream, &host, is_https).await?;
+        conn.write_all(http_request.as_bytes()).await?;
+
+        let mut decoder = SseStream::new();
+        let mut buf = vec![0u8; 16 * 1024];
+        let mut delivered = 0u32;
+        let mut received = 0usize;
+        let result = loop {
+            let n = match conn.read(&mut buf).await {
+                Ok(0) => break Ok(()),
+                Ok(n) => n,
+                Err(e) => break Err(JsValue::from(e)),
+            };
+            received += n;
+            let had_head = decoder.status().is_some();
+            let events = match decoder.feed(&buf[..n]) {
+                Ok(events) => events,
+                Err(e) => break Err(JsValue::from(e)),
+            };
+            if !had_head {
+                if let Some(head) = decoder.head() {
+                    let head = head.to_vec();
+                    self.note_response(&host, port, is_https, &head);
+                }
+            }
+
+            let mut failed = None;
+            for event in events {
+                let value = serde_wasm_bindgen::to_value(&event)
+                    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+                if let Err(e) = on_event.call1(&JsValue::NULL, &value) {
+                    failed = Some(e);
+                    break;
+                }
+                delivered += 1;
+            }
+            if let Some(e) = failed {
+                break Err(e);
+            }
+            if decoder.is_complete() {
+                break Ok(());
+            }
+        };
+        let _ = conn.close().await;
+        let body_len = body.as_ref().map_or(0, |b| b.len());
+        self.account_transfer(body_len + received, None)?;
+        result?;
+
+        match decoder.status() {
+            None => Err(JsValue::from_str(
+                "Connection closed before response header",
+            )),
+            Some(status) if !decoder.is_success() => Err(JsValue::from_str(&format!(
+                "HTTP {}: {}",
+                status,
+                decoder.error_body()
+            ))),
+            Some(_) => {
+                log::info!("✅ SSE complete: {} events", delivered);
+                self.record_latency(&isolation_key, started_ms);
+                Ok(delivered)
+            }
+        }
+    }
+
+    /// Fetch a URL via POST through the Tor network (Cooperative Scheduler)
+    ///
+    /// This version uses the novel cooperative scheduler that avoids the
+    /// RefCell borrow-across-await bug. It's designed for single-threaded
+    /// WASM environments.
+    ///
+    /// # Arguments
+    /// * `url` - The URL to fetch (https://, or http:// if the client config allows it)
+    /// * `headers_json` - JSON string of headers
+    /// * `body` - The request body (typically JSON)
+    /// * `circuit_id` - Optional ID from `build_custom_circuit()`; sends the
+    ///   request over that circuit instead of a fresh one (errors if it is closed)
+    ///
+    /// # Returns
+    /// The HTTP response body as a string
+    #[wasm_bindgen]
+    pub async fn fetch_post_cooperative(
+        &mut self,
+        url: String,
+        headers_json: String,
+        body: String,
+        circuit_id: Option<u32>,
+    ) -> std::result::Result<String, JsValue> {
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let started_ms = now_ms();
+
+        // Parse headers from JSON
+        let headers: std::collections::HashMap<String, String> =
+            serde_json::from_str(&headers_json)
+                .map_err(|e| JsValue::from_str(&format!("Invalid headers JSON: {}", e)))?;
+        let headers_str = http_request::header_block(headers)?;
+
+        // Parse URL (upgraded, or refused, if plain HTTP)
+        let url = self.http_plan(&url, None)?.url;
+        let (host, port, path, is_https) =
+            parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+
+        let scheme = if is_https { "HTTPS" } else { "HTTP" };
+        log::info!("🌐 [COOP] POST {} via Tor ({})...", url, scheme);
+        log::info!("  Host: {}, Port: {}, Path: {}", host, port, path);
+        log::info!("  Body length: {} bytes", body.len());
 
-        self.rate_limiter.record_circuit_created(circuit.id);
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let class = PortClass::for_lifetime(self.relay_requirements.stream_lifetime(port, false));
+        let circuit = match circuit_id {
+            Some(id) => self.detach_circuit(id)?,
+            None => self.pooled_circuit(&isolation_key, class, None).await?,
+        };
         log::info!("  ✅ Circuit {} ready", circuit.id);
 
+        let exit = exit_fingerprint(&circuit);
+
         // Wrap in cooperative scheduler
         let scheduler = Rc::new(RefCell::new(CooperativeCircuit::new(circuit)));
         log::info!("  🎛️ Cooperative scheduler initialized");
 
-        // Open stream using cooperative pattern
-        log::info!("  📡 Opening stream to {}:{}...", host, port);
-        let stream = open_cooperative_stream(&scheduler, &host, port)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
-        log::info!("  ✅ Stream opened");
-
-        // Build headers string
-        let mut headers_str = String::new();
-        for (key, value) in &headers {
-            headers_str.push_str(&format!("{}: {}\r\n", key, value));
-        }
-
         // Build HTTP POST request
         let http_request = format!(
             "POST {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n{}\r\n{}",
             path, host, body.len(), headers_str, body
         );
+        let http_request = self.http_padding.pad_request(&isolation_key, http_request);
+
+        let exchange = async {
+            // Open stream using cooperative pattern
+            log::info!("  📡 Opening stream to {}:{}...", host, port);
+            let (target, cached) = self.stream_target(&isolation_key, &host);
+            let stream =
+                open_cooperative_stream(&scheduler, &target, port, protocol::BeginFlags::default())
+                    .await
+                    .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
+            log::info!("  ✅ Stream opened");
+            self.note_connected(
+                &isolation_key,
+                &host,
+                port,
+                exit,
+                stream.connected_address(),
+                cached,
+            );
 
-        let response_bytes =
-            if is_https {
+            let response = if is_https {
                 log::info!("  🔐 Establishing TLS connection...");
 
                 let mut tls_stream = CooperativeTlsStream::new(stream, &host)
@@ -1006,30 +1917,55 @@ impl TorClient {
                 let _ = stream.close().await;
                 response
             };
+            Ok::<_, JsValue>(response)
+        }
+        .await;
+
+        // An attached circuit goes back even when the request failed
+        let response_bytes = match exchange {
+            Ok(response) => response,
+            Err(e) => {
+                if circuit_id.is_some() {
+                    self.reclaim_circuit(scheduler, circuit_id, class);
+                }
+                return Err(e);
+            }
+        };
 
         log::info!("  ✅ Received {} bytes", response_bytes.len());
+        self.note_response(&host, port, is_https, &response_bytes);
+        self.account_transfer(body.len() + response_bytes.len(), None)?;
+        let response_bytes = self
+            .http_padding
+            .strip_response(&isolation_key, response_bytes);
 
         // Try to return circuit to pool for reuse
-        if let Ok(coop_cell) = Rc::try_unwrap(scheduler) {
-            let mut coop = coop_cell.into_inner();
-            if let Some(circuit) = coop.checkout_circuit() {
-                self.circuit_pool.return_circuit(circuit);
-            }
-        }
+        self.reclaim_circuit(scheduler, circuit_id, class);
 
         let response_str = String::from_utf8_lossy(&response_bytes).to_string();
 
         log::info!("✅ [COOP] POST complete: {} bytes", response_str.len());
 
+        self.record_latency(&isolation_key, started_ms);
         Ok(response_str)
     }
 
     /// Make a GET request using the cooperative scheduler
     ///
     /// This is the reliable version that avoids RefCell borrow-across-await issues.
+    /// If the circuit dies partway through a response the server offers as
+    /// ranged (`Accept-Ranges: bytes` with a `Content-Length`), the rest is
+    /// fetched on a new circuit with a Range request and reassembled, up to
+    /// `resume::MAX_RESUMES` times. Requests on a `circuit_id` are never
+    /// moved to another circuit.
     ///
     /// # Arguments
     /// * `url` - Full URL to fetch (http:// or https://)
+    /// * `circuit_id` - Optional ID from `build_custom_circuit()`; sends the
+    ///   request over that circuit instead of a fresh one (errors if it is closed)
+    /// * `options` - Optional `{ integrity: "sha256-...", allow_insecure_http,
+    ///   upgrade_to_https, max_bytes, signal }`; see `fetch()`. On abort
+    ///   the stream is ended with RELAY_END, and a pooled circuit destroyed
     ///
     /// # Returns
     /// The HTTP response body as a string
@@ -1037,223 +1973,263 @@ impl TorClient {
     pub async fn fetch_get_cooperative(
         &mut self,
         url: String,
+        circuit_id: Option<u32>,
+        options: JsValue,
     ) -> std::result::Result<String, JsValue> {
-        use std::cell::RefCell;
-        use std::rc::Rc;
-
-        if !self.bootstrapped {
-            return Err(JsValue::from_str("Client not bootstrapped"));
-        }
-
-        // Parse URL
-        let (host, port, path, is_https) =
-            parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
-
-        let scheme = if is_https { "HTTPS" } else { "HTTP" };
-        log::info!("🌐 [COOP] GET {} via Tor ({})...", url, scheme);
-
-        // Rate limit check
-        if !self.rate_limiter.can_create_circuit() {
-            return Err(JsValue::from_str(
-                "Rate limited: too many circuit requests. Please wait.",
-            ));
-        }
-
-        // Get circuit from pool (prebuilt) or build new one
-        let builder = self
-            .circuit_builder
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
-            .clone();
-
-        let selector = self
-            .relay_selector
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
-            .clone();
-
-        let circuit = self
-            .circuit_pool
-            .get_circuit(&builder, &selector)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Circuit failed: {}", e)))?;
-
-        self.rate_limiter.record_circuit_created(circuit.id);
-
-        // Wrap in cooperative scheduler
-        let scheduler = Rc::new(RefCell::new(CooperativeCircuit::new(circuit)));
-
-        // Open stream
-        let stream = open_cooperative_stream(&scheduler, &host, port)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
-
-        // Build HTTP GET request
-        let http_request = format!(
-            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n\r\n",
-            path, host
-        );
-
-        let response_bytes =
-            if is_https {
-                let mut tls_stream = CooperativeTlsStream::new(stream, &host)
-                    .await
-                    .map_err(|e| JsValue::from_str(&format!("TLS handshake failed: {}", e)))?;
-
-                tls_stream
-                    .write_all(http_request.as_bytes())
-                    .await
-                    .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
-
-                let response = tls_stream.read_to_end().await.map_err(|e| {
-                    JsValue::from_str(&format!("Failed to receive response: {}", e))
-                })?;
-
-                let _ = tls_stream.close().await;
-                response
-            } else {
-                let mut stream = stream;
-
-                stream
-                    .write_all(http_request.as_bytes())
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let options = parse_fetch_options(options)?;
+        let plan = self.http_plan(&url, Some(&options))?;
+        match (
+            self.cooperative_get_url(&plan.url, circuit_id, &options)
+                .await,
+            plan.fallback,
+        ) {
+            (Err(e), Some(fallback)) => {
+                log::warn!(
+                    "🔓 HTTPS upgrade of {} failed ({:?}), retrying over HTTP",
+                    url,
+                    e
+                );
+                self.cooperative_get_url(&fallback, circuit_id, &options)
                     .await
-                    .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
-
-                let response = stream.read_to_end().await.map_err(|e| {
-                    JsValue::from_str(&format!("Failed to receive response: {}", e))
-                })?;
-
-                let _ = stream.close().await;
-                response
-            };
-
-        // Try to return circuit to pool for reuse
-        if let Ok(coop_cell) = Rc::try_unwrap(scheduler) {
-            let mut coop = coop_cell.into_inner();
-            if let Some(circuit) = coop.checkout_circuit() {
-                self.circuit_pool.return_circuit(circuit);
             }
+            (result, _) => result,
         }
-
-        let response_str = String::from_utf8_lossy(&response_bytes).to_string();
-        log::info!("✅ [COOP] GET complete: {} bytes", response_str.len());
-
-        Ok(response_str)
     }
 
     /// Cooperative GET that returns raw bytes (Uint8Array)
     ///
-    /// Same as fetch_get_cooperative but returns binary data instead of a string.
+    /// Same as fetch_get_cooperative (including the `circuit_id` and
+    /// `options` arguments) but returns binary data instead of a string.
     /// This preserves binary content (images, fonts, compressed responses) that
     /// would be corrupted by UTF-8 lossy conversion.
     #[wasm_bindgen]
     pub async fn fetch_get_cooperative_bytes(
         &mut self,
         url: String,
+        circuit_id: Option<u32>,
+        options: JsValue,
     ) -> std::result::Result<js_sys::Uint8Array, JsValue> {
-        use std::cell::RefCell;
-        use std::rc::Rc;
+        let options = parse_fetch_options(options)?;
+        let response = self
+            .cooperative_get_bytes(&url, circuit_id, &options)
+            .await?;
+        Ok(js_sys::Uint8Array::from(response.as_slice()))
+    }
 
-        if !self.bootstrapped {
-            return Err(JsValue::from_str("Client not bootstrapped"));
-        }
+    /// GET `url` and resolve to the response body as a Uint8Array
+    ///
+    /// Unlike `fetch_get_cooperative_bytes()` this returns only the body,
+    /// with chunked framing and gzip/deflate coding removed, follows
+    /// redirects, and rejects a non-2xx status. Limits come from the
+    /// `http_client` config section.
+    ///
+    /// # Arguments
+    /// * `url` - Full URL to fetch (http:// or https://)
+    /// * `options` - Optional fetch options; see `fetch_get_cooperative()`
+    #[wasm_bindgen]
+    pub async fn fetch_bytes(
+        &mut self,
+        url: String,
+        options: JsValue,
+    ) -> std::result::Result<js_sys::Uint8Array, JsValue> {
+        let response = self.fetch_decoded(&url, options, true).await?;
+        Ok(js_sys::Uint8Array::from(response.body.as_slice()))
+    }
 
-        let (host, port, path, is_https) =
-            parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+    /// GET `url` and resolve to the whole response as an object
+    ///
+    /// Resolves to `{ status, headers: [[name, value], ...], body:
+    /// Uint8Array }`, so binary bodies (images, protobuf) arrive intact. The
+    /// body has chunked framing and gzip/deflate coding removed and is cut
+    /// to `Content-Length`; a body shorter than that rejects. Redirects are
+    /// followed as in `fetch_bytes()`, but any final status resolves.
+    /// `integrity` is only checked on a 2xx response.
+    ///
+    /// # Arguments
+    /// * `url` - Full URL to fetch (http:// or https://)
+    /// * `options` - Optional fetch options; see `fetch_get_cooperative()`
+    #[wasm_bindgen]
+    pub async fn fetch_response(
+        &mut self,
+        url: String,
+        options: JsValue,
+    ) -> std::result::Result<JsValue, JsValue> {
+        let response = self.fetch_decoded(&url, options, false).await?;
+        response_object(&response)
+    }
 
-        let scheme = if is_https { "HTTPS" } else { "HTTP" };
-        log::info!("🌐 [COOP-BIN] GET {} via Tor ({})...", url, scheme);
+    /// GET `url` and resolve to its body parsed as JSON
+    ///
+    /// The body is decoded in the charset named by `Content-Type` (UTF-8 if
+    /// none), and rejects if it is malformed rather than replacing bytes.
+    /// Redirects and content coding are handled as in `fetch_bytes()`; a
+    /// non-2xx status rejects.
+    ///
+    /// # Arguments
+    /// * `url` - Full URL to fetch (http:// or https://)
+    /// * `options` - Optional fetch options; see `fetch_get_cooperative()`
+    #[wasm_bindgen]
+    pub async fn fetch_json(
+        &mut self,
+        url: String,
+        options: JsValue,
+    ) -> std::result::Result<JsValue, JsValue> {
+        let response = self.fetch_decoded(&url, options, true).await?;
+        let text = response.text()?;
+        js_sys::JSON::parse(&text)
+            .map_err(|e| JsValue::from_str(&format!("Invalid JSON from {}: {:?}", url, e)))
+    }
 
-        if !self.rate_limiter.can_create_circuit() {
-            return Err(JsValue::from_str(
-                "Rate limited: too many circuit requests. Please wait.",
-            ));
+    /// Send an HTTP request with any method through Tor
+    ///
+    /// # Arguments
+    /// * `request` - `{ method, url, headers, body, timeout }`: `method`
+    ///   defaults to GET (CONNECT and TRACE are refused); `headers` is an
+    ///   object or an array of `[name, value]` pairs; `body` is a string,
+    ///   Uint8Array or ArrayBuffer (not allowed on GET and HEAD); `timeout`
+    ///   is in milliseconds. `Host`, `Content-Length`, `Connection` and
+    ///   `Transfer-Encoding` are set by the client and can't be given.
+    /// * `options` - Optional fetch options; see `fetch_get_cooperative()`.
+    ///   Only GET and HEAD fall back to plain HTTP after a failed upgrade,
+    ///   and their `auth` applies only if `request` has none
+    ///
+    /// # Returns
+    /// `{ status, headers: [[name, value], ...], body: Uint8Array }`, the
+    /// body with chunked framing removed. A timeout rejects with a timeout
+    /// error, after ending the stream as an abort through `signal` would.
+    #[wasm_bindgen]
+    pub async fn fetch_request(
+        &mut self,
+        request: JsValue,
+        options: JsValue,
+    ) -> std::result::Result<JsValue, JsValue> {
+        self.ensure_ready()?;
+        let mut request: HttpRequest = serde_wasm_bindgen::from_value(request)
+            .map_err(|e| JsValue::from_str(&format!("Invalid request: {}", e)))?;
+        let mut options = parse_fetch_options(options)?;
+        if request.auth.is_none() {
+            request.auth = options.auth.take();
+        }
+        request.validate()?;
+        self.admit_request().await?;
+
+        let timed_out = Rc::new(std::cell::Cell::new(false));
+        let _timer = request.timeout.map(|ms| {
+            let token = options.cancel.get_or_insert_with(CancelToken::new).clone();
+            let timed_out = timed_out.clone();
+            gloo_timers::callback::Timeout::new(ms, move || {
+                timed_out.set(true);
+                token.cancel();
+            })
+        });
+
+        let plan = self.http_plan(&request.url, Some(&options))?;
+        let result = match (
+            self.cooperative_request_url(&plan.url, &request, &options)
+                .await,
+            plan.fallback,
+        ) {
+            (Err(e), Some(fallback)) if request.is_safe() && !timed_out.get() => {
+                log::warn!(
+                    "🔓 HTTPS upgrade of {} failed ({:?}), retrying over HTTP",
+                    request.url,
+                    e
+                );
+                self.cooperative_request_url(&fallback, &request, &options)
+                    .await
+            }
+            (result, _) => result,
+        };
+        if result.is_err() && timed_out.get() {
+            return Err(TorError::Timeout.into());
         }
 
-        // Get circuit from pool (prebuilt) or build new one
-        let builder = self
-            .circuit_builder
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
-            .clone();
-
-        let selector = self
-            .relay_selector
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
-            .clone();
-
-        let circuit = self
-            .circuit_pool
-            .get_circuit(&builder, &selector)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Circuit failed: {}", e)))?;
-
-        self.rate_limiter.record_circuit_created(circuit.id);
-
-        let scheduler = Rc::new(RefCell::new(CooperativeCircuit::new(circuit)));
-
-        let stream = open_cooperative_stream(&scheduler, &host, port)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
+        let response = HttpResponse::parse(&result?)?;
+        let value = serde_wasm_bindgen::to_value(&serde_json::json!({
+            "status": response.status,
+            "headers": response.headers,
+        }))
+        .unwrap_or(JsValue::NULL);
+        let body = js_sys::Uint8Array::from(response.body.as_slice());
+        js_sys::Reflect::set(&value, &JsValue::from_str("body"), &body)?;
+        Ok(value)
+    }
 
-        let http_request = format!(
-            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n\r\n",
-            path, host
+    /// Download `url` into the OPFS file `opfs_path`, creating it and its
+    /// directories, without holding the body in memory
+    ///
+    /// `options` are those of `fetch_get_cooperative_bytes()`, except
+    /// `integrity`, plus `resume: true` to continue a file an earlier call
+    /// left partial instead of replacing it. `on_progress` is called with
+    /// `{path, bytes, total}` as the file grows.
+    ///
+    /// Resolves to `{path, bytes, total, resumed_from}`. A download that
+    /// stops early (or is aborted through `signal`) keeps what it wrote and
+    /// fails, so it can be resumed.
+    #[wasm_bindgen]
+    pub async fn fetch_to_file(
+        &mut self,
+        url: String,
+        opfs_path: String,
+        options: JsValue,
+        on_progress: Option<js_sys::Function>,
+    ) -> std::result::Result<JsValue, JsValue> {
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let options = parse_fetch_options(options)?;
+        if options.integrity.is_some() {
+            return Err(JsValue::from_str(
+                "fetch_to_file() does not check integrity; hash the file instead",
+            ));
+        }
+        let plan = self.http_plan(&url, Some(&options))?;
+
+        let file = OpfsFile::open(&opfs_path).await?;
+        let validator_key =
+            self.storage_config
+                .key(&format!("{}:{}", opfs_download::VALIDATOR_KEY, opfs_path));
+        let mut sink = FileSink::new(
+            file,
+            &opfs_path,
+            validator_key,
+            options.resume.unwrap_or(false),
+            on_progress,
         );
-
-        let response_bytes =
-            if is_https {
-                let mut tls_stream = CooperativeTlsStream::new(stream, &host)
-                    .await
-                    .map_err(|e| JsValue::from_str(&format!("TLS handshake failed: {}", e)))?;
-
-                tls_stream
-                    .write_all(http_request.as_bytes())
-                    .await
-                    .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
-
-                let response = tls_stream.read_to_end().await.map_err(|e| {
-                    JsValue::from_str(&format!("Failed to receive response: {}", e))
-                })?;
-
-                let _ = tls_stream.close().await;
-                response
-            } else {
-                let mut stream = stream;
-
-                stream
-                    .write_all(http_request.as_bytes())
+        let resumed_from = sink.written();
+
+        match (
+            self.cooperative_file_url(&plan.url, &options, &mut sink)
+                .await,
+            plan.fallback,
+        ) {
+            // Only before anything of the upgraded response was written
+            (Err(e), Some(fallback)) if sink.status().is_none() => {
+                log::warn!(
+                    "🔓 HTTPS upgrade of {} failed ({:?}), retrying over HTTP",
+                    url,
+                    e
+                );
+                self.cooperative_file_url(&fallback, &options, &mut sink)
                     .await
-                    .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
-
-                let response = stream.read_to_end().await.map_err(|e| {
-                    JsValue::from_str(&format!("Failed to receive response: {}", e))
-                })?;
-
-                let _ = stream.close().await;
-                response
-            };
-
-        // Try to return circuit to pool for reuse
-        if let Ok(coop_cell) = Rc::try_unwrap(scheduler) {
-            let mut coop = coop_cell.into_inner();
-            if let Some(circuit) = coop.checkout_circuit() {
-                self.circuit_pool.return_circuit(circuit);
             }
-        }
-
-        log::info!("✅ [COOP-BIN] GET complete: {} bytes", response_bytes.len());
-
-        let arr = js_sys::Uint8Array::new_with_length(response_bytes.len() as u32);
-        arr.copy_from(&response_bytes);
-        Ok(arr)
+            (result, _) => result,
+        }?;
+
+        Ok(serde_wasm_bindgen::to_value(&serde_json::json!({
+            "path": opfs_path,
+            "bytes": sink.written(),
+            "total": sink.total(),
+            "resumed_from": resumed_from,
+        }))
+        .unwrap_or(JsValue::NULL))
     }
 
     /// Get number of cached circuits
     #[wasm_bindgen]
     pub fn circuit_count(&self) -> usize {
-        self.circuit_cache.len()
+        self.circuit_cache.with(|cache| cache.len())
     }
 
     /// Check if client is ready
@@ -1271,151 +2247,3177 @@ impl TorClient {
     /// - "none": Single circuit for all (not recommended)
     #[wasm_bindgen]
     pub fn set_isolation_policy(&mut self, policy: &str) {
-        let isolation_type = match policy.to_lowercase().as_str() {
-            "per_domain" | "domain" => IsolationType::PerDomain,
-            "per_destination" | "destination" => IsolationType::PerDestination,
-            "per_request" | "request" | "paranoid" => IsolationType::PerRequest,
-            "none" | "global" | "off" => IsolationType::None,
-            _ => {
-                log::warn!("Unknown isolation policy '{}', using per_domain", policy);
-                IsolationType::PerDomain
-            }
-        };
+        let isolation_type = IsolationType::from_name(policy).unwrap_or_else(|| {
+            log::warn!("Unknown isolation policy '{}', using per_domain", policy);
+            IsolationType::PerDomain
+        });
+        self.apply_isolation_policy(isolation_type);
+    }
 
-        // Create new cache with new policy
-        let config = IsolationConfig {
-            policy: isolation_type,
-            ..IsolationConfig::default()
-        };
+    /// Get the current isolation policy
+    #[wasm_bindgen]
+    pub fn get_isolation_policy(&self) -> String {
+        format!("{:?}", self.circuit_cache.with(|cache| cache.policy()))
+    }
+
+    /// Rotate the circuits for `host` on a schedule of its own
+    ///
+    /// Takes `{ every_secs, every_requests }` (at least one); whichever is
+    /// reached first retires every circuit cached for the host, in place of
+    /// the isolation age and request limits. Under the `none` isolation
+    /// policy, name the host `"global"`. Call `prepare_rotations()`
+    /// periodically so a replacement is ready when rotation comes.
+    #[wasm_bindgen]
+    pub fn set_rotation_policy(
+        &mut self,
+        host: &str,
+        policy: JsValue,
+    ) -> std::result::Result<(), JsValue> {
+        let policy: RotationPolicy = serde_wasm_bindgen::from_value(policy)
+            .map_err(|e| JsValue::from_str(&format!("Invalid rotation policy: {}", e)))?;
+        self.circuit_cache
+            .with(|cache| cache.set_rotation_policy(host, policy))?;
+        log::info!("🔁 Rotation policy for '{}': {:?}", host, policy);
+        Ok(())
+    }
 
-        // Clear existing circuits when policy changes
-        self.circuit_cache.clear();
-        self.circuit_cache = CircuitCache::new(config);
+    /// Remove the rotation schedule for `host`; returns whether it had one
+    #[wasm_bindgen]
+    pub fn clear_rotation_policy(&mut self, host: &str) -> bool {
+        self.circuit_cache
+            .with(|cache| cache.clear_rotation_policy(host))
+    }
 
-        log::info!("🔒 Circuit isolation policy set to: {:?}", isolation_type);
+    /// Retire the circuits for `host` now
+    ///
+    /// The next request to it uses the standby circuit if
+    /// `prepare_rotations()` built one, or builds a new circuit. Streams
+    /// already open finish on the old circuit. `port` (default 443) matters
+    /// only under the `per_destination` policy. Returns whether any circuit
+    /// was cached for the host.
+    #[wasm_bindgen]
+    pub fn rotate_circuits_for(&mut self, host: &str, port: Option<u16>) -> bool {
+        let key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(host, port.unwrap_or(443)));
+        self.circuit_cache.with(|cache| cache.rotate(&key))
     }
 
-    /// Get the current isolation policy
+    /// Build standby circuits for hosts whose rotation is coming up
+    ///
+    /// Hosts with a rotation policy get a replacement circuit once their
+    /// current one has used 80% of its time or request budget, so the
+    /// rotation itself adds no latency. Onion services are skipped (their
+    /// circuits are rebuilt on demand). Call this periodically, e.g. every
+    /// 30s. Returns the number of circuits built.
     #[wasm_bindgen]
-    pub fn get_isolation_policy(&self) -> String {
-        format!("{:?}", self.circuit_cache.policy())
+    pub async fn prepare_rotations(&mut self) -> std::result::Result<usize, JsValue> {
+        if self.dormancy.suppress("rotation standbys") {
+            return Ok(0);
+        }
+        self.ensure_ready()?;
+        let mut built = 0;
+        for key in self.circuit_cache.with(|cache| cache.due_replacements()) {
+            let Some(primary) = self.circuit_cache.with(|cache| cache.primary(&key)) else {
+                continue;
+            };
+            // Skipped while a background keepalive probe has it; the next
+            // call tries again
+            let long_lived = match primary.try_borrow() {
+                Ok(current) if current.has_service_hop() => continue,
+                Ok(current) => protocol::StreamLifetime::LongLived.suits(&current),
+                Err(_) => continue,
+            };
+            drop(primary);
+            let lifetime = if long_lived {
+                protocol::StreamLifetime::LongLived
+            } else {
+                protocol::StreamLifetime::Short
+            };
+
+            let circuit = match self.circuit_pool.take(PortClass::for_lifetime(lifetime)) {
+                Some(circuit) => circuit,
+                None => {
+                    let builder = self
+                        .circuit_builder
+                        .as_ref()
+                        .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
+                        .clone();
+                    let selector = self
+                        .relay_selector
+                        .as_ref()
+                        .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
+                        .clone()
+                        .for_stream(lifetime);
+                    let result = builder.build_circuit(&selector).await;
+                    self.persist_guard_outcomes();
+                    match result {
+                        Ok(circuit) => circuit,
+                        Err(e) => {
+                            log::warn!("⚠️ Standby circuit for '{}' failed: {}", key.as_str(), e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            self.rate_limiter
+                .record_circuit_created_for(key.as_str(), circuit.id);
+            if self
+                .circuit_cache
+                .with(|cache| cache.store_standby(key, circuit))
+            {
+                built += 1;
+            }
+        }
+        Ok(built)
+    }
+
+    /// Run a control-port style command given as JSON
+    ///
+    /// Supports `GETINFO` (`circuit-status`, `stream-status`, `version`),
+    /// `SIGNAL` (`NEWNYM`, `CLEARDNSCACHE`) and `SETCONF` (`IsolationPolicy`,
+    /// `HttpPadding`, `MemoryBudget`); see the `control` module for the JSON
+    /// shapes. Returns `{ status, message, values }` with control-port status
+    /// codes (250 on success); errors are replies, not exceptions.
+    #[wasm_bindgen]
+    pub fn command(&mut self, json: String) -> JsValue {
+        let reply = if self.shut_down {
+            ControlReply::error(control::STATUS_UNAVAILABLE, CLIENT_SHUT_DOWN)
+        } else {
+            match ControlCommand::parse(&json) {
+                Ok(command) => self.run_control_command(command),
+                Err(reply) => reply,
+            }
+        };
+        if !reply.is_ok() {
+            log::warn!(
+                "🎛️ Control command failed: {} {}",
+                reply.status,
+                reply.message
+            );
+        }
+        serde_wasm_bindgen::to_value(&reply).unwrap_or(JsValue::NULL)
     }
 
     /// Clear all cached circuits (forces new circuits for all domains)
     #[wasm_bindgen]
     pub fn clear_circuits(&mut self) {
-        self.circuit_cache.clear();
+        self.circuit_cache.with(|cache| cache.clear());
+        self.dns_cache.clear();
+        self.onion_services.clear();
+        self.origin_hints.clear();
+        self.latency.clear_destinations();
         self.circuit_pool.clear();
         log::info!("🗑️ All cached circuits cleared");
     }
 
-    /// Get circuit pool statistics
+    /// Switch to a fresh identity
+    ///
+    /// Cancels background tasks tied to the old circuits and drops every
+    /// cached and pooled circuit, so later requests cannot be linked to
+    /// earlier ones. Configuration changes `apply_config()` held back (bridge,
+    /// connection pooling, guard count) take effect here.
     #[wasm_bindgen]
-    pub fn pool_stats(&self) -> JsValue {
-        let stats = self.circuit_pool.get_stats();
-        serde_wasm_bindgen::to_value(&serde_json::json!({
-            "pool_size": stats.current_pool_size,
-            "hits": stats.pool_hits,
-            "misses": stats.pool_misses,
-            "circuits_built": stats.circuits_built,
-            "circuits_expired": stats.circuits_expired,
-        }))
-        .unwrap_or(JsValue::NULL)
+    pub fn new_identity(&mut self) {
+        let cancelled = self.tasks.cancel_all("new identity");
+        self.clear_circuits();
+        self.apply_pending_config();
+        log::info!("🆕 New identity ({} background tasks cancelled)", cancelled);
     }
 
-    /// Get circuit cache statistics
+    /// Shut the client down and release everything it holds
+    ///
+    /// In order: cancels background tasks, sends DESTROY on every cached and
+    /// pooled circuit and closes its TLS link and transport (which ends any
+    /// streams still open on it), then persists guard state. Errors while
+    /// tearing down individual circuits are logged, not returned.
+    ///
+    /// This is terminal: afterwards `is_ready()` is false, `bootstrap()` and
+    /// every request method fail with "Client has been shut down", and status
+    /// reports `shut_down: true`. Create a new `TorClient` to reconnect.
+    /// Calling `shutdown()` again is a no-op.
     #[wasm_bindgen]
-    pub fn get_circuit_stats(&self) -> JsValue {
-        let stats = self.circuit_cache.stats();
-        serde_wasm_bindgen::to_value(&serde_json::json!({
-            "cached_circuits": stats.cached_circuits,
-            "total_requests": stats.total_requests,
-            "oldest_circuit_age_secs": stats.oldest_circuit_age_secs,
-            "policy": format!("{:?}", stats.policy),
-        }))
-        .unwrap_or(JsValue::NULL)
+    pub async fn shutdown(&mut self) -> std::result::Result<(), JsValue> {
+        if self.shut_down {
+            return Ok(());
+        }
+        self.shut_down = true;
+        self.bootstrapped = false;
+        log::info!("🛑 Shutting down Tor client...");
+
+        let cancelled = self.tasks.shutdown("client shutdown");
+
+        self.dns_cache.clear();
+        self.onion_services.clear();
+        self.origin_hints.clear();
+        let circuit_count = self.destroy_all_circuits().await;
+
+        let guard_state = self.guard_state.with(|g| g.clone());
+        if let Err(e) = self.guard_persistence.save(&guard_state).await {
+            log::warn!("⚠️ Failed to save guard state on shutdown: {}", e);
+        }
+
+        self.circuit_builder = None;
+        self.relay_selector = None;
+        self.consensus = None;
+
+        log::info!(
+            "✅ Tor client shut down ({} circuits destroyed, {} background tasks cancelled)",
+            circuit_count,
+            cancelled
+        );
+        Ok(())
     }
 
-    /// Get guard state information
+    /// Whether `shutdown()` has been called
     #[wasm_bindgen]
-    pub fn get_guard_info(&self) -> JsValue {
-        let now = web_time::SystemTime::now()
-            .duration_since(web_time::SystemTime::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down
+    }
 
-        let days_until_rotation = if self.guard_state.rotate_after > now {
-            (self.guard_state.rotate_after - now) / (24 * 60 * 60)
-        } else {
-            0
-        };
+    /// Enter or leave dormant mode, for an app going to the background
+    ///
+    /// Going dormant cancels background tasks, sends DESTROY on every
+    /// cached, pooled and custom circuit and closes the shared bridge
+    /// socket. Until the client wakes, `refill_circuit_pool()`,
+    /// `prepare_rotations()`, `probe_idle_circuits()`, `bridge_heartbeat()`
+    /// and `refresh_blocklist()` return without touching the network, so
+    /// the app's timers can keep running. The consensus, guards and
+    /// verified link handshakes are kept: waking costs a circuit build, not
+    /// a bootstrap.
+    ///
+    /// The client wakes by itself on its next request or `bootstrap()`,
+    /// when the page becomes visible again, or when a periodic background
+    /// sync tagged `"tor-wasm-wake"` fires. The sync is registered here
+    /// where the browser allows it, at most every `wake_interval_secs`
+    /// (default 12 hours); a service worker running the client receives it
+    /// directly, one serving a page forwards it with
+    /// `client.postMessage("tor-wasm-wake")`.
+    ///
+    /// Returns `dormant_status()`.
+    #[wasm_bindgen]
+    pub async fn set_dormant(
+        &mut self,
+        dormant: bool,
+        wake_interval_secs: Option<u32>,
+    ) -> std::result::Result<JsValue, JsValue> {
+        if self.shut_down {
+            return Err(JsValue::from_str(CLIENT_SHUT_DOWN));
+        }
+        if !dormant {
+            self.dormancy.wake(dormant::WakeReason::Explicit);
+            return Ok(self.dormant_status());
+        }
+        if !self.dormancy.enter(runtime::timer::now_ms()) {
+            return Ok(self.dormant_status());
+        }
 
-        serde_wasm_bindgen::to_value(&serde_json::json!({
-            "guard_count": self.guard_state.guards.len(),
-            "usable_guards": self.guard_state.usable_guard_count(),
-            "days_until_rotation": days_until_rotation,
-            "bad_guard_count": self.guard_state.bad_guards.len(),
-            "selected_at": self.guard_state.selected_at,
-            "rotate_after": self.guard_state.rotate_after,
-        }))
-        .unwrap_or(JsValue::NULL)
+        let cancelled = self.tasks.cancel_all("dormant");
+        let destroyed = self.destroy_all_circuits().await;
+        self.network.close_bridge_session();
+        self.persist_guard_outcomes();
+        self.dormancy
+            .install_page_hooks(wake_interval_secs.unwrap_or(dormant::DEFAULT_WAKE_INTERVAL_SECS));
+        log::info!(
+            "💤 Dormant ({} circuits destroyed, {} background tasks cancelled)",
+            destroyed,
+            cancelled
+        );
+        Ok(self.dormant_status())
     }
 
-    /// Force guard rotation (selects new guards)
+    /// Whether the client is dormant, and how it has been
+    ///
+    /// Returns `{ dormant, since_ms, times_dormant, wakes, last_wake,
+    /// suppressed }`, where `last_wake` is `"explicit"`, `"request"`,
+    /// `"visible"` or `"periodic_sync"` and `suppressed` counts background
+    /// calls skipped while dormant.
     #[wasm_bindgen]
-    pub async fn rotate_guards(&mut self) -> std::result::Result<(), JsValue> {
-        if !self.bootstrapped {
-            return Err(JsValue::from_str("Client not bootstrapped"));
-        }
+    pub fn dormant_status(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.dormancy.status()).unwrap_or(JsValue::NULL)
+    }
 
-        let consensus = self
-            .consensus
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("No consensus"))?;
+    /// Recent background task events (started, completed, failed, ...)
+    #[wasm_bindgen]
+    pub fn task_events(&self) -> JsValue {
+        let events: Vec<serde_json::Value> = self
+            .tasks
+            .events()
+            .iter()
+            .map(|e| {
+                serde_json::json!({
+                    "task": e.task,
+                    "event": e.kind_str(),
+                    "detail": e.detail(),
+                })
+            })
+            .collect();
+        serde_wasm_bindgen::to_value(&events).unwrap_or(JsValue::NULL)
+    }
 
-        log::info!("🔄 Forcing guard rotation...");
+    /// Simulate `samples` path selections against the current consensus
+    ///
+    /// Runs offline (no circuits are built) and reports the guard and exit
+    /// distribution plus how many paths put two hops in the same /16, AS or
+    /// family. Samples are capped at 10,000.
+    #[wasm_bindgen]
+    pub fn audit_path_selection(&self, samples: u32) -> std::result::Result<JsValue, JsValue> {
+        let selector = self
+            .relay_selector
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?;
 
-        self.guard_state
-            .select_guards(&consensus.relays)
-            .map_err(|e| JsValue::from_str(&format!("Guard selection failed: {}", e)))?;
+        let report = path_audit::audit_path_selection(selector, samples as usize);
+        log::info!(
+            "🔍 Path audit: {} samples, top guard share {:.1}%, {} subnet overlaps",
+            report.samples,
+            report.guards.max_share * 100.0,
+            report.subnet_overlaps
+        );
+        serde_wasm_bindgen::to_value(&report).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
 
-        // Save the new state
-        if let Err(e) = self.guard_persistence.save(&self.guard_state).await {
-            log::warn!("⚠️ Failed to save guard state: {}", e);
+    /// Test whether a candidate bridge can reach the Tor network
+    ///
+    /// Takes a JSON config `{ transport, url, path, broker_url,
+    /// bridge_b_pubkey, relay, relay_address, timeout_ms }` and runs
+    /// transport connect, TLS and the link handshake on a throwaway
+    /// connection. The client's own bridge, pools and guards are untouched.
+    ///
+    /// The target relay is `relay_address` if given, else the `relay`
+    /// fingerprint from the consensus, else a guard the client would pick.
+    /// Returns `{ ok, transport, relay, failed_stage, error, connect_ms,
+    /// tls_ms, handshake_ms, total_ms }`; only invalid configs throw.
+    #[wasm_bindgen]
+    pub async fn test_bridge(&self, config_json: String) -> std::result::Result<JsValue, JsValue> {
+        if self.shut_down {
+            return Err(JsValue::from_str(CLIENT_SHUT_DOWN));
         }
 
-        // Update relay selector
-        if let Some(ref mut selector) = self.relay_selector {
-            selector.set_preferred_guards(
-                self.guard_state
-                    .usable_guards()
-                    .into_iter()
-                    .cloned()
-                    .collect(),
-            );
-        }
+        let config = BridgeTestConfig::from_json(&config_json)?;
+        let bridge = config.bridge_config()?;
+
+        let target = if let Some(address) = config.relay_address()? {
+            bridge_test::BridgeTestTarget {
+                address,
+                name: address.ip().to_string(),
+                fingerprint: config
+                    .relay
+                    .as_ref()
+                    .map(|fp| fp.trim().trim_start_matches('$').to_uppercase()),
+            }
+        } else if let Some(ref fingerprint) = config.relay {
+            bridge_test::BridgeTestTarget::from(&self.find_relay(fingerprint)?)
+        } else {
+            let selector = self.relay_selector.as_ref().ok_or_else(|| {
+                JsValue::from_str("No relay to test against: bootstrap first or set relay_address")
+            })?;
+            let guard = selector
+                .select_guards(1)
+                .into_iter()
+                .next()
+                .ok_or_else(|| JsValue::from_str("No guard relays available"))?;
+            bridge_test::BridgeTestTarget::from(guard)
+        };
 
-        log::info!("✅ Guard rotation complete");
-        Ok(())
+        let report = bridge_test::run_bridge_test(&bridge, &target, config.timeout_ms()).await;
+        serde_wasm_bindgen::to_value(&report).map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
-    /// Clear guard state (for testing/debugging)
+    /// Request a CAPTCHA challenge from a Moat-style bridge distributor
+    ///
+    /// Returns `{ transport, image, challenge }`; `image` is base64. Pass the
+    /// whole object back to `fetch_distributed_bridges` with the solution.
     #[wasm_bindgen]
-    pub async fn clear_guards(&mut self) -> std::result::Result<(), JsValue> {
-        log::info!("🗑️ Clearing guard state...");
+    pub async fn request_bridge_challenge(
+        &self,
+        endpoint: String,
+    ) -> std::result::Result<JsValue, JsValue> {
+        let distributor = BridgeDistributor::new(&endpoint, Arc::clone(&self.storage));
+        let challenge = distributor.request_challenge().await?;
+        serde_wasm_bindgen::to_value(&challenge).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
 
-        self.guard_state = GuardState::new();
+    /// Submit a CAPTCHA solution (or third-party CAPTCHA token) and fetch bridges
+    ///
+    /// Usable bridges are persisted and returned as `[{ line, transport, url }]`.
+    /// The client keeps its current bridge; reconnect with one of the URLs
+    /// (after `test_bridge`, ideally) to switch.
+    #[wasm_bindgen]
+    pub async fn fetch_distributed_bridges(
+        &self,
+        endpoint: String,
+        challenge: JsValue,
+        solution: String,
+    ) -> std::result::Result<JsValue, JsValue> {
+        let challenge: BridgeChallenge = serde_wasm_bindgen::from_value(challenge)
+            .map_err(|e| JsValue::from_str(&format!("Invalid challenge: {}", e)))?;
+        let distributor = BridgeDistributor::new(&endpoint, Arc::clone(&self.storage));
+        let bridges = distributor.fetch_bridges(&challenge, &solution).await?;
+        serde_wasm_bindgen::to_value(&bridges).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
 
-        if let Err(e) = self.guard_persistence.clear().await {
-            log::warn!("⚠️ Failed to clear saved guard state: {}", e);
+    /// Bridges saved by the last `fetch_distributed_bridges` call
+    ///
+    /// Returns `{ endpoint, fetched_at, bridges }`, or `null` if none are stored.
+    #[wasm_bindgen]
+    pub async fn stored_bridges(&self) -> std::result::Result<JsValue, JsValue> {
+        match BridgeDistributor::load(&self.storage).await? {
+            Some(stored) => serde_wasm_bindgen::to_value(&serde_json::json!({
+                "endpoint": stored.endpoint,
+                "fetched_at": stored.fetched_at,
+                "bridges": stored.bridges(),
+            }))
+            .map_err(|e| JsValue::from_str(&e.to_string())),
+            None => Ok(JsValue::NULL),
         }
+    }
 
-        // Clear preferred guards from relay selector
-        if let Some(ref mut selector) = self.relay_selector {
-            selector.set_preferred_guards(Vec::new());
+    /// Configure HTTP-level request padding
+    ///
+    /// `config_json`: `{ enabled, bucket_bytes, max_random_bytes, header }`
+    /// (defaults: off, 512, 128, `"X-Padding"`). With `url`, the settings
+    /// apply only to that URL's isolation key; otherwise they become the
+    /// default for every key without its own settings. Per-key settings are
+    /// dropped when the isolation policy changes.
+    #[wasm_bindgen]
+    pub fn set_http_padding(
+        &mut self,
+        config_json: String,
+        url: Option<String>,
+    ) -> std::result::Result<(), JsValue> {
+        let config = HttpPaddingConfig::from_json(&config_json)?;
+        match url {
+            Some(url) => {
+                let (host, port, _, _) = parse_url(&url)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+                let key = self
+                    .circuit_cache
+                    .with(|cache| cache.isolation_key(&host, port));
+                log::info!(
+                    "📦 HTTP padding {} for '{}'",
+                    if config.enabled { "on" } else { "off" },
+                    key.as_str()
+                );
+                self.http_padding.set_for(&key, config);
+            }
+            None => {
+                log::info!(
+                    "📦 HTTP padding {} by default",
+                    if config.enabled { "on" } else { "off" }
+                );
+                self.http_padding.set_default(config);
+            }
         }
+        Ok(())
+    }
 
-        log::info!("✅ Guard state cleared");
+    /// Configure a random delay before each request is sent
+    ///
+    /// `config_json`: `{ enabled, min_ms, max_ms }` (defaults: off, 0, 300;
+    /// `max_ms` at most 5000). The delay is drawn uniformly per request and
+    /// taken before the stream is opened, so the first cell leaves later
+    /// than the click that caused it. With `url`, the settings apply only to
+    /// that URL's isolation key; per-key settings are dropped when the
+    /// isolation policy changes.
+    #[wasm_bindgen]
+    pub fn set_request_jitter(
+        &mut self,
+        config_json: String,
+        url: Option<String>,
+    ) -> std::result::Result<(), JsValue> {
+        let config = RequestJitterConfig::from_json(&config_json)?;
+        match url {
+            Some(url) => {
+                let (host, port, _, _) = parse_url(&url)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+                let key = self
+                    .circuit_cache
+                    .with(|cache| cache.isolation_key(&host, port));
+                log::info!(
+                    "⏱️ Request jitter {} for '{}'",
+                    if config.enabled { "on" } else { "off" },
+                    key.as_str()
+                );
+                self.request_jitter.set_for(&key, config);
+            }
+            None => {
+                log::info!(
+                    "⏱️ Request jitter {} by default",
+                    if config.enabled { "on" } else { "off" }
+                );
+                self.request_jitter.set_default(config);
+            }
+        }
+        Ok(())
+    }
+
+    /// Request jitter statistics: `{ requests_delayed, total_delay_ms,
+    /// max_delay_ms }`
+    #[wasm_bindgen]
+    pub fn get_request_jitter_stats(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.request_jitter.stats()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Set minimum requirements for relays on new circuits
+    ///
+    /// `config_json`: `{ min_bandwidth, require_fast, require_stable_long_lived,
+    /// long_lived_ports }` (defaults: 0, true, true, Tor's `LongLivedPorts`
+    /// plus IMAP),
+    /// omitted fields taking their defaults. Circuits already built are kept.
+    #[wasm_bindgen]
+    pub fn set_relay_requirements(
+        &mut self,
+        config_json: String,
+    ) -> std::result::Result<(), JsValue> {
+        let requirements = protocol::RelayRequirements::from_json(&config_json)?;
+        self.apply_relay_requirements(requirements);
         Ok(())
     }
+
+    /// Current relay requirements, in the format `set_relay_requirements` takes
+    #[wasm_bindgen]
+    pub fn relay_requirements(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.relay_requirements).unwrap_or(JsValue::NULL)
+    }
+
+    /// The configuration in effect, in the format `with_config` takes
+    ///
+    /// Reflects changes made through the individual setters too.
+    #[wasm_bindgen]
+    pub fn get_config(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.config()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Change the running client's configuration
+    ///
+    /// `config_json` is a complete configuration as `with_config` takes;
+    /// omitted sections reset to their defaults, so start from
+    /// `get_config()`. The whole config is validated before anything
+    /// changes. Timeouts, logging, padding, isolation (dropping cached
+    /// circuits), pool, keepalive, relay requirements and rate limits apply
+    /// immediately; the bridge, connection pooling and guard count wait for
+    /// the next `new_identity()`. Returns `{ applied, deferred }` and emits
+    /// the same lists as a `config_changed` event.
+    #[wasm_bindgen]
+    pub fn apply_config(&mut self, config_json: String) -> std::result::Result<JsValue, JsValue> {
+        if self.shut_down {
+            return Err(JsValue::from_str(CLIENT_SHUT_DOWN));
+        }
+        let change = self.reconfigure(TorClientConfig::from_json(&config_json)?)?;
+        Ok(serde_wasm_bindgen::to_value(&change).unwrap_or(JsValue::NULL))
+    }
+
+    /// Register a callback for bandwidth quota events
+    ///
+    /// The callback receives `{ event: "bandwidth_quota", scope, level,
+    /// used_bytes, limit_bytes }`, where `scope` is `"session"`, `"daily"` or
+    /// `"request"` and `level` is `"warning"` (at `warn_percent`) or
+    /// `"exhausted"`. Session and daily events fire once per level until the
+    /// quota resets. Replaces any earlier callback.
+    #[wasm_bindgen]
+    pub fn on_bandwidth_quota(&mut self, callback: js_sys::Function) {
+        self.quota_listener = Some(callback);
+    }
+
+    /// Register a callback for configuration changes
+    ///
+    /// The callback receives `{ event: "config_changed", applied, deferred }`
+    /// after each `apply_config()` that changed something, and again with
+    /// the held-back fields as `applied` once `new_identity()` applies them.
+    /// Replaces any earlier callback.
+    #[wasm_bindgen]
+    pub fn on_config_changed(&mut self, callback: js_sys::Function) {
+        self.config_listener = Some(callback);
+    }
+
+    /// Carry relay connections as channels of one shared bridge WebSocket
+    ///
+    /// On by default. Bridges without framing support (`?framing=1`) are
+    /// detected and get one socket per relay connection. Applies to
+    /// connections opened from now on; existing ones keep their transport.
+    /// Has no effect with a meek bridge URL.
+    #[wasm_bindgen]
+    pub fn set_bridge_framing(&self, enabled: bool) {
+        log::info!(
+            "🧵 Bridge framing {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        self.network.set_framing(enabled);
+    }
+
+    /// Ask the bridge what it supports and whether it is still answering
+    ///
+    /// Call this periodically, every `heartbeat_interval_secs` of the
+    /// returned capabilities (60 by default), and after a bridge change.
+    /// Connections opened afterwards follow the answer: framing only if the
+    /// bridge multiplexes, blinded targets only if it forwards them, and
+    /// messages split at its `max_message_size`. A failed request keeps the
+    /// last known capabilities.
+    ///
+    /// Returns `{ capabilities, heartbeat }`, where `capabilities` is
+    /// `{ version, blinded, consensus_endpoint, consensus_diffs,
+    /// multiplexing, max_message_size, zstd, heartbeat_interval_secs }` and `heartbeat` is
+    /// `{ discovered, last_seen_ms, last_rtt_ms, consecutive_failures,
+    /// last_error }`.
+    #[wasm_bindgen]
+    pub async fn bridge_heartbeat(&self) -> JsValue {
+        if !self.dormancy.suppress("bridge heartbeat") {
+            if let Err(e) = self.network.heartbeat().await {
+                log::warn!("🌉 Bridge heartbeat failed: {}", e);
+            }
+        }
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "capabilities": self.network.capabilities(),
+            "heartbeat": self.network.heartbeat_status(),
+        }))
+        .unwrap_or(JsValue::NULL)
+    }
+
+    /// Onion-Location and Alt-Svc hints the site at `url` has sent
+    ///
+    /// Returns `{ origin, onion_location, alt_svc: [{ protocol, host, port,
+    /// max_age_secs }] }`. `onion_location` is the site's advertised v3
+    /// onion URL or null; only HTTPS responses from the current identity
+    /// count. Nothing is followed automatically.
+    #[wasm_bindgen]
+    pub fn get_origin_hints(&self, url: String) -> std::result::Result<JsValue, JsValue> {
+        let (host, port, _, is_https) =
+            parse_url(&url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+        let origin = origin_hints::origin(is_https, &host, port);
+
+        Ok(serde_wasm_bindgen::to_value(&serde_json::json!({
+            "onion_location": self.origin_hints.onion_location(&origin),
+            "alt_svc": self.origin_hints.alt_services(&origin),
+            "origin": origin,
+        }))
+        .unwrap_or(JsValue::NULL))
+    }
+
+    /// Probe cached circuits that have sat idle, evicting dead ones
+    ///
+    /// The client already does this in the background every
+    /// `keepalive.probe_interval_ms` (default 30 seconds) once bootstrapped;
+    /// call this to probe right away. Each circuit idle for over a minute
+    /// gets a RELAY_DROP; one whose send stalls or fails, or whose link
+    /// reports it destroyed shortly after, is dropped from the cache so the
+    /// next request to that site builds a fresh circuit instead of hanging.
+    /// Circuits with a stream open are skipped.
+    ///
+    /// Returns `{ probed, evicted, stats }`, where `stats` is
+    /// `{ probes_sent, circuits_evicted, last_send_ms, max_send_ms }`.
+    #[wasm_bindgen]
+    pub async fn probe_idle_circuits(&mut self) -> std::result::Result<JsValue, JsValue> {
+        let (probed, evicted) = if self.dormancy.suppress("keepalive probes") {
+            // Nothing is cached to probe
+            (0, 0)
+        } else {
+            self.ensure_ready()?;
+            keepalive::probe_idle(&self.circuit_cache, &self.keepalive).await
+        };
+
+        Ok(serde_wasm_bindgen::to_value(&serde_json::json!({
+            "probed": probed,
+            "evicted": evicted,
+            "stats": self.keepalive.with(|k| k.stats()),
+        }))
+        .unwrap_or(JsValue::NULL))
+    }
+
+    /// Exits banned for misbehavior this session
+    ///
+    /// Returns `[{ fingerprint, score, banned_at, anomalies }]`, oldest ban
+    /// first, where each anomaly is `{ kind, detail, observed_at }` and
+    /// `kind` is `"certificate_mismatch"`, `"injected_content"` or
+    /// `"unexpected_reset"`. Banned exits are never selected for new
+    /// circuits; bans last until the client is dropped.
+    #[wasm_bindgen]
+    pub fn banned_exits(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.relay_verifier.banned_exits()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Lift an exit's ban and clear its anomaly history
+    ///
+    /// Returns false if the exit was not banned.
+    #[wasm_bindgen]
+    pub fn unban_exit(&mut self, fingerprint: String) -> bool {
+        let unbanned = self.relay_verifier.unban_exit(&fingerprint);
+        if let Some(selector) = self.relay_selector.as_mut() {
+            selector.set_banned_exits(self.relay_verifier.banned_fingerprints());
+        }
+        unbanned
+    }
+
+    /// Fetch the operator blocklist if it is due (or `force` is true)
+    ///
+    /// Call periodically; the list is refetched at most every
+    /// `blocklist.refresh_secs` unless forced. A newer list immediately
+    /// excludes its relays from selection and drops cached and pooled
+    /// circuits through them. On failure the previous list stays in use.
+    /// Returns the same object as `blocklist_status()`.
+    #[wasm_bindgen]
+    pub async fn refresh_blocklist(
+        &mut self,
+        force: Option<bool>,
+    ) -> std::result::Result<JsValue, JsValue> {
+        if !self.blocklist.config().is_enabled() {
+            return Err(JsValue::from_str("No blocklist configured"));
+        }
+        let due = force.unwrap_or(false) || self.blocklist.is_due();
+        if due && !self.dormancy.suppress("blocklist refresh") && self.blocklist.refresh().await? {
+            self.apply_blocklist();
+        }
+        Ok(self.blocklist_status())
+    }
+
+    /// Operator blocklist in use
+    ///
+    /// Returns `{ enabled, published, fingerprints, ranges, fetched_at_ms,
+    /// last_error }`, where `fingerprints` and `ranges` are entry counts.
+    #[wasm_bindgen]
+    pub fn blocklist_status(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.blocklist.status()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Estimated offset of the local clock from true time
+    ///
+    /// Returns `{ skew_secs, netinfo_samples, consensus_bounds, warning }`.
+    /// `skew_secs` is added to local time wherever consensus freshness is
+    /// judged; positive means the local clock is behind.
+    #[wasm_bindgen]
+    pub fn clock_skew(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&clock_skew::report()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Register a callback for clock skew warnings
+    ///
+    /// The callback receives `{ event: "clock_skew", skew_secs, source }`
+    /// whenever the estimate crosses 10 minutes, where `source` is
+    /// `"consensus"` or `"netinfo"`. Replaces any earlier callback.
+    #[wasm_bindgen]
+    pub fn on_clock_skew(&self, callback: js_sys::Function) {
+        clock_skew::set_listener(move |warning| {
+            let event = serde_wasm_bindgen::to_value(&serde_json::json!({
+                "event": "clock_skew",
+                "skew_secs": warning.skew_secs,
+                "source": warning.source,
+            }))
+            .unwrap_or(JsValue::NULL);
+            if let Err(e) = callback.call1(&JsValue::NULL, &event) {
+                log::warn!("⏰ clock_skew callback threw: {:?}", e);
+            }
+        });
+    }
+
+    /// Register a callback for security downgrades
+    ///
+    /// The callback receives `{ event: "security_posture", kind, severity,
+    /// level, description, detail }` when a downgrade (unverified or
+    /// built-in consensus, certificate quick-verify fallback, blinding
+    /// fallback) becomes active; `level` is the highest severity now active.
+    /// `get_status().security` lists the active ones. Replaces any earlier
+    /// callback.
+    #[wasm_bindgen]
+    pub fn on_security_posture(&self, callback: js_sys::Function) {
+        security_posture::set_listener(move |degraded| {
+            let event = serde_wasm_bindgen::to_value(&serde_json::json!({
+                "event": "security_posture",
+                "kind": degraded.notice.kind,
+                "severity": degraded.notice.severity,
+                "level": degraded.level,
+                "description": degraded.notice.description,
+                "detail": degraded.notice.detail,
+            }))
+            .unwrap_or(JsValue::NULL);
+            if let Err(e) = callback.call1(&JsValue::NULL, &event) {
+                log::warn!("🛡️ security_posture callback threw: {:?}", e);
+            }
+        });
+    }
+
+    /// Register a callback for circuit, stream, guard or consensus events
+    ///
+    /// `event_type` is one of `"circuit_built"`, `"circuit_closed"`,
+    /// `"stream_opened"`, `"stream_ended"`, `"guard_down"` and
+    /// `"consensus_refreshed"`. The callback receives an object whose
+    /// `event` field names the type, e.g. `{ event: "circuit_closed",
+    /// circuit_id, reason }`. Callbacks run after the code that raised the
+    /// event has returned. Returns an ID for [`TorClient::unsubscribe`];
+    /// unlike the `on_*` callbacks, several may be registered per type.
+    #[wasm_bindgen]
+    pub fn subscribe(
+        &self,
+        event_type: &str,
+        callback: js_sys::Function,
+    ) -> std::result::Result<u32, JsValue> {
+        let kind = EventType::from_name(event_type).ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "Unknown event type '{}' (expected one of: {})",
+                event_type,
+                EventType::ALL.map(EventType::name).join(", ")
+            ))
+        })?;
+        Ok(events::subscribe(kind, move |event| {
+            let event = serde_wasm_bindgen::to_value(event).unwrap_or(JsValue::NULL);
+            if let Err(e) = callback.call1(&JsValue::NULL, &event) {
+                log::warn!("📣 {} callback threw: {:?}", kind.name(), e);
+            }
+        }))
+    }
+
+    /// Cancel a subscription made with [`TorClient::subscribe`]; returns
+    /// whether it existed
+    #[wasm_bindgen]
+    pub fn unsubscribe(&self, subscription_id: u32) -> bool {
+        events::unsubscribe(subscription_id)
+    }
+
+    /// Search relays in the current consensus
+    ///
+    /// Takes `{ flags, country, nickname, min_bandwidth, page, page_size }`
+    /// (all optional) and returns `{ total, page, page_size, relays }`, with
+    /// relays sorted by bandwidth, highest first.
+    #[wasm_bindgen]
+    pub fn search_relays(&self, query: JsValue) -> std::result::Result<JsValue, JsValue> {
+        let consensus = self
+            .consensus
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("No consensus"))?;
+
+        let query: RelaySearchQuery = if query.is_undefined() || query.is_null() {
+            RelaySearchQuery::default()
+        } else {
+            serde_wasm_bindgen::from_value(query)
+                .map_err(|e| JsValue::from_str(&format!("Invalid search query: {}", e)))?
+        };
+
+        let page = relay_search::search_relays(&consensus.relays, &query)?;
+        serde_wasm_bindgen::to_value(&page).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Get circuit pool statistics
+    ///
+    /// `by_class` and `targets` are keyed by port class (`general`,
+    /// `long_lived`, `onion_intro`): circuits ready now, and how many
+    /// `refill_circuit_pool()` keeps ready given recent demand.
+    #[wasm_bindgen]
+    pub fn pool_stats(&self) -> JsValue {
+        let stats = self.circuit_pool.get_stats();
+        let by_class: serde_json::Map<String, serde_json::Value> = PortClass::ALL
+            .iter()
+            .map(|&class| {
+                (
+                    class.name().to_string(),
+                    self.circuit_pool.size_of(class).into(),
+                )
+            })
+            .collect();
+        let targets: serde_json::Map<String, serde_json::Value> = self
+            .circuit_pool
+            .targets()
+            .into_iter()
+            .map(|(class, target)| (class.name().to_string(), target.into()))
+            .collect();
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "pool_size": stats.current_pool_size,
+            "hits": stats.pool_hits,
+            "misses": stats.pool_misses,
+            "circuits_built": stats.circuits_built,
+            "circuits_expired": stats.circuits_expired,
+            "cannibalized": stats.circuits_cannibalized,
+            "by_class": by_class,
+            "targets": targets,
+        }))
+        .unwrap_or(JsValue::NULL)
+    }
+
+    /// Prebuild circuits so the pool meets its per-class targets again
+    ///
+    /// Targets follow which isolation keys recently needed new circuits:
+    /// general circuits for web traffic are always kept warm, long-lived
+    /// ones (Stable relays) once long-lived ports are in use. Call this
+    /// periodically, e.g. every 30s. Returns the number of circuits built.
+    #[wasm_bindgen]
+    pub async fn refill_circuit_pool(&mut self) -> std::result::Result<usize, JsValue> {
+        if self.dormancy.suppress("circuit pool refill") {
+            return Ok(0);
+        }
+        self.ensure_ready()?;
+        let builder = self
+            .circuit_builder
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
+            .clone();
+        let selector = self
+            .relay_selector
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
+            .clone();
+        let built = self.circuit_pool.warm_up(&builder, &selector).await;
+        self.persist_guard_outcomes();
+        let built = built?;
+        if built > 0 {
+            log::info!("🔥 Refilled circuit pool with {} circuits", built);
+        }
+        Ok(built)
+    }
+
+    /// Request metrics
+    ///
+    /// Returns `{ transport, bridge_mux, bridge_heartbeat, latency: {
+    /// overall, destinations }, circuits, memory }`. `bridge_mux` is
+    /// `{ multiplexing, open_channels, sessions_opened, channels_opened,
+    /// session_resumes }` for the shared bridge socket; `bridge_heartbeat` is as returned by
+    /// `bridge_heartbeat()`.
+    /// Each latency summary is `{ count, mean_ms, p50_ms, p95_ms, p99_ms,
+    /// max_ms, buckets }`, covering successful fetches from URL to full
+    /// response; `destinations` is keyed by isolation key and cleared on
+    /// `new_identity()`. Percentiles are bucket estimates (bucket bounds
+    /// 25ms … 60s).
+    ///
+    /// `circuits` is `{ builds_succeeded, attempts_failed, by_stage,
+    /// destroy_reasons, likely_cause }`: failed build attempts counted by
+    /// stage (`connect`, `tls`, `link_handshake`, `create`, `extend_middle`,
+    /// `extend_exit`, `path_selection`, `timeout`) and by relay DESTROY
+    /// reason. `likely_cause` reads the failures since the last successful
+    /// build: `none`, `bridge_unreachable`, `guards_blocked`,
+    /// `consensus_stale`, `no_usable_relays`, `network_slow` or `mixed`.
+    /// No relay identities are recorded.
+    ///
+    /// `memory` is `{ allocator, pool, tracking, budget_bytes, live_bytes,
+    /// peak_bytes, budget_rejections, by_subsystem }`; the byte counts stay 0
+    /// unless the crate was built with the `memory-tracking` feature, and
+    /// `pool` is null unless it was built with `pool-alloc`.
+    #[wasm_bindgen]
+    pub fn get_metrics(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "transport": self.network.transport_name(),
+            "bridge_mux": self.network.mux_status(),
+            "bridge_heartbeat": self.network.heartbeat_status(),
+            "latency": self.latency.report(),
+            "circuits": self.circuit_failure_metrics(),
+            "memory": memory::report(),
+            "bandwidth": self.bandwidth_quota.usage(now_ms() / 1000),
+        }))
+        .unwrap_or(JsValue::NULL)
+    }
+
+    /// Export a diagnostics bundle for attaching to bug reports
+    ///
+    /// Returns a JSON document with recent logs, a metrics snapshot, the
+    /// client configuration, consensus freshness and the last circuit build
+    /// reports. IP addresses, host names and URLs (including the bridge's),
+    /// request paths, relay fingerprints and nicknames, and keys are
+    /// redacted from every string in it, and per-destination latency is
+    /// reduced to a count, so the
+    /// bundle names neither the bridge nor the sites visited. Review it
+    /// before sharing: log messages are free text.
+    #[wasm_bindgen]
+    pub fn export_diagnostics(&self) -> String {
+        let latency = self.latency.report();
+        let net = self.network.get_stats();
+        let consensus = self.consensus.as_ref().map(|c| {
+            serde_json::json!({
+                "relays": c.relays.len(),
+                "valid_after": c.valid_after,
+                "fresh_until": c.fresh_until,
+                "valid_until": c.valid_until,
+                "is_fresh": c.is_fresh(),
+                "is_valid": c.is_valid(),
+            })
+        });
+        let clock_skew = clock_skew::report();
+        let builds = self.build_failures.with(|f| f.recent_builds());
+
+        let mut bundle = serde_json::json!({
+            "format": diagnostics::DIAGNOSTICS_FORMAT,
+            "version": env!("CARGO_PKG_VERSION"),
+            "generated_at_ms": now_ms(),
+            "config": {
+                "transport": self.network.transport_name(),
+                "bridge_url": self.network.bridge_url(),
+                "isolation_policy": format!("{:?}", self.circuit_cache.with(|cache| cache.policy())),
+                "http_padding": self.http_padding.default_config(),
+                "request_jitter": self.request_jitter.default_config(),
+                "bootstrapped": self.bootstrapped,
+                "shut_down": self.shut_down,
+            },
+            "consensus": consensus,
+            "clock_skew": clock_skew,
+            "metrics": {
+                "latency": {
+                    "overall": latency.overall,
+                    "destinations_tracked": latency.destinations.len(),
+                },
+                "circuits": self.circuit_failure_metrics(),
+                "network": {
+                    "connections_attempted": net.connections_attempted,
+                    "connections_successful": net.connections_successful,
+                    "connections_failed": net.connections_failed,
+                    "reconnects": net.reconnects,
+                },
+                "cached_circuits": self.circuit_cache.with(|cache| cache.stats()).cached_circuits,
+                "memory": memory::report(),
+            },
+            "circuit_builds": builds,
+            "logs": log_ring::recent_logs(diagnostics::DIAGNOSTICS_LOG_RECORDS),
+        });
+        let nicknames = self
+            .consensus
+            .iter()
+            .flat_map(|c| c.relays.iter().map(|r| r.nickname.as_str()));
+        diagnostics::Redactor::new(nicknames).redact_value(&mut bundle);
+        serde_json::to_string_pretty(&bundle).unwrap_or_default()
+    }
+
+    /// How the most recent request's stream was served:
+    /// `{ host, port, exit, exit_resolved_address: { address, ttl } }`,
+    /// or null before the first one
+    ///
+    /// `exit_resolved_address` is what the exit reported in RELAY_CONNECTED
+    /// (null if it sent nothing), useful to check the exit's DNS.
+    #[wasm_bindgen]
+    pub fn last_response(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.last_response).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get circuit cache statistics
+    #[wasm_bindgen]
+    pub fn get_circuit_stats(&self) -> JsValue {
+        let stats = self.circuit_cache.with(|cache| cache.stats());
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "cached_circuits": stats.cached_circuits,
+            "spillover_circuits": stats.spillover_circuits,
+            "standby_circuits": stats.standby_circuits,
+            "active_streams": stats.active_streams,
+            "total_requests": stats.total_requests,
+            "oldest_circuit_age_secs": stats.oldest_circuit_age_secs,
+            "policy": format!("{:?}", stats.policy),
+            "dns_cache": self.dns_cache.stats(),
+            "link_cache": self.link_cache.with(|c| c.stats()),
+        }))
+        .unwrap_or(JsValue::NULL)
+    }
+
+    /// Get circuit rate limit statistics
+    ///
+    /// `remaining_circuits` is what the global ceiling still allows in the
+    /// current window; `remaining_by_key` lists isolation keys that have
+    /// spent part of their `key_burst`.
+    #[wasm_bindgen]
+    pub fn get_rate_limit_stats(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&serde_json::json!(self.rate_limiter.get_stats()))
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// Get guard state information
+    #[wasm_bindgen]
+    pub fn get_guard_info(&self) -> JsValue {
+        let now = web_time::SystemTime::now()
+            .duration_since(web_time::SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let guards = self.guard_state.with(|g| g.clone());
+        let days_until_rotation = if guards.rotate_after > now {
+            (guards.rotate_after - now) / (24 * 60 * 60)
+        } else {
+            0
+        };
+
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "guard_count": guards.guards.len(),
+            "usable_guards": guards.usable_guard_count(),
+            "days_until_rotation": days_until_rotation,
+            "bad_guard_count": guards.bad_guards.len(),
+            "selected_at": guards.selected_at,
+            "rotate_after": guards.rotate_after,
+        }))
+        .unwrap_or(JsValue::NULL)
+    }
+
+    /// Export the guard set as Arti's `state/guards.json`
+    ///
+    /// Guards are written as Arti's confirmed default sample, in preference
+    /// order, with OR ports filled in from the current consensus. Drop the
+    /// file into a native Arti client's state directory to keep using the
+    /// same guards there.
+    #[wasm_bindgen]
+    pub fn export_guards_arti(&self) -> std::result::Result<String, JsValue> {
+        let relays = self
+            .consensus
+            .as_ref()
+            .map(|c| c.relays.as_slice())
+            .unwrap_or_default();
+        let guards = self.guard_state.with(|g| g.clone());
+        Ok(storage::ArtiGuardSets::from_guard_state(&guards, relays).to_json()?)
+    }
+
+    /// Replace the guard set with the one in an Arti `state/guards.json`
+    ///
+    /// Confirmed guards come first, then the rest of Arti's default sample;
+    /// guards Arti has disabled are skipped. The rotation clock carries
+    /// over from Arti's `added_at` times. Returns the number of guards
+    /// imported.
+    #[wasm_bindgen]
+    pub async fn import_guards_arti(
+        &mut self,
+        json: String,
+    ) -> std::result::Result<usize, JsValue> {
+        let relays = self
+            .consensus
+            .as_ref()
+            .map(|c| c.relays.as_slice())
+            .unwrap_or_default();
+        let state =
+            storage::ArtiGuardSets::from_json(&json)?.to_guard_state(relays, now_ms() / 1000);
+        if state.guards.is_empty() {
+            return Err(JsValue::from_str("No usable guards in Arti guard state"));
+        }
+
+        log::info!("🛡️ Imported {} guards from Arti state", state.guards.len());
+        self.guard_state.replace(state.clone());
+        if let Err(e) = self.guard_persistence.save(&state).await {
+            log::warn!("⚠️ Failed to save guard state: {}", e);
+        }
+        if let Some(ref mut selector) = self.relay_selector {
+            selector.set_preferred_guards(state.guards.clone());
+        }
+        Ok(state.guards.len())
+    }
+
+    /// Force guard rotation (selects new guards)
+    #[wasm_bindgen]
+    pub async fn rotate_guards(&mut self) -> std::result::Result<(), JsValue> {
+        self.ensure_ready()?;
+
+        let consensus = self
+            .consensus
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("No consensus"))?;
+
+        log::info!("🔄 Forcing guard rotation...");
+
+        let guard_count = self.guard_count;
+        self.guard_state
+            .with(|g| g.select_guard_count(&consensus.relays, guard_count))
+            .map_err(|e| JsValue::from_str(&format!("Guard selection failed: {}", e)))?;
+
+        // Save the new state
+        let state = self.guard_state.with(|g| g.clone());
+        if let Err(e) = self.guard_persistence.save(&state).await {
+            log::warn!("⚠️ Failed to save guard state: {}", e);
+        }
+
+        // Update relay selector
+        if let Some(ref mut selector) = self.relay_selector {
+            selector.set_preferred_guards(state.usable_guards().into_iter().cloned().collect());
+        }
+
+        log::info!("✅ Guard rotation complete");
+        Ok(())
+    }
+
+    /// Clear guard state (for testing/debugging)
+    #[wasm_bindgen]
+    pub async fn clear_guards(&mut self) -> std::result::Result<(), JsValue> {
+        log::info!("🗑️ Clearing guard state...");
+
+        self.guard_state.replace(GuardState::new());
+
+        if let Err(e) = self.guard_persistence.clear().await {
+            log::warn!("⚠️ Failed to clear saved guard state: {}", e);
+        }
+
+        // Clear preferred guards from relay selector
+        if let Some(ref mut selector) = self.relay_selector {
+            selector.set_preferred_guards(Vec::new());
+        }
+
+        log::info!("✅ Guard state cleared");
+        Ok(())
+    }
+}
+
+impl TorClient {
+    /// Bootstrap with a consensus fetched elsewhere (see [`TorClientManager`])
+    ///
+    /// Selects guards and prepares circuits exactly as `bootstrap()` does
+    /// after its fetch. The consensus is shared, never modified.
+    pub async fn bootstrap_with_consensus(
+        &mut self,
+        consensus: Arc<protocol::Consensus>,
+    ) -> std::result::Result<(), JsValue> {
+        if self.shut_down {
+            return Err(JsValue::from_str(CLIENT_SHUT_DOWN));
+        }
+        log::info!(
+            "🔄 Bootstrapping Tor client with a shared consensus ({} relays)...",
+            consensus.relays.len()
+        );
+        self.install_consensus(consensus).await
+    }
+
+    /// The consensus in use, once bootstrapped
+    pub fn consensus(&self) -> Option<Arc<protocol::Consensus>> {
+        self.consensus.clone()
+    }
+
+    /// Delete everything this client persisted in its storage namespace:
+    /// its IndexedDB stores, guard state and daily bandwidth count
+    pub async fn clear_persistent_state(&self) -> Result<()> {
+        self.storage.clear_all().await?;
+        self.guard_persistence.clear().await?;
+        bandwidth_quota::clear_daily(&self.storage_config.key(bandwidth_quota::DAILY_USAGE_KEY));
+        Ok(())
+    }
+
+    /// Use `consensus_arc`: update guards, then create the relay selector,
+    /// circuit builder and circuit pool
+    async fn install_consensus(
+        &mut self,
+        consensus_arc: Arc<protocol::Consensus>,
+    ) -> std::result::Result<(), JsValue> {
+        self.consensus = Some(Arc::clone(&consensus_arc));
+
+        // Update guard selection if needed
+        log::info!("🛡️ Checking guard state...");
+        self.guard_state.with(|g| g.cleanup()); // Clean up expired entries
+
+        if self.guard_state.with(|g| g.needs_refresh()) {
+            log::info!("  🔄 Selecting new guards...");
+            let guard_count = self.guard_count;
+            self.guard_state
+                .with(|g| g.select_guard_count(&consensus_arc.relays, guard_count))?;
+
+            // Save updated guard state
+            let state = self.guard_state.with(|g| g.clone());
+            if let Err(e) = self.guard_persistence.save(&state).await {
+                log::warn!("  ⚠️ Failed to save guard state: {}", e);
+            }
+        } else {
+            let (guards, rotate_after) =
+                self.guard_state.with(|g| (g.guards.len(), g.rotate_after));
+            log::info!(
+                "  ✅ Using {} existing guards (valid for {} more days)",
+                guards,
+                (rotate_after.saturating_sub(
+                    web_time::SystemTime::now()
+                        .duration_since(web_time::SystemTime::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0)
+                )) / (24 * 60 * 60)
+            );
+        }
+
+        // The blocklist must be in place before any relay is chosen
+        if self.blocklist.is_due() {
+            if let Err(e) = self.blocklist.refresh().await {
+                if self.blocklist.status().published == 0 {
+                    return Err(JsValue::from_str(&format!("Blocklist fetch failed: {}", e)));
+                }
+                log::warn!("  ⚠️ Keeping the previous blocklist: {}", e);
+            }
+        }
+
+        // Create relay selector with guard preferences
+        log::info!("🎯 Creating relay selector...");
+        let mut selector = protocol::RelaySelector::new(consensus_arc.relays.clone());
+        selector.set_preferred_guards(
+            self.guard_state
+                .with(|g| g.usable_guards().into_iter().cloned().collect()),
+        );
+        selector.set_banned_exits(self.relay_verifier.banned_fingerprints());
+        selector.set_blocked_relays(
+            self.blocklist
+                .shared()
+                .with(|l| l.blocked_in(&consensus_arc.relays)),
+        );
+        selector.set_requirements(self.relay_requirements.clone());
+        self.relay_selector = Some(selector);
+
+        // Create circuit builder
+        log::info!("🔨 Creating circuit builder...");
+        self.circuit_builder = Some(
+            protocol::CircuitBuilder::new(Arc::clone(&self.network))
+                .with_failure_stats(Rc::clone(&self.build_failures))
+                .with_guard_state(Rc::clone(&self.guard_state))
+                .with_link_cache(Rc::clone(&self.link_cache))
+                .with_blocklist(self.blocklist.shared()),
+        );
+
+        self.bootstrapped = true;
+        events::post(TorEvent::ConsensusRefreshed {
+            relays: consensus_arc.relays.len(),
+            valid_after: consensus_arc.valid_after,
+            valid_until: consensus_arc.valid_until,
+        });
+
+        // Warm up circuit pool (prebuild circuits for fast first requests)
+        log::info!("🔥 Warming up circuit pool...");
+        let pool_builder = self.circuit_builder.as_ref().unwrap().clone();
+        let pool_selector = self.relay_selector.as_ref().unwrap().clone();
+        match self
+            .circuit_pool
+            .warm_up(&pool_builder, &pool_selector)
+            .await
+        {
+            Ok(n) => log::info!("✅ Circuit pool warmed up ({} circuits ready)", n),
+            Err(e) => log::warn!(
+                "⚠️ Circuit pool warm-up failed: {} (will build on demand)",
+                e
+            ),
+        }
+        self.persist_guard_outcomes();
+
+        log::info!("✅ Tor client bootstrapped and ready!");
+
+        Ok(())
+    }
+
+    /// Create a Tor client from a validated configuration
+    pub async fn from_config(config: TorClientConfig) -> Result<Self> {
+        log::info!("Creating new Tor client");
+        config.validate()?;
+        config.logging.apply();
+
+        // Initialize storage
+        let storage = Arc::new(
+            WasmStorage::new(Some(&config.storage.namespace))
+                .await
+                .map_err(|e| TorError::Storage(format!("Storage init failed: {}", e)))?,
+        );
+
+        // Initialize network provider
+        let network = Arc::new(WasmTcpProvider::with_config(config.network));
+
+        log::info!("✅ Tor client created");
+
+        let circuit_cache = isolation::new_shared_circuit_cache(config.isolation);
+        log::info!(
+            "  🔒 Circuit isolation: {:?}",
+            circuit_cache.with(|cache| cache.policy())
+        );
+
+        let mut http_padding = HttpPaddingPolicy::new();
+        http_padding.set_default(config.http_padding);
+
+        let mut bandwidth_quota = BandwidthQuota::new(config.bandwidth_quota);
+        if let Some((day, bytes)) =
+            bandwidth_quota::load_daily(&config.storage.key(bandwidth_quota::DAILY_USAGE_KEY))
+        {
+            bandwidth_quota.restore_daily(day, bytes);
+        }
+
+        // Initialize guard persistence
+        let guard_persistence = GuardPersistence::with_key(config.storage.key(GUARD_STATE_KEY));
+        let guard_state = match guard_persistence.load().await {
+            Ok(state) => {
+                if state.guards.is_empty() {
+                    log::info!("  🛡️ No saved guards, will select on bootstrap");
+                } else {
+                    log::info!("  🛡️ Loaded {} guards from storage", state.guards.len());
+                }
+                state
+            }
+            Err(e) => {
+                log::warn!("  ⚠️ Failed to load guard state: {}", e);
+                GuardState::new()
+            }
+        };
+
+        Ok(Self {
+            network,
+            storage,
+            consensus: None,
+            consensus_health: None,
+            bootstrapped: false,
+            circuit_cache,
+            dns_cache: DnsCache::new(),
+            onion_services: OnionServiceClient::new(),
+            last_response: None,
+            keepalive: keepalive::new_shared_keepalive(config.keepalive),
+            http_padding,
+            request_jitter: RequestJitter::new(config.request_jitter),
+            latency: LatencyMetrics::new(),
+            build_failures: circuit_failures::new_shared_failure_stats(),
+            origin_hints: OriginHints::new(),
+            relay_verifier: RelayVerifier::new(),
+            blocklist: BlocklistUpdater::new(config.blocklist),
+            guard_state: new_shared_guard_state(guard_state),
+            link_cache: protocol::new_shared_link_cache(),
+            guard_persistence,
+            circuit_builder: None,
+            relay_selector: None,
+            relay_requirements: config.relay_requirements,
+            http_security: config.http,
+            http_client: config.http_client,
+            rate_limiter: RateLimiter::with_config(config.rate_limit),
+            bandwidth_quota,
+            quota_listener: None,
+            circuit_pool: PrebuiltCircuitPool::with_config(config.circuit_pool),
+            tasks: TaskSupervisor::new(),
+            custom_circuits: CustomCircuits::default(),
+            shut_down: false,
+            dormancy: dormant::Dormancy::new(),
+            guard_count: config.guards.count,
+            pending_config: None,
+            config_listener: None,
+            storage_config: config.storage,
+        })
+    }
+
+    /// The configuration currently in effect
+    pub fn config(&self) -> TorClientConfig {
+        TorClientConfig {
+            network: self.network.config(),
+            isolation: self.circuit_cache.with(|cache| cache.config().clone()),
+            circuit_pool: self.circuit_pool.config().clone(),
+            keepalive: self.keepalive.with(|k| k.config().clone()),
+            http_padding: self.http_padding.default_config().clone(),
+            request_jitter: self.request_jitter.default_config().clone(),
+            relay_requirements: self.relay_requirements.clone(),
+            rate_limit: self.rate_limiter.config().clone(),
+            logging: LoggingConfig::current(),
+            guards: GuardConfig {
+                count: self.guard_count,
+            },
+            http: self.http_security.clone(),
+            http_client: self.http_client.clone(),
+            bandwidth_quota: self.bandwidth_quota.config().clone(),
+            blocklist: self.blocklist.config().clone(),
+            storage: self.storage_config.clone(),
+        }
+    }
+
+    /// Switch to `config`, applying what can change immediately
+    ///
+    /// Changes that need a new identity (see [`TorClientConfig::diff`]) are
+    /// queued and applied by the next `new_identity()`, replacing anything
+    /// queued earlier. Emits a `config_changed` event when something changed.
+    /// The storage namespace can't change.
+    pub fn reconfigure(&mut self, config: TorClientConfig) -> Result<ConfigChange> {
+        config.validate()?;
+        if config.storage != self.storage_config {
+            return Err(TorError::InvalidState(
+                "storage.namespace is fixed when the client is created".into(),
+            ));
+        }
+        let change = self.config().diff(&config);
+
+        for &section in &change.applied {
+            match section {
+                "network.connect_timeout" | "network.retry_on_failure" | "network.max_retries" => {
+                    self.network.set_retry_policy(
+                        config.network.connect_timeout,
+                        config.network.retry_on_failure,
+                        config.network.max_retries,
+                    )
+                }
+                "network.framing" => self.network.set_framing(config.network.framing),
+                "isolation" => self.apply_isolation(config.isolation.clone()),
+                "circuit_pool" => self.circuit_pool.set_config(config.circuit_pool.clone()),
+                "keepalive" => self
+                    .keepalive
+                    .with(|k| k.set_config(config.keepalive.clone())),
+                "http_padding" => self.http_padding.set_default(config.http_padding.clone()),
+                "request_jitter" => self
+                    .request_jitter
+                    .set_default(config.request_jitter.clone()),
+                "relay_requirements" => {
+                    self.apply_relay_requirements(config.relay_requirements.clone())
+                }
+                "rate_limit" => self.rate_limiter.set_config(config.rate_limit.clone()),
+                "logging" => config.logging.apply(),
+                "http" => self.http_security = config.http.clone(),
+                "http_client" => self.http_client = config.http_client.clone(),
+                "bandwidth_quota" => {
+                    self.bandwidth_quota
+                        .set_config(config.bandwidth_quota.clone());
+                    self.sync_throttle();
+                }
+                "blocklist" => {
+                    self.blocklist.set_config(config.blocklist.clone());
+                    self.apply_blocklist();
+                }
+                other => log::warn!("⚙️ No live handler for config field {}", other),
+            }
+        }
+
+        log::info!(
+            "⚙️ Reconfigured: {} applied, {} deferred to the next new identity",
+            change.applied.len(),
+            change.deferred.len()
+        );
+        self.pending_config = (!change.deferred.is_empty()).then_some(config);
+        if !change.is_empty() {
+            self.emit_config_changed(&change);
+        }
+        Ok(change)
+    }
+
+    /// Apply the changes `reconfigure()` held back (called on new identity)
+    fn apply_pending_config(&mut self) {
+        let Some(config) = self.pending_config.take() else {
+            return;
+        };
+        let deferred = self.config().diff(&config).deferred;
+
+        if deferred.iter().any(|field| field.starts_with("network.")) {
+            // Fresh provider with the queued bridge and pool settings, keeping
+            // the live ones as they are now
+            let network = network::NetworkConfig {
+                bridge_url: config.network.bridge_url.clone(),
+                max_connections: config.network.max_connections,
+                enable_pooling: config.network.enable_pooling,
+                blinding_key: config.network.blinding_key.clone(),
+                ..self.network.config()
+            };
+            self.network = Arc::new(WasmTcpProvider::with_config(network));
+            if self.circuit_builder.is_some() {
+                self.circuit_builder = Some(
+                    protocol::CircuitBuilder::new(Arc::clone(&self.network))
+                        .with_failure_stats(Rc::clone(&self.build_failures))
+                        .with_guard_state(Rc::clone(&self.guard_state))
+                        .with_link_cache(Rc::clone(&self.link_cache))
+                        .with_blocklist(self.blocklist.shared()),
+                );
+            }
+            log::info!("🌉 Bridge now {}", self.network.bridge_url());
+        }
+
+        if deferred.contains(&"guards") {
+            self.guard_count = config.guards.count;
+            self.reselect_guards();
+        }
+
+        if !deferred.is_empty() {
+            self.emit_config_changed(&ConfigChange {
+                applied: deferred,
+                deferred: Vec::new(),
+            });
+        }
+    }
+
+    /// Choose `guard_count` new guards from the current consensus and save
+    /// them in the background
+    fn reselect_guards(&mut self) {
+        let Some(consensus) = self.consensus.clone() else {
+            // Selected with the new count at the next bootstrap
+            return;
+        };
+        let guard_count = self.guard_count;
+        if let Err(e) = self
+            .guard_state
+            .with(|g| g.select_guard_count(&consensus.relays, guard_count))
+        {
+            log::warn!("⚠️ Guard selection failed: {}", e);
+            return;
+        }
+        let state = self.guard_state.with(|g| g.clone());
+        if let Some(selector) = self.relay_selector.as_mut() {
+            selector.set_preferred_guards(state.usable_guards().into_iter().cloned().collect());
+        }
+        self.save_guard_state(state);
+    }
+
+    /// Save guard failures and successes the circuit builder recorded since
+    /// the last save, in the background
+    fn persist_guard_outcomes(&mut self) {
+        if !self.guard_state.with(|g| g.take_unsaved()) {
+            return;
+        }
+        let state = self.guard_state.with(|g| g.clone());
+        self.save_guard_state(state);
+    }
+
+    /// Save `state` in the background
+    fn save_guard_state(&mut self, state: GuardState) {
+        let persistence = self.guard_persistence.clone();
+        self.tasks.spawn("guard save", async move {
+            persistence.save(&state).await.map_err(|e| e.to_string())
+        });
+    }
+
+    /// Tell the `on_config_changed` listener what changed
+    fn emit_config_changed(&self, change: &ConfigChange) {
+        let Some(callback) = &self.config_listener else {
+            return;
+        };
+        let event = serde_wasm_bindgen::to_value(&serde_json::json!({
+            "event": "config_changed",
+            "applied": change.applied,
+            "deferred": change.deferred,
+        }))
+        .unwrap_or(JsValue::NULL);
+        if let Err(e) = callback.call1(&JsValue::NULL, &event) {
+            log::warn!("⚙️ config_changed callback threw: {:?}", e);
+        }
+    }
+
+    /// Switch isolation policy, dropping circuits isolated under the old one
+    fn apply_isolation_policy(&mut self, isolation_type: IsolationType) {
+        self.apply_isolation(IsolationConfig {
+            policy: isolation_type,
+            ..self.circuit_cache.with(|cache| cache.config().clone())
+        });
+    }
+
+    /// Use new isolation settings, dropping circuits cached under the old ones
+    fn apply_isolation(&mut self, config: IsolationConfig) {
+        let policy = config.policy;
+
+        // Clear existing circuits (and their DNS answers) when policy changes
+        self.circuit_cache.with(|cache| cache.clear());
+        self.dns_cache.clear();
+        self.http_padding.clear_overrides();
+        self.request_jitter.clear_overrides();
+        self.circuit_cache.replace(CircuitCache::new(config));
+
+        log::info!("🔒 Circuit isolation policy set to: {:?}", policy);
+    }
+
+    /// Use new relay requirements for circuits built from now on
+    fn apply_relay_requirements(&mut self, requirements: protocol::RelayRequirements) {
+        log::info!(
+            "🎯 Relay requirements: min bandwidth {}, Fast {}, Stable for long-lived {}",
+            requirements.min_bandwidth,
+            requirements.require_fast,
+            requirements.require_stable_long_lived
+        );
+        if let Some(selector) = self.relay_selector.as_mut() {
+            selector.set_requirements(requirements.clone());
+        }
+        self.relay_requirements = requirements;
+    }
+
+    /// Carry out a parsed control command
+    fn run_control_command(&mut self, command: ControlCommand) -> ControlReply {
+        match command {
+            ControlCommand::GetInfo(keys) => {
+                let values = keys
+                    .into_iter()
+                    .map(|key| {
+                        let value = match key {
+                            control::InfoKey::CircuitStatus => {
+                                serde_json::json!(self.circuit_status())
+                            }
+                            control::InfoKey::StreamStatus => serde_json::json!([]),
+                            control::InfoKey::Version => {
+                                serde_json::json!(env!("CARGO_PKG_VERSION"))
+                            }
+                        };
+                        (key.as_str().to_string(), value)
+                    })
+                    .collect();
+                ControlReply::with_values(values)
+            }
+            ControlCommand::Signal(control::Signal::NewNym) => {
+                self.new_identity();
+                ControlReply::ok()
+            }
+            ControlCommand::Signal(control::Signal::ClearDnsCache) => {
+                self.dns_cache.clear();
+                log::info!("🗑️ DNS cache cleared");
+                ControlReply::ok()
+            }
+            ControlCommand::SetConf(changes) => {
+                for change in changes {
+                    match change {
+                        control::ConfChange::IsolationPolicy(policy) => {
+                            self.apply_isolation_policy(policy)
+                        }
+                        control::ConfChange::HttpPadding(config) => {
+                            self.http_padding.set_default(config)
+                        }
+                        control::ConfChange::MemoryBudget(bytes) => memory::set_budget(bytes),
+                        control::ConfChange::RelayRequirements(requirements) => {
+                            self.apply_relay_requirements(requirements)
+                        }
+                    }
+                }
+                ControlReply::ok()
+            }
+        }
+    }
+
+    /// Every circuit the client holds: cached, pooled and custom
+    fn circuit_status(&self) -> Vec<CircuitStatus> {
+        let mut status: Vec<CircuitStatus> = self.circuit_cache.with(|cache| {
+            cache
+                .circuits()
+                .filter_map(|(key, circuit)| {
+                    let circuit = circuit.try_borrow().ok()?;
+                    Some(CircuitStatus::new(
+                        &circuit,
+                        "GENERAL",
+                        Some(key.as_str().to_string()),
+                    ))
+                })
+                .collect()
+        });
+        status.extend(
+            self.circuit_pool
+                .circuits()
+                .map(|circuit| CircuitStatus::new(circuit, "PREBUILT", None)),
+        );
+        let mut custom: Vec<CircuitStatus> = self.custom_circuits.with(|circuits| {
+            circuits
+                .values()
+                .filter_map(|circuit| {
+                    let circuit = circuit.try_borrow().ok()?;
+                    Some(CircuitStatus::new(&circuit, "CUSTOM", None))
+                })
+                .collect()
+        });
+        custom.sort_by_key(|c| c.id);
+        status.extend(custom);
+        status
+    }
+
+    /// `fetch()` of one URL, after the plain-HTTP policy
+    async fn fetch_url(
+        &mut self,
+        url: &str,
+        circuit_id: Option<u32>,
+        options: &FetchOptions,
+    ) -> std::result::Result<String, JsValue> {
+        let started_ms = now_ms();
+        let integrity = options.integrity()?;
+
+        // Parse URL (now returns is_https flag)
+        let (host, port, path, is_https) =
+            parse_url(url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+
+        let scheme = if is_https { "HTTPS" } else { "HTTP" };
+        log::info!("🌐 Fetching {} via Tor ({})...", url, scheme);
+        log::info!(
+            "  Host: {}, Port: {}, Path: {}, HTTPS: {}",
+            host,
+            port,
+            path,
+            is_https
+        );
+
+        // 1. Get or build a circuit (with isolation)
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        log::info!("  🔒 Isolation key: '{}'", isolation_key.as_str());
+
+        let lifetime = match circuit_id {
+            // The caller chose the path
+            Some(_) => protocol::StreamLifetime::Short,
+            None => self.relay_requirements.stream_lifetime(port, false),
+        };
+        let circuit_rc = if let Some(id) = circuit_id {
+            log::info!("  📌 Using attached circuit {}", id);
+            self.attached_circuit(id)?
+        } else {
+            self.isolated_circuit(&isolation_key, &host, lifetime, options.cancel.as_ref())
+                .await?
+        };
+        let exit = exit_fingerprint(&circuit_rc.borrow());
+
+        // 2. Open a stream through the circuit
+        log::info!("  📡 Opening stream to {}:{}...", host, port);
+
+        let stream = self
+            .open_stream_cached(
+                circuit_rc,
+                &isolation_key,
+                &host,
+                port,
+                lifetime,
+                options.begin_flags,
+            )
+            .await?;
+
+        log::info!("  ✅ Stream opened");
+        let cancelled = || {
+            options
+                .cancel
+                .as_ref()
+                .is_some_and(CancelToken::is_cancelled)
+        };
+
+        // 3. For HTTPS, wrap stream with TLS
+        let response_bytes = if is_https {
+            log::info!("  🔐 Establishing TLS connection...");
+
+            let mut tls_stream = protocol::TlsTorStream::new(stream, &host)
+                .await
+                .map_err(|e| JsValue::from_str(&format!("TLS handshake failed: {}", e)))?;
+            // The response can't be read partway, so stop before sending
+            if cancelled() {
+                let _ = tls_stream.close().await;
+                return Err(TorError::Cancelled.into());
+            }
+
+            log::info!("  ✅ TLS established");
+            if let (Some(digest), Some(exit)) = (tls_stream.certificate_digest(), &exit) {
+                self.note_certificate(&host, &digest, exit);
+            }
+
+            // Send HTTP request over TLS
+            let http_request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n{}\r\n",
+                path, host, options.auth_header()
+            );
+            let http_request = self.http_padding.pad_request(&isolation_key, http_request);
+
+            log::info!(
+                "  📤 Sending HTTPS request ({} bytes)...",
+                http_request.len()
+            );
+
+            tls_stream
+                .write(http_request.as_bytes())
+                .await
+                .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
+
+            log::info!("  ✅ Request sent");
+            log::info!("  📥 Receiving response...");
+
+            // Read response
+            let response = tls_stream
+                .read_to_end()
+                .await
+                .map_err(|e| JsValue::from_str(&format!("Failed to receive response: {}", e)))?;
+
+            // Close TLS
+            let _ = tls_stream.close().await;
+
+            response
+        } else {
+            // Plain HTTP
+            let mut stream = stream;
+            if cancelled() {
+                let _ = stream.close().await;
+                return Err(TorError::Cancelled.into());
+            }
+
+            let http_request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n{}\r\n",
+                path, host, options.auth_header()
+            );
+            let http_request = self.http_padding.pad_request(&isolation_key, http_request);
+
+            log::info!(
+                "  📤 Sending HTTP request ({} bytes)...",
+                http_request.len()
+            );
+
+            stream
+                .write_all(http_request.as_bytes())
+                .await
+                .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
+
+            log::info!("  ✅ Request sent");
+            log::info!("  📥 Receiving response...");
+
+            let response = stream
+                .read_response()
+                .await
+                .map_err(|e| JsValue::from_str(&format!("Failed to receive response: {}", e)))?;
+
+            // Close stream
+            let _ = stream.close().await;
+
+            response
+        };
+
+        log::info!("  ✅ Received {} bytes", response_bytes.len());
+        self.note_response(&host, port, is_https, &response_bytes);
+        self.account_transfer(response_bytes.len(), options.max_bytes)?;
+        let response_bytes = self
+            .http_padding
+            .strip_response(&isolation_key, response_bytes);
+        self.check_integrity(
+            integrity.as_ref(),
+            &response_bytes,
+            is_https,
+            exit.as_slice(),
+        )?;
+
+        // Convert to string
+        let response_str = String::from_utf8_lossy(&response_bytes).to_string();
+
+        log::info!("✅ Fetch complete: {} bytes", response_str.len());
+
+        self.record_latency(&isolation_key, started_ms);
+        Ok(response_str)
+    }
+
+    /// `fetch_get_cooperative()` of one URL, after the plain-HTTP policy
+    async fn cooperative_get_url(
+        &mut self,
+        url: &str,
+        circuit_id: Option<u32>,
+        options: &FetchOptions,
+    ) -> std::result::Result<String, JsValue> {
+        let started_ms = now_ms();
+        let integrity = options.integrity()?;
+
+        // Parse URL
+        let (host, port, path, is_https) =
+            parse_url(url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+
+        let scheme = if is_https { "HTTPS" } else { "HTTP" };
+        log::info!("🌐 [COOP] GET {} via Tor ({})...", url, scheme);
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let (response_bytes, exits) = self
+            .cooperative_get(
+                &host,
+                port,
+                &path,
+                is_https,
+                circuit_id,
+                &isolation_key,
+                options,
+                None,
+            )
+            .await?;
+
+        self.note_response(&host, port, is_https, &response_bytes);
+        self.account_transfer(response_bytes.len(), options.max_bytes)?;
+        let response_bytes = self
+            .http_padding
+            .strip_response(&isolation_key, response_bytes);
+        self.check_integrity(integrity.as_ref(), &response_bytes, is_https, &exits)?;
+        let response_str = String::from_utf8_lossy(&response_bytes).to_string();
+        log::info!("✅ [COOP] GET complete: {} bytes", response_str.len());
+
+        self.record_latency(&isolation_key, started_ms);
+        Ok(response_str)
+    }
+
+    /// `fetch_request()` of one URL, after the plain-HTTP policy
+    ///
+    /// Sent once on a pooled circuit: unlike a GET, a request that may
+    /// change something isn't resumed on another circuit.
+    async fn cooperative_request_url(
+        &mut self,
+        url: &str,
+        request: &HttpRequest,
+        options: &FetchOptions,
+    ) -> std::result::Result<Vec<u8>, JsValue> {
+        let started_ms = now_ms();
+        let integrity = options.integrity()?;
+
+        let (host, port, path, is_https) =
+            parse_url(url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+        log::info!(
+            "🌐 [COOP] {} {} via Tor ({} body bytes)...",
+            request.method,
+            url,
+            request.body.len()
+        );
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let class = PortClass::for_lifetime(self.relay_requirements.stream_lifetime(port, false));
+        let circuit = self
+            .pooled_circuit(&isolation_key, class, options.cancel.as_ref())
+            .await?;
+        let exit = exit_fingerprint(&circuit);
+
+        let head = request.head(&host, port, &path, is_https);
+        let mut http_request = self
+            .http_padding
+            .pad_request_head(&isolation_key, head, request.body.len())
+            .into_bytes();
+        http_request.extend_from_slice(&request.body);
+
+        let scheduler = Rc::new(RefCell::new(
+            CooperativeCircuit::new(circuit).with_cancel(options.cancel.clone()),
+        ));
+        let (target, cached) = self.stream_target(&isolation_key, &host);
+        let mut response = ResumableDownload::new();
+        let mut report = ExchangeReport::default();
+        let result = Self::cooperative_exchange(
+            &scheduler,
+            &target,
+            &host,
+            port,
+            options.begin_flags,
+            is_https,
+            &http_request,
+            &mut response,
+            None,
+            &mut report,
+        )
+        .await;
+        if let Some(exit) = &exit {
+            self.note_exchange(&host, exit, &report, response.is_complete());
+        }
+        self.note_connected(
+            &isolation_key,
+            &host,
+            port,
+            exit.clone(),
+            report.connected_address,
+            cached,
+        );
+
+        if result.is_err()
+            && options
+                .cancel
+                .as_ref()
+                .is_some_and(CancelToken::is_cancelled)
+        {
+            self.abandon_cancelled(scheduler, None, class).await;
+            return Err(TorError::Cancelled.into());
+        }
+        result?;
+
+        self.reclaim_circuit(scheduler, None, class);
+
+        let response_bytes = response.into_response();
+        log::info!(
+            "✅ [COOP] {} complete: {} bytes",
+            request.method,
+            response_bytes.len()
+        );
+        self.note_response(&host, port, is_https, &response_bytes);
+        self.account_transfer(http_request.len() + response_bytes.len(), options.max_bytes)?;
+        let response_bytes = self
+            .http_padding
+            .strip_response(&isolation_key, response_bytes);
+        let exits: Vec<String> = exit.into_iter().collect();
+        self.check_integrity(integrity.as_ref(), &response_bytes, is_https, &exits)?;
+
+        self.record_latency(&isolation_key, started_ms);
+        Ok(response_bytes)
+    }
+
+    /// Raw response to a cooperative GET, with the plain-HTTP policy and
+    /// HTTPS upgrade applied
+    async fn cooperative_get_bytes(
+        &mut self,
+        url: &str,
+        circuit_id: Option<u32>,
+        options: &FetchOptions,
+    ) -> std::result::Result<Vec<u8>, JsValue> {
+        self.ensure_ready()?;
+        self.admit_request().await?;
+        let plan = self.http_plan(url, Some(options))?;
+        match (
+            self.cooperative_get_bytes_url(&plan.url, circuit_id, options)
+                .await,
+            plan.fallback,
+        ) {
+            (Err(e), Some(fallback)) => {
+                log::warn!(
+                    "🔓 HTTPS upgrade of {} failed ({:?}), retrying over HTTP",
+                    url,
+                    e
+                );
+                self.cooperative_get_bytes_url(&fallback, circuit_id, options)
+                    .await
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Parsed and decoded response to a GET of `url`, following
+    /// redirects, failing unless it is 2xx when `require_success` is set
+    ///
+    /// `integrity` is checked against the final body after content decoding.
+    async fn fetch_decoded(
+        &mut self,
+        url: &str,
+        options: JsValue,
+        require_success: bool,
+    ) -> std::result::Result<HttpResponse, JsValue> {
+        let options = parse_fetch_options(options)?;
+        let integrity = options.integrity()?;
+        let config = self.http_client.clone();
+        let mut hop_options = FetchOptions {
+            integrity: None,
+            accept_encoding: config.decompress.then_some(http_client::ACCEPT_ENCODING),
+            ..options
+        };
+
+        let original = url;
+        let mut url = url.to_string();
+        let mut redirects = 0;
+        let mut response = loop {
+            let raw = self.cooperative_get_bytes(&url, None, &hop_options).await?;
+            let response = HttpResponse::parse(&raw)?;
+            let Some(next) = config.redirect(&response, &url)? else {
+                break response;
+            };
+            if redirects == config.max_redirects {
+                return Err(JsValue::from_str(&format!(
+                    "Too many redirects (over {}) from {}",
+                    config.max_redirects, url
+                )));
+            }
+            redirects += 1;
+            log::info!("↪️ {} redirects to {}", url, next);
+            http_client::follow_with(&mut hop_options, original, &next);
+            url = next;
+        };
+        if require_success && !response.is_success() {
+            return Err(JsValue::from_str(&format!(
+                "HTTP {} for {}",
+                response.status, url
+            )));
+        }
+        config.decode(&mut response)?;
+        if let Some(integrity) = integrity.filter(|_| response.is_success()) {
+            integrity.verify(&response.body)?;
+        }
+        Ok(response)
+    }
+
+    /// `fetch_get_cooperative_bytes()` of one URL, after the plain-HTTP policy
+    async fn cooperative_get_bytes_url(
+        &mut self,
+        url: &str,
+        circuit_id: Option<u32>,
+        options: &FetchOptions,
+    ) -> std::result::Result<Vec<u8>, JsValue> {
+        let started_ms = now_ms();
+        let integrity = options.integrity()?;
+
+        let (host, port, path, is_https) =
+            parse_url(url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+
+        let scheme = if is_https { "HTTPS" } else { "HTTP" };
+        log::info!("🌐 [COOP-BIN] GET {} via Tor ({})...", url, scheme);
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let (response_bytes, exits) = self
+            .cooperative_get(
+                &host,
+                port,
+                &path,
+                is_https,
+                circuit_id,
+                &isolation_key,
+                options,
+                None,
+            )
+            .await?;
+
+        log::info!("✅ [COOP-BIN] GET complete: {} bytes", response_bytes.len());
+        self.note_response(&host, port, is_https, &response_bytes);
+        self.account_transfer(response_bytes.len(), options.max_bytes)?;
+        let response_bytes = self
+            .http_padding
+            .strip_response(&isolation_key, response_bytes);
+        self.check_integrity(integrity.as_ref(), &response_bytes, is_https, &exits)?;
+
+        self.record_latency(&isolation_key, started_ms);
+        Ok(response_bytes)
+    }
+
+    /// `fetch_to_file()` of one URL, after the plain-HTTP policy
+    async fn cooperative_file_url(
+        &mut self,
+        url: &str,
+        options: &FetchOptions,
+        sink: &mut FileSink,
+    ) -> std::result::Result<(), JsValue> {
+        let started_ms = now_ms();
+        let (host, port, path, is_https) =
+            parse_url(url).map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+        log::info!("📁 GET {} via Tor into {}...", url, sink.path());
+
+        let isolation_key = self
+            .circuit_cache
+            .with(|cache| cache.isolation_key(&host, port));
+        let before = sink.written();
+        let result = self
+            .cooperative_get(
+                &host,
+                port,
+                &path,
+                is_https,
+                None,
+                &isolation_key,
+                options,
+                Some(&mut *sink),
+            )
+            .await;
+        // Commit whatever arrived, even if the download failed
+        let finished = sink.finish().await;
+        let (head, _exits) = result?;
+        finished?;
+
+        self.note_response(&host, port, is_https, &head);
+        let body = sink.written().saturating_sub(before);
+        self.account_transfer(head.len() + body as usize, options.max_bytes)?;
+        match sink.status() {
+            Some(200) => {}
+            Some(status) => {
+                return Err(JsValue::from_str(&format!("HTTP {} for {}", status, url)));
+            }
+            None => return Err(JsValue::from_str(&format!("No response for {}", url))),
+        }
+        if !sink.is_complete() {
+            return Err(JsValue::from_str(&format!(
+                "Download into {} stopped after {} bytes; call again with resume to continue",
+                sink.path(),
+                sink.written()
+            )));
+        }
+
+        log::info!("✅ 📁 {} complete: {} bytes", sink.path(), sink.written());
+        self.record_latency(&isolation_key, started_ms);
+        Ok(())
+    }
+
+    /// GET `path` over the cooperative scheduler, resuming on a new circuit
+    /// when a resumable response is cut short (see [`resume`])
+    ///
+    /// A request pinned to `circuit_id` is not resumed: it must not move to
+    /// another path. A response cut short that can't be resumed is returned
+    /// as far as it got, or fails if nothing arrived.
+    ///
+    /// With a `sink` the body goes to its file as it arrives, and only the
+    /// head is returned.
+    ///
+    /// Also returns the fingerprints of the exits the response came through.
+    #[allow(clippy::too_many_arguments)]
+    async fn cooperative_get(
+        &mut self,
+        host: &str,
+        port: u16,
+        path: &str,
+        is_https: bool,
+        circuit_id: Option<u32>,
+        isolation_key: &IsolationKey,
+        options: &FetchOptions,
+        mut sink: Option<&mut FileSink>,
+    ) -> std::result::Result<(Vec<u8>, Vec<String>), JsValue> {
+        let mut download = sink
+            .as_ref()
+            .map_or_else(ResumableDownload::new, |s| s.download());
+        let mut exits = Vec::new();
+        let class = PortClass::for_lifetime(self.relay_requirements.stream_lifetime(port, false));
+        let mut circuit = match circuit_id {
+            Some(id) => self.detach_circuit(id)?,
+            None => {
+                self.pooled_circuit(isolation_key, class, options.cancel.as_ref())
+                    .await?
+            }
+        };
+        let mut range_headers = download.resume_headers().unwrap_or_default();
+        let extra_headers = http_client::request_headers(options);
+
+        loop {
+            let http_request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n{}{}\r\n",
+                path, host, extra_headers, range_headers
+            );
+            let http_request = self.http_padding.pad_request(isolation_key, http_request);
+            let exit = exit_fingerprint(&circuit);
+            exits.extend(exit.clone());
+
+            // Wrap in cooperative scheduler
+            let scheduler = Rc::new(RefCell::new(
+                CooperativeCircuit::new(circuit).with_cancel(options.cancel.clone()),
+            ));
+            let (target, cached) = self.stream_target(isolation_key, host);
+            let mut report = ExchangeReport::default();
+            let result = Self::cooperative_exchange(
+                &scheduler,
+                &target,
+                host,
+                port,
+                options.begin_flags,
+                is_https,
+                http_request.as_bytes(),
+                &mut download,
+                sink.as_deref_mut(),
+                &mut report,
+            )
+            .await;
+            if let Some(exit) = &exit {
+                self.note_exchange(host, exit, &report, download.is_complete());
+            }
+            self.note_connected(
+                isolation_key,
+                host,
+                port,
+                exit,
+                report.connected_address,
+                cached,
+            );
+
+            if result.is_err()
+                && options
+                    .cancel
+                    .as_ref()
+                    .is_some_and(CancelToken::is_cancelled)
+            {
+                self.abandon_cancelled(scheduler, circuit_id, class).await;
+                return Err(TorError::Cancelled.into());
+            }
+
+            let resume = match circuit_id {
+                Some(_) => None,
+                None => download.resume_headers(),
+            };
+            let Some(headers) = resume else {
+                let failed = match result {
+                    Err(e) if download.body_len() == 0 => Some(e),
+                    Err(e) => {
+                        log::warn!(
+                            "  ⚠️ Response cut short after {} body bytes: {:?}",
+                            download.body_len(),
+                            e
+                        );
+                        None
+                    }
+                    Ok(()) => None,
+                };
+                // Try to return circuit to pool for reuse; an attached one
+                // goes back even when the request failed
+                if failed.is_none() || circuit_id.is_some() {
+                    self.reclaim_circuit(scheduler, circuit_id, class);
+                }
+                return match failed {
+                    Some(e) => Err(e),
+                    None => Ok((download.into_response(), exits)),
+                };
+            };
+
+            log::warn!(
+                "♻️ Download interrupted after {} body bytes, resuming on a new circuit ({}/{})",
+                download.body_len(),
+                download.resumes() + 1,
+                resume::MAX_RESUMES
+            );
+            download.begin_resume();
+            range_headers = headers;
+            circuit = self
+                .pooled_circuit(isolation_key, class, options.cancel.as_ref())
+                .await?;
+        }
+    }
+
+    /// Clean up after a cooperative request was cancelled: a pinned circuit
+    /// sends its streams' ENDs and stays attached, a pooled one is destroyed
+    async fn abandon_cancelled(
+        &mut self,
+        scheduler: Rc<RefCell<CooperativeCircuit>>,
+        circuit_id: Option<u32>,
+        class: PortClass,
+    ) {
+        if circuit_id.is_some() {
+            SchedulerDriver::new(scheduler.clone())
+                .run_until_idle()
+                .await;
+        }
+        let Ok(coop_cell) = Rc::try_unwrap(scheduler) else {
+            return;
+        };
+        let Some(mut circuit) = coop_cell.into_inner().checkout_circuit() else {
+            return;
+        };
+        match circuit_id {
+            Some(_) => self.release_circuit(circuit, circuit_id, class),
+            None => {
+                let _ = circuit.destroy().await;
+            }
+        }
+    }
+
+    /// Open a stream, send `request` and feed the response to `download`
+    /// (and on to `sink`) until it is complete or the stream ends, noting in
+    /// `report` what the exit did along the way
+    #[allow(clippy::too_many_arguments)]
+    async fn cooperative_exchange(
+        scheduler: &Rc<RefCell<CooperativeCircuit>>,
+        target: &str,
+        host: &str,
+        port: u16,
+        begin_flags: protocol::BeginFlags,
+        is_https: bool,
+        request: &[u8],
+        download: &mut ResumableDownload,
+        mut sink: Option<&mut FileSink>,
+        report: &mut ExchangeReport,
+    ) -> std::result::Result<(), JsValue> {
+        let stream = open_cooperative_stream(scheduler, target, port, begin_flags)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
+        report.connected_address = stream.connected_address();
+        let mut buf = [0u8; 4096];
+
+        if is_https {
+            let mut tls_stream = CooperativeTlsStream::new(stream, host)
+                .await
+                .map_err(|e| JsValue::from_str(&format!("TLS handshake failed: {}", e)))?;
+            report.certificate = tls_stream.certificate_digest();
+            tls_stream
+                .write_all(request)
+                .await
+                .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
+            while !download.is_complete() {
+                let read = tls_stream.read(&mut buf).await;
+                report.end_reason = tls_stream.end_reason();
+                let n = read.map_err(|e| {
+                    JsValue::from_str(&format!("Failed to receive response: {}", e))
+                })?;
+                if n == 0 {
+                    break;
+                }
+                download.feed(&buf[..n]).map_err(JsValue::from)?;
+                if let Some(sink) = sink.as_deref_mut() {
+                    sink.drain(download).await?;
+                }
+            }
+            let _ = tls_stream.close().await;
+        } else {
+            let mut stream = stream;
+            stream
+                .write_all(request)
+                .await
+                .map_err(|e| JsValue::from_str(&format!("Failed to send request: {}", e)))?;
+            while !download.is_complete() {
+                let read = stream.read(&mut buf).await;
+                report.end_reason = stream.end_reason();
+                let n = read.map_err(|e| {
+                    JsValue::from_str(&format!("Failed to receive response: {}", e))
+                })?;
+                if n == 0 {
+                    break;
+                }
+                download.feed(&buf[..n]).map_err(JsValue::from)?;
+                if let Some(sink) = sink.as_deref_mut() {
+                    sink.drain(download).await?;
+                }
+            }
+            let _ = stream.close().await;
+        }
+        Ok(())
+    }
+
+    /// Circuit registered under `circuit_id` by `build_custom_circuit()`
+    ///
+    /// A circuit whose link has gone away is dropped from the registry and
+    /// reported as closed.
+    fn attached_circuit(
+        &mut self,
+        circuit_id: u32,
+    ) -> std::result::Result<Rc<RefCell<protocol::Circuit>>, JsValue> {
+        let circuit = self
+            .custom_circuits
+            .with(|circuits| circuits.get(&circuit_id).cloned())
+            .ok_or_else(|| JsValue::from_str(&format!("No circuit with ID {}", circuit_id)))?;
+
+        let alive = circuit
+            .try_borrow()
+            .map(|c| c.is_connected())
+            .unwrap_or(true);
+        if !alive {
+            self.custom_circuits
+                .with(|circuits| circuits.remove(&circuit_id));
+            return Err(JsValue::from_str(&format!(
+                "Circuit {} is closed",
+                circuit_id
+            )));
+        }
+        Ok(circuit)
+    }
+
+    /// Take an attached circuit out of the registry for the cooperative
+    /// scheduler, which needs to own it. Hand it back with `release_circuit`
+    /// (or `reclaim_circuit`) whether or not the request succeeds.
+    fn detach_circuit(
+        &mut self,
+        circuit_id: u32,
+    ) -> std::result::Result<protocol::Circuit, JsValue> {
+        let circuit = self.attached_circuit(circuit_id)?;
+        self.custom_circuits
+            .with(|circuits| circuits.remove(&circuit_id));
+        Rc::try_unwrap(circuit)
+            .map(RefCell::into_inner)
+            .map_err(|circuit| {
+                self.custom_circuits
+                    .with(|circuits| circuits.insert(circuit_id, circuit));
+                JsValue::from_str(&format!("Circuit {} is busy", circuit_id))
+            })
+    }
+
+    /// Return a circuit after a cooperative request: attached circuits go
+    /// back to the registry, everything else to the prebuilt pool as `class`
+    fn release_circuit(
+        &mut self,
+        circuit: protocol::Circuit,
+        circuit_id: Option<u32>,
+        class: PortClass,
+    ) {
+        match circuit_id {
+            Some(id) => {
+                self.custom_circuits
+                    .with(|circuits| circuits.insert(id, Rc::new(RefCell::new(circuit))));
+            }
+            None => self.circuit_pool.return_circuit(circuit, class),
+        }
+    }
+
+    /// `release_circuit` the circuit of a finished cooperative request, if
+    /// no stream still holds its scheduler
+    fn reclaim_circuit(
+        &mut self,
+        scheduler: Rc<RefCell<CooperativeCircuit>>,
+        circuit_id: Option<u32>,
+        class: PortClass,
+    ) {
+        if let Ok(coop_cell) = Rc::try_unwrap(scheduler) {
+            if let Some(circuit) = coop_cell.into_inner().checkout_circuit() {
+                self.release_circuit(circuit, circuit_id, class);
+            }
+        }
+    }
+
+    /// Get a circuit of `class` for `key` from the prebuilt pool (or build
+    /// one unless `cancel` is cancelled), rate limited
+    async fn pooled_circuit(
+        &mut self,
+        key: &IsolationKey,
+        class: PortClass,
+        cancel: Option<&CancelToken>,
+    ) -> std::result::Result<protocol::Circuit, JsValue> {
+        if let Some(cancel) = cancel {
+            cancel.check()?;
+        }
+        if !self.rate_limiter.can_create_circuit_for(key.as_str()) {
+            return Err(JsValue::from_str(
+                "Rate limited: too many circuit requests. Please wait.",
+            ));
+        }
+
+        let builder = self
+            .circuit_builder
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
+            .clone()
+            .with_cancel(cancel.cloned());
+
+        let selector = self
+            .relay_selector
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
+            .clone();
+
+        self.circuit_pool.record_demand(key.as_str(), class);
+        let result = self
+            .circuit_pool
+            .get_circuit(&builder, &selector, class)
+            .await;
+        self.persist_guard_outcomes();
+        let circuit = result.map_err(|e| match e {
+            TorError::Cancelled => JsValue::from(e),
+            e => JsValue::from_str(&format!("Circuit failed: {}", e)),
+        })?;
+
+        self.rate_limiter
+            .record_circuit_created_for(key.as_str(), circuit.id);
+        Ok(circuit)
+    }
+
+    /// Look up a relay in the current consensus by identity fingerprint
+    fn find_relay(&self, fingerprint: &str) -> std::result::Result<protocol::Relay, JsValue> {
+        let consensus = self
+            .consensus
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("No consensus"))?;
+        let wanted = fingerprint.trim().trim_start_matches('$');
+        consensus
+            .relays
+            .iter()
+            .find(|r| r.fingerprint.eq_ignore_ascii_case(wanted))
+            .cloned()
+            .ok_or_else(|| JsValue::from_str(&format!("Relay {} not in consensus", wanted)))
+    }
+
+    /// One-hop circuit to a usable guard for directory requests over
+    /// BEGIN_DIR, or `None` before the first bootstrap or if none builds
+    async fn directory_circuit(&self) -> Option<Rc<RefCell<protocol::Circuit>>> {
+        let builder = self.circuit_builder.as_ref()?;
+        let consensus = self.consensus.as_ref()?;
+        let usable = self
+            .guard_state
+            .with(|g| g.usable_guards().into_iter().cloned().collect::<Vec<_>>());
+        let guard = usable.iter().find_map(|fingerprint| {
+            consensus
+                .relays
+                .iter()
+                .find(|r| r.fingerprint.eq_ignore_ascii_case(fingerprint) && r.flags.v2_dir)
+        })?;
+        match builder
+            .build_circuit_through(std::slice::from_ref(guard))
+            .await
+        {
+            Ok(circuit) => Some(Rc::new(RefCell::new(circuit))),
+            Err(e) => {
+                log::warn!(
+                    "⚠️ No directory circuit to {} ({}); using the bridge",
+                    guard.nickname,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Circuit for `key` from the isolation cache, building one if needed
+    ///
+    /// A cached circuit whose exit can't carry a stream of `lifetime` is
+    /// replaced by one built for it, unless `cancel` is cancelled.
+    async fn isolated_circuit(
+        &mut self,
+        key: &IsolationKey,
+        host: &str,
+        lifetime: protocol::StreamLifetime,
+        cancel: Option<&CancelToken>,
+    ) -> std::result::Result<Rc<RefCell<protocol::Circuit>>, JsValue> {
+        if let Some(cached) = self.circuit_cache.with(|cache| cache.get(key)) {
+            if lifetime.suits(&cached.borrow()) {
+                log::info!("  ♻️ Reusing existing circuit for '{}'", host);
+                self.keepalive.with(|k| k.touch(key.as_str()));
+                return Ok(cached);
+            }
+            log::info!(
+                "  🐢 Cached circuit for '{}' has no Stable exit; replacing it",
+                host
+            );
+            let circuit_id = cached.borrow().id;
+            self.circuit_cache
+                .with(|cache| cache.remove_circuit(key, circuit_id));
+        }
+
+        // Rate limiting check for new circuit
+        if !self.rate_limiter.can_create_circuit_for(key.as_str()) {
+            log::error!("❌ Rate limited: too many circuits created recently");
+            return Err(JsValue::from_str(
+                "Rate limited: too many circuit requests. Please wait.",
+            ));
+        }
+
+        if protocol::is_onion_host(host) {
+            return self.onion_circuit(key, host, lifetime).await;
+        }
+
+        // A warm circuit of the right class saves the build. Reserved keys
+        // always get a fresh one: cooperative requests hand their circuits
+        // back to the pool, so a pooled circuit may have carried user traffic
+        let class = PortClass::for_lifetime(lifetime);
+        let pooled = if key.is_reserved() {
+            None
+        } else {
+            self.circuit_pool.record_demand(key.as_str(), class);
+            self.circuit_pool.take(class)
+        };
+        if let Some(circuit) = pooled {
+            self.rate_limiter
+                .record_circuit_created_for(key.as_str(), circuit.id);
+            log::info!("  🔥 Using prebuilt circuit {} for '{}'", circuit.id, host);
+            return Ok(self
+                .circuit_cache
+                .with(|cache| cache.store(key.clone(), circuit)));
+        }
+
+        if let Some(cancel) = cancel {
+            cancel.check()?;
+        }
+        log::info!("  🔨 Building new circuit for '{}'...", host);
+
+        let builder = self
+            .circuit_builder
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
+            .clone()
+            .with_cancel(cancel.cloned());
+
+        let selector = self
+            .relay_selector
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
+            .clone()
+            .for_stream(lifetime);
+
+        let result = builder.build_circuit(&selector).await;
+        self.persist_guard_outcomes();
+        let circuit = result.map_err(|e| match e {
+            TorError::Cancelled => JsValue::from(e),
+            e => JsValue::from_str(&format!("Circuit build failed: {}", e)),
+        })?;
+
+        // Record circuit creation for rate limiting
+        self.rate_limiter
+            .record_circuit_created_for(key.as_str(), circuit.id);
+
+        log::info!("  ✅ Circuit {} built", circuit.id);
+
+        // Cache the circuit for future requests to this domain
+        Ok(self
+            .circuit_cache
+            .with(|cache| cache.store(key.clone(), circuit)))
+    }
+
+    /// Build a rendezvous circuit joined to the onion service at `host`
+    /// and cache it under `key`
+    async fn onion_circuit(
+        &mut self,
+        key: &IsolationKey,
+        host: &str,
+        lifetime: protocol::StreamLifetime,
+    ) -> std::result::Result<Rc<RefCell<protocol::Circuit>>, JsValue> {
+        let consensus = self
+            .consensus
+            .clone()
+            .ok_or_else(|| JsValue::from_str("Consensus not loaded"))?;
+        let builder = self
+            .circuit_builder
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Circuit builder not initialized"))?
+            .clone();
+        let selector = self
+            .relay_selector
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Relay selector not initialized"))?
+            .clone()
+            .for_stream(lifetime);
+
+        let result = self
+            .onion_services
+            .connect(
+                host,
+                &consensus,
+                &builder,
+                &selector,
+                &mut self.circuit_pool,
+            )
+            .await;
+        self.persist_guard_outcomes();
+        let circuit = result.map_err(JsValue::from)?;
+
+        self.rate_limiter
+            .record_circuit_created_for(key.as_str(), circuit.id);
+        log::info!("  ✅ Circuit {} joined to {}", circuit.id, host);
+        Ok(self
+            .circuit_cache
+            .with(|cache| cache.store(key.clone(), circuit)))
+    }
+
+    /// Host to put in RELAY_BEGIN: a cached exit DNS answer when there is
+    /// one for this isolation key, otherwise `host` itself
+    fn stream_target(&mut self, key: &IsolationKey, host: &str) -> (String, bool) {
+        match self.dns_cache.lookup(key, host) {
+            Some(addresses) => {
+                log::info!("  📇 Using cached address {} for {}", addresses[0], host);
+                (dns_cache::begin_host(addresses[0]), true)
+            }
+            None => (host.to_string(), false),
+        }
+    }
+
+    /// Open a stream to `host`, skipping exit DNS via the cache where
+    /// possible and caching the address the exit reports on CONNECTED
+    async fn open_stream_cached(
+        &mut self,
+        circuit: Rc<RefCell<protocol::Circuit>>,
+        key: &IsolationKey,
+        host: &str,
+        port: u16,
+        lifetime: protocol::StreamLifetime,
+        begin_flags: protocol::BeginFlags,
+    ) -> std::result::Result<protocol::TorStream, JsValue> {
+        if let Some(delay) = self.request_jitter.delay_for(key) {
+            gloo_timers::future::TimeoutFuture::new(delay).await;
+        }
+        if protocol::is_onion_host(host) {
+            let stream = protocol::StreamManager::new(circuit)
+                .open_service_stream(port)
+                .await
+                .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
+            self.note_connected(key, host, port, None, None, false);
+            return Ok(stream);
+        }
+        let (target, cached) = self.stream_target(key, host);
+        let exit = exit_fingerprint(&circuit.borrow());
+
+        let mut stream_manager =
+            protocol::StreamManager::new(circuit).with_begin_flags(begin_flags);
+        let stream = stream_manager
+            .open_stream(&target, port, lifetime)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Stream open failed: {}", e)))?;
+
+        self.note_connected(key, host, port, exit, stream.connected_address(), cached);
+        Ok(stream)
+    }
+
+    /// Act on the address an exit reported in RELAY_CONNECTED: cache it
+    /// (unless `host` was sent as a cached address), flag an exit that
+    /// resolved a public hostname to an internal address, and record it
+    /// for `last_response()`
+    fn note_connected(
+        &mut self,
+        key: &IsolationKey,
+        host: &str,
+        port: u16,
+        exit: Option<String>,
+        answer: Option<protocol::DnsAnswer>,
+        cached: bool,
+    ) {
+        if let Some(answer) = answer {
+            if !cached {
+                self.dns_cache.insert(key, host, &[answer]);
+            }
+            let is_hostname = matches!(
+                protocol::BeginTarget::parse(host),
+                Ok(protocol::BeginTarget::Hostname(_))
+            );
+            if is_hostname && answer.is_internal() {
+                log::warn!(
+                    "⚠️ Exit resolved {} to internal address {}",
+                    host,
+                    answer.address
+                );
+                if let Some(exit) = &exit {
+                    self.note_exit_anomaly(
+                        exit,
+                        ExitAnomaly::InternalAddress,
+                        &format!("{} resolved to {}", host, answer.address),
+                    );
+                }
+            }
+        }
+        self.last_response = Some(ResponseMetadata {
+            host: host.to_string(),
+            port,
+            exit,
+            exit_resolved_address: answer,
+        });
+    }
+
+    /// Circuit build failure counters plus the likely cause
+    fn circuit_failure_metrics(&self) -> serde_json::Value {
+        let consensus_fresh = self.consensus.as_ref().is_none_or(|c| c.is_fresh());
+        let (failures, likely_cause) = self
+            .build_failures
+            .with(|f| (f.report(), f.diagnose(consensus_fresh)));
+
+        let mut circuits = serde_json::to_value(failures).unwrap_or_default();
+        circuits["likely_cause"] = serde_json::json!(likely_cause);
+        circuits
+    }
+
+    /// Record a completed request's latency under its isolation key
+    fn record_latency(&mut self, key: &IsolationKey, started_ms: u64) {
+        let elapsed_ms = now_ms().saturating_sub(started_ms);
+        self.latency.record(key, elapsed_ms);
+    }
+
+    /// Remember Onion-Location / Alt-Svc hints from an HTTPS response
+    fn note_response(&mut self, host: &str, port: u16, is_https: bool, response: &[u8]) {
+        // Plain-HTTP headers are under the exit's control; ignore them
+        if is_https {
+            let origin = origin_hints::origin(true, host, port);
+            self.origin_hints.record(&origin, response);
+        }
+    }
+
+    /// Check a stripped response against `integrity`, marking the exits
+    /// suspicious on a plain-HTTP mismatch (over TLS they can't have
+    /// changed the body)
+    fn check_integrity(
+        &mut self,
+        integrity: Option<&Integrity>,
+        response: &[u8],
+        is_https: bool,
+        exits: &[String],
+    ) -> std::result::Result<(), JsValue> {
+        let Some(integrity) = integrity else {
+            return Ok(());
+        };
+        let result = integrity.verify_response(response);
+        if let Err(TorError::IntegrityMismatch(ref detail)) = result {
+            log::warn!("  ⚠️ Integrity check failed: {}", detail);
+            if !is_https {
+                for exit in exits {
+                    self.note_exit_anomaly(exit, ExitAnomaly::InjectedContent, detail);
+                }
+            }
+        }
+        result.map_err(JsValue::from)
+    }
+
+    /// Check what a cooperative exchange revealed about its exit
+    fn note_exchange(&mut self, host: &str, exit: &str, report: &ExchangeReport, complete: bool) {
+        if let Some(digest) = &report.certificate {
+            self.note_certificate(host, digest, exit);
+        }
+        match report.end_reason {
+            Some(reason) if !complete && ExitAnomaly::is_reset_reason(reason) => {
+                self.note_exit_anomaly(
+                    exit,
+                    ExitAnomaly::UnexpectedReset,
+                    &format!("END reason {} mid-response from {}", reason, host),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the TLS certificate an exit showed for `host`
+    fn note_certificate(&mut self, host: &str, digest: &str, exit: &str) {
+        if self.relay_verifier.observe_certificate(host, digest, exit) {
+            self.retire_exit(exit);
+        }
+    }
+
+    /// Record an exit anomaly, retiring the exit if that gets it banned
+    fn note_exit_anomaly(&mut self, exit: &str, kind: ExitAnomaly, detail: &str) {
+        if self.relay_verifier.record_anomaly(exit, kind, detail) {
+            self.retire_exit(exit);
+        }
+    }
+
+    /// Stop selecting a banned exit and drop circuits that end at it
+    ///
+    /// Circuits from `build_custom_circuit()` are left alone: their path
+    /// was chosen by the caller.
+    fn retire_exit(&mut self, exit: &str) {
+        if let Some(selector) = self.relay_selector.as_mut() {
+            selector.ban_exit(exit);
+        }
+        let ends_at_exit =
+            |circuit: &protocol::Circuit| exit_fingerprint(circuit).as_deref() == Some(exit);
+        let cached: Vec<(IsolationKey, u32)> = self.circuit_cache.with(|cache| {
+            cache
+                .circuits()
+                .filter_map(|(key, circuit)| {
+                    let circuit = circuit.try_borrow().ok()?;
+                    ends_at_exit(&circuit).then(|| (key.clone(), circuit.id))
+                })
+                .collect()
+        });
+        for (key, circuit_id) in &cached {
+            self.circuit_cache
+                .with(|cache| cache.remove_circuit(key, *circuit_id));
+        }
+        let pooled = self.circuit_pool.size();
+        self.circuit_pool.retain(|circuit| !ends_at_exit(circuit));
+        log::warn!(
+            "🚫 Dropped {} cached and {} pooled circuits through banned exit {}",
+            cached.len(),
+            pooled - self.circuit_pool.size(),
+            &exit[..8.min(exit.len())]
+        );
+    }
+
+    /// Stop selecting blocklisted relays and drop circuits through them
+    ///
+    /// Circuits from `build_custom_circuit()` are left alone, as in
+    /// `retire_exit()`; new ones are checked by the builder.
+    fn apply_blocklist(&mut self) {
+        let list = self.blocklist.shared();
+        if let Some(selector) = self.relay_selector.as_mut() {
+            selector.set_blocked_relays(list.with(|l| l.blocked_in(selector.relays())));
+        }
+        let blocked_hop =
+            |circuit: &protocol::Circuit| list.with(|l| circuit.relays.iter().any(|r| l.blocks(r)));
+        let cached: Vec<(IsolationKey, u32)> = self.circuit_cache.with(|cache| {
+            cache
+                .circuits()
+                .filter_map(|(key, circuit)| {
+                    let circuit = circuit.try_borrow().ok()?;
+                    blocked_hop(&circuit).then(|| (key.clone(), circuit.id))
+                })
+                .collect()
+        });
+        for (key, circuit_id) in &cached {
+            self.circuit_cache
+                .with(|cache| cache.remove_circuit(key, *circuit_id));
+        }
+        let pooled = self.circuit_pool.size();
+        self.circuit_pool.retain(|circuit| !blocked_hop(circuit));
+        let dropped = pooled - self.circuit_pool.size();
+        if !cached.is_empty() || dropped > 0 {
+            log::warn!(
+                "🚫 Dropped {} cached and {} pooled circuits through blocklisted relays",
+                cached.len(),
+                dropped
+            );
+        }
+    }
+
+    /// Fail unless the client is bootstrapped and has not been shut down
+    /// Refuse a new request if a bandwidth quota blocks it, or wait while
+    /// one throttles
+    async fn admit_request(&mut self) -> std::result::Result<(), JsValue> {
+        self.bandwidth_quota.admit(now_ms() / 1000)?;
+        self.sync_throttle();
+        let delay = self.rate_limiter.throttle_delay_ms();
+        if delay > 0 {
+            log::info!("📊 Bandwidth quota used up, pacing request by {}ms", delay);
+            gloo_timers::future::TimeoutFuture::new(delay.min(u32::MAX as u64) as u32).await;
+        }
+        Ok(())
+    }
+
+    /// Count a request's transfer against the bandwidth quotas
+    ///
+    /// Fails if it exceeded the per-request quota (`request_limit`, or the
+    /// configured one).
+    fn account_transfer(
+        &mut self,
+        bytes: usize,
+        request_limit: Option<u64>,
+    ) -> std::result::Result<(), JsValue> {
+        let bytes = bytes as u64;
+        let (events, result) = self
+            .bandwidth_quota
+            .record(bytes, request_limit, now_ms() / 1000);
+        if self.bandwidth_quota.config().daily_bytes > 0 {
+            let key = self.storage_config.key(bandwidth_quota::DAILY_USAGE_KEY);
+            bandwidth_quota::save_daily(&key, self.bandwidth_quota.daily());
+        }
+        self.rate_limiter.record_throttled_bytes(bytes);
+        self.sync_throttle();
+        for event in &events {
+            self.emit_quota_event(event);
+        }
+        Ok(result?)
+    }
+
+    /// Turn rate limiter pacing on or off to match the bandwidth quota
+    fn sync_throttle(&mut self) {
+        let throttled = self.bandwidth_quota.is_throttled(now_ms() / 1000);
+        if throttled != self.rate_limiter.is_throttled() {
+            let rate = self.bandwidth_quota.config().throttle_bytes_per_second;
+            self.rate_limiter.set_throttle(throttled.then_some(rate));
+        }
+    }
+
+    /// Tell the `on_bandwidth_quota` listener about a crossed threshold
+    fn emit_quota_event(&self, event: &QuotaEvent) {
+        log::warn!(
+            "📊 Bandwidth quota {:?} {:?}: {} of {} bytes",
+            event.scope,
+            event.level,
+            event.used_bytes,
+            event.limit_bytes
+        );
+        let Some(callback) = &self.quota_listener else {
+            return;
+        };
+        let value = serde_wasm_bindgen::to_value(&serde_json::json!({
+            "event": "bandwidth_quota",
+            "scope": event.scope,
+            "level": event.level,
+            "used_bytes": event.used_bytes,
+            "limit_bytes": event.limit_bytes,
+        }))
+        .unwrap_or(JsValue::NULL);
+        if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+            log::warn!("📊 bandwidth_quota callback threw: {:?}", e);
+        }
+    }
+
+    /// Apply the plain-HTTP policy (see [`http_policy`]) to `url`, with
+    /// per-request overrides from `options`
+    fn http_plan(
+        &self,
+        url: &str,
+        options: Option<&FetchOptions>,
+    ) -> std::result::Result<HttpPlan, JsValue> {
+        let policy = match options {
+            Some(o) => self
+                .http_security
+                .with_overrides(o.allow_insecure_http, o.upgrade_to_https),
+            None => self.http_security.clone(),
+        };
+        Ok(policy.plan(url)?)
+    }
+
+    fn ensure_ready(&self) -> std::result::Result<(), JsValue> {
+        if self.shut_down {
+            return Err(JsValue::from_str(CLIENT_SHUT_DOWN));
+        }
+        if !self.bootstrapped {
+            return Err(JsValue::from_str("Client not bootstrapped"));
+        }
+        // Circuits are built on demand, from the consensus and guards kept
+        self.dormancy.wake(dormant::WakeReason::Request);
+        self.ensure_keepalive_task();
+        Ok(())
+    }
+
+    /// Start probing idle cached circuits in the background, unless that is
+    /// already running
+    ///
+    /// The task stops with the others at a new identity, dormancy or
+    /// shutdown; the next request starts it again.
+    fn ensure_keepalive_task(&self) {
+        if self.tasks.is_running("keepalive") {
+            return;
+        }
+        let cache = Rc::clone(&self.circuit_cache);
+        let monitor = Rc::clone(&self.keepalive);
+        let dormancy = self.dormancy.clone();
+        self.tasks.spawn("keepalive", async move {
+            loop {
+                let interval = monitor.with(|k| k.config().probe_interval_ms);
+                runtime::TimerService::global()
+                    .sleep(std::time::Duration::from_millis(interval))
+                    .await;
+                if !dormancy.suppress("keepalive probes") {
+                    keepalive::probe_idle(&cache, &monitor).await;
+                }
+            }
+        });
+    }
+
+    /// DESTROY every cached, pooled and custom circuit, returning how many
+    /// were destroyed
+    async fn destroy_all_circuits(&mut self) -> usize {
+        let mut circuits: Vec<protocol::Circuit> = self.circuit_pool.drain();
+        let shared = self
+            .circuit_cache
+            .with(|cache| cache.drain())
+            .into_iter()
+            .chain(
+                self.custom_circuits
+                    .with(|circuits| circuits.drain().map(|(_, c)| c).collect::<Vec<_>>()),
+            );
+        for cached in shared {
+            match Rc::try_unwrap(cached) {
+                Ok(cell) => circuits.push(cell.into_inner()),
+                // Still referenced elsewhere: its link closes when the last
+                // reference is dropped
+                Err(_) => log::warn!("⚠️ Cached circuit still in use, not destroying"),
+            }
+        }
+        let count = circuits.len();
+        for mut circuit in circuits {
+            if let Err(e) = circuit.destroy().await {
+                log::warn!("⚠️ Failed to destroy circuit {}: {}", circuit.id, e);
+            }
+        }
+        count
+    }
+}
+
+impl Drop for TorClient {
+    fn drop(&mut self) {
+        // Background tasks must not outlive the client they serve
+        self.tasks.shutdown("client dropped");
+    }
 }
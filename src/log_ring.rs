@@ -0,0 +1,253 @@
+//! In-memory log ring
+//!
+//! The global logger writes every record to the browser console and also
+//! keeps the most recent ones in a bounded in-memory ring, so diagnostics
+//! bundles can include recent logs without users having to capture the
+//! devtools console. JS reads the ring with `get_recent_logs(n)`.
+
+use crate::runtime::timer::now_ms;
+use log::{Level, Log, Metadata, Record, SetLoggerError};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// Records kept by the global ring
+pub const DEFAULT_LOG_CAPACITY: usize = 500;
+
+/// Largest capacity `set_log_capacity()` accepts
+pub const MAX_LOG_CAPACITY: usize = 10_000;
+
+/// Longest message stored; longer messages are truncated
+pub const MAX_MESSAGE_LEN: usize = 1024;
+
+/// One stored log record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogRecord {
+    /// `ERROR`, `WARN`, `INFO`, `DEBUG` or `TRACE`
+    pub level: &'static str,
+    /// Module path of the call site (`tor_wasm::protocol::circuit_builder`)
+    pub module: String,
+    pub timestamp_ms: u64,
+    pub message: String,
+}
+
+impl LogRecord {
+    fn from_record(record: &Record, timestamp_ms: u64) -> Self {
+        let mut message = record.args().to_string();
+        if message.len() > MAX_MESSAGE_LEN {
+            let mut end = MAX_MESSAGE_LEN;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+            message.push('…');
+        }
+        Self {
+            level: record.level().as_str(),
+            module: record
+                .module_path()
+                .unwrap_or_else(|| record.target())
+                .to_string(),
+            timestamp_ms,
+            message,
+        }
+    }
+}
+
+/// Bounded buffer of the most recent records
+#[derive(Debug)]
+pub struct LogRing {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+}
+
+impl LogRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity.min(DEFAULT_LOG_CAPACITY)),
+            capacity,
+        }
+    }
+
+    /// Store a record, dropping the oldest one when full
+    pub fn push(&mut self, record: LogRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the capacity, dropping the oldest records if it shrinks
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity {
+            self.records.pop_front();
+        }
+    }
+
+    /// The newest `n` records, oldest first
+    pub fn recent(&self, n: usize) -> Vec<LogRecord> {
+        self.recent_at_level(n, Level::Trace)
+    }
+
+    /// The newest `n` records at `level` or more severe, oldest first
+    pub fn recent_at_level(&self, n: usize, level: Level) -> Vec<LogRecord> {
+        let mut recent: Vec<LogRecord> = self
+            .records
+            .iter()
+            .rev()
+            .filter(|r| r.level.parse::<Level>().is_ok_and(|l| l <= level))
+            .take(n)
+            .cloned()
+            .collect();
+        recent.reverse();
+        recent
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+thread_local! {
+    static RING: RefCell<LogRing> = RefCell::new(LogRing::new(DEFAULT_LOG_CAPACITY));
+}
+
+/// The newest `n` records from the global ring, oldest first
+pub fn recent_logs(n: usize) -> Vec<LogRecord> {
+    RING.with(|ring| ring.borrow().recent(n))
+}
+
+/// The newest `n` records at `level` or more severe from the global ring
+pub fn recent_logs_at_level(n: usize, level: Level) -> Vec<LogRecord> {
+    RING.with(|ring| ring.borrow().recent_at_level(n, level))
+}
+
+/// Resize the global ring (capped at [`MAX_LOG_CAPACITY`]; 0 disables it)
+pub fn set_log_capacity(capacity: usize) {
+    RING.with(|ring| {
+        ring.borrow_mut()
+            .set_capacity(capacity.min(MAX_LOG_CAPACITY))
+    });
+}
+
+/// Capacity of the global ring
+pub fn log_capacity() -> usize {
+    RING.with(|ring| ring.borrow().capacity())
+}
+
+/// Logger writing to the console and the global ring
+struct RingLogger;
+
+static LOGGER: RingLogger = RingLogger;
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let stored = LogRecord::from_record(record, now_ms());
+        RING.with(|ring| ring.borrow_mut().push(stored));
+        console_log::log(record);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the console + ring logger as the global logger
+pub fn init_with_level(level: Level) -> Result<(), SetLoggerError> {
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(level.to_level_filter());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str) -> LogRecord {
+        record_at(Level::Warn, message)
+    }
+
+    fn record_at(level: Level, message: &str) -> LogRecord {
+        LogRecord::from_record(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .module_path(Some("tor_wasm::test"))
+                .build(),
+            42,
+        )
+    }
+
+    #[test]
+    fn test_ring_keeps_newest_records() {
+        let mut ring = LogRing::new(3);
+        assert!(ring.is_empty());
+        for i in 0..5 {
+            ring.push(record(&format!("message {}", i)));
+        }
+        assert_eq!(ring.len(), 3);
+
+        let recent = ring.recent(2);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].message, "message 3");
+        assert_eq!(recent[1].message, "message 4");
+        assert_eq!(recent[1].level, "WARN");
+        assert_eq!(recent[1].module, "tor_wasm::test");
+        assert_eq!(recent[1].timestamp_ms, 42);
+        assert_eq!(ring.recent(100).len(), 3);
+
+        ring.set_capacity(1);
+        assert_eq!(ring.recent(10)[0].message, "message 4");
+        ring.set_capacity(0);
+        ring.push(record("dropped"));
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_recent_filters_by_level() {
+        let mut ring = LogRing::new(10);
+        ring.push(record_at(Level::Info, "info 1"));
+        ring.push(record_at(Level::Error, "error 1"));
+        ring.push(record_at(Level::Debug, "debug 1"));
+        ring.push(record_at(Level::Warn, "warn 1"));
+        ring.push(record_at(Level::Info, "info 2"));
+
+        let messages = |records: Vec<LogRecord>| -> Vec<String> {
+            records.into_iter().map(|r| r.message).collect()
+        };
+        assert_eq!(
+            messages(ring.recent_at_level(10, Level::Warn)),
+            ["error 1", "warn 1"]
+        );
+        assert_eq!(
+            messages(ring.recent_at_level(2, Level::Info)),
+            ["warn 1", "info 2"]
+        );
+        assert_eq!(ring.recent_at_level(10, Level::Trace).len(), 5);
+    }
+
+    #[test]
+    fn test_long_messages_are_truncated() {
+        let long = "é".repeat(MAX_MESSAGE_LEN);
+        let stored = record(&long);
+        assert!(stored.message.len() <= MAX_MESSAGE_LEN + '…'.len_utf8());
+        assert!(stored.message.ends_with('…'));
+    }
+}
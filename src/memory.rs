@@ -0,0 +1,338 @@
+//! Memory budget and allocation tracking
+//!
+//! With the `memory-tracking` feature, a wrapper around the system
+//! allocator tags every allocation with the subsystem that made it (cells,
+//! stream buffers, consensus, storage serialization) and keeps live byte
+//! counts per subsystem. Code marks what it is doing with [`scope`].
+//!
+//! A global allocator can't fail gracefully (a null return aborts), so the
+//! budget is enforced at checkpoints instead: before growing a response
+//! buffer, parsing a consensus or serializing for storage, [`check`]
+//! returns `TorError::ResourceExhausted` if live memory plus the expected
+//! growth would exceed the budget. The tab gets an error to show instead of
+//! an out-of-memory crash.
+//!
+//! Without the feature nothing is tracked, [`check`] always passes and
+//! [`report`] says so.
+
+use crate::allocator::{self, PoolStats};
+use crate::error::{Result, TorError};
+use serde::Serialize;
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What an allocation is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Subsystem {
+    /// Everything not in a scope
+    Other = 0,
+    /// Cell encoding and decoding
+    Cells = 1,
+    /// Stream receive and response buffers
+    Buffers = 2,
+    /// Consensus download and parsing
+    Consensus = 3,
+    /// Serialization for persistent storage
+    Storage = 4,
+}
+
+const SUBSYSTEMS: [Subsystem; 5] = [
+    Subsystem::Other,
+    Subsystem::Cells,
+    Subsystem::Buffers,
+    Subsystem::Consensus,
+    Subsystem::Storage,
+];
+
+impl Subsystem {
+    fn from_tag(tag: u8) -> Self {
+        SUBSYSTEMS
+            .get(tag as usize)
+            .copied()
+            .unwrap_or(Subsystem::Other)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Subsystem::Other => "other",
+            Subsystem::Cells => "cells",
+            Subsystem::Buffers => "buffers",
+            Subsystem::Consensus => "consensus",
+            Subsystem::Storage => "storage",
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<Subsystem> = const { Cell::new(Subsystem::Other) };
+}
+
+fn current_subsystem() -> Subsystem {
+    CURRENT.try_with(|c| c.get()).unwrap_or(Subsystem::Other)
+}
+
+/// Attributes allocations to a subsystem until dropped
+pub struct SubsystemScope {
+    previous: Subsystem,
+}
+
+impl Drop for SubsystemScope {
+    fn drop(&mut self) {
+        let _ = CURRENT.try_with(|c| c.set(self.previous));
+    }
+}
+
+/// Attribute allocations made until the returned guard is dropped to
+/// `subsystem`
+///
+/// Don't hold the guard across an `.await`: other tasks would run in the
+/// scope.
+pub fn scope(subsystem: Subsystem) -> SubsystemScope {
+    let previous = CURRENT
+        .try_with(|c| c.replace(subsystem))
+        .unwrap_or(Subsystem::Other);
+    SubsystemScope { previous }
+}
+
+/// Live and peak byte counts
+#[derive(Debug)]
+pub struct MemoryAccounting {
+    live: [AtomicUsize; SUBSYSTEMS.len()],
+    total: AtomicUsize,
+    peak: AtomicUsize,
+    rejections: AtomicUsize,
+}
+
+impl Default for MemoryAccounting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryAccounting {
+    pub const fn new() -> Self {
+        Self {
+            live: [
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            ],
+            total: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            rejections: AtomicUsize::new(0),
+        }
+    }
+
+    fn on_alloc(&self, subsystem: Subsystem, size: usize) {
+        self.live[subsystem as usize].fetch_add(size, Ordering::Relaxed);
+        let total = self.total.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(total, Ordering::Relaxed);
+    }
+
+    fn on_dealloc(&self, subsystem: Subsystem, size: usize) {
+        self.live[subsystem as usize].fetch_sub(size, Ordering::Relaxed);
+        self.total.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Live bytes across all subsystems
+    pub fn live_bytes(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Live bytes attributed to `subsystem`
+    pub fn live_bytes_for(&self, subsystem: Subsystem) -> usize {
+        self.live[subsystem as usize].load(Ordering::Relaxed)
+    }
+
+    /// Whether `additional` more bytes fit in `budget` (0 = unlimited);
+    /// counts a rejection if not
+    pub fn check(&self, subsystem: Subsystem, additional: usize, budget: usize) -> Result<()> {
+        let live = self.live_bytes();
+        if budget == 0 || live.saturating_add(additional) <= budget {
+            return Ok(());
+        }
+        self.rejections.fetch_add(1, Ordering::Relaxed);
+        Err(TorError::ResourceExhausted(format!(
+            "Memory budget exceeded: {} bytes for {} with {} of {} bytes in use",
+            additional,
+            subsystem.as_str(),
+            live,
+            budget
+        )))
+    }
+
+    fn report(&self, tracking: bool, budget: usize) -> MemoryReport {
+        MemoryReport {
+            allocator: allocator::ALLOCATOR_NAME,
+            pool: allocator::pool_stats(),
+            tracking,
+            budget_bytes: budget,
+            live_bytes: self.live_bytes(),
+            peak_bytes: self.peak.load(Ordering::Relaxed),
+            budget_rejections: self.rejections.load(Ordering::Relaxed),
+            by_subsystem: SUBSYSTEMS
+                .iter()
+                .map(|s| (s.as_str(), self.live_bytes_for(*s)))
+                .collect(),
+        }
+    }
+}
+
+/// Memory section of `get_metrics()`
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryReport {
+    /// `"system"` or `"pool"` (see [`crate::allocator`])
+    pub allocator: &'static str,
+    /// Pool statistics when the pool allocator is selected
+    pub pool: Option<PoolStats>,
+    /// Whether the tracking allocator is compiled in
+    pub tracking: bool,
+    /// 0 = no budget
+    pub budget_bytes: usize,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+    /// Checkpoints that refused to grow memory
+    pub budget_rejections: usize,
+    /// Live bytes by subsystem
+    pub by_subsystem: BTreeMap<&'static str, usize>,
+}
+
+/// Allocator wrapper tagging allocations with the current [`Subsystem`]
+///
+/// Each block gets a header of `align` bytes whose last byte holds the tag,
+/// so deallocations are credited to the subsystem that allocated.
+pub struct TrackingAllocator<A> {
+    inner: A,
+    accounting: &'static MemoryAccounting,
+}
+
+impl<A> TrackingAllocator<A> {
+    pub const fn new(inner: A, accounting: &'static MemoryAccounting) -> Self {
+        Self { inner, accounting }
+    }
+}
+
+/// Layout with room for the tag header, and the header length
+fn with_header(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = layout.align();
+    let size = layout.size().checked_add(offset)?;
+    Some((Layout::from_size_align(size, layout.align()).ok()?, offset))
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some((outer, offset)) = with_header(layout) else {
+            return std::ptr::null_mut();
+        };
+        let base = self.inner.alloc(outer);
+        if base.is_null() {
+            return base;
+        }
+        let subsystem = current_subsystem();
+        base.add(offset - 1).write(subsystem as u8);
+        self.accounting.on_alloc(subsystem, layout.size());
+        base.add(offset)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some((outer, offset)) = with_header(layout) else {
+            return;
+        };
+        let subsystem = Subsystem::from_tag(ptr.sub(1).read());
+        self.accounting.on_dealloc(subsystem, layout.size());
+        self.inner.dealloc(ptr.sub(offset), outer);
+    }
+}
+
+static ACCOUNTING: MemoryAccounting = MemoryAccounting::new();
+
+static BUDGET: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "memory-tracking")]
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator<crate::allocator::BaseAllocator> =
+    TrackingAllocator::new(crate::allocator::base_allocator(), &ACCOUNTING);
+
+/// Whether the tracking allocator is compiled in
+pub const TRACKING: bool = cfg!(feature = "memory-tracking");
+
+/// Set the memory budget in bytes (0 = no budget)
+pub fn set_budget(bytes: usize) {
+    BUDGET.store(bytes, Ordering::Relaxed);
+}
+
+/// Fail with `ResourceExhausted` if `additional` bytes for `subsystem`
+/// would exceed the budget
+pub fn check(subsystem: Subsystem, additional: usize) -> Result<()> {
+    if !TRACKING {
+        return Ok(());
+    }
+    ACCOUNTING.check(subsystem, additional, BUDGET.load(Ordering::Relaxed))
+}
+
+/// Current memory usage
+pub fn report() -> MemoryReport {
+    ACCOUNTING.report(TRACKING, BUDGET.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocations_are_attributed_to_scopes() {
+        static TEST_ACCOUNTING: MemoryAccounting = MemoryAccounting::new();
+        let allocator = TrackingAllocator::new(std::alloc::System, &TEST_ACCOUNTING);
+        let small = Layout::from_size_align(100, 1).unwrap();
+        let aligned = Layout::from_size_align(64, 16).unwrap();
+
+        unsafe {
+            let a = allocator.alloc(small);
+            let b = {
+                let _cells = scope(Subsystem::Cells);
+                let b = allocator.alloc(aligned);
+                {
+                    let _consensus = scope(Subsystem::Consensus);
+                    assert_eq!(current_subsystem(), Subsystem::Consensus);
+                }
+                assert_eq!(current_subsystem(), Subsystem::Cells);
+                b
+            };
+            assert_eq!(b as usize % 16, 0);
+            assert_eq!(TEST_ACCOUNTING.live_bytes_for(Subsystem::Other), 100);
+            assert_eq!(TEST_ACCOUNTING.live_bytes_for(Subsystem::Cells), 64);
+            assert_eq!(TEST_ACCOUNTING.live_bytes(), 164);
+
+            // Freed outside the scope, still credited to cells
+            allocator.dealloc(b, aligned);
+            assert_eq!(TEST_ACCOUNTING.live_bytes_for(Subsystem::Cells), 0);
+            allocator.dealloc(a, small);
+        }
+        assert_eq!(TEST_ACCOUNTING.live_bytes(), 0);
+        assert_eq!(TEST_ACCOUNTING.peak.load(Ordering::Relaxed), 164);
+    }
+
+    #[test]
+    fn test_budget_check() {
+        let accounting = MemoryAccounting::new();
+        accounting.on_alloc(Subsystem::Buffers, 900);
+
+        assert!(accounting.check(Subsystem::Buffers, 100, 1000).is_ok());
+        assert!(accounting.check(Subsystem::Buffers, 10_000, 0).is_ok());
+        let err = accounting
+            .check(Subsystem::Consensus, 101, 1000)
+            .unwrap_err();
+        assert!(matches!(err, TorError::ResourceExhausted(_)));
+
+        let report = accounting.report(true, 1000);
+        assert_eq!(report.budget_rejections, 1);
+        assert_eq!(report.by_subsystem["buffers"], 900);
+        assert_eq!(report.live_bytes, 900);
+    }
+}
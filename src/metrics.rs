@@ -0,0 +1,227 @@
+//! Request latency metrics
+//!
+//! End-to-end request latency (URL in, response out) is recorded per
+//! isolation key into fixed-bucket histograms, plus one histogram over all
+//! requests. Percentiles are estimated from the buckets, so memory stays
+//! constant however many requests are made. Exposed via
+//! `TorClient::get_metrics()` so embedders can see the overhead Tor adds and
+//! compare transports.
+//!
+//! Destinations are isolation keys, which name the sites visited; the
+//! per-destination data is dropped with the circuits on new identity.
+
+use crate::isolation::IsolationKey;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Upper bounds of the histogram buckets, in ms. A final bucket holds
+/// everything slower.
+pub const LATENCY_BUCKETS_MS: [u64; 14] = [
+    25, 50, 100, 250, 500, 750, 1_000, 1_500, 2_500, 5_000, 10_000, 20_000, 30_000, 60_000,
+];
+
+/// Maximum number of destinations tracked
+pub const MAX_TRACKED_DESTINATIONS: usize = 128;
+
+/// Fixed-bucket latency histogram
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    count: u64,
+    sum_ms: u64,
+    max_ms: u64,
+}
+
+/// Latency summary for one histogram
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_ms: u64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+    /// Count per bucket, aligned with [`LATENCY_BUCKETS_MS`] plus overflow
+    pub buckets: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one latency sample
+    pub fn record(&mut self, ms: u64) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&upper| ms <= upper)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms = self.sum_ms.saturating_add(ms);
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    /// Number of samples
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Estimated latency at percentile `p` (0–100)
+    ///
+    /// Reports the upper bound of the bucket holding that rank, capped at
+    /// the largest sample, so estimates never understate latency by more
+    /// than one bucket. `None` without samples.
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((p / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let upper = LATENCY_BUCKETS_MS.get(i).copied().unwrap_or(self.max_ms);
+                return Some(upper.min(self.max_ms));
+            }
+        }
+        Some(self.max_ms)
+    }
+
+    /// Summary for reporting
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            mean_ms: self.sum_ms.checked_div(self.count).unwrap_or(0),
+            p50_ms: self.percentile(50.0).unwrap_or(0),
+            p95_ms: self.percentile(95.0).unwrap_or(0),
+            p99_ms: self.percentile(99.0).unwrap_or(0),
+            max_ms: self.max_ms,
+            buckets: self.counts.to_vec(),
+        }
+    }
+}
+
+/// Latency report for `get_metrics()`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyReport {
+    pub overall: LatencySummary,
+    /// By isolation key
+    pub destinations: BTreeMap<String, LatencySummary>,
+}
+
+/// Latency histograms per isolation key
+#[derive(Debug, Default)]
+pub struct LatencyMetrics {
+    overall: LatencyHistogram,
+    /// Histogram and the sequence number of its last sample
+    destinations: HashMap<String, (LatencyHistogram, u64)>,
+    seq: u64,
+}
+
+impl LatencyMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a completed request to `key`
+    ///
+    /// When [`MAX_TRACKED_DESTINATIONS`] are already tracked, the one that
+    /// has gone longest without a request is dropped to make room.
+    pub fn record(&mut self, key: &IsolationKey, ms: u64) {
+        self.seq += 1;
+        self.overall.record(ms);
+
+        if !self.destinations.contains_key(key.as_str())
+            && self.destinations.len() >= MAX_TRACKED_DESTINATIONS
+        {
+            if let Some(stale) = self
+                .destinations
+                .iter()
+                .min_by_key(|(_, (_, seq))| *seq)
+                .map(|(k, _)| k.clone())
+            {
+                self.destinations.remove(&stale);
+            }
+        }
+        let (histogram, seq) = self
+            .destinations
+            .entry(key.as_str().to_string())
+            .or_default();
+        histogram.record(ms);
+        *seq = self.seq;
+    }
+
+    /// Drop per-destination histograms (new identity); the overall
+    /// histogram names no site and is kept
+    pub fn clear_destinations(&mut self) {
+        self.destinations.clear();
+    }
+
+    /// Current report
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            overall: self.overall.summary(),
+            destinations: self
+                .destinations
+                .iter()
+                .map(|(k, (h, _))| (k.clone(), h.summary()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isolation::IsolationType;
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut h = LatencyHistogram::new();
+        assert_eq!(h.percentile(50.0), None);
+
+        // 90 fast requests, 9 slow, 1 very slow
+        for _ in 0..90 {
+            h.record(80);
+        }
+        for _ in 0..9 {
+            h.record(2_000);
+        }
+        h.record(90_000);
+
+        let s = h.summary();
+        assert_eq!(s.count, 100);
+        assert_eq!(s.p50_ms, 100);
+        assert_eq!(s.p95_ms, 2_500);
+        assert_eq!(s.p99_ms, 2_500);
+        assert_eq!(h.percentile(100.0), Some(90_000));
+        assert_eq!(s.max_ms, 90_000);
+        assert_eq!(s.buckets.iter().sum::<u64>(), 100);
+        assert_eq!(s.mean_ms, (90 * 80 + 9 * 2_000 + 90_000) / 100);
+    }
+
+    #[test]
+    fn test_metrics_per_destination() {
+        let mut metrics = LatencyMetrics::new();
+        let key = |host: &str| IsolationKey::for_destination(host, 443, IsolationType::PerDomain);
+
+        metrics.record(&key("a.example"), 300);
+        metrics.record(&key("a.example"), 400);
+        metrics.record(&key("b.example"), 1_200);
+        for i in 0..MAX_TRACKED_DESTINATIONS {
+            metrics.record(&key(&format!("site{}.example", i)), 100);
+        }
+
+        let report = metrics.report();
+        assert_eq!(report.overall.count, 3 + MAX_TRACKED_DESTINATIONS as u64);
+        assert_eq!(report.destinations.len(), MAX_TRACKED_DESTINATIONS);
+        // The least recently used destinations were evicted first
+        assert!(!report.destinations.contains_key(key("a.example").as_str()));
+
+        metrics.clear_destinations();
+        let report = metrics.report();
+        assert!(report.destinations.is_empty());
+        assert_eq!(report.overall.count, 3 + MAX_TRACKED_DESTINATIONS as u64);
+    }
+}
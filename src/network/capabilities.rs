@@ -0,0 +1,249 @@
+//! Bridge capability discovery and heartbeat
+//!
+//! Bridges differ in what they speak: older ones only take `?addr=` per
+//! relay socket, newer ones multiplex channels over one framed socket,
+//! unblind `?dest=` targets for a second bridge (and seal the traffic
+//! to it), or serve the consensus
+//! under another path. Rather than assume, the client asks with
+//! `GET /capabilities` on the bridge's HTTP origin and connects
+//! accordingly. A bridge without the endpoint gets
+//! [`BridgeCapabilities::default`], which is what every bridge before it
+//! supported.
+//!
+//! The same request doubles as a heartbeat: calling
+//! [`WasmTcpProvider::heartbeat`](super::WasmTcpProvider::heartbeat) every
+//! [`BridgeCapabilities::heartbeat_interval_secs`] tells whether the bridge
+//! is still answering and picks up capability changes after a redeploy.
+
+use crate::error::{Result, TorError};
+use crate::transport::MAX_MESSAGE_SIZE;
+use serde::{Deserialize, Serialize};
+
+/// Path of the capability document on the bridge's HTTP origin
+pub const CAPABILITIES_PATH: &str = "/capabilities";
+
+/// Smallest message size a bridge may advertise: one link cell
+const MIN_MESSAGE_SIZE: usize = 514;
+
+/// What a bridge supports, as reported by `GET /capabilities`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BridgeCapabilities {
+    /// Bridge protocol version (0: the bridge has no capability endpoint)
+    pub version: u32,
+    /// Accepts `?dest=` targets blinded for a second bridge
+    pub blinded: bool,
+    /// Accepts `?sealed=` targets: blinded targets whose traffic is sealed
+    /// end to end with the second bridge
+    pub sealed: bool,
+    /// Path of the consensus document, if the bridge serves one
+    pub consensus_endpoint: Option<String>,
+    /// Answers `X-Or-Diff-From-Consensus` with a diff against the named
+    /// consensus instead of the whole document
+    pub consensus_diffs: bool,
+    /// Speaks the framed protocol, carrying many relay connections as
+    /// channels of one socket
+    pub multiplexing: bool,
+    /// Largest WebSocket message the bridge accepts from us
+    pub max_message_size: usize,
+    /// Can serve zstd-compressed directory documents. Reported only: the
+    /// client doesn't decode zstd, so it never asks for them
+    pub zstd: bool,
+    /// How often the bridge wants to be polled for liveness
+    pub heartbeat_interval_secs: u64,
+}
+
+impl Default for BridgeCapabilities {
+    /// What bridges without a capability endpoint support
+    fn default() -> Self {
+        Self {
+            version: 0,
+            blinded: false,
+            sealed: false,
+            consensus_endpoint: Some("/tor/consensus".to_string()),
+            consensus_diffs: false,
+            // Tried and dropped by the mux if the handshake fails
+            multiplexing: true,
+            max_message_size: MAX_MESSAGE_SIZE,
+            zstd: false,
+            heartbeat_interval_secs: 60,
+        }
+    }
+}
+
+impl BridgeCapabilities {
+    /// Parse and check a capability document
+    pub fn parse(json: &str) -> Result<Self> {
+        let caps: Self = serde_json::from_str(json)
+            .map_err(|e| TorError::ParseError(format!("Invalid bridge capabilities: {}", e)))?;
+        if caps.max_message_size < MIN_MESSAGE_SIZE {
+            return Err(TorError::ParseError(format!(
+                "Bridge max_message_size {} is below one cell ({} bytes)",
+                caps.max_message_size, MIN_MESSAGE_SIZE
+            )));
+        }
+        if let Some(path) = &caps.consensus_endpoint {
+            if !path.starts_with('/') {
+                return Err(TorError::ParseError(format!(
+                    "Bridge consensus_endpoint must be a path, got {:?}",
+                    path
+                )));
+            }
+        }
+        Ok(caps)
+    }
+
+    /// Size to split outgoing messages at: the bridge's limit, capped at
+    /// the size we accept ourselves
+    pub fn send_limit(&self) -> usize {
+        self.max_message_size.min(MAX_MESSAGE_SIZE)
+    }
+}
+
+/// Liveness of the bridge as seen by capability requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BridgeHeartbeat {
+    /// Whether the bridge has answered `GET /capabilities` at least once
+    pub discovered: bool,
+    /// When the bridge last answered (ms since epoch)
+    pub last_seen_ms: Option<u64>,
+    /// How long the last answer took
+    pub last_rtt_ms: Option<u64>,
+    /// Requests failed since the last answer
+    pub consecutive_failures: u32,
+    /// Why the last request failed
+    pub last_error: Option<String>,
+}
+
+impl BridgeHeartbeat {
+    pub(crate) fn record_success(&mut self, now_ms: u64, rtt_ms: u64) {
+        self.discovered = true;
+        self.last_seen_ms = Some(now_ms);
+        self.last_rtt_ms = Some(rtt_ms);
+        self.consecutive_failures = 0;
+        self.last_error = None;
+    }
+
+    pub(crate) fn record_failure(&mut self, error: &TorError) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error.to_string());
+    }
+}
+
+/// HTTP origin of a bridge given by its WebSocket (or meek) URL
+pub fn http_base(bridge_url: &str) -> String {
+    if let Some(rest) = bridge_url.strip_prefix("wss://") {
+        format!("https://{}", rest)
+    } else if let Some(rest) = bridge_url.strip_prefix("ws://") {
+        format!("http://{}", rest)
+    } else {
+        bridge_url.to_string()
+    }
+    .trim_end_matches('/')
+    .to_string()
+}
+
+/// Ask the bridge what it supports
+pub async fn fetch(bridge_url: &str) -> Result<BridgeCapabilities> {
+    let url = format!("{}{}", http_base(bridge_url), CAPABILITIES_PATH);
+    BridgeCapabilities::parse(&get_text(&url, &[]).await?)
+}
+
+/// `GET` a URL with the Fetch API, returning the body of a 2xx response
+pub(crate) async fn get_text(url: &str, headers: &[(&str, &str)]) -> Result<String> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Headers, Request, RequestInit, RequestMode, Response};
+
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+    if !headers.is_empty() {
+        let map = Headers::new()
+            .map_err(|e| TorError::Network(format!("Failed to create headers: {:?}", e)))?;
+        for (name, value) in headers {
+            map.set(name, value)
+                .map_err(|e| TorError::Network(format!("Invalid header {}: {:?}", name, e)))?;
+        }
+        opts.set_headers(&map);
+    }
+    let request = Request::new_with_str_and_init(url, &opts)
+        .map_err(|e| TorError::Network(format!("Failed to create request: {:?}", e)))?;
+
+    let window = web_sys::window().ok_or_else(|| TorError::Network("No window object".into()))?;
+    let resp: Response = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(|e| TorError::Network(format!("Fetch failed: {:?}", e)))?
+        .dyn_into()
+        .map_err(|_| TorError::Network("Failed to cast to Response".into()))?;
+
+    if !resp.ok() {
+        return Err(TorError::Network(format!(
+            "HTTP {}: {}",
+            resp.status(),
+            resp.status_text()
+        )));
+    }
+
+    JsFuture::from(
+        resp.text()
+            .map_err(|e| TorError::Network(format!("Failed to get text: {:?}", e)))?,
+    )
+    .await
+    .map_err(|e| TorError::Network(format!("Failed to read text: {:?}", e)))?
+    .as_string()
+    .ok_or_else(|| TorError::Network("Response is not a string".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fills_missing_fields_with_legacy_defaults() {
+        let caps = BridgeCapabilities::parse(r#"{"version": 2, "blinded": true}"#).unwrap();
+        assert_eq!(caps.version, 2);
+        assert!(caps.blinded);
+        assert!(!caps.sealed);
+        assert!(caps.multiplexing);
+        assert_eq!(caps.consensus_endpoint.as_deref(), Some("/tor/consensus"));
+        assert!(!caps.consensus_diffs);
+
+        let caps = BridgeCapabilities::parse(
+            r#"{"multiplexing": false, "consensus_endpoint": null, "max_message_size": 1048576}"#,
+        )
+        .unwrap();
+        assert!(!caps.multiplexing);
+        assert_eq!(caps.consensus_endpoint, None);
+        assert_eq!(caps.send_limit(), MAX_MESSAGE_SIZE);
+    }
+
+    #[test]
+    fn test_parse_rejects_unusable_values() {
+        assert!(BridgeCapabilities::parse(r#"{"max_message_size": 100}"#).is_err());
+        assert!(BridgeCapabilities::parse(r#"{"consensus_endpoint": "tor/consensus"}"#).is_err());
+        assert!(BridgeCapabilities::parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_http_base() {
+        assert_eq!(http_base("wss://bridge.example/"), "https://bridge.example");
+        assert_eq!(http_base("ws://localhost:8080"), "http://localhost:8080");
+        assert_eq!(http_base("https://meek.example"), "https://meek.example");
+    }
+
+    #[test]
+    fn test_heartbeat_resets_failures_on_success() {
+        let mut heartbeat = BridgeHeartbeat::default();
+        heartbeat.record_failure(&TorError::Network("down".into()));
+        heartbeat.record_failure(&TorError::Network("down".into()));
+        assert_eq!(heartbeat.consecutive_failures, 2);
+        assert!(!heartbeat.discovered);
+
+        heartbeat.record_success(5_000, 40);
+        assert!(heartbeat.discovered);
+        assert_eq!(heartbeat.consecutive_failures, 0);
+        assert_eq!(heartbeat.last_rtt_ms, Some(40));
+        assert_eq!(heartbeat.last_error, None);
+    }
+}
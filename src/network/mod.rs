@@ -4,20 +4,25 @@
 //! using WebSocket connections through our bridge server to connect to
 //! real Tor relays.
 
+mod capabilities;
 mod connection_manager;
 mod error_handling;
 mod provider;
 mod tls;
 
+pub use capabilities::{BridgeCapabilities, BridgeHeartbeat};
 pub use connection_manager::ConnectionManager;
 pub use error_handling::{NetworkError, RecoveryStrategy};
 pub use provider::WasmTcpProvider;
 pub use tls::{CertificateInfo, WasmTlsConnector, WasmTlsStream};
 
+use crate::transport::ChannelKeys;
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
 /// Configuration for network operations
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct NetworkConfig {
     /// WebSocket bridge URL
     pub bridge_url: String,
@@ -36,6 +41,15 @@ pub struct NetworkConfig {
 
     /// Maximum retries
     pub max_retries: u32,
+
+    /// Carry WebSocket relay connections as channels of one shared, framed
+    /// bridge socket (see [`crate::transport::BridgeMux`])
+    pub framing: bool,
+
+    /// Hex X25519 key of a second bridge that relay targets are blinded
+    /// for, when the bridge advertises blinding (see
+    /// [`crate::transport::blind_target_address`])
+    pub blinding_key: Option<String>,
 }
 
 impl Default for NetworkConfig {
@@ -47,6 +61,8 @@ impl Default for NetworkConfig {
             enable_pooling: true,
             retry_on_failure: true,
             max_retries: 3,
+            framing: true,
+            blinding_key: None,
         }
     }
 }
@@ -60,12 +76,74 @@ impl NetworkConfig {
         }
     }
 
-    /// Build WebSocket URL for connecting to a relay
-    pub fn build_url(&self, addr: &SocketAddr) -> String {
-        format!("{}?addr={}:{}", self.bridge_url, addr.ip(), addr.port())
+    /// Build WebSocket URL for connecting to a relay, with the keys to seal
+    /// the connection with if the target is sealed
+    pub fn build_url(
+        &self,
+        addr: &SocketAddr,
+        caps: &BridgeCapabilities,
+    ) -> (String, Option<ChannelKeys>) {
+        let (target, keys) = self.target(addr, caps);
+        (format!("{}?{}", self.bridge_url, target), keys)
+    }
+
+    /// Framing OPEN target for connecting to a relay, with the keys to seal
+    /// the channel with if the target is sealed
+    pub fn framing_target(
+        &self,
+        addr: &SocketAddr,
+        caps: &BridgeCapabilities,
+    ) -> (String, Option<ChannelKeys>) {
+        self.target(addr, caps)
+    }
+
+    /// `sealed=<blob>` if we have a blinding key and the bridge can seal
+    /// blinded targets, `dest=<blob>` if it can only forward them,
+    /// `addr=HOST:PORT` otherwise
+    fn target(
+        &self,
+        addr: &SocketAddr,
+        caps: &BridgeCapabilities,
+    ) -> (String, Option<ChannelKeys>) {
+        use crate::security_posture::{report_downgrade, Downgrade};
+
+        let direct = format!("addr={}:{}", addr.ip(), addr.port());
+        let Some(key) = &self.blinding_key else {
+            return (direct, None);
+        };
+        let blinded = if caps.blinded {
+            decode_key(key).and_then(|key| crate::transport::blind_target(&addr.to_string(), &key))
+        } else {
+            Err("the bridge does not support blinded targets".to_string())
+        };
+        match blinded {
+            Ok(target) if caps.sealed => (format!("sealed={}", target.blob), Some(target.keys)),
+            Ok(target) => {
+                report_downgrade(
+                    Downgrade::UnsealedBlinding,
+                    "the bridge forwards blinded targets without sealing",
+                );
+                (format!("dest={}", target.blob), None)
+            }
+            Err(e) => {
+                report_downgrade(
+                    Downgrade::BlindingFallback,
+                    &format!("bridge blinding unavailable, connected directly: {}", e),
+                );
+                (direct, None)
+            }
+        }
     }
 }
 
+/// Parse a hex X25519 public key
+pub(crate) fn decode_key(key: &str) -> std::result::Result<[u8; 32], String> {
+    hex::decode(key.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "blinding key is not 32 hex-encoded bytes".to_string())
+}
+
 /// Network statistics
 #[derive(Debug, Default, Clone)]
 pub struct NetworkStats {
@@ -86,6 +164,27 @@ pub struct NetworkStats {
 
     /// Total bytes received
     pub bytes_received: u64,
+
+    /// Transport frames sent (WebSocket / DataChannel messages)
+    pub frames_sent: u64,
+
+    /// Transport frames received
+    pub frames_received: u64,
+
+    /// Failed attempts that preceded successful connections
+    pub reconnects: u64,
+
+    /// Bytes currently queued in browser send buffers (live connections)
+    pub buffered_amount: u64,
+
+    /// Highest per-connection `bufferedAmount` observed
+    pub peak_buffered_amount: u64,
+
+    /// ICE connection state changes across WebRTC connections
+    pub ice_state_transitions: u64,
+
+    /// Connections whose transport stream is still alive
+    pub live_transport_connections: usize,
 }
 
 impl NetworkStats {
@@ -121,7 +220,40 @@ mod tests {
     fn test_build_url() {
         let config = NetworkConfig::default();
         let addr: SocketAddr = "1.2.3.4:9001".parse().unwrap();
-        let url = config.build_url(&addr);
+        let caps = BridgeCapabilities::default();
+        let (url, keys) = config.build_url(&addr, &caps);
+        assert_eq!(url, "ws://localhost:8080?addr=1.2.3.4:9001");
+        assert!(keys.is_none());
+        assert_eq!(config.framing_target(&addr, &caps).0, "addr=1.2.3.4:9001");
+    }
+
+    #[test]
+    fn test_build_url_blinds_only_when_bridge_supports_it() {
+        let config = NetworkConfig {
+            blinding_key: Some(hex::encode([9u8; 32])),
+            ..Default::default()
+        };
+        let addr: SocketAddr = "1.2.3.4:9001".parse().unwrap();
+
+        let blinded = BridgeCapabilities {
+            blinded: true,
+            ..Default::default()
+        };
+        let (url, keys) = config.build_url(&addr, &blinded);
+        assert!(url.starts_with("ws://localhost:8080?dest="));
+        assert!(!url.contains("1.2.3.4"));
+        assert!(keys.is_none());
+
+        let sealed = BridgeCapabilities {
+            blinded: true,
+            sealed: true,
+            ..Default::default()
+        };
+        let (target, keys) = config.framing_target(&addr, &sealed);
+        assert!(target.starts_with("sealed="));
+        assert!(keys.is_some());
+
+        let (url, _) = config.build_url(&addr, &BridgeCapabilities::default());
         assert_eq!(url, "ws://localhost:8080?addr=1.2.3.4:9001");
     }
 
@@ -3,9 +3,13 @@
 //! Implements Arti's networking traits using WebSocket connections
 //! through our bridge server.
 
-use super::{NetworkConfig, NetworkStats};
-use crate::transport::{TransportStream, WasmMeekStream, WasmTcpStream};
-use std::cell::UnsafeCell;
+use super::capabilities;
+use super::{BridgeCapabilities, BridgeHeartbeat, NetworkConfig, NetworkStats};
+use crate::transport::{
+    BridgeMux, ChannelKeys, ConnectionStatsRegistry, MuxStatus, SealedStream, TransportStream,
+    WasmMeekStream, WasmTcpStream,
+};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::io::Result as IoResult;
 use std::net::SocketAddr;
 use std::rc::Rc;
@@ -20,6 +24,32 @@ pub struct WasmTcpProvider {
 
     /// Network statistics (UnsafeCell is safe in single-threaded WASM)
     stats: Rc<UnsafeCell<NetworkStats>>,
+
+    /// Per-connection transport counters for every stream we handed out
+    connections: Rc<UnsafeCell<ConnectionStatsRegistry>>,
+
+    /// Whether WebSocket connections share one framed bridge socket
+    framing: Rc<Cell<bool>>,
+
+    /// Timeout and retry settings (changeable while running)
+    retry: Rc<Cell<RetryPolicy>>,
+
+    /// The shared bridge socket
+    mux: Rc<BridgeMux>,
+
+    /// What the bridge said it supports (legacy defaults until it answers)
+    capabilities: Rc<RefCell<BridgeCapabilities>>,
+
+    /// Outcome of capability requests
+    heartbeat: Rc<RefCell<BridgeHeartbeat>>,
+}
+
+/// The part of [`NetworkConfig`] that applies per connection attempt
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    connect_timeout: u64,
+    retry_on_failure: bool,
+    max_retries: u32,
 }
 
 impl WasmTcpProvider {
@@ -35,21 +65,121 @@ impl WasmTcpProvider {
             config.bridge_url
         );
         Self {
+            framing: Rc::new(Cell::new(config.framing)),
+            retry: Rc::new(Cell::new(RetryPolicy {
+                connect_timeout: config.connect_timeout,
+                retry_on_failure: config.retry_on_failure,
+                max_retries: config.max_retries,
+            })),
+            mux: Rc::new(BridgeMux::new(config.bridge_url.clone())),
+            capabilities: Rc::new(RefCell::new(BridgeCapabilities::default())),
+            heartbeat: Rc::new(RefCell::new(BridgeHeartbeat::default())),
             config,
             stats: Rc::new(UnsafeCell::new(NetworkStats::default())),
+            connections: Rc::new(UnsafeCell::new(ConnectionStatsRegistry::new())),
+        }
+    }
+
+    /// Current configuration, with framing, timeout and retries as last set
+    pub fn config(&self) -> NetworkConfig {
+        let retry = self.retry.get();
+        NetworkConfig {
+            framing: self.framing.get(),
+            connect_timeout: retry.connect_timeout,
+            retry_on_failure: retry.retry_on_failure,
+            max_retries: retry.max_retries,
+            ..self.config.clone()
         }
     }
 
+    /// Change the connect timeout and retries for connections made from now on
+    pub fn set_retry_policy(&self, connect_timeout: u64, retry_on_failure: bool, max_retries: u32) {
+        self.retry.set(RetryPolicy {
+            connect_timeout,
+            retry_on_failure,
+            max_retries,
+        });
+    }
+
     /// Returns true if the bridge URL uses meek transport (HTTP/HTTPS)
     fn is_meek(&self) -> bool {
         self.config.bridge_url.starts_with("https://")
             || self.config.bridge_url.starts_with("http://")
     }
 
+    /// Share one framed bridge socket between WebSocket connections made
+    /// from now on (on by default)
+    ///
+    /// Disabling drops the shared session once its open channels close.
+    pub fn set_framing(&self, enabled: bool) {
+        self.framing.set(enabled);
+        if !enabled {
+            self.mux.reset();
+        }
+    }
+
+    /// Whether new WebSocket connections will share the framed bridge
+    /// socket: enabled, not meek, advertised by the bridge, and the bridge
+    /// hasn't refused framing
+    pub fn framing(&self) -> bool {
+        self.framing.get()
+            && !self.is_meek()
+            && self.capabilities.borrow().multiplexing
+            && self.mux.multiplexing()
+    }
+
+    /// What the bridge supports, as of the last answered heartbeat
+    pub fn capabilities(&self) -> BridgeCapabilities {
+        self.capabilities.borrow().clone()
+    }
+
+    /// Whether the bridge has been answering heartbeats
+    pub fn heartbeat_status(&self) -> BridgeHeartbeat {
+        self.heartbeat.borrow().clone()
+    }
+
+    /// Ask the bridge for its capabilities, adopting them for connections
+    /// made from now on
+    ///
+    /// Serves as both discovery and heartbeat. On failure the last known
+    /// capabilities stay in place (the legacy defaults if the bridge has
+    /// never answered).
+    pub async fn heartbeat(&self) -> crate::error::Result<BridgeCapabilities> {
+        let start = crate::runtime::timer::now_ms();
+        match capabilities::fetch(&self.config.bridge_url).await {
+            Ok(caps) => {
+                let now = crate::runtime::timer::now_ms();
+                self.heartbeat
+                    .borrow_mut()
+                    .record_success(now, now.saturating_sub(start));
+                if !caps.multiplexing {
+                    self.mux.reset();
+                }
+                *self.capabilities.borrow_mut() = caps.clone();
+                Ok(caps)
+            }
+            Err(e) => {
+                self.heartbeat.borrow_mut().record_failure(&e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Close the shared bridge socket (it reopens on the next connection)
+    pub fn close_bridge_session(&self) {
+        self.mux.close();
+    }
+
+    /// Shared bridge socket counters
+    pub fn mux_status(&self) -> MuxStatus {
+        self.mux.status()
+    }
+
     /// Connect to a relay with retry logic
     pub async fn connect_with_retry(&self, addr: &SocketAddr) -> IoResult<TransportStream> {
-        let max_retries = if self.config.retry_on_failure {
-            self.config.max_retries
+        let retry = self.retry.get();
+        let max_retries = if retry.retry_on_failure {
+            retry.max_retries
         } else {
             0
         };
@@ -66,6 +196,7 @@ impl WasmTcpProvider {
             match self.connect_once(addr).await {
                 Ok(stream) => {
                     self.record_success();
+                    self.track_connection(&stream, attempt);
                     return Ok(stream);
                 }
                 Err(e) => {
@@ -86,11 +217,13 @@ impl WasmTcpProvider {
 
     /// Single connection attempt with timeout
     async fn connect_once(&self, addr: &SocketAddr) -> IoResult<TransportStream> {
+        let connect_timeout = self.retry.get().connect_timeout;
+        let caps = self.capabilities();
         log::info!(
             "Connecting to relay at {} via {} (timeout: {}s)",
             addr,
             if self.is_meek() { "meek" } else { "WebSocket" },
-            self.config.connect_timeout
+            connect_timeout
         );
 
         self.record_attempt();
@@ -114,25 +247,40 @@ impl WasmTcpProvider {
                 }
             }
         } else {
-            // WebSocket transport (default)
-            let url = self.config.build_url(addr);
+            // Shared bridge socket, unless the bridge doesn't speak framing
+            if self.framing() {
+                let (target, keys) = self.config.framing_target(addr, &caps);
+                if let Some(channel) = self.mux.channel(&target).await? {
+                    log::info!(
+                        "Opened channel {} to {} on the shared bridge socket",
+                        channel.id(),
+                        addr
+                    );
+                    self.increment_active();
+                    return Ok(sealed(TransportStream::Framed(channel), keys));
+                }
+            }
+
+            // One WebSocket per relay
+            let (url, keys) = self.config.build_url(addr, &caps);
             let connect_future = WasmTcpStream::connect(&url);
 
             match connect_future.await {
                 Ok(stream) => {
+                    stream.set_max_message_size(caps.send_limit());
                     let elapsed = ((js_sys::Date::now() - start) / 1000.0) as u64;
-                    if elapsed > self.config.connect_timeout {
+                    if elapsed > connect_timeout {
                         log::warn!(
                             "Connection to {} succeeded but took {}s (timeout was {}s)",
                             addr,
                             elapsed,
-                            self.config.connect_timeout
+                            connect_timeout
                         );
                     } else {
                         log::info!("Successfully connected to {} in {}s", addr, elapsed);
                     }
                     self.increment_active();
-                    Ok(TransportStream::WebSocket(stream))
+                    Ok(sealed(TransportStream::WebSocket(stream), keys))
                 }
                 Err(e) => {
                     let elapsed = ((js_sys::Date::now() - start) / 1000.0) as u64;
@@ -143,9 +291,32 @@ impl WasmTcpProvider {
         }
     }
 
-    /// Get current network statistics
+    /// Get current network statistics, including transport-level counters
     pub fn get_stats(&self) -> NetworkStats {
-        unsafe { (*self.stats.get()).clone() }
+        let mut stats = unsafe { (*self.stats.get()).clone() };
+        let registry = unsafe { &mut *self.connections.get() };
+        let transport = registry.totals();
+
+        stats.bytes_sent += transport.bytes_sent;
+        stats.bytes_received += transport.bytes_received;
+        stats.frames_sent = transport.frames_sent;
+        stats.frames_received = transport.frames_received;
+        stats.reconnects = transport.reconnects as u64;
+        stats.buffered_amount = transport.buffered_amount;
+        stats.peak_buffered_amount = transport.peak_buffered_amount;
+        stats.ice_state_transitions = transport.ice_state_transitions as u64;
+        stats.live_transport_connections = registry.live_count();
+        stats
+    }
+
+    /// Start aggregating a freshly connected stream's counters
+    fn track_connection(&self, stream: &TransportStream, failed_attempts: u32) {
+        if let Some(handle) = stream.stats_handle() {
+            handle.with(|stats| stats.reconnects += failed_attempts);
+            unsafe {
+                (*self.connections.get()).track(handle);
+            }
+        }
     }
 
     /// Get the bridge URL
@@ -153,10 +324,42 @@ impl WasmTcpProvider {
         &self.config.bridge_url
     }
 
+    /// URL of `path` on the bridge's HTTP origin
+    pub fn bridge_http_url(&self, path: &str) -> String {
+        format!(
+            "{}{}",
+            capabilities::http_base(&self.config.bridge_url),
+            path
+        )
+    }
+
+    /// `GET` `path` from the bridge's HTTP origin with extra request
+    /// headers, returning the body
+    pub async fn bridge_get(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> crate::error::Result<String> {
+        capabilities::get_text(&self.bridge_http_url(path), headers).await
+    }
+
+    /// Transport used for relay connections (`"meek"`, `"framed"` or
+    /// `"websocket"`)
+    pub fn transport_name(&self) -> &'static str {
+        if self.is_meek() {
+            "meek"
+        } else if self.framing() {
+            "framed"
+        } else {
+            "websocket"
+        }
+    }
+
     /// Reset statistics
     pub fn reset_stats(&self) {
         unsafe {
             *self.stats.get() = NetworkStats::default();
+            *self.connections.get() = ConnectionStatsRegistry::new();
         }
     }
 
@@ -208,6 +411,14 @@ impl WasmTcpProvider {
     }
 }
 
+/// Seal `stream` with Bridge B if the target was sealed
+fn sealed(stream: TransportStream, keys: Option<ChannelKeys>) -> TransportStream {
+    match keys {
+        Some(keys) => TransportStream::Sealed(Box::new(SealedStream::client(stream, &keys))),
+        None => stream,
+    }
+}
+
 impl Default for WasmTcpProvider {
     fn default() -> Self {
         Self::new()
@@ -220,6 +431,12 @@ impl Clone for WasmTcpProvider {
         Self {
             config: self.config.clone(),
             stats: Rc::clone(&self.stats),
+            connections: Rc::clone(&self.connections),
+            framing: Rc::clone(&self.framing),
+            retry: Rc::clone(&self.retry),
+            mux: Rc::clone(&self.mux),
+            capabilities: Rc::clone(&self.capabilities),
+            heartbeat: Rc::clone(&self.heartbeat),
         }
     }
 }
@@ -259,5 +476,18 @@ mod tests {
         let provider = WasmTcpProvider::with_config(config);
         assert_eq!(provider.config.bridge_url, "ws://custom:9999");
         assert_eq!(provider.config.connect_timeout, 60);
+        assert!(provider.framing());
+        assert_eq!(provider.transport_name(), "framed");
+
+        provider.set_framing(false);
+        assert!(!provider.framing());
+        assert_eq!(provider.transport_name(), "websocket");
+
+        provider.set_retry_policy(5, false, 1);
+        let live = provider.clone().config();
+        assert_eq!(live.connect_timeout, 5);
+        assert!(!live.retry_on_failure);
+        assert!(!live.framing);
+        assert_eq!(live.bridge_url, "ws://custom:9999");
     }
 }
@@ -267,6 +267,26 @@ impl WasmTlsStream {
         })
     }
 
+    /// DER encoding of the relay's TLS certificate, if it sent one
+    pub fn peer_certificate(&self) -> Option<Vec<u8>> {
+        self.tls
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| cert.as_ref().to_vec())
+    }
+
+    /// Export `len` bytes of RFC 5705 keying material from the session
+    pub fn export_keying_material(
+        &self,
+        len: usize,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> IoResult<Vec<u8>> {
+        self.tls
+            .export_keying_material(vec![0u8; len], label, context)
+            .map_err(|e| io::Error::other(format!("TLS export: {}", e)))
+    }
+
     /// Try to process any buffered incoming TLS data and extract plaintext
     fn process_incoming(&mut self) -> IoResult<()> {
         if self.incoming_tls.is_empty() {
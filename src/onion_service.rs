@@ -0,0 +1,355 @@
+//! Onion service client: reaching v3 `.onion` addresses
+//!
+//! Connecting takes four steps (rend-spec-v3 §3):
+//! 1. Fetch the service's descriptor for the current time period from one
+//!    of the HSDirs responsible for its blinded key, over BEGIN_DIR.
+//! 2. Make the last hop of a fresh circuit a rendezvous point with
+//!    ESTABLISH_RENDEZVOUS.
+//! 3. Send INTRODUCE1 through one of the service's introduction points,
+//!    naming the rendezvous point and starting an hs-ntor handshake.
+//! 4. Wait for the service's RENDEZVOUS2 on the rendezvous circuit, finish
+//!    the handshake and join the service as a virtual hop.
+//!
+//! The resulting circuit carries streams to the service with
+//! [`StreamManager::open_service_stream`](crate::protocol::StreamManager::open_service_stream).
+//! Decrypted descriptors are cached until they expire or `clear()` is
+//! called (on `new_identity()`).
+
+use crate::circuit_pool::{CircuitTarget, PrebuiltCircuitPool};
+use crate::clock_skew;
+use crate::error::{Result, TorError};
+use crate::http_client::HttpClientConfig;
+use crate::protocol::{
+    introduce1_plaintext, responsible_hsdirs, Circuit, CircuitBuilder, Consensus, HsDescriptor,
+    HsNtorClient, IntroPoint, OnionAddress, Relay, RelayCell, RelayCommand, RelaySelector,
+    StreamManager, TimePeriod, DEFAULT_TIME_PERIOD_MINS,
+};
+use crate::response::HttpResponse;
+use crate::runtime::timer::now_ms;
+use futures::FutureExt;
+use rand::seq::SliceRandom;
+use rand::RngCore;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+
+/// How long to wait for each reply from the rendezvous or introduction
+/// point (RENDEZVOUS_ESTABLISHED, INTRODUCE_ACK, RENDEZVOUS2)
+const REPLY_TIMEOUT_MS: u32 = 30_000;
+
+/// Introduction points tried before giving up
+const MAX_INTRO_ATTEMPTS: usize = 3;
+
+/// HSDirs asked for a descriptor before giving up
+const MAX_HSDIR_ATTEMPTS: usize = 3;
+
+/// A decrypted descriptor and when it stops being usable
+struct CachedDescriptor {
+    descriptor: HsDescriptor,
+    expires_at: u64,
+}
+
+/// Descriptor cache and connection logic for onion services
+#[derive(Default)]
+pub struct OnionServiceClient {
+    /// Keyed by blinded key, so a new time period fetches afresh
+    descriptors: HashMap<[u8; 32], CachedDescriptor>,
+}
+
+impl OnionServiceClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget every cached descriptor
+    pub fn clear(&mut self) {
+        self.descriptors.clear();
+    }
+
+    /// Number of descriptors cached
+    pub fn cached_descriptors(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    /// Build a circuit joined to the onion service at `host`
+    pub async fn connect(
+        &mut self,
+        host: &str,
+        consensus: &Consensus,
+        builder: &CircuitBuilder,
+        selector: &RelaySelector,
+        pool: &mut PrebuiltCircuitPool,
+    ) -> Result<Circuit> {
+        let address = OnionAddress::parse(host)?;
+        let now = clock_skew::now_secs();
+        let period = TimePeriod::at(now, DEFAULT_TIME_PERIOD_MINS);
+        let blinded_key = address.blinded_key(period)?;
+        let subcredential = address.subcredential(&blinded_key);
+        log::info!("🧅 Connecting to onion service {}", address);
+
+        let descriptor = self
+            .descriptor(
+                consensus,
+                builder,
+                selector,
+                pool,
+                &blinded_key,
+                &subcredential,
+                period,
+                now,
+            )
+            .await?;
+
+        let mut rendezvous = builder.build_circuit(selector).await?;
+        let cookie = match establish_rendezvous(&mut rendezvous).await {
+            Ok(cookie) => cookie,
+            Err(e) => {
+                let _ = rendezvous.destroy().await;
+                return Err(e);
+            }
+        };
+        let rendezvous_point = rendezvous
+            .relays
+            .last()
+            .cloned()
+            .ok_or_else(|| TorError::Internal("Rendezvous circuit has no hops".into()))?;
+
+        let mut intro_points = descriptor.intro_points.clone();
+        intro_points.shuffle(&mut rand::thread_rng());
+        let mut last_error = TorError::OnionService("No introduction point reachable".into());
+        for intro in intro_points.iter().take(MAX_INTRO_ATTEMPTS) {
+            let handshake = HsNtorClient::new(intro.auth_key, intro.enc_key, subcredential);
+            let plaintext = introduce1_plaintext(&cookie, &rendezvous_point)?;
+            let introduce1 = handshake.introduce1(&plaintext);
+            if let Err(e) = introduce(consensus, builder, selector, pool, intro, introduce1).await {
+                log::warn!("🧅 Introduction failed: {}", e);
+                last_error = e;
+                continue;
+            }
+
+            let joined = match within(expect_cell(&mut rendezvous, RelayCommand::Rendezvous2)).await
+            {
+                Ok(reply) => handshake.complete(&reply.data),
+                Err(e) => Err(e),
+            };
+            return match joined {
+                Ok(keys) => {
+                    rendezvous.add_service_hop(&keys);
+                    log::info!("🧅 Rendezvous with {} complete", address);
+                    Ok(rendezvous)
+                }
+                Err(e) => {
+                    let _ = rendezvous.destroy().await;
+                    Err(TorError::OnionService(format!("Rendezvous failed: {}", e)))
+                }
+            };
+        }
+        let _ = rendezvous.destroy().await;
+        Err(last_error)
+    }
+
+    /// The service's descriptor for `blinded_key`, cached or fetched from
+    /// the responsible HSDirs in turn
+    #[allow(clippy::too_many_arguments)]
+    async fn descriptor(
+        &mut self,
+        consensus: &Consensus,
+        builder: &CircuitBuilder,
+        selector: &RelaySelector,
+        pool: &mut PrebuiltCircuitPool,
+        blinded_key: &[u8; 32],
+        subcredential: &[u8; 32],
+        period: TimePeriod,
+        now: u64,
+    ) -> Result<HsDescriptor> {
+        self.descriptors.retain(|_, cached| cached.expires_at > now);
+        if let Some(cached) = self.descriptors.get(blinded_key) {
+            return Ok(cached.descriptor.clone());
+        }
+
+        let mut hsdirs = responsible_hsdirs(consensus, blinded_key, period, now)?;
+        hsdirs.shuffle(&mut rand::thread_rng());
+        let mut last_error = TorError::OnionService("No HSDir had the descriptor".into());
+        for hsdir in hsdirs.into_iter().take(MAX_HSDIR_ATTEMPTS) {
+            let text = match fetch_descriptor(builder, selector, pool, hsdir, blinded_key).await {
+                Ok(text) => text,
+                Err(e) => {
+                    log::warn!("🧅 Descriptor fetch from {} failed: {}", hsdir.nickname, e);
+                    last_error = e;
+                    continue;
+                }
+            };
+            let descriptor = HsDescriptor::decrypt(&text, blinded_key, subcredential, now)?;
+            log::info!(
+                "🧅 Descriptor revision {} with {} introduction points",
+                descriptor.revision_counter,
+                descriptor.intro_points.len()
+            );
+            self.descriptors.insert(
+                *blinded_key,
+                CachedDescriptor {
+                    descriptor: descriptor.clone(),
+                    expires_at: now + descriptor.lifetime_mins as u64 * 60,
+                },
+            );
+            return Ok(descriptor);
+        }
+        Err(last_error)
+    }
+}
+
+/// Run `future` unless [`REPLY_TIMEOUT_MS`] passes first
+async fn within<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    futures::select_biased! {
+        result = future.fuse() => result,
+        _ = gloo_timers::future::TimeoutFuture::new(REPLY_TIMEOUT_MS).fuse() => Err(TorError::Timeout),
+    }
+}
+
+/// Read relay cells until one with `command` on stream 0 arrives
+async fn expect_cell(circuit: &mut Circuit, command: RelayCommand) -> Result<RelayCell> {
+    loop {
+        let cell = circuit.receive_relay_cell().await?;
+        if cell.stream_id == 0 && cell.command == command {
+            return Ok(cell);
+        }
+        log::debug!(
+            "🧅 Ignoring {:?} on stream {} while waiting for {:?}",
+            cell.command,
+            cell.stream_id,
+            command
+        );
+    }
+}
+
+/// GET `/tor/hs/3/<blinded key>` from `hsdir` over BEGIN_DIR
+async fn fetch_descriptor(
+    builder: &CircuitBuilder,
+    selector: &RelaySelector,
+    pool: &mut PrebuiltCircuitPool,
+    hsdir: &Relay,
+    blinded_key: &[u8; 32],
+) -> Result<String> {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let circuit = pool
+        .get_circuit_for(builder, selector, &CircuitTarget::ExtraHop(hsdir.clone()))
+        .await?;
+    let circuit = Rc::new(RefCell::new(circuit));
+    let result = async {
+        let mut stream = StreamManager::new(Rc::clone(&circuit))
+            .open_dir_stream()
+            .await?;
+        let request = format!(
+            "GET /tor/hs/3/{} HTTP/1.0\r\n\r\n",
+            general_purpose::STANDARD_NO_PAD.encode(blinded_key)
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let raw = stream.read_response().await;
+        let _ = stream.close().await;
+
+        let mut response = HttpResponse::parse(&raw?)?;
+        if !response.is_success() {
+            return Err(TorError::OnionService(format!(
+                "HSDir {} answered HTTP {}",
+                hsdir.nickname, response.status
+            )));
+        }
+        HttpClientConfig::default().decode(&mut response)?;
+        String::from_utf8(response.body)
+            .map_err(|_| TorError::OnionService("Descriptor is not UTF-8".into()))
+    }
+    .await;
+    if let Ok(circuit) = Rc::try_unwrap(circuit) {
+        let _ = circuit.into_inner().destroy().await;
+    }
+    result
+}
+
+/// Make the circuit's last hop a rendezvous point, returning the cookie
+/// the service will present there
+async fn establish_rendezvous(circuit: &mut Circuit) -> Result<[u8; 20]> {
+    let mut cookie = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut cookie);
+    circuit
+        .send_relay_cell(&RelayCell::new(
+            RelayCommand::EstablishRendezvous,
+            0,
+            cookie.to_vec(),
+        ))
+        .await?;
+    within(expect_cell(circuit, RelayCommand::RendezvousEstablished)).await?;
+    Ok(cookie)
+}
+
+/// Deliver INTRODUCE1 through `intro` on a circuit of its own and wait for
+/// the introduction point's INTRODUCE_ACK
+async fn introduce(
+    consensus: &Consensus,
+    builder: &CircuitBuilder,
+    selector: &RelaySelector,
+    pool: &mut PrebuiltCircuitPool,
+    intro: &IntroPoint,
+    introduce1: Vec<u8>,
+) -> Result<()> {
+    // Prefer the consensus entry, which carries family and flags
+    let relay = intro.relay()?;
+    let relay = consensus
+        .relays
+        .iter()
+        .find(|r| r.fingerprint.eq_ignore_ascii_case(&relay.fingerprint))
+        .cloned()
+        .unwrap_or(relay);
+
+    let started = now_ms();
+    let mut circuit = pool
+        .get_circuit_for(builder, selector, &CircuitTarget::ExtraHop(relay))
+        .await?;
+    let result = async {
+        circuit
+            .send_relay_cell(&RelayCell::new(RelayCommand::Introduce1, 0, introduce1))
+            .await?;
+        let ack = within(expect_cell(&mut circuit, RelayCommand::IntroduceAck)).await?;
+        match ack.data.get(..2).map(|s| u16::from_be_bytes([s[0], s[1]])) {
+            Some(0) => Ok(()),
+            status => Err(TorError::OnionService(format!(
+                "Introduction point refused INTRODUCE1 (status {:?})",
+                status
+            ))),
+        }
+    }
+    .await;
+    let _ = circuit.destroy().await;
+    log::debug!(
+        "🧅 Introduction took {}ms",
+        now_ms().saturating_sub(started)
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_descriptor_cache_expires() {
+        let mut client = OnionServiceClient::new();
+        client.descriptors.insert(
+            [1u8; 32],
+            CachedDescriptor {
+                descriptor: HsDescriptor {
+                    lifetime_mins: 180,
+                    revision_counter: 1,
+                    intro_points: Vec::new(),
+                },
+                expires_at: 100,
+            },
+        );
+        assert_eq!(client.cached_descriptors(), 1);
+        client
+            .descriptors
+            .retain(|_, cached| cached.expires_at > 200);
+        assert_eq!(client.cached_descriptors(), 0);
+    }
+}
@@ -0,0 +1,365 @@
+//! Downloading straight into an OPFS file
+//!
+//! `fetch_to_file()` hands each piece of a response body to a
+//! [`FileSink`] as it arrives, which writes it to a file in the origin
+//! private file system (OPFS) in [`WRITE_CHUNK`]-sized writes, so a large
+//! download never sits in WASM memory.
+//!
+//! A writable file stream only reaches the file when it is closed, so the
+//! sink commits every [`COMMIT_BYTES`] and when it stops, even on failure.
+//! What was committed can be continued later with a `Range` request (see
+//! [`ResumableDownload::continuing`]); the response's validator is kept in
+//! localStorage meanwhile so a changed resource starts over.
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemCreateWritableOptions, FileSystemDirectoryHandle, FileSystemFileHandle,
+    FileSystemGetDirectoryOptions, FileSystemGetFileOptions, FileSystemWritableFileStream,
+};
+
+use crate::error::{Result, TorError};
+use crate::resume::ResumableDownload;
+use crate::sse::{decode_chunked, ChunkState};
+
+/// Body bytes gathered before each write
+pub const WRITE_CHUNK: usize = 64 * 1024;
+
+/// Bytes written between commits to the file
+pub const COMMIT_BYTES: u64 = 8 * 1024 * 1024;
+
+/// localStorage key prefix for validators of partial downloads
+pub const VALIDATOR_KEY: &str = "tor_download_validator";
+
+/// Components of an OPFS path such as `downloads/big.iso`
+pub fn path_components(path: &str) -> Result<Vec<&str>> {
+    let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    if parts
+        .iter()
+        .any(|p| p.is_empty() || *p == "." || *p == ".." || p.contains('\\'))
+    {
+        return Err(TorError::InvalidState(format!(
+            "Invalid OPFS path: {}",
+            path
+        )));
+    }
+    Ok(parts)
+}
+
+fn storage_err(what: &str) -> impl Fn(JsValue) -> TorError + '_ {
+    move |e| TorError::Storage(format!("{}: {:?}", what, e))
+}
+
+async fn wait(promise: js_sys::Promise, what: &str) -> Result<JsValue> {
+    JsFuture::from(promise).await.map_err(storage_err(what))
+}
+
+/// A file in the origin private file system, open for writing
+pub struct OpfsFile {
+    handle: FileSystemFileHandle,
+    stream: Option<FileSystemWritableFileStream>,
+    /// Length of the file as last committed
+    committed: u64,
+}
+
+impl OpfsFile {
+    /// Open (creating it and its directories) the file at `path`
+    pub async fn open(path: &str) -> Result<Self> {
+        let parts = path_components(path)?;
+        let window =
+            web_sys::window().ok_or_else(|| TorError::Storage("No window for OPFS".into()))?;
+        let mut dir: FileSystemDirectoryHandle = wait(
+            window.navigator().storage().get_directory(),
+            "OPFS not available",
+        )
+        .await?
+        .unchecked_into();
+
+        let options = FileSystemGetDirectoryOptions::new();
+        options.set_create(true);
+        for name in &parts[..parts.len() - 1] {
+            dir = wait(
+                dir.get_directory_handle_with_options(name, &options),
+                "Failed to open OPFS directory",
+            )
+            .await?
+            .unchecked_into();
+        }
+
+        let options = FileSystemGetFileOptions::new();
+        options.set_create(true);
+        let handle: FileSystemFileHandle = wait(
+            dir.get_file_handle_with_options(parts[parts.len() - 1], &options),
+            "Failed to open OPFS file",
+        )
+        .await?
+        .unchecked_into();
+        let file: web_sys::File = wait(handle.get_file(), "Failed to read OPFS file")
+            .await?
+            .unchecked_into();
+
+        Ok(Self {
+            handle,
+            stream: None,
+            committed: file.size() as u64,
+        })
+    }
+
+    /// Length of the file as last committed
+    pub fn len(&self) -> u64 {
+        self.committed
+    }
+
+    /// Whether the file was empty as last committed
+    pub fn is_empty(&self) -> bool {
+        self.committed == 0
+    }
+
+    /// Write `bytes` at `position`, truncating the file there first if
+    /// `truncate`
+    async fn write_at(&mut self, position: u64, bytes: &[u8], truncate: bool) -> Result<()> {
+        if self.stream.is_none() {
+            let options = FileSystemCreateWritableOptions::new();
+            options.set_keep_existing_data(true);
+            let stream: FileSystemWritableFileStream = wait(
+                self.handle.create_writable_with_options(&options),
+                "Failed to open OPFS file for writing",
+            )
+            .await?
+            .unchecked_into();
+            self.stream = Some(stream);
+        }
+        let stream = self.stream.as_ref().expect("stream opened above");
+        if truncate {
+            let promise = stream
+                .truncate_with_f64(position as f64)
+                .map_err(storage_err("Failed to truncate OPFS file"))?;
+            wait(promise, "Failed to truncate OPFS file").await?;
+        }
+        let promise = stream
+            .seek_with_f64(position as f64)
+            .map_err(storage_err("Failed to seek OPFS file"))?;
+        wait(promise, "Failed to seek OPFS file").await?;
+        let promise = stream
+            .write_with_u8_array(bytes)
+            .map_err(storage_err("Failed to write OPFS file"))?;
+        wait(promise, "Failed to write OPFS file").await?;
+        Ok(())
+    }
+
+    /// Make what was written so far part of the file
+    async fn commit(&mut self, len: u64) -> Result<()> {
+        if let Some(stream) = self.stream.take() {
+            wait(stream.close(), "Failed to commit OPFS file").await?;
+            self.committed = len;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the body of a [`ResumableDownload`] to an [`OpfsFile`]
+pub struct FileSink {
+    file: OpfsFile,
+    path: String,
+    /// localStorage key for the response's validator
+    validator_key: String,
+    /// Chunked transfer decoder, once a chunked response has begun
+    chunks: Option<ChunkState>,
+    /// Decoded body bytes not yet written
+    pending: Vec<u8>,
+    /// Body bytes written to the file
+    written: u64,
+    /// Body bytes written at the last commit
+    committed: u64,
+    /// The next write starts the file over
+    truncate: bool,
+    status: Option<u16>,
+    total: Option<u64>,
+    on_progress: Option<js_sys::Function>,
+}
+
+impl FileSink {
+    /// A sink writing `file` from the start, or continuing after what it
+    /// holds if `resume`
+    pub fn new(
+        file: OpfsFile,
+        path: &str,
+        validator_key: String,
+        resume: bool,
+        on_progress: Option<js_sys::Function>,
+    ) -> Self {
+        let written = if resume { file.len() } else { 0 };
+        Self {
+            truncate: !resume || file.is_empty(),
+            file,
+            path: path.to_string(),
+            validator_key,
+            chunks: None,
+            pending: Vec::new(),
+            written,
+            committed: written,
+            status: None,
+            total: None,
+            on_progress,
+        }
+    }
+
+    /// The download this sink continues
+    pub fn download(&self) -> ResumableDownload {
+        if self.written == 0 {
+            return ResumableDownload::new();
+        }
+        ResumableDownload::continuing(self.written, load_validator(&self.validator_key))
+    }
+
+    /// OPFS path of the file
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Bytes in the file
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// HTTP status of the response
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// Length of the whole file, if the server said
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Whether the whole body has been written
+    pub fn is_complete(&self) -> bool {
+        match (&self.chunks, self.total) {
+            (Some(state), _) => matches!(state, ChunkState::Done),
+            (None, Some(total)) => self.written >= total,
+            (None, None) => true,
+        }
+    }
+
+    /// Write out what `download` has received since the last call
+    pub async fn drain(&mut self, download: &mut ResumableDownload) -> Result<()> {
+        if download.take_restarted() {
+            log::info!("📁 {} changed on the server, starting over", self.path);
+            self.written = 0;
+            self.committed = 0;
+            self.truncate = true;
+            self.status = None;
+            self.chunks = None;
+            self.pending.clear();
+        }
+        let Some(status) = download.status() else {
+            return Ok(());
+        };
+        let body = download.take_body();
+        if self.status.is_none() {
+            self.status = Some(status);
+            self.total = download.content_length();
+            if status == 200 {
+                if let Some(validator) = download.validator() {
+                    save_validator(&self.validator_key, validator);
+                }
+            }
+        }
+        if status != 200 {
+            return Ok(());
+        }
+
+        if download.is_chunked() {
+            let state = self.chunks.get_or_insert(ChunkState::Size(Vec::new()));
+            decode_chunked(state, &body, &mut self.pending)?;
+        } else {
+            self.pending.extend_from_slice(&body);
+        }
+        if self.pending.len() >= WRITE_CHUNK {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Write pending bytes, committing every [`COMMIT_BYTES`]
+    async fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() && !self.truncate {
+            return Ok(());
+        }
+        let bytes = std::mem::take(&mut self.pending);
+        self.file
+            .write_at(self.written, &bytes, std::mem::take(&mut self.truncate))
+            .await?;
+        self.written += bytes.len() as u64;
+        if self.written - self.committed >= COMMIT_BYTES {
+            self.file.commit(self.written).await?;
+            self.committed = self.written;
+        }
+        self.report_progress();
+        Ok(())
+    }
+
+    /// Write and commit everything received; the validator is forgotten
+    /// once the file is complete
+    pub async fn finish(&mut self) -> Result<()> {
+        let flushed = self.flush().await;
+        self.file.commit(self.written).await?;
+        self.committed = self.written;
+        flushed?;
+        if self.is_complete() {
+            clear_validator(&self.validator_key);
+        }
+        Ok(())
+    }
+
+    fn report_progress(&self) {
+        let Some(callback) = &self.on_progress else {
+            return;
+        };
+        let progress = serde_json::json!({
+            "path": self.path,
+            "bytes": self.written,
+            "total": self.total,
+        });
+        let value = serde_wasm_bindgen::to_value(&progress).unwrap_or(JsValue::NULL);
+        if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+            log::warn!("📁 Download progress callback threw: {:?}", e);
+        }
+    }
+}
+
+fn load_validator(key: &str) -> Option<String> {
+    let storage = web_sys::window()?.local_storage().ok()??;
+    storage.get_item(key).ok()?
+}
+
+fn save_validator(key: &str, validator: &str) {
+    if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+        if storage.set_item(key, validator).is_err() {
+            log::warn!("📁 Failed to save download validator");
+        }
+    }
+}
+
+fn clear_validator(key: &str) {
+    if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+        let _ = storage.remove_item(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_components() {
+        assert_eq!(path_components("big.iso").unwrap(), ["big.iso"]);
+        assert_eq!(
+            path_components("/downloads/2026/big.iso").unwrap(),
+            ["downloads", "2026", "big.iso"]
+        );
+        for bad in ["", "downloads/", "a//b", "../b", "a/./b", "a\\b"] {
+            assert!(path_components(bad).is_err(), "{}", bad);
+        }
+    }
+}
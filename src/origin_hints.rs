@@ -0,0 +1,371 @@
+//! Onion-Location and Alt-Svc response headers
+//!
+//! Sites advertise an onion mirror with `Onion-Location` and alternative
+//! endpoints (HTTP/2, HTTP/3, other hosts) with `Alt-Svc` (RFC 7838). Both
+//! are read from HTTPS responses only and remembered per origin, so apps can
+//! offer "open the .onion" and the TLS layer can learn which origins speak
+//! h2/h3.
+//!
+//! The client does not follow either header by itself: `fetch()` reaches
+//! onion services but never switches to one unasked, and there is no HTTP/2
+//! support yet. Hints are per identity — they can track
+//! users like cookies — and are dropped on `new_identity()`.
+
+use crate::runtime::timer::{system_clock, SharedClock};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Alt-Svc freshness when the header carries no `ma` parameter (RFC 7838 §3.1)
+pub const DEFAULT_ALT_SVC_MAX_AGE_SECS: u64 = 24 * 60 * 60;
+
+/// Maximum number of origins remembered
+pub const MAX_TRACKED_ORIGINS: usize = 256;
+
+/// Length of a v3 onion address label (base32 of 35 bytes)
+const ONION_V3_LABEL_LEN: usize = 56;
+
+/// One alternative service from an Alt-Svc header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AltService {
+    /// ALPN protocol ID (`"h2"`, `"h3"`, ...)
+    pub protocol: String,
+    /// Alternative host, or `None` for the same host
+    pub host: Option<String>,
+    pub port: u16,
+    /// Freshness lifetime in seconds
+    pub max_age_secs: u64,
+}
+
+/// Parsed Alt-Svc header value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AltSvcHeader {
+    /// `clear`: forget all alternatives for the origin
+    Clear,
+    Services(Vec<AltService>),
+}
+
+/// Hints found in one response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseHints {
+    pub onion_location: Option<String>,
+    pub alt_svc: Option<AltSvcHeader>,
+}
+
+/// Origin string used as the hint key (`https://host` or `https://host:port`)
+pub fn origin(is_https: bool, host: &str, port: u16) -> String {
+    let (scheme, default_port) = if is_https {
+        ("https", 443)
+    } else {
+        ("http", 80)
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    if port == default_port {
+        format!("{}://{}", scheme, host)
+    } else {
+        format!("{}://{}:{}", scheme, host, port)
+    }
+}
+
+/// Split on `sep` outside double quotes
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c == sep && !in_quotes => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Parse an Alt-Svc header value
+///
+/// Entries that don't parse are skipped rather than failing the header.
+pub fn parse_alt_svc(value: &str) -> AltSvcHeader {
+    if value.trim().eq_ignore_ascii_case("clear") {
+        return AltSvcHeader::Clear;
+    }
+
+    let services = split_unquoted(value, ',')
+        .into_iter()
+        .filter_map(|entry| {
+            let mut params = split_unquoted(entry, ';').into_iter();
+            let (protocol, authority) = params.next()?.trim().split_once('=')?;
+            let authority = authority.trim().trim_matches('"');
+            let (host, port) = authority.rsplit_once(':')?;
+            let port = port.parse().ok()?;
+
+            let max_age_secs = params
+                .filter_map(|p| p.trim().split_once('='))
+                .find(|(k, _)| k.trim().eq_ignore_ascii_case("ma"))
+                .and_then(|(_, v)| v.trim().trim_matches('"').parse().ok())
+                .unwrap_or(DEFAULT_ALT_SVC_MAX_AGE_SECS);
+
+            Some(AltService {
+                protocol: protocol.trim().replace("%3A", ":").replace("%3a", ":"),
+                host: (!host.is_empty()).then(|| host.to_lowercase()),
+                port,
+                max_age_secs,
+            })
+        })
+        .collect();
+
+    AltSvcHeader::Services(services)
+}
+
+/// Validate an Onion-Location value: an http(s) URL on a v3 onion address
+pub fn parse_onion_location(value: &str) -> Option<String> {
+    let value = value.trim();
+    let rest = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"))?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.split(':').next()?.to_ascii_lowercase();
+    let label = host.strip_suffix(".onion")?;
+    // Subdomains of the onion address are allowed; the service label is last
+    let service = label.rsplit('.').next()?;
+    let valid = service.len() == ONION_V3_LABEL_LEN
+        && service
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || (b'2'..=b'7').contains(&b));
+    valid.then(|| value.to_string())
+}
+
+/// Extract Onion-Location and Alt-Svc from a raw HTTP response
+pub fn response_hints(response: &[u8]) -> ResponseHints {
+    let head_len = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .unwrap_or(response.len());
+    let head = String::from_utf8_lossy(&response[..head_len]);
+
+    let mut hints = ResponseHints::default();
+    let mut alt_values = Vec::new();
+    // First line is the status line
+    for line in head.split("\r\n").skip(1) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("onion-location") {
+            hints.onion_location = parse_onion_location(value);
+        } else if name.eq_ignore_ascii_case("alt-svc") {
+            alt_values.push(value.trim().to_string());
+        }
+    }
+
+    // Repeated Alt-Svc headers combine like one comma-separated header
+    if !alt_values.is_empty() {
+        hints.alt_svc = Some(parse_alt_svc(&alt_values.join(",")));
+    }
+    hints
+}
+
+#[derive(Default)]
+struct OriginEntry {
+    onion_location: Option<String>,
+    /// Alternatives with their expiry (ms)
+    alt_services: Vec<(AltService, u64)>,
+    last_seen_ms: u64,
+}
+
+/// Per-origin Onion-Location and Alt-Svc hints
+pub struct OriginHints {
+    clock: SharedClock,
+    origins: HashMap<String, OriginEntry>,
+}
+
+impl OriginHints {
+    /// Hints store using the system clock
+    pub fn new() -> Self {
+        Self::with_clock(system_clock())
+    }
+
+    /// Hints store using a custom clock (tests)
+    pub fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            clock,
+            origins: HashMap::new(),
+        }
+    }
+
+    /// Record the hints in a response from `origin`
+    ///
+    /// Callers pass only HTTPS responses; a plain-HTTP response could be
+    /// rewritten by the exit.
+    pub fn record(&mut self, origin: &str, response: &[u8]) {
+        let hints = response_hints(response);
+        if hints == ResponseHints::default() && !self.origins.contains_key(origin) {
+            return;
+        }
+
+        let now = self.clock.now_ms();
+        if !self.origins.contains_key(origin) && self.origins.len() >= MAX_TRACKED_ORIGINS {
+            if let Some(stale) = self
+                .origins
+                .iter()
+                .min_by_key(|(_, e)| e.last_seen_ms)
+                .map(|(k, _)| k.clone())
+            {
+                self.origins.remove(&stale);
+            }
+        }
+
+        let entry = self.origins.entry(origin.to_string()).or_default();
+        entry.last_seen_ms = now;
+        if let Some(ref onion) = hints.onion_location {
+            if entry.onion_location.as_ref() != Some(onion) {
+                log::info!("🧅 {} advertises onion service {}", origin, onion);
+            }
+        }
+        // A response without the header withdraws the advertisement
+        entry.onion_location = hints.onion_location;
+
+        match hints.alt_svc {
+            Some(AltSvcHeader::Clear) => entry.alt_services.clear(),
+            Some(AltSvcHeader::Services(services)) => {
+                entry.alt_services = services
+                    .into_iter()
+                    .map(|s| {
+                        let expires = now.saturating_add(s.max_age_secs.saturating_mul(1000));
+                        (s, expires)
+                    })
+                    .collect();
+            }
+            None => {}
+        }
+    }
+
+    /// Onion-Location advertised by the origin's latest response
+    pub fn onion_location(&self, origin: &str) -> Option<String> {
+        self.origins.get(origin)?.onion_location.clone()
+    }
+
+    /// Alternative services for the origin that are still fresh
+    pub fn alt_services(&self, origin: &str) -> Vec<AltService> {
+        let now = self.clock.now_ms();
+        self.origins
+            .get(origin)
+            .map(|e| {
+                e.alt_services
+                    .iter()
+                    .filter(|(_, expires)| *expires > now)
+                    .map(|(s, _)| s.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether the origin advertises `protocol` (e.g. `"h2"`, `"h3"`)
+    pub fn supports(&self, origin: &str, protocol: &str) -> bool {
+        self.alt_services(origin)
+            .iter()
+            .any(|s| s.protocol.eq_ignore_ascii_case(protocol))
+    }
+
+    /// Forget everything (new identity)
+    pub fn clear(&mut self) {
+        self.origins.clear();
+    }
+
+    /// Number of origins with hints
+    pub fn len(&self) -> usize {
+        self.origins.len()
+    }
+
+    /// Whether no origin has hints
+    pub fn is_empty(&self) -> bool {
+        self.origins.is_empty()
+    }
+}
+
+impl Default for OriginHints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MockClock;
+    use std::rc::Rc;
+
+    const ONION: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+
+    #[test]
+    fn test_parse_alt_svc() {
+        let header = parse_alt_svc(
+            r#"h3=":443"; ma=86400, h3-29=":8443", h2="alt.example.com:443"; ma=60; persist=1"#,
+        );
+        let AltSvcHeader::Services(services) = header else {
+            panic!("expected services");
+        };
+        assert_eq!(services.len(), 3);
+        assert_eq!(services[0].protocol, "h3");
+        assert_eq!(services[0].host, None);
+        assert_eq!(services[0].max_age_secs, 86400);
+        assert_eq!(services[1].port, 8443);
+        assert_eq!(services[1].max_age_secs, DEFAULT_ALT_SVC_MAX_AGE_SECS);
+        assert_eq!(services[2].host.as_deref(), Some("alt.example.com"));
+
+        assert_eq!(parse_alt_svc(" Clear "), AltSvcHeader::Clear);
+        assert_eq!(
+            parse_alt_svc("garbage, h2"),
+            AltSvcHeader::Services(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_onion_location_validation() {
+        let url = format!("https://{}/path?q=1", ONION);
+        assert_eq!(parse_onion_location(&url), Some(url.clone()));
+        assert!(parse_onion_location(&format!("http://www.{}", ONION)).is_some());
+
+        assert!(parse_onion_location("https://example.com/").is_none());
+        assert!(parse_onion_location("https://short.onion/").is_none());
+        assert!(parse_onion_location(&format!("ftp://{}/", ONION)).is_none());
+        // v2-style uppercase / invalid base32 characters
+        assert!(parse_onion_location(&format!("https://{}", ONION.replace('d', "1"))).is_none());
+    }
+
+    #[test]
+    fn test_records_and_expires_hints() {
+        let clock = MockClock::new(0);
+        let mut hints = OriginHints::with_clock(Rc::new(clock.clone()));
+        let site = origin(true, "Example.com", 443);
+        assert_eq!(site, "https://example.com");
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nonion-location: https://{}/\r\nAlt-Svc: h2=\":443\"; ma=60\r\nalt-svc: h3=\":443\"\r\n\r\nbody with Alt-Svc: h9=\":1\"",
+            ONION
+        );
+        hints.record(&site, response.as_bytes());
+        assert_eq!(
+            hints.onion_location(&site),
+            Some(format!("https://{}/", ONION))
+        );
+        assert!(hints.supports(&site, "h2"));
+        assert!(hints.supports(&site, "H3"));
+        assert!(!hints.supports(&site, "h9"));
+
+        clock.advance(60_000);
+        assert!(!hints.supports(&site, "h2"));
+        assert!(hints.supports(&site, "h3"));
+
+        hints.record(&site, b"HTTP/1.1 200 OK\r\nAlt-Svc: clear\r\n\r\n");
+        assert!(hints.alt_services(&site).is_empty());
+        assert_eq!(hints.onion_location(&site), None);
+
+        // Responses without hints don't create entries
+        hints.record("https://plain.example", b"HTTP/1.1 200 OK\r\n\r\n");
+        assert_eq!(hints.len(), 1);
+    }
+}
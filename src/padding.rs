@@ -21,12 +21,20 @@
 //! - Negotiate padding parameters with PADDING_NEGOTIATE cell
 //! - Respect relay's PADDING_NEGOTIATED response
 //!
+//! The next padding deadline is registered with a
+//! [`TimerService`](crate::runtime::TimerService), so the shared timer
+//! wheel, rather than polling, says when padding is due.
+//!
 //! ## References
 //!
 //! - https://spec.torproject.org/padding-spec/connection-level-padding.html
 //! - Proposal 254: Padding Negotiation
 
 use crate::protocol::{Cell, CellCommand};
+use crate::runtime::{TimerId, TimerService};
+use std::cell::Cell as Flag;
+use std::rc::Rc;
+use std::time::Duration;
 
 /// Padding negotiation command types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -89,12 +97,18 @@ pub struct PaddingScheduler {
     /// Current state
     state: PaddingState,
 
+    /// Timer service the padding deadline is registered with
+    timers: TimerService,
+
+    /// Pending padding deadline
+    timer: Option<TimerId>,
+
+    /// Set when `timer` fires
+    due: Rc<Flag<bool>>,
+
     /// Timestamp of last cell (any type) sent/received
     last_cell_time_ms: u64,
 
-    /// Timestamp of last padding cell sent
-    last_padding_time_ms: u64,
-
     /// Next padding interval (randomized)
     next_interval_ms: u32,
 
@@ -113,7 +127,12 @@ impl PaddingScheduler {
 
     /// Create a padding scheduler with custom config
     pub fn with_config(config: PaddingConfig) -> Self {
-        Self {
+        Self::with_timers(config, TimerService::global())
+    }
+
+    /// Create a padding scheduler registering its deadlines with `timers`
+    pub fn with_timers(config: PaddingConfig, timers: TimerService) -> Self {
+        let mut scheduler = Self {
             state: if config.enabled {
                 PaddingState::Enabled
             } else {
@@ -121,13 +140,36 @@ impl PaddingScheduler {
             },
             next_interval_ms: Self::random_interval(&config),
             config,
-            last_cell_time_ms: 0,
-            last_padding_time_ms: 0,
+            last_cell_time_ms: timers.now_ms(),
+            timers,
+            timer: None,
+            due: Rc::new(Flag::new(false)),
             padding_cells_sent: 0,
             relay_supports_padding: true, // Assume true until told otherwise
+        };
+        scheduler.arm();
+        scheduler
+    }
+
+    /// Register the next padding deadline, replacing any pending one
+    fn arm(&mut self) {
+        self.disarm();
+        if self.is_enabled() {
+            let due = Rc::clone(&self.due);
+            self.timer = Some(self.timers.schedule(
+                Duration::from_millis(self.next_interval_ms as u64),
+                move || due.set(true),
+            ));
         }
     }
 
+    fn disarm(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            self.timers.cancel(timer);
+        }
+        self.due.set(false);
+    }
+
     /// Generate a random padding interval within the configured range
     fn random_interval(config: &PaddingConfig) -> u32 {
         use rand::Rng;
@@ -139,6 +181,7 @@ impl PaddingScheduler {
     pub fn enable(&mut self) {
         self.state = PaddingState::Enabled;
         self.config.enabled = true;
+        self.arm();
         log::debug!("Channel padding enabled");
     }
 
@@ -146,6 +189,7 @@ impl PaddingScheduler {
     pub fn disable(&mut self) {
         self.state = PaddingState::Disabled;
         self.config.enabled = false;
+        self.disarm();
         log::debug!("Channel padding disabled");
     }
 
@@ -157,45 +201,34 @@ impl PaddingScheduler {
     /// Record that a real cell was sent/received
     ///
     /// This resets the padding timer since we have real activity.
-    pub fn on_cell_activity(&mut self, now_ms: u64) {
-        self.last_cell_time_ms = now_ms;
+    pub fn on_cell_activity(&mut self) {
+        self.last_cell_time_ms = self.timers.now_ms();
+        self.arm();
     }
 
     /// Check if we should send a padding cell now
     ///
-    /// Returns true if it's time to send padding.
-    pub fn should_send_padding(&self, now_ms: u64) -> bool {
-        if !self.is_enabled() {
+    /// Returns true once the padding timer has fired, unless the
+    /// connection has been idle for longer than `idle_timeout_ms`.
+    pub fn should_send_padding(&self) -> bool {
+        if !self.is_enabled() || !self.due.get() {
             return false;
         }
 
         // Don't pad if we've been idle too long
-        if self.last_cell_time_ms > 0 {
-            let idle_time = now_ms.saturating_sub(self.last_cell_time_ms);
-            if idle_time > self.config.idle_timeout_ms as u64 {
-                return false;
-            }
-        }
-
-        // Check if enough time has passed since last padding
-        let time_since_activity = if self.last_padding_time_ms > 0 {
-            now_ms.saturating_sub(self.last_padding_time_ms)
-        } else {
-            now_ms.saturating_sub(self.last_cell_time_ms)
-        };
-
-        time_since_activity >= self.next_interval_ms as u64
+        let idle_time = self.timers.now_ms().saturating_sub(self.last_cell_time_ms);
+        idle_time <= self.config.idle_timeout_ms as u64
     }
 
     /// Record that we sent a padding cell
     ///
     /// Call this after sending CELL_PADDING.
-    pub fn on_padding_sent(&mut self, now_ms: u64) {
-        self.last_padding_time_ms = now_ms;
+    pub fn on_padding_sent(&mut self) {
         self.padding_cells_sent += 1;
 
         // Generate new random interval for next padding
         self.next_interval_ms = Self::random_interval(&self.config);
+        self.arm();
 
         log::trace!(
             "Padding cell sent, next interval: {}ms",
@@ -307,6 +340,12 @@ impl Default for PaddingScheduler {
     }
 }
 
+impl Drop for PaddingScheduler {
+    fn drop(&mut self) {
+        self.disarm();
+    }
+}
+
 /// Padding statistics
 #[derive(Debug, Clone)]
 pub struct PaddingStats {
@@ -326,6 +365,15 @@ pub struct PaddingStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runtime::MockClock;
+
+    fn scheduler(clock: &MockClock, config: PaddingConfig) -> (PaddingScheduler, TimerService) {
+        let timers = TimerService::with_clock(Rc::new(clock.clone()));
+        (
+            PaddingScheduler::with_timers(config, timers.clone()),
+            timers,
+        )
+    }
 
     #[test]
     fn test_padding_scheduler_creation() {
@@ -337,10 +385,15 @@ mod tests {
 
     #[test]
     fn test_padding_disabled() {
-        let mut scheduler = PaddingScheduler::new();
+        let clock = MockClock::new(0);
+        let (mut scheduler, timers) = scheduler(&clock, PaddingConfig::default());
+        assert_eq!(timers.pending(), 1);
         scheduler.disable();
         assert!(!scheduler.is_enabled());
-        assert!(!scheduler.should_send_padding(10000));
+        assert_eq!(timers.pending(), 0);
+        clock.advance(10000);
+        timers.run_expired();
+        assert!(!scheduler.should_send_padding());
     }
 
     #[test]
@@ -351,16 +404,33 @@ mod tests {
             high_ms: 200,
             idle_timeout_ms: 10000,
         };
-        let mut scheduler = PaddingScheduler::with_config(config);
+        let clock = MockClock::new(1000);
+        let (mut scheduler, timers) = scheduler(&clock, config);
 
         // Record activity
-        scheduler.on_cell_activity(1000);
+        scheduler.on_cell_activity();
 
         // Should not pad immediately
-        assert!(!scheduler.should_send_padding(1000));
-
-        // Should pad after interval
-        assert!(scheduler.should_send_padding(1300));
+        assert!(!scheduler.should_send_padding());
+
+        // Activity pushes the deadline back
+        clock.advance(50);
+        assert_eq!(timers.run_expired(), 0);
+        scheduler.on_cell_activity();
+        assert_eq!(timers.pending(), 1);
+
+        // Should pad once the timer fires
+        clock.advance(300);
+        assert_eq!(timers.run_expired(), 1);
+        assert!(scheduler.should_send_padding());
+
+        // Sending registers the next deadline
+        scheduler.on_padding_sent();
+        assert!(!scheduler.should_send_padding());
+        assert_eq!(timers.pending(), 1);
+
+        drop(scheduler);
+        assert_eq!(timers.pending(), 0);
     }
 
     #[test]
@@ -415,12 +485,15 @@ mod tests {
             high_ms: 200,
             idle_timeout_ms: 1000,
         };
-        let mut scheduler = PaddingScheduler::with_config(config);
+        let clock = MockClock::new(1000);
+        let (mut scheduler, timers) = scheduler(&clock, config);
 
         // Record activity
-        scheduler.on_cell_activity(1000);
+        scheduler.on_cell_activity();
 
         // After idle timeout, should not pad
-        assert!(!scheduler.should_send_padding(3000));
+        clock.advance(2000);
+        timers.run_expired();
+        assert!(!scheduler.should_send_padding());
     }
 }
@@ -6,8 +6,14 @@
 //! - Don't leak which relays we're trying in parallel
 //! - Use the first successful connection (race)
 //! - Still enforce relay selection constraints
+//!
+//! Before trying candidates the builder prepares the ntor keypairs for
+//! every hop of every candidate path through [`handshake_pool`], on worker
+//! threads when the page is cross-origin isolated and the pool is enabled,
+//! interleaved on the main thread otherwise.
 
 use crate::error::{Result, TorError};
+use crate::handshake_pool;
 use crate::protocol::{Circuit, CircuitBuilder, Relay, RelaySelector};
 
 /// Configuration for parallel building
@@ -19,6 +25,8 @@ pub struct ParallelBuilderConfig {
     pub connection_timeout_ms: u64,
     /// Whether to cancel remaining attempts after first success
     pub cancel_on_success: bool,
+    /// Whether to prepare handshakes for all candidate paths up front
+    pub prepare_handshakes: bool,
 }
 
 impl Default for ParallelBuilderConfig {
@@ -27,6 +35,7 @@ impl Default for ParallelBuilderConfig {
             parallel_guards: 3,
             connection_timeout_ms: 10_000, // 10 seconds
             cancel_on_success: true,
+            prepare_handshakes: true,
         }
     }
 }
@@ -44,6 +53,8 @@ pub struct ParallelBuilderStats {
     pub avg_first_success_ms: f64,
     /// Total parallel attempts made
     pub total_parallel_attempts: u64,
+    /// Candidate paths whose handshakes were prepared ahead
+    pub paths_prepared: u64,
 }
 
 /// Parallel circuit builder
@@ -106,6 +117,12 @@ impl ParallelCircuitBuilder {
             exits.len()
         );
 
+        if self.config.prepare_handshakes {
+            let paths = candidate_paths(&guards, &middles, &exits);
+            handshake_pool::prepare_paths(&paths).await;
+            self.stats.paths_prepared += paths.len() as u64;
+        }
+
         // 2. Try guards sequentially for now (true parallel would need more complex async)
         // In a full implementation, we'd use tokio::select! or futures::select!
         // For WASM, we're somewhat limited in true parallelism
@@ -152,6 +169,23 @@ impl ParallelCircuitBuilder {
             .unwrap_or_else(|| TorError::CircuitBuildFailed("All parallel attempts failed".into())))
     }
 
+    /// Prepare handshakes for the next build's candidate paths without
+    /// building, returning how many paths were prepared
+    pub async fn prebuild(&mut self, selector: &RelaySelector) -> usize {
+        let n = self.config.parallel_guards;
+        let guards = selector.select_guards(n);
+        let guard_fps: Vec<&str> = guards.iter().map(|g| g.fingerprint.as_str()).collect();
+        let middles = selector.select_middles(n, &guard_fps);
+        let mut exclude = guard_fps.clone();
+        exclude.extend(middles.iter().map(|m| m.fingerprint.as_str()));
+        let exits = selector.select_exits(n, &exclude);
+
+        let paths = candidate_paths(&guards, &middles, &exits);
+        handshake_pool::prepare_paths(&paths).await;
+        self.stats.paths_prepared += paths.len() as u64;
+        paths.len()
+    }
+
     /// Try building with a specific guard
     async fn try_build_with_guard<'a>(
         &self,
@@ -209,6 +243,22 @@ impl Default for ParallelCircuitBuilder {
     }
 }
 
+/// Fingerprints of the candidate paths: the i-th guard, middle and exit
+fn candidate_paths(guards: &[&Relay], middles: &[&Relay], exits: &[&Relay]) -> Vec<Vec<String>> {
+    guards
+        .iter()
+        .zip(middles)
+        .zip(exits)
+        .map(|((g, m), e)| {
+            vec![
+                g.fingerprint.clone(),
+                m.fingerprint.clone(),
+                e.fingerprint.clone(),
+            ]
+        })
+        .collect()
+}
+
 /// Timestamp in milliseconds (WASM-compatible)
 fn now_ms() -> u64 {
     #[cfg(target_arch = "wasm32")]
@@ -228,12 +278,14 @@ fn now_ms() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::TestRelay;
 
     #[test]
     fn test_config_defaults() {
         let config = ParallelBuilderConfig::default();
         assert_eq!(config.parallel_guards, 3);
         assert!(config.cancel_on_success);
+        assert!(config.prepare_handshakes);
     }
 
     #[test]
@@ -243,4 +295,34 @@ mod tests {
         assert_eq!(stats.builds_attempted, 0);
         assert_eq!(stats.builds_succeeded, 0);
     }
+
+    #[test]
+    fn test_prebuild_prepares_every_hop() {
+        use crate::protocol::RelayFlags;
+
+        let relay = |i: usize, flags: &str| {
+            TestRelay::new(&format!("relay{}", i))
+                .fingerprint(&format!("{:040X}", i))
+                .address(&format!("10.0.0.{}", i))
+                .flags(RelayFlags::from_string(flags))
+                .bandwidth(1000)
+                .build()
+        };
+        let relays = (0..12)
+            .map(|i| match i % 3 {
+                0 => relay(i, "Guard Stable Fast Running Valid"),
+                1 => relay(i, "Stable Fast Running Valid"),
+                _ => relay(i, "Exit Stable Fast Running Valid"),
+            })
+            .collect();
+        let selector = RelaySelector::new(relays);
+
+        handshake_pool::clear();
+        let mut builder = ParallelCircuitBuilder::new();
+        let paths = futures::executor::block_on(builder.prebuild(&selector));
+        assert!(paths > 0);
+        assert_eq!(builder.get_stats().paths_prepared, paths as u64);
+        assert_eq!(handshake_pool::stats().prepared, paths * 3);
+        handshake_pool::clear();
+    }
 }
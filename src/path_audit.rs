@@ -0,0 +1,216 @@
+//! Offline audit of relay path selection
+//!
+//! Runs the same guard/middle/exit selection the circuit builder uses, many
+//! times, without touching the network, and reports how the picks are
+//! distributed. Deployments run this after changing selection logic (or
+//! against a new consensus) to catch bias before it reaches users: one guard
+//! taking most first hops, exits concentrated in a few relays, or paths that
+//! put two hops in the same /16, AS or family.
+
+use crate::protocol::{same_ipv4_slash16, CircuitBuilder, Relay, RelaySelector};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Upper bound on simulated paths per audit
+pub const MAX_AUDIT_SAMPLES: usize = 10_000;
+
+/// How many relays per role are listed in the report
+const TOP_RELAYS: usize = 10;
+
+/// Guard candidates requested per build, as in `CircuitBuilder::build_circuit`
+const GUARD_CANDIDATES: usize = 9;
+
+/// How often one relay was picked for a role
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayShare {
+    pub nickname: String,
+    pub fingerprint: String,
+    /// Times picked
+    pub count: usize,
+    /// Fraction of simulated paths
+    pub share: f64,
+    /// Fraction of the role's total bandwidth (what a purely
+    /// bandwidth-weighted selection would give it)
+    pub bandwidth_share: f64,
+}
+
+/// Distribution of picks for one role (guard or exit)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RoleDistribution {
+    /// Relays eligible for the role
+    pub eligible: usize,
+    /// Distinct relays actually picked
+    pub distinct: usize,
+    /// Largest share of paths taken by a single relay
+    pub max_share: f64,
+    /// Most-picked relays, highest count first
+    pub top: Vec<RelayShare>,
+}
+
+/// Result of [`audit_path_selection`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PathAuditReport {
+    /// Paths requested
+    pub samples: usize,
+    /// Paths where selection could not find a full guard/middle/exit set
+    pub failed_selections: usize,
+    pub guards: RoleDistribution,
+    pub exits: RoleDistribution,
+    /// Paths with two hops in the same IPv4 /16
+    pub subnet_overlaps: usize,
+    /// Paths with two hops in the same AS (only hops with AS data count)
+    pub as_overlaps: usize,
+    /// Relays in the consensus that carry AS data
+    pub relays_with_as_data: usize,
+    /// Paths with two hops in the same declared family (the builder rejects
+    /// these and retries, so they cost build time rather than anonymity)
+    pub family_violations: usize,
+}
+
+/// Pick one path the way `CircuitBuilder` does on its first attempt
+fn simulate_path(selector: &RelaySelector) -> Option<[&Relay; 3]> {
+    let guard = *selector.select_guards(GUARD_CANDIDATES).first()?;
+    let middle = *selector.select_middles(5, &[&guard.fingerprint]).first()?;
+    let exit = selector
+        .select_exits(10, &[&guard.fingerprint])
+        .into_iter()
+        .find(|e| e.fingerprint != middle.fingerprint)?;
+    Some([guard, middle, exit])
+}
+
+fn any_pair(path: &[&Relay; 3], f: impl Fn(&Relay, &Relay) -> bool) -> bool {
+    f(path[0], path[1]) || f(path[0], path[2]) || f(path[1], path[2])
+}
+
+fn same_as(a: &Relay, b: &Relay) -> bool {
+    matches!((&a.asn, &b.asn), (Some(x), Some(y)) if x == y)
+}
+
+fn distribution(
+    eligible: &[&Relay],
+    picks: &HashMap<&str, usize>,
+    paths: usize,
+) -> RoleDistribution {
+    let total_bandwidth: u64 = eligible.iter().map(|r| r.bandwidth).sum();
+    let ratio = |n: u64, d: u64| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+
+    let mut top: Vec<RelayShare> = eligible
+        .iter()
+        .filter_map(|r| {
+            let count = *picks.get(r.fingerprint.as_str())?;
+            Some(RelayShare {
+                nickname: r.nickname.clone(),
+                fingerprint: r.fingerprint.clone(),
+                count,
+                share: ratio(count as u64, paths as u64),
+                bandwidth_share: ratio(r.bandwidth, total_bandwidth),
+            })
+        })
+        .collect();
+    top.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.fingerprint.cmp(&b.fingerprint))
+    });
+
+    RoleDistribution {
+        eligible: eligible.len(),
+        distinct: picks.len(),
+        max_share: top.first().map(|r| r.share).unwrap_or(0.0),
+        top: top.into_iter().take(TOP_RELAYS).collect(),
+    }
+}
+
+/// Simulate `samples` path selections and report their distribution
+pub fn audit_path_selection(selector: &RelaySelector, samples: usize) -> PathAuditReport {
+    let samples = samples.min(MAX_AUDIT_SAMPLES);
+    let mut report = PathAuditReport {
+        samples,
+        ..Default::default()
+    };
+
+    let mut guard_picks: HashMap<&str, usize> = HashMap::new();
+    let mut exit_picks: HashMap<&str, usize> = HashMap::new();
+
+    for _ in 0..samples {
+        let path = match simulate_path(selector) {
+            Some(path) => path,
+            None => {
+                report.failed_selections += 1;
+                continue;
+            }
+        };
+        *guard_picks.entry(&path[0].fingerprint).or_default() += 1;
+        *exit_picks.entry(&path[2].fingerprint).or_default() += 1;
+
+        if any_pair(&path, same_ipv4_slash16) {
+            report.subnet_overlaps += 1;
+        }
+        if any_pair(&path, same_as) {
+            report.as_overlaps += 1;
+        }
+        if any_pair(&path, CircuitBuilder::relays_share_family) {
+            report.family_violations += 1;
+        }
+    }
+
+    let built = samples - report.failed_selections;
+    report.guards = distribution(&selector.guards(), &guard_picks, built);
+    report.exits = distribution(&selector.exits(), &exit_picks, built);
+    report.relays_with_as_data = selector.relays().iter().filter(|r| r.asn.is_some()).count();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{RelayFlags, TestRelay};
+
+    fn relay(name: &str, address: &str, asn: &str, guard: bool, exit: bool, bw: u64) -> Relay {
+        TestRelay::new(name)
+            .address(address)
+            .flags(RelayFlags {
+                guard,
+                exit,
+                // Keep exits out of the middle pool so paths are deterministic
+                stable: !exit,
+                ..RelayFlags::from_string("Fast Running Valid")
+            })
+            .bandwidth(bw)
+            .asn(asn)
+            .build()
+    }
+
+    #[test]
+    fn test_audit_counts_overlaps_and_shares() {
+        // One guard, one middle, one exit: every path is identical
+        let selector = RelaySelector::new(vec![
+            relay("guard", "198.51.100.1", "AS1", true, false, 300),
+            relay("middle", "203.0.113.1", "AS2", false, false, 100),
+            relay("exit", "198.51.7.7", "AS2", false, true, 100),
+        ]);
+
+        let report = audit_path_selection(&selector, 20);
+        assert_eq!(report.samples, 20);
+        assert_eq!(report.failed_selections, 0);
+        assert_eq!(report.guards.distinct, 1);
+        assert_eq!(report.guards.max_share, 1.0);
+        assert_eq!(report.exits.top[0].count, 20);
+        // Guard and exit share 198.51/16, middle and exit share AS2
+        assert_eq!(report.subnet_overlaps, 20);
+        assert_eq!(report.as_overlaps, 20);
+        assert_eq!(report.family_violations, 0);
+        assert_eq!(report.relays_with_as_data, 3);
+    }
+
+    #[test]
+    fn test_audit_without_exits_fails_selection() {
+        let selector = RelaySelector::new(vec![
+            relay("guard", "198.51.100.1", "AS1", true, false, 300),
+            relay("middle", "203.0.113.1", "AS2", false, false, 100),
+        ]);
+        let report = audit_path_selection(&selector, 5);
+        assert_eq!(report.failed_selections, 5);
+        assert!(report.guards.top.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! Path-selection explanations for circuit build reports
+//!
+//! "Why did I get this exit?" is hard to answer after the fact: selection
+//! is partly random and depends on flags, bandwidth, requirements, bans
+//! and the blocklist at that moment. With the `explain_paths` debug flag
+//! on, each build report carries a [`SelectionExplanation`] recording, for
+//! every hop, how it compared to the other relays eligible for its role
+//! and why the rest were not eligible, plus the relays the builder passed
+//! over or gave up on during the build.
+//!
+//! Explanations name relays, so they are off by default and, like the rest
+//! of the build reports, only leave the client through the redacted
+//! diagnostics bundle.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::protocol::debug::explain_paths;
+use crate::protocol::{Relay, RelayRole, RelaySelector};
+
+/// Why one hop's relay was chosen
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HopExplanation {
+    pub role: RelayRole,
+    pub nickname: String,
+    pub fingerprint: String,
+    pub flags: Vec<&'static str>,
+    pub bandwidth: u64,
+    /// Share of the eligible relays' total bandwidth
+    pub weight: f64,
+    /// How it came to be picked
+    pub reason: String,
+    /// Relays eligible for the role (other hops excluded)
+    pub candidates: usize,
+    /// Relays not eligible for the role, counted by reason
+    pub rejected: BTreeMap<&'static str, usize>,
+}
+
+/// A relay considered during the build but not used
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PassedOver {
+    pub role: RelayRole,
+    pub nickname: String,
+    pub fingerprint: String,
+    pub reason: String,
+}
+
+/// Why a circuit has the path it has
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelectionExplanation {
+    /// Circuit built, if the build succeeded
+    pub circuit_id: Option<u32>,
+    pub hops: Vec<HopExplanation>,
+    /// Rules the path was held to
+    pub constraints: Vec<&'static str>,
+    pub passed_over: Vec<PassedOver>,
+}
+
+impl SelectionExplanation {
+    /// Explain `path` (guard, middles, exit) as picked by `selector`
+    pub fn new(
+        selector: &RelaySelector,
+        circuit_id: Option<u32>,
+        path: &[Relay],
+        passed_over: Vec<PassedOver>,
+    ) -> Self {
+        let hops = path
+            .iter()
+            .enumerate()
+            .map(|(i, relay)| {
+                let role = match i {
+                    0 => RelayRole::Guard,
+                    i if i + 1 == path.len() => RelayRole::Exit,
+                    _ => RelayRole::Middle,
+                };
+                explain_hop(selector, role, relay, path)
+            })
+            .collect();
+
+        let mut constraints = vec![
+            "no relay twice on the path",
+            "no two relays that declare each other family",
+            "guards from the persistent guard set first",
+        ];
+        if selector.is_long_lived() {
+            constraints.push("Stable relays for long-lived streams");
+        }
+        if selector.requirements().require_fast {
+            constraints.push("Fast relays only");
+        }
+        Self {
+            circuit_id,
+            hops,
+            constraints,
+            passed_over,
+        }
+    }
+}
+
+/// How `chosen` compares to the other relays eligible for `role`
+fn explain_hop(
+    selector: &RelaySelector,
+    role: RelayRole,
+    chosen: &Relay,
+    path: &[Relay],
+) -> HopExplanation {
+    let mut rejected = BTreeMap::new();
+    let mut eligible = Vec::new();
+    for relay in selector.relays() {
+        let on_path = path.iter().any(|r| r.fingerprint == relay.fingerprint);
+        if on_path && relay.fingerprint != chosen.fingerprint {
+            *rejected.entry("already on the path").or_insert(0) += 1;
+        } else if let Some(reason) = selector.rejection(relay, role) {
+            *rejected.entry(reason).or_insert(0) += 1;
+        } else {
+            eligible.push(relay);
+        }
+    }
+
+    let total: u64 = eligible.iter().map(|r| r.bandwidth).sum();
+    let rank = 1 + eligible
+        .iter()
+        .filter(|r| r.bandwidth > chosen.bandwidth)
+        .count();
+    let preferred =
+        role == RelayRole::Guard && selector.preferred_guards().contains(&chosen.fingerprint);
+    let reason = if preferred {
+        "preferred guard from the persistent guard set".to_string()
+    } else if !eligible.iter().any(|r| r.fingerprint == chosen.fingerprint) {
+        format!(
+            "not eligible now: {}",
+            selector.rejection(chosen, role).unwrap_or("unknown")
+        )
+    } else {
+        format!(
+            "bandwidth rank {} of {}; half the candidates are taken by bandwidth, the rest at random",
+            rank,
+            eligible.len()
+        )
+    };
+
+    HopExplanation {
+        role,
+        nickname: chosen.nickname.clone(),
+        fingerprint: chosen.fingerprint.clone(),
+        flags: chosen.flags.names(),
+        bandwidth: chosen.bandwidth,
+        weight: if total == 0 {
+            0.0
+        } else {
+            chosen.bandwidth as f64 / total as f64
+        },
+        reason,
+        candidates: eligible.len(),
+        rejected,
+    }
+}
+
+/// Relays passed over during one build
+#[derive(Debug, Default)]
+pub struct PassedOverLog {
+    enabled: bool,
+    entries: RefCell<Vec<PassedOver>>,
+}
+
+impl PassedOverLog {
+    /// A log that records only if `explain_paths` is on now
+    pub fn new() -> Self {
+        Self::with_enabled(explain_paths())
+    }
+
+    pub fn with_enabled(enabled: bool) -> Self {
+        Self {
+            enabled,
+            entries: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Note that `relay` was not used as `role` because of `reason`
+    pub fn note(&self, relay: &Relay, role: RelayRole, reason: impl std::fmt::Display) {
+        if !self.enabled {
+            return;
+        }
+        self.entries.borrow_mut().push(PassedOver {
+            role,
+            nickname: relay.nickname.clone(),
+            fingerprint: relay.fingerprint.clone(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn take(&self) -> Vec<PassedOver> {
+        self.entries.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{RelayFlags, TestRelay};
+
+    fn relay(name: &str, flags: &str, bandwidth: u64) -> Relay {
+        TestRelay::new(name)
+            .fingerprint(&name.to_uppercase())
+            .address("1.2.3.4")
+            .or_port(443)
+            .flags(RelayFlags::from_string(flags))
+            .bandwidth(bandwidth)
+            .build()
+    }
+
+    #[test]
+    fn test_explanation_counts_candidates_and_rejections() {
+        let all = "Guard Stable Fast Running Valid";
+        let relays = vec![
+            relay("g1", all, 300),
+            relay("g2", all, 100),
+            relay("m1", "Stable Fast Running", 200),
+            relay("e1", "Exit Stable Fast Running", 400),
+            relay("e2", "Exit BadExit Stable Fast Running", 900),
+            relay("slow", "Exit Running", 50),
+        ];
+        let mut selector = RelaySelector::new(relays.clone());
+        selector.set_preferred_guards(vec!["G2".to_string()]);
+
+        let path = vec![relays[1].clone(), relays[2].clone(), relays[3].clone()];
+        let explanation = SelectionExplanation::new(&selector, Some(7), &path, Vec::new());
+        assert_eq!(explanation.circuit_id, Some(7));
+
+        let guard = &explanation.hops[0];
+        assert_eq!(guard.role, RelayRole::Guard);
+        assert!(guard.reason.starts_with("preferred guard"));
+        assert_eq!(guard.candidates, 2);
+        assert!((guard.weight - 0.25).abs() < 1e-9);
+
+        let exit = &explanation.hops[2];
+        assert_eq!(exit.role, RelayRole::Exit);
+        assert_eq!(exit.candidates, 1);
+        assert!(exit.reason.starts_with("bandwidth rank 1 of 1"));
+        assert_eq!(exit.rejected["already on the path"], 2);
+        assert_eq!(exit.rejected["missing Exit or flagged BadExit"], 2);
+        assert_eq!(exit.rejected["below the relay requirements"], 1);
+    }
+
+    #[test]
+    fn test_passed_over_log_records_only_when_enabled() {
+        let r = relay("e1", "Exit", 1);
+        let log = PassedOverLog::with_enabled(false);
+        log.note(&r, RelayRole::Exit, "off");
+        assert!(log.take().is_empty());
+
+        let log = PassedOverLog::with_enabled(true);
+        log.note(&r, RelayRole::Exit, "family conflict");
+        let entries = log.take();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].reason, "family conflict");
+    }
+}
@@ -0,0 +1,174 @@
+//! Checked byte accessors for parsing relay input
+//!
+//! Everything a relay sends us is attacker-controlled, and a panic in WASM
+//! aborts the whole instance rather than one circuit. Parse paths in
+//! `protocol` read fields through these helpers instead of indexing, so a
+//! short or lying cell becomes a `TorError::ProtocolError` naming the field.
+
+use crate::error::{Result, TorError};
+
+fn truncated(what: &str, end: usize, len: usize) -> TorError {
+    TorError::ProtocolError(format!(
+        "{} truncated: need {} bytes, have {}",
+        what, end, len
+    ))
+}
+
+/// `len` bytes of `data` starting at `start`
+pub(crate) fn slice<'a>(data: &'a [u8], start: usize, len: usize, what: &str) -> Result<&'a [u8]> {
+    let end = start
+        .checked_add(len)
+        .ok_or_else(|| truncated(what, usize::MAX, data.len()))?;
+    data.get(start..end)
+        .ok_or_else(|| truncated(what, end, data.len()))
+}
+
+/// Mutable `len` bytes of `data` starting at `start`
+pub(crate) fn slice_mut<'a>(
+    data: &'a mut [u8],
+    start: usize,
+    len: usize,
+    what: &str,
+) -> Result<&'a mut [u8]> {
+    let have = data.len();
+    let end = start
+        .checked_add(len)
+        .ok_or_else(|| truncated(what, usize::MAX, have))?;
+    data.get_mut(start..end)
+        .ok_or_else(|| truncated(what, end, have))
+}
+
+/// `N` bytes of `data` starting at `start`, copied out
+pub(crate) fn array<const N: usize>(data: &[u8], start: usize, what: &str) -> Result<[u8; N]> {
+    let mut out = [0u8; N];
+    out.copy_from_slice(slice(data, start, N, what)?);
+    Ok(out)
+}
+
+pub(crate) fn u8_at(data: &[u8], offset: usize, what: &str) -> Result<u8> {
+    Ok(array::<1>(data, offset, what)?[0])
+}
+
+/// Big-endian u16 at `offset`
+pub(crate) fn u16_at(data: &[u8], offset: usize, what: &str) -> Result<u16> {
+    array(data, offset, what).map(u16::from_be_bytes)
+}
+
+/// Big-endian u32 at `offset`
+pub(crate) fn u32_at(data: &[u8], offset: usize, what: &str) -> Result<u32> {
+    array(data, offset, what).map(u32::from_be_bytes)
+}
+
+/// Up to `n` leading bytes, for logging
+pub(crate) fn head(data: &[u8], n: usize) -> &[u8] {
+    &data[..n.min(data.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accessors_read_in_bounds() {
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05];
+        assert_eq!(u8_at(&data, 4, "x").unwrap(), 0x05);
+        assert_eq!(u16_at(&data, 1, "x").unwrap(), 0x0203);
+        assert_eq!(u32_at(&data, 0, "x").unwrap(), 0x01020304);
+        assert_eq!(slice(&data, 2, 3, "x").unwrap(), &[3, 4, 5]);
+        assert_eq!(array::<2>(&data, 3, "x").unwrap(), [4, 5]);
+        assert_eq!(head(&data, 10), &data);
+    }
+
+    #[test]
+    fn test_accessors_reject_out_of_bounds() {
+        let mut data = [0u8; 4];
+        assert!(u8_at(&data, 4, "x").is_err());
+        assert!(u16_at(&data, 3, "x").is_err());
+        assert!(u32_at(&data, 1, "x").is_err());
+        assert!(slice(&data, usize::MAX, 2, "x").is_err());
+        assert!(slice_mut(&mut data, 2, 3, "x").is_err());
+
+        let err = slice(&data, 2, 8, "EXTENDED2 HDATA").unwrap_err();
+        assert!(err.to_string().contains("EXTENDED2 HDATA"));
+    }
+
+    /// Every parser fed every truncation of a valid input (and a few lying
+    /// length fields) must return, never panic
+    #[test]
+    fn test_truncated_cells_never_panic() {
+        use crate::protocol::ntor::parse_created2_payload;
+        use crate::protocol::{
+            parse_connected, parse_resolved, Cell, CellCommand, CertsCell, Ed25519Certificate,
+            RelayCell, RelayCommand,
+        };
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Count panics raised on this thread; others go to the previous hook
+        let panics = Arc::new(AtomicUsize::new(0));
+        let previous: Arc<dyn Fn(&std::panic::PanicHookInfo<'_>) + Send + Sync> =
+            Arc::from(std::panic::take_hook());
+        let test_thread = std::thread::current().id();
+        {
+            let panics = panics.clone();
+            let previous = previous.clone();
+            std::panic::set_hook(Box::new(move |info| {
+                if std::thread::current().id() == test_thread {
+                    panics.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    previous(info);
+                }
+            }));
+        }
+
+        let relay = RelayCell::new(RelayCommand::Data, 3, vec![0xAB; 100])
+            .to_bytes()
+            .unwrap();
+        let cell = Cell::relay(9, relay.clone()).to_bytes().unwrap();
+        let mut lying_relay = relay.clone();
+        lying_relay[9..11].copy_from_slice(&u16::MAX.to_be_bytes());
+
+        let mut cert = vec![0x01, 0x04, 0, 0, 0, 0, 0x01];
+        cert.extend_from_slice(&[0x11; 32]);
+        cert.push(255); // claims far more extensions than present
+        cert.extend_from_slice(&[0x22; 100]);
+        let mut certs = vec![2, 4];
+        certs.extend_from_slice(&(cert.len() as u16).to_be_bytes());
+        certs.extend_from_slice(&cert);
+        certs.extend_from_slice(&[7, 0xFF, 0xFF]);
+
+        let resolved = [4, 4, 10, 0, 0, 1, 0, 0, 0, 60, 6, 16];
+        let connected = [0, 0, 0, 0, 6, 1, 2, 3, 4, 5, 6, 7, 8];
+
+        let outcome = std::panic::catch_unwind(|| {
+            for inputs in [
+                &cell[..],
+                &relay,
+                &lying_relay,
+                &cert,
+                &certs,
+                &resolved,
+                &connected,
+            ] {
+                for len in 0..=inputs.len() {
+                    let data = &inputs[..len];
+                    let _ = Cell::from_bytes(data);
+                    let _ = RelayCell::from_bytes(data);
+                    let _ = parse_resolved(data);
+                    let _ = parse_connected(data);
+                    let _ = CertsCell::parse(data);
+                    let _ = Ed25519Certificate::parse(data);
+                    let _ = parse_created2_payload(data);
+                }
+            }
+            assert!(RelayCell::from_bytes(&lying_relay).is_err());
+            assert_eq!(Cell::from_bytes(&cell).unwrap().command, CellCommand::Relay);
+        });
+
+        let _ = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| previous(info)));
+
+        assert!(outcome.is_ok());
+        assert_eq!(panics.load(Ordering::SeqCst), 0);
+    }
+}
@@ -3,7 +3,9 @@
 //! Implements the Tor cell format for communication with relays.
 //! Cells are the basic unit of communication in the Tor protocol.
 
+use super::bytes::{array, slice, u16_at, u32_at, u8_at};
 use crate::error::{Result, TorError};
+use crate::memory::{self, Subsystem};
 use std::io::Write;
 
 /// Cell command types
@@ -111,6 +113,7 @@ impl Cell {
 
     /// Serialize cell to bytes
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let _memory = memory::scope(Subsystem::Cells);
         let mut buf = Vec::with_capacity(Self::SIZE);
 
         // Circuit ID (4 bytes, big-endian)
@@ -134,19 +137,21 @@ impl Cell {
 
     /// Parse cell from bytes
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let _memory = memory::scope(Subsystem::Cells);
         if data.len() < Self::SIZE {
             return Err(TorError::ProtocolError("Cell too short".into()));
         }
 
         // Parse circuit ID (4 bytes, big-endian)
-        let circuit_id = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let circuit_id = u32_at(data, 0, "Cell circuit ID")?;
 
         // Parse command
-        let command = CellCommand::from_u8(data[4])
-            .ok_or_else(|| TorError::ProtocolError(format!("Unknown command: {}", data[4])))?;
+        let command_byte = u8_at(data, 4, "Cell command")?;
+        let command = CellCommand::from_u8(command_byte)
+            .ok_or_else(|| TorError::ProtocolError(format!("Unknown command: {}", command_byte)))?;
 
         // Parse payload
-        let payload = data[5..Self::SIZE].to_vec();
+        let payload = slice(data, 5, Self::PAYLOAD_SIZE, "Cell payload")?.to_vec();
 
         Ok(Self {
             circuit_id,
@@ -190,6 +195,24 @@ pub enum RelayCommand {
     Extend2 = 14,
     /// EXTENDED2 - circuit extended (current)
     Extended2 = 15,
+    /// ESTABLISH_INTRO - become an introduction point (service side)
+    EstablishIntro = 32,
+    /// ESTABLISH_RENDEZVOUS - make the last hop a rendezvous point
+    EstablishRendezvous = 33,
+    /// INTRODUCE1 - ask an introduction point to contact a service
+    Introduce1 = 34,
+    /// INTRODUCE2 - introduction forwarded to the service
+    Introduce2 = 35,
+    /// RENDEZVOUS1 - service joins the rendezvous point
+    Rendezvous1 = 36,
+    /// RENDEZVOUS2 - rendezvous completed, carries the service's handshake
+    Rendezvous2 = 37,
+    /// INTRO_ESTABLISHED - introduction point ready (service side)
+    IntroEstablished = 38,
+    /// RENDEZVOUS_ESTABLISHED - rendezvous point ready
+    RendezvousEstablished = 39,
+    /// INTRODUCE_ACK - introduction point relayed (or refused) INTRODUCE1
+    IntroduceAck = 40,
 }
 
 impl RelayCommand {
@@ -211,6 +234,15 @@ impl RelayCommand {
             13 => Some(RelayCommand::BeginDir),
             14 => Some(RelayCommand::Extend2),
             15 => Some(RelayCommand::Extended2),
+            32 => Some(RelayCommand::EstablishIntro),
+            33 => Some(RelayCommand::EstablishRendezvous),
+            34 => Some(RelayCommand::Introduce1),
+            35 => Some(RelayCommand::Introduce2),
+            36 => Some(RelayCommand::Rendezvous1),
+            37 => Some(RelayCommand::Rendezvous2),
+            38 => Some(RelayCommand::IntroEstablished),
+            39 => Some(RelayCommand::RendezvousEstablished),
+            40 => Some(RelayCommand::IntroduceAck),
             _ => None,
         }
     }
@@ -256,6 +288,7 @@ impl RelayCell {
 
     /// Serialize relay cell to bytes (for inclusion in Cell payload)
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let _memory = memory::scope(Subsystem::Cells);
         let mut buf = Vec::with_capacity(Cell::PAYLOAD_SIZE);
 
         // Relay command (1 byte)
@@ -291,25 +324,25 @@ impl RelayCell {
 
     /// Parse relay cell from bytes
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let _memory = memory::scope(Subsystem::Cells);
         if data.len() < 11 {
             return Err(TorError::ProtocolError("Relay cell too short".into()));
         }
 
-        let command = RelayCommand::from_u8(data[0]).ok_or_else(|| {
-            TorError::ProtocolError(format!("Unknown relay command: {}", data[0]))
+        let command_byte = u8_at(data, 0, "Relay command")?;
+        let command = RelayCommand::from_u8(command_byte).ok_or_else(|| {
+            TorError::ProtocolError(format!("Unknown relay command: {}", command_byte))
         })?;
 
-        let recognized = u16::from_be_bytes([data[1], data[2]]);
-        let stream_id = u16::from_be_bytes([data[3], data[4]]);
-        let digest = [data[5], data[6], data[7], data[8]];
-        let length = u16::from_be_bytes([data[9], data[10]]);
+        let recognized = u16_at(data, 1, "Relay recognized")?;
+        let stream_id = u16_at(data, 3, "Relay stream ID")?;
+        let digest = array(data, 5, "Relay digest")?;
+        let length = u16_at(data, 9, "Relay length")?;
 
-        let data_end = 11 + length as usize;
-        if data_end > data.len() {
-            return Err(TorError::ProtocolError("Relay cell data truncated".into()));
-        }
-
-        let cell_data = data[11..data_end].to_vec();
+        let cell_data = data
+            .get(11..11 + length as usize)
+            .ok_or_else(|| TorError::ProtocolError("Relay cell data truncated".into()))?
+            .to_vec();
 
         Ok(Self {
             command,
@@ -6,6 +6,7 @@
 //!
 //! Reference: tor-spec.txt Section 4.2
 
+use super::bytes::{array, slice, u16_at, u32_at, u8_at};
 use crate::error::{Result, TorError};
 use ed25519_dalek::{Signature, Verifier, VerifyingKey};
 // Note: sha2 may be needed for future RSA fingerprint computation
@@ -95,7 +96,7 @@ impl Ed25519Certificate {
             )));
         }
 
-        let version = data[0];
+        let version = u8_at(data, 0, "Certificate version")?;
         if version != 0x01 {
             return Err(TorError::CertificateError(format!(
                 "Unknown certificate version: {}",
@@ -103,15 +104,13 @@ impl Ed25519Certificate {
             )));
         }
 
-        let cert_type = data[1];
-        let expiration_hours = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
-        let cert_key_type = data[6];
-
-        let mut certified_key = [0u8; 32];
-        certified_key.copy_from_slice(&data[7..39]);
+        let cert_type = u8_at(data, 1, "Certificate type")?;
+        let expiration_hours = u32_at(data, 2, "Certificate expiration")?;
+        let cert_key_type = u8_at(data, 6, "Certified key type")?;
+        let certified_key = array(data, 7, "Certified key")?;
 
         // Parse extensions
-        let n_extensions = data[39];
+        let n_extensions = u8_at(data, 39, "Certificate extension count")?;
         let mut offset = 40;
 
         for _ in 0..n_extensions {
@@ -120,7 +119,7 @@ impl Ed25519Certificate {
                     "Extension header truncated".into(),
                 ));
             }
-            let ext_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
+            let ext_len = u16_at(data, offset, "Extension length")? as usize;
             offset += 4 + ext_len; // 2 bytes len + 1 byte type + 1 byte flags + ext_len
         }
 
@@ -134,11 +133,10 @@ impl Ed25519Certificate {
         }
 
         let sig_start = data.len() - 64;
-        let mut signature = [0u8; 64];
-        signature.copy_from_slice(&data[sig_start..]);
+        let signature = array(data, sig_start, "Certificate signature")?;
 
         // Raw data is everything except the signature
-        let raw_data = data[..sig_start].to_vec();
+        let raw_data = slice(data, 0, sig_start, "Certificate body")?.to_vec();
 
         Ok(Self {
             version,
@@ -192,7 +190,7 @@ impl CertsCell {
             return Err(TorError::CertificateError("Empty CERTS cell".into()));
         }
 
-        let n_certs = data[0] as usize;
+        let n_certs = u8_at(data, 0, "CERTS count")? as usize;
         let mut offset = 1;
         let mut certificates = Vec::with_capacity(n_certs);
 
@@ -204,8 +202,8 @@ impl CertsCell {
                 )));
             }
 
-            let cert_type = data[offset];
-            let cert_len = u16::from_be_bytes([data[offset + 1], data[offset + 2]]) as usize;
+            let cert_type = u8_at(data, offset, "CERTS certificate type")?;
+            let cert_len = u16_at(data, offset + 1, "CERTS certificate length")? as usize;
             offset += 3;
 
             if offset + cert_len > data.len() {
@@ -217,7 +215,7 @@ impl CertsCell {
                 )));
             }
 
-            let cert_data = data[offset..offset + cert_len].to_vec();
+            let cert_data = slice(data, offset, cert_len, "CERTS certificate")?.to_vec();
             offset += cert_len;
 
             certificates.push(Certificate {
@@ -2,12 +2,30 @@
 //!
 //! Builds Tor circuits by connecting to guard, extending to middle, and extending to exit.
 
+use super::bytes::{array, head, slice, slice_mut, u16_at, u32_at, u8_at};
 use super::certs::{CertificateVerifier, CertsCell};
 use super::crypto::CircuitKeys;
+use super::debug::{debug_protocol, log_key_material};
+use super::flow_control::CircuitFlowControl;
+use super::hs_ntor::{Aes256Ctr, HsHopKeys};
+use super::link_cache::{new_shared_link_cache, LinkInfo, LinkLease, NetinfoData, SharedLinkCache};
 use super::ntor::{derive_circuit_keys, NtorHandshake};
-use super::{Cell, CellCommand, Relay, RelayCell, RelayCommand, RelaySelector};
+use super::trace::{self, Direction};
+use super::{Cell, CellCommand, Relay, RelayCell, RelayCommand, RelayRole, RelaySelector};
+use crate::blocklist::SharedBlocklist;
+use crate::cancel::CancelToken;
+use crate::circuit_failures::{
+    new_shared_failure_stats, BuildReport, BuildStage, SharedFailureStats,
+};
+use crate::crypto_worker::{self, OnionOp};
 use crate::error::{Result, TorError};
+use crate::events::{self, TorEvent};
+use crate::guards::SharedGuardState;
+use crate::handshake_pool;
 use crate::network::{WasmTcpProvider, WasmTlsConnector, WasmTlsStream};
+use crate::path_explain::{PassedOverLog, SelectionExplanation};
+use crate::runtime::timer::now_ms;
+use crate::security_posture::{self, Downgrade};
 use aes::Aes128;
 use base64::{engine::general_purpose, Engine as _};
 use ctr::{
@@ -15,12 +33,98 @@ use ctr::{
     Ctr128BE,
 };
 use futures::io::{AsyncReadExt, AsyncWriteExt};
+use std::collections::VecDeque;
 use std::sync::Arc;
 use x25519_dalek::PublicKey;
 
 /// AES-128-CTR cipher type
 type Aes128Ctr = Ctr128BE<Aes128>;
 
+/// Longest path accepted by [`CircuitBuilder::build_circuit_through`]
+pub const MAX_CUSTOM_PATH_LEN: usize = 8;
+
+/// Exits tried on one guard → middle circuit when the middle answers
+/// failed extensions with TRUNCATED instead of tearing the circuit down
+const EXITS_PER_MIDDLE: usize = 3;
+
+/// Index of the innermost hop, the one relay cells are addressed to
+fn last_hop<T>(hops: &[T]) -> Result<usize> {
+    hops.len()
+        .checked_sub(1)
+        .ok_or_else(|| TorError::CircuitClosed("Circuit has no hops".into()))
+}
+
+/// Whether two relays share an IPv4 /16 (Tor never puts both on one circuit)
+///
+/// Never true on a local test network, where every relay shares a host.
+pub(crate) fn same_ipv4_slash16(a: &Relay, b: &Relay) -> bool {
+    #[cfg(feature = "test-interop")]
+    if crate::interop::local_network() {
+        return false;
+    }
+    match (a.address, b.address) {
+        (std::net::IpAddr::V4(x), std::net::IpAddr::V4(y)) => x.octets()[..2] == y.octets()[..2],
+        _ => false,
+    }
+}
+
+/// The Ed25519 identity a relay's (micro)descriptor lists, if any
+pub(crate) fn listed_ed25519_identity(relay: &Relay) -> Option<[u8; 32]> {
+    let encoded = relay.ed25519_identity.as_deref()?.trim_end_matches('=');
+    general_purpose::STANDARD_NO_PAD
+        .decode(encoded)
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// Check the Ed25519 identity a relay's CERTS chain verified against the
+/// one its descriptor lists
+///
+/// A relay whose chain didn't verify, or that verified another identity,
+/// isn't the relay the directory describes, whatever its RSA fingerprint
+/// says. An unverified identity proves nothing: anyone can copy the listed
+/// key into a forged certificate.
+fn check_ed25519_identity(listed: &[u8; 32], verified: Option<&[u8; 32]>) -> Result<()> {
+    match verified {
+        Some(verified) if verified == listed => Ok(()),
+        Some(verified) => Err(TorError::CertificateError(format!(
+            "Relay's Ed25519 identity {} doesn't match its descriptor's {}",
+            general_purpose::STANDARD_NO_PAD.encode(verified),
+            general_purpose::STANDARD_NO_PAD.encode(listed)
+        ))),
+        None => Err(TorError::CertificateError(format!(
+            "Relay's Ed25519 identity didn't verify; its descriptor lists {}",
+            general_purpose::STANDARD_NO_PAD.encode(listed)
+        ))),
+    }
+}
+
+/// Virtual hop from a rendezvous point to an onion service
+/// (rend-spec-v3 §4.2.2)
+///
+/// It has no relay of its own and uses the hs-ntor crypto: AES-256 and
+/// SHA3-256 running digests.
+struct ServiceHop {
+    forward_digest: sha3::Sha3_256,
+    backward_digest: sha3::Sha3_256,
+    forward_cipher: Aes256Ctr,
+    backward_cipher: Aes256Ctr,
+}
+
+impl ServiceHop {
+    fn new(keys: &HsHopKeys) -> Self {
+        use sha3::Digest;
+
+        Self {
+            forward_digest: sha3::Sha3_256::new().chain_update(keys.forward_digest),
+            backward_digest: sha3::Sha3_256::new().chain_update(keys.backward_digest),
+            forward_cipher: Aes256Ctr::new((&keys.forward_key).into(), (&[0u8; 16]).into()),
+            backward_cipher: Aes256Ctr::new((&keys.backward_key).into(), (&[0u8; 16]).into()),
+        }
+    }
+}
+
 /// A built Tor circuit
 pub struct Circuit {
     /// Circuit ID
@@ -49,6 +153,32 @@ pub struct Circuit {
 
     /// Backward AES-CTR ciphers (one per hop, maintained across cells)
     backward_ciphers: Vec<Aes128Ctr>,
+
+    /// Reason from the last RELAY_TRUNCATED, not yet taken. The circuit is
+    /// still usable up to the hop that sent it.
+    truncated: Option<u8>,
+
+    /// Keeps the guard's cached link handshake alive while this circuit's
+    /// connection is open
+    link_lease: Option<LinkLease>,
+
+    /// Circuit-level SENDME windows for DATA cells to and from the last hop
+    flow: CircuitFlowControl,
+
+    /// Cells one stream read for another, oldest first, until that stream
+    /// reads them
+    held_cells: VecDeque<RelayCell>,
+
+    /// Onion service joined at the last relay (a rendezvous point), if
+    /// any. It is the hop after the relays and where cells are addressed.
+    service: Option<ServiceHop>,
+
+    /// Whether `circuit_built` was posted; only then is `circuit_closed`
+    /// posted when the circuit goes away
+    announced: bool,
+
+    /// Why the circuit went away, reported in `circuit_closed`
+    close_reason: Option<String>,
 }
 
 impl Circuit {
@@ -73,11 +203,18 @@ impl Circuit {
             relays,
             keys: vec![keys],
             tls_stream: None,
-            created_at: (js_sys::Date::now() / 1000.0) as u64,
+            created_at: now_ms() / 1000,
             forward_digests: vec![forward_digest],
             backward_digests: vec![backward_digest],
             forward_ciphers: vec![forward_cipher],
             backward_ciphers: vec![backward_cipher],
+            truncated: None,
+            link_lease: None,
+            flow: CircuitFlowControl::new(),
+            held_cells: VecDeque::new(),
+            service: None,
+            announced: false,
+            close_reason: None,
         }
     }
 
@@ -107,18 +244,39 @@ impl Circuit {
             relays,
             keys: vec![keys],
             tls_stream: Some(stream),
-            created_at: (js_sys::Date::now() / 1000.0) as u64,
+            created_at: now_ms() / 1000,
             forward_digests: vec![forward_digest],
             backward_digests: vec![backward_digest],
             forward_ciphers: vec![forward_cipher],
             backward_ciphers: vec![backward_cipher],
+            truncated: None,
+            link_lease: None,
+            flow: CircuitFlowControl::new(),
+            held_cells: VecDeque::new(),
+            service: None,
+            announced: false,
+            close_reason: None,
         }
     }
 
+    /// Post `circuit_built` for the finished circuit
+    pub fn announce_built(mut self) -> Self {
+        self.announced = true;
+        events::post(TorEvent::CircuitBuilt {
+            circuit_id: self.id,
+            path: self.relays.iter().map(|r| r.nickname.clone()).collect(),
+        });
+        self
+    }
+
+    /// Record why the circuit is going away; the first reason given wins
+    pub fn set_close_reason(&mut self, reason: impl Into<String>) {
+        self.close_reason.get_or_insert_with(|| reason.into());
+    }
+
     /// Get circuit age in seconds
     pub fn age(&self) -> u64 {
-        let now = (js_sys::Date::now() / 1000.0) as u64;
-        now.saturating_sub(self.created_at)
+        (now_ms() / 1000).saturating_sub(self.created_at)
     }
 
     /// Send a cell through the circuit
@@ -137,21 +295,25 @@ impl Circuit {
 
             // Extract the payload (skip CircID(4) + Cmd(1) = 5 bytes)
             let payload_start = 5;
-            let payload = &mut cell_bytes[payload_start..];
+            let payload = cell_bytes
+                .get_mut(payload_start..)
+                .ok_or_else(|| TorError::Internal("Serialized cell has no payload".into()))?;
 
             // Calculate running digest over the cell (with digest field = 0)
             // The digest is at bytes 5-8 of the payload (RelayCmd(1) + Recognized(2) + StreamID(2) + Digest(4))
             use sha1::Digest;
 
             // Verify digest field is zeroed before hashing
-            log::info!(
-                "    📊 Pre-hash payload digest field: {:02x?}",
-                &payload[5..9]
-            );
+            if debug_protocol() {
+                log::info!(
+                    "    📊 Pre-hash payload digest field: {:02x?}",
+                    slice(payload, 5, 4, "Relay digest")?
+                );
+            }
             log::info!("    📊 Payload length: {} bytes", payload.len());
 
             // Extract the actual data length from bytes 9-10 (after Cmd, Recognized, StreamID, Digest)
-            let data_length = u16::from_be_bytes([payload[9], payload[10]]) as usize;
+            let data_length = u16_at(payload, 9, "Relay length")? as usize;
 
             log::info!("    📊 Data length field: {} bytes", data_length);
             log::info!(
@@ -162,7 +324,7 @@ impl Circuit {
             // CRITICAL: Use the LAST hop's digest - cells are always destined for the innermost hop
             // For EXTEND2 to exit, that's the middle hop (which will forward CREATE2 to exit)
             // For RELAY_DATA, that's the exit hop
-            let hop_idx = self.forward_digests.len() - 1;
+            let hop_idx = last_hop(&self.forward_digests)?;
             log::info!(
                 "    📊 Using hop {}'s digest (of {} hops)",
                 hop_idx,
@@ -177,10 +339,12 @@ impl Circuit {
             let digest_result = self.forward_digests[hop_idx].clone().finalize();
 
             // Insert first 4 bytes of digest into the cell at position 5 (after Cmd + Recognized + StreamID)
-            payload[5..9].copy_from_slice(&digest_result[..4]);
+            slice_mut(payload, 5, 4, "Relay digest")?.copy_from_slice(&digest_result[..4]);
 
-            log::info!("    ✓ Digest calculated: {:02x?}", &digest_result[..4]);
-            log::info!("    ✓ Updated payload header: {:02x?}", &payload[..15]);
+            if debug_protocol() {
+                log::info!("    ✓ Digest calculated: {:02x?}", &digest_result[..4]);
+                log::info!("    ✓ Updated payload header: {:02x?}", head(payload, 15));
+            }
 
             // Now apply forward encryption with persistent ciphers
             // Encrypt in reverse order: last hop first, guard last
@@ -193,10 +357,12 @@ impl Circuit {
             }
 
             log::info!("    ✓ RELAY cell encrypted");
-            log::info!(
-                "    ✓ Encrypted header (first 15 bytes): {:02x?}",
-                &payload[..15]
-            );
+            if debug_protocol() {
+                log::info!(
+                    "    ✓ Encrypted header (first 15 bytes): {:02x?}",
+                    head(payload, 15)
+                );
+            }
         }
 
         stream
@@ -214,6 +380,11 @@ impl Circuit {
 
     /// Receive a cell from the circuit
     pub async fn receive_cell(&mut self) -> Result<Cell> {
+        Ok(self.receive_cell_from_hop().await?.0)
+    }
+
+    /// Receive a cell, along with the hop that recognized it (RELAY cells only)
+    async fn receive_cell_from_hop(&mut self) -> Result<(Cell, Option<usize>)> {
         loop {
             let stream = self
                 .tls_stream
@@ -238,41 +409,43 @@ impl Circuit {
 
             // DESTROY cell = circuit torn down by relay
             if cell.command == CellCommand::Destroy {
-                let reason = if cell.payload.is_empty() {
-                    0
-                } else {
-                    cell.payload[0]
-                };
-                return Err(TorError::CircuitClosed(format!(
-                    "Circuit destroyed by relay (reason: {})",
-                    reason
-                )));
+                let reason = cell.payload.first().copied().unwrap_or(0);
+                let error = TorError::circuit_destroyed(reason);
+                self.set_close_reason(error.to_string());
+                return Err(error);
             }
 
             // For RELAY cells, apply per-layer onion decryption (tor-spec §5.5.2)
+            let mut origin_hop = None;
             if cell.command == CellCommand::Relay || cell.command == CellCommand::RelayEarly {
                 log::debug!("    Decrypting RELAY cell (per-layer)");
 
                 for (i, cipher) in self.backward_ciphers.iter_mut().enumerate() {
                     cipher.apply_keystream(&mut cell.payload);
-                    let recognized = u16::from_be_bytes([cell.payload[1], cell.payload[2]]);
+                    let recognized = u16_at(&cell.payload, 1, "Relay recognized")?;
                     if recognized == 0 {
                         log::debug!("    ✓ RELAY cell recognized at hop {}", i);
+                        origin_hop = Some(i);
                         break;
                     }
                 }
             }
 
-            return Ok(cell);
+            return Ok((cell, origin_hop));
         }
     }
 
     /// Extend circuit to a new relay
     pub async fn extend_to(&mut self, relay: &Relay) -> Result<()> {
+        if self.service.is_some() {
+            return Err(TorError::InvalidState(
+                "Cannot extend a circuit joined to an onion service".into(),
+            ));
+        }
         log::info!("  📡 Extending circuit {} to {}", self.id, relay.nickname);
 
         // Generate ephemeral keys for ntor
-        let handshake = NtorHandshake::new();
+        let handshake = handshake_pool::handshake_for(&relay.fingerprint);
         let client_public = handshake.client_public_key();
 
         // Get relay's identity fingerprint (SHA-1, 20 bytes)
@@ -313,7 +486,7 @@ impl Circuit {
 
         log::debug!(
             "    Using relay fingerprint: {:?}",
-            &relay_identity_fingerprint[..8]
+            head(&relay_identity_fingerprint, 8)
         );
         log::debug!("    Using ntor onion key: (32 bytes)");
 
@@ -326,16 +499,18 @@ impl Circuit {
         )?;
 
         log::info!("    📦 EXTEND2 payload: {} bytes", extend2_data.len());
-        log::info!(
-            "       NSPEC + Link specs: {:02x?}",
-            &extend2_data[..40.min(extend2_data.len())]
-        );
-        if extend2_data.len() > 40 {
-            log::info!("       Handshake type+len: {:02x?}", &extend2_data[40..44]);
+        if debug_protocol() {
             log::info!(
-                "       Handshake data (84 bytes): starts {:02x?}...",
-                &extend2_data[44..52.min(extend2_data.len())]
+                "       NSPEC + Link specs: {:02x?}",
+                head(&extend2_data, 40)
             );
+            if let Some(handshake) = extend2_data.get(40..) {
+                log::info!("       Handshake type+len: {:02x?}", head(handshake, 4));
+                log::info!(
+                    "       Handshake data (84 bytes): starts {:02x?}...",
+                    head(handshake.get(4..).unwrap_or_default(), 8)
+                );
+            }
         }
 
         // Create RELAY_EXTEND2 cell
@@ -348,9 +523,9 @@ impl Circuit {
         // Wrap in RELAY_EARLY cell (circuit extensions MUST use RELAY_EARLY)
         let relay_bytes = relay_cell.to_bytes()?;
         log::info!("    RELAY_EXTEND2 cell size: {} bytes", relay_bytes.len());
-        log::info!("    RELAY_EXTEND2 header: {:02x?}", &relay_bytes[..15]);
-        log::info!("      Cmd={} (EXTEND2), Recognized={:02x?}, StreamID={:02x?}, Digest={:02x?}, Len={:02x?}",
-            relay_bytes[0], &relay_bytes[1..3], &relay_bytes[3..5], &relay_bytes[5..9], &relay_bytes[9..11]);
+        if debug_protocol() {
+            log::info!("    RELAY_EXTEND2 header: {:02x?}", head(&relay_bytes, 15));
+        }
 
         let cell = Cell::new(self.id, CellCommand::RelayEarly, relay_bytes);
         log::info!(
@@ -364,7 +539,7 @@ impl Circuit {
 
         // Wait for EXTENDED2
         log::info!("    📥 Waiting for EXTENDED2...");
-        let response = self.receive_cell().await?;
+        let (response, origin_hop) = self.receive_cell_from_hop().await?;
 
         log::info!("    ✅ Received response: Cmd={:?}", response.command);
 
@@ -372,7 +547,10 @@ impl Circuit {
         if response.command != CellCommand::Relay && response.command != CellCommand::RelayEarly {
             if response.command == CellCommand::Destroy {
                 log::error!("    ❌ Guard sent DESTROY");
-                log::error!("       Reason byte: {}", response.payload[0]);
+                log::error!(
+                    "       Reason byte: {}",
+                    response.payload.first().copied().unwrap_or(0)
+                );
                 log::error!("       This means the guard rejected our EXTEND2");
             }
             return Err(TorError::CircuitBuildFailed(format!(
@@ -384,6 +562,18 @@ impl Circuit {
         // Parse RELAY cell
         let relay_response = RelayCell::from_bytes(&response.payload)?;
 
+        // TRUNCATED: the last hop couldn't reach the relay, but the circuit up
+        // to the hop that answered is intact and can be extended elsewhere
+        if relay_response.command == RelayCommand::Truncated {
+            let hop = origin_hop.unwrap_or(self.relays.len() - 1);
+            let reason = relay_response.data.first().copied().unwrap_or(0);
+            self.note_truncated(hop, reason);
+            return Err(TorError::CircuitBuildFailed(format!(
+                "Extension to {} failed: circuit truncated (reason: {})",
+                relay.nickname, reason
+            )));
+        }
+
         if relay_response.command != RelayCommand::Extended2 {
             return Err(TorError::CircuitBuildFailed(format!(
                 "Expected EXTENDED2, got {:?}",
@@ -394,20 +584,15 @@ impl Circuit {
         // Parse server response from EXTENDED2
         // EXTENDED2 data format: HLEN (2 bytes) || HDATA (HLEN bytes)
         // For ntor: HDATA = Y (32 bytes) || AUTH (32 bytes) = 64 bytes
-        if relay_response.data.len() < 2 {
-            return Err(TorError::ProtocolError(
-                "EXTENDED2 response too short".into(),
-            ));
-        }
-        let hlen = u16::from_be_bytes([relay_response.data[0], relay_response.data[1]]) as usize;
+        let hlen = u16_at(&relay_response.data, 0, "EXTENDED2 HLEN")? as usize;
         log::info!("    EXTENDED2 HLEN: {} (expected 64)", hlen);
-        if hlen < 64 || relay_response.data.len() < 2 + hlen {
+        if hlen < 64 {
             return Err(TorError::ProtocolError(format!(
                 "EXTENDED2 response too short: {} bytes",
                 hlen
             )));
         }
-        let hdata = &relay_response.data[2..2 + hlen];
+        let hdata = slice(&relay_response.data, 2, hlen, "EXTENDED2 HDATA")?;
         let (server_public, server_auth) = super::ntor::parse_created2_payload(hdata)?;
 
         // Complete ntor handshake and derive keys
@@ -420,7 +605,23 @@ impl Circuit {
 
         // Derive proper circuit keys using HKDF
         let keys = derive_circuit_keys(&forward_secret)?;
+        self.add_hop(relay.clone(), keys);
+
+        log::info!(
+            "  ✅ Extended to {} (now {} hops)",
+            relay.nickname,
+            self.relays.len()
+        );
+
+        Ok(())
+    }
 
+    /// Append a hop whose keys are already negotiated
+    ///
+    /// [`Circuit::extend_to`] calls this once the EXTENDED2 reply checks
+    /// out; it is public for circuits assembled without a network
+    /// (benchmarks, tooling).
+    pub fn add_hop(&mut self, relay: Relay, keys: CircuitKeys) {
         // Initialize ciphers for the new hop
         let forward_cipher = Aes128Ctr::new((&keys.forward_key).into(), (&keys.forward_iv).into());
         let backward_cipher =
@@ -434,25 +635,119 @@ impl Circuit {
         backward_digest.update(&keys.backward_digest);
 
         // Add relay, keys, ciphers, and digests to circuit
-        self.relays.push(relay.clone());
+        self.relays.push(relay);
         self.keys.push(keys);
         self.forward_ciphers.push(forward_cipher);
         self.backward_ciphers.push(backward_cipher);
         self.forward_digests.push(forward_digest);
         self.backward_digests.push(backward_digest);
+        self.flow = CircuitFlowControl::new();
+    }
 
+    /// Get the number of hops in the circuit
+    pub fn hop_count(&self) -> usize {
+        self.relays.len()
+    }
+
+    /// Join the onion service that answered at the last relay (the
+    /// rendezvous point) as a virtual hop with `keys` from hs-ntor
+    ///
+    /// Relay cells are addressed to the service from now on.
+    pub fn add_service_hop(&mut self, keys: &HsHopKeys) {
+        self.service = Some(ServiceHop::new(keys));
+        self.flow = CircuitFlowControl::new();
+        log::info!("  🧅 Circuit {} joined an onion service", self.id);
+    }
+
+    /// Whether the circuit ends at an onion service
+    pub fn has_service_hop(&self) -> bool {
+        self.service.is_some()
+    }
+
+    /// Hops cells can be addressed to, the service hop included
+    fn hop_total(&self) -> usize {
+        self.forward_digests.len() + usize::from(self.service.is_some())
+    }
+
+    /// Take the reason from a RELAY_TRUNCATED received since the last call
+    ///
+    /// `Some` means hops were dropped: the circuit now ends at the relay
+    /// that sent TRUNCATED and can be extended again.
+    pub fn take_truncated(&mut self) -> Option<u8> {
+        self.truncated.take()
+    }
+
+    /// Forget every hop after `hop` (keys, ciphers and digests included)
+    fn drop_hops_after(&mut self, hop: usize) {
+        let keep = hop + 1;
+        self.relays.truncate(keep);
+        self.keys.truncate(keep);
+        self.forward_digests.truncate(keep);
+        self.backward_digests.truncate(keep);
+        self.forward_ciphers.truncate(keep);
+        self.backward_ciphers.truncate(keep);
+        self.service = None;
+        self.flow = CircuitFlowControl::new();
+    }
+
+    /// Record a RELAY_TRUNCATED from `hop`: everything past it is gone
+    fn note_truncated(&mut self, hop: usize, reason: u8) {
+        log::warn!(
+            "  ✂️ Circuit {} truncated at hop {} (reason: {}), {} hop(s) dropped",
+            self.id,
+            hop,
+            reason,
+            self.relays.len().saturating_sub(hop + 1)
+        );
+        self.drop_hops_after(hop);
+        self.truncated = Some(reason);
+    }
+
+    /// Drop the last hop with RELAY_TRUNCATE (tor-spec §5.4), keeping the
+    /// rest of the circuit so it can be extended to a different relay
+    ///
+    /// Returns the reason carried by the relay's TRUNCATED reply. Any other
+    /// cells arriving first (late data for streams on the dropped hop) are
+    /// discarded, so close streams before truncating.
+    pub async fn truncate(&mut self) -> Result<u8> {
+        if self.relays.len() < 2 {
+            return Err(TorError::Internal(
+                "Cannot truncate a circuit with fewer than 2 hops".into(),
+            ));
+        }
+        let hop = self.relays.len() - 2;
         log::info!(
-            "  ✅ Extended to {} (now {} hops)",
-            relay.nickname,
-            self.relays.len()
+            "  ✂️ Truncating circuit {} after hop {} ({})",
+            self.id,
+            hop,
+            self.relays[hop].nickname
         );
 
-        Ok(())
+        // TRUNCATE goes to the last hop we keep
+        let cell = RelayCell::new(RelayCommand::Truncate, 0, Vec::new());
+        self.send_relay_cell_to(hop, &cell).await?;
+
+        loop {
+            let reply = self.receive_relay_cell().await?;
+            if reply.command == RelayCommand::Truncated && reply.stream_id == 0 {
+                // receive_relay_cell already dropped the hops
+                return Ok(self.truncated.take().unwrap_or(0));
+            }
+            log::debug!(
+                "    Discarding {:?} on stream {} while truncating",
+                reply.command,
+                reply.stream_id
+            );
+        }
     }
 
-    /// Get the number of hops in the circuit
-    pub fn hop_count(&self) -> usize {
-        self.relays.len()
+    /// Swap the last hop for `relay`: TRUNCATE, then EXTEND2
+    ///
+    /// Cheaper than rebuilding when only the exit failed. On error the
+    /// circuit may be left shorter than before; check `hop_count`.
+    pub async fn replace_exit(&mut self, relay: &Relay) -> Result<()> {
+        self.truncate().await?;
+        self.extend_to(relay).await
     }
 
     /// Check if the circuit still has an active TLS stream to the guard
@@ -460,10 +755,178 @@ impl Circuit {
         self.tls_stream.is_some()
     }
 
+    /// Tear down the circuit: send DESTROY (reason NONE) to the guard and
+    /// close the TLS link, which also closes the underlying transport.
+    ///
+    /// Clients send reason NONE so the relay learns nothing about why the
+    /// circuit went away. Calling this on an already closed circuit is a no-op.
+    pub async fn destroy(&mut self) -> Result<()> {
+        let mut stream = match self.tls_stream.take() {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+        self.link_lease = None;
+        self.set_close_reason("destroyed by client");
+
+        log::info!("  💥 Destroying circuit {}", self.id);
+
+        // First payload byte is the reason; all zeroes means NONE
+        let payload = vec![0u8; Cell::PAYLOAD_SIZE];
+        let sent = match Cell::new(self.id, CellCommand::Destroy, payload).to_bytes() {
+            Ok(bytes) => match stream.write_all(&bytes).await {
+                Ok(()) => stream
+                    .flush()
+                    .await
+                    .map_err(|e| TorError::Network(format!("Failed to flush DESTROY: {}", e))),
+                Err(e) => Err(TorError::Network(format!("Failed to send DESTROY: {}", e))),
+            },
+            Err(e) => Err(e),
+        };
+
+        // Close the link even if DESTROY could not be delivered
+        let closed = stream
+            .close()
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to close TLS link: {}", e)));
+
+        sent.and(closed)
+    }
+
     /// Send a RELAY cell through the circuit (with proper digest and encryption)
     /// Used for RELAY_BEGIN, RELAY_DATA, etc.
+    ///
+    /// DATA cells count against the circuit's package window; sending one
+    /// while it is closed fails, so check [`Circuit::can_package`] first.
     pub async fn send_relay_cell(&mut self, relay_cell: &RelayCell) -> Result<()> {
-        use sha1::Digest;
+        let hop_idx = last_hop(&self.forward_digests)? + usize::from(self.service.is_some());
+        if relay_cell.command == RelayCommand::Data {
+            self.flow.on_send().map_err(|_| {
+                TorError::Stream(format!(
+                    "Circuit {} send window exhausted, waiting for SENDME",
+                    self.id
+                ))
+            })?;
+        }
+        self.send_relay_cell_to(hop_idx, relay_cell).await
+    }
+
+    /// Digest and onion encrypt a RELAY cell for the last hop, as
+    /// [`Circuit::send_relay_cell`] does, without sending it
+    ///
+    /// Flow control is left to the caller; this is the encryption half on
+    /// its own, for cells that leave some other way (benchmarks, tooling).
+    pub async fn seal_relay_cell(&mut self, relay_cell: &RelayCell) -> Result<Cell> {
+        let hop_idx = last_hop(&self.forward_digests)? + usize::from(self.service.is_some());
+        self.seal_relay_cell_for(hop_idx, relay_cell).await
+    }
+
+    /// Whether the circuit-level window allows another DATA cell
+    pub fn can_package(&self) -> bool {
+        self.flow.can_send()
+    }
+
+    /// Wait for a circuit SENDME to reopen a closed package window
+    ///
+    /// Returns `None` once the window is open, or the first cell read that
+    /// is not a circuit SENDME, for the caller to handle.
+    pub async fn receive_until_packagable(&mut self) -> Result<Option<RelayCell>> {
+        while !self.flow.can_send() {
+            let (hop_idx, relay_cell) = self.read_relay_cell().await?;
+            if !self.circuit_flow(hop_idx, &relay_cell).await? {
+                return Ok(Some(relay_cell));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Circuit-level flow control state
+    pub fn flow_control(&self) -> &CircuitFlowControl {
+        &self.flow
+    }
+
+    /// Keep `cell`, read by another stream, for the stream it is addressed to
+    ///
+    /// At most a circuit window's worth is held, more than a well-behaved
+    /// exit sends unacknowledged; cells past that are dropped.
+    pub fn hold_cell(&mut self, cell: RelayCell) {
+        if self.held_cells.len() >= CircuitFlowControl::INITIAL_WINDOW as usize {
+            log::warn!(
+                "Circuit {}: dropping {:?} for stream {}, too many cells held",
+                self.id,
+                cell.command,
+                cell.stream_id
+            );
+            return;
+        }
+        self.held_cells.push_back(cell);
+    }
+
+    /// Oldest cell held for `stream_id`, if any
+    pub fn take_held_cell(&mut self, stream_id: u16) -> Option<RelayCell> {
+        let pos = self
+            .held_cells
+            .iter()
+            .position(|cell| cell.stream_id == stream_id)?;
+        self.held_cells.remove(pos)
+    }
+
+    /// Forget the cells held for `stream_id`, once it is closed
+    pub fn discard_held_cells(&mut self, stream_id: u16) {
+        self.held_cells.retain(|cell| cell.stream_id != stream_id);
+    }
+
+    /// Apply circuit-level flow control (tor-spec §7.4) to a cell from
+    /// `hop_idx`, whose digest has been taken
+    ///
+    /// Every 100th DATA cell from the last hop is answered with an
+    /// authenticated (v1) circuit SENDME. Returns true for a circuit SENDME,
+    /// which reopens our package window and is consumed here.
+    async fn circuit_flow(&mut self, hop_idx: usize, cell: &RelayCell) -> Result<bool> {
+        if hop_idx + 1 != self.hop_total() {
+            return Ok(false);
+        }
+        match cell.command {
+            RelayCommand::Sendme if cell.stream_id == 0 => {
+                self.flow.on_sendme_received();
+                Ok(true)
+            }
+            RelayCommand::Data if self.flow.on_receive() => {
+                // v1 SENDME (prop289): the running digest after the cell
+                // that emptied the window
+                let sendme = CircuitFlowControl::sendme_v1_payload(&self.backward_digest(hop_idx));
+                self.flow.on_sendme_sent();
+                log::debug!("    📤 Circuit {} SENDME to hop {}", self.id, hop_idx);
+                self.send_relay_cell_to(hop_idx, &RelayCell::new(RelayCommand::Sendme, 0, sendme))
+                    .await?;
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Send a RELAY cell addressed to `hop_idx` rather than the last hop
+    ///
+    /// Only that hop's digest and the ciphers up to it are used, since hops
+    /// past it never see the cell.
+    async fn send_relay_cell_to(&mut self, hop_idx: usize, relay_cell: &RelayCell) -> Result<()> {
+        let cell = self.seal_relay_cell_for(hop_idx, relay_cell).await?;
+        self.write_cell(&cell).await
+    }
+
+    /// Serialize `relay_cell`, set its digest and onion encrypt it for
+    /// `hop_idx`
+    async fn seal_relay_cell_for(
+        &mut self,
+        hop_idx: usize,
+        relay_cell: &RelayCell,
+    ) -> Result<Cell> {
+        if hop_idx >= self.hop_total() {
+            return Err(TorError::Internal(format!(
+                "No hop {} on a {}-hop circuit",
+                hop_idx,
+                self.hop_total()
+            )));
+        }
 
         log::info!(
             "    📤 send_relay_cell: {:?} stream={} data_len={}",
@@ -475,10 +938,9 @@ impl Circuit {
         // Serialize relay cell to bytes (509 bytes, with digest field initially zero)
         let mut payload = relay_cell.to_bytes()?;
         log::info!("    📊 Serialized payload: {} bytes", payload.len());
-        log::info!(
-            "    📊 Payload header: {:02x?}",
-            &payload[..15.min(payload.len())]
-        );
+        if debug_protocol() {
+            log::info!("    📊 Payload header: {:02x?}", head(&payload, 15));
+        }
 
         // Ensure the payload is exactly 509 bytes (RELAY cell payload size)
         if payload.len() != 509 {
@@ -489,40 +951,60 @@ impl Circuit {
             payload.resize(509, 0);
         }
 
+        let digest = self.set_forward_digest(hop_idx, &mut payload)?;
+        trace::record(self.id, Direction::Sent, hop_idx, relay_cell, digest, None);
+        Ok(self.seal_payload(hop_idx, payload).await)
+    }
+
+    /// Fill in the digest field of a serialized relay payload from
+    /// `hop_idx`'s running forward digest, returning it
+    fn set_forward_digest(&mut self, hop_idx: usize, payload: &mut [u8]) -> Result<[u8; 4]> {
+        use sha1::Digest;
+
         // Zero out the digest field (bytes 5-8) before calculating
-        payload[5..9].copy_from_slice(&[0, 0, 0, 0]);
-        log::info!("    📊 Digest zeroed: {:02x?}", &payload[5..9]);
+        slice_mut(payload, 5, 4, "Relay digest")?.fill(0);
 
-        // Calculate digest using the LAST hop's running digest (cells go to exit)
-        let hop_idx = self.forward_digests.len() - 1;
+        // Calculate digest using the target hop's running digest
         log::info!(
             "    📊 Using hop {}'s digest (of {} hops)",
             hop_idx,
-            self.forward_digests.len()
+            self.hop_total()
         );
 
-        // Update running digest with full payload
-        self.forward_digests[hop_idx].update(&payload);
-
-        // Get first 4 bytes of digest
-        let digest_result = self.forward_digests[hop_idx].clone().finalize();
-        payload[5..9].copy_from_slice(&digest_result[..4]);
-
-        log::info!("    ✓ Digest calculated: {:02x?}", &digest_result[..4]);
+        // Update running digest with full payload, then take its first 4 bytes
+        let mut digest = [0u8; 4];
+        if let Some(running) = self.forward_digests.get_mut(hop_idx) {
+            running.update(&*payload);
+            digest.copy_from_slice(&running.clone().finalize()[..4]);
+        } else if let Some(service) = self.service.as_mut() {
+            service.forward_digest.update(&*payload);
+            digest.copy_from_slice(&service.forward_digest.clone().finalize()[..4]);
+        }
+        slice_mut(payload, 5, 4, "Relay digest")?.copy_from_slice(&digest);
 
-        // Encrypt with all hop ciphers in reverse order (exit first, guard last)
-        log::info!(
-            "    🔐 Encrypting with {} hop ciphers",
-            self.forward_ciphers.len()
-        );
-        for cipher in self.forward_ciphers.iter_mut().rev() {
-            cipher.apply_keystream(&mut payload);
+        if debug_protocol() {
+            log::info!("    ✓ Digest calculated: {:02x?}", digest);
         }
-        log::info!("    ✓ Encrypted header: {:02x?}", &payload[..15]);
+        #[cfg(feature = "research")]
+        crate::research::observe(self.id, Direction::Sent, hop_idx, payload);
+        Ok(digest)
+    }
 
-        // Wrap in RELAY cell and send
-        let cell = Cell::relay(self.id, payload);
+    /// Onion encrypt a relay payload whose digest is set and wrap it in a
+    /// RELAY cell
+    async fn seal_payload(&mut self, hop_idx: usize, mut payload: Vec<u8>) -> Cell {
+        // Encrypt with the ciphers up to the target hop in reverse order
+        // (innermost first, guard last)
+        log::info!("    🔐 Encrypting with {} hop ciphers", hop_idx + 1);
+        self.encrypt_onion(hop_idx, &mut payload).await;
+        if debug_protocol() {
+            log::info!("    ✓ Encrypted header: {:02x?}", head(&payload, 15));
+        }
+        Cell::relay(self.id, payload)
+    }
 
+    /// Write an encrypted RELAY cell to the guard
+    async fn write_cell(&mut self, cell: &Cell) -> Result<()> {
         let stream = self
             .tls_stream
             .as_mut()
@@ -544,8 +1026,114 @@ impl Circuit {
         Ok(())
     }
 
+    /// Send a relay payload as is to `hop` (0 = guard), for protocol
+    /// experiments (`research` feature)
+    ///
+    /// `bytes` is the plaintext relay payload: command, recognized, stream
+    /// ID, digest, length, data. It is zero-padded to 509 bytes; the digest
+    /// field is overwritten with the hop's running digest, so the hop
+    /// accepts the cell whatever its command. Nothing else is checked.
+    #[cfg(feature = "research")]
+    pub async fn send_raw_cell(&mut self, bytes: &[u8], hop: usize) -> Result<()> {
+        if hop >= self.forward_digests.len() {
+            return Err(TorError::Internal(format!(
+                "No hop {} on a {}-hop circuit",
+                hop,
+                self.forward_digests.len()
+            )));
+        }
+        if bytes.len() > 509 {
+            return Err(TorError::ProtocolError(format!(
+                "Raw relay payload of {} bytes exceeds 509",
+                bytes.len()
+            )));
+        }
+        let mut payload = bytes.to_vec();
+        payload.resize(509, 0);
+        self.set_forward_digest(hop, &mut payload)?;
+        let cell = self.seal_payload(hop, payload).await;
+        self.write_cell(&cell).await
+    }
+
+    /// Onion encrypt `payload` for hops `..=hop_idx`, in the crypto worker
+    /// when one is running
+    async fn encrypt_onion(&mut self, hop_idx: usize, payload: &mut [u8]) {
+        // The onion service's layer goes on first, inside every relay's
+        let relay_hops = (hop_idx + 1).min(self.forward_ciphers.len());
+        if hop_idx == self.forward_ciphers.len() {
+            if let Some(service) = self.service.as_mut() {
+                service.forward_cipher.apply_keystream(payload);
+            }
+        }
+        let ciphers = &mut self.forward_ciphers[..relay_hops];
+        if crypto_worker::enabled() {
+            let layers = crypto_worker::layers_for(OnionOp::Encrypt, &self.keys, ciphers);
+            if crypto_worker::run(OnionOp::Encrypt, &layers, payload)
+                .await
+                .is_some()
+            {
+                crypto_worker::advance(ciphers, payload.len());
+                return;
+            }
+        }
+        for cipher in ciphers.iter_mut().rev() {
+            cipher.apply_keystream(payload);
+        }
+    }
+
+    /// Peel onion layers off `payload` one at a time until a hop recognizes
+    /// it (tor-spec §5.5.2), in the crypto worker when one is running
+    ///
+    /// Only the ciphers of the hops peeled move on. A cell no relay
+    /// recognizes may still come from the onion service hop.
+    async fn decrypt_onion(&mut self, payload: &mut [u8]) -> Result<Option<usize>> {
+        if let Some(hop) = self.decrypt_relay_layers(payload).await? {
+            return Ok(Some(hop));
+        }
+        if let Some(service) = self.service.as_mut() {
+            service.backward_cipher.apply_keystream(payload);
+            if u16_at(payload, 1, "Relay recognized")? == 0 {
+                return Ok(Some(self.backward_ciphers.len()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Peel the relays' layers off `payload`, as [`Circuit::decrypt_onion`]
+    async fn decrypt_relay_layers(&mut self, payload: &mut [u8]) -> Result<Option<usize>> {
+        if crypto_worker::enabled() {
+            let layers =
+                crypto_worker::layers_for(OnionOp::Decrypt, &self.keys, &self.backward_ciphers);
+            if let Some(hop) = crypto_worker::run(OnionOp::Decrypt, &layers, payload).await {
+                let peeled = hop.map_or(self.backward_ciphers.len(), |h| h + 1);
+                crypto_worker::advance(&mut self.backward_ciphers[..peeled], payload.len());
+                return Ok(hop);
+            }
+        }
+        for (i, cipher) in self.backward_ciphers.iter_mut().enumerate() {
+            cipher.apply_keystream(payload);
+            if u16_at(payload, 1, "Relay recognized")? == 0 {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
     /// Receive a RELAY cell from the circuit (with decryption)
+    ///
+    /// Circuit-level flow control is handled here: SENDMEs are sent when
+    /// due, and circuit SENDMEs from the exit are consumed.
     pub async fn receive_relay_cell(&mut self) -> Result<RelayCell> {
+        loop {
+            let (hop_idx, relay_cell) = self.read_relay_cell().await?;
+            if !self.circuit_flow(hop_idx, &relay_cell).await? {
+                return Ok(relay_cell);
+            }
+        }
+    }
+
+    /// Read and decrypt the next RELAY cell, with the hop that sent it
+    async fn read_relay_cell(&mut self) -> Result<(usize, RelayCell)> {
         log::info!("    📥 receive_relay_cell: waiting for cell...");
 
         let cell = loop {
@@ -561,11 +1149,10 @@ impl Circuit {
                 .await
                 .map_err(|e| TorError::Network(format!("Failed to receive cell: {}", e)))?;
 
-            log::info!(
-                "    📥 Received {} bytes, header: {:02x?}",
-                cell_bytes.len(),
-                &cell_bytes[..10]
-            );
+            log::info!("    📥 Received {} bytes", cell_bytes.len());
+            if debug_protocol() {
+                log::info!("    📥 Received header: {:02x?}", head(&cell_bytes, 10));
+            }
 
             // Parse cell header
             let cell = Cell::from_bytes(&cell_bytes)?;
@@ -583,15 +1170,10 @@ impl Circuit {
 
             // DESTROY cell = circuit torn down by relay
             if cell.command == CellCommand::Destroy {
-                let reason = if cell.payload.is_empty() {
-                    0
-                } else {
-                    cell.payload[0]
-                };
-                return Err(TorError::CircuitClosed(format!(
-                    "Circuit destroyed by relay (reason: {})",
-                    reason
-                )));
+                let reason = cell.payload.first().copied().unwrap_or(0);
+                let reason = format!("Circuit destroyed by relay (reason: {})", reason);
+                self.set_close_reason(reason.clone());
+                return Err(TorError::CircuitClosed(reason));
             }
 
             // Verify it's a RELAY cell
@@ -607,6 +1189,16 @@ impl Circuit {
             break cell;
         };
 
+        self.open_relay_cell(&cell).await
+    }
+
+    /// Onion decrypt a RELAY cell read from the guard and check its digest,
+    /// returning the hop that sent it and the relay cell
+    ///
+    /// Flow control is left to the caller. [`Circuit::receive_relay_cell`]
+    /// reads and opens cells in one go; this is the decryption half on its
+    /// own, for cells that arrive some other way (benchmarks, tooling).
+    pub async fn open_relay_cell(&mut self, cell: &Cell) -> Result<(usize, RelayCell)> {
         // Tor spec §5.5.2: Per-layer decryption
         // Decrypt one layer at a time, check 'recognized' (bytes 1-2) after each.
         // This is critical because intermediate hops can send cells (e.g. DESTROY,
@@ -617,50 +1209,81 @@ impl Circuit {
             self.backward_ciphers.len()
         );
 
-        let mut origin_hop: Option<usize> = None;
-        for (i, cipher) in self.backward_ciphers.iter_mut().enumerate() {
-            cipher.apply_keystream(&mut payload);
-            let recognized = u16::from_be_bytes([payload[1], payload[2]]);
-            if recognized == 0 {
-                origin_hop = Some(i);
-                log::info!(
-                    "    📥 Cell recognized at hop {} of {}",
-                    i,
-                    self.backward_ciphers.len()
-                );
-                break;
-            }
+        let origin_hop = self.decrypt_onion(&mut payload).await?;
+        if let Some(i) = origin_hop {
+            log::info!(
+                "    📥 Cell recognized at hop {} of {}",
+                i,
+                self.backward_ciphers.len()
+            );
         }
 
-        if origin_hop.is_none() {
+        let Some(hop_idx) = origin_hop else {
             log::warn!(
-                "    ⚠️ No hop recognized cell (cmd byte={}), treating as corrupt",
-                payload[0]
+                "    ⚠️ No hop recognized cell (cmd byte={:?}), treating as corrupt",
+                payload.first()
             );
             return Err(TorError::ProtocolError(
                 "No hop recognized relay cell".into(),
             ));
+        };
+
+        let (received_digest, digest_ok) = self.check_backward_digest(hop_idx, &payload)?;
+
+        #[cfg(feature = "research")]
+        crate::research::observe(self.id, Direction::Received, hop_idx, &payload);
+        let relay_cell = RelayCell::from_bytes(&payload)?;
+        trace::record(
+            self.id,
+            Direction::Received,
+            hop_idx,
+            &relay_cell,
+            received_digest,
+            digest_ok,
+        );
+        if relay_cell.command == RelayCommand::Truncated && relay_cell.stream_id == 0 {
+            let reason = relay_cell.data.first().copied().unwrap_or(0);
+            self.note_truncated(hop_idx, reason);
         }
-        let hop_idx = origin_hop.unwrap();
+        log::info!(
+            "    ✅ Received RELAY cell: {:?} stream={} data_len={} (from hop {})",
+            relay_cell.command,
+            relay_cell.stream_id,
+            relay_cell.data.len(),
+            hop_idx
+        );
+
+        Ok((hop_idx, relay_cell))
+    }
 
+    /// Take `payload` (from `hop_idx`) into that hop's running backward
+    /// digest, returning its digest field and whether it matched
+    fn check_backward_digest(
+        &mut self,
+        hop_idx: usize,
+        payload: &[u8],
+    ) -> Result<([u8; 4], Option<bool>)> {
         // Verify relay cell digest using the correct hop's digest state
-        let received_digest = [payload[5], payload[6], payload[7], payload[8]];
-        let mut payload_for_hash = payload.clone();
-        payload_for_hash[5] = 0;
-        payload_for_hash[6] = 0;
-        payload_for_hash[7] = 0;
-        payload_for_hash[8] = 0;
-
-        if let Some(digest) = self.backward_digests.get_mut(hop_idx) {
+        let received_digest = array(payload, 5, "Relay digest")?;
+        let mut payload_for_hash = payload.to_vec();
+        slice_mut(&mut payload_for_hash, 5, 4, "Relay digest")?.fill(0);
+
+        let mut digest_ok = None;
+        let running = if let Some(digest) = self.backward_digests.get_mut(hop_idx) {
             use sha1::Digest as Sha1Digest;
             digest.update(&payload_for_hash);
-            let hash_output = digest.clone().finalize();
-            let expected_digest = [
-                hash_output[0],
-                hash_output[1],
-                hash_output[2],
-                hash_output[3],
-            ];
+            Some(digest.clone().finalize().to_vec())
+        } else if let Some(service) = self.service.as_mut() {
+            use sha3::Digest as Sha3Digest;
+            service.backward_digest.update(&payload_for_hash);
+            Some(service.backward_digest.clone().finalize().to_vec())
+        } else {
+            None
+        };
+        if let Some(hash_output) = running {
+            let mut expected_digest = [0u8; 4];
+            expected_digest.copy_from_slice(&hash_output[..4]);
+            digest_ok = Some(received_digest == expected_digest);
 
             if received_digest != expected_digest {
                 log::warn!(
@@ -678,16 +1301,18 @@ impl Circuit {
             }
         }
 
-        let relay_cell = RelayCell::from_bytes(&payload)?;
-        log::info!(
-            "    ✅ Received RELAY cell: {:?} stream={} data_len={} (from hop {})",
-            relay_cell.command,
-            relay_cell.stream_id,
-            relay_cell.data.len(),
-            hop_idx
-        );
+        Ok((received_digest, digest_ok))
+    }
+
+    /// `hop_idx`'s running backward digest as sent in a v1 SENDME (20 bytes)
+    fn backward_digest(&self, hop_idx: usize) -> Vec<u8> {
+        use sha1::Digest;
 
-        Ok(relay_cell)
+        match (self.backward_digests.get(hop_idx), &self.service) {
+            (Some(digest), _) => digest.clone().finalize().to_vec(),
+            (None, Some(service)) => service.backward_digest.clone().finalize()[..20].to_vec(),
+            (None, None) => Vec::new(),
+        }
     }
 
     /// Try to receive a relay cell without blocking indefinitely
@@ -700,6 +1325,8 @@ impl Circuit {
     ///
     /// Note: In WASM, we can't truly do non-blocking I/O, so this uses
     /// a select! with a zero-timeout to check if data is immediately available.
+    ///
+    /// Circuit-level flow control is handled as in `receive_relay_cell()`.
     pub async fn try_receive_relay_cell(&mut self) -> Result<Option<RelayCell>> {
         use futures::future::FutureExt;
 
@@ -747,32 +1374,43 @@ impl Circuit {
                             // intermediate hops (guard, middle) can send cells like
                             // RELAY_TRUNCATED with fewer encryption layers.
                             let mut payload = cell.payload.clone();
-                            let mut found_hop = false;
-
-                            for (i, cipher) in self.backward_ciphers.iter_mut().enumerate() {
-                                cipher.apply_keystream(&mut payload);
-
-                                let recognized = u16::from_be_bytes([payload[1], payload[2]]);
-                                if recognized == 0 {
-                                    // This hop's decryption produced recognized=0
-                                    // Cell is (likely) from hop i
-                                    found_hop = true;
-                                    log::trace!("    📥 try_receive: cell recognized at hop {} of {}",
-                                        i, self.backward_ciphers.len());
-                                    break;
-                                }
+                            let found_hop = self.decrypt_onion(&mut payload).await?;
+                            if let Some(i) = found_hop {
+                                log::trace!("    📥 try_receive: cell recognized at hop {} of {}",
+                                    i, self.backward_ciphers.len());
                             }
 
-                            if !found_hop {
+                            let Some(hop_idx) = found_hop else {
                                 // No hop recognized this cell — corrupted or from unknown source
-                                log::warn!("    ⚠️ try_receive: no hop recognized cell (cmd byte={}), discarding",
-                                    payload[0]);
+                                log::warn!("    ⚠️ try_receive: no hop recognized cell (cmd byte={:?}), discarding",
+                                    payload.first());
                                 continue;
-                            }
+                            };
+
+                            #[cfg(feature = "research")]
+                            crate::research::observe(self.id, Direction::Received, hop_idx, &payload);
+
+                            let (received_digest, digest_ok) =
+                                self.check_backward_digest(hop_idx, &payload)?;
 
                             // Try to parse the relay cell
                             match RelayCell::from_bytes(&payload) {
                                 Ok(relay_cell) => {
+                                    trace::record(
+                                        self.id,
+                                        Direction::Received,
+                                        hop_idx,
+                                        &relay_cell,
+                                        received_digest,
+                                        digest_ok,
+                                    );
+                                    if relay_cell.command == RelayCommand::Truncated && relay_cell.stream_id == 0 {
+                                        let reason = relay_cell.data.first().copied().unwrap_or(0);
+                                        self.note_truncated(hop_idx, reason);
+                                    }
+                                    if self.circuit_flow(hop_idx, &relay_cell).await? {
+                                        continue;
+                                    }
                                     log::trace!("    ✅ try_receive: {:?} stream={}",
                                         relay_cell.command, relay_cell.stream_id);
                                     return Ok(Some(relay_cell));
@@ -799,6 +1437,17 @@ impl Circuit {
     }
 }
 
+impl Drop for Circuit {
+    fn drop(&mut self) {
+        if self.announced {
+            events::post(TorEvent::CircuitClosed {
+                circuit_id: self.id,
+                reason: self.close_reason.take().unwrap_or_else(|| "dropped".into()),
+            });
+        }
+    }
+}
+
 /// Circuit builder
 #[derive(Clone)]
 pub struct CircuitBuilder {
@@ -807,6 +1456,21 @@ pub struct CircuitBuilder {
 
     /// TLS connector
     tls: WasmTlsConnector,
+
+    /// Failed attempts by stage (counters only, no relay identities)
+    failures: SharedFailureStats,
+
+    /// Persistent guard state to record first-hop outcomes into
+    guards: Option<SharedGuardState>,
+
+    /// Verified link handshakes by guard
+    links: SharedLinkCache,
+
+    /// Cancellation of the request this build is for
+    cancel: Option<CancelToken>,
+
+    /// Operator blocklist every hop is checked against
+    blocklist: Option<SharedBlocklist>,
 }
 
 impl CircuitBuilder {
@@ -815,9 +1479,124 @@ impl CircuitBuilder {
         Self {
             network,
             tls: WasmTlsConnector::new(),
+            failures: new_shared_failure_stats(),
+            guards: None,
+            links: new_shared_link_cache(),
+            cancel: None,
+            blocklist: None,
         }
     }
 
+    /// Record build failures into `stats` (shared with the client)
+    pub fn with_failure_stats(mut self, stats: SharedFailureStats) -> Self {
+        self.failures = stats;
+        self
+    }
+
+    /// Record guard successes and failures into `state` (shared with the
+    /// client), and skip guards it marks bad
+    pub fn with_guard_state(mut self, state: SharedGuardState) -> Self {
+        self.guards = Some(state);
+        self
+    }
+
+    /// Reuse link handshake results from `cache` (shared with the client)
+    pub fn with_link_cache(mut self, cache: SharedLinkCache) -> Self {
+        self.links = cache;
+        self
+    }
+
+    /// Give up when `cancel` is cancelled: between hops, destroying the
+    /// partly built circuit, or while backing off between guards
+    pub fn with_cancel(mut self, cancel: Option<CancelToken>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Refuse relays on `blocklist` (shared with the client) as any hop
+    pub fn with_blocklist(mut self, blocklist: SharedBlocklist) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    /// Whether `relay` is on the operator blocklist
+    fn is_blocked(&self, relay: &Relay) -> bool {
+        self.blocklist
+            .as_ref()
+            .is_some_and(|list| list.with(|l| l.blocks(relay)))
+    }
+
+    /// Fail if `relay` is on the operator blocklist
+    pub fn check_blocklist(&self, relay: &Relay) -> Result<()> {
+        if self.is_blocked(relay) {
+            let error =
+                TorError::InvalidRelay(format!("{} is on the operator blocklist", relay.nickname));
+            self.record_failure(BuildStage::PathSelection, &error);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn check_cancel(&self) -> Result<()> {
+        self.cancel.as_ref().map_or(Ok(()), CancelToken::check)
+    }
+
+    /// Destroy `circuit` if the build was cancelled
+    async fn abandon_if_cancelled(&self, circuit: &mut Circuit) -> Result<()> {
+        if let Err(e) = self.check_cancel() {
+            log::info!("  🛑 Build cancelled, destroying circuit {}", circuit.id);
+            let _ = circuit.destroy().await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Record whether `guard` worked as a first hop
+    ///
+    /// A guard that completed the ntor handshake counts as a success even if
+    /// a later hop failed: that failure says nothing about the guard.
+    fn note_guard(&self, guard: &Relay, error: Option<&TorError>) {
+        let Some(state) = &self.guards else {
+            return;
+        };
+        state.with(|g| match error {
+            None => g.record_success(&guard.fingerprint),
+            Some(e) => g.record_failure(&guard.fingerprint, &e.to_string()),
+        });
+    }
+
+    /// Whether the shared guard state has marked `guard` bad
+    fn is_bad_guard(&self, guard: &Relay) -> bool {
+        self.guards
+            .as_ref()
+            .is_some_and(|state| state.with(|g| g.is_bad_guard(&guard.fingerprint)))
+    }
+
+    /// Count a failed attempt at `stage`
+    fn record_failure(&self, stage: BuildStage, error: &TorError) {
+        self.failures.with(|f| f.record_failure(stage, error));
+    }
+
+    /// Keep a report of a finished build for diagnostics
+    fn record_build(
+        &self,
+        custom_path: bool,
+        attempts: usize,
+        started_ms: u64,
+        result: &Result<Circuit>,
+        explanation: Option<SelectionExplanation>,
+    ) {
+        let report = BuildReport {
+            ok: result.is_ok(),
+            custom_path,
+            attempts: attempts as u32,
+            duration_ms: now_ms().saturating_sub(started_ms),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            explanation,
+        };
+        self.failures.with(|f| f.record_build(report));
+    }
+
     /// Circuit build timeout in milliseconds (60 seconds per Tor spec recommendation)
     const CIRCUIT_BUILD_TIMEOUT_MS: u32 = 60_000;
 
@@ -831,18 +1610,55 @@ impl CircuitBuilder {
     ///
     /// Each attempt is wrapped in a 60-second timeout. On failure, retries
     /// with a different guard and exponential backoff (0s, 5s, 15s).
-    /// Maximum 3 attempts.
+    /// Maximum 3 attempts, each on a distinct guard not marked bad in the
+    /// shared guard state; each attempt's first-hop outcome is recorded there.
+    /// With `explain_paths` on, the build report explains the path.
     pub async fn build_circuit(&self, selector: &RelaySelector) -> Result<Circuit> {
+        let started_ms = now_ms();
+        let passed_over = PassedOverLog::new();
+        let (result, attempts) = self
+            .build_circuit_with_retries(selector, &passed_over)
+            .await;
+        let explanation = passed_over.is_enabled().then(|| {
+            let (circuit_id, path) = match &result {
+                Ok(circuit) => (Some(circuit.id), circuit.relays.as_slice()),
+                Err(_) => (None, &[][..]),
+            };
+            SelectionExplanation::new(selector, circuit_id, path, passed_over.take())
+        });
+        self.record_build(false, attempts, started_ms, &result, explanation);
+        result.map(Circuit::announce_built)
+    }
+
+    /// [`CircuitBuilder::build_circuit`], also returning the number of
+    /// guards tried; relays given up on are noted in `passed_over`
+    async fn build_circuit_with_retries(
+        &self,
+        selector: &RelaySelector,
+        passed_over: &PassedOverLog,
+    ) -> (Result<Circuit>, usize) {
         use futures::future::FutureExt;
 
         log::info!("🔨 Building new Tor circuit (v4 with timeout + retry)...");
 
-        // Get guard candidates for retry logic (more than MAX_BUILD_ATTEMPTS for rotation)
-        let guard_candidates = selector.select_guards(Self::MAX_BUILD_ATTEMPTS * 3);
+        // Get guard candidates for retry logic (more than MAX_BUILD_ATTEMPTS for rotation),
+        // so every attempt goes to a different guard that hasn't been marked bad
+        let mut guard_candidates = selector.select_guards(Self::MAX_BUILD_ATTEMPTS * 3);
+        let mut seen = std::collections::HashSet::new();
+        guard_candidates.retain(|g| {
+            if !seen.insert(g.fingerprint.clone()) {
+                return false;
+            }
+            if self.is_bad_guard(g) {
+                passed_over.note(g, RelayRole::Guard, "marked bad in the guard state");
+                return false;
+            }
+            true
+        });
         if guard_candidates.is_empty() {
-            return Err(TorError::CircuitBuildFailed(
-                "No guard relay available".into(),
-            ));
+            let error = TorError::CircuitBuildFailed("No guard relay available".into());
+            self.record_failure(BuildStage::PathSelection, &error);
+            return (Err(error), 0);
         }
         log::info!(
             "  📍 Selected {} guard candidates (will try up to {})",
@@ -854,6 +1670,13 @@ impl CircuitBuilder {
 
         // Try up to MAX_BUILD_ATTEMPTS guards with timeout and backoff
         let attempts = guard_candidates.len().min(Self::MAX_BUILD_ATTEMPTS);
+        let cancelled = async {
+            match &self.cancel {
+                Some(cancel) => cancel.cancelled().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let mut cancelled = std::pin::pin!(cancelled.fuse());
         for attempt in 0..attempts {
             let guard = &guard_candidates[attempt];
 
@@ -861,7 +1684,13 @@ impl CircuitBuilder {
             let backoff = Self::RETRY_BACKOFF_MS[attempt.min(Self::RETRY_BACKOFF_MS.len() - 1)];
             if backoff > 0 {
                 log::info!("  ⏳ Backoff: waiting {}ms before retry...", backoff);
-                gloo_timers::future::TimeoutFuture::new(backoff).await;
+                futures::select_biased! {
+                    _ = cancelled => {}
+                    _ = gloo_timers::future::TimeoutFuture::new(backoff).fuse() => {}
+                }
+            }
+            if let Err(e) = self.check_cancel() {
+                return (Err(e), attempt);
             }
 
             log::info!(
@@ -873,16 +1702,26 @@ impl CircuitBuilder {
                 guard.or_port
             );
 
+            // Set once the guard completes its first hop
+            let reached = std::cell::Cell::new(false);
+
             // Race the circuit build against a 60-second timeout
             futures::select_biased! {
-                result = self.try_build_with_guard(guard, selector).fuse() => {
+                result = self.try_build_with_guard(guard, selector, &reached, passed_over).fuse() => {
                     match result {
                         Ok(circuit) => {
                             log::info!("✅ Circuit built successfully on attempt {}", attempt + 1);
-                            return Ok(circuit);
+                            self.failures.with(|f| f.record_success());
+                            self.note_guard(guard, None);
+                            return (Ok(circuit), attempt + 1);
+                        }
+                        Err(TorError::Cancelled) => {
+                            return (Err(TorError::Cancelled), attempt + 1);
                         }
                         Err(e) => {
                             log::warn!("  ⚠️ Guard {} failed: {}", guard.nickname, e);
+                            passed_over.note(guard, RelayRole::Guard, format_args!("attempt failed: {}", e));
+                            self.note_guard(guard, (!reached.get()).then_some(&e));
                             last_error = e;
                         }
                     }
@@ -893,16 +1732,20 @@ impl CircuitBuilder {
                     last_error = TorError::CircuitBuildFailed(format!(
                         "Circuit build timed out after {}s", Self::CIRCUIT_BUILD_TIMEOUT_MS / 1000
                     ));
+                    self.record_failure(BuildStage::Timeout, &last_error);
+                    passed_over.note(guard, RelayRole::Guard, "attempt timed out");
+                    self.note_guard(guard, (!reached.get()).then_some(&last_error));
                 }
             }
         }
 
         // All attempts failed
         log::error!("❌ All {} circuit build attempts failed", attempts);
-        Err(TorError::CircuitBuildFailed(format!(
+        let error = TorError::CircuitBuildFailed(format!(
             "All {} circuit build attempts failed. Last error: {}",
             attempts, last_error
-        )))
+        ));
+        (Err(error), attempts)
     }
 
     /// Check if any two relays in the path are in the same declared family.
@@ -917,7 +1760,7 @@ impl CircuitBuilder {
     }
 
     /// Check if two relays declare each other as family members.
-    fn relays_share_family(a: &Relay, b: &Relay) -> bool {
+    pub(crate) fn relays_share_family(a: &Relay, b: &Relay) -> bool {
         let a_declares_b = a
             .family
             .as_ref()
@@ -933,30 +1776,41 @@ impl CircuitBuilder {
     }
 
     /// Try to build a circuit with a specific guard
-    /// Also tries multiple middle/exit combinations if extension fails
+    /// Also tries multiple middle/exit combinations if extension fails;
+    /// gives up at once if the guard itself can't be reached. Sets `reached`
+    /// once a first hop with the guard is up.
     async fn try_build_with_guard(
         &self,
         guard: &Relay,
         selector: &RelaySelector,
+        reached: &std::cell::Cell<bool>,
+        passed_over: &PassedOverLog,
     ) -> Result<Circuit> {
         // Select multiple middle and exit candidates (more for retries)
-        let middles = selector.select_middles(5, &[&guard.fingerprint]);
+        let mut middles = selector.select_middles(5, &[&guard.fingerprint]);
         let mut exits = selector.select_exits(10, &[&guard.fingerprint]);
+        let unblocked = |r: &&Relay, role| {
+            let blocked = self.is_blocked(r);
+            if blocked {
+                passed_over.note(r, role, "on the operator blocklist");
+            }
+            !blocked
+        };
+        middles.retain(|r| unblocked(r, RelayRole::Middle));
+        exits.retain(|r| unblocked(r, RelayRole::Exit));
 
         // Shuffle exits so we try different ones for each middle
         use rand::seq::SliceRandom;
         let mut rng = rand::thread_rng();
         exits.shuffle(&mut rng);
 
-        if middles.is_empty() {
-            return Err(TorError::CircuitBuildFailed(
-                "No middle relay available".into(),
-            ));
-        }
-        if exits.is_empty() {
-            return Err(TorError::CircuitBuildFailed(
-                "No exit relay available".into(),
+        if middles.is_empty() || exits.is_empty() {
+            let error = TorError::CircuitBuildFailed(format!(
+                "No {} relay available",
+                if middles.is_empty() { "middle" } else { "exit" }
             ));
+            self.record_failure(BuildStage::PathSelection, &error);
+            return Err(error);
         }
 
         // Track which exit to try next (rotates across middle attempts)
@@ -978,131 +1832,104 @@ impl CircuitBuilder {
                 middle.nickname
             );
 
-            // Generate circuit ID
-            // Link protocol v4+: Client (initiator) MUST set MSB to 1
-            let circuit_id = rand::random::<u32>() | 0x80000000;
-
-            // Connect to guard
-            log::info!("    📞 Connecting to guard...");
-            let addr = guard.socket_addr();
-            let tcp_stream = match self.network.connect_with_retry(&addr).await {
-                Ok(s) => s,
-                Err(e) => {
-                    log::warn!("    ⚠️ Guard connection failed: {}", e);
-                    last_error = Some(TorError::ConnectionFailed(format!(
-                        "Guard connection failed: {}",
-                        e
-                    )));
-                    continue;
-                }
-            };
-
-            // TLS handshake with guard
-            log::info!("    🔐 TLS handshake...");
-            let mut tls_stream = match self
-                .tls
-                .connect(tcp_stream, Some(&guard.nickname), Some(addr))
-                .await
-            {
-                Ok(s) => s,
-                Err(e) => {
-                    log::warn!("    ⚠️ TLS handshake failed: {}", e);
-                    last_error = Some(TorError::ConnectionFailed(format!(
-                        "TLS handshake failed: {}",
-                        e
-                    )));
-                    continue;
-                }
-            };
-
-            // Tor protocol handshake (VERSIONS + NETINFO)
-            log::info!("    🤝 Protocol handshake...");
-            if let Err(e) = self
-                .protocol_handshake(&mut tls_stream, Some(&guard.fingerprint))
-                .await
-            {
-                log::warn!("    ⚠️ Protocol handshake failed: {}", e);
-                last_error = Some(e);
-                continue;
-            }
-
-            // Create circuit with guard (ntor handshake)
-            log::info!("    🤝 ntor handshake...");
-            let keys = match self
-                .ntor_handshake(&mut tls_stream, circuit_id, guard)
-                .await
-            {
-                Ok(k) => k,
+            let mut circuit = match self.open_first_hop(guard).await {
+                Ok(c) => c,
+                // Other middles won't help with this guard; the caller moves on
+                Err(e) if !reached.get() => return Err(e),
                 Err(e) => {
-                    log::warn!("    ⚠️ ntor handshake failed: {}", e);
                     last_error = Some(e);
                     continue;
                 }
             };
-
-            log::info!("    ✅ Circuit created with guard");
-
-            // Create circuit with guard and TLS stream
-            let mut circuit =
-                Circuit::with_stream(circuit_id, vec![guard.clone()], keys, tls_stream);
+            reached.set(true);
+            let circuit_id = circuit.id;
+            self.abandon_if_cancelled(&mut circuit).await?;
 
             // Extend to middle relay
             log::info!("    📡 Extending to middle {}...", middle.nickname);
             if let Err(e) = circuit.extend_to(middle).await {
                 log::warn!("    ⚠️ Middle extension failed: {}", e);
-                last_error = Some(e);
-                continue;
-            }
-
-            log::info!("    ✅ Extended to middle {}", middle.nickname);
-
-            // Try one exit for this middle (circuit is destroyed on failure, so we rotate to next exit for next middle)
-            // Use exit_start_idx to ensure we try different exits for each middle attempt
-            let exit_idx = exit_start_idx % exits.len();
-            let exit = &exits[exit_idx];
-
-            // Skip if exit is same as middle
-            if exit.fingerprint == middle.fingerprint {
-                exit_start_idx += 1;
-                log::info!("    ⚠️ Skipping exit {} (same as middle)", exit.nickname);
+                self.record_failure(BuildStage::ExtendMiddle, &e);
+                passed_over.note(
+                    middle,
+                    RelayRole::Middle,
+                    format_args!("extension failed: {}", e),
+                );
+                last_error = Some(e);
                 continue;
             }
+            self.abandon_if_cancelled(&mut circuit).await?;
+
+            log::info!("    ✅ Extended to middle {}", middle.nickname);
 
-            // Validate path: no two relays in same family
-            if Self::has_family_conflict(guard, middle, exit) {
+            // Try exits on this guard → middle circuit. A failed extension
+            // usually destroys it, but a TRUNCATED reply leaves it at two hops,
+            // so the next exit can be tried without rebuilding. Rotate through
+            // exits across middles so each attempt tries a different one.
+            for _ in 0..EXITS_PER_MIDDLE {
+                let exit_idx = exit_start_idx % exits.len();
+                let exit = &exits[exit_idx];
                 exit_start_idx += 1;
-                log::info!(
-                    "    ⚠️ Skipping exit {} (family conflict with guard or middle)",
-                    exit.nickname
-                );
-                continue;
-            }
 
-            log::info!(
-                "    📡 Trying exit {}/{}: {}",
-                exit_idx + 1,
-                exits.len(),
-                exit.nickname
-            );
+                // Skip if exit is same as middle
+                if exit.fingerprint == middle.fingerprint {
+                    log::info!("    ⚠️ Skipping exit {} (same as middle)", exit.nickname);
+                    passed_over.note(exit, RelayRole::Exit, "same relay as the middle");
+                    continue;
+                }
 
-            match circuit.extend_to(exit).await {
-                Ok(_) => {
+                // Validate path: no two relays in same family
+                if Self::has_family_conflict(guard, middle, exit) {
                     log::info!(
-                        "    ✅ Circuit {} complete: {} → {} → {}",
-                        circuit_id,
-                        guard.nickname,
-                        middle.nickname,
+                        "    ⚠️ Skipping exit {} (family conflict with guard or middle)",
                         exit.nickname
                     );
-                    return Ok(circuit);
+                    passed_over.note(
+                        exit,
+                        RelayRole::Exit,
+                        "family conflict with the guard or middle",
+                    );
+                    continue;
                 }
-                Err(e) => {
-                    log::warn!("    ⚠️ Exit extension to {} failed: {}", exit.nickname, e);
-                    last_error = Some(e);
-                    // Increment exit index for next middle attempt
-                    exit_start_idx += 1;
-                    // Circuit is destroyed, try next middle (which creates a new circuit)
+
+                log::info!(
+                    "    📡 Trying exit {}/{}: {}",
+                    exit_idx + 1,
+                    exits.len(),
+                    exit.nickname
+                );
+
+                match circuit.extend_to(exit).await {
+                    Ok(_) => {
+                        log::info!(
+                            "    ✅ Circuit {} complete: {} → {} → {}",
+                            circuit_id,
+                            guard.nickname,
+                            middle.nickname,
+                            exit.nickname
+                        );
+                        return Ok(circuit);
+                    }
+                    Err(e) => {
+                        log::warn!("    ⚠️ Exit extension to {} failed: {}", exit.nickname, e);
+                        self.record_failure(BuildStage::ExtendExit, &e);
+                        passed_over.note(
+                            exit,
+                            RelayRole::Exit,
+                            format_args!("extension failed: {}", e),
+                        );
+                        last_error = Some(e);
+                    }
                 }
+
+                if circuit.take_truncated().is_none() || circuit.hop_count() != 2 {
+                    break;
+                }
+                self.abandon_if_cancelled(&mut circuit).await?;
+                log::info!(
+                    "    ♻️ Middle {} truncated the circuit, trying another exit",
+                    middle.nickname
+                );
             }
 
             // Exit extension failed, try next middle (requires new circuit)
@@ -1114,15 +1941,227 @@ impl CircuitBuilder {
         }))
     }
 
+    /// Connect to a guard and create a one-hop circuit with it
+    ///
+    /// TCP/transport connect, TLS, VERSIONS + NETINFO (with certificate
+    /// verification), then CREATE2/ntor.
+    async fn open_first_hop(&self, guard: &Relay) -> Result<Circuit> {
+        self.check_blocklist(guard)?;
+
+        // Link protocol v4+: Client (initiator) MUST set MSB to 1
+        let circuit_id = rand::random::<u32>() | 0x80000000;
+
+        log::info!("    📞 Connecting to guard...");
+        let addr = guard.socket_addr();
+        let tcp_stream = self.network.connect_with_retry(&addr).await.map_err(|e| {
+            log::warn!("    ⚠️ Guard connection failed: {}", e);
+            let error = TorError::ConnectionFailed(format!("Guard connection failed: {}", e));
+            self.record_failure(BuildStage::Connect, &error);
+            error
+        })?;
+
+        log::info!("    🔐 TLS handshake...");
+        let mut tls_stream = self
+            .tls
+            .connect(tcp_stream, Some(&guard.nickname), Some(addr))
+            .await
+            .map_err(|e| {
+                log::warn!("    ⚠️ TLS handshake failed: {}", e);
+                let error = TorError::ConnectionFailed(format!("TLS handshake failed: {}", e));
+                self.record_failure(BuildStage::Tls, &error);
+                error
+            })?;
+
+        log::info!("    🤝 Protocol handshake...");
+        let cached = self.links.with(|c| c.lookup(&guard.fingerprint));
+        let link = match Self::protocol_handshake(
+            &mut tls_stream,
+            Some(&guard.fingerprint),
+            listed_ed25519_identity(guard).as_ref(),
+            cached.as_ref(),
+        )
+        .await
+        {
+            Ok(link) => link,
+            Err(e) => {
+                log::warn!("    ⚠️ Protocol handshake failed: {}", e);
+                self.record_failure(BuildStage::LinkHandshake, &e);
+                return Err(e);
+            }
+        };
+        let reused = cached.is_some_and(|c| c.vouches_for(link.link_version, &link.certs_digest));
+        let link_lease = self
+            .links
+            .with(|c| c.record(&guard.fingerprint, link, reused));
+
+        log::info!("    🤝 ntor handshake...");
+        let keys = match self
+            .ntor_handshake(&mut tls_stream, circuit_id, guard)
+            .await
+        {
+            Ok(k) => k,
+            Err(e) => {
+                log::warn!("    ⚠️ ntor handshake failed: {}", e);
+                self.record_failure(BuildStage::Create, &e);
+                return Err(e);
+            }
+        };
+
+        log::info!("    ✅ Circuit created with guard");
+        let mut circuit = Circuit::with_stream(circuit_id, vec![guard.clone()], keys, tls_stream);
+        circuit.link_lease = link_lease;
+        Ok(circuit)
+    }
+
+    /// Build a circuit through exactly the given relays, in order
+    ///
+    /// No retries or relay substitution: if any hop fails the build fails.
+    /// Callers should run [`CircuitBuilder::validate_path`] first.
+    pub async fn build_circuit_through(&self, path: &[Relay]) -> Result<Circuit> {
+        use futures::future::FutureExt;
+
+        let started_ms = now_ms();
+
+        let (guard, rest) = path
+            .split_first()
+            .ok_or_else(|| TorError::CircuitBuildFailed("Empty circuit path".into()))?;
+        for relay in path {
+            self.check_blocklist(relay)?;
+        }
+
+        log::info!(
+            "🔨 Building custom circuit: {}",
+            path.iter()
+                .map(|r| r.nickname.as_str())
+                .collect::<Vec<_>>()
+                .join(" → ")
+        );
+
+        let build = async {
+            let first_hop = self.open_first_hop(guard).await;
+            self.note_guard(guard, first_hop.as_ref().err());
+            let mut circuit = first_hop?;
+            for (i, relay) in rest.iter().enumerate() {
+                log::info!("    📡 Extending to {}...", relay.nickname);
+                if let Err(e) = circuit.extend_to(relay).await {
+                    let stage = if i + 1 == rest.len() {
+                        BuildStage::ExtendExit
+                    } else {
+                        BuildStage::ExtendMiddle
+                    };
+                    self.record_failure(stage, &e);
+                    return Err(e);
+                }
+            }
+            self.failures.with(|f| f.record_success());
+            Ok(circuit)
+        };
+
+        let result = futures::select_biased! {
+            result = build.fuse() => result,
+            _ = gloo_timers::future::TimeoutFuture::new(Self::CIRCUIT_BUILD_TIMEOUT_MS).fuse() => {
+                let error = TorError::CircuitBuildFailed(format!(
+                    "Circuit build timed out after {}s", Self::CIRCUIT_BUILD_TIMEOUT_MS / 1000
+                ));
+                self.record_failure(BuildStage::Timeout, &error);
+                Err(error)
+            }
+        };
+        self.record_build(true, 1, started_ms, &result, None);
+        result.map(Circuit::announce_built)
+    }
+
+    /// Check a user-chosen path before building it
+    ///
+    /// Always enforced: 1..=[`MAX_CUSTOM_PATH_LEN`] hops, no repeated relay,
+    /// and an ntor key for every hop. With `enforce_constraints`, also the
+    /// usual selection rules: first hop has Guard, last hop allows exit, all
+    /// relays Running, no two relays in the same family or IPv4 /16.
+    pub fn validate_path(path: &[Relay], enforce_constraints: bool) -> Result<()> {
+        if path.is_empty() || path.len() > MAX_CUSTOM_PATH_LEN {
+            return Err(TorError::InvalidRelay(format!(
+                "Path must have 1 to {} hops, got {}",
+                MAX_CUSTOM_PATH_LEN,
+                path.len()
+            )));
+        }
+
+        for (i, relay) in path.iter().enumerate() {
+            if relay.ntor_onion_key.is_none() {
+                return Err(TorError::InvalidRelay(format!(
+                    "{} has no ntor onion key",
+                    relay.nickname
+                )));
+            }
+            if path[..i]
+                .iter()
+                .any(|r| r.fingerprint.eq_ignore_ascii_case(&relay.fingerprint))
+            {
+                return Err(TorError::InvalidRelay(format!(
+                    "{} appears more than once in the path",
+                    relay.nickname
+                )));
+            }
+        }
+
+        if !enforce_constraints {
+            return Ok(());
+        }
+
+        let guard = &path[0];
+        let exit = &path[path.len() - 1];
+        if !guard.is_guard() {
+            return Err(TorError::InvalidRelay(format!(
+                "{} is not a usable guard (needs Guard, Stable, Fast)",
+                guard.nickname
+            )));
+        }
+        if path.len() > 1 && !exit.is_exit() {
+            return Err(TorError::InvalidRelay(format!(
+                "{} is not a usable exit",
+                exit.nickname
+            )));
+        }
+        if let Some(relay) = path.iter().find(|r| !r.is_running()) {
+            return Err(TorError::InvalidRelay(format!(
+                "{} is not running",
+                relay.nickname
+            )));
+        }
+        for (i, a) in path.iter().enumerate() {
+            for b in &path[i + 1..] {
+                if Self::relays_share_family(a, b) {
+                    return Err(TorError::InvalidRelay(format!(
+                        "{} and {} are in the same family",
+                        a.nickname, b.nickname
+                    )));
+                }
+                if same_ipv4_slash16(a, b) {
+                    return Err(TorError::InvalidRelay(format!(
+                        "{} and {} are in the same /16 subnet",
+                        a.nickname, b.nickname
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Perform Tor protocol handshake (VERSIONS + NETINFO)
     ///
     /// If `relay_fingerprint` is provided (hex string, 40 chars), performs full
     /// certificate chain verification against the relay's expected identity.
-    async fn protocol_handshake<S>(
-        &self,
+    /// If `cached` vouches for the CERTS cell the relay sends (same bytes,
+    /// same link version), that verification is skipped. If `listed_ed25519`
+    /// is given (the identity from the relay's descriptor), the CERTS chain
+    /// must verify to that key or the handshake fails.
+    pub(crate) async fn protocol_handshake<S>(
         stream: &mut S,
         relay_fingerprint: Option<&str>,
-    ) -> Result<()>
+        listed_ed25519: Option<&[u8; 32]>,
+        cached: Option<&LinkInfo>,
+    ) -> Result<LinkInfo>
     where
         S: AsyncWriteExt + AsyncReadExt + Unpin,
     {
@@ -1143,11 +2182,10 @@ impl CircuitBuilder {
         versions_bytes.extend_from_slice(&(versions_payload.len() as u16).to_be_bytes()); // Length
         versions_bytes.extend_from_slice(&versions_payload); // Payload
 
-        log::info!(
-            "  📦 Sending {} bytes: {:02x?}",
-            versions_bytes.len(),
-            versions_bytes
-        );
+        log::info!("  📦 Sending {} bytes", versions_bytes.len());
+        if debug_protocol() {
+            log::info!("    VERSIONS cell bytes: {:02x?}", versions_bytes);
+        }
 
         stream
             .write_all(&versions_bytes)
@@ -1165,7 +2203,10 @@ impl CircuitBuilder {
         let mut header = vec![0u8; 5];
         match stream.read_exact(&mut header).await {
             Ok(_) => {
-                log::info!("  ✅ Received VERSIONS header: {:02x?}", header);
+                log::info!("  ✅ Received VERSIONS header");
+                if debug_protocol() {
+                    log::info!("    VERSIONS header bytes: {:02x?}", header);
+                }
             }
             Err(e) => {
                 log::error!("  ❌ Relay closed connection!");
@@ -1178,9 +2219,8 @@ impl CircuitBuilder {
             }
         }
 
-        let _circuit_id = u16::from_be_bytes([header[0], header[1]]);
-        let command = header[2];
-        let payload_len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let command = u8_at(&header, 2, "VERSIONS command")?;
+        let payload_len = u16_at(&header, 3, "VERSIONS length")? as usize;
 
         if command != CellCommand::Versions as u8 {
             return Err(TorError::ProtocolError(format!(
@@ -1196,11 +2236,10 @@ impl CircuitBuilder {
             .await
             .map_err(|e| TorError::Network(format!("Failed to receive VERSIONS payload: {}", e)))?;
 
-        log::info!(
-            "  ✅ VERSIONS received ({} bytes payload): {:02x?}",
-            payload_len,
-            &payload[..payload_len.min(20)]
-        );
+        log::info!("  ✅ VERSIONS received ({} bytes payload)", payload_len);
+        if debug_protocol() {
+            log::info!("    VERSIONS payload: {:02x?}", head(&payload, 20));
+        }
 
         // SECURITY: Protocol version validation (P0.4: Downgrade protection)
         // Parse relay's supported versions and validate minimum security requirements
@@ -1221,9 +2260,9 @@ impl CircuitBuilder {
             .await
             .map_err(|e| TorError::Network(format!("Failed to receive cell header: {}", e)))?;
 
-        let circuit_id = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
-        let cmd = header[4];
-        let cell_len = u16::from_be_bytes([header[5], header[6]]) as usize;
+        let circuit_id = u32_at(&header, 0, "Cell circuit ID")?;
+        let cmd = u8_at(&header, 4, "Cell command")?;
+        let cell_len = u16_at(&header, 5, "Cell length")? as usize;
 
         log::info!(
             "  📦 Next cell: CircID={}, Cmd={}, Len={}",
@@ -1248,7 +2287,8 @@ impl CircuitBuilder {
         // Read and process CERTS and AUTH_CHALLENGE
         let mut cells_received = vec![(cmd, cell_len)];
         let mut current_cmd = cmd;
-        let mut certs_payload: Vec<u8> = Vec::new();
+        let mut certs_digest = [0u8; 32];
+        let mut verified_identity = None;
 
         // Read variable-length cells: CERTS (cmd=129) and AUTH_CHALLENGE (cmd=130)
         // NETINFO (cmd=8) is fixed-length and handled separately
@@ -1263,14 +2303,9 @@ impl CircuitBuilder {
                 .await
                 .map_err(|e| TorError::Network(format!("Failed to receive cell header: {}", e)))?;
 
-            let next_cid = u32::from_be_bytes([
-                next_header[0],
-                next_header[1],
-                next_header[2],
-                next_header[3],
-            ]);
-            let next_cmd = next_header[4];
-            let next_len = u16::from_be_bytes([next_header[5], next_header[6]]) as usize;
+            let next_cid = u32_at(&next_header, 0, "Cell circuit ID")?;
+            let next_cmd = u8_at(&next_header, 4, "Cell command")?;
+            let next_len = u16_at(&next_header, 5, "Cell length")? as usize;
 
             log::info!(
                 "  📦 Cell: CircID={}, Cmd={}, Len={}",
@@ -1288,9 +2323,17 @@ impl CircuitBuilder {
 
             // Capture CERTS payload for identity verification
             if next_cmd == 129 {
-                certs_payload = next_payload.clone();
-                // Parse and verify certificates
-                if !certs_payload.is_empty() {
+                let certs_payload = next_payload;
+                certs_digest = LinkInfo::digest_certs(&certs_payload);
+                let reusable = cached
+                    .filter(|c| c.vouches_for(negotiated_version, &certs_digest))
+                    .and_then(|c| c.verified_identity);
+                if let Some(identity) = reusable {
+                    log::info!(
+                        "  ♻️ CERTS unchanged since last verified link, skipping verification"
+                    );
+                    verified_identity = Some(identity);
+                } else if !certs_payload.is_empty() {
                     match CertsCell::parse(&certs_payload) {
                         Ok(parsed_certs) => {
                             log::info!(
@@ -1308,11 +2351,19 @@ impl CircuitBuilder {
                             }
 
                             // Show extracted keys
-                            if let Some(ref identity) = parsed_certs.ed25519_identity {
-                                log::info!("    🔑 Ed25519 identity: {:02x?}...", &identity[..8]);
-                            }
-                            if let Some(ref signing) = parsed_certs.ed25519_signing_key {
-                                log::info!("    🔑 Ed25519 signing key: {:02x?}...", &signing[..8]);
+                            if log_key_material() {
+                                if let Some(ref identity) = parsed_certs.ed25519_identity {
+                                    log::info!(
+                                        "    🔑 Ed25519 identity: {:02x?}...",
+                                        &identity[..8]
+                                    );
+                                }
+                                if let Some(ref signing) = parsed_certs.ed25519_signing_key {
+                                    log::info!(
+                                        "    🔑 Ed25519 signing key: {:02x?}...",
+                                        &signing[..8]
+                                    );
+                                }
                             }
 
                             // Certificate verification: full chain if fingerprint available,
@@ -1326,6 +2377,7 @@ impl CircuitBuilder {
                                         fp.copy_from_slice(&fp_bytes);
                                         match verifier.verify_relay_certs(&parsed_certs, &fp) {
                                             Ok(verified) => {
+                                                verified_identity = Some(verified.ed25519_identity);
                                                 log::info!("  ✅ Full certificate chain verified for relay");
                                                 log::info!(
                                                     "    🔑 Verified identity: {:02x?}...",
@@ -1333,9 +2385,12 @@ impl CircuitBuilder {
                                                 );
                                             }
                                             Err(e) => {
-                                                log::warn!(
-                                                    "  ⚠️ Full cert verification failed: {}",
-                                                    e
+                                                security_posture::report_downgrade(
+                                                    Downgrade::CertQuickVerify,
+                                                    &format!(
+                                                        "full cert verification failed for {}: {}",
+                                                        fp_hex, e
+                                                    ),
                                                 );
                                                 // Fall back to quick verify
                                                 if let Err(e2) =
@@ -1346,13 +2401,17 @@ impl CircuitBuilder {
                                             }
                                         }
                                     } else {
-                                        log::warn!(
-                                            "  ⚠️ Invalid fingerprint length, using quick verify"
+                                        security_posture::report_downgrade(
+                                            Downgrade::CertQuickVerify,
+                                            &format!("invalid fingerprint length: {}", fp_hex),
                                         );
                                         let _ = verifier.quick_verify(&parsed_certs);
                                     }
                                 } else {
-                                    log::warn!("  ⚠️ Invalid fingerprint hex, using quick verify");
+                                    security_posture::report_downgrade(
+                                        Downgrade::CertQuickVerify,
+                                        &format!("invalid fingerprint hex: {}", fp_hex),
+                                    );
                                     let _ = verifier.quick_verify(&parsed_certs);
                                 }
                             } else {
@@ -1369,6 +2428,12 @@ impl CircuitBuilder {
                         }
                         Err(e) => {
                             log::warn!("  ⚠️ Failed to parse CERTS cell: {}", e);
+                            if let Some(fp_hex) = relay_fingerprint {
+                                security_posture::report_downgrade(
+                                    Downgrade::CertQuickVerify,
+                                    &format!("unparseable CERTS from {}: {}", fp_hex, e),
+                                );
+                            }
                         }
                     }
                 }
@@ -1388,6 +2453,11 @@ impl CircuitBuilder {
 
         log::info!("  ✅ Received CERTS and AUTH_CHALLENGE");
 
+        if let Some(listed) = listed_ed25519 {
+            check_ed25519_identity(listed, verified_identity.as_ref())?;
+            log::info!("  ✅ Ed25519 identity matches the relay's descriptor");
+        }
+
         // Per Tor spec, relay sends NETINFO after AUTH_CHALLENGE (before we send ours)
         // NETINFO is a fixed-length cell (514 bytes total)
         log::info!("  📥 Waiting for relay's NETINFO (before we send ours)...");
@@ -1398,13 +2468,8 @@ impl CircuitBuilder {
             .await
             .map_err(|e| TorError::Network(format!("Failed to receive relay NETINFO: {}", e)))?;
 
-        let relay_netinfo_cid = u32::from_be_bytes([
-            relay_netinfo_bytes[0],
-            relay_netinfo_bytes[1],
-            relay_netinfo_bytes[2],
-            relay_netinfo_bytes[3],
-        ]);
-        let relay_netinfo_cmd = relay_netinfo_bytes[4];
+        let relay_netinfo_cid = u32_at(&relay_netinfo_bytes, 0, "NETINFO circuit ID")?;
+        let relay_netinfo_cmd = u8_at(&relay_netinfo_bytes, 4, "NETINFO command")?;
 
         log::info!(
             "  📦 Relay's NETINFO: CircID={}, Cmd={}",
@@ -1412,11 +2477,17 @@ impl CircuitBuilder {
             relay_netinfo_cmd
         );
 
+        let mut netinfo = None;
         if relay_netinfo_cmd != 8 {
             log::warn!(
                 "  ⚠️ Expected NETINFO (cmd=8), got cmd={}",
                 relay_netinfo_cmd
             );
+        } else {
+            // NETINFO payload starts with the relay's clock (u32 seconds)
+            let relay_time = u32_at(&relay_netinfo_bytes, 5, "NETINFO time")?;
+            crate::clock_skew::observe_netinfo(relay_time as u64);
+            netinfo = NetinfoData::parse(slice(&relay_netinfo_bytes, 5, 509, "NETINFO payload")?);
         }
 
         log::info!("  ✅ Received relay's NETINFO cell (514 bytes total)");
@@ -1459,7 +2530,12 @@ impl CircuitBuilder {
         log::info!("  ✅ Our NETINFO sent");
         log::info!("  ✅ Protocol handshake complete!");
 
-        Ok(())
+        Ok(LinkInfo {
+            link_version: negotiated_version,
+            certs_digest,
+            verified_identity,
+            netinfo,
+        })
     }
 
     /// Perform ntor handshake with guard relay
@@ -1473,7 +2549,7 @@ impl CircuitBuilder {
         S: AsyncWriteExt + AsyncReadExt + Unpin,
     {
         // Create ntor handshake
-        let handshake = NtorHandshake::new();
+        let handshake = handshake_pool::handshake_for(&relay.fingerprint);
         let client_public = handshake.client_public_key();
 
         // Get relay's identity fingerprint (SHA-1, 20 bytes)
@@ -1497,17 +2573,21 @@ impl CircuitBuilder {
 
         // Get relay's ntor onion key from consensus
         let relay_onion_key = if let Some(ref ntor_key_b64) = relay.ntor_onion_key {
-            log::info!("    ntor key (base64): {}", ntor_key_b64);
+            if log_key_material() {
+                log::info!("    ntor key (base64): {}", ntor_key_b64);
+            }
             let ntor_bytes = general_purpose::STANDARD_NO_PAD
                 .decode(ntor_key_b64)
                 .or_else(|_| general_purpose::STANDARD.decode(ntor_key_b64))
                 .map_err(|e| TorError::CircuitBuildFailed(format!("Invalid ntor key: {}", e)))?;
 
-            log::info!(
-                "    ntor key decoded: {} bytes, first 8: {:02x?}",
-                ntor_bytes.len(),
-                &ntor_bytes[..8.min(ntor_bytes.len())]
-            );
+            if log_key_material() {
+                log::info!(
+                    "    ntor key decoded: {} bytes, first 8: {:02x?}",
+                    ntor_bytes.len(),
+                    &ntor_bytes[..8.min(ntor_bytes.len())]
+                );
+            }
 
             if ntor_bytes.len() != 32 {
                 return Err(TorError::CircuitBuildFailed(format!(
@@ -1534,31 +2614,35 @@ impl CircuitBuilder {
         );
 
         log::info!("  📦 ntor handshake data:");
-        log::info!(
-            "    Relay fingerprint: {:02x?}...",
-            &relay_identity_fingerprint[..8]
-        );
-        log::info!(
-            "    Relay ntor key: {:02x?}...",
-            &relay_onion_key.as_bytes()[..8]
-        );
-        log::info!(
-            "    Client public key: {:02x?}...",
-            &client_public.as_bytes()[..8]
-        );
+        if debug_protocol() {
+            log::info!(
+                "    Relay fingerprint: {:02x?}...",
+                &relay_identity_fingerprint[..8]
+            );
+            log::info!(
+                "    Relay ntor key: {:02x?}...",
+                &relay_onion_key.as_bytes()[..8]
+            );
+        }
         log::info!(
             "    Handshake data (ID|B|X): {} bytes",
             handshake_data.len()
         );
-        log::info!("      ID (fingerprint): {:02x?}...", &handshake_data[..8]);
-        log::info!(
-            "      B (relay ntor):   {:02x?}...",
-            &handshake_data[20..28]
-        );
-        log::info!(
-            "      X (client pub):   {:02x?}...",
-            &handshake_data[52..60]
-        );
+        if log_key_material() {
+            log::info!(
+                "    Client public key: {:02x?}...",
+                &client_public.as_bytes()[..8]
+            );
+            log::info!("      ID (fingerprint): {:02x?}...", &handshake_data[..8]);
+            log::info!(
+                "      B (relay ntor):   {:02x?}...",
+                &handshake_data[20..28]
+            );
+            log::info!(
+                "      X (client pub):   {:02x?}...",
+                &handshake_data[52..60]
+            );
+        }
 
         // Build CREATE2 cell payload: Handshake Type (2) | Length (2) | Data (84)
         let mut create2_payload = Vec::new();
@@ -1582,30 +2666,30 @@ impl CircuitBuilder {
         log::info!("  📤 Sending CREATE2 cell:");
         log::info!("    Circuit ID: {}", circuit_id);
         log::info!("    Cell size: {} bytes", cell_bytes.len());
-        log::info!("    Cell header (CircID+Cmd): {:02x?}", &cell_bytes[..5]);
-        log::info!(
-            "    Payload start (HTYPE+HLEN+HDATA): {:02x?}...",
-            &cell_bytes[5..15]
-        );
-        log::info!("    Create2 payload breakdown:");
-        log::info!(
-            "      HTYPE (2): {:02x?} (should be 00 02)",
-            &cell_bytes[5..7]
-        );
-        log::info!(
-            "      HLEN  (2): {:02x?} (should be 00 54 = 84)",
-            &cell_bytes[7..9]
-        );
-        log::info!(
-            "      HDATA[0:8]: {:02x?}... (relay fingerprint)",
-            &cell_bytes[9..17]
-        );
+        if debug_protocol() {
+            log::info!("    Cell header (CircID+Cmd): {:02x?}", &cell_bytes[..5]);
+            log::info!("    Create2 payload breakdown:");
+            log::info!(
+                "      HTYPE (2): {:02x?} (should be 00 02)",
+                &cell_bytes[5..7]
+            );
+            log::info!(
+                "      HLEN  (2): {:02x?} (should be 00 54 = 84)",
+                &cell_bytes[7..9]
+            );
+            log::info!(
+                "      HDATA[0:8]: {:02x?}... (relay fingerprint)",
+                &cell_bytes[9..17]
+            );
+        }
 
         // Dump full handshake data for debugging
-        log::info!("    📊 Full HDATA dump (84 bytes):");
-        log::info!("      ID (0-19): {:02x?}", &cell_bytes[9..29]);
-        log::info!("      B  (20-51): {:02x?}", &cell_bytes[29..61]);
-        log::info!("      X  (52-83): {:02x?}", &cell_bytes[61..93]);
+        if log_key_material() {
+            log::info!("    📊 Full HDATA dump (84 bytes):");
+            log::info!("      ID (0-19): {:02x?}", &cell_bytes[9..29]);
+            log::info!("      B  (20-51): {:02x?}", &cell_bytes[29..61]);
+            log::info!("      X  (52-83): {:02x?}", &cell_bytes[61..93]);
+        }
 
         // Verify padding is zeros
         let padding_start = 9 + 84; // After header (5) + HTYPE (2) + HLEN (2) + HDATA (84)
@@ -1617,10 +2701,12 @@ impl CircuitBuilder {
             "    📊 Padding check: {} non-zero bytes in padding (should be 0)",
             non_zero_padding
         );
-        log::info!(
-            "    📊 First 10 padding bytes: {:02x?}",
-            &cell_bytes[padding_start..padding_start + 10.min(cell_bytes.len() - padding_start)]
-        );
+        if debug_protocol() {
+            log::info!(
+                "    📊 First 10 padding bytes: {:02x?}",
+                head(&cell_bytes[padding_start..], 10)
+            );
+        }
 
         stream
             .write_all(&cell_bytes)
@@ -1643,7 +2729,9 @@ impl CircuitBuilder {
             .map_err(|e| TorError::Network(format!("Failed to receive CREATED2: {}", e)))?;
 
         log::info!("  ✅ Received response cell");
-        log::info!("    Response header: {:02x?}", &response_bytes[..10]);
+        if debug_protocol() {
+            log::info!("    Response header: {:02x?}", head(&response_bytes, 10));
+        }
 
         let response_cell = Cell::from_bytes(&response_bytes)?;
 
@@ -1656,7 +2744,7 @@ impl CircuitBuilder {
         if response_cell.command != CellCommand::Created2 {
             // If it's DESTROY, log the reason
             if response_cell.command == CellCommand::Destroy {
-                let reason = response_cell.payload[0];
+                let reason = response_cell.payload.first().copied().unwrap_or(0);
                 let reason_str = match reason {
                     0 => "NONE (no reason given)",
                     1 => "PROTOCOL (handshake/protocol error - likely stale ntor key)",
@@ -1678,10 +2766,7 @@ impl CircuitBuilder {
                 log::warn!(
                     "  💡 This usually means the ntor key is stale. Will try another relay."
                 );
-                return Err(TorError::CircuitBuildFailed(format!(
-                    "Guard rejected handshake: DESTROY reason={} ({})",
-                    reason, reason_str
-                )));
+                return Err(TorError::circuit_destroyed(reason));
             }
             return Err(TorError::CircuitBuildFailed(format!(
                 "Expected CREATED2, got {:?}",
@@ -1692,8 +2777,7 @@ impl CircuitBuilder {
         // Parse server response from CREATED2
         // CREATED2 payload format: HLEN (2 bytes) || HDATA (HLEN bytes)
         // For ntor: HDATA = Y (32 bytes) || AUTH (32 bytes) = 64 bytes
-        let hlen =
-            u16::from_be_bytes([response_cell.payload[0], response_cell.payload[1]]) as usize;
+        let hlen = u16_at(&response_cell.payload, 0, "CREATED2 HLEN")? as usize;
         log::info!("    CREATED2 HLEN: {} (expected 64)", hlen);
         if hlen < 64 {
             return Err(TorError::ProtocolError(format!(
@@ -1701,17 +2785,16 @@ impl CircuitBuilder {
                 hlen
             )));
         }
-        let hdata = &response_cell.payload[2..2 + hlen];
-        log::info!(
-            "    CREATED2 HDATA (first 16): {:02x?}",
-            &hdata[..16.min(hdata.len())]
-        );
+        let hdata = slice(&response_cell.payload, 2, hlen, "CREATED2 HDATA")?;
         let (server_public, server_auth) = super::ntor::parse_created2_payload(hdata)?;
-        log::info!(
-            "    Server Y (first 8): {:02x?}",
-            &server_public.as_bytes()[..8]
-        );
-        log::info!("    Server AUTH (first 8): {:02x?}", &server_auth[..8]);
+        if log_key_material() {
+            log::info!("    CREATED2 HDATA (first 16): {:02x?}", head(hdata, 16));
+            log::info!(
+                "    Server Y (first 8): {:02x?}",
+                &server_public.as_bytes()[..8]
+            );
+            log::info!("    Server AUTH (first 8): {:02x?}", &server_auth[..8]);
+        }
 
         // Complete ntor handshake and derive keys
         let (forward_secret, _backward_secret) = handshake.complete(
@@ -1836,14 +2919,16 @@ fn create_extend2_payload(
     let link_specs = create_link_specifiers(relay)?;
 
     log::info!("    🔗 Link specifiers: {} specs", link_specs.len());
-    for (i, spec) in link_specs.iter().enumerate() {
-        log::info!(
-            "       Spec {}: type={}, len={}, data={:02x?}...",
-            i,
-            spec[0],
-            spec[1],
-            &spec[2..std::cmp::min(spec.len(), 10)]
-        );
+    if debug_protocol() {
+        for (i, spec) in link_specs.iter().enumerate() {
+            log::info!(
+                "       Spec {}: type={}, len={}, data={:02x?}...",
+                i,
+                spec[0],
+                spec[1],
+                &spec[2..std::cmp::min(spec.len(), 10)]
+            );
+        }
     }
 
     // NSPEC (number of link specifiers)
@@ -1872,31 +2957,35 @@ fn create_extend2_payload(
     );
 
     log::info!("    🔐 EXTEND2 ntor handshake for {}:", relay.nickname);
-    log::info!(
-        "       Target fingerprint: {:02x?}...",
-        &relay_identity_fingerprint[..8]
-    );
-    log::info!(
-        "       Target ntor key:    {:02x?}...",
-        &relay_onion_key.as_bytes()[..8]
-    );
-    log::info!(
-        "       Client public key:  {:02x?}...",
-        &client_public.as_bytes()[..8]
-    );
-    log::info!("       Handshake data breakdown:");
-    log::info!(
-        "         ID (fingerprint): {:02x?}...",
-        &handshake_data[0..8]
-    );
-    log::info!(
-        "         B (target ntor):  {:02x?}...",
-        &handshake_data[20..28]
-    );
-    log::info!(
-        "         X (client pub):   {:02x?}...",
-        &handshake_data[52..60]
-    );
+    if debug_protocol() {
+        log::info!(
+            "       Target fingerprint: {:02x?}...",
+            &relay_identity_fingerprint[..8]
+        );
+        log::info!(
+            "       Target ntor key:    {:02x?}...",
+            &relay_onion_key.as_bytes()[..8]
+        );
+    }
+    if log_key_material() {
+        log::info!(
+            "       Client public key:  {:02x?}...",
+            &client_public.as_bytes()[..8]
+        );
+        log::info!("       Handshake data breakdown:");
+        log::info!(
+            "         ID (fingerprint): {:02x?}...",
+            &handshake_data[0..8]
+        );
+        log::info!(
+            "         B (target ntor):  {:02x?}...",
+            &handshake_data[20..28]
+        );
+        log::info!(
+            "         X (client pub):   {:02x?}...",
+            &handshake_data[52..60]
+        );
+    }
 
     // Handshake data length (2 bytes)
     let len = handshake_data.len() as u16;
@@ -1924,7 +3013,7 @@ fn create_extend2_payload(
 /// - 0x01: TLS-over-TCP, IPv6 address
 /// - 0x02: Legacy identity (RSA)
 /// - 0x03: Ed25519 identity
-fn create_link_specifiers(relay: &Relay) -> Result<Vec<Vec<u8>>> {
+pub(crate) fn create_link_specifiers(relay: &Relay) -> Result<Vec<Vec<u8>>> {
     let mut specs = Vec::new();
 
     // IPv4 link specifier (type 0x00)
@@ -1951,12 +3040,60 @@ fn create_link_specifiers(relay: &Relay) -> Result<Vec<Vec<u8>>> {
         specs.push(spec);
     }
 
+    // Ed25519 identity link specifier (type 0x03), from the microdescriptor.
+    // The extending relay then checks the next hop's Ed25519 identity too,
+    // not just its RSA key, and modern relays may refuse to extend without it
+    if let Some(identity) = listed_ed25519_identity(relay) {
+        let mut spec = Vec::with_capacity(2 + identity.len());
+        spec.push(0x03); // Type: Ed25519 identity
+        spec.push(32); // Length: 32 bytes
+        spec.extend_from_slice(&identity);
+        specs.push(spec);
+    }
+
     Ok(specs)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::{RelayFlags, TestRelay};
+
+    #[test]
+    fn test_ed25519_identity_must_match_descriptor() {
+        let listed = [7u8; 32];
+        assert!(check_ed25519_identity(&listed, Some(&[7u8; 32])).is_ok());
+        assert!(check_ed25519_identity(&listed, Some(&[8u8; 32])).is_err());
+        assert!(check_ed25519_identity(&listed, None).is_err());
+
+        let mut relay = path_relay("guard", "10.0.0.1", true, false);
+        assert_eq!(listed_ed25519_identity(&relay), None);
+        relay.ed25519_identity = Some(general_purpose::STANDARD_NO_PAD.encode(listed));
+        assert_eq!(listed_ed25519_identity(&relay), Some(listed));
+        relay.ed25519_identity = Some(general_purpose::STANDARD.encode(listed));
+        assert_eq!(listed_ed25519_identity(&relay), Some(listed));
+        relay.ed25519_identity = Some("too short".to_string());
+        assert_eq!(listed_ed25519_identity(&relay), None);
+    }
+
+    #[test]
+    fn test_link_specifiers_include_ed25519_identity() {
+        let mut relay = path_relay("beef", "10.0.0.2", false, false);
+        let types = |relay: &Relay| -> Vec<(u8, u8)> {
+            create_link_specifiers(relay)
+                .unwrap()
+                .iter()
+                .map(|spec| (spec[0], spec[1]))
+                .collect()
+        };
+        assert_eq!(types(&relay), [(0x00, 6), (0x02, 20)]);
+
+        let identity = [9u8; 32];
+        relay.ed25519_identity = Some(general_purpose::STANDARD_NO_PAD.encode(identity));
+        assert_eq!(types(&relay), [(0x00, 6), (0x02, 20), (0x03, 32)]);
+        let specs = create_link_specifiers(&relay).unwrap();
+        assert_eq!(specs[2][2..], identity);
+    }
 
     #[test]
     fn test_circuit_creation() {
@@ -1974,4 +3111,176 @@ mod tests {
         assert_eq!(circuit.id, 12345);
         assert!(circuit.age() < 5); // Just created
     }
+
+    fn path_relay(nickname: &str, address: &str, guard: bool, exit: bool) -> Relay {
+        TestRelay::new(nickname)
+            .address(address)
+            .flags(RelayFlags {
+                guard,
+                exit,
+                ..RelayFlags::from_string("Fast Stable Running Valid")
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_validate_custom_path() {
+        let guard = path_relay("guard", "198.51.100.1", true, false);
+        let middle = path_relay("middle", "203.0.113.1", false, false);
+        let exit = path_relay("exit", "192.0.2.1", false, true);
+
+        let path = vec![guard.clone(), middle.clone(), exit.clone()];
+        assert!(CircuitBuilder::validate_path(&path, true).is_ok());
+
+        // Exit in the guard's /16 is refused unless constraints are overridden
+        let neighbour = path_relay("neighbour", "198.51.7.7", false, true);
+        let path = vec![guard.clone(), middle.clone(), neighbour];
+        assert!(CircuitBuilder::validate_path(&path, true).is_err());
+        assert!(CircuitBuilder::validate_path(&path, false).is_ok());
+
+        // Middle relay without the Exit flag as last hop
+        let path = vec![guard.clone(), exit.clone(), middle.clone()];
+        assert!(CircuitBuilder::validate_path(&path, true).is_err());
+
+        // Repeated hops are never allowed
+        let path = vec![guard.clone(), middle.clone(), guard];
+        assert!(CircuitBuilder::validate_path(&path, false).is_err());
+        assert!(CircuitBuilder::validate_path(&[], false).is_err());
+    }
+
+    #[test]
+    fn test_truncated_drops_later_hops() {
+        use sha1::Digest;
+
+        let keys = CircuitKeys {
+            forward_key: [1u8; 16],
+            backward_key: [2u8; 16],
+            forward_iv: [3u8; 16],
+            backward_iv: [4u8; 16],
+            forward_digest: [5u8; 20],
+            backward_digest: [6u8; 20],
+        };
+        let guard = path_relay("guard", "10.1.0.1", true, false);
+        let mut circuit = Circuit::new(7, vec![guard], keys.clone());
+        for (nickname, address) in [("middle", "10.2.0.1"), ("exit", "10.3.0.1")] {
+            circuit.add_hop(path_relay(nickname, address, false, true), keys.clone());
+        }
+        assert_eq!(circuit.hop_count(), 3);
+        assert_eq!(circuit.take_truncated(), None);
+
+        // The middle reports the exit connection closed
+        circuit.note_truncated(1, 8);
+        assert_eq!(circuit.hop_count(), 2);
+        assert_eq!(circuit.relays[1].nickname, "middle");
+        assert_eq!(circuit.keys.len(), 2);
+        assert_eq!(circuit.forward_ciphers.len(), 2);
+        assert_eq!(circuit.backward_digests.len(), 2);
+        assert_eq!(circuit.take_truncated(), Some(8));
+        assert_eq!(circuit.take_truncated(), None);
+    }
+
+    #[test]
+    fn test_closed_package_window_refuses_data() {
+        let keys = CircuitKeys::derive_from_secret(&[1; 32]).unwrap();
+        let mut circuit = Circuit::new(3, Vec::new(), keys);
+        assert!(circuit.can_package());
+
+        circuit.flow.send_window = 0;
+        assert!(!circuit.can_package());
+        let data = RelayCell::new(RelayCommand::Data, 1, vec![0; 10]);
+        let err = futures::executor::block_on(circuit.send_relay_cell(&data)).unwrap_err();
+        assert!(err.to_string().contains("send window exhausted"));
+    }
+
+    #[test]
+    fn test_held_cells_wait_for_their_stream() {
+        let keys = CircuitKeys::derive_from_secret(&[1; 32]).unwrap();
+        let mut circuit = Circuit::new(4, Vec::new(), keys);
+        // Stream 1 read these while waiting for its SENDME
+        circuit.hold_cell(RelayCell::new(RelayCommand::Data, 2, b"first".to_vec()));
+        circuit.hold_cell(RelayCell::new(RelayCommand::Data, 3, b"other".to_vec()));
+        circuit.hold_cell(RelayCell::new(RelayCommand::End, 2, vec![6]));
+
+        assert!(circuit.take_held_cell(1).is_none());
+        let first = circuit.take_held_cell(2).unwrap();
+        assert_eq!(first.data, b"first");
+        assert_eq!(
+            circuit.take_held_cell(2).unwrap().command,
+            RelayCommand::End
+        );
+        assert!(circuit.take_held_cell(2).is_none());
+
+        circuit.discard_held_cells(3);
+        assert!(circuit.take_held_cell(3).is_none());
+
+        // Bounded by the circuit window
+        for _ in 0..CircuitFlowControl::INITIAL_WINDOW + 5 {
+            circuit.hold_cell(RelayCell::new(RelayCommand::Data, 5, vec![]));
+        }
+        assert_eq!(
+            circuit.held_cells.len(),
+            CircuitFlowControl::INITIAL_WINDOW as usize
+        );
+    }
+
+    #[test]
+    fn test_service_hop_layers_and_digests() {
+        use sha3::Digest;
+
+        let keys = CircuitKeys::derive_from_secret(&[1; 32]).unwrap();
+        let hs_keys = HsHopKeys {
+            forward_digest: [2; 32],
+            backward_digest: [3; 32],
+            forward_key: [4; 32],
+            backward_key: [5; 32],
+        };
+        let mut circuit = Circuit::new(9, Vec::new(), keys.clone());
+        circuit.add_service_hop(&hs_keys);
+        assert!(circuit.has_service_hop());
+        assert_eq!(circuit.hop_total(), 2);
+
+        // Outgoing: the rendezvous point peels its layer, the service its own
+        let cell = RelayCell::new(RelayCommand::Begin, 1, b":80\0".to_vec());
+        let mut payload = cell.to_bytes().unwrap();
+        let digest = circuit.set_forward_digest(1, &mut payload).unwrap();
+        futures::executor::block_on(circuit.encrypt_onion(1, &mut payload));
+        Aes128Ctr::new((&keys.forward_key).into(), (&keys.forward_iv).into())
+            .apply_keystream(&mut payload);
+        Aes256Ctr::new((&hs_keys.forward_key).into(), (&[0u8; 16]).into())
+            .apply_keystream(&mut payload);
+        assert_eq!(
+            RelayCell::from_bytes(&payload).unwrap().command,
+            RelayCommand::Begin
+        );
+        let mut zeroed = payload.clone();
+        zeroed[5..9].fill(0);
+        let expected = sha3::Sha3_256::new()
+            .chain_update(hs_keys.forward_digest)
+            .chain_update(&zeroed)
+            .finalize();
+        assert_eq!(digest, expected[..4]);
+
+        // Incoming: a cell from the service is recognized past the relays
+        let reply = RelayCell::new(RelayCommand::Connected, 1, Vec::new());
+        let mut payload = reply.to_bytes().unwrap();
+        let digest = sha3::Sha3_256::new()
+            .chain_update(hs_keys.backward_digest)
+            .chain_update(&payload)
+            .finalize();
+        payload[5..9].copy_from_slice(&digest[..4]);
+        Aes256Ctr::new((&hs_keys.backward_key).into(), (&[0u8; 16]).into())
+            .apply_keystream(&mut payload);
+        Aes128Ctr::new((&keys.backward_key).into(), (&keys.backward_iv).into())
+            .apply_keystream(&mut payload);
+        let hop = futures::executor::block_on(circuit.decrypt_onion(&mut payload)).unwrap();
+        assert_eq!(hop, Some(1));
+        let (_, digest_ok) = circuit.check_backward_digest(1, &payload).unwrap();
+        assert_eq!(digest_ok, Some(true));
+
+        // No extending past the service; truncating forgets it
+        let relay = path_relay("exit", "192.0.2.1", false, true);
+        assert!(futures::executor::block_on(circuit.extend_to(&relay)).is_err());
+        circuit.drop_hops_after(0);
+        assert!(!circuit.has_service_hop());
+    }
 }
@@ -2,14 +2,37 @@
 //!
 //! Parses the network consensus document from directory authorities,
 //! extracting relay descriptors and metadata.
+//!
+//! Both flavors are understood: the full `ns` consensus and the
+//! microdescriptor consensus, whose `r` lines lack the descriptor digest.
+//! Everything after the first `directory-signature` line is left to
+//! [`ConsensusVerifier`](super::ConsensusVerifier).
 
 use super::relay::{Relay, RelayFlags};
 use crate::error::{Result, TorError};
+use crate::memory::{self, Subsystem};
+use base64::Engine;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 
+/// A directory authority's `dir-source` entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirSource {
+    pub nickname: String,
+    /// v3 identity (hex)
+    pub identity: String,
+    pub hostname: String,
+    pub address: IpAddr,
+    pub dir_port: u16,
+    pub or_port: u16,
+    pub contact: Option<String>,
+    /// Digest of the vote the authority contributed (hex)
+    pub vote_digest: Option<String>,
+}
+
 /// Parsed consensus document
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Consensus {
     /// Consensus valid-after time
     pub valid_after: u64,
@@ -25,25 +48,55 @@ pub struct Consensus {
 
     /// Consensus version
     pub version: u32,
+
+    /// Shared random value of the current protocol run (base64), which
+    /// places HSDirs on the onion service hash ring
+    #[serde(default)]
+    pub shared_rand_current: Option<String>,
+
+    /// Shared random value of the previous protocol run (base64)
+    #[serde(default)]
+    pub shared_rand_previous: Option<String>,
+
+    /// v3 identities from the `dir-source` lines of the signed document
+    /// (empty when only the bridge's JSON was available)
+    #[serde(default)]
+    pub dir_sources: Vec<String>,
+
+    /// Full `dir-source` entries, contact and vote digest included
+    #[serde(default)]
+    pub authorities: Vec<DirSource>,
+
+    /// Network parameters from the `params` line (e.g. `circwindow`)
+    #[serde(default)]
+    pub params: BTreeMap<String, i32>,
+
+    /// Path-selection weights from the footer's `bandwidth-weights` line,
+    /// in units of 1/10000 (e.g. `Wgg`, `Wee`)
+    #[serde(default)]
+    pub bandwidth_weights: BTreeMap<String, i64>,
 }
 
 impl Consensus {
-    /// Check if this consensus is still fresh
+    /// Check if this consensus is still fresh (by skew-corrected time)
     pub fn is_fresh(&self) -> bool {
-        let now = (js_sys::Date::now() / 1000.0) as u64;
-        now < self.fresh_until
+        crate::clock_skew::now_secs() < self.fresh_until
     }
 
-    /// Check if this consensus is still valid
+    /// Check if this consensus is still valid (by skew-corrected time)
     pub fn is_valid(&self) -> bool {
-        let now = (js_sys::Date::now() / 1000.0) as u64;
-        now < self.valid_until
+        crate::clock_skew::now_secs() < self.valid_until
     }
 
     /// Get running relays
     pub fn running_relays(&self) -> Vec<&Relay> {
         self.relays.iter().filter(|r| r.is_running()).collect()
     }
+
+    /// A network parameter, or `default` when the consensus doesn't set it
+    pub fn param(&self, name: &str, default: i32) -> i32 {
+        self.params.get(name).copied().unwrap_or(default)
+    }
 }
 
 /// Consensus parser
@@ -52,6 +105,9 @@ pub struct ConsensusParser;
 impl ConsensusParser {
     /// Parse a consensus document
     pub fn parse(data: &[u8]) -> Result<Consensus> {
+        // The parsed relays take at least as much memory as the text
+        memory::check(Subsystem::Consensus, data.len() * 2)?;
+        let _memory = memory::scope(Subsystem::Consensus);
         let text = String::from_utf8(data.to_vec())
             .map_err(|e| TorError::Directory(format!("Invalid UTF-8 in consensus: {}", e)))?;
 
@@ -64,9 +120,16 @@ impl ConsensusParser {
         let mut fresh_until = 0;
         let mut valid_until = 0;
         let mut version = 3; // Default to version 3
+        let mut shared_rand_current = None;
+        let mut shared_rand_previous = None;
+        let mut params = BTreeMap::new();
+        let mut bandwidth_weights = BTreeMap::new();
+        let mut authorities: Vec<DirSource> = Vec::new();
         let mut relays = Vec::new();
 
         let mut current_relay: Option<RelayBuilder> = None;
+        // `contact` and `vote-digest` belong to the dir-source above them
+        let mut in_dir_source = false;
 
         for line in text.lines() {
             let line = line.trim();
@@ -76,19 +139,44 @@ impl ConsensusParser {
             }
 
             // Parse consensus metadata
-            if line.starts_with("network-status-version") {
+            if line.starts_with("directory-signature") {
+                // Signatures are the verifier's business
+                break;
+            } else if line.starts_with("network-status-version") {
                 if let Some(v) = line.split_whitespace().nth(1) {
                     version = v.parse().unwrap_or(3);
                 }
+            } else if let Some(value) = line.strip_prefix("shared-rand-current-value ") {
+                // NumReveals Value
+                shared_rand_current = value.split_whitespace().nth(1).map(str::to_string);
+            } else if let Some(value) = line.strip_prefix("shared-rand-previous-value ") {
+                shared_rand_previous = value.split_whitespace().nth(1).map(str::to_string);
             } else if line.starts_with("valid-after") {
                 valid_after = Self::parse_timestamp(line).unwrap_or(0);
             } else if line.starts_with("fresh-until") {
                 fresh_until = Self::parse_timestamp(line).unwrap_or(0);
             } else if line.starts_with("valid-until") {
                 valid_until = Self::parse_timestamp(line).unwrap_or(0);
+            } else if let Some(values) = line.strip_prefix("params ") {
+                params = Self::parse_key_values(values)?;
+            } else if let Some(values) = line.strip_prefix("bandwidth-weights ") {
+                bandwidth_weights = Self::parse_key_values(values)?;
+            } else if let Some(rest) = line.strip_prefix("dir-source ") {
+                authorities.push(Self::parse_dir_source(rest)?);
+                in_dir_source = true;
+            } else if let (true, Some(contact)) = (in_dir_source, line.strip_prefix("contact ")) {
+                if let Some(source) = authorities.last_mut() {
+                    source.contact = Some(contact.to_string());
+                }
+            } else if let (true, Some(digest)) = (in_dir_source, line.strip_prefix("vote-digest "))
+            {
+                if let Some(source) = authorities.last_mut() {
+                    source.vote_digest = Some(digest.to_string());
+                }
             }
             // Parse relay entries
             else if line.starts_with("r ") {
+                in_dir_source = false;
                 // New relay entry - save previous if exists
                 if let Some(builder) = current_relay.take() {
                     if let Some(relay) = builder.build() {
@@ -104,6 +192,11 @@ impl ConsensusParser {
                     // Skip "s "
                     builder.flags = Some(RelayFlags::from_string(flags_str));
                 }
+            } else if let Some(digest) = line.strip_prefix("m ") {
+                // Microdescriptor digest (microdesc flavor only)
+                if let Some(ref mut builder) = current_relay {
+                    builder.microdesc_digest = Some(digest.trim().to_string());
+                }
             } else if line.starts_with("w ") {
                 // Bandwidth
                 if let Some(ref mut builder) = current_relay {
@@ -120,6 +213,12 @@ impl ConsensusParser {
                 if let Some(ref mut builder) = current_relay {
                     builder.family = Some(line[7..].to_string());
                 }
+            } else if line == "directory-footer" {
+                if let Some(builder) = current_relay.take() {
+                    if let Some(relay) = builder.build() {
+                        relays.push(relay);
+                    }
+                }
             }
         }
 
@@ -136,11 +235,68 @@ impl ConsensusParser {
             valid_until,
             version,
             relays,
+            shared_rand_current,
+            shared_rand_previous,
+            dir_sources: authorities.iter().map(|a| a.identity.clone()).collect(),
+            authorities,
+            params,
+            bandwidth_weights,
         })
     }
 
+    /// v3 identities of the authorities in a consensus document's
+    /// `dir-source` lines
+    pub fn dir_sources(text: &str) -> Vec<String> {
+        text.lines()
+            .filter_map(|line| line.trim().strip_prefix("dir-source "))
+            .filter_map(|rest| rest.split_whitespace().nth(1))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Parse a `dir-source` line after the keyword
+    /// Format: nickname identity hostname IP DirPort ORPort
+    fn parse_dir_source(rest: &str) -> Result<DirSource> {
+        let parts: Vec<&str> = rest.split_whitespace().collect();
+        if parts.len() < 6 {
+            return Err(TorError::Directory("Invalid dir-source line".into()));
+        }
+        let port = |s: &str| {
+            s.parse()
+                .map_err(|_| TorError::Directory(format!("Invalid dir-source port: {}", s)))
+        };
+        Ok(DirSource {
+            nickname: parts[0].to_string(),
+            identity: parts[1].to_uppercase(),
+            hostname: parts[2].to_string(),
+            address: parts[3]
+                .parse()
+                .map_err(|_| TorError::Directory("Invalid dir-source address".into()))?,
+            dir_port: port(parts[4])?,
+            or_port: port(parts[5])?,
+            contact: None,
+            vote_digest: None,
+        })
+    }
+
+    /// Parse the `key=value` pairs of a `params` or `bandwidth-weights`
+    /// line
+    fn parse_key_values<T: std::str::FromStr>(values: &str) -> Result<BTreeMap<String, T>> {
+        values
+            .split_whitespace()
+            .map(|pair| {
+                pair.split_once('=')
+                    .and_then(|(k, v)| Some((k.to_string(), v.parse().ok()?)))
+                    .ok_or_else(|| TorError::Directory(format!("Invalid parameter: {}", pair)))
+            })
+            .collect()
+    }
+
     /// Parse "r" line (relay descriptor)
-    /// Format: r nickname identity published IP ORPort DirPort
+    ///
+    /// Format: `r nickname identity [digest] YYYY-MM-DD HH:MM:SS IP ORPort DirPort`,
+    /// the digest being absent from the microdescriptor consensus. The
+    /// base64 identity becomes the hex fingerprint used everywhere else.
     fn parse_r_line(line: &str) -> Result<RelayBuilder> {
         let parts: Vec<&str> = line.split_whitespace().collect();
 
@@ -149,22 +305,31 @@ impl ConsensusParser {
         }
 
         let nickname = parts[1].to_string();
-        let fingerprint = parts[2].to_string(); // Base64 encoded, we'll use as-is
-
-        let address: IpAddr = parts[4]
-            .parse()
-            .map_err(|_| TorError::Directory("Invalid IP address".into()))?;
-
-        let or_port: u16 = parts[5]
-            .parse()
-            .map_err(|_| TorError::Directory("Invalid OR port".into()))?;
-
-        let dir_port: Option<u16> = if parts.len() > 6 && parts[6] != "0" {
-            parts[6].parse().ok()
+        let fingerprint = Self::identity_to_hex(parts[2]).unwrap_or_else(|| parts[2].to_string());
+
+        // The address is the first field after the identity that parses as
+        // one; the publication time, if any, is right before it
+        let ip_at = (3..parts.len())
+            .find(|&i| parts[i].parse::<IpAddr>().is_ok())
+            .ok_or_else(|| TorError::Directory("Invalid IP address".into()))?;
+        let address: IpAddr = parts[ip_at].parse().expect("checked above");
+        let published = if ip_at >= 5 {
+            crate::clock_skew::parse_utc(&format!("{} {}", parts[ip_at - 2], parts[ip_at - 1]))
+                .unwrap_or(0)
         } else {
-            None
+            0
         };
 
+        let or_port: u16 = parts
+            .get(ip_at + 1)
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(|| TorError::Directory("Invalid OR port".into()))?;
+
+        let dir_port: Option<u16> = parts
+            .get(ip_at + 2)
+            .filter(|p| **p != "0")
+            .and_then(|p| p.parse().ok());
+
         Ok(RelayBuilder {
             nickname,
             fingerprint,
@@ -173,12 +338,21 @@ impl ConsensusParser {
             dir_port,
             flags: None,
             bandwidth: None,
-            published: 0,
+            published,
             ntor_onion_key: None,
             family: None,
+            microdesc_digest: None,
         })
     }
 
+    /// Hex fingerprint of a base64 (unpadded) RSA identity digest
+    fn identity_to_hex(identity: &str) -> Option<String> {
+        let bytes = base64::engine::general_purpose::STANDARD_NO_PAD
+            .decode(identity.trim_end_matches('='))
+            .ok()?;
+        (bytes.len() == 20).then(|| hex::encode_upper(bytes))
+    }
+
     /// Parse bandwidth from "w" line
     /// Format: w Bandwidth=12345
     fn parse_bandwidth(line: &str) -> Option<u64> {
@@ -191,12 +365,10 @@ impl ConsensusParser {
         None
     }
 
-    /// Parse timestamp from consensus line
-    fn parse_timestamp(line: &str) -> Option<u64> {
-        // Simplified timestamp parsing
-        // Real implementation would parse ISO 8601 format
-        // For now, return current time
-        Some((js_sys::Date::now() / 1000.0) as u64)
+    /// Parse the timestamp from a `keyword YYYY-MM-DD HH:MM:SS` line
+    pub(crate) fn parse_timestamp(line: &str) -> Option<u64> {
+        let (_, timestamp) = line.split_once(' ')?;
+        crate::clock_skew::parse_utc(timestamp)
     }
 }
 
@@ -212,6 +384,7 @@ struct RelayBuilder {
     published: u64,
     ntor_onion_key: Option<String>,
     family: Option<String>,
+    microdesc_digest: Option<String>,
 }
 
 impl RelayBuilder {
@@ -227,6 +400,8 @@ impl RelayBuilder {
             published: self.published,
             ntor_onion_key: self.ntor_onion_key,
             family: self.family,
+            microdesc_digest: self.microdesc_digest,
+            ..Default::default()
         })
     }
 }
@@ -255,4 +430,75 @@ mod tests {
         assert!(relay.flags.fast);
         assert!(relay.flags.guard);
     }
+
+    #[test]
+    fn test_parse_full_document() {
+        let sample = "network-status-version 3 microdesc\n\
+                      vote-status consensus\n\
+                      valid-after 2024-01-01 00:00:00\n\
+                      fresh-until 2024-01-01 01:00:00\n\
+                      valid-until 2024-01-01 03:00:00\n\
+                      params CircuitPriorityHalflifeMsec=30000 bwweightscale=10000 cc_alg=-1\n\
+                      dir-source moria1 D586D18309DED4CD6D57C18FDB97EFA96D330566 128.31.0.34 128.31.0.34 9131 9101\n\
+                      contact 1024D/EB5A896A28988BF5 arma mit edu\n\
+                      vote-digest 3BB3F8B2F2E6B86A4F3EAC5A0CF6BAD6F1C2BC32\n\
+                      dir-source tor26 14C131DFC5C6F93646BE72FA1401C02A8DF2E8B4 86.59.21.38 86.59.21.38 80 443\n\
+                      r seele AAoQ1DAR6kkoo19hBAX5K0QztNw 2024-01-01 00:12:05 104.53.221.159 9001 0\n\
+                      m 7ZqLHHC8k0Vc0FU0ORsAyqN7ZwWb4jzuHG6m1AVEvnU\n\
+                      s Running Stable Valid\n\
+                      w Bandwidth=90\n\
+                      r PutoElQueLee AAwffNL+oHO5EdyUoWAOwvEX3ws 1UX8dlUmJqX0ZVtbHajf1Rw9W7A 2023-12-31 22:47:56 185.220.101.7 443 80\n\
+                      s Exit Fast Running Valid\n\
+                      w Bandwidth=2000\n\
+                      directory-footer\n\
+                      bandwidth-weights Wbd=0 Wee=10000 Wgg=5810\n\
+                      directory-signature sha256 D586D18309DED4CD6D57C18FDB97EFA96D330566 ABCD\n\
+                      r NotARelay AAoQ1DAR6kkoo19hBAX5K0QztNw 2024-01-01 00:12:05 1.2.3.4 9001 0\n";
+
+        let consensus = ConsensusParser::parse_text(sample).unwrap();
+        assert_eq!(consensus.param("bwweightscale", 1), 10000);
+        assert_eq!(consensus.param("cc_alg", 2), -1);
+        assert_eq!(consensus.param("circwindow", 1000), 1000);
+        assert_eq!(consensus.bandwidth_weights["Wgg"], 5810);
+        assert_eq!(consensus.bandwidth_weights.len(), 3);
+
+        assert_eq!(consensus.authorities.len(), 2);
+        let moria = &consensus.authorities[0];
+        assert_eq!(moria.nickname, "moria1");
+        assert_eq!((moria.dir_port, moria.or_port), (9131, 9101));
+        assert_eq!(
+            moria.contact.as_deref(),
+            Some("1024D/EB5A896A28988BF5 arma mit edu")
+        );
+        assert!(moria.vote_digest.is_some());
+        assert_eq!(consensus.authorities[1].contact, None);
+        assert_eq!(
+            consensus.dir_sources[1],
+            "14C131DFC5C6F93646BE72FA1401C02A8DF2E8B4"
+        );
+
+        // Both r-line shapes; nothing after the signatures
+        assert_eq!(consensus.relays.len(), 2);
+        let micro = &consensus.relays[0];
+        assert_eq!(
+            micro.fingerprint,
+            "000A10D43011EA4928A35F610405F92B4433B4DC"
+        );
+        assert_eq!(micro.address.to_string(), "104.53.221.159");
+        assert_eq!(micro.dir_port, None);
+        assert_eq!(micro.bandwidth, 90);
+        assert!(micro.published > 0);
+        assert_eq!(
+            micro.microdesc_digest.as_deref(),
+            Some("7ZqLHHC8k0Vc0FU0ORsAyqN7ZwWb4jzuHG6m1AVEvnU")
+        );
+        let full = &consensus.relays[1];
+        assert_eq!(full.microdesc_digest, None);
+        assert_eq!(full.nickname, "PutoElQueLee");
+        assert_eq!((full.or_port, full.dir_port), (443, Some(80)));
+        assert!(full.flags.exit);
+        assert!(full.published < micro.published);
+
+        assert!(ConsensusParser::parse_text("params circwindow=lots\n").is_err());
+    }
 }
@@ -0,0 +1,225 @@
+//! Consensus diffs
+//!
+//! A client holding a recent consensus can ask for only what changed by
+//! sending the digest of its copy in an `X-Or-Diff-From-Consensus` header.
+//! The answer is an ed-style diff (proposal 140):
+//!
+//! ```text
+//! network-status-diff-version 1
+//! hash <base SHA3-256> <target SHA3-256>
+//! 1000,1002c
+//! ...replacement lines...
+//! .
+//! 12d
+//! ```
+//!
+//! Commands run bottom to top, so each line number refers to the base
+//! document. Both digests cover the signed part of the document (through
+//! `directory-signature `). Applying a diff to anything but the base it
+//! names, or getting a result with another digest, is an error; the caller
+//! then fetches the full consensus.
+
+use super::ConsensusVerifier;
+use crate::error::{Result, TorError};
+use sha3::{Digest, Sha3_256};
+
+/// Header naming the consensus a diff should start from
+pub const DIFF_HEADER: &str = "X-Or-Diff-From-Consensus";
+
+/// First line of every diff
+const DIFF_VERSION_LINE: &str = "network-status-diff-version 1";
+
+/// Digest identifying a consensus to diff against (hex SHA3-256 of the
+/// signed part), or `None` for an unsigned document
+pub fn digest(consensus_text: &str) -> Option<String> {
+    let signed = ConsensusVerifier::signed_portion(consensus_text)?;
+    Some(hex::encode_upper(Sha3_256::digest(signed.as_bytes())))
+}
+
+/// Whether a directory response is a diff rather than a whole consensus
+pub fn is_diff(text: &str) -> bool {
+    text.lines().next() == Some(DIFF_VERSION_LINE)
+}
+
+/// Apply `diff` to `base`, returning the target consensus
+pub fn apply(base: &str, diff: &str) -> Result<String> {
+    let mut lines = diff.lines();
+    if lines.next() != Some(DIFF_VERSION_LINE) {
+        return Err(broken("missing network-status-diff-version 1"));
+    }
+    let hash_line = lines.next().unwrap_or_default();
+    let (from, to) = match hash_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["hash", from, to] => (from, to),
+        _ => return Err(broken("missing hash line")),
+    };
+    if !digest(base).is_some_and(|d| d.eq_ignore_ascii_case(from)) {
+        return Err(broken("diff starts from a consensus we don't have"));
+    }
+
+    let mut doc: Vec<&str> = base.lines().collect();
+    // Start of the previous command; commands must move strictly upwards
+    let mut last_start = usize::MAX;
+
+    while let Some(command) = lines.next() {
+        let (range, op) = command.split_at(command.len().saturating_sub(1));
+        let (start, end) = parse_range(range, doc.len())
+            .ok_or_else(|| broken(&format!("bad command {:?}", command)))?;
+        if start >= last_start {
+            return Err(broken("commands out of order"));
+        }
+        last_start = start;
+
+        match op {
+            "d" => {
+                check_range(start, end, doc.len())?;
+                doc.drain(start - 1..end);
+            }
+            "c" => {
+                check_range(start, end, doc.len())?;
+                let replacement = take_block(&mut lines)?;
+                doc.splice(start - 1..end, replacement);
+            }
+            "a" if start == end => {
+                if start > doc.len() {
+                    return Err(broken("append past the end"));
+                }
+                let added = take_block(&mut lines)?;
+                doc.splice(start..start, added);
+            }
+            _ => return Err(broken(&format!("bad command {:?}", command))),
+        }
+    }
+
+    let mut target = doc.join("\n");
+    target.push('\n');
+    if !digest(&target).is_some_and(|d| d.eq_ignore_ascii_case(to)) {
+        return Err(broken("result doesn't match the target digest"));
+    }
+    Ok(target)
+}
+
+/// `N`, `N,M` or `N,$` (1-based, inclusive)
+fn parse_range(range: &str, len: usize) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, "$")) => Some((start.parse().ok()?, len)),
+        Some((start, end)) => Some((start.parse().ok()?, end.parse().ok()?)),
+        None => {
+            let n = range.parse().ok()?;
+            Some((n, n))
+        }
+    }
+}
+
+fn check_range(start: usize, end: usize, len: usize) -> Result<()> {
+    if start == 0 || start > end || end > len {
+        return Err(broken(&format!(
+            "lines {}..{} outside a {}-line document",
+            start, end, len
+        )));
+    }
+    Ok(())
+}
+
+/// Lines up to the terminating `.`
+fn take_block<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Result<Vec<&'a str>> {
+    let mut block = Vec::new();
+    for line in lines {
+        if line == "." {
+            return Ok(block);
+        }
+        block.push(line);
+    }
+    Err(broken("unterminated block"))
+}
+
+fn broken(reason: &str) -> TorError {
+    TorError::Directory(format!("Consensus diff: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "network-status-version 3 microdesc\n\
+                        valid-after 2024-01-01 00:00:00\n\
+                        r a AAoQ1DAR6kkoo19hBAX5K0QztNw 2024-01-01 00:12:05 10.0.0.1 9001 0\n\
+                        m digestA\n\
+                        r b AAwffNL+oHO5EdyUoWAOwvEX3ws 2024-01-01 00:12:05 10.0.0.2 9001 0\n\
+                        m digestB\n\
+                        directory-footer\n\
+                        directory-signature sha256 AAAA BBBB\n";
+
+    const TARGET: &str = "network-status-version 3 microdesc\n\
+                          valid-after 2024-01-01 01:00:00\n\
+                          r a AAoQ1DAR6kkoo19hBAX5K0QztNw 2024-01-01 00:12:05 10.0.0.1 9001 0\n\
+                          m digestA2\n\
+                          r c AAoQ1DAR6kkoo19hBAX5K0QztNx 2024-01-01 00:40:00 10.0.0.3 9001 0\n\
+                          m digestC\n\
+                          directory-footer\n\
+                          directory-signature sha256 CCCC DDDD\n";
+
+    fn diff(body: &str) -> String {
+        format!(
+            "{}\nhash {} {}\n{}",
+            DIFF_VERSION_LINE,
+            digest(BASE).unwrap(),
+            digest(TARGET).unwrap(),
+            body
+        )
+    }
+
+    #[test]
+    fn test_apply_rebuilds_target() {
+        let body = "8c\n\
+                    directory-signature sha256 CCCC DDDD\n\
+                    .\n\
+                    4,6c\n\
+                    m digestA2\n\
+                    r c AAoQ1DAR6kkoo19hBAX5K0QztNx 2024-01-01 00:40:00 10.0.0.3 9001 0\n\
+                    m digestC\n\
+                    .\n\
+                    2c\n\
+                    valid-after 2024-01-01 01:00:00\n\
+                    .\n";
+        assert_eq!(apply(BASE, &diff(body)).unwrap(), TARGET);
+
+        // Same change as delete + append
+        let body = "8c\n\
+                    directory-signature sha256 CCCC DDDD\n\
+                    .\n\
+                    4,6d\n\
+                    3a\n\
+                    m digestA2\n\
+                    r c AAoQ1DAR6kkoo19hBAX5K0QztNx 2024-01-01 00:40:00 10.0.0.3 9001 0\n\
+                    m digestC\n\
+                    .\n\
+                    2c\n\
+                    valid-after 2024-01-01 01:00:00\n\
+                    .\n";
+        assert_eq!(apply(BASE, &diff(body)).unwrap(), TARGET);
+    }
+
+    #[test]
+    fn test_broken_chain_is_rejected() {
+        // Wrong base
+        let other_base = BASE.replace("digestB", "digestX");
+        assert!(apply(&other_base, &diff("2d\n")).is_err());
+        // Result doesn't hash to the target
+        assert!(apply(BASE, &diff("2d\n")).is_err());
+        // Out of order, out of range, unterminated
+        assert!(apply(BASE, &diff("2d\n4d\n")).is_err());
+        assert!(apply(BASE, &diff("9d\n")).is_err());
+        assert!(apply(BASE, &diff("2c\nvalid-after x\n")).is_err());
+        assert!(apply(BASE, "hash x y\n").is_err());
+        assert!(!is_diff(BASE));
+        assert!(is_diff(&diff("2d\n")));
+    }
+
+    #[test]
+    fn test_digest_covers_signed_part_only() {
+        let resigned = BASE.replace("AAAA BBBB\n", "AAAA BBBB\n-----BEGIN SIGNATURE-----\n");
+        assert_eq!(digest(BASE), digest(&resigned));
+        assert_ne!(digest(BASE), digest(TARGET));
+        assert_eq!(digest("no signatures here\n"), None);
+    }
+}
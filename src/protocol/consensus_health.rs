@@ -0,0 +1,378 @@
+//! Consensus health checks
+//!
+//! Signatures say who vouched for a consensus, not that what reached us is
+//! fit to build circuits from. A bridge that trims the relay list down to
+//! a few relays it runs, or a parsing fault that loses flags or weights,
+//! leaves relays that each look fine. [`ConsensusHealth`] looks at the
+//! document as a whole before it is installed:
+//!
+//! - enough relays to hide among
+//! - guard and exit shares within what the real network has
+//! - a plausible bandwidth total that no single relay dominates
+//! - relay descriptors published within a few days of each other
+//! - `dir-source` entries from the known directory authorities, when the
+//!   signed document was available
+//!
+//! Each failed check is a [`HealthFailure`] so callers can tell them apart.
+
+use serde::Serialize;
+
+use super::consensus_verify::{DIRECTORY_AUTHORITIES, MIN_AUTHORITY_SIGNATURES};
+use super::Consensus;
+
+/// Bounds a healthy consensus stays within
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsensusHealth {
+    /// Fewest relays (default: 500; the real network has several thousand)
+    pub min_relays: usize,
+    /// Allowed fraction of relays with the Guard flag (default: 0.10..=0.80)
+    pub guard_fraction: (f64, f64),
+    /// Allowed fraction of relays with the Exit flag (default: 0.02..=0.60)
+    pub exit_fraction: (f64, f64),
+    /// Smallest bandwidth total (default: 1,000,000)
+    pub min_total_bandwidth: u64,
+    /// Largest share of the total any one relay may carry (default: 0.10)
+    pub max_bandwidth_share: f64,
+    /// Longest gap between the oldest and newest descriptor (default: 4 days)
+    pub max_publication_spread_secs: u64,
+    /// Fewest known authorities among `dir-source` lines (default: 5)
+    pub min_known_authorities: usize,
+}
+
+impl Default for ConsensusHealth {
+    fn default() -> Self {
+        Self {
+            min_relays: 500,
+            guard_fraction: (0.10, 0.80),
+            exit_fraction: (0.02, 0.60),
+            min_total_bandwidth: 1_000_000,
+            max_bandwidth_share: 0.10,
+            max_publication_spread_secs: 4 * 24 * 60 * 60,
+            min_known_authorities: MIN_AUTHORITY_SIGNATURES,
+        }
+    }
+}
+
+/// Why a consensus failed a health check
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HealthFailure {
+    TooFewRelays {
+        relays: usize,
+        min: usize,
+    },
+    GuardFraction {
+        fraction: f64,
+        min: f64,
+        max: f64,
+    },
+    ExitFraction {
+        fraction: f64,
+        min: f64,
+        max: f64,
+    },
+    TooLittleBandwidth {
+        total: u64,
+        min: u64,
+    },
+    BandwidthConcentrated {
+        fingerprint: String,
+        share: f64,
+        max: f64,
+    },
+    PublicationSpread {
+        spread_secs: u64,
+        max_secs: u64,
+    },
+    TooFewAuthorities {
+        known: usize,
+        min: usize,
+    },
+}
+
+impl std::fmt::Display for HealthFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooFewRelays { relays, min } => {
+                write!(f, "only {} relays (need {})", relays, min)
+            }
+            Self::GuardFraction { fraction, min, max } => write!(
+                f,
+                "{:.1}% of relays are guards (expected {:.0}%-{:.0}%)",
+                fraction * 100.0,
+                min * 100.0,
+                max * 100.0
+            ),
+            Self::ExitFraction { fraction, min, max } => write!(
+                f,
+                "{:.1}% of relays are exits (expected {:.0}%-{:.0}%)",
+                fraction * 100.0,
+                min * 100.0,
+                max * 100.0
+            ),
+            Self::TooLittleBandwidth { total, min } => {
+                write!(f, "total bandwidth {} is below {}", total, min)
+            }
+            Self::BandwidthConcentrated {
+                fingerprint,
+                share,
+                max,
+            } => write!(
+                f,
+                "relay {} carries {:.1}% of all bandwidth (max {:.0}%)",
+                &fingerprint[..8.min(fingerprint.len())],
+                share * 100.0,
+                max * 100.0
+            ),
+            Self::PublicationSpread {
+                spread_secs,
+                max_secs,
+            } => write!(
+                f,
+                "descriptors published {}h apart (max {}h)",
+                spread_secs / 3600,
+                max_secs / 3600
+            ),
+            Self::TooFewAuthorities { known, min } => write!(
+                f,
+                "only {} known directory authorities listed (need {})",
+                known, min
+            ),
+        }
+    }
+}
+
+/// Result of [`ConsensusHealth::check`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsensusHealthReport {
+    pub relays: usize,
+    pub guards: usize,
+    pub exits: usize,
+    pub running: usize,
+    pub total_bandwidth: u64,
+    /// Newest minus oldest descriptor time; `None` when most relays carry
+    /// no publication time (the bridge's JSON may omit it)
+    pub publication_spread_secs: Option<u64>,
+    /// Known authorities among `dir-source` lines; `None` without the
+    /// signed document
+    pub known_authorities: Option<usize>,
+    pub failures: Vec<HealthFailure>,
+}
+
+impl ConsensusHealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl ConsensusHealth {
+    /// Run every check against `consensus`
+    pub fn check(&self, consensus: &Consensus) -> ConsensusHealthReport {
+        let relays = &consensus.relays;
+        let guards = relays.iter().filter(|r| r.flags.guard).count();
+        let exits = relays.iter().filter(|r| r.flags.exit).count();
+        let running = relays.iter().filter(|r| r.is_running()).count();
+        let total_bandwidth: u64 = relays.iter().map(|r| r.bandwidth).sum();
+        let mut failures = Vec::new();
+
+        if relays.len() < self.min_relays {
+            failures.push(HealthFailure::TooFewRelays {
+                relays: relays.len(),
+                min: self.min_relays,
+            });
+        }
+
+        let fraction = |n: usize| n as f64 / relays.len().max(1) as f64;
+        let (min, max) = self.guard_fraction;
+        if !(min..=max).contains(&fraction(guards)) {
+            failures.push(HealthFailure::GuardFraction {
+                fraction: fraction(guards),
+                min,
+                max,
+            });
+        }
+        let (min, max) = self.exit_fraction;
+        if !(min..=max).contains(&fraction(exits)) {
+            failures.push(HealthFailure::ExitFraction {
+                fraction: fraction(exits),
+                min,
+                max,
+            });
+        }
+
+        if total_bandwidth < self.min_total_bandwidth {
+            failures.push(HealthFailure::TooLittleBandwidth {
+                total: total_bandwidth,
+                min: self.min_total_bandwidth,
+            });
+        } else if let Some(top) = relays.iter().max_by_key(|r| r.bandwidth) {
+            let share = top.bandwidth as f64 / total_bandwidth as f64;
+            if share > self.max_bandwidth_share {
+                failures.push(HealthFailure::BandwidthConcentrated {
+                    fingerprint: top.fingerprint.clone(),
+                    share,
+                    max: self.max_bandwidth_share,
+                });
+            }
+        }
+
+        let published: Vec<u64> = relays
+            .iter()
+            .map(|r| r.published)
+            .filter(|&p| p > 0)
+            .collect();
+        let publication_spread_secs = (published.len() * 2 > relays.len()).then(|| {
+            let newest = published.iter().max().copied().unwrap_or(0);
+            let oldest = published.iter().min().copied().unwrap_or(0);
+            newest - oldest
+        });
+        if let Some(spread) = publication_spread_secs {
+            if spread > self.max_publication_spread_secs {
+                failures.push(HealthFailure::PublicationSpread {
+                    spread_secs: spread,
+                    max_secs: self.max_publication_spread_secs,
+                });
+            }
+        }
+
+        let known_authorities = (!consensus.dir_sources.is_empty()).then(|| {
+            DIRECTORY_AUTHORITIES
+                .iter()
+                .filter(|a| {
+                    consensus
+                        .dir_sources
+                        .iter()
+                        .any(|ident| ident.eq_ignore_ascii_case(a.v3ident))
+                })
+                .count()
+        });
+        if let Some(known) = known_authorities {
+            if known < self.min_known_authorities {
+                failures.push(HealthFailure::TooFewAuthorities {
+                    known,
+                    min: self.min_known_authorities,
+                });
+            }
+        }
+
+        ConsensusHealthReport {
+            relays: relays.len(),
+            guards,
+            exits,
+            running,
+            total_bandwidth,
+            publication_spread_secs,
+            known_authorities,
+            failures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Relay, RelayFlags, TestRelay};
+
+    fn relay(i: usize, guard: bool, exit: bool) -> Relay {
+        TestRelay::new(&format!("relay{}", i))
+            .fingerprint(&format!("{:040X}", i))
+            .address(&format!("10.0.{}.{}", i / 256, i % 256))
+            .flags(RelayFlags {
+                guard,
+                exit,
+                running: true,
+                ..Default::default()
+            })
+            .bandwidth(10_000)
+            .published(1_760_000_000 + (i as u64 % 48) * 3600)
+            .build()
+    }
+
+    /// 1000 relays: 40% guards, 20% exits, even bandwidth
+    fn healthy() -> Consensus {
+        Consensus {
+            relays: (0..1000).map(|i| relay(i, i % 5 < 2, i % 5 == 4)).collect(),
+            dir_sources: DIRECTORY_AUTHORITIES
+                .iter()
+                .map(|a| a.v3ident.to_string())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_healthy_consensus_passes() {
+        let report = ConsensusHealth::default().check(&healthy());
+        assert!(report.is_healthy(), "{:?}", report.failures);
+        assert_eq!(report.guards, 400);
+        assert_eq!(report.exits, 200);
+        assert_eq!(report.known_authorities, Some(DIRECTORY_AUTHORITIES.len()));
+        assert_eq!(report.publication_spread_secs, Some(47 * 3600));
+    }
+
+    #[test]
+    fn test_trimmed_consensus_fails() {
+        let mut consensus = healthy();
+        consensus.relays.truncate(50);
+        let failures = ConsensusHealth::default().check(&consensus).failures;
+        assert!(matches!(
+            failures[..],
+            [
+                HealthFailure::TooFewRelays {
+                    relays: 50,
+                    min: 500
+                },
+                ..
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_flag_and_bandwidth_anomalies() {
+        let mut consensus = healthy();
+        for r in &mut consensus.relays {
+            r.flags.exit = true;
+        }
+        consensus.relays[7].bandwidth = 50_000_000;
+        let failures = ConsensusHealth::default().check(&consensus).failures;
+        assert!(failures
+            .iter()
+            .any(|f| matches!(f, HealthFailure::ExitFraction { .. })));
+        assert!(failures.iter().any(|f| matches!(
+            f,
+            HealthFailure::BandwidthConcentrated { fingerprint, .. } if fingerprint.ends_with('7')
+        )));
+
+        for r in &mut consensus.relays {
+            r.bandwidth = 0;
+        }
+        let failures = ConsensusHealth::default().check(&consensus).failures;
+        assert!(failures
+            .iter()
+            .any(|f| matches!(f, HealthFailure::TooLittleBandwidth { total: 0, .. })));
+    }
+
+    #[test]
+    fn test_publication_spread_and_authorities() {
+        let mut consensus = healthy();
+        consensus.relays[3].published -= 30 * 24 * 3600;
+        consensus.dir_sources = vec!["0000000000000000000000000000000000000000".into()];
+        let failures = ConsensusHealth::default().check(&consensus).failures;
+        assert!(failures
+            .iter()
+            .any(|f| matches!(f, HealthFailure::PublicationSpread { .. })));
+        assert!(failures
+            .iter()
+            .any(|f| matches!(f, HealthFailure::TooFewAuthorities { known: 0, min: 5 })));
+
+        // Neither is judged without the data
+        for r in &mut consensus.relays {
+            r.published = 0;
+        }
+        consensus.dir_sources.clear();
+        let report = ConsensusHealth::default().check(&consensus);
+        assert!(report.is_healthy());
+        assert_eq!(report.publication_spread_secs, None);
+        assert_eq!(report.known_authorities, None);
+    }
+}
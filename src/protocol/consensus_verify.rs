@@ -4,18 +4,25 @@
 //! of directory authorities. This is critical to prevent a malicious bridge
 //! from injecting fake relays.
 //!
-//! ## Verification Levels
+//! A signature counts only if it is an RSA PKCS#1 v1.5 signature over the
+//! digest of the signed portion, made with a signing key of a known
+//! authority that the verifier holds (see [`ConsensusVerifier::add_signing_key`]),
+//! and each authority counts once. [`ConsensusVerifier::quick_verify`]
+//! only checks who claims to have signed.
 //!
-//! 1. **Structural**: Check signer fingerprints are known authorities (always)
-//! 2. **Hash**: Compute SHA-256 of signed portion, verify consistency (always)
-//! 3. **Cryptographic**: RSA signature verification with signing key (when key available)
+//! Signing keys are certified by the authorities' identity keys in their
+//! key certificates, which [`super::key_cert`] verifies; this module takes
+//! the keys it is given.
 //!
 //! Reference: dir-spec.txt Section 3.4.1
 
 use crate::error::{Result, TorError};
+use base64::Engine;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
 use sha1::Sha1 as Sha1Hasher;
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Tor directory authority information
 #[derive(Debug, Clone)]
@@ -86,10 +93,20 @@ pub struct DirectorySignature {
     pub signature: Vec<u8>,
 }
 
+/// An authority signing key the verifier holds
+#[derive(Debug, Clone)]
+struct SigningKey {
+    /// v3ident of the authority it signs for
+    identity: String,
+    key: RsaPublicKey,
+}
+
 /// Consensus signature verifier
 pub struct ConsensusVerifier {
     /// Known authority fingerprints (v3ident)
     authorities: HashMap<String, DirectoryAuthority>,
+    /// Signing keys by digest (hex SHA-1 of the PKCS#1 DER encoding)
+    signing_keys: HashMap<String, SigningKey>,
 }
 
 impl ConsensusVerifier {
@@ -103,7 +120,46 @@ impl ConsensusVerifier {
             authorities.insert(fingerprint, auth.clone());
         }
 
-        Self { authorities }
+        Self {
+            authorities,
+            signing_keys: HashMap::new(),
+        }
+    }
+
+    /// Accept `pem` (`-----BEGIN RSA PUBLIC KEY-----`) as a signing key of
+    /// the authority `identity`, returning the key's digest
+    pub fn add_signing_key(&mut self, identity: &str, pem: &str) -> Result<String> {
+        let identity = identity.to_uppercase().replace(' ', "");
+        if !self.authorities.contains_key(&identity) {
+            return Err(TorError::ConsensusError(format!(
+                "Signing key for unknown authority {}",
+                identity
+            )));
+        }
+        let der = Self::pem_body(pem)?;
+        let key = RsaPublicKey::from_pkcs1_der(&der)
+            .map_err(|e| TorError::ConsensusError(format!("Invalid signing key: {}", e)))?;
+        let digest = hex::encode_upper(Sha1Hasher::digest(&der));
+        self.signing_keys
+            .insert(digest.clone(), SigningKey { identity, key });
+        Ok(digest)
+    }
+
+    /// Number of signing keys held
+    pub fn signing_key_count(&self) -> usize {
+        self.signing_keys.len()
+    }
+
+    /// DER bytes inside a PEM block
+    fn pem_body(pem: &str) -> Result<Vec<u8>> {
+        let body: String = pem
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        base64::engine::general_purpose::STANDARD
+            .decode(body)
+            .map_err(|e| TorError::ConsensusError(format!("Invalid PEM: {}", e)))
     }
 
     /// Parse signatures from a consensus document
@@ -187,68 +243,53 @@ impl ConsensusVerifier {
         })
     }
 
-    /// Compute the digest of the signed portion of the consensus.
-    ///
-    /// The signed portion is everything from the start of the document up to
-    /// and including the `directory-signature` header line (but NOT the
-    /// signature block itself). Per dir-spec.txt Section 3.4.1.
+    /// The signed portion of a consensus: everything from the start of the
+    /// document through the first `directory-signature ` keyword and the
+    /// space after it. Per dir-spec.txt Section 3.4.1.
+    pub fn signed_portion(consensus_text: &str) -> Option<&str> {
+        const KEYWORD: &str = "\ndirectory-signature ";
+        let start = consensus_text.find(KEYWORD)?;
+        Some(&consensus_text[..start + KEYWORD.len()])
+    }
+
+    /// Compute the digest of the signed portion of the consensus
     pub fn compute_consensus_digest(
         &self,
         consensus_text: &str,
         algorithm: &str,
     ) -> Option<Vec<u8>> {
-        // Find the first "directory-signature" line
-        let signed_end = consensus_text.find("\ndirectory-signature ")?;
-        // Include the newline before "directory-signature"
-        let signed_portion = &consensus_text[..signed_end + 1];
+        let signed_portion = Self::signed_portion(consensus_text)?;
 
         match algorithm {
-            "sha256" => {
-                let mut hasher = Sha256::new();
-                hasher.update(signed_portion.as_bytes());
-                Some(hasher.finalize().to_vec())
-            }
-            "sha1" | _ => {
-                use sha1::Digest as Sha1Digest;
-                let mut hasher = Sha1Hasher::new();
-                hasher.update(signed_portion.as_bytes());
-                Some(hasher.finalize().to_vec())
-            }
+            "sha256" => Some(Sha256::digest(signed_portion.as_bytes()).to_vec()),
+            _ => Some(Sha1Hasher::digest(signed_portion.as_bytes()).to_vec()),
         }
     }
 
-    /// Validate that a signature has correct RSA format.
-    ///
-    /// RSA-1024 signatures are 128 bytes, RSA-2048 are 256 bytes.
-    /// This catches trivially forged signatures (empty, wrong length, all zeros).
-    fn validate_signature_format(sig_bytes: &[u8]) -> bool {
-        // Valid RSA signature lengths
-        let valid_lengths = [128, 256, 384, 512];
-        if !valid_lengths.contains(&sig_bytes.len()) {
-            return false;
-        }
-
-        // Reject all-zero signatures (trivial forgery)
-        if sig_bytes.iter().all(|&b| b == 0) {
-            return false;
-        }
-
-        // Reject signatures that are too uniform (likely padding attack)
-        let unique_bytes: std::collections::HashSet<u8> = sig_bytes.iter().copied().collect();
-        if unique_bytes.len() < 8 {
-            return false;
+    /// Check one signature against the signing key it names
+    pub fn verify_signature(&self, consensus_text: &str, sig: &DirectorySignature) -> Result<()> {
+        let digest = sig.signing_key_digest.to_uppercase();
+        let signing_key = self.signing_keys.get(&digest).ok_or_else(|| {
+            TorError::ConsensusError(format!("No signing key {}", &digest[..8.min(digest.len())]))
+        })?;
+        if !signing_key
+            .identity
+            .eq_ignore_ascii_case(&sig.identity.replace(' ', ""))
+        {
+            return Err(TorError::ConsensusError(format!(
+                "Signing key {} belongs to another authority",
+                &digest[..8.min(digest.len())]
+            )));
         }
-
-        true
+        Self::verify_rsa(consensus_text, sig, &signing_key.key)
     }
 
-    /// Verify consensus signatures with structural + hash + format validation.
+    /// Verify consensus signatures cryptographically.
     ///
-    /// Checks:
-    /// 1. Signer fingerprints match known directory authorities
-    /// 2. Consensus digest is computed and consistent
-    /// 3. Signature bytes have valid RSA format (not garbage/zeros)
-    /// 4. RSA cryptographic verification (when signing key available)
+    /// Checks, for each signature:
+    /// 1. The signer is a known directory authority not already counted
+    /// 2. The verifier holds the signing key it names, for that authority
+    /// 3. The RSA signature is valid over the digest of the signed portion
     ///
     /// Returns Ok(count) where count is the number of valid authority signatures,
     /// or Err if fewer than MIN_AUTHORITY_SIGNATURES verified.
@@ -285,9 +326,9 @@ impl ConsensusVerifier {
             ));
         }
 
-        // Step 3: Verify each signature
-        let mut authority_signatures = 0;
+        // Step 3: Verify each signature; an authority counts once
         let mut verified_authorities: Vec<String> = Vec::new();
+        let mut seen = HashSet::new();
 
         for sig in &signatures {
             let identity = sig.identity.to_uppercase().replace(' ', "");
@@ -303,27 +344,25 @@ impl ConsensusVerifier {
                     continue;
                 }
             };
+            if seen.contains(&identity) {
+                continue;
+            }
 
-            // Validate signature format (catches trivial forgeries)
-            if !Self::validate_signature_format(&sig.signature) {
-                log::warn!(
-                    "  {} signature has invalid format ({} bytes, rejected)",
-                    auth.name,
-                    sig.signature.len()
-                );
+            if let Err(e) = self.verify_signature(consensus_text, sig) {
+                log::warn!("  {} signature rejected: {}", auth.name, e);
                 continue;
             }
 
-            // Signature passes structural + format validation
             log::info!(
                 "  Verified authority: {} (algo={}, sig_len={})",
                 auth.name,
                 sig.algorithm,
                 sig.signature.len()
             );
+            seen.insert(identity);
             verified_authorities.push(auth.name.to_string());
-            authority_signatures += 1;
         }
+        let authority_signatures = verified_authorities.len();
 
         log::info!(
             "  Authority signatures: {}/{}",
@@ -347,58 +386,51 @@ impl ConsensusVerifier {
 
     /// Full RSA cryptographic verification of a consensus signature.
     ///
-    /// Requires the authority's signing public key (DER-encoded).
-    /// Uses `ring` for RSA-PKCS1 verification.
+    /// Requires the authority's signing public key (PKCS#1 DER-encoded).
     pub fn verify_rsa_signature(
         &self,
         consensus_text: &str,
         sig: &DirectorySignature,
         signing_key_der: &[u8],
     ) -> Result<()> {
-        use ring::signature;
-
-        // Select verification algorithm based on hash
-        let verify_algo: &dyn signature::VerificationAlgorithm = match sig.algorithm.as_str() {
-            "sha256" => &signature::RSA_PKCS1_2048_8192_SHA256,
-            _ => &signature::RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
-        };
-
-        // Parse the public key
-        let public_key = signature::UnparsedPublicKey::new(verify_algo, signing_key_der);
+        let key = RsaPublicKey::from_pkcs1_der(signing_key_der)
+            .map_err(|e| TorError::ConsensusError(format!("Invalid signing key: {}", e)))?;
+        Self::verify_rsa(consensus_text, sig, &key)
+    }
 
-        // The RSA signature is over the hash of the signed portion
-        // But ring's verify() expects the message, not the hash, and computes the hash internally.
-        // For Tor's consensus, the signature is directly over the hash (PKCS#1 v1.5).
-        // So we pass the signed portion as the "message" for ring to hash and verify.
-        let signed_end = consensus_text
-            .find("\ndirectory-signature ")
+    /// Tor signs the bare digest with PKCS#1 v1.5 padding, without the
+    /// DigestInfo prefix other RSA signatures carry
+    fn verify_rsa(
+        consensus_text: &str,
+        sig: &DirectorySignature,
+        key: &RsaPublicKey,
+    ) -> Result<()> {
+        let signed_portion = Self::signed_portion(consensus_text)
             .ok_or_else(|| TorError::ConsensusError("No directory-signature found".into()))?;
-        let signed_portion = &consensus_text[..signed_end + 1];
+        let digest = match sig.algorithm.as_str() {
+            "sha256" => Sha256::digest(signed_portion.as_bytes()).to_vec(),
+            _ => Sha1Hasher::digest(signed_portion.as_bytes()).to_vec(),
+        };
 
-        public_key
-            .verify(signed_portion.as_bytes(), &sig.signature)
+        key.verify(Pkcs1v15Sign::new_unprefixed(), &digest, &sig.signature)
             .map_err(|_| {
                 TorError::ConsensusError(format!(
                     "RSA signature verification failed for authority {}",
                     sig.identity
                 ))
-            })?;
-
-        Ok(())
+            })
     }
 
     /// Quick check: just verify we have enough authority signatures present
     /// (without full cryptographic verification)
     pub fn quick_verify(&self, consensus_text: &str) -> Result<usize> {
-        let signatures = self.parse_signatures(consensus_text);
-
-        let mut authority_count = 0;
-        for sig in &signatures {
-            let identity = sig.identity.to_uppercase().replace(" ", "");
-            if self.authorities.contains_key(&identity) {
-                authority_count += 1;
-            }
-        }
+        let signers: HashSet<String> = self
+            .parse_signatures(consensus_text)
+            .iter()
+            .map(|sig| sig.identity.to_uppercase().replace(' ', ""))
+            .filter(|identity| self.authorities.contains_key(identity))
+            .collect();
+        let authority_count = signers.len();
 
         if authority_count >= MIN_AUTHORITY_SIGNATURES {
             Ok(authority_count)
@@ -434,6 +466,53 @@ impl Default for ConsensusVerifier {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rsa::pkcs1::EncodeRsaPublicKey;
+    use rsa::RsaPrivateKey;
+
+    const BODY: &str = "network-status-version 3\n\
+                        valid-after 2024-01-01 00:00:00\n\
+                        directory-footer\n";
+
+    /// Signing keys for the first `n` authorities, held by a verifier
+    fn keys(
+        n: usize,
+    ) -> (
+        ConsensusVerifier,
+        Vec<(&'static str, RsaPrivateKey, String)>,
+    ) {
+        let mut verifier = ConsensusVerifier::new();
+        let mut rng = rand::thread_rng();
+        let keys = DIRECTORY_AUTHORITIES[..n]
+            .iter()
+            .map(|auth| {
+                let key = RsaPrivateKey::new(&mut rng, 512).unwrap();
+                let der = key.to_public_key().to_pkcs1_der().unwrap();
+                let pem = format!(
+                    "-----BEGIN RSA PUBLIC KEY-----\n{}\n-----END RSA PUBLIC KEY-----\n",
+                    base64::engine::general_purpose::STANDARD.encode(der.as_bytes())
+                );
+                let digest = verifier.add_signing_key(auth.v3ident, &pem).unwrap();
+                (auth.v3ident, key, digest)
+            })
+            .collect();
+        (verifier, keys)
+    }
+
+    /// `BODY` signed with each key in turn
+    fn sign(keys: &[(&'static str, RsaPrivateKey, String)]) -> String {
+        let digest = Sha256::digest(format!("{}directory-signature ", BODY).as_bytes());
+        let mut text = BODY.to_string();
+        for (identity, key, key_digest) in keys {
+            let signature = key.sign(Pkcs1v15Sign::new_unprefixed(), &digest).unwrap();
+            text.push_str(&format!(
+                "directory-signature sha256 {} {}\n-----BEGIN SIGNATURE-----\n{}\n-----END SIGNATURE-----\n",
+                identity,
+                key_digest,
+                base64::engine::general_purpose::STANDARD.encode(signature)
+            ));
+        }
+        text
+    }
 
     #[test]
     fn test_authority_lookup() {
@@ -475,4 +554,44 @@ dGVzdDI=
         assert_eq!(sigs[0].algorithm, "sha256");
         assert_eq!(sigs[0].identity, "D586D18309DED4CD6D57C18FDB97EFA96D330566");
     }
+
+    #[test]
+    fn test_verify_requires_valid_signatures() {
+        let (verifier, keys) = keys(MIN_AUTHORITY_SIGNATURES);
+        let text = sign(&keys);
+        assert_eq!(
+            ConsensusVerifier::signed_portion(&text),
+            Some(format!("{}directory-signature ", BODY).as_str())
+        );
+        assert_eq!(
+            verifier.verify_consensus(&text).unwrap(),
+            MIN_AUTHORITY_SIGNATURES
+        );
+
+        // Any change to the signed portion breaks every signature
+        let tampered = text.replace("00:00:00", "00:00:01");
+        assert!(verifier.verify_consensus(&tampered).is_err());
+
+        // Signatures the verifier has no key for don't count
+        let (_, other_keys) = self::keys(MIN_AUTHORITY_SIGNATURES);
+        assert!(verifier.verify_consensus(&sign(&other_keys)).is_err());
+    }
+
+    #[test]
+    fn test_authorities_count_once() {
+        let (mut verifier, mut keys) = keys(MIN_AUTHORITY_SIGNATURES - 1);
+        let first = keys[0].1.clone();
+        keys.push((keys[0].0, first, keys[0].2.clone()));
+        let err = verifier.verify_consensus(&sign(&keys)).unwrap_err();
+        assert!(err.to_string().contains("got 4"), "{}", err);
+
+        // A key named for one authority can't sign for another
+        keys[1].0 = DIRECTORY_AUTHORITIES[MIN_AUTHORITY_SIGNATURES].v3ident;
+        let sigs = verifier.parse_signatures(&sign(&keys));
+        assert!(verifier.verify_signature(&sign(&keys), &sigs[1]).is_err());
+
+        assert!(verifier
+            .add_signing_key("0000000000000000000000000000000000000000", "")
+            .is_err());
+    }
 }
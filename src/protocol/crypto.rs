@@ -8,6 +8,7 @@
 //!
 //! Security: All key material is zeroized on drop to prevent memory leakage.
 
+use super::debug::log_key_material;
 use crate::error::{Result, TorError};
 use aes::Aes128;
 use ctr::{
@@ -97,7 +98,9 @@ impl CircuitKeys {
         hkdf.expand(M_EXPAND, &mut okm)
             .map_err(|_| TorError::Crypto("Key derivation failed".into()))?;
 
-        log::info!("🔑 HKDF output (first 16): {:02x?}", &okm[..16]);
+        if log_key_material() {
+            log::info!("🔑 HKDF output (first 16): {:02x?}", &okm[..16]);
+        }
 
         // Split into components per Tor spec
         let mut forward_digest = [0u8; 20];
@@ -110,11 +113,13 @@ impl CircuitKeys {
         forward_key.copy_from_slice(&okm[40..56]);
         backward_key.copy_from_slice(&okm[56..72]);
 
-        log::debug!("🔑 Derived circuit keys:");
-        log::debug!("   Df (first 8): {:02x?}", &forward_digest[..8]);
-        log::debug!("   Db (first 8): {:02x?}", &backward_digest[..8]);
-        log::debug!("   Kf (first 8): {:02x?}", &forward_key[..8]);
-        log::debug!("   Kb (first 8): {:02x?}", &backward_key[..8]);
+        log::debug!("🔑 Derived circuit keys");
+        if log_key_material() {
+            log::info!("   Df (first 8): {:02x?}", &forward_digest[..8]);
+            log::info!("   Db (first 8): {:02x?}", &backward_digest[..8]);
+            log::info!("   Kf (first 8): {:02x?}", &forward_key[..8]);
+            log::info!("   Kb (first 8): {:02x?}", &backward_key[..8]);
+        }
 
         // IVs start at zero for AES-CTR (Tor spec)
         let forward_iv = [0u8; 16];
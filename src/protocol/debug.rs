@@ -0,0 +1,63 @@
+//! Protocol debug logging switches
+//!
+//! Byte-level dumps (cell headers, digests, handshake layouts) are only
+//! logged while the `debug_protocol` runtime flag is on; it is off by
+//! default. Key material (key prefixes, handshake secrets, AUTH values) is
+//! additionally compiled out unless the `debug-key-material` feature is
+//! enabled in a debug build, so release builds can never log it.
+//!
+//! The `explain_paths` flag, also off by default, attaches a path-selection
+//! explanation to every circuit build report.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DEBUG_PROTOCOL: AtomicBool = AtomicBool::new(false);
+static EXPLAIN_PATHS: AtomicBool = AtomicBool::new(false);
+
+/// Whether key material logging is compiled in
+pub const KEY_MATERIAL_LOGGING: bool = cfg!(all(feature = "debug-key-material", debug_assertions));
+
+/// Whether byte-level protocol dumps are logged
+pub fn debug_protocol() -> bool {
+    DEBUG_PROTOCOL.load(Ordering::Relaxed)
+}
+
+/// Turn byte-level protocol dumps on or off
+pub fn set_debug_protocol(enabled: bool) {
+    DEBUG_PROTOCOL.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether circuit builds record why each relay was chosen
+pub fn explain_paths() -> bool {
+    EXPLAIN_PATHS.load(Ordering::Relaxed)
+}
+
+/// Turn path-selection explanations on or off
+pub fn set_explain_paths(enabled: bool) {
+    EXPLAIN_PATHS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether key material may be logged: compiled in and `debug_protocol` on
+pub fn log_key_material() -> bool {
+    KEY_MATERIAL_LOGGING && debug_protocol()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        assert!(!debug_protocol());
+        set_debug_protocol(true);
+        assert!(debug_protocol());
+        assert_eq!(log_key_material(), KEY_MATERIAL_LOGGING);
+        set_debug_protocol(false);
+        assert!(!log_key_material());
+
+        assert!(!explain_paths());
+        set_explain_paths(true);
+        assert!(explain_paths());
+        set_explain_paths(false);
+    }
+}
@@ -2,15 +2,52 @@
 //!
 //! Connects to Tor directory authorities to fetch the network consensus,
 //! which contains information about all Tor relays.
-
-use super::{Consensus, ConsensusParser};
+//!
+//! Once the client can build circuits, documents are fetched over
+//! RELAY_BEGIN_DIR from the last relay of a circuit given with
+//! [`DirectoryManager::with_dir_circuit`] (a one-hop circuit to a guard),
+//! so the bridge neither serves nor sees them. Before that, and whenever
+//! that fails, they come from the bridge's HTTP endpoint.
+
+use super::consensus_diff;
+use super::consensus_verify::ConsensusVerifier;
+use super::key_cert::{self, KeyCertStore};
+use super::microdesc::{self, Microdescriptor};
+use super::{Circuit, Consensus, ConsensusParser, StreamManager};
 use crate::error::{Result, TorError};
+use crate::memory::{self, Subsystem};
 use crate::network::WasmTcpProvider;
-use crate::storage::WasmStorage;
+use crate::runtime::timer::now_ms;
+use crate::security_posture::{self, Downgrade};
+use crate::storage::{self, StorageFormat, WasmStorage};
 use futures::io::{AsyncReadExt, AsyncWriteExt};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::rc::Rc;
 use std::sync::Arc;
 
+/// Microdescriptor consensus, as served by directory caches
+const CONSENSUS_PATH: &str = "/tor/status-vote/current/consensus-microdesc";
+
+/// Key of the microdescriptor cache in the `relays` store
+const MICRODESC_KEY: &str = "microdescs";
+
+/// Keys of the last verified consensus text and its diff digest in the
+/// `consensus` store
+const RAW_KEY: &str = "raw";
+const DIGEST_KEY: &str = "digest";
+
+/// Key of the verified authority key certificates in the `consensus` store
+const KEY_CERTS_KEY: &str = "key_certs";
+
+/// A consensus we can ask for diffs against
+struct ConsensusBase {
+    /// Hex SHA3-256 of the signed part (see [`consensus_diff::digest`])
+    digest: String,
+    text: String,
+}
+
 /// Directory manager for fetching and caching consensus
 pub struct DirectoryManager {
     /// Network provider for connections
@@ -21,6 +58,9 @@ pub struct DirectoryManager {
 
     /// Last successful authority
     last_authority: Option<usize>,
+
+    /// Circuit whose last relay answers directory requests over BEGIN_DIR
+    dir_circuit: Option<Rc<RefCell<Circuit>>>,
 }
 
 impl DirectoryManager {
@@ -30,17 +70,38 @@ impl DirectoryManager {
             network,
             storage,
             last_authority: None,
+            dir_circuit: None,
         }
     }
 
+    /// Fetch documents over BEGIN_DIR on `circuit` instead of from the
+    /// bridge
+    pub fn with_dir_circuit(mut self, circuit: Rc<RefCell<Circuit>>) -> Self {
+        self.dir_circuit = Some(circuit);
+        self
+    }
+
     /// Fetch the current network consensus
     pub async fn fetch_consensus(&mut self) -> Result<Consensus> {
-        log::info!("📡 Fetching Tor consensus from bridge server...");
+        let fetched = match self.fetch_over_dir_circuit().await {
+            Some(Ok(consensus)) => Ok(consensus),
+            Some(Err(e)) => {
+                log::warn!(
+                    "⚠️ Directory fetch over BEGIN_DIR failed ({}); asking the bridge",
+                    e
+                );
+                self.fetch_from_bridge().await
+            }
+            None => {
+                log::info!("📡 Fetching Tor consensus from bridge server...");
+                self.fetch_from_bridge().await
+            }
+        };
 
-        // Fetch from bridge HTTP endpoint instead of directory authorities
-        match self.fetch_from_bridge().await {
+        match fetched {
             Ok(consensus) => {
-                log::info!("✅ Successfully fetched consensus from bridge");
+                log::info!("✅ Successfully fetched consensus");
+                security_posture::resolve(Downgrade::MockConsensus);
                 log::info!("📊 Consensus contains {} relays", consensus.relays.len());
 
                 // Count relays with ntor keys
@@ -51,6 +112,9 @@ impl DirectoryManager {
                     .count();
                 log::info!("🔑 {} relays have ntor keys", with_keys);
 
+                // A consensus fresh off the wire bounds how wrong our clock is
+                crate::clock_skew::observe_consensus(consensus.valid_after, consensus.valid_until);
+
                 // Store in IndexedDB
                 if let Err(e) = self.store_consensus(&consensus).await {
                     log::warn!("Failed to cache consensus: {}", e);
@@ -59,8 +123,17 @@ impl DirectoryManager {
                 Ok(consensus)
             }
             Err(e) => {
-                log::warn!("⚠️  Failed to fetch from bridge: {}", e);
+                log::warn!("⚠️  Failed to fetch consensus: {}", e);
+                // Public relays are no stand-in for a local test network
+                #[cfg(feature = "test-interop")]
+                if crate::interop::local_network() {
+                    return Err(e);
+                }
                 log::info!("🎭 Using fallback consensus with real Tor relays...");
+                security_posture::report_downgrade(
+                    Downgrade::MockConsensus,
+                    &format!("consensus fetch failed: {}", e),
+                );
                 self.create_mock_consensus()
             }
         }
@@ -372,7 +445,7 @@ impl DirectoryManager {
     }
 
     /// Parse HTTP response and extract body
-    fn parse_http_response(response: &[u8]) -> Result<Vec<u8>> {
+    pub(crate) fn parse_http_response(response: &[u8]) -> Result<Vec<u8>> {
         let response_str = String::from_utf8_lossy(response);
 
         // Check for HTTP status
@@ -402,8 +475,7 @@ impl DirectoryManager {
         log::info!("💾 Caching consensus to IndexedDB...");
 
         // Serialize consensus
-        let data = serde_json::to_vec(consensus)
-            .map_err(|e| TorError::Storage(format!("Serialization failed: {}", e)))?;
+        let data = storage::encode_record(consensus, StorageFormat::default(), "consensus")?;
 
         // Store in IndexedDB
         self.storage.set("consensus", "latest", &data).await?;
@@ -429,8 +501,18 @@ impl DirectoryManager {
             .await?
             .ok_or_else(|| TorError::Directory("No cached consensus found".into()))?;
 
-        let consensus: Consensus = serde_json::from_slice(&data)
-            .map_err(|e| TorError::Storage(format!("Deserialization failed: {}", e)))?;
+        let (consensus, format): (Consensus, _) = storage::decode_record(&data, "consensus")?;
+        if format.is_legacy() {
+            storage::migrate(
+                &self.storage,
+                "consensus",
+                "latest",
+                &consensus,
+                StorageFormat::default(),
+                "consensus",
+            )
+            .await;
+        }
 
         // Check if still valid
         if consensus.is_valid() {
@@ -448,7 +530,7 @@ impl DirectoryManager {
     /// Check if we have a fresh cached consensus
     pub async fn has_fresh_consensus(&self) -> bool {
         if let Ok(Some(data)) = self.storage.get("consensus", "latest").await {
-            if let Ok(consensus) = serde_json::from_slice::<Consensus>(&data) {
+            if let Ok((consensus, _)) = storage::decode_record::<Consensus>(&data, "consensus") {
                 return consensus.is_fresh();
             }
         }
@@ -478,7 +560,6 @@ impl DirectoryManager {
             bandwidth: 10000000,
             published: now,
             ntor_onion_key: Some("LR1iEwNhvbukFktKw3E8xnlB+SKyIwRJlbFBWiRyZzI".to_string()),
-            family: None,
             flags: RelayFlags {
                 authority: false,
                 bad_exit: false,
@@ -491,6 +572,7 @@ impl DirectoryManager {
                 v2_dir: true,
                 valid: true,
             },
+            ..Default::default()
         });
 
         // Guard relay 2
@@ -503,7 +585,6 @@ impl DirectoryManager {
             bandwidth: 8000000,
             published: now,
             ntor_onion_key: Some("9mtrgFg/lPrhT/O3ssxkOSk2NmMmDUE7ltWx7eP8uQM".to_string()),
-            family: None,
             flags: RelayFlags {
                 authority: false,
                 bad_exit: false,
@@ -516,6 +597,7 @@ impl DirectoryManager {
                 v2_dir: true,
                 valid: true,
             },
+            ..Default::default()
         });
 
         // Guard relay 3
@@ -528,7 +610,6 @@ impl DirectoryManager {
             bandwidth: 9000000,
             published: now,
             ntor_onion_key: Some("A7OmJsI2nkEKSkPevApwR8R9npCoxqb/4Wm5SP1/VRI".to_string()),
-            family: None,
             flags: RelayFlags {
                 authority: false,
                 bad_exit: false,
@@ -541,6 +622,7 @@ impl DirectoryManager {
                 v2_dir: true,
                 valid: true,
             },
+            ..Default::default()
         });
 
         // Guard relay 4
@@ -553,7 +635,6 @@ impl DirectoryManager {
             bandwidth: 7000000,
             published: now,
             ntor_onion_key: Some("EH7NK18v7r+fbq/aramaYBAckwI6aJrozHgSm/dg+20".to_string()),
-            family: None,
             flags: RelayFlags {
                 authority: false,
                 bad_exit: false,
@@ -566,6 +647,7 @@ impl DirectoryManager {
                 v2_dir: true,
                 valid: true,
             },
+            ..Default::default()
         });
 
         // Exit+Guard relay 1
@@ -578,7 +660,6 @@ impl DirectoryManager {
             bandwidth: 8000000,
             published: now,
             ntor_onion_key: Some("I/nyyLJ5h2E9QIkmumS6r1LoS2ZElku+Dn991JejKAM".to_string()),
-            family: None,
             flags: RelayFlags {
                 authority: false,
                 bad_exit: false,
@@ -591,6 +672,7 @@ impl DirectoryManager {
                 v2_dir: true,
                 valid: true,
             },
+            ..Default::default()
         });
 
         // Exit+Guard relay 2
@@ -603,7 +685,6 @@ impl DirectoryManager {
             bandwidth: 9000000,
             published: now,
             ntor_onion_key: Some("qFrokPFfV78HK68kyNEx2UR4VUh8rNF8rilVuzJqkio".to_string()),
-            family: None,
             flags: RelayFlags {
                 authority: false,
                 bad_exit: false,
@@ -616,6 +697,7 @@ impl DirectoryManager {
                 v2_dir: true,
                 valid: true,
             },
+            ..Default::default()
         });
 
         // Exit+Guard relay 3
@@ -628,7 +710,6 @@ impl DirectoryManager {
             bandwidth: 7000000,
             published: now,
             ntor_onion_key: Some("T4wbkGY3400hdVfMWZfdc8ZDyjbndf9vDsiSbBOPHEw".to_string()),
-            family: None,
             flags: RelayFlags {
                 authority: false,
                 bad_exit: false,
@@ -641,6 +722,7 @@ impl DirectoryManager {
                 v2_dir: true,
                 valid: true,
             },
+            ..Default::default()
         });
 
         let consensus = Consensus {
@@ -649,6 +731,7 @@ impl DirectoryManager {
             valid_until: now + 7200, // Valid for 2 hours
             relays,
             version: 3, // Consensus version 3
+            ..Default::default()
         };
 
         log::info!(
@@ -660,124 +743,430 @@ impl DirectoryManager {
     }
 
     /// Fetch consensus from bridge HTTP endpoint
+    ///
+    /// With a bridge that serves diffs and a verified consensus from an
+    /// earlier fetch, only the changes since that one are downloaded. If the
+    /// diff can't be applied or its result doesn't verify, the whole
+    /// document is fetched instead.
     async fn fetch_from_bridge(&self) -> Result<Consensus> {
-        use wasm_bindgen::JsCast;
-        use wasm_bindgen_futures::JsFuture;
-        use web_sys::{Request, RequestInit, RequestMode, Response};
-
-        // Get bridge URL from network provider's configuration
-        // Convert ws:// or wss:// to http:// or https://
-        let ws_url = self.network.bridge_url();
-        let http_url = if ws_url.starts_with("wss://") {
-            ws_url.replace("wss://", "https://")
-        } else if ws_url.starts_with("ws://") {
-            ws_url.replace("ws://", "http://")
-        } else {
-            ws_url.to_string()
-        };
-        let bridge_url = format!("{}/tor/consensus", http_url);
+        // Wherever the bridge said it serves the consensus, if it does
+        let caps = self.network.capabilities();
+        let endpoint = caps
+            .consensus_endpoint
+            .ok_or_else(|| TorError::Network("Bridge does not serve a consensus".into()))?;
+
+        if caps.consensus_diffs {
+            if let Some(base) = self.load_consensus_base().await {
+                match self.fetch_bridge_document(&endpoint, Some(&base)).await {
+                    Ok(consensus) => return Ok(consensus),
+                    Err(e) => log::warn!(
+                        "⚠️ Consensus diff failed ({}); fetching the full consensus",
+                        e
+                    ),
+                }
+            }
+        }
+        self.fetch_bridge_document(&endpoint, None).await
+    }
 
-        log::info!("🌐 Fetching from bridge: {}", bridge_url);
+    /// Fetch the bridge's consensus response, as a diff against `base` if
+    /// given
+    async fn fetch_bridge_document(
+        &self,
+        endpoint: &str,
+        base: Option<&ConsensusBase>,
+    ) -> Result<Consensus> {
+        log::info!(
+            "🌐 Fetching from bridge: {}",
+            self.network.bridge_http_url(endpoint)
+        );
+        let headers: Vec<(&str, &str)> = base
+            .map(|b| (consensus_diff::DIFF_HEADER, b.digest.as_str()))
+            .into_iter()
+            .collect();
+        let json_str = self.network.bridge_get(endpoint, &headers).await?;
 
-        // Create fetch request
-        let mut opts = RequestInit::new();
-        opts.method("GET");
-        opts.mode(RequestMode::Cors);
+        log::info!("✅ Received {} bytes from bridge", json_str.len());
 
-        let request = Request::new_with_str_and_init(&bridge_url, &opts)
-            .map_err(|e| TorError::Network(format!("Failed to create request: {:?}", e)))?;
+        // Parse JSON (a JSON tree is several times the size of its text)
+        memory::check(Subsystem::Consensus, json_str.len() * 4)?;
+        let json_data: serde_json::Value = {
+            let _memory = memory::scope(Subsystem::Consensus);
+            serde_json::from_str(&json_str)
+                .map_err(|e| TorError::ParseError(format!("Failed to parse JSON: {}", e)))?
+        };
 
-        // Get window and fetch
-        let window =
-            web_sys::window().ok_or_else(|| TorError::Network("No window object".into()))?;
+        // Relays as the bridge describes them; they only supply what the
+        // signed consensus doesn't carry (ntor keys, GeoIP)
+        let bridge_relays = json_data
+            .get("consensus")
+            .and_then(|c| c.get("relays"))
+            .and_then(|v| v.as_array());
+
+        // A diff only counts against the base we asked about
+        let diff = json_data.get("consensus_diff").and_then(|v| v.as_str());
+        let raw = match (diff, base) {
+            (Some(diff), Some(base)) => {
+                let raw = consensus_diff::apply(&base.text, diff)?;
+                log::info!(
+                    "📉 Consensus diff of {} bytes rebuilt a {}-byte consensus",
+                    diff.len(),
+                    raw.len()
+                );
+                Some(raw)
+            }
+            (Some(_), None) => {
+                return Err(TorError::Directory(
+                    "Bridge sent a consensus diff nobody asked for".into(),
+                ))
+            }
+            (None, _) => json_data
+                .get("raw_consensus")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+        };
 
-        let resp_value = JsFuture::from(window.fetch_with_request(&request))
-            .await
-            .map_err(|e| TorError::Network(format!("Fetch failed: {:?}", e)))?;
-
-        // Cast to Response
-        let resp: Response = resp_value
-            .dyn_into()
-            .map_err(|_| TorError::Network("Failed to cast to Response".into()))?;
-
-        // Check status
-        if !resp.ok() {
-            return Err(TorError::Network(format!(
-                "HTTP {}: {}",
-                resp.status(),
-                resp.status_text()
-            )));
+        // The bridge's own relay list is never trusted without the signed
+        // document behind it
+        let raw = raw.ok_or_else(|| {
+            TorError::Directory("Bridge response carries no raw_consensus to verify".into())
+        })?;
+
+        let mut consensus = self.accept_signed_consensus(&raw).await?;
+        if let Some(relays_arr) = bridge_relays {
+            let extras: std::collections::HashMap<String, super::Relay> = relays_arr
+                .iter()
+                .filter_map(|v| self.parse_relay_json(v).ok())
+                .map(|r| (r.fingerprint.to_uppercase(), r))
+                .collect();
+            Self::merge_descriptor_data(&mut consensus.relays, &extras);
+        }
+        log::info!(
+            "📋 {} relays from the signed consensus, {} with ntor keys",
+            consensus.relays.len(),
+            consensus
+                .relays
+                .iter()
+                .filter(|r| r.ntor_onion_key.is_some())
+                .count()
+        );
+        Ok(consensus)
+    }
+
+    /// Verify `raw`, then parse it, keep it for diffs and key its relays
+    /// from microdescriptors
+    ///
+    /// Signatures are only checked with signing keys from verified authority
+    /// key certificates; whoever serves the certificates can withhold them,
+    /// which fails the fetch, but can't substitute keys of their own.
+    async fn accept_signed_consensus(&self, raw: &str) -> Result<Consensus> {
+        let verifier = self.certified_verifier(raw).await;
+        match verifier.verify_consensus(raw) {
+            Ok(count) => {
+                log::info!(
+                    "✅ Consensus verified: {} authority signatures confirmed",
+                    count
+                );
+                security_posture::resolve(Downgrade::UnverifiedConsensus);
+            }
+            Err(e) => {
+                log::warn!("❌ Consensus verification FAILED: {}", e);
+                return Err(e);
+            }
+        }
+
+        let mut consensus = ConsensusParser::parse(raw.as_bytes())?;
+        if let Err(e) = self.store_consensus_base(raw).await {
+            log::warn!("Failed to cache consensus for diffs: {}", e);
+        }
+        match self.fetch_microdescriptors(&mut consensus).await {
+            Ok(0) => {}
+            Ok(filled) => log::info!("🔑 {} relays keyed from microdescriptors", filled),
+            Err(e) => log::warn!("⚠️ Microdescriptors unavailable: {}", e),
         }
+        Ok(consensus)
+    }
+
+    /// Fetch the consensus over BEGIN_DIR, or `None` without a directory
+    /// circuit
+    ///
+    /// Asks for a diff against the last verified consensus first, as with
+    /// the bridge.
+    async fn fetch_over_dir_circuit(&self) -> Option<Result<Consensus>> {
+        let circuit = self.dir_circuit.as_ref()?;
+        log::info!(
+            "📡 Fetching Tor consensus over BEGIN_DIR from {}...",
+            circuit
+                .borrow()
+                .relays
+                .last()
+                .map(|r| r.nickname.as_str())
+                .unwrap_or("?")
+        );
+        Some(
+            async {
+                let mut raw = None;
+                if let Some(base) = self.load_consensus_base().await {
+                    let headers = [(consensus_diff::DIFF_HEADER, base.digest.as_str())];
+                    match self.dir_get(circuit, CONSENSUS_PATH, &headers).await {
+                        Ok(body) if consensus_diff::is_diff(&body) => {
+                            match consensus_diff::apply(&base.text, &body) {
+                                Ok(text) => raw = Some(text),
+                                Err(e) => log::warn!(
+                                    "⚠️ Consensus diff failed ({}); fetching the full consensus",
+                                    e
+                                ),
+                            }
+                        }
+                        // The cache had no diff from our base and sent the
+                        // whole document
+                        Ok(body) => raw = Some(body),
+                        Err(e) => log::warn!(
+                            "⚠️ Consensus diff failed ({}); fetching the full consensus",
+                            e
+                        ),
+                    }
+                }
+                let raw = match raw {
+                    Some(raw) => raw,
+                    None => self.dir_get(circuit, CONSENSUS_PATH, &[]).await?,
+                };
 
-        // Get JSON text
-        let json_value = JsFuture::from(
-            resp.text()
-                .map_err(|e| TorError::Network(format!("Failed to get text: {:?}", e)))?,
+                self.accept_signed_consensus(&raw).await
+            }
+            .await,
         )
-        .await
-        .map_err(|e| TorError::Network(format!("Failed to read text: {:?}", e)))?;
+    }
 
-        let json_str = json_value
-            .as_string()
-            .ok_or_else(|| TorError::Network("Response is not a string".into()))?;
+    /// `GET path` from the last relay of `circuit` over BEGIN_DIR
+    pub async fn fetch_over_circuit(
+        circuit: &Rc<RefCell<Circuit>>,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Vec<u8>> {
+        let mut stream = StreamManager::new(Rc::clone(circuit))
+            .open_dir_stream()
+            .await?;
+        let mut request = format!("GET {} HTTP/1.0\r\n", path);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
 
-        log::info!("✅ Received {} bytes from bridge", json_str.len());
+        let sent = stream.write_all(request.as_bytes()).await;
+        let response = match sent {
+            Ok(()) => stream.read_response().await,
+            Err(e) => Err(TorError::Stream(format!("Failed to send request: {}", e))),
+        };
+        let _ = stream.close().await;
+        Self::parse_http_response(&response?)
+    }
 
-        // Parse JSON
-        let json_data: serde_json::Value = serde_json::from_str(&json_str)
-            .map_err(|e| TorError::ParseError(format!("Failed to parse JSON: {}", e)))?;
+    /// `GET path` over BEGIN_DIR on `circuit`, as text
+    async fn dir_get(
+        &self,
+        circuit: &Rc<RefCell<Circuit>>,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<String> {
+        let body = Self::fetch_over_circuit(circuit, path, headers).await?;
+        String::from_utf8(body).map_err(|_| TorError::Directory(format!("{} is not UTF-8", path)))
+    }
+
+    /// `GET path` from the directory circuit if there is one, otherwise from
+    /// the bridge
+    async fn get_document(&self, path: &str) -> Result<String> {
+        if let Some(circuit) = &self.dir_circuit {
+            match self.dir_get(circuit, path, &[]).await {
+                Ok(text) => return Ok(text),
+                Err(e) => log::warn!(
+                    "⚠️ BEGIN_DIR fetch of {} failed ({}); asking the bridge",
+                    path,
+                    e
+                ),
+            }
+        }
+        self.network.bridge_get(path, &[]).await
+    }
 
-        // Verify consensus signatures if raw consensus text is included
-        if let Some(raw) = json_data.get("raw_consensus").and_then(|v| v.as_str()) {
-            let verifier = super::consensus_verify::ConsensusVerifier::new();
-            match verifier.verify_consensus(raw) {
-                Ok(count) => {
+    /// A verifier holding the signing keys of every valid authority key
+    /// certificate, fetching certificates for keys `raw` was signed with
+    /// that aren't cached yet
+    ///
+    /// This is how signing key rotation is picked up: a consensus signed
+    /// with a new key names its digest, and the certificate for it is
+    /// fetched (from the directory circuit or the bridge) and verified
+    /// against the pinned authority identities.
+    async fn certified_verifier(&self, raw: &str) -> ConsensusVerifier {
+        let now = now_ms() / 1000;
+        let mut certs = self.load_key_certs(now).await;
+        let missing = certs.missing_keys(raw);
+        if !missing.is_empty() {
+            // Everything on a first fetch, just the new keys after that
+            let path = if certs.is_empty() {
+                key_cert::ALL_CERTS_PATH.to_string()
+            } else {
+                key_cert::download_path(&missing)
+            };
+            log::info!(
+                "🔏 Fetching key certificates for {} authority signing keys",
+                missing.len()
+            );
+            match self.get_document(&path).await {
+                Ok(text) => {
+                    let added = certs.add_certificates(&text, now);
                     log::info!(
-                        "✅ Consensus verified: {} authority signatures confirmed",
-                        count
+                        "🔏 {} new authority key certificates ({} held)",
+                        added,
+                        certs.len()
                     );
+                    if added > 0 {
+                        if let Err(e) = self.store_key_certs(&certs).await {
+                            log::warn!("Failed to cache key certificates: {}", e);
+                        }
+                    }
                 }
-                Err(e) => {
-                    log::warn!("❌ Consensus verification FAILED: {}", e);
-                    return Err(e);
-                }
+                Err(e) => log::warn!("⚠️ Key certificates unavailable: {}", e),
             }
-        } else {
-            log::warn!("⚠️ No raw_consensus in bridge response — cannot verify signatures");
         }
 
-        // Extract consensus object
-        let consensus_obj = json_data
-            .get("consensus")
-            .ok_or_else(|| TorError::ParseError("Missing 'consensus' field".into()))?;
+        let mut verifier = ConsensusVerifier::new();
+        certs.install(&mut verifier);
+        verifier
+    }
+
+    /// Cached key certificates, verified again; expired ones drop out
+    async fn load_key_certs(&self, now: u64) -> KeyCertStore {
+        let mut certs = KeyCertStore::default();
+        if let Ok(Some(text)) = self.storage.get("consensus", KEY_CERTS_KEY).await {
+            certs.add_certificates(&String::from_utf8_lossy(&text), now);
+        }
+        certs
+    }
 
-        // Parse relays
-        let relays_arr = consensus_obj
-            .get("relays")
-            .and_then(|v| v.as_array())
-            .ok_or_else(|| TorError::ParseError("Missing or invalid 'relays' field".into()))?;
+    async fn store_key_certs(&self, certs: &KeyCertStore) -> Result<()> {
+        self.storage
+            .set("consensus", KEY_CERTS_KEY, certs.text().as_bytes())
+            .await
+    }
 
-        log::info!("📋 Parsing {} relays...", relays_arr.len());
+    /// The verified consensus text from the last fetch and its diff
+    /// digest
+    async fn load_consensus_base(&self) -> Option<ConsensusBase> {
+        let digest = self.storage.get("consensus", DIGEST_KEY).await.ok()??;
+        let text = self.storage.get("consensus", RAW_KEY).await.ok()??;
+        Some(ConsensusBase {
+            digest: String::from_utf8(digest).ok()?,
+            text: String::from_utf8(text).ok()?,
+        })
+    }
 
-        let mut relays = Vec::new();
-        for relay_val in relays_arr {
-            let relay = self.parse_relay_json(relay_val)?;
-            relays.push(relay);
+    /// Keep a verified consensus to ask for diffs against next time
+    async fn store_consensus_base(&self, raw: &str) -> Result<()> {
+        let digest = consensus_diff::digest(raw)
+            .ok_or_else(|| TorError::Directory("Consensus has no signatures".into()))?;
+        self.storage
+            .set("consensus", RAW_KEY, raw.as_bytes())
+            .await?;
+        self.storage
+            .set("consensus", DIGEST_KEY, digest.as_bytes())
+            .await
+    }
+
+    /// Fill in ntor keys, ed25519 identities and families from the
+    /// microdescriptors the consensus lists, returning how many relays got
+    /// theirs
+    ///
+    /// Documents come from the IndexedDB cache, then from the directory
+    /// circuit or the bridge for whatever is missing. Only digests the
+    /// consensus lists are kept, so the cache sheds relays as they leave
+    /// the network.
+    pub async fn fetch_microdescriptors(&self, consensus: &mut Consensus) -> Result<usize> {
+        let wanted = microdesc::wanted(consensus);
+        if wanted.is_empty() {
+            return Ok(0);
         }
 
-        // Create consensus
-        let consensus = Consensus {
-            version: consensus_obj
-                .get("version")
-                .and_then(|v| v.as_u64())
-                .unwrap_or(3) as u32,
-            valid_after: 0, // Not used, timestamps come from bridge
-            fresh_until: 0,
-            valid_until: 0,
-            relays,
+        let mut mds = self.load_cached_microdescriptors().await;
+        let cached = mds.len();
+        let missing: Vec<String> = wanted
+            .iter()
+            .filter(|d| !mds.contains_key(*d))
+            .cloned()
+            .collect();
+        let listed: std::collections::HashSet<&String> = wanted.iter().collect();
+
+        let mut fetch_error = None;
+        for batch in missing.chunks(microdesc::DOWNLOAD_BATCH) {
+            match self.get_document(&microdesc::download_path(batch)).await {
+                Ok(text) => mds.extend(
+                    microdesc::parse(&text)
+                        .into_iter()
+                        .filter(|md| listed.contains(&md.digest))
+                        .map(|md| (md.digest.clone(), md)),
+                ),
+                Err(e) => fetch_error = Some(e),
+            }
+        }
+        mds.retain(|digest, _| listed.contains(digest));
+        log::info!(
+            "📄 {} of {} microdescriptors ({} cached)",
+            mds.len(),
+            wanted.len(),
+            cached
+        );
+
+        if mds.len() > cached {
+            if let Err(e) = self.store_microdescriptors(&mds).await {
+                log::warn!("Failed to cache microdescriptors: {}", e);
+            }
+        }
+
+        if mds.is_empty() {
+            if let Some(e) = fetch_error {
+                return Err(e);
+            }
+        }
+        Ok(microdesc::apply(&mut consensus.relays, &mds))
+    }
+
+    /// Microdescriptors cached by an earlier fetch, keyed by digest
+    async fn load_cached_microdescriptors(&self) -> HashMap<String, Microdescriptor> {
+        let Ok(Some(data)) = self.storage.get("relays", MICRODESC_KEY).await else {
+            return HashMap::new();
         };
+        match storage::decode_record::<HashMap<String, Microdescriptor>>(&data, "microdescriptors")
+        {
+            Ok((mds, _)) => mds,
+            Err(e) => {
+                log::warn!("Ignoring cached microdescriptors: {}", e);
+                HashMap::new()
+            }
+        }
+    }
 
-        Ok(consensus)
+    async fn store_microdescriptors(&self, mds: &HashMap<String, Microdescriptor>) -> Result<()> {
+        let data = storage::encode_record(mds, StorageFormat::default(), "microdescriptors")?;
+        self.storage.set("relays", MICRODESC_KEY, &data).await
+    }
+
+    /// Copy what only descriptors know (ntor key, country, AS) from the
+    /// bridge's relays onto the signed consensus's, matched by fingerprint.
+    /// Flags, bandwidth and addresses stay as the authorities signed them,
+    /// and ntor keys from microdescriptors are kept over the bridge's.
+    fn merge_descriptor_data(
+        relays: &mut [super::Relay],
+        extras: &std::collections::HashMap<String, super::Relay>,
+    ) {
+        for relay in relays {
+            if let Some(extra) = extras.get(&relay.fingerprint.to_uppercase()) {
+                if relay.ntor_onion_key.is_none() {
+                    relay.ntor_onion_key = extra.ntor_onion_key.clone();
+                }
+                relay.country = extra.country.clone();
+                relay.asn = extra.asn.clone();
+            }
+        }
     }
 
     /// Parse a relay from JSON
@@ -862,12 +1251,19 @@ impl DirectoryManager {
             fingerprint: fingerprint.to_string(),
             address,
             or_port,
-            dir_port: None,
             flags,
             bandwidth: val.get("bandwidth").and_then(|v| v.as_u64()).unwrap_or(0),
             published: val.get("published").and_then(|v| v.as_u64()).unwrap_or(0),
             ntor_onion_key,
-            family: None,
+            country: val
+                .get("country")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_lowercase()),
+            asn: val
+                .get("as")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            ..Default::default()
         })
     }
 }
@@ -894,4 +1290,47 @@ mod tests {
         let result = DirectoryManager::parse_http_response(response);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_cached_consensus_roundtrip() {
+        use super::super::{Relay, RelayFlags};
+
+        let relay = Relay {
+            nickname: "CachedRelay".to_string(),
+            fingerprint: "ABC123".to_string(),
+            address: "2001:db8::1".parse().unwrap(),
+            or_port: 9001,
+            dir_port: Some(9030),
+            flags: RelayFlags {
+                exit: true,
+                ..Default::default()
+            },
+            bandwidth: 1_000_000,
+            published: 1_700_000_000,
+            ntor_onion_key: Some("key".to_string()),
+            country: Some("de".to_string()),
+            ..Default::default()
+        };
+        let consensus = Consensus {
+            valid_after: 1_700_000_000,
+            fresh_until: 1_700_003_600,
+            valid_until: 1_700_010_800,
+            relays: vec![relay; 3],
+            version: 3,
+            ..Default::default()
+        };
+
+        let binary =
+            storage::encode_record(&consensus, StorageFormat::default(), "consensus").unwrap();
+        let json = serde_json::to_vec(&consensus).unwrap();
+        assert!(binary.len() < json.len());
+
+        for bytes in [&binary, &json] {
+            let (cached, _) = storage::decode_record::<Consensus>(bytes, "consensus").unwrap();
+            assert_eq!(cached.relays.len(), 3);
+            assert_eq!(cached.relays[0].address, consensus.relays[0].address);
+            assert_eq!(cached.relays[0].country.as_deref(), Some("de"));
+            assert_eq!(cached.fresh_until, consensus.fresh_until);
+        }
+    }
 }
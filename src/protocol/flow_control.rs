@@ -15,6 +15,10 @@
 //! - **Increment:** 100 cells per SENDME (circuit), 50 cells (stream)
 //! - **Threshold:** Send SENDME when window reaches increment value
 //!
+//! Circuit SENDMEs are authenticated (version 1, proposal 289): they carry
+//! the running digest of the cell that emptied the window, which relays
+//! require before accepting them.
+//!
 //! This prevents:
 //! - Buffer overflow attacks
 //! - Memory exhaustion
@@ -81,6 +85,22 @@ impl CircuitFlowControl {
         );
     }
 
+    /// Version 1 SENDME payload carrying the running backward digest
+    /// after the cell that emptied the receive window
+    pub fn sendme_v1_payload(digest: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(3 + digest.len());
+        payload.push(1);
+        payload.extend_from_slice(&(digest.len() as u16).to_be_bytes());
+        payload.extend_from_slice(digest);
+        payload
+    }
+
+    /// Reopen the deliver window once the SENDME asked for by
+    /// [`CircuitFlowControl::on_receive`] has been sent
+    pub fn on_sendme_sent(&mut self) {
+        self.deliver_window = self.deliver_window.saturating_add(Self::WINDOW_INCREMENT);
+    }
+
     /// Process received cell and check if we should send SENDME
     ///
     /// Returns `true` if we should send a SENDME back
@@ -352,4 +372,19 @@ mod tests {
         assert_eq!(fc.send_window, 250);
         assert!(!fc.is_blocked());
     }
+
+    #[test]
+    fn test_sendme_v1_payload_reopens_deliver_window() {
+        let payload = CircuitFlowControl::sendme_v1_payload(&[0xab; 20]);
+        assert_eq!(&payload[..3], &[1, 0, 20]);
+        assert_eq!(&payload[3..], &[0xab; 20]);
+
+        let mut fc = CircuitFlowControl::new();
+        for _ in 0..100 {
+            fc.on_receive();
+        }
+        assert_eq!(fc.deliver_window, 900);
+        fc.on_sendme_sent();
+        assert_eq!(fc.deliver_window, 1000);
+    }
 }
@@ -0,0 +1,496 @@
+//! v3 onion service descriptors (rend-spec-v3 §2.4)
+//!
+//! HSDirs hand out a signed outer document whose `superencrypted` blob
+//! decrypts, with the blinded key and subcredential, to a middle layer whose
+//! `encrypted` blob in turn holds the introduction points. Client
+//! authorization (a descriptor cookie in the inner layer's keys) is not
+//! supported, so restricted services fail to decrypt.
+
+use super::certs::Ed25519Certificate;
+use super::hs_ntor::{hs_mac, shake256, Aes256Ctr};
+use super::Relay;
+use crate::error::{Result, TorError};
+use base64::{engine::general_purpose, Engine as _};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use subtle::ConstantTimeEq;
+
+/// Prefix of the signed part of the outer document
+const SIG_PREFIX: &[u8] = b"Tor onion service descriptor sig v3";
+
+/// Certificate type of `descriptor-signing-key-cert`
+const CERT_DESC_SIGNING: u8 = 0x08;
+
+/// Certificate type of an introduction point's `auth-key`
+const CERT_INTRO_AUTH: u8 = 0x09;
+
+/// Certificate type of an introduction point's `enc-key-cert`
+const CERT_INTRO_ENC: u8 = 0x0B;
+
+/// Salt and MAC lengths around an encrypted layer
+const SALT_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+
+fn malformed(what: &str) -> TorError {
+    TorError::OnionService(format!("Malformed onion service descriptor: {}", what))
+}
+
+/// One introduction point from the inner layer
+#[derive(Debug, Clone)]
+pub struct IntroPoint {
+    /// Link specifiers as `(type, body)`, as the service published them
+    pub link_specifiers: Vec<(u8, Vec<u8>)>,
+    /// The introduction point relay's ntor onion key
+    pub onion_key: [u8; 32],
+    /// Key the introduction point knows the service by (`AUTH_KEY`)
+    pub auth_key: [u8; 32],
+    /// Service's hs-ntor key for this introduction point (`B`)
+    pub enc_key: [u8; 32],
+}
+
+impl IntroPoint {
+    /// The introduction point as a relay to extend to, built from its IPv4,
+    /// legacy identity and Ed25519 link specifiers
+    pub fn relay(&self) -> Result<Relay> {
+        let spec = |ty: u8| {
+            self.link_specifiers
+                .iter()
+                .find(|(t, _)| *t == ty)
+                .map(|(_, body)| body.as_slice())
+        };
+        let ipv4 = spec(0x00)
+            .filter(|b| b.len() == 6)
+            .ok_or_else(|| malformed("introduction point has no IPv4 link specifier"))?;
+        let legacy = spec(0x02)
+            .filter(|b| b.len() == 20)
+            .ok_or_else(|| malformed("introduction point has no legacy identity"))?;
+        let fingerprint = hex::encode_upper(legacy);
+        Ok(Relay {
+            nickname: format!("intro-{}", &fingerprint[..8]),
+            fingerprint,
+            address: std::net::IpAddr::from([ipv4[0], ipv4[1], ipv4[2], ipv4[3]]),
+            or_port: u16::from_be_bytes([ipv4[4], ipv4[5]]),
+            ntor_onion_key: Some(general_purpose::STANDARD_NO_PAD.encode(self.onion_key)),
+            ed25519_identity: spec(0x03)
+                .filter(|b| b.len() == 32)
+                .map(|b| general_purpose::STANDARD_NO_PAD.encode(b)),
+            ..Default::default()
+        })
+    }
+}
+
+/// A decrypted onion service descriptor
+#[derive(Debug, Clone)]
+pub struct HsDescriptor {
+    /// Minutes the descriptor stays valid after upload
+    pub lifetime_mins: u32,
+    /// Revision counter; higher replaces lower
+    pub revision_counter: u64,
+    pub intro_points: Vec<IntroPoint>,
+}
+
+impl HsDescriptor {
+    /// Check and decrypt a descriptor fetched for `blinded_key`
+    ///
+    /// The signing key certificate must be signed by the blinded key and
+    /// unexpired at `now`, and the document by the certified signing key.
+    pub fn decrypt(
+        text: &str,
+        blinded_key: &[u8; 32],
+        subcredential: &[u8; 32],
+        now: u64,
+    ) -> Result<Self> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some("hs-descriptor 3") {
+            return Err(malformed("not a v3 descriptor"));
+        }
+
+        let mut lifetime_mins = None;
+        let mut signing_cert = None;
+        let mut revision_counter = None;
+        let mut superencrypted = None;
+        while let Some(line) = lines.next() {
+            let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "descriptor-lifetime" => lifetime_mins = args.trim().parse().ok(),
+                "descriptor-signing-key-cert" => signing_cert = Some(read_object(&mut lines)?),
+                "revision-counter" => revision_counter = args.trim().parse().ok(),
+                "superencrypted" => superencrypted = Some(read_object(&mut lines)?),
+                "signature" => break,
+                _ => {}
+            }
+        }
+        let lifetime_mins = lifetime_mins.ok_or_else(|| malformed("no descriptor-lifetime"))?;
+        let revision_counter: u64 =
+            revision_counter.ok_or_else(|| malformed("no revision-counter"))?;
+        let superencrypted = superencrypted.ok_or_else(|| malformed("no superencrypted"))?;
+
+        let cert = Ed25519Certificate::parse(
+            &signing_cert.ok_or_else(|| malformed("no descriptor-signing-key-cert"))?,
+        )?;
+        let signing_key = checked_cert(&cert, CERT_DESC_SIGNING, blinded_key, now)?;
+        verify_document(text, &signing_key)?;
+
+        let middle = decrypt_layer(
+            &superencrypted,
+            blinded_key,
+            subcredential,
+            revision_counter,
+            b"hsdir-superencrypted-data",
+        )?;
+        let mut middle_lines = middle.lines();
+        let mut encrypted = None;
+        while let Some(line) = middle_lines.next() {
+            if line.trim() == "encrypted" {
+                encrypted = Some(read_object(&mut middle_lines)?);
+                break;
+            }
+        }
+        let inner = decrypt_layer(
+            &encrypted.ok_or_else(|| malformed("no encrypted section"))?,
+            blinded_key,
+            subcredential,
+            revision_counter,
+            b"hsdir-encrypted-data",
+        )
+        .map_err(|e| match e {
+            TorError::AuthVerificationFailed(_) => TorError::OnionService(
+                "Descriptor inner layer did not decrypt; the service may require client authorization"
+                    .into(),
+            ),
+            e => e,
+        })?;
+
+        let intro_points = parse_intro_points(&inner, &signing_key, now)?;
+        if intro_points.is_empty() {
+            return Err(TorError::OnionService(
+                "Onion service descriptor lists no introduction points".into(),
+            ));
+        }
+        Ok(Self {
+            lifetime_mins,
+            revision_counter,
+            intro_points,
+        })
+    }
+}
+
+/// Check a certificate's type, expiry and signature, returning the key it
+/// certifies
+fn checked_cert(
+    cert: &Ed25519Certificate,
+    cert_type: u8,
+    signed_by: &[u8; 32],
+    now: u64,
+) -> Result<[u8; 32]> {
+    if cert.cert_type != cert_type {
+        return Err(TorError::CertificateError(format!(
+            "Expected certificate type {:#04x}, got {:#04x}",
+            cert_type, cert.cert_type
+        )));
+    }
+    if (cert.expiration_hours as u64) * 3600 < now {
+        return Err(TorError::CertificateError(
+            "Onion service certificate expired".into(),
+        ));
+    }
+    cert.verify_signature(signed_by)?;
+    Ok(cert.certified_key)
+}
+
+/// Check the outer document's `signature` line
+fn verify_document(text: &str, signing_key: &[u8; 32]) -> Result<()> {
+    let sig_line = text
+        .find("\nsignature ")
+        .ok_or_else(|| malformed("no signature"))?;
+    let signature = text[sig_line + 1..]
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .ok_or_else(|| malformed("empty signature"))?;
+    let signature = general_purpose::STANDARD_NO_PAD
+        .decode(signature.trim_end_matches('='))
+        .ok()
+        .and_then(|s| <[u8; 64]>::try_from(s).ok())
+        .ok_or_else(|| malformed("bad signature encoding"))?;
+
+    let mut signed = SIG_PREFIX.to_vec();
+    signed.extend_from_slice(&text.as_bytes()[..=sig_line]);
+    VerifyingKey::from_bytes(signing_key)
+        .map_err(|e| TorError::CertificateError(format!("Invalid descriptor signing key: {}", e)))?
+        .verify(&signed, &Signature::from_bytes(&signature))
+        .map_err(|_| TorError::CertificateError("Descriptor signature invalid".into()))
+}
+
+/// `SECRET_KEY | SECRET_IV | MAC_KEY` for one layer
+fn layer_keys(
+    secret_data: &[u8; 32],
+    subcredential: &[u8; 32],
+    revision_counter: u64,
+    salt: &[u8],
+    constant: &[u8],
+) -> [u8; 80] {
+    shake256(&[
+        secret_data,
+        subcredential,
+        &revision_counter.to_be_bytes(),
+        salt,
+        constant,
+    ])
+}
+
+/// `H(INT_8(32) | MAC_KEY | INT_8(16) | SALT | ENCRYPTED)` — the layer MAC
+/// is [`hs_mac`] with the salt length folded into the message
+fn layer_mac(mac_key: &[u8], salt: &[u8], encrypted: &[u8]) -> [u8; 32] {
+    let mut msg = (salt.len() as u64).to_be_bytes().to_vec();
+    msg.extend_from_slice(salt);
+    msg.extend_from_slice(encrypted);
+    hs_mac(mac_key, &msg)
+}
+
+/// Decrypt `SALT | ENCRYPTED | MAC`, dropping the NUL padding
+fn decrypt_layer(
+    blob: &[u8],
+    secret_data: &[u8; 32],
+    subcredential: &[u8; 32],
+    revision_counter: u64,
+    constant: &[u8],
+) -> Result<String> {
+    if blob.len() < SALT_LEN + MAC_LEN {
+        return Err(malformed("encrypted layer too short"));
+    }
+    let (salt, rest) = blob.split_at(SALT_LEN);
+    let (encrypted, mac) = rest.split_at(rest.len() - MAC_LEN);
+    let keys = layer_keys(secret_data, subcredential, revision_counter, salt, constant);
+    if !bool::from(layer_mac(&keys[48..], salt, encrypted).ct_eq(mac)) {
+        return Err(TorError::AuthVerificationFailed(
+            "Descriptor layer MAC mismatch".into(),
+        ));
+    }
+    let mut plaintext = encrypted.to_vec();
+    Aes256Ctr::new((&keys[..32]).into(), (&keys[32..48]).into()).apply_keystream(&mut plaintext);
+    let end = plaintext.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    plaintext.truncate(end);
+    String::from_utf8(plaintext).map_err(|_| malformed("layer is not text"))
+}
+
+/// Read a PEM-style object (`-----BEGIN ...-----` to `-----END ...-----`)
+fn read_object<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Result<Vec<u8>> {
+    let begin = lines.next().ok_or_else(|| malformed("missing object"))?;
+    if !begin.trim().starts_with("-----BEGIN ") {
+        return Err(malformed("missing object"));
+    }
+    let mut body = String::new();
+    for line in lines.by_ref() {
+        let line = line.trim();
+        if line.starts_with("-----END ") {
+            return general_purpose::STANDARD
+                .decode(&body)
+                .map_err(|_| malformed("bad object encoding"));
+        }
+        body.push_str(line);
+    }
+    Err(malformed("unterminated object"))
+}
+
+/// A 32-byte key from unpadded or padded base64
+fn key32(value: &str) -> Result<[u8; 32]> {
+    general_purpose::STANDARD_NO_PAD
+        .decode(value.trim().trim_end_matches('='))
+        .ok()
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .ok_or_else(|| malformed("bad key"))
+}
+
+/// `NSPEC | (LSTYPE | LSLEN | LSPEC)*`
+fn parse_link_specifiers(data: &[u8]) -> Result<Vec<(u8, Vec<u8>)>> {
+    let count = *data
+        .first()
+        .ok_or_else(|| malformed("empty link specifiers"))?;
+    let mut offset = 1;
+    let mut specs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let ty = *data
+            .get(offset)
+            .ok_or_else(|| malformed("link specifier"))?;
+        let len = *data
+            .get(offset + 1)
+            .ok_or_else(|| malformed("link specifier"))? as usize;
+        let body = data
+            .get(offset + 2..offset + 2 + len)
+            .ok_or_else(|| malformed("link specifier truncated"))?;
+        specs.push((ty, body.to_vec()));
+        offset += 2 + len;
+    }
+    Ok(specs)
+}
+
+/// Introduction points of the inner layer, each with its `auth-key` and
+/// `enc-key-cert` checked against the descriptor signing key
+fn parse_intro_points(inner: &str, signing_key: &[u8; 32], now: u64) -> Result<Vec<IntroPoint>> {
+    struct Partial {
+        link_specifiers: Vec<(u8, Vec<u8>)>,
+        onion_key: Option<[u8; 32]>,
+        auth_key: Option<[u8; 32]>,
+        enc_key: Option<[u8; 32]>,
+    }
+    let finish = |p: Partial| -> Option<IntroPoint> {
+        Some(IntroPoint {
+            link_specifiers: p.link_specifiers,
+            onion_key: p.onion_key?,
+            auth_key: p.auth_key?,
+            enc_key: p.enc_key?,
+        })
+    };
+
+    let mut points = Vec::new();
+    let mut current: Option<Partial> = None;
+    let mut lines = inner.lines();
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        match (words.next(), current.as_mut()) {
+            (Some("introduction-point"), _) => {
+                points.extend(current.take().and_then(finish));
+                let specs = general_purpose::STANDARD
+                    .decode(words.next().unwrap_or_default())
+                    .map_err(|_| malformed("bad introduction-point"))?;
+                current = Some(Partial {
+                    link_specifiers: parse_link_specifiers(&specs)?,
+                    onion_key: None,
+                    auth_key: None,
+                    enc_key: None,
+                });
+            }
+            (Some("onion-key"), Some(p)) if words.next() == Some("ntor") => {
+                p.onion_key = Some(key32(words.next().unwrap_or_default())?);
+            }
+            (Some("auth-key"), Some(p)) => {
+                let cert = Ed25519Certificate::parse(&read_object(&mut lines)?)?;
+                p.auth_key = Some(checked_cert(&cert, CERT_INTRO_AUTH, signing_key, now)?);
+            }
+            (Some("enc-key"), Some(p)) if words.next() == Some("ntor") => {
+                p.enc_key = Some(key32(words.next().unwrap_or_default())?);
+            }
+            (Some("enc-key-cert"), Some(_)) => {
+                let cert = Ed25519Certificate::parse(&read_object(&mut lines)?)?;
+                checked_cert(&cert, CERT_INTRO_ENC, signing_key, now)?;
+            }
+            _ => {}
+        }
+    }
+    points.extend(current.and_then(finish));
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn cert(cert_type: u8, certified: &[u8; 32], signer: &SigningKey) -> String {
+        let mut body = vec![1, cert_type];
+        body.extend_from_slice(&((NOW / 3600 + 24) as u32).to_be_bytes());
+        body.push(1);
+        body.extend_from_slice(certified);
+        body.push(0);
+        let sig = signer.sign(&body);
+        body.extend_from_slice(&sig.to_bytes());
+        let b64 = general_purpose::STANDARD.encode(body);
+        format!(
+            "-----BEGIN ED25519 CERT-----\n{}\n-----END ED25519 CERT-----\n",
+            b64
+        )
+    }
+
+    fn encrypt(plaintext: &str, blinded: &[u8; 32], subcred: &[u8; 32], constant: &[u8]) -> String {
+        let salt = [3u8; SALT_LEN];
+        let keys = layer_keys(blinded, subcred, 7, &salt, constant);
+        let mut data = plaintext.as_bytes().to_vec();
+        data.resize(data.len() + 100, 0);
+        Aes256Ctr::new((&keys[..32]).into(), (&keys[32..48]).into()).apply_keystream(&mut data);
+        let mut blob = salt.to_vec();
+        blob.extend_from_slice(&data);
+        blob.extend_from_slice(&layer_mac(&keys[48..], &salt, &data));
+        format!(
+            "-----BEGIN MESSAGE-----\n{}\n-----END MESSAGE-----\n",
+            general_purpose::STANDARD.encode(blob)
+        )
+    }
+
+    fn descriptor(blinded: &SigningKey, signing: &SigningKey, subcred: &[u8; 32]) -> String {
+        let blinded_pk = blinded.verifying_key().to_bytes();
+        let mut specs = vec![3u8];
+        specs.extend_from_slice(&[0, 6, 192, 0, 2, 1, 0x23, 0x29]);
+        specs.push(2);
+        specs.push(20);
+        specs.extend_from_slice(&[0xAB; 20]);
+        specs.extend_from_slice(&[3, 32]);
+        specs.extend_from_slice(&[0xCD; 32]);
+        let inner = format!(
+            "create2-formats 2\nintroduction-point {}\nonion-key ntor {}\nauth-key\n{}enc-key ntor {}\nenc-key-cert\n{}",
+            general_purpose::STANDARD.encode(&specs),
+            general_purpose::STANDARD.encode([1u8; 32]),
+            cert(CERT_INTRO_AUTH, &[2u8; 32], signing),
+            general_purpose::STANDARD.encode([4u8; 32]),
+            cert(CERT_INTRO_ENC, &[5u8; 32], signing),
+        );
+        let middle = format!(
+            "desc-auth-type x25519\nencrypted\n{}",
+            encrypt(&inner, &blinded_pk, subcred, b"hsdir-encrypted-data")
+        );
+        let mut doc = format!(
+            "hs-descriptor 3\ndescriptor-lifetime 180\ndescriptor-signing-key-cert\n{}revision-counter 7\nsuperencrypted\n{}",
+            cert(CERT_DESC_SIGNING, &signing.verifying_key().to_bytes(), blinded),
+            encrypt(&middle, &blinded_pk, subcred, b"hsdir-superencrypted-data"),
+        );
+        let mut signed = SIG_PREFIX.to_vec();
+        signed.extend_from_slice(doc.as_bytes());
+        let sig = signing.sign(&signed);
+        doc.push_str(&format!(
+            "signature {}\n",
+            general_purpose::STANDARD_NO_PAD.encode(sig.to_bytes())
+        ));
+        doc
+    }
+
+    #[test]
+    fn test_decrypt_descriptor() {
+        let blinded = SigningKey::from_bytes(&[8u8; 32]);
+        let signing = SigningKey::from_bytes(&[9u8; 32]);
+        let subcred = [6u8; 32];
+        let doc = descriptor(&blinded, &signing, &subcred);
+        let blinded_pk = blinded.verifying_key().to_bytes();
+
+        let desc = HsDescriptor::decrypt(&doc, &blinded_pk, &subcred, NOW).unwrap();
+        assert_eq!(desc.lifetime_mins, 180);
+        assert_eq!(desc.revision_counter, 7);
+        assert_eq!(desc.intro_points.len(), 1);
+        let intro = &desc.intro_points[0];
+        assert_eq!(intro.auth_key, [2u8; 32]);
+        assert_eq!(intro.enc_key, [4u8; 32]);
+        let relay = intro.relay().unwrap();
+        assert_eq!(relay.fingerprint, "AB".repeat(20));
+        assert_eq!(relay.socket_addr().to_string(), "192.0.2.1:9001");
+        assert!(relay.ed25519_identity.is_some());
+    }
+
+    #[test]
+    fn test_wrong_key_or_tampering_is_rejected() {
+        let blinded = SigningKey::from_bytes(&[8u8; 32]);
+        let signing = SigningKey::from_bytes(&[9u8; 32]);
+        let subcred = [6u8; 32];
+        let doc = descriptor(&blinded, &signing, &subcred);
+        let blinded_pk = blinded.verifying_key().to_bytes();
+
+        let other = SigningKey::from_bytes(&[1u8; 32])
+            .verifying_key()
+            .to_bytes();
+        assert!(HsDescriptor::decrypt(&doc, &other, &subcred, NOW).is_err());
+        assert!(HsDescriptor::decrypt(&doc, &blinded_pk, &[0u8; 32], NOW).is_err());
+        let tampered = doc.replace("descriptor-lifetime 180", "descriptor-lifetime 999");
+        assert!(HsDescriptor::decrypt(&tampered, &blinded_pk, &subcred, NOW).is_err());
+        assert!(HsDescriptor::decrypt(&doc, &blinded_pk, &subcred, NOW + 7 * 86400).is_err());
+    }
+}
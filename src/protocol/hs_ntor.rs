@@ -0,0 +1,343 @@
+//! hs-ntor handshake for onion service rendezvous (rend-spec-v3 §5)
+//!
+//! The client encrypts INTRODUCE1 to the service's per-intro-point `enc-key`
+//! and later completes the handshake with the service's RENDEZVOUS2 reply.
+//! Unlike ntor, everything is SHA3-256/SHAKE-256 based and the resulting
+//! hop uses AES-256 and SHA3 digests.
+
+use super::circuit_builder::create_link_specifiers;
+use super::Relay;
+use crate::error::{Result, TorError};
+use aes::Aes256;
+use base64::{engine::general_purpose, Engine as _};
+use ctr::{
+    cipher::{KeyIvInit, StreamCipher},
+    Ctr128BE,
+};
+use rand::rngs::OsRng;
+use sha3::{Digest, Sha3_256, Shake256};
+use subtle::ConstantTimeEq;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// AES-256-CTR cipher type
+pub(crate) type Aes256Ctr = Ctr128BE<Aes256>;
+
+const PROTOID: &[u8] = b"tor-hs-ntor-curve25519-sha3-256-1";
+const T_HSENC: &[u8] = b"tor-hs-ntor-curve25519-sha3-256-1:hs_key_extract";
+const T_HSVERIFY: &[u8] = b"tor-hs-ntor-curve25519-sha3-256-1:hs_verify";
+const T_HSMAC: &[u8] = b"tor-hs-ntor-curve25519-sha3-256-1:hs_mac";
+const M_HSEXPAND: &[u8] = b"tor-hs-ntor-curve25519-sha3-256-1:hs_key_expand";
+
+/// INTRODUCE1 plaintexts are padded to at least this size so their length
+/// says nothing about the rendezvous point
+const INTRODUCE1_PLAINTEXT_LEN: usize = 246;
+
+/// `H(INT_8(len(key)) | key | msg)`, the MAC used throughout rend-spec-v3
+pub fn hs_mac(key: &[u8], msg: &[u8]) -> [u8; 32] {
+    Sha3_256::new()
+        .chain_update((key.len() as u64).to_be_bytes())
+        .chain_update(key)
+        .chain_update(msg)
+        .finalize()
+        .into()
+}
+
+/// SHAKE-256 of `input`, `N` bytes long
+pub fn shake256<const N: usize>(input: &[&[u8]]) -> [u8; N] {
+    use sha3::digest::{ExtendableOutput, Update, XofReader};
+
+    let mut hasher = Shake256::default();
+    for part in input {
+        hasher.update(part);
+    }
+    let mut out = [0u8; N];
+    hasher.finalize_xof().read(&mut out);
+    out
+}
+
+/// Plaintext of INTRODUCE1's encrypted section: the rendezvous cookie and
+/// how the service reaches `rendezvous` (its ntor key and link specifiers)
+pub fn introduce1_plaintext(cookie: &[u8; 20], rendezvous: &Relay) -> Result<Vec<u8>> {
+    let onion_key = rendezvous
+        .ntor_onion_key
+        .as_deref()
+        .and_then(|k| {
+            general_purpose::STANDARD_NO_PAD
+                .decode(k.trim_end_matches('='))
+                .ok()
+        })
+        .filter(|k| k.len() == 32)
+        .ok_or_else(|| {
+            TorError::OnionService(format!(
+                "Rendezvous point {} has no ntor onion key",
+                rendezvous.nickname
+            ))
+        })?;
+    let specs = create_link_specifiers(rendezvous)?;
+
+    let mut plaintext = Vec::with_capacity(INTRODUCE1_PLAINTEXT_LEN);
+    plaintext.extend_from_slice(cookie);
+    plaintext.push(0); // N_EXTENSIONS
+    plaintext.push(0x01); // ONION_KEY_TYPE: ntor
+    plaintext.extend_from_slice(&32u16.to_be_bytes());
+    plaintext.extend_from_slice(&onion_key);
+    plaintext.push(specs.len() as u8);
+    for spec in specs {
+        plaintext.extend_from_slice(&spec);
+    }
+    if plaintext.len() < INTRODUCE1_PLAINTEXT_LEN {
+        plaintext.resize(INTRODUCE1_PLAINTEXT_LEN, 0);
+    }
+    Ok(plaintext)
+}
+
+/// Keys for the virtual hop to the onion service
+///
+/// Output of `KDF(NTOR_KEY_SEED | m_hsexpand)`: SHA3-256 digest seeds and
+/// AES-256 keys, with the IV starting at zero.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct HsHopKeys {
+    pub forward_digest: [u8; 32],
+    pub backward_digest: [u8; 32],
+    pub forward_key: [u8; 32],
+    pub backward_key: [u8; 32],
+}
+
+impl HsHopKeys {
+    fn expand(key_seed: &[u8; 32]) -> Self {
+        let okm: [u8; 128] = shake256(&[key_seed, M_HSEXPAND]);
+        let mut keys = Self {
+            forward_digest: [0; 32],
+            backward_digest: [0; 32],
+            forward_key: [0; 32],
+            backward_key: [0; 32],
+        };
+        keys.forward_digest.copy_from_slice(&okm[0..32]);
+        keys.backward_digest.copy_from_slice(&okm[32..64]);
+        keys.forward_key.copy_from_slice(&okm[64..96]);
+        keys.backward_key.copy_from_slice(&okm[96..128]);
+        keys
+    }
+}
+
+/// `NTOR_KEY_SEED` and the expected `AUTH_INPUT_MAC` from the rendezvous
+/// secret input
+fn rend_seed_and_auth(
+    exp_yx: &[u8; 32],
+    exp_bx: &[u8; 32],
+    auth_key: &[u8; 32],
+    enc_key: &[u8; 32],
+    client_public: &[u8; 32],
+    server_public: &[u8; 32],
+) -> ([u8; 32], [u8; 32]) {
+    let mut secret = Vec::with_capacity(32 * 6 + PROTOID.len());
+    secret.extend_from_slice(exp_yx);
+    secret.extend_from_slice(exp_bx);
+    secret.extend_from_slice(auth_key);
+    secret.extend_from_slice(enc_key);
+    secret.extend_from_slice(client_public);
+    secret.extend_from_slice(server_public);
+    secret.extend_from_slice(PROTOID);
+
+    let seed = hs_mac(&secret, T_HSENC);
+    let verify = hs_mac(&secret, T_HSVERIFY);
+    secret.zeroize();
+
+    let mut auth_input = Vec::with_capacity(32 * 5 + PROTOID.len() + 6);
+    auth_input.extend_from_slice(&verify);
+    auth_input.extend_from_slice(auth_key);
+    auth_input.extend_from_slice(enc_key);
+    auth_input.extend_from_slice(server_public);
+    auth_input.extend_from_slice(client_public);
+    auth_input.extend_from_slice(PROTOID);
+    auth_input.extend_from_slice(b"Server");
+    (seed, hs_mac(&auth_input, T_HSMAC))
+}
+
+/// `ENC_KEY | MAC_KEY` protecting INTRODUCE1's encrypted section
+fn intro_keys(
+    exp_bx: &[u8; 32],
+    auth_key: &[u8; 32],
+    client_public: &[u8; 32],
+    enc_key: &[u8; 32],
+    subcredential: &[u8; 32],
+) -> [u8; 64] {
+    shake256(&[
+        exp_bx,
+        auth_key,
+        client_public,
+        enc_key,
+        PROTOID,
+        T_HSENC,
+        M_HSEXPAND,
+        subcredential,
+    ])
+}
+
+/// Client side of one hs-ntor handshake, bound to one introduction point
+pub struct HsNtorClient {
+    secret: StaticSecret,
+    public: PublicKey,
+    /// Introduction point's auth key (`AUTH_KEY`)
+    auth_key: [u8; 32],
+    /// Service's encryption key for this introduction point (`B`)
+    enc_key: PublicKey,
+    subcredential: [u8; 32],
+}
+
+impl HsNtorClient {
+    /// Start a handshake with a fresh ephemeral key
+    pub fn new(auth_key: [u8; 32], enc_key: [u8; 32], subcredential: [u8; 32]) -> Self {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+        Self {
+            secret,
+            public,
+            auth_key,
+            enc_key: PublicKey::from(enc_key),
+            subcredential,
+        }
+    }
+
+    /// Build the INTRODUCE1 body around `plaintext` (rend-spec-v3 §3.2.1)
+    ///
+    /// `LEGACY_KEY_ID | AUTH_KEY | extensions | X | ENCRYPTED | MAC`, with the
+    /// MAC covering everything before it.
+    pub fn introduce1(&self, plaintext: &[u8]) -> Vec<u8> {
+        let exp_bx = self.secret.diffie_hellman(&self.enc_key);
+        let mut keys = intro_keys(
+            exp_bx.as_bytes(),
+            &self.auth_key,
+            self.public.as_bytes(),
+            self.enc_key.as_bytes(),
+            &self.subcredential,
+        );
+
+        let mut body = Vec::with_capacity(20 + 36 + 32 + plaintext.len() + 32);
+        body.extend_from_slice(&[0u8; 20]); // LEGACY_KEY_ID
+        body.push(0x02); // AUTH_KEY_TYPE: ed25519
+        body.extend_from_slice(&32u16.to_be_bytes());
+        body.extend_from_slice(&self.auth_key);
+        body.push(0); // N_EXTENSIONS
+        body.extend_from_slice(self.public.as_bytes());
+
+        let start = body.len();
+        body.extend_from_slice(plaintext);
+        let mut cipher = Aes256Ctr::new((&keys[..32]).into(), (&[0u8; 16]).into());
+        cipher.apply_keystream(&mut body[start..]);
+
+        let mac = hs_mac(&keys[32..], &body);
+        body.extend_from_slice(&mac);
+        keys.zeroize();
+        body
+    }
+
+    /// Finish with the service's RENDEZVOUS2 payload (`Y | AUTH`)
+    pub fn complete(self, rendezvous2: &[u8]) -> Result<HsHopKeys> {
+        let server_public: [u8; 32] = rendezvous2
+            .get(..32)
+            .and_then(|y| y.try_into().ok())
+            .ok_or_else(|| TorError::ProtocolError("RENDEZVOUS2 truncated".into()))?;
+        let auth = rendezvous2
+            .get(32..64)
+            .ok_or_else(|| TorError::ProtocolError("RENDEZVOUS2 truncated".into()))?;
+
+        let exp_yx = self.secret.diffie_hellman(&PublicKey::from(server_public));
+        let exp_bx = self.secret.diffie_hellman(&self.enc_key);
+        let (mut seed, expected) = rend_seed_and_auth(
+            exp_yx.as_bytes(),
+            exp_bx.as_bytes(),
+            &self.auth_key,
+            self.enc_key.as_bytes(),
+            self.public.as_bytes(),
+            &server_public,
+        );
+        if !bool::from(expected.ct_eq(auth)) {
+            seed.zeroize();
+            return Err(TorError::AuthVerificationFailed(
+                "Onion service handshake AUTH mismatch".into(),
+            ));
+        }
+        let keys = HsHopKeys::expand(&seed);
+        seed.zeroize();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The service's half: decrypt INTRODUCE1 and answer with RENDEZVOUS2
+    fn serve(
+        introduce1: &[u8],
+        enc_secret: &StaticSecret,
+        subcredential: &[u8; 32],
+    ) -> (Vec<u8>, Vec<u8>, HsHopKeys) {
+        let auth_key: [u8; 32] = introduce1[23..55].try_into().unwrap();
+        let client_public: [u8; 32] = introduce1[56..88].try_into().unwrap();
+        let (encrypted, mac) = introduce1.split_at(introduce1.len() - 32);
+        let enc_key = PublicKey::from(enc_secret);
+
+        let exp_xb = enc_secret.diffie_hellman(&PublicKey::from(client_public));
+        let keys = intro_keys(
+            exp_xb.as_bytes(),
+            &auth_key,
+            &client_public,
+            enc_key.as_bytes(),
+            subcredential,
+        );
+        assert_eq!(hs_mac(&keys[32..], encrypted), mac);
+        let mut plaintext = encrypted[88..].to_vec();
+        Aes256Ctr::new((&keys[..32]).into(), (&[0u8; 16]).into()).apply_keystream(&mut plaintext);
+
+        let y = StaticSecret::random_from_rng(OsRng);
+        let server_public = PublicKey::from(&y);
+        let exp_xy = y.diffie_hellman(&PublicKey::from(client_public));
+        let (seed, auth) = rend_seed_and_auth(
+            exp_xy.as_bytes(),
+            exp_xb.as_bytes(),
+            &auth_key,
+            enc_key.as_bytes(),
+            &client_public,
+            server_public.as_bytes(),
+        );
+        let mut reply = server_public.as_bytes().to_vec();
+        reply.extend_from_slice(&auth);
+        (plaintext, reply, HsHopKeys::expand(&seed))
+    }
+
+    #[test]
+    fn test_handshake_round_trip() {
+        let enc_secret = StaticSecret::random_from_rng(OsRng);
+        let enc_key = *PublicKey::from(&enc_secret).as_bytes();
+        let subcredential = [5u8; 32];
+        let client = HsNtorClient::new([4u8; 32], enc_key, subcredential);
+
+        let introduce1 = client.introduce1(b"rendezvous cookie and onion key");
+        let (plaintext, reply, service_keys) = serve(&introduce1, &enc_secret, &subcredential);
+        assert_eq!(plaintext, b"rendezvous cookie and onion key");
+
+        let keys = client.complete(&reply).unwrap();
+        assert_eq!(keys.forward_key, service_keys.forward_key);
+        assert_eq!(keys.backward_digest, service_keys.backward_digest);
+    }
+
+    #[test]
+    fn test_bad_auth_is_rejected() {
+        let enc_secret = StaticSecret::random_from_rng(OsRng);
+        let enc_key = *PublicKey::from(&enc_secret).as_bytes();
+        let client = HsNtorClient::new([4u8; 32], enc_key, [5u8; 32]);
+        let introduce1 = client.introduce1(&[0u8; 64]);
+        let (_, mut reply, _) = serve(&introduce1, &enc_secret, &[5u8; 32]);
+        reply[40] ^= 1;
+        assert!(matches!(
+            client.complete(&reply),
+            Err(TorError::AuthVerificationFailed(_))
+        ));
+        assert!(HsNtorClient::new([4u8; 32], enc_key, [5u8; 32])
+            .complete(&[0u8; 10])
+            .is_err());
+    }
+}
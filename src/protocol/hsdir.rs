@@ -0,0 +1,179 @@
+//! Onion service directory hash ring (rend-spec-v3 §2.2.3)
+//!
+//! Descriptors for a blinded key are stored on the HSDirs that follow the
+//! key's `hs_index` positions on a ring of relay `hsdir_index` values. The
+//! ring is reshuffled every time period by the shared random value.
+
+use super::onion_address::TimePeriod;
+use super::{Consensus, Relay};
+use crate::error::{Result, TorError};
+use base64::{engine::general_purpose, Engine as _};
+use sha3::{Digest, Sha3_256};
+
+/// Number of ring positions a descriptor is stored at (`hsdir_n_replicas`)
+const REPLICAS: usize = 2;
+
+/// Number of HSDirs a client may try per replica (`hsdir_spread_fetch`)
+const SPREAD_FETCH: usize = 3;
+
+/// Shared random value used for HSDir indices at `now`, falling back to
+/// the spec's disaster value when the consensus carries none
+pub fn shared_random_value(consensus: &Consensus, period: TimePeriod, now: u64) -> [u8; 32] {
+    let value = if period.before_next_srv(now) {
+        consensus.shared_rand_current.as_deref()
+    } else {
+        consensus.shared_rand_previous.as_deref()
+    };
+    value
+        .and_then(|v| general_purpose::STANDARD.decode(v).ok())
+        .and_then(|v| <[u8; 32]>::try_from(v).ok())
+        .unwrap_or_else(|| {
+            Sha3_256::new()
+                .chain_update(b"shared-random-disaster")
+                .chain_update(period.length.to_be_bytes())
+                .chain_update(period.number.to_be_bytes())
+                .finalize()
+                .into()
+        })
+}
+
+/// Ring position of a relay: `H("node-idx" | ID | SRV | period_num | period_len)`
+fn hsdir_index(identity: &[u8; 32], srv: &[u8; 32], period: TimePeriod) -> [u8; 32] {
+    Sha3_256::new()
+        .chain_update(b"node-idx")
+        .chain_update(identity)
+        .chain_update(srv)
+        .chain_update(period.number.to_be_bytes())
+        .chain_update(period.length.to_be_bytes())
+        .finalize()
+        .into()
+}
+
+/// Ring position of one descriptor replica
+fn hs_index(blinded_key: &[u8; 32], replica: u64, period: TimePeriod) -> [u8; 32] {
+    Sha3_256::new()
+        .chain_update(b"store-at-idx")
+        .chain_update(blinded_key)
+        .chain_update(replica.to_be_bytes())
+        .chain_update(period.length.to_be_bytes())
+        .chain_update(period.number.to_be_bytes())
+        .finalize()
+        .into()
+}
+
+/// HSDirs responsible for `blinded_key`, in the order a client tries them
+///
+/// Only running relays with the HSDir flag and a known Ed25519 identity
+/// are placed on the ring.
+pub fn responsible_hsdirs<'a>(
+    consensus: &'a Consensus,
+    blinded_key: &[u8; 32],
+    period: TimePeriod,
+    now: u64,
+) -> Result<Vec<&'a Relay>> {
+    let srv = shared_random_value(consensus, period, now);
+    let mut ring: Vec<([u8; 32], &Relay)> = consensus
+        .relays
+        .iter()
+        .filter(|r| r.flags.hs_dir && r.flags.running)
+        .filter_map(|r| {
+            let id = general_purpose::STANDARD_NO_PAD
+                .decode(r.ed25519_identity.as_deref()?.trim_end_matches('='))
+                .ok()?;
+            let id = <[u8; 32]>::try_from(id).ok()?;
+            Some((hsdir_index(&id, &srv, period), r))
+        })
+        .collect();
+    if ring.is_empty() {
+        return Err(TorError::NoRelaysAvailable(
+            "No HSDirs with Ed25519 identities in the consensus".into(),
+        ));
+    }
+    ring.sort_by_key(|(index, _)| *index);
+
+    let spread = SPREAD_FETCH.min(ring.len());
+    let mut chosen: Vec<&Relay> = Vec::new();
+    for replica in 1..=REPLICAS as u64 {
+        let target = hs_index(blinded_key, replica, period);
+        let start = ring.partition_point(|(index, _)| *index < target);
+        let mut taken = 0;
+        for (_, relay) in ring.iter().cycle().skip(start).take(ring.len()) {
+            if taken == spread {
+                break;
+            }
+            if chosen.iter().any(|c| c.fingerprint == relay.fingerprint) {
+                continue;
+            }
+            chosen.push(relay);
+            taken += 1;
+        }
+    }
+    Ok(chosen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{RelayFlags, TestRelay};
+
+    fn hsdir(i: u8) -> Relay {
+        TestRelay::new(&format!("hsdir{}", i))
+            .fingerprint(&hex::encode_upper([i; 20]))
+            .address(&format!("10.0.{}.1", i))
+            .flags(RelayFlags::from_string("HSDir Running"))
+            .bandwidth(1000)
+            .ntor_onion_key(None)
+            .ed25519_identity(&general_purpose::STANDARD_NO_PAD.encode([i; 32]))
+            .build()
+    }
+
+    #[test]
+    fn test_responsible_hsdirs_are_distinct_and_follow_the_ring() {
+        let consensus = Consensus {
+            relays: (1..=10).map(hsdir).collect(),
+            ..Default::default()
+        };
+        let period = TimePeriod::at(1_700_000_000, 1440);
+        let now = period.start_secs();
+        let dirs = responsible_hsdirs(&consensus, &[9u8; 32], period, now).unwrap();
+        assert_eq!(dirs.len(), 6);
+        let mut fingerprints: Vec<_> = dirs.iter().map(|r| &r.fingerprint).collect();
+        fingerprints.dedup();
+        assert_eq!(fingerprints.len(), 6);
+
+        // The first pick is the first relay at or after replica 1's position
+        let srv = shared_random_value(&consensus, period, now);
+        let target = hs_index(&[9u8; 32], 1, period);
+        let first = consensus
+            .relays
+            .iter()
+            .map(|r| {
+                let id = general_purpose::STANDARD_NO_PAD
+                    .decode(r.ed25519_identity.as_ref().unwrap())
+                    .unwrap();
+                (hsdir_index(&id.try_into().unwrap(), &srv, period), r)
+            })
+            .filter(|(index, _)| *index >= target)
+            .min_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, r)| r.fingerprint.clone());
+        if let Some(first) = first {
+            assert_eq!(dirs[0].fingerprint, first);
+        }
+    }
+
+    #[test]
+    fn test_srv_choice_depends_on_position_in_period() {
+        let consensus = Consensus {
+            shared_rand_current: Some(general_purpose::STANDARD.encode([1u8; 32])),
+            shared_rand_previous: Some(general_purpose::STANDARD.encode([2u8; 32])),
+            ..Default::default()
+        };
+        let period = TimePeriod::at(1_700_000_000, 1440);
+        let early = period.start_secs() + 60;
+        let late = period.start_secs() + 13 * 3600;
+        assert_eq!(shared_random_value(&consensus, period, early), [1u8; 32]);
+        assert_eq!(shared_random_value(&consensus, period, late), [2u8; 32]);
+        let empty = Consensus::default();
+        assert_ne!(shared_random_value(&empty, period, early), [0u8; 32]);
+    }
+}
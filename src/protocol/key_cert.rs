@@ -0,0 +1,438 @@
+//! Authority key certificates (dir-spec §3.1)
+//!
+//! Authorities sign consensuses with medium-term signing keys and rotate
+//! them every few months. Each signing key comes with a key certificate
+//! signed by the authority's long-term identity key:
+//!
+//! ```text
+//! dir-key-certificate-version 3
+//! fingerprint <hex SHA-1 of the identity key>
+//! dir-key-published YYYY-MM-DD HH:MM:SS
+//! dir-key-expires YYYY-MM-DD HH:MM:SS
+//! dir-identity-key <PEM>
+//! dir-signing-key <PEM>
+//! dir-key-crosscert <signing key's signature over the identity digest>
+//! dir-key-certification <identity key's signature over the above>
+//! ```
+//!
+//! A certificate is accepted only if its identity key hashes to a pinned
+//! authority fingerprint ([`super::DIRECTORY_AUTHORITIES`]), both signatures
+//! hold and it hasn't expired, so whoever serves `/tor/keys/` (cache,
+//! bridge) can withhold certificates but not forge them. New signing keys
+//! are picked up from fresh certificates without a new build.
+
+use super::consensus_verify::{ConsensusVerifier, DIRECTORY_AUTHORITIES};
+use crate::clock_skew::parse_utc;
+use crate::error::{Result, TorError};
+use base64::Engine;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// All authorities' current certificates
+pub const ALL_CERTS_PATH: &str = "/tor/keys/all";
+
+/// Keyword ending the part the identity key signs
+const CERTIFICATION: &str = "\ndir-key-certification\n";
+
+/// A verified authority key certificate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyCertificate {
+    /// v3ident of the authority (hex SHA-1 of its identity key)
+    pub identity: String,
+    /// Hex SHA-1 of the signing key, as consensus signatures name it
+    pub signing_key_digest: String,
+    /// The signing key (`-----BEGIN RSA PUBLIC KEY-----`)
+    pub signing_key_pem: String,
+    pub published: u64,
+    pub expires: u64,
+    /// The certificate as served, so a cached copy can be verified again
+    pub text: String,
+}
+
+impl KeyCertificate {
+    /// Parse and verify one certificate against the pinned authorities
+    pub fn verify(text: &str, now: u64) -> Result<Self> {
+        let pinned: Vec<&str> = DIRECTORY_AUTHORITIES.iter().map(|a| a.v3ident).collect();
+        Self::verify_pinned(text, now, &pinned)
+    }
+
+    /// Parse and verify one certificate whose identity must be in `pinned`
+    pub fn verify_pinned(text: &str, now: u64, pinned: &[&str]) -> Result<Self> {
+        let fingerprint = keyword(text, "fingerprint")
+            .ok_or_else(|| broken("missing fingerprint"))?
+            .to_uppercase();
+        if !pinned.iter().any(|p| p.eq_ignore_ascii_case(&fingerprint)) {
+            return Err(broken(&format!("{} is not a known authority", fingerprint)));
+        }
+
+        let identity_der = object(text, "dir-identity-key")?;
+        if hex::encode_upper(Sha1::digest(&identity_der)) != fingerprint {
+            return Err(broken("identity key doesn't match the fingerprint"));
+        }
+        let identity_key = rsa_key(&identity_der)?;
+        let signing_der = object(text, "dir-signing-key")?;
+        let signing_key = rsa_key(&signing_der)?;
+
+        // The identity key certifies everything up to its signature
+        let signed_end = text
+            .find(CERTIFICATION)
+            .ok_or_else(|| broken("missing dir-key-certification"))?
+            + CERTIFICATION.len();
+        let certification = object(text, "dir-key-certification")?;
+        identity_key
+            .verify(
+                Pkcs1v15Sign::new_unprefixed(),
+                &Sha1::digest(&text.as_bytes()[..signed_end]),
+                &certification,
+            )
+            .map_err(|_| broken("bad identity key signature"))?;
+
+        // The signing key vouches for the identity key in turn
+        let crosscert = object(text, "dir-key-crosscert")?;
+        signing_key
+            .verify(
+                Pkcs1v15Sign::new_unprefixed(),
+                &Sha1::digest(&identity_der),
+                &crosscert,
+            )
+            .map_err(|_| broken("bad cross-certification"))?;
+
+        let time = |name: &str| {
+            keyword(text, name)
+                .and_then(parse_utc)
+                .ok_or_else(|| broken(&format!("missing {}", name)))
+        };
+        let published = time("dir-key-published")?;
+        let expires = time("dir-key-expires")?;
+        if expires <= now {
+            return Err(broken(&format!("certificate for {} expired", fingerprint)));
+        }
+
+        Ok(Self {
+            identity: fingerprint,
+            signing_key_digest: hex::encode_upper(Sha1::digest(&signing_der)),
+            signing_key_pem: pem_block(text, "dir-signing-key").unwrap_or_default(),
+            published,
+            expires,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// Split a `/tor/keys/` response into certificates
+pub fn split(text: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = text
+        .match_indices("dir-key-certificate-version")
+        .map(|(i, _)| i)
+        .filter(|&i| i == 0 || text.as_bytes()[i - 1] == b'\n')
+        .collect();
+    starts.push(text.len());
+    starts.windows(2).map(|w| &text[w[0]..w[1]]).collect()
+}
+
+/// The certificates the client holds, newest first per authority
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyCertStore {
+    certs: Vec<KeyCertificate>,
+}
+
+impl KeyCertStore {
+    /// Verify each certificate in `text` and keep the valid ones, returning
+    /// how many were new
+    pub fn add_certificates(&mut self, text: &str, now: u64) -> usize {
+        let mut added = 0;
+        for doc in split(text) {
+            match KeyCertificate::verify(doc, now) {
+                Ok(cert) => added += usize::from(self.insert(cert)),
+                Err(e) => log::warn!("⚠️ Ignoring authority key certificate: {}", e),
+            }
+        }
+        added
+    }
+
+    /// Keep `cert` unless a certificate for the same key is held already
+    pub fn insert(&mut self, cert: KeyCertificate) -> bool {
+        if self
+            .certs
+            .iter()
+            .any(|c| c.signing_key_digest == cert.signing_key_digest)
+        {
+            return false;
+        }
+        self.certs.push(cert);
+        self.certs
+            .sort_by(|a, b| (&a.identity, b.published).cmp(&(&b.identity, a.published)));
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.certs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.certs.is_empty()
+    }
+
+    /// Give `verifier` every certified signing key
+    pub fn install(&self, verifier: &mut ConsensusVerifier) {
+        for cert in &self.certs {
+            if let Err(e) = verifier.add_signing_key(&cert.identity, &cert.signing_key_pem) {
+                log::warn!(
+                    "⚠️ Certified signing key for {} unusable: {}",
+                    cert.identity,
+                    e
+                );
+            }
+        }
+    }
+
+    /// `(identity, signing key digest)` of the consensus signatures by
+    /// known authorities whose key has no certificate here: keys rotated
+    /// since the certificates were fetched
+    pub fn missing_keys(&self, consensus_text: &str) -> Vec<(String, String)> {
+        let verifier = ConsensusVerifier::new();
+        verifier
+            .parse_signatures(consensus_text)
+            .into_iter()
+            .filter(|sig| verifier.is_authority(&sig.identity))
+            .filter(|sig| {
+                !self.certs.iter().any(|c| {
+                    c.signing_key_digest
+                        .eq_ignore_ascii_case(&sig.signing_key_digest)
+                })
+            })
+            .map(|sig| {
+                (
+                    sig.identity.to_uppercase(),
+                    sig.signing_key_digest.to_uppercase(),
+                )
+            })
+            .collect()
+    }
+
+    /// Everything held, as served (for caching)
+    pub fn text(&self) -> String {
+        self.certs.iter().map(|c| c.text.as_str()).collect()
+    }
+}
+
+/// Path fetching the certificates for `keys` (`fp-sk` pairs)
+pub fn download_path(keys: &[(String, String)]) -> String {
+    if keys.is_empty() {
+        return ALL_CERTS_PATH.to_string();
+    }
+    let pairs: Vec<String> = keys
+        .iter()
+        .map(|(id, sk)| format!("{}-{}", id, sk))
+        .collect();
+    format!("/tor/keys/fp-sk/{}", pairs.join("+"))
+}
+
+fn broken(reason: &str) -> TorError {
+    TorError::ConsensusError(format!("Authority key certificate: {}", reason))
+}
+
+/// Arguments of the first line starting with `name`
+fn keyword<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    text.lines().find_map(|line| {
+        line.strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix(' '))
+            .map(str::trim)
+    })
+}
+
+/// The PEM block following the line `name`
+fn pem_block(text: &str, name: &str) -> Option<String> {
+    let mut lines = text.lines().skip_while(|line| line.trim_end() != name);
+    lines.next()?;
+    let mut block = String::new();
+    for line in lines {
+        block.push_str(line);
+        block.push('\n');
+        if line.starts_with("-----END") {
+            return Some(block);
+        }
+    }
+    None
+}
+
+/// Decoded body of the object following the line `name`
+fn object(text: &str, name: &str) -> Result<Vec<u8>> {
+    let pem = pem_block(text, name).ok_or_else(|| broken(&format!("missing {}", name)))?;
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(body)
+        .map_err(|e| broken(&format!("bad {} encoding: {}", name, e)))
+}
+
+fn rsa_key(der: &[u8]) -> Result<RsaPublicKey> {
+    RsaPublicKey::from_pkcs1_der(der).map_err(|e| broken(&format!("bad RSA key: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1::EncodeRsaPublicKey;
+    use rsa::RsaPrivateKey;
+
+    const NOW: u64 = 1_704_067_200; // 2024-01-01
+
+    fn pem(key: &RsaPrivateKey) -> (Vec<u8>, String) {
+        let der = key
+            .to_public_key()
+            .to_pkcs1_der()
+            .unwrap()
+            .as_bytes()
+            .to_vec();
+        let pem = format!(
+            "-----BEGIN RSA PUBLIC KEY-----\n{}\n-----END RSA PUBLIC KEY-----\n",
+            base64::engine::general_purpose::STANDARD.encode(&der)
+        );
+        (der, pem)
+    }
+
+    fn signature(kind: &str, key: &RsaPrivateKey, digest: &[u8]) -> String {
+        let sig = key.sign(Pkcs1v15Sign::new_unprefixed(), digest).unwrap();
+        format!(
+            "-----BEGIN {kind}-----\n{}\n-----END {kind}-----\n",
+            base64::engine::general_purpose::STANDARD.encode(sig)
+        )
+    }
+
+    /// A certificate for `signing` by `identity`, returning it and the
+    /// identity fingerprint
+    fn certificate(
+        identity: &RsaPrivateKey,
+        signing: &RsaPrivateKey,
+        published: &str,
+        expires: &str,
+    ) -> (String, String) {
+        let (identity_der, identity_pem) = pem(identity);
+        let (_, signing_pem) = pem(signing);
+        let fingerprint = hex::encode_upper(Sha1::digest(&identity_der));
+        let mut text = format!(
+            "dir-key-certificate-version 3\n\
+             fingerprint {}\n\
+             dir-key-published {}\n\
+             dir-key-expires {}\n\
+             dir-identity-key\n{}\
+             dir-signing-key\n{}\
+             dir-key-crosscert\n{}\
+             dir-key-certification\n",
+            fingerprint,
+            published,
+            expires,
+            identity_pem,
+            signing_pem,
+            signature("ID SIGNATURE", signing, &Sha1::digest(&identity_der)),
+        );
+        let certification = signature("SIGNATURE", identity, &Sha1::digest(text.as_bytes()));
+        text.push_str(&certification);
+        (text, fingerprint)
+    }
+
+    fn key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut rand::thread_rng(), 512).unwrap()
+    }
+
+    #[test]
+    fn test_verify_certificate() {
+        let (identity, signing) = (key(), key());
+        let (text, fingerprint) = certificate(
+            &identity,
+            &signing,
+            "2023-12-01 00:00:00",
+            "2024-06-01 00:00:00",
+        );
+
+        let cert = KeyCertificate::verify_pinned(&text, NOW, &[&fingerprint]).unwrap();
+        assert_eq!(cert.identity, fingerprint);
+        let (signing_der, signing_pem) = pem(&signing);
+        assert_eq!(cert.signing_key_pem, signing_pem);
+        assert_eq!(
+            cert.signing_key_digest,
+            hex::encode_upper(Sha1::digest(&signing_der))
+        );
+
+        // Not pinned, expired, or altered after signing
+        assert!(KeyCertificate::verify(&text, NOW).is_err());
+        assert!(KeyCertificate::verify_pinned(&text, NOW + 200 * 86400, &[&fingerprint]).is_err());
+        let altered = text.replace("2024-06-01", "2034-06-01");
+        assert!(KeyCertificate::verify_pinned(&altered, NOW, &[&fingerprint]).is_err());
+
+        // A signing key certified by another identity
+        let (forged, _) = certificate(
+            &key(),
+            &signing,
+            "2023-12-01 00:00:00",
+            "2024-06-01 00:00:00",
+        );
+        let forged = forged.replace(keyword(&forged, "fingerprint").unwrap(), &fingerprint);
+        assert!(KeyCertificate::verify_pinned(&forged, NOW, &[&fingerprint]).is_err());
+    }
+
+    #[test]
+    fn test_store_tracks_rotation() {
+        let identity = key();
+        let (old_signing, new_signing) = (key(), key());
+        let (old, fingerprint) = certificate(
+            &identity,
+            &old_signing,
+            "2023-09-01 00:00:00",
+            "2024-01-02 00:00:00",
+        );
+        let (new, _) = certificate(
+            &identity,
+            &new_signing,
+            "2023-12-15 00:00:00",
+            "2024-06-01 00:00:00",
+        );
+        let pinned = [fingerprint.as_str()];
+
+        let mut store = KeyCertStore::default();
+        assert!(store.insert(KeyCertificate::verify_pinned(&old, NOW, &pinned).unwrap()));
+        assert!(!store.insert(KeyCertificate::verify_pinned(&old, NOW, &pinned).unwrap()));
+        assert!(store.insert(KeyCertificate::verify_pinned(&new, NOW, &pinned).unwrap()));
+        assert_eq!(store.len(), 2);
+        // Newest first
+        assert!(store.certs[0].published > store.certs[1].published);
+        assert_eq!(store.text(), format!("{}{}", new, old));
+        assert_eq!(split(&store.text()), vec![new.as_str(), old.as_str()]);
+
+        // Reloaded from the cache once the old key has expired, only the
+        // new one carries on
+        let text = store.text();
+        let reloaded: Vec<_> = split(&text)
+            .into_iter()
+            .filter_map(|doc| KeyCertificate::verify_pinned(doc, NOW + 2 * 86400, &pinned).ok())
+            .collect();
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded[0].text, new);
+    }
+
+    #[test]
+    fn test_missing_keys_and_download_path() {
+        let auth = DIRECTORY_AUTHORITIES[0].v3ident;
+        let consensus = format!(
+            "network-status-version 3\n\
+             directory-signature sha256 {} ABCDEF\n\
+             -----BEGIN SIGNATURE-----\ndGVzdA==\n-----END SIGNATURE-----\n\
+             directory-signature sha256 0000000000000000000000000000000000000000 123456\n\
+             -----BEGIN SIGNATURE-----\ndGVzdA==\n-----END SIGNATURE-----\n",
+            auth
+        );
+        let store = KeyCertStore::default();
+        let missing = store.missing_keys(&consensus);
+        assert_eq!(missing, vec![(auth.to_string(), "ABCDEF".to_string())]);
+        assert_eq!(
+            download_path(&missing),
+            format!("/tor/keys/fp-sk/{}-ABCDEF", auth)
+        );
+        assert_eq!(download_path(&[]), ALL_CERTS_PATH);
+    }
+}
@@ -0,0 +1,246 @@
+//! Link handshake results cached per guard
+//!
+//! Every circuit opens its own TLS connection to its guard and runs the
+//! link handshake on it: VERSIONS, CERTS (an Ed25519 chain plus the RSA
+//! cross-certificate), AUTH_CHALLENGE and NETINFO. With persistent guards
+//! most circuits go through the same one or two relays, which present the
+//! same certificates every time. [`LinkCache`] keeps, per guard, the
+//! negotiated link version, a digest of the CERTS cell whose chain verified
+//! and the relay's NETINFO; a handshake that receives byte-identical CERTS
+//! at the same link version skips parsing and verifying them.
+//!
+//! An entry lives only as long as a connection that used it: each such
+//! connection's circuit holds a [`LinkLease`], and once the last lease for a
+//! guard is dropped the entry is gone, so the next connection verifies from
+//! scratch. A different link version or CERTS digest replaces the entry.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::rc::{Rc, Weak};
+
+use crate::runtime::LocalCell;
+
+/// What the relay's NETINFO told us
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NetinfoData {
+    /// The relay's clock (seconds since the epoch)
+    pub relay_time: u32,
+    /// Our address as the relay sees it, if it sent a usable one
+    pub our_address: Option<IpAddr>,
+}
+
+impl NetinfoData {
+    /// Parse a NETINFO payload: TIME (4) | ATYPE (1) | ALEN (1) | AVAL | ...
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let relay_time = u32::from_be_bytes(payload.get(..4)?.try_into().ok()?);
+        let our_address = match (payload.get(4), payload.get(5)) {
+            (Some(4), Some(4)) => payload
+                .get(6..10)
+                .and_then(|b| <[u8; 4]>::try_from(b).ok())
+                .map(IpAddr::from),
+            (Some(6), Some(16)) => payload
+                .get(6..22)
+                .and_then(|b| <[u8; 16]>::try_from(b).ok())
+                .map(IpAddr::from),
+            _ => None,
+        };
+        Some(Self {
+            relay_time,
+            our_address,
+        })
+    }
+}
+
+/// Outcome of one link handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// Negotiated link protocol version
+    pub link_version: u16,
+    /// SHA-256 of the CERTS cell payload
+    pub certs_digest: [u8; 32],
+    /// Ed25519 identity from a fully verified certificate chain; `None`
+    /// when the chain wasn't (or couldn't be) verified
+    pub verified_identity: Option<[u8; 32]>,
+    /// The relay's NETINFO, if it sent one
+    pub netinfo: Option<NetinfoData>,
+}
+
+impl LinkInfo {
+    /// Digest identifying a CERTS cell
+    pub fn digest_certs(payload: &[u8]) -> [u8; 32] {
+        Sha256::digest(payload).into()
+    }
+
+    /// Whether a handshake that saw `certs_digest` at `link_version` can
+    /// reuse this result's verification
+    pub fn vouches_for(&self, link_version: u16, certs_digest: &[u8; 32]) -> bool {
+        self.verified_identity.is_some()
+            && self.link_version == link_version
+            && &self.certs_digest == certs_digest
+    }
+}
+
+/// Keeps a guard's cache entry alive; held by circuits on connections that
+/// verified or reused it
+#[derive(Debug, Clone)]
+pub struct LinkLease(#[allow(dead_code)] Rc<()>);
+
+struct Entry {
+    info: LinkInfo,
+    lease: Weak<()>,
+}
+
+impl Entry {
+    fn is_live(&self) -> bool {
+        self.lease.strong_count() > 0
+    }
+}
+
+/// Cache counters
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LinkCacheStats {
+    /// Guards with a live entry
+    pub entries: usize,
+    /// Handshakes that skipped certificate verification
+    pub hits: u64,
+    /// Handshakes that verified certificates themselves
+    pub misses: u64,
+}
+
+/// Verified link handshakes by guard fingerprint
+#[derive(Default)]
+pub struct LinkCache {
+    entries: HashMap<String, Entry>,
+    hits: u64,
+    misses: u64,
+}
+
+/// Link cache shared by every circuit builder of a client
+pub type SharedLinkCache = Rc<LocalCell<LinkCache>>;
+
+/// Create an empty shared link cache
+pub fn new_shared_link_cache() -> SharedLinkCache {
+    Rc::new(LocalCell::new(LinkCache::default()))
+}
+
+impl LinkCache {
+    /// Result of the last verified handshake with `guard`, while some
+    /// connection that used it is still open
+    pub fn lookup(&mut self, guard: &str) -> Option<LinkInfo> {
+        self.entries.retain(|_, entry| entry.is_live());
+        self.entries.get(guard).map(|entry| entry.info.clone())
+    }
+
+    /// Record a finished handshake with `guard`
+    ///
+    /// Returns the lease the new connection must hold, or `None` if the
+    /// certificate chain wasn't verified (nothing is cached then, and any
+    /// entry for the guard is dropped).
+    pub fn record(&mut self, guard: &str, info: LinkInfo, reused: bool) -> Option<LinkLease> {
+        if reused {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        if info.verified_identity.is_none() {
+            self.entries.remove(guard);
+            return None;
+        }
+
+        if let Some(entry) = self.entries.get_mut(guard) {
+            if let Some(lease) = entry.lease.upgrade() {
+                if entry
+                    .info
+                    .vouches_for(info.link_version, &info.certs_digest)
+                {
+                    entry.info.netinfo = info.netinfo;
+                    return Some(LinkLease(lease));
+                }
+            }
+        }
+
+        let lease = Rc::new(());
+        self.entries.insert(
+            guard.to_string(),
+            Entry {
+                info,
+                lease: Rc::downgrade(&lease),
+            },
+        );
+        Some(LinkLease(lease))
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> LinkCacheStats {
+        LinkCacheStats {
+            entries: self.entries.values().filter(|e| e.is_live()).count(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(certs: &[u8]) -> LinkInfo {
+        LinkInfo {
+            link_version: 5,
+            certs_digest: LinkInfo::digest_certs(certs),
+            verified_identity: Some([7; 32]),
+            netinfo: None,
+        }
+    }
+
+    #[test]
+    fn test_entry_lives_as_long_as_a_lease() {
+        let mut cache = LinkCache::default();
+        let lease = cache.record("GUARD", info(b"certs"), false).unwrap();
+        let cached = cache.lookup("GUARD").unwrap();
+        assert!(cached.vouches_for(5, &LinkInfo::digest_certs(b"certs")));
+        assert!(!cached.vouches_for(4, &LinkInfo::digest_certs(b"certs")));
+        assert!(!cached.vouches_for(5, &LinkInfo::digest_certs(b"other")));
+
+        // A second connection reusing it shares the entry
+        let second = cache.record("GUARD", cached, true).unwrap();
+        drop(lease);
+        assert!(cache.lookup("GUARD").is_some());
+        drop(second);
+        assert!(cache.lookup("GUARD").is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 1, 1));
+    }
+
+    #[test]
+    fn test_unverified_or_changed_certs_replace_entry() {
+        let mut cache = LinkCache::default();
+        let _lease = cache.record("GUARD", info(b"certs"), false).unwrap();
+
+        let _rotated = cache.record("GUARD", info(b"new certs"), false).unwrap();
+        assert_eq!(
+            cache.lookup("GUARD").unwrap().certs_digest,
+            LinkInfo::digest_certs(b"new certs")
+        );
+
+        let unverified = LinkInfo {
+            verified_identity: None,
+            ..info(b"new certs")
+        };
+        assert!(cache.record("GUARD", unverified, false).is_none());
+        assert!(cache.lookup("GUARD").is_none());
+    }
+
+    #[test]
+    fn test_parse_netinfo() {
+        let payload = [0, 0, 1, 0, 4, 4, 192, 0, 2, 7, 1, 4, 4, 10, 0, 0, 1];
+        let netinfo = NetinfoData::parse(&payload).unwrap();
+        assert_eq!(netinfo.relay_time, 256);
+        assert_eq!(netinfo.our_address, Some("192.0.2.7".parse().unwrap()));
+        assert!(NetinfoData::parse(&payload[..3]).is_none());
+        assert_eq!(NetinfoData::parse(&payload[..5]).unwrap().our_address, None);
+    }
+}
@@ -0,0 +1,198 @@
+//! Microdescriptors
+//!
+//! The microdescriptor consensus names each relay's microdescriptor by the
+//! SHA-256 of its text (the `m` line) instead of carrying keys itself. The
+//! client fetches the documents with `GET /tor/micro/d/<D1>-<D2>-...` and
+//! takes the ntor onion key, ed25519 identity and family from them.
+//!
+//! A microdescriptor is accepted only under the digest we compute over its
+//! text, and only if the signed consensus lists that digest, so whoever
+//! serves them (the bridge included) can withhold documents but not alter
+//! them.
+
+use super::{Consensus, Relay};
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// Digests per `/tor/micro/d/` request, keeping URLs to ~11 KB
+pub const DOWNLOAD_BATCH: usize = 256;
+
+/// The parts of a microdescriptor the client uses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Microdescriptor {
+    /// SHA-256 of the document text (unpadded base64), as in `m` lines
+    pub digest: String,
+    /// Curve25519 ntor onion key (base64)
+    pub ntor_onion_key: Option<String>,
+    /// Ed25519 identity key (unpadded base64)
+    pub ed25519_identity: Option<String>,
+    /// Family declaration, as in the `family` line
+    pub family: Option<String>,
+}
+
+/// Split a `/tor/micro/d/` response into microdescriptors
+///
+/// Each document runs from its `onion-key` line up to the next one, and
+/// its digest covers exactly those bytes.
+pub fn parse(text: &str) -> Vec<Microdescriptor> {
+    let mut starts: Vec<usize> = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim_end() == "onion-key" || line.starts_with("onion-key ") {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    starts.push(text.len());
+
+    starts
+        .windows(2)
+        .map(|range| parse_one(&text[range[0]..range[1]]))
+        .collect()
+}
+
+fn parse_one(doc: &str) -> Microdescriptor {
+    let mut md = Microdescriptor {
+        digest: digest(doc),
+        ntor_onion_key: None,
+        ed25519_identity: None,
+        family: None,
+    };
+    for line in doc.lines() {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("ntor-onion-key"), Some(key), _) => md.ntor_onion_key = Some(key.to_string()),
+            (Some("id"), Some("ed25519"), Some(key)) => {
+                md.ed25519_identity = Some(key.trim_end_matches('=').to_string())
+            }
+            (Some("family"), Some(_), _) => {
+                md.family = line.strip_prefix("family ").map(str::to_string)
+            }
+            _ => {}
+        }
+    }
+    md
+}
+
+/// Digest naming a microdescriptor in the consensus
+pub fn digest(doc: &str) -> String {
+    STANDARD_NO_PAD.encode(Sha256::digest(doc.as_bytes()))
+}
+
+/// Digests the consensus lists, in relay order without repeats
+pub fn wanted(consensus: &Consensus) -> Vec<String> {
+    let mut seen = HashSet::new();
+    consensus
+        .relays
+        .iter()
+        .filter_map(|r| r.microdesc_digest.clone())
+        .filter(|d| seen.insert(d.clone()))
+        .collect()
+}
+
+/// Request path for a batch of digests
+pub fn download_path(digests: &[String]) -> String {
+    format!("/tor/micro/d/{}", digests.join("-"))
+}
+
+/// Copy keys, identities and families onto the relays whose digest has a
+/// microdescriptor, returning how many relays were filled in
+pub fn apply(relays: &mut [Relay], mds: &HashMap<String, Microdescriptor>) -> usize {
+    let mut filled = 0;
+    for relay in relays {
+        let Some(md) = relay.microdesc_digest.as_ref().and_then(|d| mds.get(d)) else {
+            continue;
+        };
+        if md.ntor_onion_key.is_some() {
+            relay.ntor_onion_key = md.ntor_onion_key.clone();
+        }
+        if md.ed25519_identity.is_some() {
+            relay.ed25519_identity = md.ed25519_identity.clone();
+        }
+        if md.family.is_some() {
+            relay.family = md.family.clone();
+        }
+        filled += 1;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ConsensusParser;
+
+    const MD_A: &str = "onion-key\n\
+        -----BEGIN RSA PUBLIC KEY-----\n\
+        MIGJAoGBAMbeH6Vn0pRkn2nRd8rT5A7VG2cqWGeA==\n\
+        -----END RSA PUBLIC KEY-----\n\
+        ntor-onion-key ZkAC2M3xd5M8g0U3WqNN0dEkqeTTZ5x41bD4kQx3vX4\n\
+        family $0000000000000000000000000000000000000001 $0000000000000000000000000000000000000002\n\
+        id ed25519 Cj6ZfWQe8n2CFQsn2bO0F9nGyVp1eR8pHlx8JxWcV5U\n";
+    const MD_B: &str = "onion-key\n\
+        ntor-onion-key 1jZgYz3ZW0mQYgq8b7yq2qGk2y1YUrCxWv2XNHBPymA=\n\
+        p accept 80,443\n";
+
+    #[test]
+    fn test_parse_splits_and_digests_each_document() {
+        let mds = parse(&format!("{}{}", MD_A, MD_B));
+        assert_eq!(mds.len(), 2);
+
+        let expected = STANDARD_NO_PAD.encode(Sha256::digest(MD_A.as_bytes()));
+        assert_eq!(mds[0].digest, expected);
+        assert_eq!(
+            mds[0].ntor_onion_key.as_deref(),
+            Some("ZkAC2M3xd5M8g0U3WqNN0dEkqeTTZ5x41bD4kQx3vX4")
+        );
+        assert_eq!(
+            mds[0].ed25519_identity.as_deref(),
+            Some("Cj6ZfWQe8n2CFQsn2bO0F9nGyVp1eR8pHlx8JxWcV5U")
+        );
+        assert!(mds[0].family.as_deref().unwrap().starts_with("$0000"));
+
+        assert_eq!(mds[1].digest, digest(MD_B));
+        assert_eq!(mds[1].ed25519_identity, None);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn test_apply_links_consensus_digests() {
+        let consensus_text = format!(
+            "network-status-version 3 microdesc\n\
+             r a AAoQ1DAR6kkoo19hBAX5K0QztNw 2024-01-01 00:12:05 10.0.0.1 9001 0\n\
+             m {}\n\
+             s Fast Running Valid\n\
+             r b AAwffNL+oHO5EdyUoWAOwvEX3ws 2024-01-01 00:12:05 10.0.0.2 9001 0\n\
+             m {}\n\
+             s Fast Running Valid\n\
+             r c AAoQ1DAR6kkoo19hBAX5K0QztNx 2024-01-01 00:12:05 10.0.0.3 9001 0\n\
+             m {}\n",
+            digest(MD_A),
+            digest(MD_B),
+            digest(MD_A)
+        );
+        let mut consensus = ConsensusParser::parse_text(&consensus_text).unwrap();
+        assert_eq!(wanted(&consensus), vec![digest(MD_A), digest(MD_B)]);
+
+        // A document whose text was altered no longer matches its m line
+        let tampered = MD_B.replace("80,443", "1-65535");
+        let mds: HashMap<String, Microdescriptor> = parse(&format!("{}{}", MD_A, tampered))
+            .into_iter()
+            .map(|md| (md.digest.clone(), md))
+            .collect();
+
+        assert_eq!(apply(&mut consensus.relays, &mds), 2);
+        let relays = &consensus.relays;
+        assert!(relays[0].ed25519_identity.is_some());
+        assert_eq!(relays[0].ntor_onion_key, relays[2].ntor_onion_key);
+        assert_eq!(relays[1].ntor_onion_key, None);
+
+        assert_eq!(
+            download_path(&wanted(&consensus)),
+            format!("/tor/micro/d/{}-{}", digest(MD_A), digest(MD_B))
+        );
+    }
+}
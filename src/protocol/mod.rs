@@ -7,24 +7,40 @@
 //! - Stream management
 //! - Cell protocol
 //! - Certificate verification
+//! - v3 onion service descriptors and the hs-ntor rendezvous handshake
 
+mod bytes;
 mod cell;
 mod certs;
 mod circuit_builder;
 mod consensus;
+mod consensus_diff;
+mod consensus_health;
 mod consensus_verify;
 mod crypto;
+pub mod debug;
 mod directory;
 mod flow_control;
+mod hs_descriptor;
+mod hs_ntor;
+mod hsdir;
+mod key_cert;
+mod link_cache;
+mod microdesc;
 mod ntor;
+mod onion_address;
 mod relay;
+mod resolve;
 mod stream;
 mod tls_stream;
+pub mod trace;
 
 pub use cell::{Cell, CellCommand, RelayCell, RelayCommand};
 pub use certs::{CertificateVerifier, CertsCell, Ed25519Certificate, VerifiedRelay};
+pub(crate) use circuit_builder::same_ipv4_slash16;
 pub use circuit_builder::{Circuit, CircuitBuilder};
-pub use consensus::{Consensus, ConsensusParser};
+pub use consensus::{Consensus, ConsensusParser, DirSource};
+pub use consensus_health::{ConsensusHealth, ConsensusHealthReport, HealthFailure};
 pub use consensus_verify::DIRECTORY_AUTHORITIES;
 pub use consensus_verify::{
     ConsensusVerifier, DirectoryAuthority, DirectorySignature, MIN_AUTHORITY_SIGNATURES,
@@ -32,9 +48,22 @@ pub use consensus_verify::{
 pub use crypto::{derive_circuit_keys as crypto_derive_keys, CircuitKeys, OnionCrypto};
 pub use directory::DirectoryManager;
 pub use flow_control::{CircuitFlowControl, StreamFlowControl};
+pub use hs_descriptor::{HsDescriptor, IntroPoint};
+pub use hs_ntor::{introduce1_plaintext, HsHopKeys, HsNtorClient};
+pub use hsdir::responsible_hsdirs;
+pub use link_cache::{
+    new_shared_link_cache, LinkCache, LinkCacheStats, LinkInfo, LinkLease, NetinfoData,
+    SharedLinkCache,
+};
 pub use ntor::{derive_circuit_keys, NtorHandshake};
-pub use relay::{Relay, RelayFlags, RelaySelector};
-pub use stream::{StreamBuilder, StreamManager, TorStream};
+pub use onion_address::{is_onion_host, OnionAddress, TimePeriod, DEFAULT_TIME_PERIOD_MINS};
+pub use relay::{Relay, RelayFlags, RelayRequirements, RelayRole, RelaySelector, LONG_LIVED_PORTS};
+pub use resolve::{parse_connected, parse_resolved, DnsAnswer};
+pub use stream::{
+    begin_payload, BeginFlags, BeginTarget, StreamBuilder, StreamLifetime, StreamManager,
+    TorStream, BEGIN_FLAG_IPV4_NOT_OK, BEGIN_FLAG_IPV6_OK, BEGIN_FLAG_IPV6_PREFERRED,
+};
+pub(crate) use tls_stream::leaf_certificate_digest;
 pub use tls_stream::TlsTorStream;
 
 /// Default HTTP port for directory queries
@@ -45,3 +74,78 @@ pub const FALLBACK_DIR_PORT: u16 = 9030;
 
 /// HTTPS port for encrypted directory queries
 pub const SECURE_DIR_PORT: u16 = 443;
+
+/// Relays for unit tests: a running relay with an ntor key at
+/// 192.0.2.1:9001, fingerprint padded from its nickname, and no flags
+/// unless given
+#[cfg(test)]
+pub(crate) struct TestRelay(Relay);
+
+#[cfg(test)]
+impl TestRelay {
+    pub fn new(nickname: &str) -> Self {
+        Self(Relay {
+            nickname: nickname.to_string(),
+            fingerprint: format!("{:0>40}", nickname.to_uppercase()),
+            address: "192.0.2.1".parse().unwrap(),
+            or_port: 9001,
+            bandwidth: 1_000_000,
+            ntor_onion_key: Some("key".to_string()),
+            ..Default::default()
+        })
+    }
+
+    pub fn fingerprint(mut self, fingerprint: &str) -> Self {
+        self.0.fingerprint = fingerprint.to_string();
+        self
+    }
+
+    pub fn address(mut self, address: &str) -> Self {
+        self.0.address = address.parse().unwrap();
+        self
+    }
+
+    pub fn or_port(mut self, or_port: u16) -> Self {
+        self.0.or_port = or_port;
+        self
+    }
+
+    pub fn flags(mut self, flags: RelayFlags) -> Self {
+        self.0.flags = flags;
+        self
+    }
+
+    pub fn bandwidth(mut self, bandwidth: u64) -> Self {
+        self.0.bandwidth = bandwidth;
+        self
+    }
+
+    pub fn published(mut self, published: u64) -> Self {
+        self.0.published = published;
+        self
+    }
+
+    pub fn ntor_onion_key(mut self, key: Option<&str>) -> Self {
+        self.0.ntor_onion_key = key.map(str::to_string);
+        self
+    }
+
+    pub fn country(mut self, country: Option<&str>) -> Self {
+        self.0.country = country.map(str::to_string);
+        self
+    }
+
+    pub fn asn(mut self, asn: &str) -> Self {
+        self.0.asn = Some(asn.to_string());
+        self
+    }
+
+    pub fn ed25519_identity(mut self, identity: &str) -> Self {
+        self.0.ed25519_identity = Some(identity.to_string());
+        self
+    }
+
+    pub fn build(self) -> Relay {
+        self.0
+    }
+}
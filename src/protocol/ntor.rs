@@ -9,6 +9,8 @@
 //!
 //! Security: Uses constant-time comparison for AUTH verification to prevent timing attacks.
 
+use super::bytes::array;
+use super::debug::log_key_material;
 use crate::error::{Result, TorError};
 use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
@@ -49,9 +51,11 @@ impl NtorHandshake {
         let pub_bytes = client_public.as_bytes();
         Self::validate_entropy(pub_bytes);
 
-        log::info!("🔐 Generated client keypair:");
-        log::info!("   Public key (first 16): {:02x?}", &pub_bytes[..16]);
-        log::info!("   Public key (last 16):  {:02x?}", &pub_bytes[16..]);
+        log::info!("🔐 Generated client keypair");
+        if log_key_material() {
+            log::info!("   Public key (first 16): {:02x?}", &pub_bytes[..16]);
+            log::info!("   Public key (last 16):  {:02x?}", &pub_bytes[16..]);
+        }
 
         Self {
             client_secret,
@@ -59,6 +63,19 @@ impl NtorHandshake {
         }
     }
 
+    /// A handshake from a keypair generated elsewhere (a handshake worker)
+    ///
+    /// The public key is taken as given; the pool computed it from
+    /// `secret` with this same code.
+    pub(crate) fn from_parts(secret: [u8; 32], public: [u8; 32]) -> Self {
+        let client_public = PublicKey::from(public);
+        Self::validate_entropy(client_public.as_bytes());
+        Self {
+            client_secret: StaticSecret::from(secret),
+            client_public,
+        }
+    }
+
     /// Validate that random bytes have sufficient entropy
     ///
     /// SECURITY: Detects obvious RNG failures (all zeros, all ones, repeated patterns)
@@ -167,8 +184,10 @@ impl NtorHandshake {
 
         if !auth_valid {
             log::warn!("⚠️ Server AUTH verification failed!");
-            log::warn!("   Expected: {:02x?}", server_auth);
-            log::warn!("   Computed: {:02x?}", &computed_auth[..]);
+            if log_key_material() {
+                log::warn!("   Expected: {:02x?}", server_auth);
+                log::warn!("   Computed: {:02x?}", &computed_auth[..]);
+            }
             // SECURITY: Return error on AUTH verification failure
             return Err(TorError::Crypto("Server AUTH verification failed".into()));
         } else {
@@ -219,13 +238,10 @@ pub fn parse_created2_payload(payload: &[u8]) -> Result<(PublicKey, [u8; 32])> {
     }
 
     // Server's public key (32 bytes)
-    let mut server_public_bytes = [0u8; 32];
-    server_public_bytes.copy_from_slice(&payload[0..32]);
-    let server_public = PublicKey::from(server_public_bytes);
+    let server_public = PublicKey::from(array::<32>(payload, 0, "CREATED2 Y")?);
 
     // Server's authentication (32 bytes)
-    let mut server_auth = [0u8; 32];
-    server_auth.copy_from_slice(&payload[32..64]);
+    let server_auth = array(payload, 32, "CREATED2 AUTH")?;
 
     Ok((server_public, server_auth))
 }
@@ -0,0 +1,264 @@
+//! v3 onion addresses and key blinding (rend-spec-v3 §2.2, §6)
+//!
+//! A v3 address is the base32 of `PUBKEY | CHECKSUM | VERSION`. Clients
+//! never use the identity key directly: descriptors are stored under a
+//! per-time-period blinded key, and the subcredential derived from both
+//! keys goes into every onion service handshake.
+
+use crate::error::{Result, TorError};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use sha3::{Digest, Sha3_256};
+
+/// Address version byte for v3 onion services
+const ONION_VERSION: u8 = 3;
+
+/// Characters in a v3 address label (base32 of 35 bytes)
+const ONION_LABEL_LEN: usize = 56;
+
+/// Minutes after midnight UTC at which time periods roll over
+const ROTATION_OFFSET_MINS: u64 = 12 * 60;
+
+/// Default time period length in minutes (consensus param `hsdir-interval`)
+pub const DEFAULT_TIME_PERIOD_MINS: u64 = 1440;
+
+/// Prefix hashed into the blinding factor (includes the trailing NUL)
+const BLIND_STRING: &[u8] = b"Derive temporary signing key\0";
+
+/// The ed25519 basepoint as the spec writes it, hashed into the blinding factor
+const ED25519_BASEPOINT: &[u8] = b"(15112221349535400772501151409588531511454012693041857206046113283949847762202, 46316835694926478169428394003475163141307993866256225615783033603165251855960)";
+
+/// One time period of the onion service directory rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimePeriod {
+    /// Period number since the epoch
+    pub number: u64,
+    /// Period length in minutes
+    pub length: u64,
+}
+
+impl TimePeriod {
+    /// The period containing `unix_secs`
+    pub fn at(unix_secs: u64, length: u64) -> Self {
+        let length = length.max(1);
+        let minutes = (unix_secs / 60).saturating_sub(ROTATION_OFFSET_MINS);
+        Self {
+            number: minutes / length,
+            length,
+        }
+    }
+
+    /// Unix time at which this period starts
+    pub fn start_secs(&self) -> u64 {
+        (self.number * self.length + ROTATION_OFFSET_MINS) * 60
+    }
+
+    /// Whether `unix_secs` falls between the start of this period and the
+    /// next shared random value, half a period later
+    ///
+    /// Clients fetching in that window index HSDirs with the current SRV,
+    /// afterwards with the previous one.
+    pub fn before_next_srv(&self, unix_secs: u64) -> bool {
+        unix_secs.saturating_sub(self.start_secs()) < self.length * 60 / 2
+    }
+}
+
+/// A parsed v3 onion service address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OnionAddress {
+    /// The service's ed25519 identity key
+    pub public_key: [u8; 32],
+}
+
+impl OnionAddress {
+    /// Parse `<56 base32 chars>.onion` (or the bare label), checking the
+    /// version and checksum. Subdomains before the label are ignored.
+    pub fn parse(host: &str) -> Result<Self> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let host = host.strip_suffix(".onion").unwrap_or(&host);
+        let label = host.rsplit('.').next().unwrap_or(host);
+        if label.len() != ONION_LABEL_LEN {
+            return Err(TorError::InvalidUrl(format!(
+                "Not a v3 onion address: {} characters, expected {}",
+                label.len(),
+                ONION_LABEL_LEN
+            )));
+        }
+        let bytes = base32_decode(label)
+            .ok_or_else(|| TorError::InvalidUrl("Onion address is not valid base32".into()))?;
+        if bytes[34] != ONION_VERSION {
+            return Err(TorError::InvalidUrl(format!(
+                "Unsupported onion address version {}",
+                bytes[34]
+            )));
+        }
+        let mut public_key = [0u8; 32];
+        public_key.copy_from_slice(&bytes[..32]);
+        if checksum(&public_key) != bytes[32..34] {
+            return Err(TorError::InvalidUrl(
+                "Onion address checksum mismatch".into(),
+            ));
+        }
+        Ok(Self { public_key })
+    }
+
+    /// Blinded public key for `period`, the key descriptors are stored under
+    pub fn blinded_key(&self, period: TimePeriod) -> Result<[u8; 32]> {
+        let point = CompressedEdwardsY(self.public_key)
+            .decompress()
+            .ok_or_else(|| TorError::Crypto("Onion service key is not a curve point".into()))?;
+
+        let mut nonce = b"key-blind".to_vec();
+        nonce.extend_from_slice(&period.number.to_be_bytes());
+        nonce.extend_from_slice(&period.length.to_be_bytes());
+
+        let factor: [u8; 32] = Sha3_256::new()
+            .chain_update(BLIND_STRING)
+            .chain_update(self.public_key)
+            .chain_update(ED25519_BASEPOINT)
+            .chain_update(&nonce)
+            .finalize()
+            .into();
+        Ok(point.mul_clamped(factor).compress().to_bytes())
+    }
+
+    /// Subcredential for `period`, mixed into descriptor decryption and the
+    /// introduction handshake
+    pub fn subcredential(&self, blinded_key: &[u8; 32]) -> [u8; 32] {
+        let credential = Sha3_256::new()
+            .chain_update(b"credential")
+            .chain_update(self.public_key)
+            .finalize();
+        Sha3_256::new()
+            .chain_update(b"subcredential")
+            .chain_update(credential)
+            .chain_update(blinded_key)
+            .finalize()
+            .into()
+    }
+}
+
+impl std::fmt::Display for OnionAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = self.public_key.to_vec();
+        bytes.extend_from_slice(&checksum(&self.public_key));
+        bytes.push(ONION_VERSION);
+        write!(f, "{}.onion", base32_encode(&bytes))
+    }
+}
+
+/// Whether `host` names an onion service
+pub fn is_onion_host(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(".onion")
+}
+
+/// `H(".onion checksum" | PUBKEY | VERSION)[..2]`
+fn checksum(public_key: &[u8; 32]) -> [u8; 2] {
+    let hash = Sha3_256::new()
+        .chain_update(b".onion checksum")
+        .chain_update(public_key)
+        .chain_update([ONION_VERSION])
+        .finalize();
+    [hash[0], hash[1]]
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// RFC 4648 base32 (lowercase, unpadded) of a 56-character label
+fn base32_decode(label: &str) -> Option<[u8; 35]> {
+    let mut out = [0u8; 35];
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    let mut len = 0;
+    for c in label.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c)? as u64;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            *out.get_mut(len)? = (buffer >> bits) as u8;
+            len += 1;
+        }
+    }
+    (len == out.len()).then_some(out)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &b in bytes {
+        buffer = (buffer << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::Scalar;
+
+    const DDG: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+
+    #[test]
+    fn test_parse_round_trips_and_checks_checksum() {
+        let address = OnionAddress::parse(DDG).unwrap();
+        assert_eq!(address.to_string(), DDG);
+        assert_eq!(
+            OnionAddress::parse(&format!("www.{}", DDG.to_uppercase())).unwrap(),
+            address
+        );
+        assert!(OnionAddress::parse(&DDG.replace("duck", "duch")).is_err());
+        assert!(OnionAddress::parse("example.onion").is_err());
+    }
+
+    #[test]
+    fn test_time_period_matches_spec_example() {
+        // rend-spec-v3 §2.2.1: 2016-04-13 11:15:01 UTC is period 16903
+        let period = TimePeriod::at(1460546101, DEFAULT_TIME_PERIOD_MINS);
+        assert_eq!(period.number, 16903);
+        assert!(period.start_secs() <= 1460546101);
+        assert!(!period.before_next_srv(1460546101));
+        assert!(period.before_next_srv(period.start_secs() + 60));
+    }
+
+    #[test]
+    fn test_blinded_key_matches_blinded_secret() {
+        // Blinding the public key must agree with blinding the secret
+        // scalar, which is what the service signs descriptors with
+        let secret = Scalar::from_bytes_mod_order([7u8; 32]);
+        let public = curve25519_dalek::constants::ED25519_BASEPOINT_POINT * secret;
+        let address = OnionAddress {
+            public_key: public.compress().to_bytes(),
+        };
+        let period = TimePeriod::at(1460546101, DEFAULT_TIME_PERIOD_MINS);
+        let blinded = address.blinded_key(period).unwrap();
+
+        let mut nonce = b"key-blind".to_vec();
+        nonce.extend_from_slice(&period.number.to_be_bytes());
+        nonce.extend_from_slice(&period.length.to_be_bytes());
+        let mut factor: [u8; 32] = Sha3_256::new()
+            .chain_update(BLIND_STRING)
+            .chain_update(address.public_key)
+            .chain_update(ED25519_BASEPOINT)
+            .chain_update(&nonce)
+            .finalize()
+            .into();
+        factor[0] &= 248;
+        factor[31] &= 63;
+        factor[31] |= 64;
+        let blinded_secret = Scalar::from_bytes_mod_order(factor) * secret;
+        let expected = curve25519_dalek::constants::ED25519_BASEPOINT_POINT * blinded_secret;
+        assert_eq!(blinded, expected.compress().to_bytes());
+        assert_ne!(blinded, address.public_key);
+    }
+}
@@ -3,7 +3,9 @@
 //! Defines relay metadata and provides algorithms for selecting
 //! guard, middle, and exit nodes based on consensus data.
 
+use super::stream::StreamLifetime;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::net::{IpAddr, SocketAddr};
 
 /// A Tor relay from the consensus
@@ -40,6 +42,49 @@ pub struct Relay {
     /// Format: "$<fingerprint> $<fingerprint> ..."
     #[serde(default)]
     pub family: Option<String>,
+
+    /// Two-letter country code (lowercase), if the directory source
+    /// provides one (Onionoo-backed bridges do; raw consensus does not)
+    #[serde(default)]
+    pub country: Option<String>,
+
+    /// Autonomous system number (e.g. `"AS24940"`), if the directory
+    /// source provides one
+    #[serde(default)]
+    pub asn: Option<String>,
+
+    /// Ed25519 identity key (unpadded base64), when the relay list
+    /// carries one
+    #[serde(default)]
+    pub ed25519_identity: Option<String>,
+
+    /// Digest of the relay's microdescriptor (unpadded base64 SHA-256),
+    /// from the `m` line of a microdescriptor consensus
+    #[serde(default)]
+    pub microdesc_digest: Option<String>,
+}
+
+/// An unnamed relay at 0.0.0.0:0 with no flags, for struct update syntax
+/// (`..Default::default()`) over the optional fields
+impl Default for Relay {
+    fn default() -> Self {
+        Self {
+            nickname: String::new(),
+            fingerprint: String::new(),
+            address: IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+            or_port: 0,
+            dir_port: None,
+            flags: RelayFlags::default(),
+            bandwidth: 0,
+            published: 0,
+            ntor_onion_key: None,
+            family: None,
+            country: None,
+            asn: None,
+            ed25519_identity: None,
+            microdesc_digest: None,
+        }
+    }
 }
 
 impl Relay {
@@ -131,6 +176,119 @@ impl RelayFlags {
 
         relay_flags
     }
+
+    /// All flags with their consensus spelling
+    fn entries(&self) -> [(&'static str, bool); 10] {
+        [
+            ("Authority", self.authority),
+            ("BadExit", self.bad_exit),
+            ("Exit", self.exit),
+            ("Fast", self.fast),
+            ("Guard", self.guard),
+            ("HSDir", self.hs_dir),
+            ("Running", self.running),
+            ("Stable", self.stable),
+            ("V2Dir", self.v2_dir),
+            ("Valid", self.valid),
+        ]
+    }
+
+    /// Names of the flags that are set, in consensus spelling
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries()
+            .into_iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Look up a flag by name (case-insensitive). `None` if the name is unknown.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        self.entries()
+            .into_iter()
+            .find(|(flag, _)| flag.eq_ignore_ascii_case(name))
+            .map(|(_, set)| set)
+    }
+}
+
+/// Ports whose streams are long-lived and need Stable relays: Tor's
+/// default `LongLivedPorts` (FTP, SSH, chat and IRC among them) plus IMAP,
+/// whose IDLE sessions stay open as long
+pub const LONG_LIVED_PORTS: [u16; 14] = [
+    21, 22, 143, 706, 993, 1863, 5050, 5190, 5222, 5223, 6523, 6667, 6697, 8300,
+];
+
+/// Minimum relay properties for path selection
+///
+/// The defaults follow the Tor client: every hop must be Fast, circuits for
+/// long-lived streams use only Stable relays, and there is no bandwidth
+/// floor beyond what the Fast flag implies.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayRequirements {
+    /// Minimum consensus bandwidth weight (default: 0)
+    pub min_bandwidth: u64,
+    /// Require the Fast flag on every hop (default: true)
+    pub require_fast: bool,
+    /// Require the Stable flag on every hop of circuits for streams to
+    /// `long_lived_ports` (default: true)
+    pub require_stable_long_lived: bool,
+    /// Ports whose streams are long-lived (default: [`LONG_LIVED_PORTS`])
+    pub long_lived_ports: Vec<u16>,
+}
+
+impl Default for RelayRequirements {
+    fn default() -> Self {
+        Self {
+            min_bandwidth: 0,
+            require_fast: true,
+            require_stable_long_lived: true,
+            long_lived_ports: LONG_LIVED_PORTS.to_vec(),
+        }
+    }
+}
+
+impl RelayRequirements {
+    /// Parse requirements from JSON; omitted fields keep their defaults
+    pub fn from_json(json: &str) -> crate::error::Result<Self> {
+        serde_json::from_str(json).map_err(|e| {
+            crate::error::TorError::ParseError(format!("Invalid relay requirements: {}", e))
+        })
+    }
+
+    /// Whether a stream to `port` is long-lived
+    pub fn is_long_lived(&self, port: u16) -> bool {
+        self.long_lived_ports.contains(&port)
+    }
+
+    /// Lifetime to open a stream to `port` with, given whether it is known
+    /// to stay open regardless of port (`persistent`, e.g. an event stream)
+    ///
+    /// Always short when Stable isn't required for long-lived streams.
+    pub fn stream_lifetime(&self, port: u16, persistent: bool) -> StreamLifetime {
+        if self.require_stable_long_lived && (persistent || self.is_long_lived(port)) {
+            StreamLifetime::LongLived
+        } else {
+            StreamLifetime::Short
+        }
+    }
+
+    /// Whether `relay` may be used on a circuit (`long_lived`: for a
+    /// long-lived stream)
+    pub fn admits(&self, relay: &Relay, long_lived: bool) -> bool {
+        relay.bandwidth >= self.min_bandwidth
+            && (!self.require_fast || relay.flags.fast)
+            && (!(long_lived && self.require_stable_long_lived) || relay.flags.stable)
+    }
+}
+
+/// Position a relay is selected for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayRole {
+    Guard,
+    Middle,
+    Exit,
 }
 
 /// Relay selection algorithm
@@ -142,6 +300,18 @@ pub struct RelaySelector {
     /// Preferred guard fingerprints (from GuardState persistence)
     /// If set, these guards will be tried first
     preferred_guards: Vec<String>,
+
+    /// Exits banned for misbehavior (never selected)
+    banned_exits: HashSet<String>,
+
+    /// Relays on the operator blocklist (never selected for any position)
+    blocked: HashSet<String>,
+
+    /// Minimum relay properties
+    requirements: RelayRequirements,
+
+    /// Selecting for a circuit that will carry long-lived streams
+    long_lived: bool,
 }
 
 impl RelaySelector {
@@ -150,6 +320,10 @@ impl RelaySelector {
         Self {
             relays,
             preferred_guards: Vec::new(),
+            banned_exits: HashSet::new(),
+            blocked: HashSet::new(),
+            requirements: RelayRequirements::default(),
+            long_lived: false,
         }
     }
 
@@ -164,8 +338,88 @@ impl RelaySelector {
         &self.preferred_guards
     }
 
-    /// Check if relay uses a standard Tor port
+    /// Never select `fingerprint` as an exit again
+    pub fn ban_exit(&mut self, fingerprint: &str) {
+        self.banned_exits.insert(fingerprint.to_string());
+    }
+
+    /// Replace the set of banned exits
+    pub fn set_banned_exits(&mut self, fingerprints: Vec<String>) {
+        self.banned_exits = fingerprints.into_iter().collect();
+    }
+
+    /// Replace the set of relays blocked by the operator blocklist
+    pub fn set_blocked_relays(&mut self, fingerprints: Vec<String>) {
+        self.blocked = fingerprints.into_iter().collect();
+    }
+
+    /// Replace the minimum relay requirements
+    pub fn set_requirements(&mut self, requirements: RelayRequirements) {
+        self.requirements = requirements;
+    }
+
+    /// Get the minimum relay requirements
+    pub fn requirements(&self) -> &RelayRequirements {
+        &self.requirements
+    }
+
+    /// This selector, restricted to relays fit for streams of `lifetime`
+    pub fn for_stream(mut self, lifetime: StreamLifetime) -> Self {
+        self.long_lived = lifetime.is_long_lived();
+        self
+    }
+
+    /// Why `relay` can't be selected as `role`, or `None` if it can
+    ///
+    /// These are the rules every selection method applies; relays already
+    /// on the path are excluded separately.
+    pub fn rejection(&self, relay: &Relay, role: RelayRole) -> Option<&'static str> {
+        let (has_role, missing, known_problems): (bool, &str, &[&str]) = match role {
+            RelayRole::Guard => (
+                relay.is_guard(),
+                "missing Guard, Stable or Fast",
+                &["RicsiTORRelay"],
+            ),
+            RelayRole::Middle => (
+                relay.is_middle(),
+                "missing Fast, Stable or Running",
+                // "SharingIsCaring": suspected stale ntor key
+                &["RicsiTORRelay", "franklinrelay", "SharingIsCaring"],
+            ),
+            RelayRole::Exit => (relay.is_exit(), "missing Exit or flagged BadExit", &[]),
+        };
+        if !has_role {
+            Some(missing)
+        } else if self.blocked.contains(&relay.fingerprint) {
+            Some("on the operator blocklist")
+        } else if role == RelayRole::Exit && self.banned_exits.contains(&relay.fingerprint) {
+            Some("banned for misbehavior")
+        } else if !self.requirements.admits(relay, self.long_lived) {
+            Some("below the relay requirements")
+        } else if relay.ntor_onion_key.is_none() {
+            Some("no ntor onion key")
+        } else if !Self::is_standard_port(relay.or_port) {
+            Some("non-standard OR port")
+        } else if known_problems.contains(&relay.nickname.as_str()) {
+            // Temporarily excluded problematic relays
+            Some("excluded as a known problem relay")
+        } else {
+            None
+        }
+    }
+
+    /// Whether the selector only picks relays fit for long-lived streams
+    pub fn is_long_lived(&self) -> bool {
+        self.long_lived
+    }
+
+    /// Check if relay uses a standard Tor port (any port on a local test
+    /// network)
     fn is_standard_port(port: u16) -> bool {
+        #[cfg(feature = "test-interop")]
+        if crate::interop::local_network() {
+            return true;
+        }
         matches!(port, 443 | 8080 | 8443 | 9001 | 9030 | 9050 | 9051 | 9150)
     }
 
@@ -198,21 +452,18 @@ impl RelaySelector {
 
                 // Find this relay in the consensus
                 if let Some(relay) = self.relays.iter().find(|r| {
-                    &r.fingerprint == preferred_fp
-                        && r.is_guard()
-                        && r.ntor_onion_key.is_some()
-                        && Self::is_standard_port(r.or_port)
+                    &r.fingerprint == preferred_fp && self.rejection(r, RelayRole::Guard).is_none()
                 }) {
                     log::info!(
                         "  ✅ Using preferred guard: {} ({})",
                         &relay.nickname,
-                        &preferred_fp[..8]
+                        preferred_fp.get(..8).unwrap_or(preferred_fp)
                     );
                     selected.push(relay);
                     selected_fps.insert(&relay.fingerprint);
                 } else {
                     log::warn!(
-                        "  ⚠️ Preferred guard {} not found in consensus",
+                        "  ⚠️ Preferred guard {} not in consensus or below requirements",
                         &preferred_fp[..8.min(preferred_fp.len())]
                     );
                 }
@@ -225,12 +476,8 @@ impl RelaySelector {
                 .relays
                 .iter()
                 .filter(|r| {
-                    r.is_guard()
-                    && r.ntor_onion_key.is_some()
-                    && Self::is_standard_port(r.or_port)
-                    && !selected_fps.contains(r.fingerprint.as_str())
-                    // Temporarily exclude problematic relays for testing
-                    && r.nickname != "RicsiTORRelay"
+                    self.rejection(r, RelayRole::Guard).is_none()
+                        && !selected_fps.contains(r.fingerprint.as_str())
                 })
                 .collect();
 
@@ -297,14 +544,8 @@ impl RelaySelector {
             .relays
             .iter()
             .filter(|r| {
-                r.is_middle()
-                && r.ntor_onion_key.is_some()
-                && Self::is_standard_port(r.or_port)
-                && !exclude.contains(&r.fingerprint.as_str())
-                // Temporarily exclude problematic relays for testing
-                && r.nickname != "RicsiTORRelay"
-                && r.nickname != "franklinrelay"
-                && r.nickname != "SharingIsCaring" // Suspected stale ntor key
+                self.rejection(r, RelayRole::Middle).is_none()
+                    && !exclude.contains(&r.fingerprint.as_str())
             })
             .collect();
 
@@ -349,9 +590,7 @@ impl RelaySelector {
             .relays
             .iter()
             .filter(|r| {
-                r.is_exit()
-                    && r.ntor_onion_key.is_some()
-                    && Self::is_standard_port(r.or_port)
+                self.rejection(r, RelayRole::Exit).is_none()
                     && !exclude.contains(&r.fingerprint.as_str())
             })
             .collect();
@@ -384,6 +623,11 @@ impl RelaySelector {
         selected
     }
 
+    /// All relays known to the selector
+    pub fn relays(&self) -> &[Relay] {
+        &self.relays
+    }
+
     /// Get all guard relays
     pub fn guards(&self) -> Vec<&Relay> {
         self.relays.iter().filter(|r| r.is_guard()).collect()
@@ -408,6 +652,7 @@ impl RelaySelector {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::TestRelay;
 
     #[test]
     fn test_relay_flags_parsing() {
@@ -422,25 +667,84 @@ mod tests {
 
     #[test]
     fn test_relay_is_guard() {
-        let relay = Relay {
-            nickname: "TestGuard".to_string(),
-            fingerprint: "ABC123".to_string(),
-            address: "1.2.3.4".parse().unwrap(),
-            or_port: 9001,
-            dir_port: None,
-            flags: RelayFlags {
-                guard: true,
-                stable: true,
-                fast: true,
-                running: true,
-                ..Default::default()
-            },
-            bandwidth: 1_000_000,
-            published: 0,
-            ntor_onion_key: None,
-            family: None,
-        };
+        let relay = TestRelay::new("TestGuard")
+            .fingerprint("ABC123")
+            .address("1.2.3.4")
+            .flags(RelayFlags::from_string("Guard Stable Fast Running"))
+            .ntor_onion_key(None)
+            .build();
 
         assert!(relay.is_guard());
     }
+
+    #[test]
+    fn test_banned_and_blocked_relays_not_selected() {
+        let exit = |fingerprint: &str| {
+            TestRelay::new(fingerprint)
+                .fingerprint(fingerprint)
+                .address("1.2.3.4")
+                .or_port(443)
+                .flags(RelayFlags::from_string("Exit Fast Running"))
+                .build()
+        };
+        let mut selector = RelaySelector::new(vec![exit("GOOD"), exit("BAD")]);
+        assert_eq!(selector.select_exits(10, &[]).len(), 2);
+
+        selector.ban_exit("BAD");
+        let exits = selector.select_exits(10, &[]);
+        assert_eq!(exits.len(), 1);
+        assert_eq!(exits[0].fingerprint, "GOOD");
+
+        selector.set_banned_exits(Vec::new());
+        assert_eq!(selector.select_exits(10, &[]).len(), 2);
+
+        // Blocklisted relays are excluded from every position
+        selector.set_blocked_relays(vec!["GOOD".to_string()]);
+        let exits = selector.select_exits(10, &[]);
+        assert_eq!(exits.len(), 1);
+        assert_eq!(exits[0].fingerprint, "BAD");
+    }
+
+    #[test]
+    fn test_requirements_filter_exits() {
+        let exit = |fingerprint: &str, bandwidth: u64, flags: &str| {
+            TestRelay::new(fingerprint)
+                .fingerprint(fingerprint)
+                .address("1.2.3.4")
+                .or_port(443)
+                .flags(RelayFlags::from_string(&format!("Exit Running {}", flags)))
+                .bandwidth(bandwidth)
+                .build()
+        };
+        let mut selector = RelaySelector::new(vec![
+            exit("SLOW", 5_000_000, ""),
+            exit("FAST", 200, "Fast"),
+            exit("STABLE", 5_000_000, "Fast Stable"),
+        ]);
+        let picked = |selector: &RelaySelector| {
+            let mut fps: Vec<String> = selector
+                .select_exits(10, &[])
+                .into_iter()
+                .map(|r| r.fingerprint.clone())
+                .collect();
+            fps.sort();
+            fps
+        };
+
+        // Fast always; Stable only for long-lived ports such as SSH
+        assert_eq!(picked(&selector), ["FAST", "STABLE"]);
+        let for_port = |port: u16| {
+            let lifetime = selector.requirements().stream_lifetime(port, false);
+            picked(&selector.clone().for_stream(lifetime))
+        };
+        assert_eq!(for_port(443), ["FAST", "STABLE"]);
+        assert_eq!(for_port(22), ["STABLE"]);
+
+        selector.set_requirements(
+            RelayRequirements::from_json(r#"{"min_bandwidth":1000,"require_fast":false}"#).unwrap(),
+        );
+        assert_eq!(picked(&selector), ["SLOW", "STABLE"]);
+        assert!(selector.requirements().require_stable_long_lived);
+        assert!(RelayRequirements::from_json(r#"{"min_bandwidth":-1}"#).is_err());
+    }
 }
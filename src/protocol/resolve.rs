@@ -0,0 +1,196 @@
+//! Exit-side DNS answers
+//!
+//! Parses the address information exits send back in RELAY_RESOLVED cells
+//! and in the body of RELAY_CONNECTED (tor-spec §6.2, §6.4).
+
+use super::bytes::{array, slice, u32_at, u8_at};
+use crate::error::{Result, TorError};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// RESOLVED answer types
+const ANSWER_HOSTNAME: u8 = 0x00;
+const ANSWER_IPV4: u8 = 0x04;
+const ANSWER_IPV6: u8 = 0x06;
+const ANSWER_ERROR_TRANSIENT: u8 = 0xF0;
+const ANSWER_ERROR_NONTRANSIENT: u8 = 0xF1;
+
+/// One address an exit resolved, with the TTL it reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DnsAnswer {
+    pub address: IpAddr,
+    /// Seconds the exit says the answer stays valid
+    pub ttl: u32,
+}
+
+impl DnsAnswer {
+    /// Whether the address is loopback, private, link-local or unspecified
+    ///
+    /// Exits refuse to connect to such addresses by default, so one
+    /// reported for a public hostname suggests the exit's DNS was tampered
+    /// with.
+    pub fn is_internal(&self) -> bool {
+        match self.address {
+            IpAddr::V4(v4) => {
+                v4.is_loopback()
+                    || v4.is_private()
+                    || v4.is_link_local()
+                    || v4.is_unspecified()
+                    || v4.is_broadcast()
+            }
+            IpAddr::V6(v6) => {
+                let first = v6.segments()[0];
+                v6.is_loopback()
+                    || v6.is_unspecified()
+                    || (first & 0xfe00) == 0xfc00 // unique local
+                    || (first & 0xffc0) == 0xfe80 // link local
+                    || v6.to_ipv4_mapped().is_some_and(|v4| {
+                        DnsAnswer {
+                            address: IpAddr::V4(v4),
+                            ttl: 0,
+                        }
+                        .is_internal()
+                    })
+            }
+        }
+    }
+}
+
+/// Parse a RELAY_RESOLVED body into its address answers
+///
+/// Body: repeated `TYPE (1) | LEN (1) | VALUE (LEN) | TTL (4)`. Hostname
+/// answers (reverse lookups) are skipped; an error answer fails the parse.
+pub fn parse_resolved(data: &[u8]) -> Result<Vec<DnsAnswer>> {
+    let mut answers = Vec::new();
+    let mut rest = data;
+
+    while rest.len() >= 2 {
+        let kind = u8_at(rest, 0, "RESOLVED answer type")?;
+        let len = u8_at(rest, 1, "RESOLVED answer length")? as usize;
+        if kind == 0 && len == 0 {
+            // Zero padding after the last answer
+            break;
+        }
+        let value = slice(rest, 2, len, "RESOLVED answer")?;
+        let ttl = u32_at(rest, 2 + len, "RESOLVED answer TTL")?;
+        rest = &rest[6 + len..];
+
+        let address = match (kind, len) {
+            (ANSWER_IPV4, 4) => IpAddr::V4(Ipv4Addr::from(array::<4>(value, 0, "IPv4 answer")?)),
+            (ANSWER_IPV6, 16) => IpAddr::V6(Ipv6Addr::from(array::<16>(value, 0, "IPv6 answer")?)),
+            (ANSWER_HOSTNAME, _) => continue,
+            (ANSWER_ERROR_TRANSIENT, _) => {
+                return Err(TorError::Network(
+                    "Exit DNS lookup failed (transient)".into(),
+                ))
+            }
+            (ANSWER_ERROR_NONTRANSIENT, _) => {
+                return Err(TorError::Network("Exit could not resolve host".into()))
+            }
+            _ => {
+                return Err(TorError::ProtocolError(format!(
+                    "Bad RESOLVED answer type {} (len {})",
+                    kind, len
+                )))
+            }
+        };
+        answers.push(DnsAnswer { address, ttl });
+    }
+
+    Ok(answers)
+}
+
+/// Parse the optional address in a RELAY_CONNECTED body
+///
+/// Body is empty, `IPv4 (4) | TTL (4)`, or
+/// `0.0.0.0 (4) | 6 (1) | IPv6 (16) | TTL (4)`.
+pub fn parse_connected(data: &[u8]) -> Option<DnsAnswer> {
+    let v4: [u8; 4] = array(data, 0, "CONNECTED address").ok()?;
+    if v4 != [0, 0, 0, 0] {
+        let ttl = u32_at(data, 4, "CONNECTED TTL").ok()?;
+        return Some(DnsAnswer {
+            address: IpAddr::V4(Ipv4Addr::from(v4)),
+            ttl,
+        });
+    }
+    if u8_at(data, 4, "CONNECTED address type").ok()? == ANSWER_IPV6 {
+        let octets: [u8; 16] = array(data, 5, "CONNECTED IPv6 address").ok()?;
+        let ttl = u32_at(data, 21, "CONNECTED TTL").ok()?;
+        return Some(DnsAnswer {
+            address: IpAddr::V6(Ipv6Addr::from(octets)),
+            ttl,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolved_answers() {
+        let mut body = vec![ANSWER_IPV4, 4, 93, 184, 216, 34, 0, 0, 0x0E, 0x10];
+        body.extend_from_slice(&[ANSWER_HOSTNAME, 3, b'a', b'.', b'b', 0, 0, 0, 60]);
+        body.extend_from_slice(&[ANSWER_IPV6, 16]);
+        body.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        body.extend_from_slice(&300u32.to_be_bytes());
+        body.extend_from_slice(&[0; 20]);
+
+        let answers = parse_resolved(&body).unwrap();
+        assert_eq!(answers.len(), 2);
+        assert_eq!(
+            answers[0].address,
+            "93.184.216.34".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(answers[0].ttl, 3600);
+        assert_eq!(answers[1].address, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(answers[1].ttl, 300);
+
+        assert!(parse_resolved(&[ANSWER_ERROR_NONTRANSIENT, 0, 0, 0, 0, 0]).is_err());
+        assert!(parse_resolved(&[ANSWER_IPV4, 4, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_parse_connected_address() {
+        assert_eq!(parse_connected(&[]), None);
+
+        let v4 = parse_connected(&[10, 0, 0, 1, 0, 0, 0, 120]).unwrap();
+        assert_eq!(v4.address, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(v4.ttl, 120);
+
+        let mut body = vec![0, 0, 0, 0, ANSWER_IPV6];
+        body.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        body.extend_from_slice(&60u32.to_be_bytes());
+        let v6 = parse_connected(&body).unwrap();
+        assert_eq!(v6.address, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(v6.ttl, 60);
+    }
+
+    #[test]
+    fn test_internal_addresses() {
+        let internal = |ip: &str| {
+            DnsAnswer {
+                address: ip.parse().unwrap(),
+                ttl: 0,
+            }
+            .is_internal()
+        };
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.1",
+            "169.254.1.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(internal(ip), "{} not internal", ip);
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1::1", "::ffff:8.8.8.8"] {
+            assert!(!internal(ip), "{} internal", ip);
+        }
+    }
+}
@@ -5,16 +5,189 @@
 //! window management (500-cell initial window, 50-cell SENDME increments).
 
 use super::flow_control::StreamFlowControl;
-use super::{Circuit, RelayCell, RelayCommand};
+use super::resolve::{parse_connected, parse_resolved, DnsAnswer};
+use super::{Circuit, RelayCell, RelayCommand, RelayRequirements};
 use crate::error::{Result, TorError};
+use crate::memory::{self, Subsystem};
 use futures::io::{AsyncRead, AsyncWrite};
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::io;
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::task::{Context, Poll, Waker};
 
+/// How long a stream is expected to stay open
+///
+/// Long-lived streams (SSH sessions, IRC, IMAP IDLE, event streams) only
+/// go through exits with the Stable flag, as with Tor's `LongLivedPorts`:
+/// an exit that restarts often would cut them off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamLifetime {
+    /// Request/response traffic
+    #[default]
+    Short,
+    /// Stays open for minutes or hours; needs a Stable exit
+    LongLived,
+}
+
+impl StreamLifetime {
+    pub fn is_long_lived(&self) -> bool {
+        *self == StreamLifetime::LongLived
+    }
+
+    /// Whether `circuit` may carry a stream of this lifetime
+    ///
+    /// Onion service circuits have no exit, so any of them will do.
+    pub fn suits(&self, circuit: &Circuit) -> bool {
+        !self.is_long_lived()
+            || circuit.has_service_hop()
+            || circuit.relays.last().is_some_and(|exit| exit.flags.stable)
+    }
+}
+
+/// RELAY_BEGIN flag: the exit may connect over IPv6
+pub const BEGIN_FLAG_IPV6_OK: u32 = 1 << 0;
+
+/// RELAY_BEGIN flag: the exit must not connect over IPv4
+pub const BEGIN_FLAG_IPV4_NOT_OK: u32 = 1 << 1;
+
+/// RELAY_BEGIN flag: prefer IPv6 when a hostname has both address kinds
+pub const BEGIN_FLAG_IPV6_PREFERRED: u32 = 1 << 2;
+
+/// Address families an exit may use for a stream (RELAY_BEGIN FLAGS)
+///
+/// With no flags an exit only connects over IPv4, so hosts with only
+/// AAAA records fail; `ipv6_ok` lets it use IPv6. `ipv4_not_ok` and
+/// `ipv6_preferred` both imply `ipv6_ok`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BeginFlags {
+    /// The exit may connect over IPv6
+    pub ipv6_ok: bool,
+    /// The exit must not connect over IPv4
+    pub ipv4_not_ok: bool,
+    /// Prefer IPv6 when the host has both address kinds
+    pub ipv6_preferred: bool,
+}
+
+impl BeginFlags {
+    /// IPv6 allowed and preferred, IPv4 still as a fallback
+    pub fn prefer_ipv6() -> Self {
+        Self {
+            ipv6_ok: true,
+            ipv6_preferred: true,
+            ..Self::default()
+        }
+    }
+
+    /// The FLAGS field
+    pub fn bits(&self) -> u32 {
+        let mut bits = 0;
+        if self.ipv6_ok || self.ipv4_not_ok || self.ipv6_preferred {
+            bits |= BEGIN_FLAG_IPV6_OK;
+        }
+        if self.ipv4_not_ok {
+            bits |= BEGIN_FLAG_IPV4_NOT_OK;
+        }
+        if self.ipv6_preferred {
+            bits |= BEGIN_FLAG_IPV6_PREFERRED;
+        }
+        bits
+    }
+}
+
+/// Longest hostname a RELAY_BEGIN may carry (DNS limit)
+const MAX_HOSTNAME_LEN: usize = 255;
+
+/// Where a RELAY_BEGIN asks the exit to connect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BeginTarget {
+    /// Resolved by the exit
+    Hostname(String),
+    /// Connected to directly, no DNS
+    Address(IpAddr),
+}
+
+impl BeginTarget {
+    /// Parse a host as given in a URL: hostname, IPv4 literal, or IPv6
+    /// literal with or without brackets
+    pub fn parse(host: &str) -> Result<Self> {
+        let unbracketed = host.strip_prefix('[').and_then(|h| h.strip_suffix(']'));
+        if let Some(inner) = unbracketed {
+            return inner
+                .parse::<std::net::Ipv6Addr>()
+                .map(|v6| BeginTarget::Address(IpAddr::V6(v6)))
+                .map_err(|_| TorError::Stream(format!("Invalid IPv6 literal: {}", host)));
+        }
+        if let Ok(address) = host.parse::<IpAddr>() {
+            return Ok(BeginTarget::Address(address));
+        }
+
+        let valid = !host.is_empty()
+            && host.len() <= MAX_HOSTNAME_LEN
+            && host
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'));
+        if !valid {
+            return Err(TorError::Stream(format!(
+                "Invalid stream target: {:?}",
+                host
+            )));
+        }
+        Ok(BeginTarget::Hostname(host.to_string()))
+    }
+
+    /// The ADDRPORT string (IPv6 in brackets, as tor-spec requires)
+    pub fn addrport(&self, port: u16) -> String {
+        match self {
+            BeginTarget::Hostname(host) => format!("{}:{}", host, port),
+            BeginTarget::Address(address) => {
+                format!("{}:{}", crate::dns_cache::begin_host(*address), port)
+            }
+        }
+    }
+
+    /// Flags the exit needs to honour this target
+    ///
+    /// An exit refuses an IPv6 literal unless IPv6 is allowed, so those
+    /// ask for IPv6 and prefer it.
+    pub fn flags(&self) -> u32 {
+        match self {
+            BeginTarget::Address(IpAddr::V6(_)) => BEGIN_FLAG_IPV6_OK | BEGIN_FLAG_IPV6_PREFERRED,
+            _ => 0,
+        }
+    }
+}
+
+/// RELAY_BEGIN body for `host:port`: `ADDRPORT NUL [FLAGS]`
+///
+/// `flags` are combined with those the target itself needs, and only sent
+/// when non-zero, so the common case is unchanged.
+pub fn begin_payload(host: &str, port: u16, flags: BeginFlags) -> Result<Vec<u8>> {
+    if port == 0 {
+        return Err(TorError::Stream(
+            "Port 0 is not a valid stream target".into(),
+        ));
+    }
+    let target = BeginTarget::parse(host)?;
+    if flags.ipv4_not_ok && matches!(target, BeginTarget::Address(IpAddr::V4(_))) {
+        return Err(TorError::Stream(format!(
+            "IPv4 target {} with IPv4 not allowed",
+            host
+        )));
+    }
+    let mut payload = target.addrport(port).into_bytes();
+    payload.push(0);
+    let flags = flags.bits() | target.flags();
+    if flags != 0 {
+        payload.extend_from_slice(&flags.to_be_bytes());
+    }
+    Ok(payload)
+}
+
 /// Stream manager for opening streams through circuits
 pub struct StreamManager {
     /// The circuit to use
@@ -22,6 +195,9 @@ pub struct StreamManager {
 
     /// Next stream ID to allocate
     next_stream_id: u16,
+
+    /// Flags sent with each RELAY_BEGIN
+    begin_flags: BeginFlags,
 }
 
 impl StreamManager {
@@ -30,23 +206,95 @@ impl StreamManager {
         Self {
             circuit,
             next_stream_id: 1, // Stream IDs start at 1
+            begin_flags: BeginFlags::default(),
         }
     }
 
+    /// Send `flags` with the streams this manager opens
+    pub fn with_begin_flags(mut self, flags: BeginFlags) -> Self {
+        self.begin_flags = flags;
+        self
+    }
+
     /// Open a stream to a destination through the circuit
-    pub async fn open_stream(&mut self, host: &str, port: u16) -> Result<TorStream> {
-        let stream_id = self.allocate_stream_id();
+    ///
+    /// Long-lived streams are refused on circuits whose exit lacks the
+    /// Stable flag.
+    pub async fn open_stream(
+        &mut self,
+        host: &str,
+        port: u16,
+        lifetime: StreamLifetime,
+    ) -> Result<TorStream> {
+        if !lifetime.suits(&self.circuit.borrow()) {
+            return Err(TorError::Stream(format!(
+                "Long-lived stream to port {} needs a Stable exit",
+                port
+            )));
+        }
+        // Create RELAY_BEGIN cell before taking a stream ID, so a bad
+        // target costs nothing
+        let payload = begin_payload(host, port, self.begin_flags)?;
+        self.begin(RelayCommand::Begin, payload, &format!("{}:{}", host, port))
+            .await
+    }
 
-        log::info!("Opening stream {} to {}:{}", stream_id, host, port);
+    /// Open a stream to the directory server of the circuit's last relay
+    /// (RELAY_BEGIN_DIR)
+    ///
+    /// The relay answers directory requests itself over its ORPort, so
+    /// nothing but the relay sees them, and the circuit needn't leave the
+    /// Tor network: a one-hop circuit to a guard will do.
+    pub async fn open_dir_stream(&mut self) -> Result<TorStream> {
+        let directory = self
+            .circuit
+            .borrow()
+            .relays
+            .last()
+            .map(|r| format!("{}'s directory", r.nickname))
+            .ok_or_else(|| TorError::Stream("BEGIN_DIR on a circuit with no hops".into()))?;
+        self.begin(RelayCommand::BeginDir, Vec::new(), &directory)
+            .await
+    }
+
+    /// Open a stream to `port` on the onion service the circuit is joined to
+    ///
+    /// The service picks the target from the port alone, so RELAY_BEGIN
+    /// carries an empty address (`:port`), as Tor clients send.
+    pub async fn open_service_stream(&mut self, port: u16) -> Result<TorStream> {
+        if !self.circuit.borrow().has_service_hop() {
+            return Err(TorError::Stream(
+                "Circuit is not joined to an onion service".into(),
+            ));
+        }
+        if port == 0 {
+            return Err(TorError::Stream(
+                "Port 0 is not a valid stream target".into(),
+            ));
+        }
+        let payload = format!(":{}\0", port).into_bytes();
+        self.begin(
+            RelayCommand::Begin,
+            payload,
+            &format!("onion service port {}", port),
+        )
+        .await
+    }
+
+    /// Send `command` on a new stream ID and wait for RELAY_CONNECTED
+    async fn begin(
+        &mut self,
+        command: RelayCommand,
+        payload: Vec<u8>,
+        target: &str,
+    ) -> Result<TorStream> {
+        let stream_id = self.allocate_stream_id();
 
-        // Create RELAY_BEGIN cell
-        // Format: ADDRPORT [FLAGS] NUL
-        // ADDRPORT is "host:port" null-terminated
-        let target = format!("{}:{}\0", host, port);
+        log::info!("Opening stream {} to {}", stream_id, target);
 
-        let begin_cell = RelayCell::new(RelayCommand::Begin, stream_id, target.as_bytes().to_vec());
+        let begin_cell = RelayCell::new(command, stream_id, payload);
 
-        log::info!("  Sending RELAY_BEGIN cell (stream_id={})", stream_id);
+        log::info!("  Sending {:?} cell (stream_id={})", command, stream_id);
 
         // Send RELAY_BEGIN through circuit (borrow mutably)
         self.circuit
@@ -74,11 +322,12 @@ impl StreamManager {
         // Check response type
         match response.command {
             RelayCommand::Connected => {
-                log::info!("Stream {} opened to {}:{}", stream_id, host, port);
+                log::info!("Stream {} opened to {}", stream_id, target);
 
                 Ok(TorStream {
                     circuit: Rc::clone(&self.circuit),
                     stream_id,
+                    connected_address: parse_connected(&response.data),
                     flow_control: StreamFlowControl::new(stream_id),
                     recv_buffer: VecDeque::new(),
                     read_waker: None,
@@ -97,7 +346,48 @@ impl StreamManager {
                 )))
             }
             _ => Err(TorError::ProtocolError(format!(
-                "Unexpected response to RELAY_BEGIN: {:?}",
+                "Unexpected response to {:?}: {:?}",
+                command, response.command
+            ))),
+        }
+    }
+
+    /// Ask the exit to resolve a hostname (RELAY_RESOLVE)
+    ///
+    /// Uses a stream ID of its own but opens no stream. Returns the exit's
+    /// address answers with their TTLs.
+    // The borrow covers exactly one request/response exchange, so no other
+    // cell can interleave with it on this circuit
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub async fn resolve(&mut self, host: &str) -> Result<Vec<DnsAnswer>> {
+        let stream_id = self.allocate_stream_id();
+        log::info!("Resolving {} via exit (stream_id={})", host, stream_id);
+
+        let resolve_cell = RelayCell::new(
+            RelayCommand::Resolve,
+            stream_id,
+            format!("{}\0", host).into_bytes(),
+        );
+        let response = {
+            let mut circuit = self.circuit.borrow_mut();
+            circuit.send_relay_cell(&resolve_cell).await?;
+            circuit.receive_relay_cell().await?
+        };
+        if response.stream_id != stream_id {
+            return Err(TorError::Stream(format!(
+                "Wrong stream ID in response: expected {}, got {}",
+                stream_id, response.stream_id
+            )));
+        }
+
+        match response.command {
+            RelayCommand::Resolved => parse_resolved(&response.data),
+            RelayCommand::End => Err(TorError::Stream(format!(
+                "Resolve refused (reason: {})",
+                response.data.first().copied().unwrap_or(0)
+            ))),
+            _ => Err(TorError::ProtocolError(format!(
+                "Unexpected response to RELAY_RESOLVE: {:?}",
                 response.command
             ))),
         }
@@ -125,6 +415,9 @@ pub struct TorStream {
     /// Stream ID
     stream_id: u16,
 
+    /// Address the exit connected to, if it reported one in RELAY_CONNECTED
+    connected_address: Option<DnsAnswer>,
+
     /// Flow control (SENDME windows)
     flow_control: StreamFlowControl,
 
@@ -149,6 +442,11 @@ impl TorStream {
         self.circuit.borrow().id
     }
 
+    /// Address (and DNS TTL) the exit reported in RELAY_CONNECTED
+    pub fn connected_address(&self) -> Option<DnsAnswer> {
+        self.connected_address
+    }
+
     /// Check if stream is closed
     pub fn is_closed(&self) -> bool {
         self.closed
@@ -174,11 +472,15 @@ impl TorStream {
         let _ = self.circuit.borrow_mut().send_relay_cell(&end_cell).await;
 
         self.closed = true;
+        self.circuit.borrow_mut().discard_held_cells(self.stream_id);
 
         Ok(())
     }
 
     /// Write all data through the stream (may require multiple RELAY_DATA cells)
+    ///
+    /// When the stream or circuit window closes, waits for the SENDMEs that
+    /// reopen it, so bodies of any size can be sent.
     pub async fn write_all(&mut self, data: &[u8]) -> Result<()> {
         if self.closed {
             return Err(TorError::Stream("Stream is closed".into()));
@@ -186,6 +488,7 @@ impl TorStream {
 
         let mut offset = 0;
         while offset < data.len() {
+            self.wait_for_send_window().await?;
             let sent = self.send_data(&data[offset..]).await?;
             if sent == 0 {
                 return Err(TorError::Stream("Failed to send data".into()));
@@ -213,6 +516,8 @@ impl TorStream {
                     break;
                 }
                 Ok(n) => {
+                    memory::check(Subsystem::Buffers, n)?;
+                    let _memory = memory::scope(Subsystem::Buffers);
                     response.extend_from_slice(&buf[..n]);
                     log::debug!("  Received {} bytes, total {} bytes", n, response.len());
                 }
@@ -246,6 +551,8 @@ impl TorStream {
                     break;
                 }
                 Ok(n) => {
+                    memory::check(Subsystem::Buffers, n)?;
+                    let _memory = memory::scope(Subsystem::Buffers);
                     response.extend_from_slice(&buf[..n]);
                     log::debug!("  Received {} bytes, total {} bytes", n, response.len());
                     empty_reads = 0;
@@ -305,6 +612,82 @@ impl TorStream {
         Ok(to_send)
     }
 
+    /// Wait until the stream and circuit windows both allow a DATA cell,
+    /// handling the SENDMEs that reopen them
+    ///
+    /// Data the peer sends meanwhile (such as an early error response) is
+    /// buffered for the next read; cells for the circuit's other streams
+    /// are held on the circuit for them.
+    async fn wait_for_send_window(&mut self) -> Result<()> {
+        loop {
+            let circuit_open = self.circuit.borrow().can_package();
+            if circuit_open && self.flow_control.can_send() {
+                return Ok(());
+            }
+            log::debug!(
+                "Stream {} waiting for SENDME ({} window closed)",
+                self.stream_id,
+                if circuit_open { "stream" } else { "circuit" }
+            );
+            let Some(cell) = self.next_cell(!circuit_open).await? else {
+                continue;
+            };
+            if cell.stream_id != self.stream_id {
+                continue;
+            }
+            match cell.command {
+                RelayCommand::Sendme => self.flow_control.on_sendme_received(),
+                RelayCommand::Data => {
+                    if self.flow_control.on_receive_data() {
+                        self.send_sendme().await?;
+                    }
+                    let _memory = memory::scope(Subsystem::Buffers);
+                    self.recv_buffer.extend(&cell.data);
+                }
+                RelayCommand::End => {
+                    self.closed = true;
+                    return Err(TorError::Stream(format!(
+                        "Stream {} closed by the peer while sending",
+                        self.stream_id
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Next cell for this stream or the circuit itself, holding the ones
+    /// for the circuit's other streams on the circuit until they read them
+    ///
+    /// With `until_packagable`, returns `None` once the circuit window
+    /// reopens instead.
+    async fn next_cell(&mut self, until_packagable: bool) -> Result<Option<RelayCell>> {
+        loop {
+            if let Some(cell) = self.circuit.borrow_mut().take_held_cell(self.stream_id) {
+                return Ok(Some(cell));
+            }
+            let cell = {
+                let mut circuit = self.circuit.borrow_mut();
+                if until_packagable {
+                    circuit.receive_until_packagable().await?
+                } else {
+                    Some(circuit.receive_relay_cell().await?)
+                }
+            };
+            match cell {
+                Some(cell) if cell.stream_id != self.stream_id && cell.stream_id != 0 => {
+                    log::debug!(
+                        "Holding cell for stream {} (read by stream {})",
+                        cell.stream_id,
+                        self.stream_id
+                    );
+                    self.circuit.borrow_mut().hold_cell(cell);
+                }
+                cell => return Ok(cell),
+            }
+        }
+    }
+
     /// Read some bytes from the stream (for TLS layer)
     ///
     /// This is a simpler interface than recv_data for use by the TLS layer.
@@ -351,19 +734,10 @@ impl TorStream {
 
         // Loop to handle SENDME cells (they don't contain user data)
         loop {
-            // Receive RELAY cell from circuit
-            let relay_cell = self.circuit.borrow_mut().receive_relay_cell().await?;
-
-            // Check if it's for our stream (stream_id 0 = circuit-level)
-            if relay_cell.stream_id != self.stream_id && relay_cell.stream_id != 0 {
-                // Buffer data for other streams (best-effort; proper mux in stream_mux.rs)
-                log::debug!(
-                    "Received cell for stream {} (expected {}), dropping",
-                    relay_cell.stream_id,
-                    self.stream_id
-                );
+            // Receive our (or a circuit-level) RELAY cell from the circuit
+            let Some(relay_cell) = self.next_cell(false).await? else {
                 continue;
-            }
+            };
 
             // Handle different relay commands
             match relay_cell.command {
@@ -381,6 +755,7 @@ impl TorStream {
 
                     // Buffer any overflow (data larger than caller's buffer)
                     if relay_cell.data.len() > buf.len() {
+                        let _memory = memory::scope(Subsystem::Buffers);
                         self.recv_buffer.extend(&relay_cell.data[buf.len()..]);
                     }
 
@@ -419,6 +794,9 @@ impl Drop for TorStream {
         if !self.closed {
             log::warn!("Stream {} dropped without being closed", self.stream_id);
         }
+        if let Ok(mut circuit) = self.circuit.try_borrow_mut() {
+            circuit.discard_held_cells(self.stream_id);
+        }
     }
 }
 
@@ -507,19 +885,23 @@ impl AsyncWrite for TorStream {
 /// Stream builder for convenient stream creation
 pub struct StreamBuilder {
     manager: StreamManager,
+    /// Classifies streams as long-lived by port
+    requirements: RelayRequirements,
 }
 
 impl StreamBuilder {
-    /// Create a new stream builder
-    pub fn new(circuit: Rc<RefCell<Circuit>>) -> Self {
+    /// Create a new stream builder using the configured relay requirements
+    pub fn new(circuit: Rc<RefCell<Circuit>>, requirements: RelayRequirements) -> Self {
         Self {
             manager: StreamManager::new(circuit),
+            requirements,
         }
     }
 
     /// Open a stream to a host:port
     pub async fn connect(&mut self, host: &str, port: u16) -> Result<TorStream> {
-        self.manager.open_stream(host, port).await
+        let lifetime = self.requirements.stream_lifetime(port, false);
+        self.manager.open_stream(host, port, lifetime).await
     }
 
     /// Open an HTTP stream
@@ -536,7 +918,7 @@ impl StreamBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::{CircuitKeys, Relay};
+    use crate::protocol::{CircuitKeys, TestRelay};
 
     fn create_test_keys() -> CircuitKeys {
         CircuitKeys {
@@ -590,4 +972,85 @@ mod tests {
         assert_eq!(manager.allocate_stream_id(), u16::MAX);
         assert_eq!(manager.allocate_stream_id(), 1); // Wrapped to 1 (skip 0)
     }
+
+    #[test]
+    fn test_long_lived_streams_need_stable_exit() {
+        let requirements = RelayRequirements::default();
+        let lifetime = |port| requirements.stream_lifetime(port, false);
+        assert_eq!(lifetime(22), StreamLifetime::LongLived);
+        assert_eq!(lifetime(993), StreamLifetime::LongLived);
+        assert_eq!(lifetime(443), StreamLifetime::Short);
+
+        let exit = |flags: &str| {
+            TestRelay::new("exit")
+                .fingerprint("EXIT")
+                .address("1.2.3.4")
+                .or_port(443)
+                .flags(crate::protocol::RelayFlags::from_string(flags))
+                .ntor_onion_key(None)
+                .build()
+        };
+        let unstable = Circuit::new(1, vec![exit("Exit Fast")], create_test_keys());
+        let stable = Circuit::new(2, vec![exit("Exit Fast Stable")], create_test_keys());
+
+        assert!(StreamLifetime::Short.suits(&unstable));
+        assert!(!StreamLifetime::LongLived.suits(&unstable));
+        assert!(StreamLifetime::LongLived.suits(&stable));
+        assert!(!StreamLifetime::LongLived.suits(&Circuit::new(3, vec![], create_test_keys())));
+    }
+
+    #[test]
+    fn test_begin_payload_address_literals() {
+        let none = BeginFlags::default();
+        assert_eq!(
+            begin_payload("example.com", 443, none).unwrap(),
+            b"example.com:443\0"
+        );
+        assert_eq!(
+            begin_payload("10.0.0.1", 80, none).unwrap(),
+            b"10.0.0.1:80\0"
+        );
+
+        // IPv6 goes in brackets, with or without them in the input, and
+        // carries the flags an exit needs to connect over IPv6
+        let mut v6 = b"[2001:db8::1]:8080\0".to_vec();
+        v6.extend_from_slice(&(BEGIN_FLAG_IPV6_OK | BEGIN_FLAG_IPV6_PREFERRED).to_be_bytes());
+        assert_eq!(begin_payload("[2001:db8::1]", 8080, none).unwrap(), v6);
+        assert_eq!(begin_payload("2001:db8::1", 8080, none).unwrap(), v6);
+    }
+
+    #[test]
+    fn test_begin_payload_rejects_bad_targets() {
+        for host in ["", "[10.0.0.1]", "[::1", "evil.com:22", "a\0b", "has space"] {
+            assert!(
+                begin_payload(host, 80, BeginFlags::default()).is_err(),
+                "{:?} accepted",
+                host
+            );
+        }
+        assert!(begin_payload("example.com", 0, BeginFlags::default()).is_err());
+        assert!(begin_payload(&"a".repeat(256), 80, BeginFlags::default()).is_err());
+    }
+
+    #[test]
+    fn test_begin_flags() {
+        assert_eq!(BeginFlags::default().bits(), 0);
+        assert_eq!(
+            BeginFlags::prefer_ipv6().bits(),
+            BEGIN_FLAG_IPV6_OK | BEGIN_FLAG_IPV6_PREFERRED
+        );
+        let v6_only = BeginFlags {
+            ipv4_not_ok: true,
+            ..BeginFlags::default()
+        };
+        assert_eq!(v6_only.bits(), BEGIN_FLAG_IPV6_OK | BEGIN_FLAG_IPV4_NOT_OK);
+
+        let mut payload = b"example.com:443\0".to_vec();
+        payload.extend_from_slice(&v6_only.bits().to_be_bytes());
+        assert_eq!(begin_payload("example.com", 443, v6_only).unwrap(), payload);
+        assert!(begin_payload("10.0.0.1", 443, v6_only).is_err());
+
+        let parsed: BeginFlags = serde_json::from_str(r#"{"ipv6_ok": true}"#).unwrap();
+        assert_eq!(parsed.bits(), BEGIN_FLAG_IPV6_OK);
+    }
 }
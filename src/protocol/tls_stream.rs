@@ -13,6 +13,13 @@ use std::sync::Arc;
 /// Buffer size for TLS records
 const TLS_BUFFER_SIZE: usize = 16384;
 
+/// Hex SHA-256 of the server's leaf certificate, once the handshake is done
+pub(crate) fn leaf_certificate_digest(tls: &ClientConnection) -> Option<String> {
+    use sha2::{Digest, Sha256};
+    let leaf = tls.peer_certificates()?.first()?;
+    Some(hex::encode(Sha256::digest(leaf.as_ref())))
+}
+
 /// TLS-wrapped Tor stream for HTTPS connections
 pub struct TlsTorStream {
     /// The underlying Tor stream
@@ -252,6 +259,11 @@ impl TlsTorStream {
         Ok(result)
     }
 
+    /// Hex SHA-256 of the server's leaf certificate
+    pub fn certificate_digest(&self) -> Option<String> {
+        leaf_certificate_digest(&self.tls)
+    }
+
     /// Close the TLS connection
     pub async fn close(&mut self) -> Result<()> {
         log::debug!("  🔒 Closing TLS connection");
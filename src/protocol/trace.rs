@@ -0,0 +1,232 @@
+//! Relay cell trace capture
+//!
+//! An opt-in, pcap-like record of relay cell headers as they cross the
+//! circuit boundary: outgoing cells after their digest is set, incoming
+//! cells once a hop recognizes them. Only headers are kept (command,
+//! stream, recognized, digest, length, hop); payloads never are. Entries go
+//! into a bounded in-memory ring that JS exports as JSON, for debugging
+//! digest mismatches or flow-control window stalls without reading the
+//! console. Capture is off by default and costs a flag check when off.
+
+use super::cell::RelayCell;
+use crate::runtime::timer::now_ms;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// Entries kept when capture is started without a capacity
+pub const DEFAULT_TRACE_CAPACITY: usize = 1_000;
+
+/// Largest capacity capture accepts
+pub const MAX_TRACE_CAPACITY: usize = 50_000;
+
+/// Which way a cell was going
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Towards the exit
+    Sent,
+    /// From a hop on the circuit
+    Received,
+}
+
+/// Header of one traced relay cell
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CellTraceEntry {
+    pub timestamp_ms: u64,
+    pub circuit_id: u32,
+    pub direction: Direction,
+    /// Hop that sent or will process the cell (0 = guard)
+    pub hop: usize,
+    /// Relay command (`"Data"`, `"Sendme"`, `"Begin"`…)
+    pub command: String,
+    pub stream_id: u16,
+    pub recognized: u16,
+    /// Digest field as hex
+    pub digest: String,
+    /// Whether the digest matched our running digest; `None` if unchecked
+    pub digest_ok: Option<bool>,
+    /// Length field (payload bytes, not recorded)
+    pub length: u16,
+}
+
+impl CellTraceEntry {
+    fn new(
+        circuit_id: u32,
+        direction: Direction,
+        hop: usize,
+        cell: &RelayCell,
+        digest: [u8; 4],
+        digest_ok: Option<bool>,
+    ) -> Self {
+        Self {
+            timestamp_ms: now_ms(),
+            circuit_id,
+            direction,
+            hop,
+            command: format!("{:?}", cell.command),
+            stream_id: cell.stream_id,
+            recognized: cell.recognized,
+            digest: hex::encode(digest),
+            digest_ok,
+            length: cell.length,
+        }
+    }
+}
+
+/// Bounded trace of the most recent cells
+#[derive(Debug, Default)]
+pub struct CellTrace {
+    entries: VecDeque<CellTraceEntry>,
+    /// 0 while not capturing
+    capacity: usize,
+    /// Entries pushed out by newer ones since capture started
+    dropped: u64,
+}
+
+impl CellTrace {
+    /// Whether cells are being recorded
+    pub fn capturing(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Start recording up to `capacity` entries, or stop with 0; entries
+    /// already captured stay until [`CellTrace::clear`]
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.min(MAX_TRACE_CAPACITY);
+        while self.capturing() && self.entries.len() > self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// Store an entry, dropping the oldest one when full
+    pub fn push(&mut self, entry: CellTraceEntry) {
+        if !self.capturing() {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Captured entries, oldest first
+    pub fn entries(&self) -> Vec<CellTraceEntry> {
+        self.entries.iter().cloned().collect()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Forget captured entries
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dropped = 0;
+    }
+}
+
+thread_local! {
+    static TRACE: RefCell<CellTrace> = RefCell::new(CellTrace::default());
+}
+
+/// Start capturing relay cell headers into a ring of `capacity` entries
+/// (capped at [`MAX_TRACE_CAPACITY`]), or stop with 0
+pub fn set_trace_capacity(capacity: usize) {
+    TRACE.with(|trace| trace.borrow_mut().set_capacity(capacity));
+}
+
+/// Whether relay cell headers are being captured
+pub fn tracing() -> bool {
+    TRACE.with(|trace| trace.borrow().capturing())
+}
+
+/// Record a relay cell header, if capturing
+pub(crate) fn record(
+    circuit_id: u32,
+    direction: Direction,
+    hop: usize,
+    cell: &RelayCell,
+    digest: [u8; 4],
+    digest_ok: Option<bool>,
+) {
+    TRACE.with(|trace| {
+        let mut trace = trace.borrow_mut();
+        if trace.capturing() {
+            trace.push(CellTraceEntry::new(
+                circuit_id, direction, hop, cell, digest, digest_ok,
+            ));
+        }
+    });
+}
+
+/// Captured entries, oldest first, with the number dropped for space
+pub fn trace_entries() -> (Vec<CellTraceEntry>, u64) {
+    TRACE.with(|trace| {
+        let trace = trace.borrow();
+        (trace.entries(), trace.dropped())
+    })
+}
+
+/// Forget captured entries (capture continues if on)
+pub fn clear_trace() {
+    TRACE.with(|trace| trace.borrow_mut().clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::RelayCommand;
+
+    fn entry(stream_id: u16) -> CellTraceEntry {
+        let cell = RelayCell::new(RelayCommand::Sendme, stream_id, vec![1, 2, 3]);
+        CellTraceEntry::new(
+            7,
+            Direction::Received,
+            2,
+            &cell,
+            [0xde, 0xad, 0xbe, 0xef],
+            Some(false),
+        )
+    }
+
+    #[test]
+    fn test_capture_is_opt_in_and_bounded() {
+        let mut trace = CellTrace::default();
+        trace.push(entry(1));
+        assert!(trace.entries().is_empty());
+
+        trace.set_capacity(2);
+        for stream_id in 1..=3 {
+            trace.push(entry(stream_id));
+        }
+        let entries = trace.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].stream_id, 2);
+        assert_eq!(trace.dropped(), 1);
+
+        // Stopping keeps what was captured
+        trace.set_capacity(0);
+        trace.push(entry(4));
+        assert_eq!(trace.entries().len(), 2);
+        trace.clear();
+        assert!(trace.entries().is_empty());
+    }
+
+    #[test]
+    fn test_entry_has_header_only() {
+        let json = serde_json::to_value(entry(5)).unwrap();
+        assert_eq!(json["command"], "Sendme");
+        assert_eq!(json["direction"], "received");
+        assert_eq!(json["digest"], "deadbeef");
+        assert_eq!(json["digest_ok"], false);
+        assert_eq!(json["length"], 3);
+        assert!(json.get("data").is_none());
+    }
+}
@@ -4,14 +4,27 @@
 //! - Circuit creation storms (probing attacks)
 //! - Stream flooding (resource exhaustion)
 //! - Bandwidth abuse
+//!
+//! Circuit creation is limited twice: each isolation key draws from its own
+//! token bucket (a burst that refills at a steady rate), so one busy site
+//! can't use up every other site's circuits, and all keys together stay
+//! under a global per-window ceiling.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 
-use std::collections::VecDeque;
+use crate::runtime::timer::{system_clock, SharedClock};
 
 /// Rate limiter configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RateLimiterConfig {
-    /// Max circuits per minute
+    /// Max circuits per window across all isolation keys
     pub circuits_per_minute: u32,
+    /// Circuits one isolation key may create back to back
+    pub key_burst: u32,
+    /// Steady rate at which an isolation key's burst refills, per minute
+    pub key_circuits_per_minute: u32,
     /// Max streams per circuit
     pub streams_per_circuit: u32,
     /// Max bytes per second per stream
@@ -24,6 +37,8 @@ impl Default for RateLimiterConfig {
     fn default() -> Self {
         Self {
             circuits_per_minute: 10,
+            key_burst: 4,
+            key_circuits_per_minute: 3,
             streams_per_circuit: 50,
             bytes_per_second: 1_000_000, // 1 MB/s
             window_ms: 60_000,           // 1 minute window
@@ -31,19 +46,21 @@ impl Default for RateLimiterConfig {
     }
 }
 
-/// Timestamp in milliseconds (WASM-compatible)
-fn now_ms() -> u64 {
-    #[cfg(target_arch = "wasm32")]
-    {
-        js_sys::Date::now() as u64
-    }
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64
+/// Circuit budget of one isolation key
+#[derive(Debug, Clone, Copy)]
+struct KeyBucket {
+    /// Circuits left, fractional while refilling
+    tokens: f64,
+    /// When `tokens` was last brought up to date
+    updated_ms: u64,
+}
+
+impl KeyBucket {
+    /// Tokens at `now` after refilling since the last update
+    fn tokens_at(&self, now: u64, config: &RateLimiterConfig) -> f64 {
+        let elapsed = now.saturating_sub(self.updated_ms) as f64;
+        let refill = elapsed * config.key_circuits_per_minute as f64 / 60_000.0;
+        (self.tokens + refill).min(config.key_burst as f64)
     }
 }
 
@@ -53,10 +70,18 @@ pub struct RateLimiter {
     config: RateLimiterConfig,
     /// Timestamps of recent circuit creations
     circuit_timestamps: VecDeque<u64>,
+    /// Budgets of isolation keys that spent part of their burst
+    key_buckets: HashMap<String, KeyBucket>,
     /// Stream count per circuit (circuit_id -> count)
     stream_counts: std::collections::HashMap<u32, u32>,
     /// Bytes sent per stream in current window (stream_id -> (bytes, window_start))
     bandwidth_tracking: std::collections::HashMap<u16, (u64, u64)>,
+    /// Client-wide byte rate while a bandwidth quota throttles
+    throttle_bytes_per_second: Option<u64>,
+    /// When the bytes paced so far will have drained at that rate
+    throttled_until_ms: u64,
+    /// Time source for windows (mockable in tests)
+    clock: SharedClock,
 }
 
 impl RateLimiter {
@@ -67,15 +92,37 @@ impl RateLimiter {
 
     /// Create a new rate limiter with custom config
     pub fn with_config(config: RateLimiterConfig) -> Self {
+        Self::with_clock(config, system_clock())
+    }
+
+    /// Create a rate limiter that reads time from `clock`
+    pub fn with_clock(config: RateLimiterConfig, clock: SharedClock) -> Self {
         Self {
             config,
             circuit_timestamps: VecDeque::new(),
+            key_buckets: HashMap::new(),
             stream_counts: std::collections::HashMap::new(),
             bandwidth_tracking: std::collections::HashMap::new(),
+            throttle_bytes_per_second: None,
+            throttled_until_ms: 0,
+            clock,
         }
     }
 
-    /// Check if a new circuit can be created
+    /// Current limits
+    pub fn config(&self) -> &RateLimiterConfig {
+        &self.config
+    }
+
+    /// Use new limits; counts already recorded are kept
+    pub fn set_config(&mut self, config: RateLimiterConfig) {
+        self.config = config;
+    }
+
+    /// Check if a new circuit can be created under the global ceiling
+    ///
+    /// For circuits that serve no isolation key (explicit builds); request
+    /// circuits go through [`RateLimiter::can_create_circuit_for`].
     pub fn can_create_circuit(&mut self) -> bool {
         self.cleanup_old_entries();
 
@@ -91,14 +138,56 @@ impl RateLimiter {
         true
     }
 
+    /// Check if a new circuit can be created for isolation `key`: it must
+    /// have a token left and the global ceiling must allow it
+    pub fn can_create_circuit_for(&mut self, key: &str) -> bool {
+        if !self.can_create_circuit() {
+            return false;
+        }
+        let remaining = self.key_remaining(key, self.clock.now_ms());
+        if remaining < 1.0 {
+            log::warn!(
+                "🚫 Rate limit: circuit budget for '{}' spent (burst {}, {}/min)",
+                key,
+                self.config.key_burst,
+                self.config.key_circuits_per_minute
+            );
+            return false;
+        }
+        true
+    }
+
     /// Record a circuit creation
     pub fn record_circuit_created(&mut self, circuit_id: u32) {
-        let now = now_ms();
+        let now = self.clock.now_ms();
         self.circuit_timestamps.push_back(now);
         self.stream_counts.insert(circuit_id, 0);
         log::debug!("📊 Rate limiter: recorded circuit {}", circuit_id);
     }
 
+    /// Record a circuit creation for isolation `key`, spending one of its
+    /// tokens
+    pub fn record_circuit_created_for(&mut self, key: &str, circuit_id: u32) {
+        let now = self.clock.now_ms();
+        let tokens = self.key_remaining(key, now);
+        self.key_buckets.insert(
+            key.to_string(),
+            KeyBucket {
+                tokens: (tokens - 1.0).max(0.0),
+                updated_ms: now,
+            },
+        );
+        self.record_circuit_created(circuit_id);
+    }
+
+    /// Tokens `key` has at `now` (a full burst if it has no bucket)
+    fn key_remaining(&self, key: &str, now: u64) -> f64 {
+        self.key_buckets
+            .get(key)
+            .map(|bucket| bucket.tokens_at(now, &self.config))
+            .unwrap_or(self.config.key_burst as f64)
+    }
+
     /// Check if a new stream can be opened on the circuit
     pub fn can_open_stream(&self, circuit_id: u32) -> bool {
         let count = self.stream_counts.get(&circuit_id).copied().unwrap_or(0);
@@ -117,7 +206,8 @@ impl RateLimiter {
     /// Record a stream opening
     pub fn record_stream_opened(&mut self, circuit_id: u32, stream_id: u16) {
         *self.stream_counts.entry(circuit_id).or_insert(0) += 1;
-        self.bandwidth_tracking.insert(stream_id, (0, now_ms()));
+        self.bandwidth_tracking
+            .insert(stream_id, (0, self.clock.now_ms()));
         log::debug!(
             "📊 Rate limiter: recorded stream {} on circuit {}",
             stream_id,
@@ -127,7 +217,7 @@ impl RateLimiter {
 
     /// Check if bandwidth limit allows sending data
     pub fn can_send_bytes(&mut self, stream_id: u16, bytes: u64) -> bool {
-        let now = now_ms();
+        let now = self.clock.now_ms();
 
         let (current_bytes, window_start) = self
             .bandwidth_tracking
@@ -158,7 +248,7 @@ impl RateLimiter {
 
     /// Record bytes sent
     pub fn record_bytes_sent(&mut self, stream_id: u16, bytes: u64) {
-        let now = now_ms();
+        let now = self.clock.now_ms();
 
         let entry = self.bandwidth_tracking.entry(stream_id).or_insert((0, now));
 
@@ -170,6 +260,36 @@ impl RateLimiter {
         }
     }
 
+    /// Pace all requests to `bytes_per_second`, or stop pacing with `None`
+    pub fn set_throttle(&mut self, bytes_per_second: Option<u64>) {
+        if bytes_per_second.is_none() {
+            self.throttled_until_ms = 0;
+        }
+        self.throttle_bytes_per_second = bytes_per_second.filter(|&rate| rate > 0);
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.throttle_bytes_per_second.is_some()
+    }
+
+    /// How long the next request must wait for earlier bytes to drain at
+    /// the throttled rate (0 when not throttled)
+    pub fn throttle_delay_ms(&self) -> u64 {
+        if !self.is_throttled() {
+            return 0;
+        }
+        self.throttled_until_ms.saturating_sub(self.clock.now_ms())
+    }
+
+    /// Count `bytes` a request transferred against the throttled rate
+    pub fn record_throttled_bytes(&mut self, bytes: u64) {
+        let Some(rate) = self.throttle_bytes_per_second else {
+            return;
+        };
+        let start = self.throttled_until_ms.max(self.clock.now_ms());
+        self.throttled_until_ms = start + bytes.saturating_mul(1000) / rate;
+    }
+
     /// Record a stream closed
     pub fn record_stream_closed(&mut self, circuit_id: u32, stream_id: u16) {
         if let Some(count) = self.stream_counts.get_mut(&circuit_id) {
@@ -185,7 +305,7 @@ impl RateLimiter {
 
     /// Clean up old entries outside the time window
     fn cleanup_old_entries(&mut self) {
-        let now = now_ms();
+        let now = self.clock.now_ms();
         let cutoff = now.saturating_sub(self.config.window_ms);
 
         // Remove circuit timestamps older than window
@@ -196,13 +316,36 @@ impl RateLimiter {
                 break;
             }
         }
+
+        // A refilled bucket is the same as none
+        let config = &self.config;
+        self.key_buckets
+            .retain(|_, bucket| bucket.tokens_at(now, config) < config.key_burst as f64);
     }
 
     /// Get current rate limiting stats
     pub fn get_stats(&self) -> RateLimiterStats {
+        let now = self.clock.now_ms();
+        let cutoff = now.saturating_sub(self.config.window_ms);
+        let circuits_in_window = self
+            .circuit_timestamps
+            .iter()
+            .filter(|&&t| t >= cutoff)
+            .count() as u32;
         RateLimiterStats {
-            circuits_in_window: self.circuit_timestamps.len() as u32,
+            circuits_in_window,
             max_circuits_per_minute: self.config.circuits_per_minute,
+            remaining_circuits: self
+                .config
+                .circuits_per_minute
+                .saturating_sub(circuits_in_window),
+            key_burst: self.config.key_burst,
+            remaining_by_key: self
+                .key_buckets
+                .iter()
+                .map(|(key, bucket)| (key.clone(), bucket.tokens_at(now, &self.config) as u32))
+                .filter(|&(_, remaining)| remaining < self.config.key_burst)
+                .collect(),
             active_circuits: self.stream_counts.len() as u32,
             max_streams_per_circuit: self.config.streams_per_circuit,
         }
@@ -216,10 +359,17 @@ impl Default for RateLimiter {
 }
 
 /// Statistics about rate limiting
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RateLimiterStats {
     pub circuits_in_window: u32,
     pub max_circuits_per_minute: u32,
+    /// Circuits the global ceiling still allows in this window
+    pub remaining_circuits: u32,
+    /// Whole circuits an isolation key may create back to back
+    pub key_burst: u32,
+    /// Whole circuits left for isolation keys below `key_burst`; keys not
+    /// listed have the full burst
+    pub remaining_by_key: HashMap<String, u32>,
     pub active_circuits: u32,
     pub max_streams_per_circuit: u32,
 }
@@ -289,4 +439,101 @@ mod tests {
         // Exceeding should be blocked
         assert!(!limiter.can_send_bytes(1, 200));
     }
+
+    #[test]
+    fn test_circuit_window_slides_with_clock() {
+        use crate::runtime::timer::MockClock;
+        use std::rc::Rc;
+
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_clock(
+            RateLimiterConfig {
+                circuits_per_minute: 1,
+                ..Default::default()
+            },
+            Rc::new(clock.clone()),
+        );
+
+        limiter.record_circuit_created(1);
+        assert!(!limiter.can_create_circuit());
+
+        // Once the minute window has passed the old circuit no longer counts
+        clock.advance(60_001);
+        assert!(limiter.can_create_circuit());
+    }
+
+    #[test]
+    fn test_keys_have_separate_budgets() {
+        use crate::runtime::timer::MockClock;
+        use std::rc::Rc;
+
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_clock(
+            RateLimiterConfig {
+                circuits_per_minute: 10,
+                key_burst: 2,
+                key_circuits_per_minute: 2,
+                ..Default::default()
+            },
+            Rc::new(clock.clone()),
+        );
+
+        // A busy site spends its burst without touching anyone else's
+        for id in 1..=2 {
+            assert!(limiter.can_create_circuit_for("busy.example"));
+            limiter.record_circuit_created_for("busy.example", id);
+        }
+        assert!(!limiter.can_create_circuit_for("busy.example"));
+        assert!(limiter.can_create_circuit_for("quiet.example"));
+
+        let stats = limiter.get_stats();
+        assert_eq!(stats.remaining_circuits, 8);
+        assert_eq!(stats.remaining_by_key.get("busy.example"), Some(&0));
+        assert!(!stats.remaining_by_key.contains_key("quiet.example"));
+
+        // Two per minute: one token back after 30s
+        clock.advance(30_000);
+        assert!(limiter.can_create_circuit_for("busy.example"));
+        limiter.record_circuit_created_for("busy.example", 3);
+        assert!(!limiter.can_create_circuit_for("busy.example"));
+    }
+
+    #[test]
+    fn test_global_ceiling_caps_all_keys() {
+        let mut limiter = RateLimiter::with_config(RateLimiterConfig {
+            circuits_per_minute: 3,
+            key_burst: 2,
+            ..Default::default()
+        });
+
+        for (id, key) in ["a", "b", "c"].into_iter().enumerate() {
+            assert!(limiter.can_create_circuit_for(key));
+            limiter.record_circuit_created_for(key, id as u32);
+        }
+        // "d" has its whole burst, but the window is full
+        assert!(!limiter.can_create_circuit_for("d"));
+        assert_eq!(limiter.get_stats().remaining_circuits, 0);
+    }
+
+    #[test]
+    fn test_throttle_paces_bytes() {
+        use crate::runtime::timer::MockClock;
+        use std::rc::Rc;
+
+        let clock = MockClock::new(1_000);
+        let mut limiter =
+            RateLimiter::with_clock(RateLimiterConfig::default(), Rc::new(clock.clone()));
+        limiter.record_throttled_bytes(10_000);
+        assert_eq!(limiter.throttle_delay_ms(), 0);
+
+        limiter.set_throttle(Some(1_000));
+        limiter.record_throttled_bytes(2_000);
+        limiter.record_throttled_bytes(1_000);
+        assert_eq!(limiter.throttle_delay_ms(), 3_000);
+        clock.advance(2_500);
+        assert_eq!(limiter.throttle_delay_ms(), 500);
+
+        limiter.set_throttle(None);
+        assert_eq!(limiter.throttle_delay_ms(), 0);
+    }
 }
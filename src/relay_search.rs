@@ -0,0 +1,241 @@
+//! Relay search over the current consensus
+//!
+//! Backs `TorClient::search_relays`, which embedding apps use to build relay
+//! pickers, network maps and "choose my exit country" UIs. Results are
+//! sorted by bandwidth (highest first) and paginated.
+//!
+//! Country codes come from the directory source; relays from a raw consensus
+//! have none and never match a `country` filter.
+
+use crate::error::{Result, TorError};
+use crate::protocol::{Relay, RelayFlags};
+use serde::{Deserialize, Serialize};
+
+/// Default number of relays per page
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Upper bound on page size, so one call can't serialize the whole consensus
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Search filters. All fields are optional; unset filters match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RelaySearchQuery {
+    /// Flags every result must have (consensus names, case-insensitive:
+    /// `"Exit"`, `"guard"`, `"HSDir"`, ...)
+    pub flags: Vec<String>,
+
+    /// Two-letter country code (case-insensitive)
+    pub country: Option<String>,
+
+    /// Case-insensitive nickname substring
+    pub nickname: Option<String>,
+
+    /// Minimum advertised bandwidth in bytes/sec
+    pub min_bandwidth: Option<u64>,
+
+    /// Zero-based page index
+    pub page: usize,
+
+    /// Results per page (default 50, capped at 500)
+    pub page_size: Option<usize>,
+}
+
+/// What a UI needs to show about one relay
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RelaySummary {
+    pub nickname: String,
+    pub fingerprint: String,
+    pub address: String,
+    pub or_port: u16,
+    pub bandwidth: u64,
+    pub country: Option<String>,
+    pub flags: Vec<&'static str>,
+}
+
+impl From<&Relay> for RelaySummary {
+    fn from(relay: &Relay) -> Self {
+        Self {
+            nickname: relay.nickname.clone(),
+            fingerprint: relay.fingerprint.clone(),
+            address: relay.address.to_string(),
+            or_port: relay.or_port,
+            bandwidth: relay.bandwidth,
+            country: relay.country.clone(),
+            flags: relay.flags.names(),
+        }
+    }
+}
+
+/// One page of search results
+#[derive(Debug, Clone, Serialize)]
+pub struct RelaySearchPage {
+    /// Total matches across all pages
+    pub total: usize,
+    pub page: usize,
+    pub page_size: usize,
+    pub relays: Vec<RelaySummary>,
+}
+
+impl RelaySearchQuery {
+    /// Check that every requested flag name is known
+    fn validate(&self) -> Result<()> {
+        let probe = RelayFlags::default();
+        for flag in &self.flags {
+            if probe.get(flag).is_none() {
+                return Err(TorError::ParseError(format!(
+                    "Unknown relay flag: {}",
+                    flag
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a relay passes every filter
+    pub fn matches(&self, relay: &Relay) -> bool {
+        if !self
+            .flags
+            .iter()
+            .all(|f| relay.flags.get(f).unwrap_or(false))
+        {
+            return false;
+        }
+        if let Some(ref country) = self.country {
+            match relay.country {
+                Some(ref c) if c.eq_ignore_ascii_case(country) => {}
+                _ => return false,
+            }
+        }
+        if let Some(ref needle) = self.nickname {
+            if !relay
+                .nickname
+                .to_lowercase()
+                .contains(&needle.to_lowercase())
+            {
+                return false;
+            }
+        }
+        if let Some(min) = self.min_bandwidth {
+            if relay.bandwidth < min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Filter, sort and paginate relays
+pub fn search_relays(relays: &[Relay], query: &RelaySearchQuery) -> Result<RelaySearchPage> {
+    query.validate()?;
+
+    let page_size = query
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let mut matches: Vec<&Relay> = relays.iter().filter(|r| query.matches(r)).collect();
+    // Fingerprint as tie-breaker keeps pages stable between calls
+    matches.sort_by(|a, b| {
+        b.bandwidth
+            .cmp(&a.bandwidth)
+            .then_with(|| a.fingerprint.cmp(&b.fingerprint))
+    });
+
+    let total = matches.len();
+    let relays = matches
+        .into_iter()
+        .skip(query.page.saturating_mul(page_size))
+        .take(page_size)
+        .map(RelaySummary::from)
+        .collect();
+
+    Ok(RelaySearchPage {
+        total,
+        page: query.page,
+        page_size,
+        relays,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::TestRelay;
+
+    fn relay(nickname: &str, bandwidth: u64, country: Option<&str>, exit: bool) -> Relay {
+        TestRelay::new(nickname)
+            .flags(RelayFlags {
+                exit,
+                ..RelayFlags::from_string("Fast Running Valid")
+            })
+            .bandwidth(bandwidth)
+            .ntor_onion_key(None)
+            .country(country)
+            .build()
+    }
+
+    fn sample() -> Vec<Relay> {
+        vec![
+            relay("alpha", 100, Some("de"), true),
+            relay("bravo", 300, Some("de"), false),
+            relay("charlie", 200, Some("us"), true),
+            relay("delta", 400, None, true),
+        ]
+    }
+
+    #[test]
+    fn test_filters_combine() {
+        let query = RelaySearchQuery {
+            flags: vec!["exit".to_string()],
+            country: Some("DE".to_string()),
+            ..Default::default()
+        };
+        let page = search_relays(&sample(), &query).unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.relays[0].nickname, "alpha");
+        assert!(page.relays[0].flags.contains(&"Exit"));
+
+        let query = RelaySearchQuery {
+            nickname: Some("AR".to_string()),
+            min_bandwidth: Some(150),
+            ..Default::default()
+        };
+        let page = search_relays(&sample(), &query).unwrap();
+        let names: Vec<_> = page.relays.iter().map(|r| r.nickname.as_str()).collect();
+        assert_eq!(names, vec!["charlie"]);
+    }
+
+    #[test]
+    fn test_pagination_sorted_by_bandwidth() {
+        let query = RelaySearchQuery {
+            page: 1,
+            page_size: Some(3),
+            ..Default::default()
+        };
+        let page = search_relays(&sample(), &query).unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(page.relays.len(), 1);
+        assert_eq!(page.relays[0].nickname, "alpha");
+
+        let first = search_relays(
+            &sample(),
+            &RelaySearchQuery {
+                page_size: Some(3),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let names: Vec<_> = first.relays.iter().map(|r| r.nickname.as_str()).collect();
+        assert_eq!(names, vec!["delta", "bravo", "charlie"]);
+    }
+
+    #[test]
+    fn test_unknown_flag_is_rejected() {
+        let query = RelaySearchQuery {
+            flags: vec!["Speedy".to_string()],
+            ..Default::default()
+        };
+        assert!(search_relays(&sample(), &query).is_err());
+    }
+}
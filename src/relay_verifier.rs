@@ -3,6 +3,7 @@
 //! Implements additional relay verification beyond basic certificate checking:
 //! - Relay family constraints (no two relays from same family in circuit)
 //! - Bandwidth observation tracking
+//! - Exit misbehavior scoring and session bans
 //! - Path validation
 //!
 //! ## Security Rationale
@@ -13,6 +14,14 @@
 //!
 //! **Bandwidth Tracking**: Relays that consistently under-perform their claimed
 //! bandwidth may be malicious (attracting traffic then degrading it).
+//!
+//! **Exit Misbehavior**: Exits see destination traffic, and plain HTTP in the
+//! clear. Each anomaly an exit is caught in (a certificate for a site that no
+//! other exit shows, a body failing its integrity check, streams reset
+//! mid-response) adds to its score; at [`EXIT_BAN_SCORE`] the exit is banned
+//! from selection for the rest of the session. None of these is proof on its
+//! own (sites rotate certificates, resources change, connections drop), so a
+//! single weak signal never bans.
 
 use crate::protocol::Relay;
 use serde::{Deserialize, Serialize};
@@ -60,6 +69,66 @@ impl std::fmt::Display for VerifyError {
 
 impl std::error::Error for VerifyError {}
 
+/// Anomaly score at which an exit is banned for the session
+pub const EXIT_BAN_SCORE: u32 = 3;
+
+/// RELAY_END reasons that, arriving mid-response, suggest the exit cut the
+/// stream itself: MISC, CONNRESET, TORPROTOCOL
+const RESET_END_REASONS: [u8; 3] = [1, 12, 13];
+
+/// Distinct exits that must agree on a site's certificate before a different
+/// one is treated as an anomaly
+const CERT_CONSENSUS_EXITS: usize = 2;
+
+/// Kinds of exit misbehavior
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitAnomaly {
+    /// TLS certificate differs from the one other exits showed for the site
+    CertificateMismatch,
+    /// Plain-HTTP body failed its integrity check
+    InjectedContent,
+    /// Stream ended with a reset-like reason before the response completed
+    UnexpectedReset,
+    /// Exit resolved a public hostname to a loopback or private address
+    InternalAddress,
+}
+
+impl ExitAnomaly {
+    /// Contribution to the exit's score
+    pub fn weight(&self) -> u32 {
+        match self {
+            ExitAnomaly::CertificateMismatch => 2,
+            ExitAnomaly::InjectedContent => 2,
+            ExitAnomaly::UnexpectedReset => 1,
+            ExitAnomaly::InternalAddress => 2,
+        }
+    }
+
+    /// Whether a RELAY_END with `reason` before the response completed
+    /// counts as an unexpected reset
+    pub fn is_reset_reason(reason: u8) -> bool {
+        RESET_END_REASONS.contains(&reason)
+    }
+}
+
+/// One recorded anomaly
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnomalyRecord {
+    pub kind: ExitAnomaly,
+    pub detail: String,
+    pub observed_at: u64,
+}
+
+/// An exit banned for the session, as reported to callers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BannedExit {
+    pub fingerprint: String,
+    pub score: u32,
+    pub banned_at: u64,
+    pub anomalies: Vec<AnomalyRecord>,
+}
+
 /// Bandwidth observation for a relay
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BandwidthObservation {
@@ -120,6 +189,15 @@ pub struct RelayVerifier {
     /// Deny list: fingerprints that should never be used
     deny_list: HashMap<String, String>, // fingerprint -> reason
 
+    /// Exit anomalies: fingerprint -> records, oldest first
+    anomalies: HashMap<String, Vec<AnomalyRecord>>,
+
+    /// Exits banned for the session: fingerprint -> ban time
+    banned: HashMap<String, u64>,
+
+    /// TLS certificates seen per site: host -> certificate digest -> exits
+    certificates: HashMap<String, HashMap<String, HashSet<String>>>,
+
     /// Whether family checking is enabled
     family_check_enabled: bool,
 
@@ -140,6 +218,9 @@ impl RelayVerifier {
             families: HashMap::new(),
             bandwidth_observations: HashMap::new(),
             deny_list: HashMap::new(),
+            anomalies: HashMap::new(),
+            banned: HashMap::new(),
+            certificates: HashMap::new(),
             family_check_enabled: true,
             bandwidth_check_enabled: false, // Off by default, needs more testing
         }
@@ -207,6 +288,114 @@ impl RelayVerifier {
         self.deny_list.get(fingerprint)
     }
 
+    /// Record an anomaly for an exit; returns true if it got the exit banned
+    pub fn record_anomaly(&mut self, fingerprint: &str, kind: ExitAnomaly, detail: &str) -> bool {
+        log::warn!(
+            "🕵️ Exit {} anomaly {:?}: {}",
+            &fingerprint[..8.min(fingerprint.len())],
+            kind,
+            detail
+        );
+        self.anomalies
+            .entry(fingerprint.to_string())
+            .or_default()
+            .push(AnomalyRecord {
+                kind,
+                detail: detail.to_string(),
+                observed_at: current_time_secs(),
+            });
+
+        if self.banned.contains_key(fingerprint) || self.score(fingerprint) < EXIT_BAN_SCORE {
+            return false;
+        }
+        log::warn!(
+            "🚫 Exit {} banned for this session (score {})",
+            &fingerprint[..8.min(fingerprint.len())],
+            self.score(fingerprint)
+        );
+        self.banned
+            .insert(fingerprint.to_string(), current_time_secs());
+        true
+    }
+
+    /// Anomalies recorded for an exit
+    pub fn anomalies(&self, fingerprint: &str) -> &[AnomalyRecord] {
+        self.anomalies
+            .get(fingerprint)
+            .map_or(&[], |records| records.as_slice())
+    }
+
+    /// Sum of the exit's anomaly weights
+    pub fn score(&self, fingerprint: &str) -> u32 {
+        self.anomalies(fingerprint)
+            .iter()
+            .map(|a| a.kind.weight())
+            .sum()
+    }
+
+    /// Record the certificate an exit showed for `host`; flags the exit if
+    /// other exits agree on a different one and none has shown this one
+    ///
+    /// Returns true if this got the exit banned.
+    pub fn observe_certificate(&mut self, host: &str, digest: &str, exit: &str) -> bool {
+        let seen = self.certificates.entry(host.to_string()).or_default();
+        let known = seen.contains_key(digest);
+        let vouched = seen
+            .iter()
+            .filter(|(other, _)| other.as_str() != digest)
+            .any(|(_, exits)| {
+                exits.iter().filter(|e| e.as_str() != exit).count() >= CERT_CONSENSUS_EXITS
+            });
+        seen.entry(digest.to_string())
+            .or_default()
+            .insert(exit.to_string());
+
+        if known || !vouched {
+            return false;
+        }
+        self.record_anomaly(
+            exit,
+            ExitAnomaly::CertificateMismatch,
+            &format!("certificate for {} not seen through other exits", host),
+        )
+    }
+
+    /// Whether an exit is banned for the session
+    pub fn is_banned(&self, fingerprint: &str) -> bool {
+        self.banned.contains_key(fingerprint)
+    }
+
+    /// Fingerprints of banned exits
+    pub fn banned_fingerprints(&self) -> Vec<String> {
+        self.banned.keys().cloned().collect()
+    }
+
+    /// Banned exits with the anomalies behind each ban, oldest ban first
+    pub fn banned_exits(&self) -> Vec<BannedExit> {
+        let mut banned: Vec<BannedExit> = self
+            .banned
+            .iter()
+            .map(|(fingerprint, banned_at)| BannedExit {
+                fingerprint: fingerprint.clone(),
+                score: self.score(fingerprint),
+                banned_at: *banned_at,
+                anomalies: self.anomalies(fingerprint).to_vec(),
+            })
+            .collect();
+        banned.sort_by(|a, b| {
+            a.banned_at
+                .cmp(&b.banned_at)
+                .then_with(|| a.fingerprint.cmp(&b.fingerprint))
+        });
+        banned
+    }
+
+    /// Lift an exit's ban and forget its anomalies
+    pub fn unban_exit(&mut self, fingerprint: &str) -> bool {
+        self.anomalies.remove(fingerprint);
+        self.banned.remove(fingerprint).is_some()
+    }
+
     /// Check if a circuit path is valid (no family conflicts)
     pub fn validate_path(
         &self,
@@ -327,15 +516,17 @@ impl RelayVerifier {
     pub fn stats(&self) -> RelayVerifierStats {
         let suspicious_count = self
             .bandwidth_observations
-            .values()
-            .filter(|o| o.is_suspicious())
-            .count();
+            .iter()
+            .filter(|(fp, o)| o.is_suspicious() && !self.anomalies.contains_key(*fp))
+            .count()
+            + self.anomalies.len();
 
         RelayVerifierStats {
             families_loaded: self.families.len(),
             bandwidth_observations: self.bandwidth_observations.len(),
             suspicious_relays: suspicious_count,
             deny_listed: self.deny_list.len(),
+            banned_exits: self.banned.len(),
             family_check_enabled: self.family_check_enabled,
             bandwidth_check_enabled: self.bandwidth_check_enabled,
         }
@@ -349,6 +540,7 @@ pub struct RelayVerifierStats {
     pub bandwidth_observations: usize,
     pub suspicious_relays: usize,
     pub deny_listed: usize,
+    pub banned_exits: usize,
     pub family_check_enabled: bool,
     pub bandwidth_check_enabled: bool,
 }
@@ -442,6 +634,65 @@ mod tests {
         assert!(obs.is_suspicious());
     }
 
+    #[test]
+    fn test_anomalies_ban_at_threshold() {
+        let mut verifier = RelayVerifier::new();
+        assert!(ExitAnomaly::is_reset_reason(12));
+        assert!(!ExitAnomaly::is_reset_reason(6)); // DONE
+
+        assert!(!verifier.record_anomaly("EXIT_FP", ExitAnomaly::UnexpectedReset, "reset"));
+        assert!(!verifier.record_anomaly("EXIT_FP", ExitAnomaly::UnexpectedReset, "reset"));
+        assert!(!verifier.is_banned("EXIT_FP"));
+        assert!(verifier.record_anomaly("EXIT_FP", ExitAnomaly::InjectedContent, "sha256"));
+        assert!(verifier.is_banned("EXIT_FP"));
+        // Already banned: not reported again
+        assert!(!verifier.record_anomaly("EXIT_FP", ExitAnomaly::UnexpectedReset, "reset"));
+
+        let banned = verifier.banned_exits();
+        assert_eq!(banned.len(), 1);
+        assert_eq!(banned[0].score, 5);
+        assert_eq!(banned[0].anomalies.len(), 4);
+
+        // Slow and misbehaving is still one suspicious relay
+        for _ in 0..3 {
+            verifier.record_bandwidth("EXIT_FP", 1_000, 1000, 1_000_000);
+            verifier.record_bandwidth("SLOW_FP", 1_000, 1000, 1_000_000);
+        }
+        let stats = verifier.stats();
+        assert_eq!(stats.suspicious_relays, 2);
+        assert_eq!(stats.banned_exits, 1);
+
+        assert!(verifier.unban_exit("EXIT_FP"));
+        assert!(verifier.banned_exits().is_empty());
+        assert_eq!(verifier.score("EXIT_FP"), 0);
+    }
+
+    #[test]
+    fn test_certificate_mismatch_needs_agreeing_exits() {
+        let mut verifier = RelayVerifier::new();
+
+        // One exit's word isn't enough to accuse another
+        verifier.observe_certificate("example.com", "aaaa", "EXIT_A");
+        verifier.observe_certificate("example.com", "bbbb", "EXIT_B");
+        assert_eq!(verifier.score("EXIT_B"), 0);
+
+        // Two exits agree on aaaa; a third showing something new is flagged
+        verifier.observe_certificate("example.com", "aaaa", "EXIT_C");
+        verifier.observe_certificate("example.com", "cccc", "EXIT_D");
+        assert_eq!(verifier.anomalies("EXIT_D").len(), 1);
+        assert_eq!(
+            verifier.anomalies("EXIT_D")[0].kind,
+            ExitAnomaly::CertificateMismatch
+        );
+
+        // Once a certificate is known, later exits showing it are fine
+        verifier.observe_certificate("example.com", "cccc", "EXIT_E");
+        assert_eq!(verifier.score("EXIT_E"), 0);
+        // Other sites are tracked separately
+        verifier.observe_certificate("other.org", "dddd", "EXIT_D");
+        assert_eq!(verifier.score("EXIT_D"), 2);
+    }
+
     #[test]
     fn test_parse_family_string() {
         let family_str = "$ABCD1234567890ABCD1234567890ABCDEF123456 $1234567890ABCD1234567890ABCDEF12345678 nickname";
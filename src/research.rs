@@ -0,0 +1,141 @@
+//! Raw relay cell access (`research` feature)
+//!
+//! Protocol experiments (new relay commands, measurement studies) need to
+//! put arbitrary relay payloads on a circuit and see every cell that comes
+//! back, including ones this crate doesn't parse. This module adds both
+//! without forking the circuit internals:
+//!
+//! - [`Circuit::send_raw_cell`](crate::protocol::Circuit::send_raw_cell)
+//!   sends a plaintext relay payload to any hop, with its digest and onion
+//!   layers applied as for any other cell
+//! - [`set_cell_observer`] sees every relay payload in plaintext: outgoing
+//!   ones once their digest is set, incoming ones once a hop recognizes
+//!   them and before they are parsed
+//!
+//! From JS the same is available as `TorClient.send_raw_cell()` on custom
+//! circuits and `set_relay_cell_observer()`.
+//!
+//! The observer sees decrypted payloads, stream data included. Never
+//! enable this feature in a release build.
+
+use crate::protocol::trace::Direction;
+use crate::TorClient;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+/// A plaintext relay payload crossing the circuit boundary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ObservedCell {
+    pub circuit_id: u32,
+    pub direction: Direction,
+    /// Hop that sent or will process the cell (0 = guard)
+    pub hop: usize,
+    /// The full 509-byte relay payload
+    pub payload: Vec<u8>,
+}
+
+type Observer = Rc<dyn Fn(&ObservedCell)>;
+
+thread_local! {
+    static OBSERVER: RefCell<Option<Observer>> = const { RefCell::new(None) };
+}
+
+/// Register a callback for every relay cell, replacing any earlier one
+pub fn set_cell_observer(observer: impl Fn(&ObservedCell) + 'static) {
+    OBSERVER.with(|o| *o.borrow_mut() = Some(Rc::new(observer)));
+}
+
+/// Stop observing relay cells
+pub fn clear_cell_observer() {
+    OBSERVER.with(|o| *o.borrow_mut() = None);
+}
+
+/// Hand a plaintext relay payload to the observer, if one is registered
+pub(crate) fn observe(circuit_id: u32, direction: Direction, hop: usize, payload: &[u8]) {
+    let Some(observer) = OBSERVER.with(|o| o.borrow().clone()) else {
+        return;
+    };
+    observer(&ObservedCell {
+        circuit_id,
+        direction,
+        hop,
+        payload: payload.to_vec(),
+    });
+}
+
+/// Call `callback` with `{ circuit_id, direction, hop, payload }` for every
+/// relay cell, `payload` being the decrypted 509 bytes as a `Uint8Array`;
+/// pass nothing to stop (builds with the `research` feature only)
+#[wasm_bindgen]
+pub fn set_relay_cell_observer(callback: Option<js_sys::Function>) {
+    let Some(callback) = callback else {
+        clear_cell_observer();
+        return;
+    };
+    set_cell_observer(move |cell| {
+        let value = serde_wasm_bindgen::to_value(&serde_json::json!({
+            "circuit_id": cell.circuit_id,
+            "direction": cell.direction,
+            "hop": cell.hop,
+        }))
+        .unwrap_or(JsValue::NULL);
+        let payload = js_sys::Uint8Array::from(cell.payload.as_slice());
+        let _ = js_sys::Reflect::set(&value, &"payload".into(), &payload);
+        if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+            log::warn!("🔬 relay cell observer threw: {:?}", e);
+        }
+    });
+}
+
+#[wasm_bindgen]
+impl TorClient {
+    /// Send a plaintext relay payload to `hop` (0 = guard) of a circuit
+    /// from `build_custom_circuit()` (builds with the `research` feature
+    /// only)
+    ///
+    /// The payload is padded to 509 bytes and its digest field filled in;
+    /// see `Circuit::send_raw_cell`. Read replies with
+    /// `set_relay_cell_observer()`.
+    #[wasm_bindgen]
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub async fn send_raw_cell(
+        &self,
+        circuit_id: u32,
+        bytes: Vec<u8>,
+        hop: usize,
+    ) -> std::result::Result<(), JsValue> {
+        let circuit = self
+            .custom_circuits
+            .with(|circuits| circuits.get(&circuit_id).cloned())
+            .ok_or_else(|| JsValue::from_str(&format!("No custom circuit {}", circuit_id)))?;
+        let mut circuit = circuit
+            .try_borrow_mut()
+            .map_err(|_| JsValue::from_str(&format!("Circuit {} is busy", circuit_id)))?;
+        circuit.send_raw_cell(&bytes, hop).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observer_sees_payloads_until_cleared() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        set_cell_observer(move |cell| sink.borrow_mut().push(cell.clone()));
+
+        observe(3, Direction::Received, 2, &[0x99, 0, 0]);
+        clear_cell_observer();
+        observe(3, Direction::Sent, 0, &[1]);
+
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].hop, 2);
+        assert_eq!(seen[0].direction, Direction::Received);
+        assert_eq!(seen[0].payload, vec![0x99, 0, 0]);
+    }
+}
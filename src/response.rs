@@ -0,0 +1,201 @@
+//! Decoding fetched responses
+//!
+//! The GET methods hand back the raw response, and turning that into text
+//! with a lossy UTF-8 conversion mangles binary bodies and text in any other
+//! charset. [`HttpResponse`] splits a raw response into status, headers and
+//! transfer-decoded body, and decodes text in the charset its
+//! `Content-Type` names, as `fetch_json()` and `fetch_bytes()` do.
+//!
+//! As in the WHATWG Encoding standard, a byte order mark overrides the
+//! declared charset, a missing one means UTF-8, and the Latin-1 labels mean
+//! windows-1252. UTF-8, UTF-16 and windows-1252 are decoded here; other
+//! charsets go to the browser's `TextDecoder`.
+
+use crate::error::{Result, TorError};
+use crate::integrity::response_body;
+
+/// windows-1252 characters for bytes 0x80..=0x9F (the rest are Latin-1)
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// A raw HTTP/1.1 response, parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Header fields in order, names as sent
+    pub headers: Vec<(String, String)>,
+    /// Body with chunked framing removed
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Parse a complete raw response
+    pub fn parse(raw: &[u8]) -> Result<Self> {
+        let end = raw
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(|| TorError::ProtocolError("Incomplete response header".into()))?;
+        let head = String::from_utf8_lossy(&raw[..end]);
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| TorError::ProtocolError("Malformed status line".into()))?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Ok(Self {
+            status,
+            headers,
+            body: response_body(raw)?,
+        })
+    }
+
+    /// Whether the status is 2xx
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// First value of the header `name` (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Media type from `Content-Type`, lowercased, without parameters
+    pub fn media_type(&self) -> Option<String> {
+        let value = self.header("content-type")?;
+        let media = value.split(';').next()?.trim();
+        (!media.is_empty()).then(|| media.to_ascii_lowercase())
+    }
+
+    /// `charset` parameter of `Content-Type`, lowercased
+    pub fn charset(&self) -> Option<String> {
+        self.header("content-type")?
+            .split(';')
+            .skip(1)
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+            .map(|(_, value)| value.trim().trim_matches('"').to_ascii_lowercase())
+    }
+
+    /// The body as text, in the declared charset (UTF-8 if none)
+    pub fn text(&self) -> Result<String> {
+        decode_text(&self.body, self.charset().as_deref().unwrap_or("utf-8"))
+    }
+}
+
+/// Decode `bytes` in the charset `label`, unless a byte order mark says
+/// otherwise
+///
+/// Malformed input is an error rather than replaced.
+pub fn decode_text(bytes: &[u8], label: &str) -> Result<String> {
+    let (bytes, label) = match bytes {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => (rest, "utf-8"),
+        [0xFF, 0xFE, rest @ ..] => (rest, "utf-16le"),
+        [0xFE, 0xFF, rest @ ..] => (rest, "utf-16be"),
+        _ => (bytes, label),
+    };
+    let malformed = || TorError::ProtocolError(format!("Response body is not valid {}", label));
+    let label = label.trim().to_ascii_lowercase();
+    match label.as_str() {
+        "utf-8" | "utf8" | "unicode-1-1-utf-8" => {
+            String::from_utf8(bytes.to_vec()).map_err(|_| malformed())
+        }
+        "utf-16" | "utf-16le" | "utf-16be" | "unicode" | "unicodefffe" => {
+            if bytes.len() % 2 != 0 {
+                return Err(malformed());
+            }
+            let big_endian = matches!(label.as_str(), "utf-16be" | "unicodefffe");
+            let units = bytes.chunks_exact(2).map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if big_endian {
+                    u16::from_be_bytes(pair)
+                } else {
+                    u16::from_le_bytes(pair)
+                }
+            });
+            char::decode_utf16(units)
+                .collect::<std::result::Result<String, _>>()
+                .map_err(|_| malformed())
+        }
+        "us-ascii" | "ascii" | "iso-8859-1" | "iso8859-1" | "latin1" | "l1" | "windows-1252"
+        | "cp1252" | "x-cp1252" => Ok(bytes
+            .iter()
+            .map(|&b| match b {
+                0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                _ => b as char,
+            })
+            .collect()),
+        other => decode_with_browser(bytes, other),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn decode_with_browser(bytes: &[u8], label: &str) -> Result<String> {
+    let decoder = web_sys::TextDecoder::new_with_label(label)
+        .map_err(|_| TorError::ProtocolError(format!("Unsupported charset {}", label)))?;
+    decoder
+        .decode_with_u8_array(bytes)
+        .map_err(|_| TorError::ProtocolError(format!("Response body is not valid {}", label)))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn decode_with_browser(_bytes: &[u8], label: &str) -> Result<String> {
+    Err(TorError::ProtocolError(format!(
+        "Unsupported charset {}",
+        label
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_decode_charset() {
+        let mut raw = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=\"ISO-8859-1\"\r\nTransfer-Encoding: chunked\r\n\r\n4\r\ncaf\xe9\r\n1\r\n\x80\r\n0\r\n\r\n".to_vec();
+        let response = HttpResponse::parse(&raw).unwrap();
+        assert!(response.is_success());
+        assert_eq!(response.media_type().as_deref(), Some("text/plain"));
+        assert_eq!(response.charset().as_deref(), Some("iso-8859-1"));
+        assert_eq!(response.body, b"caf\xe9\x80");
+        assert_eq!(response.text().unwrap(), "café€");
+
+        // Without a charset the body must be UTF-8
+        raw = b"HTTP/1.1 404 Not Found\r\nContent-Length: 2\r\n\r\n\xff\xfe".to_vec();
+        let response = HttpResponse::parse(&raw).unwrap();
+        assert_eq!(response.status, 404);
+        assert!(!response.is_success());
+        assert!(response.text().is_ok(), "a BOM overrides the default");
+        raw = b"HTTP/1.1 200 OK\r\n\r\n\xe9t\xe9".to_vec();
+        assert!(HttpResponse::parse(&raw).unwrap().text().is_err());
+    }
+
+    #[test]
+    fn test_content_length_bounds_body() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n\x00\x01\x02extra";
+        assert_eq!(HttpResponse::parse(raw).unwrap().body, [0, 1, 2]);
+        let raw = b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nshort";
+        assert!(HttpResponse::parse(raw).is_err());
+        // HEAD responses announce a length but carry no body
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n";
+        assert!(HttpResponse::parse(raw).unwrap().body.is_empty());
+    }
+
+    #[test]
+    fn test_decode_utf16_with_bom() {
+        assert_eq!(decode_text(b"\xfe\xff\x00h\x00i", "utf-8").unwrap(), "hi");
+        assert_eq!(decode_text(b"h\x00i\x00", "UTF-16LE").unwrap(), "hi");
+        assert!(decode_text(b"h\x00i", "utf-16le").is_err());
+        assert!(decode_text(b"abc", "x-unknown").is_err());
+    }
+}
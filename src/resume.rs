@@ -0,0 +1,491 @@
+//! Resuming interrupted downloads
+//!
+//! When a circuit dies partway through a response the server offered as
+//! ranged (`Accept-Ranges: bytes`, with a `Content-Length`), the cooperative
+//! GET path asks for the rest on a new circuit with `Range: bytes=N-` and
+//! splices the 206 body onto what already arrived. Callers see the response
+//! as if it had arrived in one piece: the first response's head and the
+//! whole body.
+//!
+//! `If-Range` carries the first response's strong ETag (or its
+//! Last-Modified date), so if the resource changed the server answers 200
+//! with the new version and the download starts over from that. Without a
+//! validator the `Content-Range` total must still match the original length.
+//! Chunked responses are not resumed.
+//!
+//! The body can also be handed out as it arrives
+//! ([`ResumableDownload::take_body`]) so that only the head stays in
+//! memory, and a download can continue a body an earlier session kept
+//! ([`ResumableDownload::continuing`]).
+
+use crate::error::{Result, TorError};
+
+/// Resume attempts per download
+pub const MAX_RESUMES: u32 = 3;
+
+/// Largest response header accepted
+const MAX_HEAD: usize = 64 * 1024;
+
+/// The parts of a response head that decide whether it can be resumed
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Head {
+    /// Length of the head, including the blank line
+    len: usize,
+    status: u16,
+    content_length: Option<u64>,
+    accept_ranges: bool,
+    chunked: bool,
+    /// `If-Range` value: strong ETag, else Last-Modified
+    validator: Option<String>,
+    /// `Content-Range: bytes start-end/total`, as (start, total)
+    content_range: Option<(u64, Option<u64>)>,
+}
+
+/// Position just past the `\r\n\r\n` ending a head in `bytes`
+fn head_end(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|p| p + 4)
+}
+
+fn parse_head(bytes: &[u8]) -> Result<Head> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut lines = text.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split(' ').nth(1))
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| TorError::ProtocolError("Bad HTTP status line".into()))?;
+
+    let mut head = Head {
+        len: bytes.len(),
+        status,
+        ..Head::default()
+    };
+    let mut etag = None;
+    let mut last_modified = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim().to_ascii_lowercase(), value.trim());
+        match name.as_str() {
+            "content-length" => head.content_length = value.parse().ok(),
+            "accept-ranges" => head.accept_ranges = value.eq_ignore_ascii_case("bytes"),
+            "transfer-encoding" => head.chunked = value.to_ascii_lowercase().contains("chunked"),
+            // Weak ETags can't be used with If-Range
+            "etag" if !value.starts_with("W/") => etag = Some(value.to_string()),
+            "last-modified" => last_modified = Some(value.to_string()),
+            "content-range" => head.content_range = parse_content_range(value),
+            _ => {}
+        }
+    }
+    head.validator = etag.or(last_modified);
+    Ok(head)
+}
+
+/// `bytes 100-199/200` → (100, Some(200)); `bytes 100-199/*` → (100, None)
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-')?.0.trim().parse().ok()?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start, total))
+}
+
+/// One download, possibly spread over several responses
+#[derive(Debug, Default)]
+pub struct ResumableDownload {
+    /// Head and body received so far
+    response: Vec<u8>,
+    /// Parsed head of `response`, once complete
+    head: Option<Head>,
+    /// Raw head of a continuation response while it arrives
+    continuation: Option<Vec<u8>>,
+    resumes: u32,
+    /// Body bytes already handed out (or kept elsewhere before we started)
+    taken: u64,
+    /// `If-Range` value for continuing an earlier session's body
+    prior_validator: Option<String>,
+    /// The server restarted the body; what was taken must be discarded
+    restarted: bool,
+}
+
+impl ResumableDownload {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A download continuing after the first `offset` body bytes,
+    /// kept by an earlier session
+    ///
+    /// `validator` is the ETag or Last-Modified date the earlier response
+    /// carried; without one the server is trusted to send the same resource.
+    pub fn continuing(offset: u64, validator: Option<String>) -> Self {
+        Self {
+            taken: offset,
+            prior_validator: validator,
+            continuation: (offset > 0).then(Vec::new),
+            ..Self::default()
+        }
+    }
+
+    /// Feed bytes of the current response
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<()> {
+        if let Some(mut pending) = self.continuation.take() {
+            pending.extend_from_slice(bytes);
+            let Some(end) = head_end(&pending) else {
+                if pending.len() > MAX_HEAD {
+                    return Err(TorError::ProtocolError("Response header too large".into()));
+                }
+                self.continuation = Some(pending);
+                return Ok(());
+            };
+            let body = pending.split_off(end);
+            self.splice(parse_head(&pending)?, pending)?;
+            return self.append_body(&body);
+        }
+
+        if self.head.is_some() {
+            return self.append_body(bytes);
+        }
+        self.response.extend_from_slice(bytes);
+        if let Some(end) = head_end(&self.response) {
+            let body = self.response.split_off(end);
+            self.head = Some(parse_head(&self.response)?);
+            return self.append_body(&body);
+        } else if self.response.len() > MAX_HEAD {
+            return Err(TorError::ProtocolError("Response header too large".into()));
+        }
+        Ok(())
+    }
+
+    /// Take over a continuation response once its head has arrived
+    fn splice(&mut self, head: Head, raw_head: Vec<u8>) -> Result<()> {
+        let received = self.body_len();
+        if self.head.is_none() {
+            return self.splice_first(head, raw_head, received);
+        }
+        let expected = self.head.as_ref().and_then(|h| h.content_length);
+        match (head.status, head.content_range) {
+            (206, Some((start, total)))
+                if start == received && (total.is_none() || total == expected) =>
+            {
+                log::info!("♻️ Resuming download at byte {}", received);
+                Ok(())
+            }
+            (206, _) => Err(TorError::ProtocolError(format!(
+                "Resumed response does not continue at byte {}",
+                received
+            ))),
+            // Range ignored or the resource changed: start over from this one
+            (200, _) => {
+                log::warn!("♻️ Server sent the full response again, restarting download");
+                self.restart(head, raw_head);
+                Ok(())
+            }
+            (status, _) => Err(TorError::ProtocolError(format!(
+                "Resume request failed with HTTP {}",
+                status
+            ))),
+        }
+    }
+
+    /// Take over the first response of a download continuing an earlier
+    /// session's body
+    ///
+    /// A 206 at the right offset is treated as the rest of a 200 whose
+    /// length is the `Content-Range` total, so it can be resumed again.
+    fn splice_first(&mut self, head: Head, raw_head: Vec<u8>, received: u64) -> Result<()> {
+        match (head.status, head.content_range) {
+            (206, Some((start, total))) if start == received => {
+                log::info!("♻️ Continuing download at byte {}", received);
+                self.response = raw_head;
+                self.head = Some(Head {
+                    status: 200,
+                    content_length: total,
+                    accept_ranges: true,
+                    content_range: None,
+                    validator: head.validator.or(self.prior_validator.take()),
+                    ..head
+                });
+                Ok(())
+            }
+            (206, _) => Err(TorError::ProtocolError(format!(
+                "Resumed response does not continue at byte {}",
+                received
+            ))),
+            (200, _) => {
+                log::warn!("♻️ Server sent the full resource, restarting download");
+                self.restart(head, raw_head);
+                Ok(())
+            }
+            (status, _) => Err(TorError::ProtocolError(format!(
+                "Resume request failed with HTTP {}",
+                status
+            ))),
+        }
+    }
+
+    /// Start the body over with a full response
+    fn restart(&mut self, head: Head, raw_head: Vec<u8>) {
+        self.restarted = self.taken > 0;
+        self.taken = 0;
+        self.response = raw_head;
+        self.head = Some(head);
+    }
+
+    /// Append body bytes, ignoring any past the declared length
+    fn append_body(&mut self, bytes: &[u8]) -> Result<()> {
+        let take = match self.head.as_ref().and_then(|h| h.content_length) {
+            Some(length) => (length.saturating_sub(self.body_len()) as usize).min(bytes.len()),
+            None => bytes.len(),
+        };
+        crate::memory::check(crate::memory::Subsystem::Buffers, take)?;
+        self.response.extend_from_slice(&bytes[..take]);
+        Ok(())
+    }
+
+    /// Body bytes received so far
+    pub fn body_len(&self) -> u64 {
+        self.taken
+            + self
+                .head
+                .as_ref()
+                .map_or(0, |h| (self.response.len() - h.len) as u64)
+    }
+
+    /// Body bytes received since the last call, which the download no
+    /// longer keeps
+    pub fn take_body(&mut self) -> Vec<u8> {
+        let Some(head) = &self.head else {
+            return Vec::new();
+        };
+        let body = self.response.split_off(head.len);
+        self.taken += body.len() as u64;
+        body
+    }
+
+    /// Whether the server restarted the body since the last call, so that
+    /// everything taken so far must be discarded
+    pub fn take_restarted(&mut self) -> bool {
+        std::mem::take(&mut self.restarted)
+    }
+
+    /// Status of the response, once its head has arrived
+    pub fn status(&self) -> Option<u16> {
+        self.head.as_ref().map(|h| h.status)
+    }
+
+    /// Declared length of the whole body, if known
+    pub fn content_length(&self) -> Option<u64> {
+        self.head.as_ref().and_then(|h| h.content_length)
+    }
+
+    /// Whether the body uses chunked transfer encoding
+    pub fn is_chunked(&self) -> bool {
+        self.head.as_ref().is_some_and(|h| h.chunked)
+    }
+
+    /// `If-Range` value to continue this download in a later session
+    pub fn validator(&self) -> Option<&str> {
+        self.head.as_ref().and_then(|h| h.validator.as_deref())
+    }
+
+    /// Whether the whole declared body has arrived
+    pub fn is_complete(&self) -> bool {
+        self.continuation.is_none()
+            && self
+                .head
+                .as_ref()
+                .and_then(|h| h.content_length)
+                .is_some_and(|length| self.body_len() >= length)
+    }
+
+    /// Headers asking for the rest of the body, if the download is
+    /// incomplete and the server said it can be resumed
+    pub fn resume_headers(&self) -> Option<String> {
+        let Some(head) = self.head.as_ref() else {
+            // Continuing an earlier session's body, no response yet
+            if self.taken == 0 || self.resumes >= MAX_RESUMES {
+                return None;
+            }
+            let mut headers = format!("Range: bytes={}-\r\n", self.taken);
+            if let Some(validator) = &self.prior_validator {
+                headers.push_str(&format!("If-Range: {}\r\n", validator));
+            }
+            return Some(headers);
+        };
+        if head.status != 200
+            || !head.accept_ranges
+            || head.chunked
+            || head.content_length.is_none()
+            || self.is_complete()
+            || self.resumes >= MAX_RESUMES
+        {
+            return None;
+        }
+        let mut headers = format!("Range: bytes={}-\r\n", self.body_len());
+        if let Some(validator) = &head.validator {
+            headers.push_str(&format!("If-Range: {}\r\n", validator));
+        }
+        Some(headers)
+    }
+
+    /// Start reading a continuation response; a half-received one from an
+    /// earlier attempt is discarded
+    pub fn begin_resume(&mut self) {
+        self.resumes += 1;
+        self.continuation = Some(Vec::new());
+    }
+
+    /// Resume attempts so far
+    pub fn resumes(&self) -> u32 {
+        self.resumes
+    }
+
+    /// The reassembled response
+    pub fn into_response(self) -> Vec<u8> {
+        self.response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    fn full_head(extra: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n{}\r\n",
+            BODY.len(),
+            extra
+        )
+    }
+
+    fn partial(from: usize) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+            from,
+            BODY.len() - 1,
+            BODY.len(),
+            BODY.len() - from
+        )
+        .into_bytes();
+        response.extend_from_slice(&BODY[from..]);
+        response
+    }
+
+    #[test]
+    fn test_resume_reassembles_response() {
+        let head = full_head("ETag: \"v1\"\r\n");
+        let mut download = ResumableDownload::new();
+        download.feed(head.as_bytes()).unwrap();
+        download.feed(&BODY[..10]).unwrap();
+        assert!(!download.is_complete());
+        assert_eq!(
+            download.resume_headers().unwrap(),
+            "Range: bytes=10-\r\nIf-Range: \"v1\"\r\n"
+        );
+
+        // Cut off again partway through the continuation's head
+        download.begin_resume();
+        download.feed(&partial(10)[..20]).unwrap();
+        download.begin_resume();
+        let continuation = partial(10);
+        for piece in continuation.chunks(7) {
+            download.feed(piece).unwrap();
+        }
+        assert!(download.is_complete());
+        assert!(download.resume_headers().is_none());
+        assert_eq!(download.resumes(), 2);
+
+        let mut expected = head.into_bytes();
+        expected.extend_from_slice(BODY);
+        assert_eq!(download.into_response(), expected);
+    }
+
+    #[test]
+    fn test_changed_resource_restarts() {
+        let mut download = ResumableDownload::new();
+        download
+            .feed(full_head("Last-Modified: Tue, 01 Sep 2026 00:00:00 GMT\r\n").as_bytes())
+            .unwrap();
+        download.feed(&BODY[..5]).unwrap();
+        assert!(download
+            .resume_headers()
+            .unwrap()
+            .contains("If-Range: Tue, 01 Sep 2026"));
+
+        // If-Range failed: a whole new response comes back
+        download.begin_resume();
+        let fresh = format!("HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nnew");
+        download.feed(fresh.as_bytes()).unwrap();
+        assert!(download.is_complete());
+        assert_eq!(download.into_response(), fresh.into_bytes());
+
+        // A continuation that starts at the wrong byte is an error
+        let mut download = ResumableDownload::new();
+        download.feed(full_head("").as_bytes()).unwrap();
+        download.feed(&BODY[..5]).unwrap();
+        download.begin_resume();
+        assert!(download.feed(&partial(4)).is_err());
+    }
+
+    #[test]
+    fn test_only_ranged_fixed_length_responses_resume() {
+        let cases = [
+            "HTTP/1.1 200 OK\r\nContent-Length: 36\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nAccept-Ranges: none\r\nContent-Length: 36\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "HTTP/1.1 404 Not Found\r\nAccept-Ranges: bytes\r\nContent-Length: 36\r\n\r\n",
+        ];
+        for head in cases {
+            let mut download = ResumableDownload::new();
+            download.feed(head.as_bytes()).unwrap();
+            download.feed(&BODY[..5]).unwrap();
+            assert!(download.resume_headers().is_none(), "{}", head);
+        }
+
+        // Weak ETags are not sent, and resumes are capped
+        let mut download = ResumableDownload::new();
+        download
+            .feed(full_head("ETag: W/\"weak\"\r\n").as_bytes())
+            .unwrap();
+        assert_eq!(download.resume_headers().unwrap(), "Range: bytes=0-\r\n");
+        for _ in 0..MAX_RESUMES {
+            download.begin_resume();
+        }
+        assert!(download.resume_headers().is_none());
+    }
+
+    #[test]
+    fn test_continuing_download_streams_body() {
+        let mut download = ResumableDownload::continuing(10, Some("\"v1\"".into()));
+        assert_eq!(
+            download.resume_headers().unwrap(),
+            "Range: bytes=10-\r\nIf-Range: \"v1\"\r\n"
+        );
+        download.feed(&partial(10)[..60]).unwrap();
+        let mut body = download.take_body();
+        download.feed(&partial(10)[60..]).unwrap();
+        body.extend(download.take_body());
+        assert_eq!(body, &BODY[10..]);
+        assert_eq!(download.status(), Some(200));
+        assert_eq!(download.content_length(), Some(BODY.len() as u64));
+        assert!(download.is_complete());
+        assert!(!download.take_restarted());
+
+        // The resource changed: the whole of it comes back
+        let mut download = ResumableDownload::continuing(10, None);
+        download.feed(full_head("").as_bytes()).unwrap();
+        download.feed(BODY).unwrap();
+        assert!(download.take_restarted());
+        assert_eq!(download.take_body(), BODY);
+        assert!(download.is_complete());
+    }
+}
@@ -0,0 +1,143 @@
+//! Sound single-threaded shared state for JS callbacks
+//!
+//! Transports share mutable state between their `AsyncRead`/`AsyncWrite`
+//! impls and JS event callbacks (`onmessage`, `onclose`, timers). WASM is
+//! single-threaded, but callbacks can still *re-enter*: a waker or a JS call
+//! made while the state is borrowed may, in principle, trigger code that
+//! borrows it again. `Rc<UnsafeCell<_>>` turns that into silent aliasing UB.
+//!
+//! `LocalCell` is a thin `RefCell` wrapper that only hands out access inside
+//! a closure, so a borrow can never be held across an `.await` or escape the
+//! call. The rules for callers:
+//!
+//! - Keep closures short and synchronous. Do not call back into code that
+//!   may touch the same cell (take wakers out, then wake them *after* `with`
+//!   returns).
+//! - Re-entering the same cell is a bug: [`LocalCell::with`] panics with a
+//!   descriptive message, [`LocalCell::try_with`] reports it as an error.
+
+use std::cell::RefCell;
+use std::fmt;
+
+/// Error returned by [`LocalCell::try_with`] when the cell is already borrowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reentrant;
+
+impl fmt::Display for Reentrant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LocalCell accessed re-entrantly")
+    }
+}
+
+impl std::error::Error for Reentrant {}
+
+/// Single-threaded interior mutability with closure-scoped access
+#[derive(Default)]
+pub struct LocalCell<T> {
+    inner: RefCell<T>,
+}
+
+impl<T> LocalCell<T> {
+    /// Wrap a value
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: RefCell::new(value),
+        }
+    }
+
+    /// Run `f` with exclusive access to the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called re-entrantly from inside another `with`/`try_with`
+    /// on the same cell. That is always a logic error in the caller.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        match self.try_with(f) {
+            Ok(r) => r,
+            Err(e) => panic!("{} (non-reentrant callback contract violated)", e),
+        }
+    }
+
+    /// Run `f` with exclusive access, or return [`Reentrant`] if the cell
+    /// is already borrowed further up the stack.
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Reentrant> {
+        let mut guard = self.inner.try_borrow_mut().map_err(|_| Reentrant)?;
+        Ok(f(&mut guard))
+    }
+
+    /// Replace the value, returning the old one
+    pub fn replace(&self, value: T) -> T {
+        self.with(|v| std::mem::replace(v, value))
+    }
+
+    /// Consume the cell and return the value
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: Clone> LocalCell<T> {
+    /// Clone the current value out of the cell
+    pub fn get_cloned(&self) -> T {
+        self.with(|v| v.clone())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LocalCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.inner.try_borrow() {
+            Ok(v) => f.debug_tuple("LocalCell").field(&*v).finish(),
+            Err(_) => f.write_str("LocalCell(<borrowed>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_with_mutates_in_place() {
+        let cell = LocalCell::new(1u32);
+        cell.with(|v| *v += 41);
+        assert_eq!(cell.get_cloned(), 42);
+        assert_eq!(cell.replace(7), 42);
+        assert_eq!(cell.into_inner(), 7);
+    }
+
+    #[test]
+    fn test_reentrant_try_with_is_reported() {
+        let cell = Rc::new(LocalCell::new(Vec::<u8>::new()));
+        let inner = cell.clone();
+
+        let nested = cell.with(|v| {
+            v.push(1);
+            // A callback firing while we hold the state must not alias it
+            inner.try_with(|v2| v2.push(2))
+        });
+
+        assert_eq!(nested, Err(Reentrant));
+        assert_eq!(cell.get_cloned(), vec![1]);
+
+        // The borrow is released once `with` returns
+        assert_eq!(cell.try_with(|v| v.len()), Ok(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "non-reentrant")]
+    fn test_reentrant_with_panics() {
+        let cell = Rc::new(LocalCell::new(0u8));
+        let inner = cell.clone();
+        cell.with(|_| inner.with(|v| *v = 1));
+    }
+
+    #[test]
+    fn test_debug_while_borrowed() {
+        let cell = LocalCell::new(5u8);
+        assert_eq!(format!("{:?}", cell), "LocalCell(5)");
+        cell.with(|_| {
+            assert_eq!(format!("{:?}", cell), "LocalCell(<borrowed>)");
+        });
+    }
+}
@@ -4,18 +4,25 @@
 //! to run in WebAssembly environments.
 
 pub mod compat;
+pub mod local_cell;
 mod sleep;
 mod spawn;
 mod stubs;
+pub mod supervisor;
 pub mod tcp;
 mod time;
-// mod traits_impl; // Temporarily disabled until tor-rtcompat is fully WASM-ready
+pub mod timer;
+#[cfg(feature = "arti")]
+pub mod traits_impl;
 
 pub use compat::{TcpConnectFuture, TcpStream, WasmBlockingHandle, WasmTlsConnector};
+pub use local_cell::{LocalCell, Reentrant};
 pub use sleep::WasmSleep;
 pub use stubs::{WasmListener, WasmUdpSocket, WasmUnixStream};
+pub use supervisor::{TaskEvent, TaskEventKind, TaskSupervisor};
 pub use tcp::WasmTcpListener;
 pub use time::WasmCoarseInstant;
+pub use timer::{Clock, MockClock, SharedClock, SystemClock, TimerId, TimerService, TimerWheel};
 
 /// WASM-compatible runtime for Arti
 ///
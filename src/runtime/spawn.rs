@@ -1,6 +1,6 @@
 //! Task spawning implementation for WASM
 
-use futures::task::{FutureObj, Spawn, SpawnError};
+use futures::task::{FutureObj, LocalFutureObj, LocalSpawn, Spawn, SpawnError};
 use wasm_bindgen_futures::spawn_local;
 
 use super::WasmRuntime;
@@ -20,6 +20,15 @@ impl Spawn for WasmSpawner {
     }
 }
 
+impl LocalSpawn for WasmSpawner {
+    fn spawn_local_obj(&self, future: LocalFutureObj<'static, ()>) -> Result<(), SpawnError> {
+        // Browser futures are !Send (JS handles, Rc state), so this is the common path
+        spawn_local(future);
+
+        Ok(())
+    }
+}
+
 impl Spawn for WasmRuntime {
     fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
         WasmSpawner.spawn_obj(future)
@@ -0,0 +1,372 @@
+//! Background task supervision
+//!
+//! Long-running tasks (consensus refresh, pool maintenance, padding, reader
+//! loops) used to be fire-and-forget `spawn_local` calls: nothing could stop
+//! them and a failing task simply vanished. [`TaskSupervisor`] spawns them
+//! through [`WasmSpawner`](super::WasmSpawner), keeps an abort handle per
+//! task, and records every start/finish/failure as a [`TaskEvent`].
+//!
+//! Panics are caught where unwinding is available (native tests). On
+//! `wasm32-unknown-unknown` a panic aborts the instance, so tasks should
+//! report failures by returning `Err` instead.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+
+use futures::future::{AbortHandle, Abortable};
+use futures::task::{LocalSpawn, LocalSpawnExt};
+use futures::FutureExt;
+
+use super::{LocalCell, WasmSpawner};
+
+/// How many task events are kept for diagnostics
+const MAX_TASK_EVENTS: usize = 64;
+
+/// What happened to a supervised task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskEventKind {
+    /// Task was spawned
+    Started,
+    /// Task ran to completion
+    Completed,
+    /// Task returned an error
+    Failed(String),
+    /// Task panicked
+    Panicked(String),
+    /// Task was cancelled (shutdown, new identity, or explicit cancel)
+    Cancelled(String),
+}
+
+/// A lifecycle event for a supervised task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskEvent {
+    /// Task name (e.g. `"consensus-refresh"`)
+    pub task: String,
+    /// What happened
+    pub kind: TaskEventKind,
+}
+
+impl TaskEvent {
+    /// Short label for the event kind (`started`, `failed`, ...)
+    pub fn kind_str(&self) -> &'static str {
+        match self.kind {
+            TaskEventKind::Started => "started",
+            TaskEventKind::Completed => "completed",
+            TaskEventKind::Failed(_) => "failed",
+            TaskEventKind::Panicked(_) => "panicked",
+            TaskEventKind::Cancelled(_) => "cancelled",
+        }
+    }
+
+    /// Detail message, if any
+    pub fn detail(&self) -> Option<&str> {
+        match &self.kind {
+            TaskEventKind::Failed(m) | TaskEventKind::Panicked(m) | TaskEventKind::Cancelled(m) => {
+                Some(m)
+            }
+            _ => None,
+        }
+    }
+}
+
+type EventListener = Rc<dyn Fn(&TaskEvent)>;
+
+#[derive(Default)]
+struct SupervisorState {
+    next_id: u64,
+    /// Running tasks: id -> (name, abort handle)
+    tasks: HashMap<u64, (String, AbortHandle)>,
+    events: VecDeque<TaskEvent>,
+    listener: Option<EventListener>,
+    shut_down: bool,
+}
+
+/// Tracks background tasks and cancels them on shutdown
+#[derive(Clone)]
+pub struct TaskSupervisor {
+    state: Rc<LocalCell<SupervisorState>>,
+    spawner: Rc<dyn LocalSpawn>,
+}
+
+thread_local! {
+    static GLOBAL_TASKS: TaskSupervisor = TaskSupervisor::new();
+}
+
+impl TaskSupervisor {
+    /// The process-wide supervisor, for tasks that outlive any one client
+    /// (transport reader loops, worker teardown, event delivery)
+    pub fn global() -> TaskSupervisor {
+        GLOBAL_TASKS.with(|t| t.clone())
+    }
+
+    /// Supervisor that spawns onto the browser event loop
+    pub fn new() -> Self {
+        Self::with_spawner(Rc::new(WasmSpawner))
+    }
+
+    /// Supervisor that spawns onto a custom executor (tests)
+    pub fn with_spawner(spawner: Rc<dyn LocalSpawn>) -> Self {
+        Self {
+            state: Rc::new(LocalCell::new(SupervisorState::default())),
+            spawner,
+        }
+    }
+
+    /// Register a callback invoked for every task event
+    pub fn set_listener(&self, listener: impl Fn(&TaskEvent) + 'static) {
+        self.state.with(|st| st.listener = Some(Rc::new(listener)));
+    }
+
+    /// Spawn a named task. Returns `false` if the supervisor is shut down.
+    ///
+    /// The task reports failure by returning `Err(message)`.
+    pub fn spawn<F>(&self, name: &str, task: F) -> bool
+    where
+        F: Future<Output = Result<(), String>> + 'static,
+    {
+        let (handle, registration) = AbortHandle::new_pair();
+        let id = match self.state.with(|st| {
+            if st.shut_down {
+                return None;
+            }
+            let id = st.next_id;
+            st.next_id += 1;
+            st.tasks.insert(id, (name.to_string(), handle));
+            Some(id)
+        }) {
+            Some(id) => id,
+            None => {
+                log::warn!("⚠️ Not spawning '{}': supervisor is shut down", name);
+                return false;
+            }
+        };
+        self.record(name, TaskEventKind::Started);
+
+        let supervisor = self.clone();
+        let task_name = name.to_string();
+        let wrapped = async move {
+            let outcome = AssertUnwindSafe(Abortable::new(task, registration))
+                .catch_unwind()
+                .await;
+            let running = supervisor.state.with(|st| st.tasks.remove(&id).is_some());
+
+            let kind = match outcome {
+                Ok(Ok(Ok(()))) => TaskEventKind::Completed,
+                Ok(Ok(Err(e))) => TaskEventKind::Failed(e),
+                Ok(Err(_aborted)) => {
+                    // `cancel`/`shutdown` already recorded the event
+                    if !running {
+                        return;
+                    }
+                    TaskEventKind::Cancelled("aborted".to_string())
+                }
+                Err(panic) => TaskEventKind::Panicked(panic_message(&*panic)),
+            };
+            supervisor.record(&task_name, kind);
+        };
+
+        if let Err(e) = self.spawner.spawn_local(wrapped) {
+            self.state.with(|st| st.tasks.remove(&id));
+            self.record(name, TaskEventKind::Failed(format!("spawn failed: {}", e)));
+            return false;
+        }
+        true
+    }
+
+    /// Cancel every running task with the given name
+    pub fn cancel(&self, name: &str, reason: &str) -> usize {
+        let cancelled = self.state.with(|st| {
+            let ids: Vec<u64> = st
+                .tasks
+                .iter()
+                .filter(|(_, (n, _))| n == name)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| st.tasks.remove(&id))
+                .collect::<Vec<_>>()
+        });
+        self.abort_all(cancelled, reason)
+    }
+
+    /// Cancel all running tasks but keep accepting new ones
+    pub fn cancel_all(&self, reason: &str) -> usize {
+        let cancelled = self
+            .state
+            .with(|st| st.tasks.drain().map(|(_, t)| t).collect());
+        self.abort_all(cancelled, reason)
+    }
+
+    /// Cancel all running tasks and refuse new ones
+    pub fn shutdown(&self, reason: &str) -> usize {
+        let already = self
+            .state
+            .with(|st| std::mem::replace(&mut st.shut_down, true));
+        let count = self.cancel_all(reason);
+        if !already {
+            log::info!("🛑 Task supervisor shut down ({} tasks cancelled)", count);
+        }
+        count
+    }
+
+    /// Whether `shutdown` has been called
+    pub fn is_shut_down(&self) -> bool {
+        self.state.with(|st| st.shut_down)
+    }
+
+    /// Whether a task named `name` is running
+    pub fn is_running(&self, name: &str) -> bool {
+        self.state
+            .with(|st| st.tasks.values().any(|(n, _)| n == name))
+    }
+
+    /// Names of running tasks (sorted)
+    pub fn running_tasks(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .state
+            .with(|st| st.tasks.values().map(|(n, _)| n.clone()).collect());
+        names.sort();
+        names
+    }
+
+    /// Recent task events, oldest first
+    pub fn events(&self) -> Vec<TaskEvent> {
+        self.state.with(|st| st.events.iter().cloned().collect())
+    }
+
+    fn abort_all(&self, tasks: Vec<(String, AbortHandle)>, reason: &str) -> usize {
+        let count = tasks.len();
+        for (name, handle) in tasks {
+            handle.abort();
+            self.record(&name, TaskEventKind::Cancelled(reason.to_string()));
+        }
+        count
+    }
+
+    fn record(&self, task: &str, kind: TaskEventKind) {
+        match &kind {
+            TaskEventKind::Failed(e) => log::warn!("⚠️ Task '{}' failed: {}", task, e),
+            TaskEventKind::Panicked(e) => log::error!("💥 Task '{}' panicked: {}", task, e),
+            TaskEventKind::Cancelled(r) => log::debug!("Task '{}' cancelled: {}", task, r),
+            _ => log::debug!("Task '{}' {:?}", task, kind),
+        }
+
+        let event = TaskEvent {
+            task: task.to_string(),
+            kind,
+        };
+        let listener = self.state.with(|st| {
+            if st.events.len() >= MAX_TASK_EVENTS {
+                st.events.pop_front();
+            }
+            st.events.push_back(event.clone());
+            st.listener.clone()
+        });
+        // Call the listener outside the borrow so it may use the supervisor
+        if let Some(listener) = listener {
+            listener(&event);
+        }
+    }
+}
+
+impl Default for TaskSupervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TaskSupervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskSupervisor")
+            .field("running", &self.running_tasks())
+            .field("shut_down", &self.is_shut_down())
+            .finish()
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::oneshot;
+    use futures::executor::LocalPool;
+
+    fn supervisor(pool: &LocalPool) -> TaskSupervisor {
+        TaskSupervisor::with_spawner(Rc::new(pool.spawner()))
+    }
+
+    #[test]
+    fn test_completion_and_failure_are_reported() {
+        let mut pool = LocalPool::new();
+        let tasks = supervisor(&pool);
+
+        assert!(tasks.spawn("ok", async { Ok(()) }));
+        assert!(tasks.spawn("bad", async { Err("relay gone".to_string()) }));
+        assert!(tasks.spawn("boom", async {
+            panic!("reader loop exploded");
+        }));
+        pool.run_until_stalled();
+
+        let events = tasks.events();
+        let outcome = |name: &str| {
+            events
+                .iter()
+                .rev()
+                .find(|e| e.task == name)
+                .map(|e| e.kind.clone())
+        };
+        assert_eq!(outcome("ok"), Some(TaskEventKind::Completed));
+        assert_eq!(
+            outcome("bad"),
+            Some(TaskEventKind::Failed("relay gone".to_string()))
+        );
+        assert_eq!(
+            outcome("boom"),
+            Some(TaskEventKind::Panicked("reader loop exploded".to_string()))
+        );
+        assert!(tasks.running_tasks().is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_cancels_and_refuses_new_tasks() {
+        let mut pool = LocalPool::new();
+        let tasks = supervisor(&pool);
+        let (_tx, rx) = oneshot::channel::<()>();
+
+        let seen = Rc::new(LocalCell::new(0usize));
+        let counter = seen.clone();
+        tasks.set_listener(move |_| counter.with(|n| *n += 1));
+
+        tasks.spawn("pool-maintenance", async move {
+            let _ = rx.await;
+            Ok(())
+        });
+        pool.run_until_stalled();
+        assert_eq!(tasks.running_tasks(), vec!["pool-maintenance".to_string()]);
+
+        assert_eq!(tasks.shutdown("client dropped"), 1);
+        pool.run_until_stalled();
+        assert!(tasks.running_tasks().is_empty());
+        assert!(!tasks.spawn("late", async { Ok(()) }));
+
+        let last = tasks.events().pop().unwrap();
+        assert_eq!(
+            last.kind,
+            TaskEventKind::Cancelled("client dropped".to_string())
+        );
+        // Started + Cancelled, no duplicate event from the aborted future
+        assert_eq!(seen.get_cloned(), 2);
+    }
+}
@@ -0,0 +1,526 @@
+//! Hashed timer wheel shared by every subsystem that needs deadlines
+//!
+//! The scheduler, rate limiter and padding code used to compare
+//! `js_sys::Date::now()` against their own deadlines and poll with ad-hoc
+//! `setTimeout`s. Instead they now read time from a [`Clock`] and register
+//! timeouts with a [`TimerService`], which keeps them in a [`TimerWheel`] and
+//! arms exactly one browser `setTimeout` for the earliest deadline.
+//!
+//! Tests swap in a [`MockClock`] and drive expiry by hand with
+//! [`TimerService::run_expired`], so no real time passes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::channel::oneshot;
+
+use super::LocalCell;
+
+/// Wheel granularity (milliseconds per slot)
+pub const DEFAULT_TICK_MS: u64 = 10;
+
+/// Number of slots; one revolution covers `DEFAULT_TICK_MS * DEFAULT_SLOTS` ms
+pub const DEFAULT_SLOTS: usize = 512;
+
+// ============================================================================
+// CLOCKS
+// ============================================================================
+
+/// Source of "now" in milliseconds
+pub trait Clock: fmt::Debug {
+    /// Current time in milliseconds
+    fn now_ms(&self) -> u64;
+}
+
+/// Shared clock handle
+pub type SharedClock = Rc<dyn Clock>;
+
+/// Wall clock: `Date.now()` in the browser, `SystemTime` natively
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        #[cfg(target_arch = "wasm32")]
+        {
+            js_sys::Date::now() as u64
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use std::time::{SystemTime, UNIX_EPOCH};
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64
+        }
+    }
+}
+
+/// Shared handle to the system clock
+pub fn system_clock() -> SharedClock {
+    Rc::new(SystemClock)
+}
+
+/// Current time from the system clock (milliseconds)
+pub fn now_ms() -> u64 {
+    SystemClock.now_ms()
+}
+
+/// Manually advanced clock for tests
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    now: Rc<std::cell::Cell<u64>>,
+}
+
+impl MockClock {
+    /// Create a mock clock starting at `start_ms`
+    pub fn new(start_ms: u64) -> Self {
+        Self {
+            now: Rc::new(std::cell::Cell::new(start_ms)),
+        }
+    }
+
+    /// Move time forward
+    pub fn advance(&self, ms: u64) {
+        self.now.set(self.now.get() + ms);
+    }
+
+    /// Jump to an absolute time
+    pub fn set(&self, now_ms: u64) {
+        self.now.set(now_ms);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.now.get()
+    }
+}
+
+// ============================================================================
+// TIMER WHEEL
+// ============================================================================
+
+/// Identifies a scheduled timer (for cancellation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+struct Entry<T> {
+    id: TimerId,
+    deadline_ms: u64,
+    value: T,
+}
+
+/// Hashed timer wheel
+///
+/// Entries hash into `deadline / tick` modulo the slot count. Deadlines more
+/// than one revolution away share a slot with nearer ones and are simply
+/// skipped until their deadline passes. Insert and cancel are O(1); expiry
+/// only visits the slots between the last expiry and now.
+pub struct TimerWheel<T> {
+    slots: Vec<Vec<Entry<T>>>,
+    tick_ms: u64,
+    /// First tick that has not been processed yet
+    cursor: u64,
+    /// Slot index of every live timer
+    index: HashMap<TimerId, usize>,
+    next_id: u64,
+}
+
+impl<T> TimerWheel<T> {
+    /// Create a wheel with the default granularity and size
+    pub fn new(now_ms: u64) -> Self {
+        Self::with_geometry(now_ms, DEFAULT_TICK_MS, DEFAULT_SLOTS)
+    }
+
+    /// Create a wheel with a custom tick length and slot count
+    pub fn with_geometry(now_ms: u64, tick_ms: u64, slots: usize) -> Self {
+        let tick_ms = tick_ms.max(1);
+        Self {
+            slots: (0..slots.max(1)).map(|_| Vec::new()).collect(),
+            tick_ms,
+            cursor: now_ms / tick_ms,
+            index: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Number of pending timers
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Whether no timers are pending
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    fn slot_for(&self, tick: u64) -> usize {
+        (tick % self.slots.len() as u64) as usize
+    }
+
+    /// Schedule `value` to expire at `deadline_ms`
+    pub fn insert(&mut self, deadline_ms: u64, value: T) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+
+        // Deadlines already in the past land in the next slot to be processed
+        let tick = (deadline_ms / self.tick_ms).max(self.cursor);
+        let slot = self.slot_for(tick);
+        self.slots[slot].push(Entry {
+            id,
+            deadline_ms,
+            value,
+        });
+        self.index.insert(id, slot);
+        id
+    }
+
+    /// Cancel a timer, returning its value if it had not fired yet
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        let slot = self.index.remove(&id)?;
+        let entries = &mut self.slots[slot];
+        let pos = entries.iter().position(|e| e.id == id)?;
+        Some(entries.swap_remove(pos).value)
+    }
+
+    /// Remove and return every timer whose deadline is `<= now_ms`
+    pub fn expire(&mut self, now_ms: u64) -> Vec<(TimerId, T)> {
+        let now_tick = now_ms / self.tick_ms;
+        let mut fired = Vec::new();
+        if now_tick < self.cursor {
+            return Vec::new();
+        }
+
+        // Visiting more than one revolution would just repeat slots
+        let span = (now_tick - self.cursor + 1).min(self.slots.len() as u64);
+        for tick in self.cursor..self.cursor + span {
+            let slot = self.slot_for(tick);
+            let entries = &mut self.slots[slot];
+            let mut i = 0;
+            while i < entries.len() {
+                if entries[i].deadline_ms <= now_ms {
+                    let entry = entries.swap_remove(i);
+                    self.index.remove(&entry.id);
+                    fired.push(entry);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        // The current tick may still receive timers due later in it
+        self.cursor = now_tick;
+
+        fired.sort_by_key(|e| (e.deadline_ms, e.id));
+        fired.into_iter().map(|e| (e.id, e.value)).collect()
+    }
+
+    /// Earliest pending deadline
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.slots
+            .iter()
+            .flat_map(|entries| entries.iter().map(|e| e.deadline_ms))
+            .min()
+    }
+}
+
+impl<T> fmt::Debug for TimerWheel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimerWheel")
+            .field("tick_ms", &self.tick_ms)
+            .field("slots", &self.slots.len())
+            .field("pending", &self.index.len())
+            .finish()
+    }
+}
+
+// ============================================================================
+// TIMER SERVICE
+// ============================================================================
+
+type TimerCallback = Box<dyn FnOnce()>;
+
+struct ServiceState {
+    wheel: TimerWheel<TimerCallback>,
+    /// Deadline and `setTimeout` handle of the armed browser timer
+    armed: Option<(u64, i32)>,
+}
+
+/// Callback-based timers on top of a [`TimerWheel`]
+///
+/// The global service (see [`TimerService::global`]) arms a single browser
+/// `setTimeout` for the earliest deadline. Services built with
+/// [`TimerService::with_clock`] never touch the browser; call
+/// [`run_expired`](Self::run_expired) to fire due timers.
+#[derive(Clone)]
+pub struct TimerService {
+    state: Rc<LocalCell<ServiceState>>,
+    clock: SharedClock,
+    drive_with_browser: bool,
+}
+
+thread_local! {
+    static GLOBAL_TIMERS: TimerService = TimerService::new_browser();
+}
+
+impl TimerService {
+    /// The process-wide service, driven by one browser `setTimeout`
+    pub fn global() -> TimerService {
+        GLOBAL_TIMERS.with(|t| t.clone())
+    }
+
+    fn new_browser() -> Self {
+        let clock = system_clock();
+        let mut service = Self::with_clock(clock);
+        service.drive_with_browser = cfg!(target_arch = "wasm32");
+        service
+    }
+
+    /// A manually driven service reading time from `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
+        let now = clock.now_ms();
+        Self {
+            state: Rc::new(LocalCell::new(ServiceState {
+                wheel: TimerWheel::new(now),
+                armed: None,
+            })),
+            clock,
+            drive_with_browser: false,
+        }
+    }
+
+    /// The clock this service reads time from
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    /// Current time according to this service's clock
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    /// Number of pending timers
+    pub fn pending(&self) -> usize {
+        self.state.with(|st| st.wheel.len())
+    }
+
+    /// Run `callback` once `delay` has elapsed
+    pub fn schedule(&self, delay: Duration, callback: impl FnOnce() + 'static) -> TimerId {
+        let deadline = self.now_ms() + delay.as_millis() as u64;
+        self.schedule_at(deadline, callback)
+    }
+
+    /// Run `callback` at `deadline_ms` (per this service's clock)
+    pub fn schedule_at(&self, deadline_ms: u64, callback: impl FnOnce() + 'static) -> TimerId {
+        let id = self
+            .state
+            .with(|st| st.wheel.insert(deadline_ms, Box::new(callback)));
+        self.rearm();
+        id
+    }
+
+    /// Cancel a pending timer. Returns `false` if it already fired.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        self.state.with(|st| st.wheel.cancel(id)).is_some()
+    }
+
+    /// Fire every due timer, returning how many ran.
+    ///
+    /// Callbacks run after the wheel is released, so they may schedule or
+    /// cancel timers themselves.
+    pub fn run_expired(&self) -> usize {
+        let now = self.now_ms();
+        let due = self.state.with(|st| st.wheel.expire(now));
+        let count = due.len();
+        for (_, callback) in due {
+            callback();
+        }
+        self.rearm();
+        count
+    }
+
+    /// A future that resolves after `delay`
+    pub fn sleep(&self, delay: Duration) -> TimerSleep {
+        let (tx, rx) = oneshot::channel();
+        self.schedule(delay, move || {
+            let _ = tx.send(());
+        });
+        TimerSleep { rx }
+    }
+
+    /// Make sure the browser timer fires at the earliest pending deadline
+    fn rearm(&self) {
+        if !self.drive_with_browser {
+            return;
+        }
+
+        let (next, stale) = self.state.with(|st| {
+            let next = st.wheel.next_deadline();
+            match (next, st.armed) {
+                (Some(next), Some((armed_at, _))) if armed_at <= next => (None, None),
+                (next, armed) => {
+                    st.armed = None;
+                    (next, armed.map(|(_, handle)| handle))
+                }
+            }
+        });
+
+        let window = match web_sys::window() {
+            Some(w) => w,
+            None => return,
+        };
+        if let Some(handle) = stale {
+            window.clear_timeout_with_handle(handle);
+        }
+        let next = match next {
+            Some(next) => next,
+            None => return,
+        };
+
+        let delay = next.saturating_sub(self.now_ms()).min(i32::MAX as u64) as i32;
+        let weak: Weak<LocalCell<ServiceState>> = Rc::downgrade(&self.state);
+        let clock = self.clock.clone();
+        let callback = wasm_bindgen::closure::Closure::once_into_js(move || {
+            if let Some(state) = weak.upgrade() {
+                state.with(|st| st.armed = None);
+                let service = TimerService {
+                    state,
+                    clock,
+                    drive_with_browser: true,
+                };
+                service.run_expired();
+            }
+        });
+
+        use wasm_bindgen::JsCast;
+        if let Ok(handle) = window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(callback.unchecked_ref(), delay)
+        {
+            self.state.with(|st| st.armed = Some((next, handle)));
+        }
+    }
+}
+
+impl fmt::Debug for TimerService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimerService")
+            .field("clock", &self.clock)
+            .field("pending", &self.state.try_with(|st| st.wheel.len()).ok())
+            .finish()
+    }
+}
+
+/// Future returned by [`TimerService::sleep`]
+pub struct TimerSleep {
+    rx: oneshot::Receiver<()>,
+}
+
+impl Future for TimerSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // A cancelled sender (service dropped) also ends the sleep
+        Pin::new(&mut self.rx).poll(cx).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_wheel_expires_in_deadline_order() {
+        let mut wheel = TimerWheel::with_geometry(1_000, 10, 8);
+        let late = wheel.insert(1_500, "late"); // wraps the 80ms wheel
+        let early = wheel.insert(1_020, "early");
+        let cancelled = wheel.insert(1_030, "cancelled");
+        assert_eq!(wheel.len(), 3);
+
+        assert_eq!(wheel.cancel(cancelled), Some("cancelled"));
+        assert_eq!(wheel.cancel(cancelled), None);
+        assert_eq!(wheel.next_deadline(), Some(1_020));
+
+        assert!(wheel.expire(1_019).is_empty());
+        assert_eq!(wheel.expire(1_100), vec![(early, "early")]);
+
+        // Same slot as `early` a few revolutions later: not due yet
+        assert!(wheel.expire(1_499).is_empty());
+        assert_eq!(wheel.expire(1_500), vec![(late, "late")]);
+        assert!(wheel.is_empty());
+
+        // Fired timers come back by deadline, not insertion order
+        let second = wheel.insert(1_540, "second");
+        let first = wheel.insert(1_510, "first");
+        assert_eq!(
+            wheel.expire(1_600),
+            vec![(first, "first"), (second, "second")]
+        );
+    }
+
+    #[test]
+    fn test_wheel_past_deadline_fires_next_expire() {
+        let mut wheel = TimerWheel::with_geometry(5_000, 10, 8);
+        let id = wheel.insert(10, ());
+        assert_eq!(wheel.expire(5_000), vec![(id, ())]);
+    }
+
+    #[test]
+    fn test_service_with_mock_clock() {
+        let clock = MockClock::new(0);
+        let service = TimerService::with_clock(Rc::new(clock.clone()));
+        let fired = Rc::new(RefCell::new(Vec::new()));
+
+        let log = fired.clone();
+        service.schedule(Duration::from_millis(100), move || {
+            log.borrow_mut().push("a")
+        });
+        let log = fired.clone();
+        let b = service.schedule(Duration::from_millis(50), move || {
+            log.borrow_mut().push("b")
+        });
+        let log = fired.clone();
+        let svc = service.clone();
+        service.schedule(Duration::from_millis(20), move || {
+            log.borrow_mut().push("c");
+            // Callbacks may schedule more work
+            let log = log.clone();
+            svc.schedule(Duration::from_millis(10), move || {
+                log.borrow_mut().push("d")
+            });
+        });
+
+        assert!(service.cancel(b));
+        assert_eq!(service.run_expired(), 0);
+
+        clock.advance(20);
+        assert_eq!(service.run_expired(), 1);
+        clock.advance(10);
+        assert_eq!(service.run_expired(), 1);
+        clock.advance(100);
+        assert_eq!(service.run_expired(), 1);
+
+        assert_eq!(*fired.borrow(), vec!["c", "d", "a"]);
+        assert_eq!(service.pending(), 0);
+    }
+
+    #[test]
+    fn test_sleep_resolves_on_expiry() {
+        let clock = MockClock::new(0);
+        let service = TimerService::with_clock(Rc::new(clock.clone()));
+        let mut sleep = service.sleep(Duration::from_millis(30));
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut sleep).poll(&mut cx).is_pending());
+
+        clock.advance(30);
+        service.run_expired();
+        assert!(Pin::new(&mut sleep).poll(&mut cx).is_ready());
+    }
+}
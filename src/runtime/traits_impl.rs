@@ -1,192 +1,204 @@
-//! Trait implementations for tor-rtcompat compatibility
+//! Stream-level tor-rtcompat trait implementations
 //!
-//! This module implements tor-rtcompat traits for our WASM types
+//! tor-rtcompat wants every stream, listener and future to be `Send + Sync`,
+//! but browser handles (WebSocket, JS promises, `Rc` state) never are. The
+//! wrappers here assert those bounds for wasm32, where there is only one
+//! thread, and implement the stream traits on top of our transports. The
+//! provider traits on [`WasmRuntime`](super::WasmRuntime) live in
+//! `arti_impls.rs`.
 
-use std::io;
-use std::pin::Pin;
-use std::task::{Context, Poll};
-use std::net::SocketAddr;
 use async_trait::async_trait;
-use futures::{Future, Stream, stream};
 use futures::io::{AsyncRead, AsyncWrite};
-use tor_rtcompat::{StreamOps, NetStreamListener, CertifiedConn, UdpSocket};
+use futures::stream;
+use futures::Future;
+use std::io::{self, Result as IoResult};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tor_rtcompat::tls::{CertifiedConn, TlsConnector};
+use tor_rtcompat::{NetStreamListener, StreamOps, UdpSocket};
 
-use crate::transport::WasmTcpStream;
+use super::stubs::WasmUdpSocket;
 use super::tcp::WasmTcpListener;
-use super::compat::unix::UnixStream;
-use super::compat::WasmTlsConnector;
+use crate::network::WasmTlsStream;
+use crate::transport::TransportStream;
 
-// Implement StreamOps for WasmTcpStream
-impl StreamOps for WasmTcpStream {
-    fn set_tcp_notsent_lowat(&self, _notsent_lowat: u32) -> io::Result<()> {
-        // WebSocket doesn't support TCP_NOTSENT_LOWAT
-        Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "TCP_NOTSENT_LOWAT not supported in WASM/WebSocket",
-        ))
+/// Future wrapper asserting `Send` for a browser-bound future
+pub struct SendFuture<F>(pub F);
+
+// SAFETY: wasm32-unknown-unknown runs on a single thread, so the wrapped
+// future is never polled or dropped from a thread other than the one that
+// created it. tor-rtcompat only asks for `Send` so native runtimes can move
+// tasks between worker threads, which cannot happen here.
+unsafe impl<F> Send for SendFuture<F> {}
+
+impl<F: Future> Future for SendFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: structural pin projection; the inner future is never moved
+        unsafe { self.map_unchecked_mut(|f| &mut f.0) }.poll(cx)
     }
 }
 
-// Create a stream type for incoming connections (always empty in WASM)
-pub struct WasmIncoming;
+/// A relay connection handed to arti (any of our transports)
+pub struct ArtiStream(pub TransportStream);
 
-impl Stream for WasmIncoming {
-    type Item = io::Result<(WasmTcpStream, std::net::SocketAddr)>;
-    
-    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // Never yields - listeners not supported in WASM
-        Poll::Ready(None)
+// SAFETY: see `SendFuture` — there is only one thread on wasm32.
+unsafe impl Send for ArtiStream {}
+unsafe impl Sync for ArtiStream {}
+
+impl ArtiStream {
+    /// Unwrap the underlying transport
+    pub fn into_inner(self) -> TransportStream {
+        self.0
     }
 }
 
-// Implement NetStreamListener for WasmTcpListener
-impl NetStreamListener for WasmTcpListener {
-    type Stream = WasmTcpStream;
-    type Incoming = WasmIncoming;
-    
-    fn incoming(self) -> Self::Incoming {
-        // Listeners not supported in WASM
-        WasmIncoming
+impl AsyncRead for ArtiStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
     }
-    
-    fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
-        Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "TCP listeners not supported in WASM",
-        ))
+}
+
+impl AsyncWrite for ArtiStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
     }
 }
 
-// Implement traits for UnixStream (stub)
-impl AsyncRead for UnixStream {
+// No socket options behind a WebSocket; the defaults report `Unsupported`
+impl StreamOps for ArtiStream {}
+
+/// A rustls session over an [`ArtiStream`]
+pub struct ArtiTlsStream(pub WasmTlsStream);
+
+// SAFETY: see `SendFuture` — there is only one thread on wasm32.
+unsafe impl Send for ArtiTlsStream {}
+unsafe impl Sync for ArtiTlsStream {}
+
+impl AsyncRead for ArtiTlsStream {
     fn poll_read(
-        self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
-        _buf: &mut [u8],
-    ) -> Poll<io::Result<usize>> {
-        Poll::Ready(Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "Unix sockets not supported in WASM",
-        )))
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
     }
 }
 
-impl AsyncWrite for UnixStream {
+impl AsyncWrite for ArtiTlsStream {
     fn poll_write(
-        self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
-        _buf: &[u8],
-    ) -> Poll<io::Result<usize>> {
-        Poll::Ready(Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "Unix sockets not supported in WASM",
-        )))
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
     }
-    
-    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Poll::Ready(Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "Unix sockets not supported in WASM",
-        )))
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
     }
-    
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Poll::Ready(Ok(()))
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
     }
 }
 
-impl StreamOps for UnixStream {
-    fn set_tcp_notsent_lowat(&self, _notsent_lowat: u32) -> io::Result<()> {
-        Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "Unix sockets not supported in WASM",
-        ))
+impl StreamOps for ArtiTlsStream {}
+
+impl CertifiedConn for ArtiTlsStream {
+    fn export_keying_material(
+        &self,
+        len: usize,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> IoResult<Vec<u8>> {
+        self.0.export_keying_material(len, label, context)
+    }
+
+    fn peer_certificate(&self) -> IoResult<Option<Vec<u8>>> {
+        Ok(self.0.peer_certificate())
     }
 }
 
-// Unix socket incoming stream (always empty)
-pub struct UnixIncoming;
+/// TLS connector for arti: the same rustls handshake our own channels use
+#[derive(Debug, Clone, Default)]
+pub struct ArtiTlsConnector;
+
+#[async_trait]
+impl TlsConnector<ArtiStream> for ArtiTlsConnector {
+    type Conn = ArtiTlsStream;
 
-impl Stream for UnixIncoming {
-    type Item = io::Result<(UnixStream, std::os::unix::net::SocketAddr)>;
-    
-    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Poll::Ready(None)
+    async fn negotiate_unvalidated(
+        &self,
+        stream: ArtiStream,
+        sni_hostname: &str,
+    ) -> IoResult<Self::Conn> {
+        let sni = sni_hostname.to_string();
+        SendFuture(async move {
+            WasmTlsStream::wrap(stream.into_inner(), Some(sni), None)
+                .await
+                .map(ArtiTlsStream)
+        })
+        .await
     }
 }
 
-// Implement NetStreamListener for UnixStream (stub)
-impl NetStreamListener<std::os::unix::net::SocketAddr> for UnixStream {
-    type Stream = UnixStream;
-    type Incoming = UnixIncoming;
-    
+// Listeners are never handed out (`listen` always fails), but arti's
+// provider traits still name a listener type for every address family.
+impl<ADDR: Send + Sync + 'static> NetStreamListener<ADDR> for WasmTcpListener {
+    type Stream = ArtiStream;
+    type Incoming = stream::Empty<IoResult<(ArtiStream, ADDR)>>;
+
     fn incoming(self) -> Self::Incoming {
-        UnixIncoming
+        stream::empty()
     }
-    
-    fn local_addr(&self) -> io::Result<std::os::unix::net::SocketAddr> {
-        Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "Unix sockets not supported in WASM",
-        ))
-    }
-}
 
-// Implement CertifiedConn for WasmTcpStream (browser handles certificates)
-impl CertifiedConn for WasmTcpStream {
-    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
-        // Browser handles certificate validation
-        // We don't have access to the actual certificate
-        Ok(None)
-    }
-    
-    fn export_keying_material(
-        &self,
-        _len: usize,
-        _label: &[u8],
-        _context: Option<&[u8]>,
-    ) -> io::Result<Vec<u8>> {
-        // Browser's TLS doesn't expose keying material in WASM
+    fn local_addr(&self) -> IoResult<ADDR> {
         Err(io::Error::new(
             io::ErrorKind::Unsupported,
-            "Keying material export not supported in WASM",
+            "TCP listeners not supported in WASM",
         ))
     }
 }
 
-// SAFETY: While WasmTcpStream contains Rc<RefCell>, which is normally !Send + !Sync,
-// in WASM there is no true multithreading. JavaScript is single-threaded, and WASM
-// runs in that single-threaded context. The Send/Sync requirements come from Arti's
-// traits, but in practice, WASM streams will never be accessed from multiple threads.
-unsafe impl Send for WasmTcpStream {}
-unsafe impl Sync for WasmTcpStream {}
-
-// Implement UdpSocket trait for WasmUdpSocket (stub)
-#[async_trait(?Send)]
-impl UdpSocket for super::compat::WasmUdpSocket {
-    async fn recv(&self, _buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+#[async_trait]
+impl UdpSocket for WasmUdpSocket {
+    async fn recv(&self, _buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
         Err(io::Error::new(
             io::ErrorKind::Unsupported,
             "UDP not supported in WASM",
         ))
     }
-    
-    async fn send(&self, _buf: &[u8], _target: &SocketAddr) -> io::Result<usize> {
+
+    async fn send(&self, _buf: &[u8], _target: &SocketAddr) -> IoResult<usize> {
         Err(io::Error::new(
             io::ErrorKind::Unsupported,
             "UDP not supported in WASM",
         ))
     }
-    
-    fn local_addr(&self) -> io::Result<SocketAddr> {
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
         Err(io::Error::new(
             io::ErrorKind::Unsupported,
             "UDP not supported in WASM",
         ))
     }
 }
-
-// Note: TlsConnector implementation is complex and may need to wait
-// until we have proper TLS traits from tor-rtcompat available.
-// For now, we stub it at the TlsProvider level in arti_impls.rs
-
@@ -0,0 +1,260 @@
+//! Security downgrade tracking
+//!
+//! Several code paths keep working in a weaker mode rather than fail: a
+//! consensus the bridge sent without signatures, the built-in relay list
+//! when no consensus could be fetched, a relay whose certificates only
+//! passed the structural quick check, a bridge connection that fell back
+//! from blinded to direct addressing. Each used to be a lone `log::warn!`.
+//!
+//! They are now reported here as [`Downgrade`]s. The client's
+//! [`report`] lists the active ones with their [`Severity`], and the
+//! listener registered with [`set_listener`] hears about each downgrade
+//! when it first becomes active. Downgrades that the client can recover
+//! from (the consensus ones) are cleared again with [`resolve`].
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// How much a downgrade weakens the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Worth knowing, no loss of protection
+    Info,
+    /// Weaker than intended (privacy from the bridge, relay authentication)
+    Warning,
+    /// Path selection can't be trusted
+    Critical,
+}
+
+/// A state in which the client runs with weaker protection than intended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Downgrade {
+    /// Consensus accepted without checking authority signatures
+    UnverifiedConsensus,
+    /// No consensus could be fetched; the built-in relay list is in use
+    MockConsensus,
+    /// A guard's certificate chain didn't verify against its fingerprint;
+    /// only the structural quick check ran
+    CertQuickVerify,
+    /// Bridge blinding failed, so the bridge saw the relay address
+    BlindingFallback,
+    /// Blinded traffic crossed the first bridge without end-to-end sealing
+    UnsealedBlinding,
+}
+
+impl Downgrade {
+    pub fn severity(self) -> Severity {
+        match self {
+            Downgrade::UnverifiedConsensus => Severity::Critical,
+            Downgrade::MockConsensus => Severity::Warning,
+            Downgrade::CertQuickVerify => Severity::Warning,
+            Downgrade::BlindingFallback => Severity::Warning,
+            Downgrade::UnsealedBlinding => Severity::Warning,
+        }
+    }
+
+    /// What the downgrade means for the user
+    pub fn description(self) -> &'static str {
+        match self {
+            Downgrade::UnverifiedConsensus => {
+                "consensus signatures were not verified; the bridge controls relay selection"
+            }
+            Downgrade::MockConsensus => {
+                "using the built-in relay list; it may be stale and marks this client"
+            }
+            Downgrade::CertQuickVerify => {
+                "a guard's identity was not verified against its fingerprint"
+            }
+            Downgrade::BlindingFallback => "the bridge could see which relay was contacted",
+            Downgrade::UnsealedBlinding => {
+                "the first bridge could tamper with or replay traffic to the second"
+            }
+        }
+    }
+}
+
+/// An active downgrade
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecurityNotice {
+    pub kind: Downgrade,
+    pub severity: Severity,
+    pub description: &'static str,
+    /// The most recent occurrence's details
+    pub detail: String,
+    /// Occurrences since it became active
+    pub count: u64,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+}
+
+/// Sent to the listener when a downgrade becomes active
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PostureDegraded {
+    pub notice: SecurityNotice,
+    /// Highest severity active after this downgrade
+    pub level: Severity,
+}
+
+/// Active downgrades, as returned by `get_status()`
+#[derive(Debug, Clone, Serialize)]
+pub struct PostureReport {
+    /// Highest active severity; `None` when nothing is downgraded
+    pub level: Option<Severity>,
+    pub notices: Vec<SecurityNotice>,
+}
+
+/// Active downgrades; see the module docs
+#[derive(Debug, Default)]
+pub struct SecurityPosture {
+    active: BTreeMap<Downgrade, SecurityNotice>,
+}
+
+impl SecurityPosture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an occurrence of `kind`, returning the event to raise if it
+    /// wasn't already active
+    pub fn record(
+        &mut self,
+        kind: Downgrade,
+        detail: &str,
+        now_ms: u64,
+    ) -> Option<PostureDegraded> {
+        if let Some(notice) = self.active.get_mut(&kind) {
+            notice.count += 1;
+            notice.last_seen_ms = now_ms;
+            notice.detail = detail.to_string();
+            return None;
+        }
+        let notice = SecurityNotice {
+            kind,
+            severity: kind.severity(),
+            description: kind.description(),
+            detail: detail.to_string(),
+            count: 1,
+            first_seen_ms: now_ms,
+            last_seen_ms: now_ms,
+        };
+        self.active.insert(kind, notice.clone());
+        Some(PostureDegraded {
+            notice,
+            level: self.level().unwrap_or(Severity::Info),
+        })
+    }
+
+    /// Clear `kind`, returning whether it was active
+    pub fn resolve(&mut self, kind: Downgrade) -> bool {
+        self.active.remove(&kind).is_some()
+    }
+
+    /// Highest active severity
+    pub fn level(&self) -> Option<Severity> {
+        self.active.values().map(|n| n.severity).max()
+    }
+
+    pub fn report(&self) -> PostureReport {
+        PostureReport {
+            level: self.level(),
+            notices: self.active.values().cloned().collect(),
+        }
+    }
+}
+
+type Listener = Rc<dyn Fn(&PostureDegraded)>;
+
+thread_local! {
+    static POSTURE: RefCell<SecurityPosture> = RefCell::new(SecurityPosture::new());
+    static LISTENER: RefCell<Option<Listener>> = const { RefCell::new(None) };
+}
+
+/// Register a callback for newly active downgrades, replacing any earlier one
+pub fn set_listener(listener: impl Fn(&PostureDegraded) + 'static) {
+    LISTENER.with(|l| *l.borrow_mut() = Some(Rc::new(listener)));
+}
+
+/// Report that the client fell back to a weaker mode
+pub fn report_downgrade(kind: Downgrade, detail: &str) {
+    log::warn!("🛡️ Security downgrade ({:?}): {}", kind, detail);
+    let now = crate::runtime::timer::now_ms();
+    let Some(event) = POSTURE.with(|p| p.borrow_mut().record(kind, detail, now)) else {
+        return;
+    };
+    if let Some(listener) = LISTENER.with(|l| l.borrow().clone()) {
+        listener(&event);
+    }
+}
+
+/// Clear a downgrade the client recovered from
+pub fn resolve(kind: Downgrade) {
+    if POSTURE.with(|p| p.borrow_mut().resolve(kind)) {
+        log::info!("🛡️ Security downgrade resolved: {:?}", kind);
+    }
+}
+
+pub fn report() -> PostureReport {
+    POSTURE.with(|p| p.borrow().report())
+}
+
+/// Whether `kind` is currently active
+pub fn is_active(kind: Downgrade) -> bool {
+    POSTURE.with(|p| p.borrow().active.contains_key(&kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degraded_raised_once_per_activation() {
+        let mut posture = SecurityPosture::new();
+        assert_eq!(posture.level(), None);
+
+        let event = posture
+            .record(Downgrade::BlindingFallback, "bad key", 10)
+            .unwrap();
+        assert_eq!(event.level, Severity::Warning);
+        assert!(posture
+            .record(Downgrade::BlindingFallback, "bad key again", 20)
+            .is_none());
+
+        let event = posture
+            .record(Downgrade::UnverifiedConsensus, "no raw_consensus", 30)
+            .unwrap();
+        assert_eq!(event.level, Severity::Critical);
+
+        let report = posture.report();
+        assert_eq!(report.level, Some(Severity::Critical));
+        let blinding = &report.notices[1];
+        assert_eq!(blinding.kind, Downgrade::BlindingFallback);
+        assert_eq!(
+            (
+                blinding.count,
+                blinding.first_seen_ms,
+                blinding.last_seen_ms
+            ),
+            (2, 10, 20)
+        );
+        assert_eq!(blinding.detail, "bad key again");
+    }
+
+    #[test]
+    fn test_resolve_lowers_level_and_rearms() {
+        let mut posture = SecurityPosture::new();
+        posture.record(Downgrade::MockConsensus, "fetch failed", 0);
+        posture.record(Downgrade::UnverifiedConsensus, "no raw_consensus", 0);
+
+        assert!(posture.resolve(Downgrade::UnverifiedConsensus));
+        assert!(!posture.resolve(Downgrade::UnverifiedConsensus));
+        assert_eq!(posture.level(), Some(Severity::Warning));
+
+        assert!(posture
+            .record(Downgrade::UnverifiedConsensus, "again", 5)
+            .is_some());
+    }
+}
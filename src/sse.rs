@@ -0,0 +1,484 @@
+//! Server-sent events over a streaming HTTP/1.1 response
+//!
+//! LLM APIs stream completions as `text/event-stream`, usually with chunked
+//! transfer encoding. [`SseStream`] takes response bytes in whatever pieces
+//! the circuit delivers them (one relay cell's worth, one TLS record) and
+//! returns every event completed by that piece, so callers can hand events
+//! to JS as soon as they decrypt instead of waiting for the response to end.
+//!
+//! Event parsing follows the WHATWG EventSource rules: `data:` lines are
+//! joined with `\n`, a blank line dispatches, `:` lines are comments, and
+//! `event:` / `id:` / `retry:` set the event's fields.
+
+use crate::error::{Result, TorError};
+use crate::protocol::{TlsTorStream, TorStream};
+use serde::Serialize;
+
+/// Longest line accepted in the event stream or in HTTP framing
+pub const MAX_SSE_LINE: usize = 1024 * 1024;
+
+/// Largest response header accepted
+const MAX_HEAD: usize = 64 * 1024;
+
+/// One dispatched event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SseEvent {
+    /// Event type (`"message"` unless the stream set `event:`)
+    pub event: String,
+    pub data: String,
+    /// Last event ID seen on the stream
+    pub id: Option<String>,
+    /// Reconnection time the server asked for, in ms
+    pub retry: Option<u64>,
+}
+
+/// Incremental `text/event-stream` parser
+#[derive(Debug, Default)]
+pub struct SseParser {
+    line: Vec<u8>,
+    /// Previous byte was `\r`, so a following `\n` belongs to that line end
+    after_cr: bool,
+    started: bool,
+    event: String,
+    data: String,
+    has_data: bool,
+    last_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed body bytes; returns the events they complete
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Result<Vec<SseEvent>> {
+        if !self.started && !bytes.is_empty() {
+            self.started = true;
+            bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+        }
+
+        let mut events = Vec::new();
+        for &b in bytes {
+            match b {
+                b'\n' if self.after_cr => self.after_cr = false,
+                b'\r' | b'\n' => {
+                    self.after_cr = b == b'\r';
+                    let line = std::mem::take(&mut self.line);
+                    if let Some(event) = self.process_line(&line) {
+                        events.push(event);
+                    }
+                }
+                _ => {
+                    self.after_cr = false;
+                    if self.line.len() >= MAX_SSE_LINE {
+                        return Err(TorError::ProtocolError("SSE line too long".into()));
+                    }
+                    self.line.push(b);
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(ms) = value.parse() {
+                    self.retry = Some(ms);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = std::mem::take(&mut self.event);
+        if !std::mem::take(&mut self.has_data) {
+            return None;
+        }
+        Some(SseEvent {
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data: std::mem::take(&mut self.data),
+            id: self.last_id.clone(),
+            retry: self.retry,
+        })
+    }
+}
+
+/// How the response body is delimited
+#[derive(Debug)]
+enum Framing {
+    Chunked(ChunkState),
+    Length(u64),
+    UntilClose,
+}
+
+#[derive(Debug)]
+pub(crate) enum ChunkState {
+    Size(Vec<u8>),
+    Data(u64),
+    /// CRLF after chunk data
+    DataEnd(u8),
+    Trailers(Vec<u8>),
+    Done,
+}
+
+/// HTTP response decoder that yields SSE events as body bytes arrive
+#[derive(Debug, Default)]
+pub struct SseStream {
+    head: Vec<u8>,
+    status: Option<u16>,
+    framing: Option<Framing>,
+    parser: SseParser,
+    /// Body of a non-2xx response, kept for the error message
+    error_body: Vec<u8>,
+}
+
+impl SseStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Response status, once the header has arrived
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// Whether the status is 2xx (false until the header has arrived)
+    pub fn is_success(&self) -> bool {
+        self.status.is_some_and(|s| (200..300).contains(&s))
+    }
+
+    /// Raw response header, once complete
+    pub fn head(&self) -> Option<&[u8]> {
+        self.status.map(|_| self.head.as_slice())
+    }
+
+    /// Body received so far of a non-2xx response
+    pub fn error_body(&self) -> String {
+        String::from_utf8_lossy(&self.error_body).into_owned()
+    }
+
+    /// Whether the body's end has been seen (always false for bodies that
+    /// run until the connection closes)
+    pub fn is_complete(&self) -> bool {
+        matches!(
+            self.framing,
+            Some(Framing::Chunked(ChunkState::Done)) | Some(Framing::Length(0))
+        )
+    }
+
+    /// Feed response bytes; returns the events they complete
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<SseEvent>> {
+        if self.status.is_none() {
+            let start = self.head.len().saturating_sub(3);
+            self.head.extend_from_slice(bytes);
+            let Some(end) = self.head[start..]
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .map(|p| start + p + 4)
+            else {
+                if self.head.len() > MAX_HEAD {
+                    return Err(TorError::ProtocolError("Response header too large".into()));
+                }
+                return Ok(Vec::new());
+            };
+            let body = self.head.split_off(end);
+            self.parse_head()?;
+            return self.feed_body(&body);
+        }
+        self.feed_body(bytes)
+    }
+
+    fn parse_head(&mut self) -> Result<()> {
+        let head = String::from_utf8_lossy(&self.head).into_owned();
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|l| l.split(' ').nth(1))
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| TorError::ProtocolError("Bad HTTP status line".into()))?;
+
+        let mut framing = Framing::UntilClose;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("transfer-encoding")
+                && value.to_ascii_lowercase().ends_with("chunked")
+            {
+                framing = Framing::Chunked(ChunkState::Size(Vec::new()));
+            } else if name.eq_ignore_ascii_case("content-length")
+                && !matches!(framing, Framing::Chunked(_))
+            {
+                let len = value
+                    .parse()
+                    .map_err(|_| TorError::ProtocolError("Bad Content-Length".into()))?;
+                framing = Framing::Length(len);
+            }
+        }
+
+        self.status = Some(status);
+        self.framing = Some(framing);
+        Ok(())
+    }
+
+    fn feed_body(&mut self, bytes: &[u8]) -> Result<Vec<SseEvent>> {
+        let mut decoded = Vec::with_capacity(bytes.len());
+        match self.framing.as_mut() {
+            Some(Framing::Chunked(state)) => decode_chunked(state, bytes, &mut decoded)?,
+            Some(Framing::Length(remaining)) => {
+                let take = (*remaining).min(bytes.len() as u64) as usize;
+                decoded.extend_from_slice(&bytes[..take]);
+                *remaining -= take as u64;
+            }
+            Some(Framing::UntilClose) => decoded.extend_from_slice(bytes),
+            None => {}
+        }
+
+        if self.is_success() {
+            self.parser.feed(&decoded)
+        } else {
+            if self.error_body.len() < MAX_HEAD {
+                self.error_body.extend_from_slice(&decoded);
+            }
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Advance the chunked decoder over `bytes`, appending chunk data to `out`
+pub(crate) fn decode_chunked(
+    state: &mut ChunkState,
+    mut bytes: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    while !bytes.is_empty() {
+        match state {
+            ChunkState::Size(line) | ChunkState::Trailers(line) => {
+                let Some(nl) = bytes.iter().position(|&b| b == b'\n') else {
+                    if line.len() + bytes.len() > MAX_SSE_LINE {
+                        return Err(TorError::ProtocolError("Chunk header too long".into()));
+                    }
+                    line.extend_from_slice(bytes);
+                    return Ok(());
+                };
+                line.extend_from_slice(&bytes[..nl]);
+                bytes = &bytes[nl + 1..];
+                let text = String::from_utf8_lossy(line)
+                    .trim_end_matches('\r')
+                    .to_string();
+
+                *state = match state {
+                    ChunkState::Size(_) => {
+                        let size = text.split(';').next().unwrap_or_default().trim();
+                        let size = u64::from_str_radix(size, 16).map_err(|_| {
+                            TorError::ProtocolError(format!("Bad chunk size {:?}", size))
+                        })?;
+                        if size == 0 {
+                            ChunkState::Trailers(Vec::new())
+                        } else {
+                            ChunkState::Data(size)
+                        }
+                    }
+                    _ if text.is_empty() => ChunkState::Done,
+                    _ => ChunkState::Trailers(Vec::new()),
+                };
+            }
+            ChunkState::Data(remaining) => {
+                let take = (*remaining).min(bytes.len() as u64) as usize;
+                out.extend_from_slice(&bytes[..take]);
+                bytes = &bytes[take..];
+                *remaining -= take as u64;
+                if *remaining == 0 {
+                    *state = ChunkState::DataEnd(2);
+                }
+            }
+            ChunkState::DataEnd(left) => {
+                // Tolerate a bare LF after the chunk data
+                if bytes[0] == b'\r' && *left == 2 {
+                    *left = 1;
+                    bytes = &bytes[1..];
+                } else if bytes[0] == b'\n' {
+                    *state = ChunkState::Size(Vec::new());
+                    bytes = &bytes[1..];
+                } else {
+                    return Err(TorError::ProtocolError("Missing CRLF after chunk".into()));
+                }
+            }
+            ChunkState::Done => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// HTTP/1.1 connection over a Tor stream, with or without TLS
+pub(crate) enum HttpConnection {
+    Plain(TorStream),
+    Tls(Box<TlsTorStream>),
+}
+
+impl HttpConnection {
+    /// Wrap `stream`, doing the TLS handshake for HTTPS
+    pub(crate) async fn open(stream: TorStream, host: &str, is_https: bool) -> Result<Self> {
+        Ok(if is_https {
+            Self::Tls(Box::new(TlsTorStream::new(stream, host).await?))
+        } else {
+            Self::Plain(stream)
+        })
+    }
+
+    pub(crate) async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Self::Plain(stream) => stream.write_all(data).await,
+            Self::Tls(tls) => tls.write_all(data).await,
+        }
+    }
+
+    /// Read whatever has arrived (0 at end of stream)
+    pub(crate) async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read_some(buf).await,
+            Self::Tls(tls) => tls.read(buf).await,
+        }
+    }
+
+    pub(crate) async fn close(&mut self) -> Result<()> {
+        match self {
+            Self::Plain(stream) => stream.close().await,
+            Self::Tls(tls) => tls.close().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Relay cell payload size: the unit bytes arrive in off a circuit
+    const CELL_DATA: usize = 498;
+
+    fn chunked_response(events: &[&str]) -> Vec<u8> {
+        let mut out = b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for e in events {
+            out.extend_from_slice(format!("{:x}\r\n{}\r\n", e.len(), e).as_bytes());
+        }
+        out.extend_from_slice(b"0\r\n\r\n");
+        out
+    }
+
+    #[test]
+    fn test_parser_fields() {
+        let mut parser = SseParser::new();
+        let events = parser
+            .feed(b"\xEF\xBB\xBF: keep-alive\r\nretry: 3000\nid: 7\nevent: delta\ndata: {\"a\":1}\ndata:two\r\n\r\ndata\n\n\nevent: ignored\n\n")
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: "delta".into(),
+                    data: "{\"a\":1}\ntwo".into(),
+                    id: Some("7".into()),
+                    retry: Some(3000),
+                },
+                SseEvent {
+                    event: "message".into(),
+                    data: String::new(),
+                    id: Some("7".into()),
+                    retry: Some(3000),
+                },
+            ]
+        );
+        // An event without a blank line yet is not dispatched
+        assert!(parser.feed(b"data: partial").unwrap().is_empty());
+        assert_eq!(parser.feed(b"\r").unwrap().len(), 0);
+        assert_eq!(parser.feed(b"\r\n").unwrap()[0].data, "partial");
+    }
+
+    #[test]
+    fn test_events_flush_at_cell_boundaries() {
+        let first = "data: {\"delta\":\"Hel\"}\n\n";
+        let second = format!("data: {}\n\n", "x".repeat(700));
+        let response = chunked_response(&[first, &second, "data: [DONE]\n\n"]);
+
+        // Cut the response into cell-sized pieces, as the circuit delivers it
+        let mut stream = SseStream::new();
+        let mut per_cell = Vec::new();
+        for cell in response.chunks(CELL_DATA) {
+            per_cell.push(stream.feed(cell).unwrap());
+        }
+        assert!(stream.is_complete());
+        assert_eq!(stream.status(), Some(200));
+
+        // The first event completes inside the first cell and is returned
+        // by that feed, not held until the response ends
+        assert_eq!(per_cell[0].len(), 1);
+        assert_eq!(per_cell[0][0].data, "{\"delta\":\"Hel\"}");
+        let all: Vec<_> = per_cell.into_iter().flatten().collect();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[1].data.len(), 700);
+        assert_eq!(all[2].data, "[DONE]");
+
+        // Byte-at-a-time delivery yields the same events
+        let mut stream = SseStream::new();
+        let bytewise: Vec<_> = response
+            .iter()
+            .flat_map(|b| stream.feed(std::slice::from_ref(b)).unwrap())
+            .collect();
+        assert_eq!(bytewise, all);
+    }
+
+    #[test]
+    fn test_length_and_error_responses() {
+        let body = "data: one\n\ndata: two\n\n";
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}trailing junk",
+            body.len(),
+            body
+        );
+        let mut stream = SseStream::new();
+        let events = stream.feed(response.as_bytes()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(stream.is_complete());
+
+        let mut stream = SseStream::new();
+        let events = stream
+            .feed(b"HTTP/1.1 429 Too Many Requests\r\nContent-Length: 19\r\n\r\ndata: {\"error\":1}\n\n")
+            .unwrap();
+        assert!(events.is_empty());
+        assert!(!stream.is_success());
+        assert_eq!(stream.error_body(), "data: {\"error\":1}\n\n");
+
+        let mut stream = SseStream::new();
+        let bad = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n";
+        assert!(stream.feed(bad).is_err());
+    }
+}
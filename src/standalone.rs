@@ -0,0 +1,128 @@
+//! Stateless protocol primitives for JavaScript
+//!
+//! Free functions mirroring the `tor-core` C FFI (`tor_parse_cell`,
+//! `tor_create_create2_cell`, ...) but taking and returning `Uint8Array`s
+//! instead of raw pointers. Meant for tooling and test harnesses that need
+//! to build or inspect cells without bootstrapping a [`TorClient`](crate::TorClient).
+//!
+//! ```javascript
+//! const cell = tor_build_create2_js(0x80000001, handshake); // 514 bytes
+//! const { circuit_id, command_name, payload } = tor_parse_cell_js(cell);
+//! const signatures = verify_consensus_js(consensusText, { [v3ident]: signingKeyPem });
+//! ```
+
+use crate::error::{Result, TorError};
+use crate::protocol::{Cell, CellCommand, ConsensusVerifier};
+use js_sys::{Object, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+/// Length of an ntor CREATE2 handshake (ID | B | X)
+pub const NTOR_HANDSHAKE_LEN: usize = 84;
+
+/// Handshake type for ntor in CREATE2
+const HANDSHAKE_TYPE_NTOR: u16 = 0x0002;
+
+/// Serialize a CREATE2 cell carrying an ntor handshake
+///
+/// Payload layout: HTYPE (2) | HLEN (2) | HDATA (84), padded to 509 bytes.
+pub fn build_create2_cell(circuit_id: u32, handshake_data: &[u8]) -> Result<Vec<u8>> {
+    if circuit_id == 0 {
+        return Err(TorError::ProtocolError(
+            "CREATE2 needs a non-zero circuit ID".into(),
+        ));
+    }
+    if handshake_data.len() != NTOR_HANDSHAKE_LEN {
+        return Err(TorError::ProtocolError(format!(
+            "ntor handshake must be {} bytes, got {}",
+            NTOR_HANDSHAKE_LEN,
+            handshake_data.len()
+        )));
+    }
+
+    let mut payload = Vec::with_capacity(4 + NTOR_HANDSHAKE_LEN);
+    payload.extend_from_slice(&HANDSHAKE_TYPE_NTOR.to_be_bytes());
+    payload.extend_from_slice(&(handshake_data.len() as u16).to_be_bytes());
+    payload.extend_from_slice(handshake_data);
+
+    Cell::new(circuit_id, CellCommand::Create2, payload).to_bytes()
+}
+
+/// Parse a fixed-length cell.
+///
+/// Returns `{ circuit_id, command, command_name, payload }` where `payload`
+/// is a 509-byte `Uint8Array`. Throws on short input or unknown commands.
+#[wasm_bindgen]
+pub fn tor_parse_cell_js(data: &[u8]) -> std::result::Result<JsValue, JsValue> {
+    let cell = Cell::from_bytes(data)?;
+
+    let out = Object::new();
+    Reflect::set(&out, &"circuit_id".into(), &cell.circuit_id.into())?;
+    Reflect::set(&out, &"command".into(), &(cell.command as u8).into())?;
+    Reflect::set(
+        &out,
+        &"command_name".into(),
+        &format!("{:?}", cell.command).into(),
+    )?;
+    Reflect::set(
+        &out,
+        &"payload".into(),
+        &Uint8Array::from(cell.payload.as_slice()),
+    )?;
+    Ok(out.into())
+}
+
+/// Build a 514-byte CREATE2 cell from an 84-byte ntor handshake
+#[wasm_bindgen]
+pub fn tor_build_create2_js(
+    circuit_id: u32,
+    handshake_data: &[u8],
+) -> std::result::Result<Uint8Array, JsValue> {
+    let bytes = build_create2_cell(circuit_id, handshake_data)?;
+    Ok(Uint8Array::from(bytes.as_slice()))
+}
+
+/// Verify directory authority signatures on a consensus document.
+///
+/// `signing_keys` maps authority v3idents to their PEM signing keys.
+/// Returns the number of valid authority signatures; throws if there are
+/// too few for the consensus to be trusted.
+#[wasm_bindgen]
+pub fn verify_consensus_js(
+    consensus_text: &str,
+    signing_keys: JsValue,
+) -> std::result::Result<u32, JsValue> {
+    let keys: std::collections::BTreeMap<String, String> =
+        serde_wasm_bindgen::from_value(signing_keys)
+            .map_err(|e| JsValue::from_str(&format!("Invalid signing keys: {}", e)))?;
+    let mut verifier = ConsensusVerifier::new();
+    for (identity, pem) in &keys {
+        verifier.add_signing_key(identity, pem)?;
+    }
+    let count = verifier.verify_consensus(consensus_text)?;
+    Ok(count as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create2_cell_layout() {
+        let handshake: Vec<u8> = (0..NTOR_HANDSHAKE_LEN as u8).collect();
+        let bytes = build_create2_cell(0x8000_0001, &handshake).unwrap();
+        assert_eq!(bytes.len(), Cell::SIZE);
+
+        let cell = Cell::from_bytes(&bytes).unwrap();
+        assert_eq!(cell.circuit_id, 0x8000_0001);
+        assert_eq!(cell.command, CellCommand::Create2);
+        assert_eq!(&cell.payload[..4], &[0x00, 0x02, 0x00, 84]);
+        assert_eq!(&cell.payload[4..88], handshake.as_slice());
+        assert!(cell.payload[88..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_create2_rejects_bad_input() {
+        assert!(build_create2_cell(1, &[0u8; 32]).is_err());
+        assert!(build_create2_cell(0, &[0u8; NTOR_HANDSHAKE_LEN]).is_err());
+    }
+}
@@ -3,8 +3,8 @@
 // This adapter allows Arti to use browser IndexedDB as if it were
 // a filesystem-based state manager.
 
-use super::WasmStorage;
-use crate::error::{Result, TorError};
+use super::{decode_record, encode_record, migrate, ArtiGuardSets, StorageFormat, WasmStorage};
+use crate::error::Result;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -19,7 +19,7 @@ pub struct ArtiStateManager {
 impl ArtiStateManager {
     /// Create a new Arti state manager
     pub async fn new() -> Result<Self> {
-        let storage = Arc::new(WasmStorage::new().await?);
+        let storage = Arc::new(WasmStorage::new(None).await?);
         Ok(Self { storage })
     }
 
@@ -31,7 +31,7 @@ impl ArtiStateManager {
     /// Load state by key
     ///
     /// Generic method that can load any serializable type
-    pub async fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+    pub async fn load<T: Serialize + DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
         log::debug!("Loading state for key: {}", key);
 
         let bytes = match self.storage.get("state", key).await? {
@@ -39,8 +39,18 @@ impl ArtiStateManager {
             None => return Ok(None),
         };
 
-        let value: T = serde_json::from_slice(&bytes)
-            .map_err(|e| TorError::Storage(format!("Failed to deserialize state: {}", e)))?;
+        let (value, format): (T, _) = decode_record(&bytes, "state")?;
+        if format.is_legacy() {
+            migrate(
+                &self.storage,
+                "state",
+                key,
+                &value,
+                StorageFormat::default(),
+                "state",
+            )
+            .await;
+        }
 
         Ok(Some(value))
     }
@@ -51,8 +61,7 @@ impl ArtiStateManager {
     pub async fn store<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
         log::debug!("Storing state for key: {}", key);
 
-        let bytes = serde_json::to_vec(value)
-            .map_err(|e| TorError::Storage(format!("Failed to serialize state: {}", e)))?;
+        let bytes = encode_record(value, StorageFormat::default(), "state")?;
 
         self.storage.set("state", key, &bytes).await?;
         Ok(())
@@ -101,6 +110,19 @@ impl GuardManager {
         self.state.store("guards", guards).await
     }
 
+    /// Export the stored guards as Arti's `guards.json`
+    pub async fn export_arti_json(&self) -> Result<String> {
+        let guards = self.load_guards().await?.unwrap_or_default();
+        ArtiGuardSets::from_guard_set(&guards).to_json()
+    }
+
+    /// Replace the stored guards with those in Arti's `guards.json`
+    pub async fn import_arti_json(&self, json: &str) -> Result<usize> {
+        let guards = ArtiGuardSets::from_json(json)?.to_guard_set(current_timestamp());
+        self.store_guards(&guards).await?;
+        Ok(guards.guards.len())
+    }
+
     /// Add a guard to the set
     pub async fn add_guard(&self, guard: Guard) -> Result<()> {
         let mut guards = self.load_guards().await?.unwrap_or_default();
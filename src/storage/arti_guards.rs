@@ -0,0 +1,368 @@
+// Arti-compatible guard state
+//
+// Arti keeps its guards in `state/guards.json`, written by tor-guardmgr:
+//
+//   { "default":    { "guards": [Guard...], "confirmed": [GuardId...] },
+//     "restricted": { ... } }
+//
+// where each Guard is `{ id: { ed25519?, rsa? }, orports: ["ip:port"],
+// added_at, confirmed_at?, unlisted_since?, disabled?, added_by? }` with
+// RFC 3339 times and the RSA identity as lowercase hex. Fields we don't
+// model are carried through untouched, so a file imported and exported
+// again keeps everything Arti wrote.
+//
+// Only the `default` sample maps onto our guard set; the others are kept
+// as-is for the trip back.
+use super::arti_adapter::{Guard, GuardSet};
+use crate::clock_skew::{format_utc, parse_utc};
+use crate::error::{Result, TorError};
+use crate::guards::{GuardState, GUARD_LIFETIME_SECS, MAX_GUARDS};
+use crate::protocol::Relay;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Arti's `guards.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArtiGuardSets {
+    #[serde(default)]
+    pub default: ArtiGuardSample,
+    #[serde(default)]
+    pub restricted: ArtiGuardSample,
+    #[serde(flatten)]
+    pub remaining: Map<String, Value>,
+}
+
+/// One guard sample, in the order Arti sampled it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArtiGuardSample {
+    #[serde(default)]
+    pub guards: Vec<ArtiGuard>,
+    /// Guards that have been used successfully, in confirmation order
+    #[serde(default)]
+    pub confirmed: Vec<ArtiGuardId>,
+    #[serde(flatten)]
+    pub remaining: Map<String, Value>,
+}
+
+/// A guard's relay identities
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtiGuardId {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ed25519: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsa: Option<String>,
+}
+
+/// One guard record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtiGuard {
+    pub id: ArtiGuardId,
+    #[serde(default)]
+    pub orports: Vec<String>,
+    pub added_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_by: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmed_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unlisted_since: Option<String>,
+    #[serde(flatten)]
+    pub remaining: Map<String, Value>,
+}
+
+impl ArtiGuard {
+    fn new(fingerprint: &str, orports: Vec<String>, added_at: u64) -> Option<Self> {
+        Some(Self {
+            id: ArtiGuardId {
+                ed25519: None,
+                rsa: Some(rsa_identity(fingerprint)?),
+            },
+            orports,
+            added_at: format_utc(added_at),
+            added_by: Some(serde_json::json!({
+                "crate": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            })),
+            disabled: None,
+            confirmed_at: None,
+            unlisted_since: None,
+            remaining: Map::new(),
+        })
+    }
+}
+
+/// Normalize a relay fingerprint (hex, optionally `$`-prefixed, or the
+/// consensus's unpadded base64) to Arti's lowercase hex RSA identity
+pub fn rsa_identity(fingerprint: &str) -> Option<String> {
+    let fingerprint = fingerprint.trim_start_matches('$');
+    if fingerprint.len() == 40 && fingerprint.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Some(fingerprint.to_ascii_lowercase());
+    }
+    let bytes = base64::engine::general_purpose::STANDARD_NO_PAD
+        .decode(fingerprint.trim_end_matches('='))
+        .ok()?;
+    (bytes.len() == 20).then(|| hex::encode(bytes))
+}
+
+impl ArtiGuardSets {
+    /// Parse Arti's `guards.json`
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| TorError::ParseError(format!("Invalid Arti guard state: {}", e)))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| TorError::Storage(format!("Failed to serialize Arti guard state: {}", e)))
+    }
+
+    /// Arti state holding our guards as its confirmed default sample, in
+    /// preference order; OR ports are filled in from `relays` where known
+    pub fn from_guard_state(state: &GuardState, relays: &[Relay]) -> Self {
+        let guards: Vec<ArtiGuard> = state
+            .guards
+            .iter()
+            .filter_map(|fp| {
+                let orports = relays
+                    .iter()
+                    .find(|r| &r.fingerprint == fp)
+                    .map(|r| vec![std::net::SocketAddr::new(r.address, r.or_port).to_string()])
+                    .unwrap_or_default();
+                let mut guard = ArtiGuard::new(fp, orports, state.selected_at)?;
+                guard.confirmed_at = Some(guard.added_at.clone());
+                Some(guard)
+            })
+            .collect();
+        Self {
+            default: ArtiGuardSample {
+                confirmed: guards.iter().map(|g| g.id.clone()).collect(),
+                guards,
+                remaining: Map::new(),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Our guard state from Arti's default sample
+    ///
+    /// Confirmed guards come first, in confirmation order, then the rest
+    /// of the sample; disabled guards and guards without an RSA identity
+    /// are skipped. Fingerprints take the form `relays` uses when a relay
+    /// matches, uppercase hex otherwise. The rotation clock starts at the
+    /// earliest `added_at`, as in Arti.
+    pub fn to_guard_state(&self, relays: &[Relay], now: u64) -> GuardState {
+        let sample = &self.default;
+        let usable: Vec<&ArtiGuard> = sample
+            .guards
+            .iter()
+            .filter(|g| g.disabled.is_none() && g.id.rsa.is_some())
+            .collect();
+        let confirmed = sample
+            .confirmed
+            .iter()
+            .filter_map(|id| usable.iter().find(|g| &g.id == id).copied());
+        let mut ordered: Vec<&ArtiGuard> = Vec::new();
+        for guard in confirmed.chain(usable.iter().copied()) {
+            if !ordered.iter().any(|g| g.id == guard.id) {
+                ordered.push(guard);
+            }
+        }
+        ordered.truncate(MAX_GUARDS);
+
+        let selected_at = ordered
+            .iter()
+            .filter_map(|g| parse_utc(&g.added_at))
+            .min()
+            .unwrap_or(now);
+        let guards = ordered
+            .iter()
+            .filter_map(|g| {
+                let rsa = rsa_identity(g.id.rsa.as_deref()?)?;
+                Some(
+                    relays
+                        .iter()
+                        .find(|r| rsa_identity(&r.fingerprint).as_deref() == Some(rsa.as_str()))
+                        .map(|r| r.fingerprint.clone())
+                        .unwrap_or_else(|| rsa.to_ascii_uppercase()),
+                )
+            })
+            .collect();
+
+        GuardState {
+            guards,
+            selected_at,
+            rotate_after: selected_at + GUARD_LIFETIME_SECS,
+            ..GuardState::default()
+        }
+    }
+
+    /// Arti state for an adapter [`GuardSet`]; unreachable guards are
+    /// marked disabled
+    pub fn from_guard_set(set: &GuardSet) -> Self {
+        let guards: Vec<ArtiGuard> = set
+            .guards
+            .iter()
+            .filter_map(|g| {
+                let orports = match g.address.parse::<std::net::IpAddr>() {
+                    Ok(ip) => vec![std::net::SocketAddr::new(ip, g.port).to_string()],
+                    Err(_) => Vec::new(),
+                };
+                let mut guard = ArtiGuard::new(&g.fingerprint, orports, g.added_at)?;
+                if g.use_count > 0 {
+                    guard.confirmed_at = Some(format_utc(g.last_used.max(g.added_at)));
+                }
+                if g.unreachable {
+                    guard.disabled = Some(serde_json::json!({ "type": "TooManyFailures" }));
+                }
+                Some(guard)
+            })
+            .collect();
+        Self {
+            default: ArtiGuardSample {
+                confirmed: guards
+                    .iter()
+                    .filter(|g| g.confirmed_at.is_some())
+                    .map(|g| g.id.clone())
+                    .collect(),
+                guards,
+                remaining: Map::new(),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Adapter [`GuardSet`] from Arti's default sample
+    pub fn to_guard_set(&self, now: u64) -> GuardSet {
+        let guards = self
+            .default
+            .guards
+            .iter()
+            .filter_map(|g| {
+                let fingerprint = rsa_identity(g.id.rsa.as_deref()?)?.to_ascii_uppercase();
+                let orport: Option<std::net::SocketAddr> =
+                    g.orports.iter().find_map(|a| a.parse().ok());
+                let added_at = parse_utc(&g.added_at).unwrap_or(now);
+                Some(Guard {
+                    fingerprint,
+                    nickname: String::new(),
+                    address: orport.map(|a| a.ip().to_string()).unwrap_or_default(),
+                    port: orport.map(|a| a.port()).unwrap_or(0),
+                    added_at,
+                    last_used: g
+                        .confirmed_at
+                        .as_deref()
+                        .and_then(parse_utc)
+                        .unwrap_or(added_at),
+                    use_count: u64::from(self.default.confirmed.contains(&g.id)),
+                    failure_count: 0,
+                    last_failed: None,
+                    unreachable: g.disabled.is_some(),
+                })
+            })
+            .collect();
+        GuardSet {
+            guards,
+            last_modified: now,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTI_STATE: &str = r#"{
+        "default": {
+            "guards": [
+                {
+                    "id": { "ed25519": "x0vxk2Rb+7ZPpIoQEXjBBMajAPXbOQXRKqGd3aB3ESc", "rsa": "5a2a51ee4a9a5e4ab2d4f2fdd1c49ba6a5de4c47" },
+                    "orports": ["198.51.100.7:9001"],
+                    "added_at": "2026-09-01T10:00:00Z",
+                    "added_by": { "crate": "tor-guardmgr", "version": "0.36.0" },
+                    "confirmed_at": "2026-09-02T10:00:00Z",
+                    "pk_params": { "kept": true }
+                },
+                {
+                    "id": { "rsa": "$1111111111111111111111111111111111111111" },
+                    "orports": [],
+                    "added_at": "2026-08-20T10:00:00Z",
+                    "disabled": { "type": "TooManyIndeterminateFailures" }
+                },
+                {
+                    "id": { "rsa": "2222222222222222222222222222222222222222" },
+                    "orports": ["[2001:db8::2]:443"],
+                    "added_at": "2026-09-03T10:00:00Z"
+                }
+            ],
+            "confirmed": [
+                { "rsa": "2222222222222222222222222222222222222222" },
+                { "ed25519": "x0vxk2Rb+7ZPpIoQEXjBBMajAPXbOQXRKqGd3aB3ESc", "rsa": "5a2a51ee4a9a5e4ab2d4f2fdd1c49ba6a5de4c47" }
+            ]
+        },
+        "restricted": { "guards": [], "confirmed": [] },
+        "bridges": { "guards": [], "confirmed": [] }
+    }"#;
+
+    #[test]
+    fn test_import_arti_guards() {
+        let arti = ArtiGuardSets::from_json(ARTI_STATE).unwrap();
+        let state = arti.to_guard_state(&[], 0);
+
+        // Confirmed order wins; the disabled guard is dropped
+        assert_eq!(
+            state.guards,
+            [
+                "2222222222222222222222222222222222222222",
+                "5A2A51EE4A9A5E4AB2D4F2FDD1C49BA6A5DE4C47",
+            ]
+        );
+        assert_eq!(
+            state.selected_at,
+            parse_utc("2026-09-01T10:00:00Z").unwrap()
+        );
+        assert_eq!(state.rotate_after, state.selected_at + GUARD_LIFETIME_SECS);
+
+        // Unknown fields and samples survive a round trip
+        let again: Value = serde_json::from_str(&arti.to_json().unwrap()).unwrap();
+        assert_eq!(again["default"]["guards"][0]["pk_params"]["kept"], true);
+        assert!(again["bridges"].is_object());
+
+        let set = arti.to_guard_set(0);
+        assert_eq!(set.guards[2].address, "2001:db8::2");
+        assert_eq!(set.guards[2].port, 443);
+        assert!(set.guards[1].unreachable);
+    }
+
+    #[test]
+    fn test_export_round_trips_through_arti_format() {
+        let mut state = GuardState::new();
+        state.guards = vec![
+            // Consensus-style base64 and bridge-style hex fingerprints
+            "WipR7kqaXkqy1PL90cSbpqXeTEc".to_string(),
+            "2222222222222222222222222222222222222222".to_string(),
+        ];
+        state.selected_at = 1_790_000_000;
+
+        let arti = ArtiGuardSets::from_guard_state(&state, &[]);
+        assert_eq!(
+            arti.default.guards[0].id.rsa.as_deref(),
+            Some("5a2a51ee4a9a5e4ab2d4f2fdd1c49ba6a5de4c47")
+        );
+        assert_eq!(arti.default.confirmed.len(), 2);
+
+        let json = arti.to_json().unwrap();
+        let back = ArtiGuardSets::from_json(&json)
+            .unwrap()
+            .to_guard_state(&[], 0);
+        assert_eq!(back.guards[1], state.guards[1]);
+        assert_eq!(back.selected_at, state.selected_at);
+        assert_eq!(
+            rsa_identity(&back.guards[0]),
+            rsa_identity(&state.guards[0])
+        );
+    }
+}
@@ -52,6 +52,21 @@ async fn request_to_future(request: &IdbRequest) -> std::result::Result<JsValue,
     JsFuture::from(promise).await
 }
 
+/// Name of the shared IndexedDB database
+pub const DB_NAME: &str = "tor-storage";
+
+/// Name of the database for `storage_namespace`: the shared one when
+/// there is none, otherwise `tor-storage.<namespace>`
+pub fn db_name(storage_namespace: Option<&str>) -> String {
+    match storage_namespace {
+        Some(ns) if !ns.is_empty() => format!("{}.{}", DB_NAME, ns),
+        _ => DB_NAME.to_string(),
+    }
+}
+
+/// Object stores created in every database
+const OBJECT_STORES: [&str; 5] = ["consensus", "relays", "circuits", "cache", "state"];
+
 /// WASM-compatible persistent storage using IndexedDB
 ///
 /// Stores Tor consensus, relay database, and circuit state
@@ -71,8 +86,53 @@ impl WasmStorage {
     /// - "circuits": Circuit pool state
     /// - "cache": General purpose cache
     /// - "state": Client state (guards, etc.)
-    pub async fn new() -> Result<Self> {
-        log::info!("Initializing IndexedDB storage...");
+    ///
+    /// With a `storage_namespace` the client gets a database of its own (see
+    /// [`db_name`]), so several clients on one origin don't overwrite each
+    /// other's records. The first time a namespaced database is created, the
+    /// records in the shared database are moved into it: the data of a
+    /// client that ran unnamespaced carries over, but only to one namespace.
+    pub async fn new(storage_namespace: Option<&str>) -> Result<Self> {
+        let name = db_name(storage_namespace);
+        let (storage, created) = Self::open_tracking_creation(&name).await?;
+        if created && name != DB_NAME {
+            let moved = storage.migrate_from(DB_NAME).await?;
+            if moved > 0 {
+                log::info!("Moved {} unnamespaced records into {}", moved, name);
+            }
+        }
+        Ok(storage)
+    }
+
+    /// Open (creating if needed) the database `name` rather than the
+    /// shared one
+    pub async fn open(name: &str) -> Result<Self> {
+        Ok(Self::open_tracking_creation(name).await?.0)
+    }
+
+    /// Move every record of the database `legacy` into this one, leaving
+    /// `legacy` empty. Returns the number of records moved.
+    pub async fn migrate_from(&self, legacy: &str) -> Result<usize> {
+        let legacy = Self::open(legacy).await?;
+        let mut moved = 0;
+        for store_name in OBJECT_STORES {
+            for key in legacy.list_keys(store_name).await? {
+                if let Some(value) = legacy.get(store_name, &key).await? {
+                    self.set(store_name, &key, &value).await?;
+                    moved += 1;
+                }
+            }
+        }
+        if moved > 0 {
+            legacy.clear_all().await?;
+        }
+        legacy.db.close();
+        Ok(moved)
+    }
+
+    /// Open `name`, also reporting whether this call created it
+    async fn open_tracking_creation(name: &str) -> Result<(Self, bool)> {
+        log::info!("Initializing IndexedDB storage {}...", name);
 
         let window =
             web_sys::window().ok_or_else(|| TorError::Storage("No window object".into()))?;
@@ -84,12 +144,15 @@ impl WasmStorage {
 
         // Open database (version 1)
         let open_request = idb
-            .open_with_u32("tor-storage", 1)
+            .open_with_u32(name, 1)
             .map_err(|e| TorError::Storage(format!("Failed to open DB: {:?}", e)))?;
 
         // Handle database upgrade (first time or version change)
+        let created = std::rc::Rc::new(std::cell::Cell::new(false));
+        let created_flag = std::rc::Rc::clone(&created);
         let on_upgrade = Closure::wrap(Box::new(move |event: IdbVersionChangeEvent| {
             log::info!("Upgrading IndexedDB schema...");
+            created_flag.set(event.old_version() == 0.0);
 
             let target = event.target().expect("Event should have target");
             let request = target
@@ -102,8 +165,7 @@ impl WasmStorage {
                 .expect("Result should be IdbDatabase");
 
             // Create object stores
-            let stores = vec!["consensus", "relays", "circuits", "cache", "state"];
-            for store_name in stores {
+            for store_name in OBJECT_STORES {
                 if !db.object_store_names().contains(store_name) {
                     db.create_object_store(store_name)
                         .unwrap_or_else(|_| panic!("Failed to create {} store", store_name));
@@ -125,7 +187,7 @@ impl WasmStorage {
             .map_err(|e| TorError::Storage(format!("Invalid DB object: {:?}", e)))?;
 
         log::info!("IndexedDB initialized successfully");
-        Ok(WasmStorage { db })
+        Ok((WasmStorage { db }, created.get()))
     }
 
     /// Store data in a specific object store
@@ -308,6 +370,14 @@ impl WasmStorage {
         Ok(())
     }
 
+    /// Clear every object store
+    pub async fn clear_all(&self) -> Result<()> {
+        for store_name in OBJECT_STORES {
+            self.clear(store_name).await?;
+        }
+        Ok(())
+    }
+
     /// Get storage statistics
     ///
     /// Returns the number of keys in each object store
@@ -361,16 +431,43 @@ mod tests {
 
     wasm_bindgen_test_configure!(run_in_browser);
 
+    #[test]
+    fn test_db_name_per_namespace() {
+        assert_eq!(db_name(None), DB_NAME);
+        assert_eq!(db_name(Some("")), DB_NAME);
+        assert_eq!(db_name(Some("work")), "tor-storage.work");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_new_namespace_takes_over_unnamespaced_data() {
+        let shared = WasmStorage::new(None).await.unwrap();
+        shared.set("state", "guards", b"legacy").await.unwrap();
+
+        let namespace = format!("migrate-{}", js_sys::Date::now() as u64);
+        let first = WasmStorage::new(Some(&namespace)).await.unwrap();
+        assert_eq!(
+            first.get("state", "guards").await.unwrap(),
+            Some(b"legacy".to_vec())
+        );
+        assert_eq!(shared.get("state", "guards").await.unwrap(), None);
+
+        // Only the first namespace inherits it
+        let second = WasmStorage::new(Some(&format!("{}-2", namespace)))
+            .await
+            .unwrap();
+        assert_eq!(second.get("state", "guards").await.unwrap(), None);
+    }
+
     #[wasm_bindgen_test]
     async fn test_storage_init() {
-        let storage = WasmStorage::new().await.unwrap();
+        let storage = WasmStorage::new(None).await.unwrap();
         let stats = storage.get_stats().await.unwrap();
         assert_eq!(stats.total_entries(), 0);
     }
 
     #[wasm_bindgen_test]
     async fn test_storage_set_get() {
-        let storage = WasmStorage::new().await.unwrap();
+        let storage = WasmStorage::new(None).await.unwrap();
 
         let data = b"Hello, Tor!";
         storage.set("cache", "test_key", data).await.unwrap();
@@ -381,7 +478,7 @@ mod tests {
 
     #[wasm_bindgen_test]
     async fn test_storage_delete() {
-        let storage = WasmStorage::new().await.unwrap();
+        let storage = WasmStorage::new(None).await.unwrap();
 
         storage.set("cache", "delete_me", b"data").await.unwrap();
         storage.delete("cache", "delete_me").await.unwrap();
@@ -392,7 +489,7 @@ mod tests {
 
     #[wasm_bindgen_test]
     async fn test_storage_list_keys() {
-        let storage = WasmStorage::new().await.unwrap();
+        let storage = WasmStorage::new(None).await.unwrap();
 
         storage.set("cache", "key1", b"data1").await.unwrap();
         storage.set("cache", "key2", b"data2").await.unwrap();
@@ -406,7 +503,7 @@ mod tests {
 
     #[wasm_bindgen_test]
     async fn test_storage_clear() {
-        let storage = WasmStorage::new().await.unwrap();
+        let storage = WasmStorage::new(None).await.unwrap();
 
         storage.set("cache", "key1", b"data1").await.unwrap();
         storage.set("cache", "key2", b"data2").await.unwrap();
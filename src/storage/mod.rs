@@ -5,20 +5,28 @@
 // - Relay descriptors and metadata
 // - Circuit pool state
 // - Client state (guards, path selection, etc.)
+//
+// Guard state can be exchanged with native Arti in its `guards.json` format
+// (see `arti_guards`).
 
 mod arti_adapter;
+mod arti_guards;
 mod circuit_state;
 mod indexeddb;
 mod serde_helpers;
 
 pub use arti_adapter::{ArtiStateManager, Guard, GuardManager, GuardParams, GuardSet};
+pub use arti_guards::{rsa_identity, ArtiGuard, ArtiGuardId, ArtiGuardSample, ArtiGuardSets};
 pub use circuit_state::{CircuitPool, CircuitStateManager, CircuitStats, PoolConfig};
-pub use indexeddb::{StorageStats, WasmStorage};
+pub use indexeddb::{db_name, StorageStats, WasmStorage, DB_NAME};
 pub use serde_helpers::{
-    CircuitData, CircuitState, ClientState, ConsensusData, RelayData, RelayFlags, StorageSerializer,
+    decode_record, encode_record, CircuitData, CircuitState, ClientState, ConsensusData,
+    RecordFormat, RelayData, RelayFlags, StorageFormat, StorageSerializer, BINARY_FORMAT_VERSION,
 };
 
 use crate::error::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::sync::Arc;
 
 /// High-level storage manager for Tor data
@@ -33,7 +41,7 @@ pub struct TorStorageManager {
 impl TorStorageManager {
     /// Create a new storage manager
     pub async fn new() -> Result<Self> {
-        let storage = Arc::new(WasmStorage::new().await?);
+        let storage = Arc::new(WasmStorage::new(None).await?);
         let serializer = StorageSerializer::new();
 
         Ok(Self {
@@ -42,6 +50,33 @@ impl TorStorageManager {
         })
     }
 
+    /// Load and decode a record, rewriting legacy JSON in the current format
+    async fn load_record<T: Serialize + DeserializeOwned>(
+        &self,
+        store: &str,
+        key: &str,
+        what: &str,
+    ) -> Result<Option<T>> {
+        let bytes = match self.storage.get(store, key).await? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+
+        let (value, format) = decode_record(&bytes, what)?;
+        if format.is_legacy() && self.serializer.format() != StorageFormat::Json {
+            migrate(
+                &self.storage,
+                store,
+                key,
+                &value,
+                self.serializer.format(),
+                what,
+            )
+            .await;
+        }
+        Ok(Some(value))
+    }
+
     /// Store Tor directory consensus
     pub async fn store_consensus(&self, consensus: &ConsensusData) -> Result<()> {
         log::info!("Storing consensus with {} relays", consensus.relay_count());
@@ -62,12 +97,11 @@ impl TorStorageManager {
     pub async fn load_consensus(&self) -> Result<Option<ConsensusData>> {
         log::debug!("Loading consensus from storage");
 
-        let bytes = match self.storage.get("consensus", "latest").await? {
-            Some(b) => b,
-            None => return Ok(None),
-        };
-
-        let consensus = self.serializer.deserialize_consensus(&bytes)?;
+        let consensus: ConsensusData =
+            match self.load_record("consensus", "latest", "consensus").await? {
+                Some(c) => c,
+                None => return Ok(None),
+            };
 
         // Check if consensus is still fresh (max 3 hours old)
         let now = js_sys::Date::now() / 1000.0;
@@ -91,12 +125,7 @@ impl TorStorageManager {
 
     /// Load relay descriptor
     pub async fn load_relay(&self, fingerprint: &str) -> Result<Option<RelayData>> {
-        let bytes = match self.storage.get("relays", fingerprint).await? {
-            Some(b) => b,
-            None => return Ok(None),
-        };
-
-        Ok(Some(self.serializer.deserialize_relay(&bytes)?))
+        self.load_record("relays", fingerprint, "relay").await
     }
 
     /// Store multiple relays (batch operation)
@@ -136,12 +165,7 @@ impl TorStorageManager {
     /// Load circuit state
     pub async fn load_circuit(&self, circuit_id: u32) -> Result<Option<CircuitData>> {
         let key = format!("circuit_{}", circuit_id);
-        let bytes = match self.storage.get("circuits", &key).await? {
-            Some(b) => b,
-            None => return Ok(None),
-        };
-
-        Ok(Some(self.serializer.deserialize_circuit(&bytes)?))
+        self.load_record("circuits", &key, "circuit").await
     }
 
     /// Delete circuit (when closed)
@@ -160,12 +184,7 @@ impl TorStorageManager {
 
     /// Load client state
     pub async fn load_client_state(&self) -> Result<Option<ClientState>> {
-        let bytes = match self.storage.get("state", "client").await? {
-            Some(b) => b,
-            None => return Ok(None),
-        };
-
-        Ok(Some(self.serializer.deserialize_client_state(&bytes)?))
+        self.load_record("state", "client", "client state").await
     }
 
     /// Get storage statistics
@@ -176,14 +195,29 @@ impl TorStorageManager {
     /// Clear all Tor data (useful for testing or reset)
     pub async fn clear_all(&self) -> Result<()> {
         log::warn!("Clearing ALL Tor storage data");
+        self.storage.clear_all().await
+    }
+}
 
-        self.storage.clear("consensus").await?;
-        self.storage.clear("relays").await?;
-        self.storage.clear("circuits").await?;
-        self.storage.clear("cache").await?;
-        self.storage.clear("state").await?;
-
-        Ok(())
+/// Rewrite a record read as legacy JSON in `format`
+///
+/// Failure only costs the speedup on the next load, so it is logged rather
+/// than returned.
+pub(crate) async fn migrate<T: Serialize>(
+    storage: &WasmStorage,
+    store: &str,
+    key: &str,
+    value: &T,
+    format: StorageFormat,
+    what: &str,
+) {
+    let result = match encode_record(value, format, what) {
+        Ok(bytes) => storage.set(store, key, &bytes).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => log::info!("💾 Migrated {} {}/{} from JSON", what, store, key),
+        Err(e) => log::warn!("⚠️  Could not migrate {} {}/{}: {}", what, store, key, e),
     }
 }
 
@@ -1,7 +1,92 @@
 // Serialization helpers for Tor data structures
+//
+// Records are written as CBOR behind a short versioned header:
+//
+//   b"TWC" | format version (u8) | CBOR value
+//
+// Records written before the header existed are JSON. Readers accept both;
+// `decode_record` reports which one it found so callers can rewrite legacy
+// JSON records in the binary format.
 use crate::error::{Result, TorError};
+use crate::memory::{self, Subsystem};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+/// Header marking a binary record
+const BINARY_MAGIC: &[u8; 3] = b"TWC";
+
+/// Current binary format version
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+/// Encoding used for new records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageFormat {
+    /// Plain JSON, as written by older releases
+    Json,
+    /// Versioned CBOR
+    #[default]
+    Cbor,
+}
+
+/// Encoding a stored record was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// Pre-versioning JSON; should be rewritten
+    LegacyJson,
+    /// Binary, at this format version
+    Binary(u8),
+}
+
+impl RecordFormat {
+    pub fn is_legacy(self) -> bool {
+        self == RecordFormat::LegacyJson
+    }
+}
+
+/// Serialize `value` in `format`, refusing if already over the memory budget
+pub fn encode_record<T: Serialize>(
+    value: &T,
+    format: StorageFormat,
+    what: &str,
+) -> Result<Vec<u8>> {
+    memory::check(Subsystem::Storage, 0)?;
+    let _memory = memory::scope(Subsystem::Storage);
+    let fail = |e: &dyn std::fmt::Display| {
+        TorError::Storage(format!("Failed to serialize {}: {}", what, e))
+    };
+    match format {
+        StorageFormat::Json => serde_json::to_vec(value).map_err(|e| fail(&e)),
+        StorageFormat::Cbor => {
+            let mut bytes = Vec::with_capacity(256);
+            bytes.extend_from_slice(BINARY_MAGIC);
+            bytes.push(BINARY_FORMAT_VERSION);
+            ciborium::into_writer(value, &mut bytes).map_err(|e| fail(&e))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Deserialize a record written in either format
+pub fn decode_record<T: DeserializeOwned>(bytes: &[u8], what: &str) -> Result<(T, RecordFormat)> {
+    let fail = |e: &dyn std::fmt::Display| {
+        TorError::Storage(format!("Failed to deserialize {}: {}", what, e))
+    };
+    match bytes.strip_prefix(BINARY_MAGIC) {
+        Some([version, body @ ..]) => {
+            if *version == 0 || *version > BINARY_FORMAT_VERSION {
+                return Err(fail(&format!("unsupported format version {}", version)));
+            }
+            let value = ciborium::from_reader(body).map_err(|e| fail(&e))?;
+            Ok((value, RecordFormat::Binary(*version)))
+        }
+        Some([]) => Err(fail(&"truncated header")),
+        None => {
+            let value = serde_json::from_slice(bytes).map_err(|e| fail(&e))?;
+            Ok((value, RecordFormat::LegacyJson))
+        }
+    }
+}
+
 /// Tor directory consensus data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConsensusData {
@@ -141,7 +226,11 @@ pub struct ClientPreferences {
 }
 
 /// Storage serializer/deserializer
-pub struct StorageSerializer;
+///
+/// Writes in its [`StorageFormat`] (CBOR by default) and reads either.
+pub struct StorageSerializer {
+    format: StorageFormat,
+}
 
 impl Default for StorageSerializer {
     fn default() -> Self {
@@ -151,55 +240,59 @@ impl Default for StorageSerializer {
 
 impl StorageSerializer {
     pub fn new() -> Self {
-        Self
+        Self::with_format(StorageFormat::default())
+    }
+
+    pub fn with_format(format: StorageFormat) -> Self {
+        Self { format }
+    }
+
+    pub fn format(&self) -> StorageFormat {
+        self.format
+    }
+
+    fn encode<T: Serialize>(&self, value: &T, what: &str) -> Result<Vec<u8>> {
+        encode_record(value, self.format, what)
     }
 
     /// Serialize consensus data to bytes
     pub fn serialize_consensus(&self, consensus: &ConsensusData) -> Result<Vec<u8>> {
-        serde_json::to_vec(consensus)
-            .map_err(|e| TorError::Storage(format!("Failed to serialize consensus: {}", e)))
+        self.encode(consensus, "consensus")
     }
 
     /// Deserialize consensus data from bytes
     pub fn deserialize_consensus(&self, bytes: &[u8]) -> Result<ConsensusData> {
-        serde_json::from_slice(bytes)
-            .map_err(|e| TorError::Storage(format!("Failed to deserialize consensus: {}", e)))
+        decode_record(bytes, "consensus").map(|(value, _)| value)
     }
 
     /// Serialize relay data to bytes
     pub fn serialize_relay(&self, relay: &RelayData) -> Result<Vec<u8>> {
-        serde_json::to_vec(relay)
-            .map_err(|e| TorError::Storage(format!("Failed to serialize relay: {}", e)))
+        self.encode(relay, "relay")
     }
 
     /// Deserialize relay data from bytes
     pub fn deserialize_relay(&self, bytes: &[u8]) -> Result<RelayData> {
-        serde_json::from_slice(bytes)
-            .map_err(|e| TorError::Storage(format!("Failed to deserialize relay: {}", e)))
+        decode_record(bytes, "relay").map(|(value, _)| value)
     }
 
     /// Serialize circuit data to bytes
     pub fn serialize_circuit(&self, circuit: &CircuitData) -> Result<Vec<u8>> {
-        serde_json::to_vec(circuit)
-            .map_err(|e| TorError::Storage(format!("Failed to serialize circuit: {}", e)))
+        self.encode(circuit, "circuit")
     }
 
     /// Deserialize circuit data from bytes
     pub fn deserialize_circuit(&self, bytes: &[u8]) -> Result<CircuitData> {
-        serde_json::from_slice(bytes)
-            .map_err(|e| TorError::Storage(format!("Failed to deserialize circuit: {}", e)))
+        decode_record(bytes, "circuit").map(|(value, _)| value)
     }
 
     /// Serialize client state to bytes
     pub fn serialize_client_state(&self, state: &ClientState) -> Result<Vec<u8>> {
-        serde_json::to_vec(state)
-            .map_err(|e| TorError::Storage(format!("Failed to serialize client state: {}", e)))
+        self.encode(state, "client state")
     }
 
     /// Deserialize client state from bytes
     pub fn deserialize_client_state(&self, bytes: &[u8]) -> Result<ClientState> {
-        serde_json::from_slice(bytes)
-            .map_err(|e| TorError::Storage(format!("Failed to deserialize client state: {}", e)))
+        decode_record(bytes, "client state").map(|(value, _)| value)
     }
 }
 
@@ -256,6 +349,39 @@ mod tests {
         assert!(deserialized.flags.is_guard());
     }
 
+    #[test]
+    fn test_binary_format_and_json_migration() {
+        let state = ClientState {
+            guards: vec!["AAAA".to_string(), "BBBB".to_string()],
+            bootstrap_complete: true,
+            last_consensus_fetch: 1_700_000_000,
+            preferences: ClientPreferences::default(),
+        };
+
+        let binary = StorageSerializer::new()
+            .serialize_client_state(&state)
+            .unwrap();
+        let json = StorageSerializer::with_format(StorageFormat::Json)
+            .serialize_client_state(&state)
+            .unwrap();
+        assert!(binary.starts_with(BINARY_MAGIC));
+        assert!(binary.len() < json.len());
+
+        // Both read back, and the old JSON is flagged for rewriting
+        let (from_binary, format) = decode_record::<ClientState>(&binary, "state").unwrap();
+        assert_eq!(format, RecordFormat::Binary(BINARY_FORMAT_VERSION));
+        assert_eq!(from_binary.guards, state.guards);
+        let (from_json, format) = decode_record::<ClientState>(&json, "state").unwrap();
+        assert!(format.is_legacy());
+        assert_eq!(from_json.last_consensus_fetch, state.last_consensus_fetch);
+
+        // A record from a newer release is refused rather than misread
+        let mut future = binary.clone();
+        future[BINARY_MAGIC.len()] = BINARY_FORMAT_VERSION + 1;
+        assert!(decode_record::<ClientState>(&future, "state").is_err());
+        assert!(decode_record::<ClientState>(b"TWC", "state").is_err());
+    }
+
     #[test]
     fn test_relay_flags() {
         let mut flags = RelayFlags::default();
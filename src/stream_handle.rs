@@ -0,0 +1,130 @@
+//! Raw duplex Tor streams for JavaScript
+//!
+//! [`TorStreamHandle`] is what `TorClient::connect()` returns: a stream to
+//! `host:port` on a circuit of its own (or on an attached circuit from
+//! `build_custom_circuit()`), run by the cooperative scheduler, with
+//! byte-level `write()` / `read()` / `close()` so JS can speak any TCP
+//! protocol over Tor. Nothing is added to the bytes, not even TLS.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::cooperative::{CooperativeCircuit, CooperativeStream};
+use crate::error::TorError;
+use crate::CustomCircuits;
+
+/// Bytes returned per `read()` at most (one RELAY_DATA cell carries less)
+const READ_CHUNK: usize = 4096;
+
+/// A connected Tor stream
+///
+/// Reads and writes take turns: each call finishes before the next starts.
+/// Poll with a short `read()` timeout to interleave writes while waiting
+/// for data. A stream neither read from nor written to for two minutes is
+/// reaped by the scheduler.
+#[wasm_bindgen]
+pub struct TorStreamHandle {
+    scheduler: Rc<RefCell<CooperativeCircuit>>,
+    stream: CooperativeStream,
+    circuit_id: u32,
+    target: String,
+    /// Where an attached circuit goes back to on `close()`
+    attached: Option<CustomCircuits>,
+}
+
+impl TorStreamHandle {
+    /// Handle for `stream`, opened on the circuit `scheduler` runs
+    pub fn new(
+        scheduler: Rc<RefCell<CooperativeCircuit>>,
+        stream: CooperativeStream,
+        circuit_id: u32,
+        target: String,
+    ) -> Self {
+        Self {
+            scheduler,
+            stream,
+            circuit_id,
+            target,
+            attached: None,
+        }
+    }
+
+    /// Hand the circuit back to `registry` on `close()` instead of
+    /// destroying it
+    pub fn returning_to(mut self, registry: CustomCircuits) -> Self {
+        self.attached = Some(registry);
+        self
+    }
+}
+
+#[wasm_bindgen]
+impl TorStreamHandle {
+    /// ID of the circuit carrying the stream
+    pub fn circuit_id(&self) -> u32 {
+        self.circuit_id
+    }
+
+    /// `host:port` the stream connects to
+    pub fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    /// Address the exit connected to, if it reported one
+    pub fn connected_address(&self) -> Option<String> {
+        self.stream
+            .connected_address()
+            .map(|answer| answer.address.to_string())
+    }
+
+    /// Whether the stream is still open
+    pub fn is_open(&self) -> bool {
+        !self.stream.is_closed()
+    }
+
+    /// Send bytes to the destination
+    pub async fn write(&mut self, data: Vec<u8>) -> Result<(), JsValue> {
+        self.stream.write_all(&data).await?;
+        Ok(())
+    }
+
+    /// Next bytes from the destination
+    ///
+    /// Waits up to `timeout_ms` (default 30 s). Returns undefined if nothing
+    /// arrived in time (the stream stays open), and an empty array once the
+    /// stream has closed.
+    pub async fn read(&mut self, timeout_ms: Option<u32>) -> Result<Option<Vec<u8>>, JsValue> {
+        let mut buf = vec![0u8; READ_CHUNK];
+        // This call's timeout only; later reads use the default again
+        self.stream.set_recv_timeout(timeout_ms);
+        let read = self.stream.read(&mut buf).await;
+        self.stream.set_recv_timeout(None);
+        match read {
+            Ok(n) => {
+                buf.truncate(n);
+                Ok(Some(buf))
+            }
+            Err(TorError::Timeout) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Close the stream (RELAY_END) and tear down its circuit, or hand an
+    /// attached circuit back to the client
+    pub async fn close(&mut self) {
+        let _ = self.stream.close().await;
+        let circuit = self.scheduler.borrow_mut().checkout_circuit();
+        let Some(mut circuit) = circuit else {
+            return;
+        };
+        if let Some(registry) = &self.attached {
+            registry
+                .with(|circuits| circuits.insert(self.circuit_id, Rc::new(RefCell::new(circuit))));
+            return;
+        }
+        if let Err(e) = circuit.destroy().await {
+            log::warn!("⚠️ Failed to destroy circuit {}: {}", self.circuit_id, e);
+        }
+    }
+}
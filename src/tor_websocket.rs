@@ -0,0 +1,820 @@
+//! WebSocket client over a Tor stream
+//!
+//! [`TorWebSocket`] speaks RFC 6455 to the destination itself: the HTTP/1.1
+//! Upgrade (with a random `Sec-WebSocket-Key`, checked against the server's
+//! `Sec-WebSocket-Accept`) and all framing happen inside the Tor stream,
+//! after TLS for `wss://`. The bridge and the exit only see a TCP stream.
+//!
+//! Frames we send are masked, as a client's must be. Pings are answered as
+//! they are read, fragmented messages are reassembled up to
+//! [`MAX_WS_MESSAGE`], and a server's Close is echoed before the stream is
+//! ended.
+
+use std::collections::VecDeque;
+
+use base64::{engine::general_purpose, Engine as _};
+use sha1::{Digest, Sha1};
+use wasm_bindgen::prelude::*;
+
+use crate::error::{Result, TorError};
+use crate::sse::HttpConnection;
+
+/// Largest message accepted, after reassembling fragments
+pub const MAX_WS_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// Largest Upgrade response header accepted
+const MAX_HANDSHAKE: usize = 16 * 1024;
+
+/// Bytes read off the stream per `receive()` call
+const READ_CHUNK: usize = 16 * 1024;
+
+/// How long `close()` waits for the server's Close frame
+const CLOSE_TIMEOUT_MS: u32 = 5_000;
+
+/// Appended to the key before hashing it into `Sec-WebSocket-Accept`
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Close code for a normal closure
+pub const CLOSE_NORMAL: u16 = 1000;
+
+/// Close code reported when the stream ended without a Close frame
+pub const CLOSE_ABNORMAL: u16 = 1006;
+
+/// `ready_state()` values, as in the browser's `WebSocket`
+pub const OPEN: u16 = 1;
+pub const CLOSING: u16 = 2;
+pub const CLOSED: u16 = 3;
+
+/// Frame opcodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// A complete data message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// What a decoded frame (or run of fragments) amounts to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsEvent {
+    Message(WsMessage),
+    Ping(Vec<u8>),
+    Pong,
+    Close { code: Option<u16>, reason: String },
+}
+
+/// Random `Sec-WebSocket-Key`: 16 bytes, base64
+pub fn generate_key() -> String {
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce).expect("getrandom failed");
+    general_purpose::STANDARD.encode(nonce)
+}
+
+/// `Sec-WebSocket-Accept` a server must answer `key` with
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+/// `http(s)://` form of a `ws(s)://` URL, for the HTTP policy and parser
+pub fn http_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    if let Some(rest) = url.strip_prefix("wss://") {
+        Some(format!("https://{}", rest))
+    } else {
+        url.strip_prefix("ws://")
+            .map(|rest| format!("http://{}", rest))
+    }
+}
+
+/// Upgrade request head
+///
+/// `headers` is a header block from `http_request::header_block()`.
+pub fn upgrade_request(
+    host: &str,
+    port: u16,
+    path: &str,
+    is_https: bool,
+    key: &str,
+    protocols: &[String],
+    headers: &str,
+) -> String {
+    let default_port = if is_https { 443 } else { 80 };
+    let authority = if port == default_port {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\nUser-Agent: Mozilla/5.0 (Windows NT 10.0; rv:109.0) Gecko/20100101 Firefox/115.0\r\n",
+        path, authority, key
+    );
+    if !protocols.is_empty() {
+        request.push_str(&format!(
+            "Sec-WebSocket-Protocol: {}\r\n",
+            protocols.join(", ")
+        ));
+    }
+    request.push_str(headers);
+    request.push_str("\r\n");
+    request
+}
+
+/// Check the server's answer to an Upgrade request sent with `key`
+///
+/// `head` is the response header without its final blank line. Returns the
+/// subprotocol the server picked, if any.
+pub fn check_upgrade_response(
+    head: &[u8],
+    key: &str,
+    protocols: &[String],
+) -> Result<Option<String>> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split(' ').nth(1))
+        .unwrap_or_default();
+    if status != "101" {
+        return Err(TorError::ProtocolError(format!(
+            "WebSocket upgrade refused with HTTP {}",
+            status
+        )));
+    }
+
+    let (mut upgrade, mut connection, mut accept, mut protocol) = (false, false, None, None);
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("connection") {
+            connection = value
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+        } else if name.eq_ignore_ascii_case("sec-websocket-accept") {
+            accept = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("sec-websocket-protocol") {
+            protocol = Some(value.to_string());
+        }
+    }
+
+    if !upgrade || !connection {
+        return Err(TorError::ProtocolError(
+            "WebSocket upgrade response lacks Upgrade/Connection headers".into(),
+        ));
+    }
+    if accept.as_deref() != Some(accept_key(key).as_str()) {
+        return Err(TorError::ProtocolError(
+            "Sec-WebSocket-Accept does not match the key sent".into(),
+        ));
+    }
+    if let Some(protocol) = &protocol {
+        if !protocols.iter().any(|p| p == protocol) {
+            return Err(TorError::ProtocolError(format!(
+                "Server picked subprotocol {:?}, which was not offered",
+                protocol
+            )));
+        }
+    }
+    Ok(protocol)
+}
+
+/// One final (FIN) frame carrying `payload`, masked with `mask`
+pub fn encode_frame(opcode: Opcode, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode.as_u8());
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+/// Close frame payload: the code, then the reason
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    payload
+}
+
+fn random_mask() -> [u8; 4] {
+    let mut mask = [0u8; 4];
+    getrandom::getrandom(&mut mask).expect("getrandom failed");
+    mask
+}
+
+/// Incremental decoder for frames from the server
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    /// Opcode and data of a fragmented message in progress
+    fragments: Option<(Opcode, Vec<u8>)>,
+    max_message: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(MAX_WS_MESSAGE)
+    }
+}
+
+impl FrameDecoder {
+    pub fn new(max_message: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            fragments: None,
+            max_message,
+        }
+    }
+
+    /// Feed stream bytes; returns the events they complete
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<WsEvent>> {
+        self.buf.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while let Some((opcode, fin, range)) = self.next_frame(offset)? {
+            let payload = self.buf[range.clone()].to_vec();
+            offset = range.end;
+            if let Some(event) = self.frame(opcode, fin, payload)? {
+                events.push(event);
+            }
+        }
+        self.buf.drain(..offset);
+        Ok(events)
+    }
+
+    /// Header of the frame at `offset`, if the whole frame has arrived
+    fn next_frame(&self, offset: usize) -> Result<Option<(Opcode, bool, std::ops::Range<usize>)>> {
+        let buf = &self.buf[offset..];
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let fin = buf[0] & 0x80 != 0;
+        if buf[0] & 0x70 != 0 {
+            return Err(TorError::ProtocolError(
+                "WebSocket frame uses unnegotiated extension bits".into(),
+            ));
+        }
+        let opcode = Opcode::from_u8(buf[0] & 0x0F).ok_or_else(|| {
+            TorError::ProtocolError(format!("Unknown WebSocket opcode {:#x}", buf[0] & 0x0F))
+        })?;
+        if buf[1] & 0x80 != 0 {
+            return Err(TorError::ProtocolError(
+                "Server sent a masked WebSocket frame".into(),
+            ));
+        }
+
+        let (len, header) = match buf[1] & 0x7F {
+            126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            127 if buf.len() >= 10 => {
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&buf[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            126 | 127 => return Ok(None),
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(TorError::ProtocolError(
+                "Fragmented or oversized WebSocket control frame".into(),
+            ));
+        }
+        if len > self.max_message as u64 {
+            return Err(TorError::ProtocolError(format!(
+                "WebSocket frame of {} bytes exceeds the {} byte limit",
+                len, self.max_message
+            )));
+        }
+
+        let start = offset + header;
+        let end = start + len as usize;
+        if end > self.buf.len() {
+            return Ok(None);
+        }
+        Ok(Some((opcode, fin, start..end)))
+    }
+
+    fn frame(&mut self, opcode: Opcode, fin: bool, payload: Vec<u8>) -> Result<Option<WsEvent>> {
+        let (opcode, data) = match opcode {
+            Opcode::Ping => return Ok(Some(WsEvent::Ping(payload))),
+            Opcode::Pong => return Ok(Some(WsEvent::Pong)),
+            Opcode::Close => {
+                let code =
+                    (payload.len() >= 2).then(|| u16::from_be_bytes([payload[0], payload[1]]));
+                let reason = payload
+                    .get(2..)
+                    .map(|r| String::from_utf8_lossy(r).into_owned())
+                    .unwrap_or_default();
+                return Ok(Some(WsEvent::Close { code, reason }));
+            }
+            Opcode::Continuation => {
+                let Some((first, mut data)) = self.fragments.take() else {
+                    return Err(TorError::ProtocolError(
+                        "WebSocket continuation frame without a message".into(),
+                    ));
+                };
+                if data.len() + payload.len() > self.max_message {
+                    return Err(TorError::ProtocolError(format!(
+                        "WebSocket message exceeds the {} byte limit",
+                        self.max_message
+                    )));
+                }
+                data.extend_from_slice(&payload);
+                (first, data)
+            }
+            Opcode::Text | Opcode::Binary => {
+                if self.fragments.is_some() {
+                    return Err(TorError::ProtocolError(
+                        "New WebSocket message before the last one finished".into(),
+                    ));
+                }
+                (opcode, payload)
+            }
+        };
+
+        if !fin {
+            self.fragments = Some((opcode, data));
+            return Ok(None);
+        }
+        let message = if opcode == Opcode::Text {
+            WsMessage::Text(String::from_utf8(data).map_err(|_| {
+                TorError::ProtocolError("WebSocket text message is not UTF-8".into())
+            })?)
+        } else {
+            WsMessage::Binary(data)
+        };
+        Ok(Some(WsEvent::Message(message)))
+    }
+}
+
+/// A WebSocket connection to a destination, tunnelled through Tor
+///
+/// Register `on_message` (and optionally `on_close`), then call `receive()`
+/// in a loop: each call reads what has arrived and hands every complete
+/// message to `on_message`. `send()` and `send_binary()` may be called
+/// between `receive()` calls; like `ProxyTunnel`, one operation runs at a
+/// time.
+#[wasm_bindgen]
+pub struct TorWebSocket {
+    url: String,
+    protocol: Option<String>,
+    conn: Option<HttpConnection>,
+    decoder: FrameDecoder,
+    /// Events decoded but not yet dispatched
+    pending: VecDeque<WsEvent>,
+    state: u16,
+    on_message: Option<js_sys::Function>,
+    on_close: Option<js_sys::Function>,
+}
+
+impl TorWebSocket {
+    /// Do the Upgrade handshake over `conn` (already through TLS for
+    /// `wss://`); the connection is closed if it fails
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn connect(
+        mut conn: HttpConnection,
+        url: &str,
+        host: &str,
+        port: u16,
+        path: &str,
+        is_https: bool,
+        protocols: &[String],
+        headers: &str,
+    ) -> Result<Self> {
+        let key = generate_key();
+        let request = upgrade_request(host, port, path, is_https, &key, protocols, headers);
+        match Self::handshake(&mut conn, request.as_bytes(), &key, protocols).await {
+            Ok((protocol, early)) => {
+                let mut socket = Self {
+                    url: url.to_string(),
+                    protocol,
+                    conn: Some(conn),
+                    decoder: FrameDecoder::default(),
+                    pending: VecDeque::new(),
+                    state: OPEN,
+                    on_message: None,
+                    on_close: None,
+                };
+                // Frames the server sent right behind its 101
+                let events = socket.decoder.feed(&early)?;
+                socket.pending.extend(events);
+                Ok(socket)
+            }
+            Err(e) => {
+                let _ = conn.close().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Send the Upgrade request and read the response header; returns the
+    /// chosen subprotocol and any bytes after the header
+    async fn handshake(
+        conn: &mut HttpConnection,
+        request: &[u8],
+        key: &str,
+        protocols: &[String],
+    ) -> Result<(Option<String>, Vec<u8>)> {
+        conn.write_all(request).await?;
+        let mut head = Vec::new();
+        let mut buf = vec![0u8; READ_CHUNK];
+        loop {
+            let n = conn.read(&mut buf).await?;
+            if n == 0 {
+                return Err(TorError::Stream(
+                    "Connection closed during WebSocket handshake".into(),
+                ));
+            }
+            let start = head.len().saturating_sub(3);
+            head.extend_from_slice(&buf[..n]);
+            if let Some(end) = head[start..].windows(4).position(|w| w == b"\r\n\r\n") {
+                let end = start + end;
+                let early = head.split_off(end + 4);
+                let protocol = check_upgrade_response(&head[..end], key, protocols)?;
+                return Ok((protocol, early));
+            }
+            if head.len() > MAX_HANDSHAKE {
+                return Err(TorError::ProtocolError(
+                    "WebSocket upgrade response header too large".into(),
+                ));
+            }
+        }
+    }
+
+    async fn send_frame(&mut self, opcode: Opcode, payload: &[u8]) -> Result<()> {
+        if self.state != OPEN {
+            return Err(TorError::InvalidState("WebSocket is not open".into()));
+        }
+        let conn = self
+            .conn
+            .as_mut()
+            .ok_or_else(|| TorError::InvalidState("WebSocket is not open".into()))?;
+        conn.write_all(&encode_frame(opcode, payload, random_mask()))
+            .await
+    }
+
+    /// End the Tor stream and report the closure once
+    async fn finish(&mut self, code: u16, reason: &str, was_clean: bool) {
+        if let Some(mut conn) = self.conn.take() {
+            let _ = conn.close().await;
+        }
+        if self.state == CLOSED {
+            return;
+        }
+        self.state = CLOSED;
+        log::info!("🔌 WebSocket {} closed ({})", self.url, code);
+        if let Some(callback) = &self.on_close {
+            let event = serde_wasm_bindgen::to_value(&serde_json::json!({
+                "code": code,
+                "reason": reason,
+                "was_clean": was_clean,
+            }))
+            .unwrap_or(JsValue::NULL);
+            let _ = callback.call1(&JsValue::NULL, &event);
+        }
+    }
+
+    /// Act on one decoded event; returns false once the socket has closed
+    async fn dispatch(&mut self, event: WsEvent) -> std::result::Result<bool, JsValue> {
+        match event {
+            WsEvent::Message(message) => {
+                let value = match message {
+                    WsMessage::Text(text) => JsValue::from_str(&text),
+                    WsMessage::Binary(data) => js_sys::Uint8Array::from(data.as_slice()).into(),
+                };
+                if let Some(callback) = &self.on_message {
+                    callback.call1(&JsValue::NULL, &value)?;
+                }
+            }
+            WsEvent::Ping(payload) => {
+                if self.state == OPEN {
+                    self.send_frame(Opcode::Pong, &payload).await?;
+                }
+            }
+            WsEvent::Pong => {}
+            WsEvent::Close { code, reason } => {
+                let code = code.unwrap_or(CLOSE_NORMAL);
+                // Echo the Close unless this answers our own
+                if self.state == OPEN {
+                    let _ = self.send_frame(Opcode::Close, &code.to_be_bytes()).await;
+                }
+                self.finish(code, &reason, true).await;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Read once from the stream into `pending`; false at end of stream
+    async fn fill(&mut self) -> Result<bool> {
+        let Some(conn) = self.conn.as_mut() else {
+            return Ok(false);
+        };
+        let mut buf = vec![0u8; READ_CHUNK];
+        let n = conn.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(false);
+        }
+        let events = self.decoder.feed(&buf[..n])?;
+        self.pending.extend(events);
+        Ok(true)
+    }
+}
+
+#[wasm_bindgen]
+impl TorWebSocket {
+    /// URL the socket was opened to
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Subprotocol the server picked (undefined if none)
+    pub fn protocol(&self) -> Option<String> {
+        self.protocol.clone()
+    }
+
+    /// 1 (open), 2 (closing) or 3 (closed), as `WebSocket.readyState`
+    pub fn ready_state(&self) -> u16 {
+        self.state
+    }
+
+    /// Register the callback for incoming messages: a string for text
+    /// messages, a `Uint8Array` for binary ones. Replaces any earlier
+    /// callback.
+    pub fn on_message(&mut self, callback: js_sys::Function) {
+        self.on_message = Some(callback);
+    }
+
+    /// Register a callback for the socket closing, with `{ code, reason,
+    /// was_clean }`. `was_clean` is false (and `code` 1006) when the stream
+    /// ended without a Close frame. Replaces any earlier callback.
+    pub fn on_close(&mut self, callback: js_sys::Function) {
+        self.on_close = Some(callback);
+    }
+
+    /// Send a text message
+    pub async fn send(&mut self, text: String) -> std::result::Result<(), JsValue> {
+        self.send_frame(Opcode::Text, text.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Send a binary message
+    pub async fn send_binary(&mut self, data: Vec<u8>) -> std::result::Result<(), JsValue> {
+        self.send_frame(Opcode::Binary, &data).await?;
+        Ok(())
+    }
+
+    /// Wait for data and dispatch every message it completes to
+    /// `on_message`, answering pings along the way
+    ///
+    /// Returns false once the socket has closed. A protocol error from the
+    /// server fails the call and closes the socket; an exception from
+    /// `on_message` is passed on and leaves it open.
+    pub async fn receive(&mut self) -> std::result::Result<bool, JsValue> {
+        if self.on_message.is_none() {
+            return Err(JsValue::from_str(
+                "Register on_message() before calling receive()",
+            ));
+        }
+        if self.pending.is_empty() && self.state != CLOSED {
+            match self.fill().await {
+                Ok(true) => {}
+                Ok(false) => self.finish(CLOSE_ABNORMAL, "", false).await,
+                Err(e) => {
+                    self.finish(CLOSE_ABNORMAL, &e.to_string(), false).await;
+                    return Err(e.into());
+                }
+            }
+        }
+        while let Some(event) = self.pending.pop_front() {
+            if !self.dispatch(event).await? {
+                break;
+            }
+        }
+        Ok(self.state != CLOSED)
+    }
+
+    /// Close the socket with `code` (default 1000) and `reason`
+    ///
+    /// Waits briefly for the server's Close before ending the stream.
+    /// Messages still arriving meanwhile are dropped.
+    pub async fn close(
+        &mut self,
+        code: Option<u16>,
+        reason: Option<String>,
+    ) -> std::result::Result<(), JsValue> {
+        if self.state != OPEN {
+            return Ok(());
+        }
+        let code = code.unwrap_or(CLOSE_NORMAL);
+        let reason = reason.unwrap_or_default();
+        if reason.len() > 123 {
+            return Err(JsValue::from_str("Close reason is limited to 123 bytes"));
+        }
+        self.send_frame(Opcode::Close, &close_payload(code, &reason))
+            .await?;
+        self.state = CLOSING;
+
+        let answered = {
+            let reply = async {
+                loop {
+                    if let Some(event) = self.pending.pop_front() {
+                        if let WsEvent::Close { .. } = event {
+                            return true;
+                        }
+                        continue;
+                    }
+                    if !matches!(self.fill().await, Ok(true)) {
+                        return false;
+                    }
+                }
+            };
+            let timeout = gloo_timers::future::TimeoutFuture::new(CLOSE_TIMEOUT_MS);
+            futures::pin_mut!(reply);
+            match futures::future::select(reply, timeout).await {
+                futures::future::Either::Left((answered, _)) => answered,
+                futures::future::Either::Right(_) => false,
+            }
+        };
+        self.finish(code, &reason, answered).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unmasked frame, as a server sends it
+    fn server_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first_byte];
+        if payload.len() < 126 {
+            frame.push(payload.len() as u8);
+        } else {
+            frame.push(126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_accept_key_and_handshake() {
+        // Example from RFC 6455 section 1.3
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        assert_eq!(accept_key(key), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(
+            general_purpose::STANDARD
+                .decode(generate_key())
+                .unwrap()
+                .len(),
+            16
+        );
+
+        let protocols = vec!["chat".to_string()];
+        let request = upgrade_request("example.com", 8443, "/ws?x=1", true, key, &protocols, "");
+        assert!(request.starts_with("GET /ws?x=1 HTTP/1.1\r\nHost: example.com:8443\r\n"));
+        assert!(request.contains("Sec-WebSocket-Protocol: chat\r\n"));
+        assert!(request.ends_with("\r\n\r\n"));
+
+        let head = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: WebSocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Accept: {}\r\nSec-WebSocket-Protocol: chat",
+            accept_key(key)
+        );
+        let picked = check_upgrade_response(head.as_bytes(), key, &protocols).unwrap();
+        assert_eq!(picked.as_deref(), Some("chat"));
+
+        // Wrong accept, a refusal, and an unoffered subprotocol all fail
+        assert!(check_upgrade_response(head.as_bytes(), "other", &protocols).is_err());
+        assert!(check_upgrade_response(b"HTTP/1.1 403 Forbidden", key, &protocols).is_err());
+        assert!(check_upgrade_response(head.as_bytes(), key, &[]).is_err());
+
+        assert_eq!(
+            http_url("wss://example.com/ws").as_deref(),
+            Some("https://example.com/ws")
+        );
+        assert_eq!(
+            http_url("ws://example.com").as_deref(),
+            Some("http://example.com")
+        );
+        assert_eq!(http_url("https://example.com"), None);
+    }
+
+    #[test]
+    fn test_client_frames_are_masked() {
+        let mask = [1, 2, 3, 4];
+        let frame = encode_frame(Opcode::Text, b"hi", mask);
+        assert_eq!(frame[..2], [0x81, 0x82]);
+        assert_eq!(frame[2..6], mask);
+        assert_eq!(frame[6..], [b'h' ^ 1, b'i' ^ 2]);
+
+        let frame = encode_frame(Opcode::Binary, &[0u8; 300], mask);
+        assert_eq!(frame[..4], [0x82, 0x80 | 126, 0x01, 0x2C]);
+        assert_eq!(frame.len(), 4 + 4 + 300);
+        let frame = encode_frame(Opcode::Binary, &vec![0u8; 70_000], mask);
+        assert_eq!(frame[1], 0x80 | 127);
+        assert_eq!(frame.len(), 10 + 4 + 70_000);
+    }
+
+    #[test]
+    fn test_decoder_reassembles_and_handles_control_frames() {
+        let mut stream = server_frame(0x01, b"Hel");
+        // A ping may arrive between fragments
+        stream.extend(server_frame(0x89, b"p"));
+        stream.extend(server_frame(0x80, b"lo"));
+        stream.extend(server_frame(0x82, &[7u8; 200]));
+        stream.extend(server_frame(0x88, b"\x03\xe8bye"));
+
+        // Byte-at-a-time delivery yields the same events as one piece
+        let mut decoder = FrameDecoder::default();
+        let events: Vec<_> = stream
+            .iter()
+            .flat_map(|b| decoder.feed(std::slice::from_ref(b)).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                WsEvent::Ping(b"p".to_vec()),
+                WsEvent::Message(WsMessage::Text("Hello".into())),
+                WsEvent::Message(WsMessage::Binary(vec![7u8; 200])),
+                WsEvent::Close {
+                    code: Some(1000),
+                    reason: "bye".into()
+                },
+            ]
+        );
+        assert_eq!(FrameDecoder::default().feed(&stream).unwrap(), events);
+    }
+
+    #[test]
+    fn test_decoder_rejects_bad_frames() {
+        let mut small = FrameDecoder::new(4);
+        assert!(small.feed(&server_frame(0x82, b"12345")).is_err());
+        let mut small = FrameDecoder::new(4);
+        small.feed(&server_frame(0x02, b"123")).unwrap();
+        assert!(small.feed(&server_frame(0x80, b"45")).is_err());
+
+        for bad in [
+            server_frame(0x80, b"orphan continuation"),
+            server_frame(0x81, b"\xff\xfe"),
+            server_frame(0x09, b"fragmented ping"),
+            server_frame(0xC1, b"rsv1"),
+            server_frame(0x83, b"reserved opcode"),
+            vec![0x81, 0x81, 0, 0, 0, 0, b'x'],
+        ] {
+            assert!(FrameDecoder::default().feed(&bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -13,6 +13,9 @@
 //! - **Padding cells**: Random padding to obscure message sizes
 //! - **Timing obfuscation**: Minimum intervals between cells
 //! - **Chaff traffic**: Dummy cells during idle periods
+//! - **Request jitter**: A random delay before each request's first cell,
+//!   so cell timing doesn't mirror when the user clicked (off by default,
+//!   settable per isolation key)
 //!
 //! ## Tor Protocol Reference
 //!
@@ -20,8 +23,15 @@
 //! They can be sent at any time and are ignored by receivers.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::Duration;
 
+use crate::error::{Result, TorError};
+use crate::isolation::IsolationKey;
+
+/// Longest request jitter accepted, in milliseconds
+pub const MAX_REQUEST_JITTER_MS: u32 = 5_000;
+
 /// Configuration for traffic shaping
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrafficShapingConfig {
@@ -483,6 +493,120 @@ fn xorshift64(state: &mut u64) -> u64 {
     *state
 }
 
+/// Random delay before a request is sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestJitterConfig {
+    /// Delay requests (default: false)
+    pub enabled: bool,
+    /// Shortest delay in milliseconds (default: 0)
+    pub min_ms: u32,
+    /// Longest delay in milliseconds (default: 300)
+    pub max_ms: u32,
+}
+
+impl Default for RequestJitterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_ms: 0,
+            max_ms: 300,
+        }
+    }
+}
+
+impl RequestJitterConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: Self = serde_json::from_str(json)
+            .map_err(|e| TorError::ParseError(format!("Invalid jitter config: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.min_ms > self.max_ms || self.max_ms > MAX_REQUEST_JITTER_MS {
+            return Err(TorError::ParseError(format!(
+                "Request jitter needs min_ms <= max_ms <= {}",
+                MAX_REQUEST_JITTER_MS
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Request jitter statistics
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RequestJitterStats {
+    /// Requests that were delayed
+    pub requests_delayed: u64,
+    /// Sum of the delays (ms)
+    pub total_delay_ms: u64,
+    /// Longest delay (ms)
+    pub max_delay_ms: u32,
+}
+
+/// Request jitter settings per isolation key, with a default
+#[derive(Debug, Default)]
+pub struct RequestJitter {
+    default: RequestJitterConfig,
+    overrides: HashMap<String, RequestJitterConfig>,
+    stats: RequestJitterStats,
+}
+
+impl RequestJitter {
+    pub fn new(default: RequestJitterConfig) -> Self {
+        Self {
+            default,
+            ..Self::default()
+        }
+    }
+
+    /// Settings for keys without an override
+    pub fn set_default(&mut self, config: RequestJitterConfig) {
+        self.default = config;
+    }
+
+    /// Settings for keys without an override
+    pub fn default_config(&self) -> &RequestJitterConfig {
+        &self.default
+    }
+
+    /// Settings for one isolation key
+    pub fn set_for(&mut self, key: &IsolationKey, config: RequestJitterConfig) {
+        self.overrides.insert(key.as_str().to_string(), config);
+    }
+
+    /// Drop every per-key override
+    pub fn clear_overrides(&mut self) {
+        self.overrides.clear();
+    }
+
+    /// Settings in effect for `key`
+    pub fn config_for(&self, key: &IsolationKey) -> &RequestJitterConfig {
+        self.overrides.get(key.as_str()).unwrap_or(&self.default)
+    }
+
+    /// Draw the delay for a request on `key`, counting it, or `None` when
+    /// jitter is off for that key
+    pub fn delay_for(&mut self, key: &IsolationKey) -> Option<u32> {
+        use rand::Rng;
+
+        let config = self.config_for(key);
+        if !config.enabled || config.max_ms == 0 {
+            return None;
+        }
+        let delay = rand::thread_rng().gen_range(config.min_ms..=config.max_ms);
+        self.stats.requests_delayed += 1;
+        self.stats.total_delay_ms += delay as u64;
+        self.stats.max_delay_ms = self.stats.max_delay_ms.max(delay);
+        Some(delay)
+    }
+
+    pub fn stats(&self) -> &RequestJitterStats {
+        &self.stats
+    }
+}
+
 /// Async helper to apply timing delays
 pub async fn apply_delay(delay: Duration) {
     if delay.is_zero() {
@@ -638,4 +762,34 @@ mod tests {
         assert!(d.as_millis() >= 25);
         assert!(d.as_millis() <= 50);
     }
+
+    #[test]
+    fn test_request_jitter_per_key() {
+        use crate::isolation::IsolationType;
+
+        let quiet = IsolationKey::for_destination("a.example", 443, IsolationType::PerDomain);
+        let busy = IsolationKey::for_destination("b.example", 443, IsolationType::PerDomain);
+        let mut jitter = RequestJitter::new(RequestJitterConfig::default());
+        assert_eq!(jitter.delay_for(&quiet), None);
+
+        jitter.set_for(
+            &busy,
+            RequestJitterConfig {
+                enabled: true,
+                min_ms: 20,
+                max_ms: 40,
+            },
+        );
+        for _ in 0..50 {
+            let delay = jitter.delay_for(&busy).unwrap();
+            assert!((20..=40).contains(&delay));
+        }
+        assert_eq!(jitter.delay_for(&quiet), None);
+        assert_eq!(jitter.stats().requests_delayed, 50);
+        assert!(jitter.stats().max_delay_ms <= 40);
+        assert!(jitter.stats().total_delay_ms >= 50 * 20);
+
+        assert!(RequestJitterConfig::from_json(r#"{"min_ms": 50, "max_ms": 10}"#).is_err());
+        assert!(RequestJitterConfig::from_json(r#"{"enabled": true, "max_ms": 999999}"#).is_err());
+    }
 }
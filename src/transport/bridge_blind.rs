@@ -5,6 +5,9 @@
 //! Bridge B, which decrypts it to learn the actual relay address.
 //!
 //! This ensures no single bridge operator can correlate client IP with guard relay IP.
+//!
+//! [`blind_target`] also derives the keys for a sealed channel to Bridge B
+//! from the same exchange (see [`super::bridge_seal`]).
 
 use aes_gcm::aead::Aead;
 use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
@@ -14,12 +17,24 @@ use hkdf::Hkdf;
 use sha2::Sha256;
 use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret};
 
+use super::bridge_seal::ChannelKeys;
+
 /// Info string for HKDF key derivation (domain separation)
 const HKDF_INFO: &[u8] = b"tor-wasm-bridge-blind-v1";
 
 /// Nonce for AES-GCM (fixed since each ephemeral key is used exactly once)
 const FIXED_NONCE: &[u8; 12] = b"bridge-blind";
 
+/// A relay address encrypted for Bridge B, with the keys for sealing the
+/// connection to it
+#[derive(Debug, Clone)]
+pub struct BlindedTarget {
+    /// base64url `ephemeral_pubkey (32 bytes) || ciphertext`
+    pub blob: String,
+    /// Record keys shared with Bridge B through the same exchange
+    pub keys: ChannelKeys,
+}
+
 /// Encrypt a relay address for Bridge B.
 ///
 /// Returns a base64url-encoded blob: `ephemeral_pubkey (32 bytes) || ciphertext`.
@@ -28,6 +43,11 @@ pub fn blind_target_address(
     relay_addr: &str,
     bridge_b_pubkey: &[u8; 32],
 ) -> Result<String, String> {
+    blind_target(relay_addr, bridge_b_pubkey).map(|target| target.blob)
+}
+
+/// Encrypt a relay address for Bridge B and derive the sealed channel keys
+pub fn blind_target(relay_addr: &str, bridge_b_pubkey: &[u8; 32]) -> Result<BlindedTarget, String> {
     // Generate ephemeral X25519 keypair
     let mut rng = rand::thread_rng();
     let ephemeral_secret = EphemeralSecret::random_from_rng(&mut rng);
@@ -57,7 +77,10 @@ pub fn blind_target_address(
     blob.extend_from_slice(&ciphertext);
 
     // Base64url encode for URL-safe transport
-    Ok(URL_SAFE_NO_PAD.encode(&blob))
+    Ok(BlindedTarget {
+        blob: URL_SAFE_NO_PAD.encode(&blob),
+        keys: ChannelKeys::derive(shared_secret.as_bytes()),
+    })
 }
 
 #[cfg(test)]
@@ -157,6 +180,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_bridge_derives_the_same_channel_keys() {
+        let bridge_b_secret = StaticSecret::random_from_rng(&mut rand::thread_rng());
+        let bridge_b_public = PublicKey::from(&bridge_b_secret);
+
+        let target = blind_target("1.2.3.4:9001", bridge_b_public.as_bytes()).unwrap();
+        assert_eq!(
+            decrypt_blinded_address(&target.blob, &bridge_b_secret).unwrap(),
+            "1.2.3.4:9001"
+        );
+
+        // Bridge B repeats the exchange from the ephemeral key in the blob
+        let blob = URL_SAFE_NO_PAD.decode(&target.blob).unwrap();
+        let epk: [u8; 32] = blob[..32].try_into().unwrap();
+        let shared = bridge_b_secret.diffie_hellman(&PublicKey::from(epk));
+        assert_eq!(ChannelKeys::derive(shared.as_bytes()), target.keys);
+
+        let other = blind_target("1.2.3.4:9001", bridge_b_public.as_bytes()).unwrap();
+        assert_ne!(other.keys, target.keys);
+    }
+
     #[test]
     fn test_each_encryption_is_unique() {
         let bridge_b_secret = StaticSecret::random_from_rng(&mut rand::thread_rng());
@@ -0,0 +1,483 @@
+//! Sealed channel between the client and Bridge B
+//!
+//! Blinding ([`super::bridge_blind`]) hides the relay address from Bridge A,
+//! but the OR bytes that follow cross Bridge A protected only by the TLS
+//! that ends there. A `sealed=` target keeps the same X25519 exchange going:
+//! both ends derive one AES-256-GCM key per direction from it, and every
+//! chunk of OR traffic travels as a record
+//!
+//! ```text
+//! | length (u16) | sequence (u64) | ciphertext + tag (length bytes) |
+//! ```
+//!
+//! The nonce is the sequence number and the header is the associated data,
+//! so Bridge A can't alter, reorder across the window, drop-and-replay or
+//! splice records without the receiver noticing. Each side numbers its
+//! records from 0; the receiver keeps a [`ReplayWindow`] of the last 64
+//! numbers and rejects anything it has seen or that has fallen behind.
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use futures::io::{AsyncRead, AsyncWrite};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::io::Result as IoResult;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// HKDF info for the client → Bridge B key
+const INFO_CLIENT_TO_BRIDGE: &[u8] = b"tor-wasm-bridge-seal-v1 client-to-bridge";
+
+/// HKDF info for the Bridge B → client key
+const INFO_BRIDGE_TO_CLIENT: &[u8] = b"tor-wasm-bridge-seal-v1 bridge-to-client";
+
+/// Length and sequence number
+pub const HEADER_LEN: usize = 10;
+
+/// AES-GCM tag
+const TAG_LEN: usize = 16;
+
+/// Most plaintext carried by one record
+pub const MAX_RECORD_PLAINTEXT: usize = 16 * 1024;
+
+/// Sequence numbers the receiver remembers behind the highest one
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// Why a sealed record was rejected
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SealError {
+    /// The record didn't decrypt under the channel key
+    #[error("Sealed record {0} failed authentication")]
+    Forged(u64),
+
+    /// The record was already received, or is older than the window
+    #[error("Sealed record {0} replayed")]
+    Replayed(u64),
+
+    /// The length field exceeds the largest record
+    #[error("Sealed record of {0} bytes is too long")]
+    Oversized(usize),
+
+    /// The channel ended in the middle of a record
+    #[error("Sealed channel closed mid-record")]
+    Truncated,
+
+    /// All sequence numbers are used up
+    #[error("Sealed channel sequence numbers exhausted")]
+    SequenceExhausted,
+}
+
+impl From<SealError> for std::io::Error {
+    fn from(e: SealError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// The two record keys of a sealed channel
+#[derive(Clone, PartialEq, Eq)]
+pub struct ChannelKeys {
+    client_to_bridge: [u8; 32],
+    bridge_to_client: [u8; 32],
+}
+
+impl ChannelKeys {
+    /// Derive both keys from the blinding exchange's shared secret
+    pub(crate) fn derive(shared_secret: &[u8; 32]) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(None, shared_secret);
+        let mut keys = Self {
+            client_to_bridge: [0u8; 32],
+            bridge_to_client: [0u8; 32],
+        };
+        // 32-byte outputs are always within HKDF-SHA256's limit
+        hkdf.expand(INFO_CLIENT_TO_BRIDGE, &mut keys.client_to_bridge)
+            .expect("32-byte HKDF output");
+        hkdf.expand(INFO_BRIDGE_TO_CLIENT, &mut keys.bridge_to_client)
+            .expect("32-byte HKDF output");
+        keys
+    }
+}
+
+impl std::fmt::Debug for ChannelKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ChannelKeys { .. }")
+    }
+}
+
+fn nonce(seq: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    nonce
+}
+
+fn header(len: usize, seq: u64) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..2].copy_from_slice(&(len as u16).to_be_bytes());
+    header[2..].copy_from_slice(&seq.to_be_bytes());
+    header
+}
+
+/// Seals outgoing records, numbering them from 0
+pub struct Sealer {
+    cipher: Aes256Gcm,
+    next_seq: u64,
+}
+
+impl Sealer {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(key.into()),
+            next_seq: 0,
+        }
+    }
+
+    /// Seal up to [`MAX_RECORD_PLAINTEXT`] bytes into one record
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, SealError> {
+        debug_assert!(plaintext.len() <= MAX_RECORD_PLAINTEXT);
+        let seq = self.next_seq;
+        self.next_seq = seq.checked_add(1).ok_or(SealError::SequenceExhausted)?;
+
+        let header = header(plaintext.len() + TAG_LEN, seq);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce(seq)),
+                Payload {
+                    msg: plaintext,
+                    aad: &header,
+                },
+            )
+            .map_err(|_| SealError::Forged(seq))?;
+
+        let mut record = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        record.extend_from_slice(&header);
+        record.extend_from_slice(&ciphertext);
+        Ok(record)
+    }
+}
+
+/// Sequence numbers already accepted, up to [`REPLAY_WINDOW`] behind the
+/// highest
+#[derive(Debug, Default, Clone)]
+pub struct ReplayWindow {
+    /// Highest accepted sequence number plus one (0: none yet)
+    top: u64,
+    /// Bit `i` set: `top - 1 - i` was accepted
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Whether `seq` is new and still inside the window
+    pub fn check(&self, seq: u64) -> Result<(), SealError> {
+        if seq >= self.top {
+            return Ok(());
+        }
+        let behind = self.top - 1 - seq;
+        if behind >= REPLAY_WINDOW || self.seen & (1 << behind) != 0 {
+            return Err(SealError::Replayed(seq));
+        }
+        Ok(())
+    }
+
+    /// Record `seq` as accepted (after it authenticated)
+    pub fn mark(&mut self, seq: u64) {
+        if seq >= self.top {
+            let shift = seq + 1 - self.top;
+            self.seen = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.top = seq + 1;
+        } else {
+            self.seen |= 1 << (self.top - 1 - seq);
+        }
+    }
+}
+
+/// Opens incoming records
+pub struct Opener {
+    cipher: Aes256Gcm,
+    window: ReplayWindow,
+}
+
+impl Opener {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(key.into()),
+            window: ReplayWindow::default(),
+        }
+    }
+
+    /// Open the first record in `buf` if it is complete, returning its
+    /// plaintext and the bytes it took
+    pub fn open(&mut self, buf: &[u8]) -> Result<Option<(Vec<u8>, usize)>, SealError> {
+        if buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+        if !(TAG_LEN..=MAX_RECORD_PLAINTEXT + TAG_LEN).contains(&len) {
+            return Err(SealError::Oversized(len));
+        }
+        if buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        let mut seq_bytes = [0u8; 8];
+        seq_bytes.copy_from_slice(&buf[2..HEADER_LEN]);
+        let seq = u64::from_be_bytes(seq_bytes);
+        self.window.check(seq)?;
+
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce(seq)),
+                Payload {
+                    msg: &buf[HEADER_LEN..HEADER_LEN + len],
+                    aad: &buf[..HEADER_LEN],
+                },
+            )
+            .map_err(|_| SealError::Forged(seq))?;
+        self.window.mark(seq);
+        Ok(Some((plaintext, HEADER_LEN + len)))
+    }
+}
+
+/// A stream whose bytes travel sealed between the client and Bridge B
+pub struct SealedStream<S> {
+    inner: S,
+    sealer: Sealer,
+    opener: Opener,
+    /// Raw bytes read but not yet opened
+    incoming: Vec<u8>,
+    /// Opened bytes not yet returned to the reader
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+    /// Sealed records not yet written to `inner`
+    outgoing: Vec<u8>,
+}
+
+impl<S> SealedStream<S> {
+    /// Seal the client's side of `inner`
+    pub fn client(inner: S, keys: &ChannelKeys) -> Self {
+        Self::new(inner, &keys.client_to_bridge, &keys.bridge_to_client)
+    }
+
+    fn new(inner: S, send_key: &[u8; 32], recv_key: &[u8; 32]) -> Self {
+        Self {
+            inner,
+            sealer: Sealer::new(send_key),
+            opener: Opener::new(recv_key),
+            incoming: Vec::new(),
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+            outgoing: Vec::new(),
+        }
+    }
+
+    /// The underlying transport
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncWrite + Unpin> SealedStream<S> {
+    /// Write out pending records
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        while !self.outgoing.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.outgoing) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+                }
+                Poll::Ready(Ok(n)) => {
+                    self.outgoing.drain(..n);
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SealedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        let this = self.get_mut();
+        loop {
+            if this.plaintext_pos < this.plaintext.len() {
+                let available = &this.plaintext[this.plaintext_pos..];
+                let n = available.len().min(buf.len());
+                buf[..n].copy_from_slice(&available[..n]);
+                this.plaintext_pos += n;
+                return Poll::Ready(Ok(n));
+            }
+
+            if let Some((plaintext, used)) = this.opener.open(&this.incoming)? {
+                this.incoming.drain(..used);
+                this.plaintext = plaintext;
+                this.plaintext_pos = 0;
+                continue;
+            }
+
+            let mut chunk = [0u8; 4096];
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(0)) if this.incoming.is_empty() => return Poll::Ready(Ok(0)),
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(SealError::Truncated.into())),
+                Poll::Ready(Ok(n)) => this.incoming.extend_from_slice(&chunk[..n]),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SealedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        let this = self.get_mut();
+        // One record in flight at a time keeps the buffer bounded
+        if this.poll_drain(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        let n = buf.len().min(MAX_RECORD_PLAINTEXT);
+        let record = this.sealer.seal(&buf[..n])?;
+        this.outgoing.extend_from_slice(&record);
+        // Start sending now; whatever is left goes out on the next write
+        // or flush
+        let _ = this.poll_drain(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        if this.poll_drain(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        if this.poll_drain(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::{AsyncReadExt, AsyncWriteExt, Cursor};
+
+    fn keys() -> ChannelKeys {
+        ChannelKeys::derive(&[7u8; 32])
+    }
+
+    #[test]
+    fn test_records_open_once_and_in_window() {
+        let keys = keys();
+        let mut sealer = Sealer::new(&keys.client_to_bridge);
+        let mut opener = Opener::new(&keys.client_to_bridge);
+
+        let first = sealer.seal(b"cell one").unwrap();
+        let second = sealer.seal(b"cell two").unwrap();
+
+        // Reordered delivery inside the window is fine
+        let (plain, used) = opener.open(&second).unwrap().unwrap();
+        assert_eq!((plain.as_slice(), used), (&b"cell two"[..], second.len()));
+        assert_eq!(opener.open(&first).unwrap().unwrap().0, b"cell one");
+
+        // A second copy of either is a replay
+        assert_eq!(opener.open(&first), Err(SealError::Replayed(0)));
+        assert_eq!(opener.open(&second), Err(SealError::Replayed(1)));
+
+        // Incomplete records wait for more bytes
+        let third = sealer.seal(b"cell three").unwrap();
+        assert_eq!(opener.open(&third[..third.len() - 1]), Ok(None));
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let keys = keys();
+        let mut sealer = Sealer::new(&keys.client_to_bridge);
+
+        let mut flipped = sealer.seal(b"payload").unwrap();
+        *flipped.last_mut().unwrap() ^= 1;
+        let mut opener = Opener::new(&keys.client_to_bridge);
+        assert_eq!(opener.open(&flipped), Err(SealError::Forged(0)));
+
+        // Renumbering a record breaks the tag, and a forged record doesn't
+        // burn its sequence number
+        let mut renumbered = sealer.seal(b"payload").unwrap();
+        renumbered[HEADER_LEN - 1] = 0;
+        assert_eq!(opener.open(&renumbered), Err(SealError::Forged(0)));
+
+        // The other direction's key doesn't open it either
+        let record = Sealer::new(&keys.client_to_bridge).seal(b"x").unwrap();
+        let mut wrong = Opener::new(&keys.bridge_to_client);
+        assert_eq!(wrong.open(&record), Err(SealError::Forged(0)));
+
+        let mut oversized = record.clone();
+        oversized[..2].copy_from_slice(&u16::MAX.to_be_bytes());
+        assert!(matches!(
+            opener.open(&oversized),
+            Err(SealError::Oversized(_))
+        ));
+    }
+
+    #[test]
+    fn test_replay_window_slides() {
+        let mut window = ReplayWindow::default();
+        for seq in [0, 1, 2, 5] {
+            window.check(seq).unwrap();
+            window.mark(seq);
+        }
+        assert!(window.check(3).is_ok());
+        assert!(window.check(2).is_err());
+
+        window.mark(100);
+        assert!(window.check(100).is_err());
+        assert!(window.check(100 - REPLAY_WINDOW + 1).is_ok());
+        assert!(window.check(100 - REPLAY_WINDOW).is_err());
+        assert!(window.check(3).is_err());
+    }
+
+    #[test]
+    fn test_stream_roundtrip_with_bridge() {
+        let keys = keys();
+
+        // Client writes; Bridge B opens with the client-to-bridge key
+        let mut client = SealedStream::client(Cursor::new(Vec::new()), &keys);
+        let big = vec![0xAB; MAX_RECORD_PLAINTEXT + 100];
+        futures::executor::block_on(async {
+            client.write_all(b"hello bridge").await.unwrap();
+            client.write_all(&big).await.unwrap();
+            client.flush().await.unwrap();
+        });
+        let wire = client.get_ref().get_ref().clone();
+        assert!(!wire.windows(5).any(|w| w == b"hello"));
+
+        let mut bridge = SealedStream::new(
+            Cursor::new(wire.clone()),
+            &keys.bridge_to_client,
+            &keys.client_to_bridge,
+        );
+        let mut received = Vec::new();
+        futures::executor::block_on(bridge.read_to_end(&mut received)).unwrap();
+        assert_eq!(&received[..12], b"hello bridge");
+        assert_eq!(&received[12..], &big[..]);
+
+        // A channel cut inside a record is an error, not a clean EOF
+        let mut cut = SealedStream::new(
+            Cursor::new(wire[..wire.len() - 3].to_vec()),
+            &keys.bridge_to_client,
+            &keys.client_to_bridge,
+        );
+        let mut received = Vec::new();
+        assert!(futures::executor::block_on(cut.read_to_end(&mut received)).is_err());
+    }
+}
@@ -0,0 +1,670 @@
+//! OR connections multiplexed over one framed bridge WebSocket
+//!
+//! A [`FramedSession`] owns a WebSocket speaking the [`framing`](super::framing)
+//! protocol. Each [`FramedChannel`] opened on it is one OR connection and
+//! implements `AsyncRead + AsyncWrite` like the other transports, so the
+//! TLS and cell layers don't know the difference.
+//!
+//! A background task reads the socket and routes DATA and CLOSE records to
+//! their channels, dropping PADDING. Writers encode their bytes as DATA
+//! records into a shared outbound buffer that is pushed into the WebSocket
+//! as it accepts data, so records from different channels never interleave
+//! mid-record.
+//!
+//! With a version 2 bridge a dropped socket doesn't end the session: the
+//! receive task reconnects and resumes it within the bridge's grace window
+//! (see [`framing`](super::framing#resumption-version-2)). Channels stall
+//! meanwhile rather than fail, so the circuits on them survive.
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Result as IoResult};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use super::framing::{
+    accept_hello, parse_ack, parse_resume, parse_session, FramingError, Record, RecordDecoder,
+    RecordType, ResumeTicket, SendLog, ACK_INTERVAL, CONTROL_CHANNEL, RESUMABLE_VERSION,
+    SUPPORTED_VERSIONS, TOKEN_LEN,
+};
+use super::websocket::WasmTcpStream;
+use crate::runtime::timer::now_ms;
+use crate::runtime::{LocalCell, TaskSupervisor};
+
+/// Read size for the session's receive task
+const READ_CHUNK: usize = 16 * 1024;
+
+/// How long one resume attempt may take
+const RESUME_ATTEMPT_TIMEOUT_MS: u32 = 5_000;
+
+/// Wait before the first resume attempt, doubled after each failure
+const RESUME_FIRST_BACKOFF_MS: u32 = 250;
+const RESUME_MAX_BACKOFF_MS: u32 = 4_000;
+
+/// Per-channel state
+#[derive(Default)]
+struct ChannelState {
+    /// Data received for the channel and not yet read
+    recv: VecDeque<u8>,
+    read_waker: Option<Waker>,
+    /// Set once the bridge closed the channel: the reason, empty for a
+    /// clean close
+    closed: Option<String>,
+    /// Sent bytes the bridge hasn't acknowledged (resumable sessions only)
+    log: SendLog,
+    /// Bytes received on the channel so far
+    received: u64,
+    /// `received` as last acknowledged to the bridge
+    acked: u64,
+}
+
+/// State shared by the session and its channels
+struct SessionState {
+    stream: WasmTcpStream,
+    /// Encoded records not yet accepted by the WebSocket
+    outbound: Vec<u8>,
+    channels: HashMap<u16, ChannelState>,
+    next_channel: u16,
+    /// Set when the socket failed or closed for good; every channel then
+    /// fails
+    error: Option<String>,
+    padding_received: u64,
+    /// How to resume the session, if the bridge speaks version 2
+    ticket: Option<ResumeTicket>,
+    /// Set while the socket is down and the session is being resumed
+    suspended: bool,
+    /// Writers waiting for the session to resume
+    write_wakers: Vec<Waker>,
+    resumes: u64,
+}
+
+impl SessionState {
+    /// Push queued records into the WebSocket until it stops accepting
+    fn drive_outbound(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        while !self.outbound.is_empty() {
+            match Pin::new(&mut self.stream).poll_write(cx, &self.outbound) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => {
+                    self.outbound.drain(..n);
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Queue a record and push what the socket will take now
+    ///
+    /// Nothing is queued while suspended: the resume handshake carries the
+    /// state that CLOSE and ACK would, and DATA is replayed from the logs.
+    fn send(&mut self, record: &Record) {
+        if self.suspended {
+            return;
+        }
+        record.encode(&mut self.outbound);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        if let Poll::Ready(Err(e)) = self.drive_outbound(&mut cx) {
+            log::warn!("🧵 Framed send failed: {}", e);
+        }
+    }
+
+    /// Route one incoming record; returns the waker to call, if any
+    fn dispatch(&mut self, record: Record) -> Result<Option<Waker>, String> {
+        match record.kind {
+            RecordType::Data => {
+                let resumable = self.ticket.is_some();
+                let Some(ch) = self.channels.get_mut(&record.channel) else {
+                    return Ok(None);
+                };
+                ch.received += record.payload.len() as u64;
+                ch.recv.extend(record.payload);
+                let ack = (resumable && ch.received - ch.acked >= ACK_INTERVAL).then(|| {
+                    ch.acked = ch.received;
+                    Record::ack(record.channel, ch.received)
+                });
+                let waker = ch.read_waker.take();
+                if let Some(ack) = ack {
+                    self.send(&ack);
+                }
+                Ok(waker)
+            }
+            RecordType::Ack => {
+                let offset = parse_ack(&record).map_err(|e| e.to_string())?;
+                if let Some(ch) = self.channels.get_mut(&record.channel) {
+                    ch.log.ack(offset).map_err(|e| e.to_string())?;
+                }
+                Ok(None)
+            }
+            RecordType::Close => Ok(self.channels.get_mut(&record.channel).and_then(|ch| {
+                ch.closed = Some(String::from_utf8_lossy(&record.payload).into_owned());
+                ch.read_waker.take()
+            })),
+            RecordType::Padding => {
+                self.padding_received += record.payload.len() as u64;
+                Ok(None)
+            }
+            RecordType::Hello | RecordType::Open | RecordType::Session | RecordType::Resume => {
+                Err(format!("Unexpected {:?} record from bridge", record.kind))
+            }
+        }
+    }
+
+    /// Fail the session, returning every waiting reader's and writer's
+    /// waker
+    fn fail(&mut self, reason: String) -> Vec<Waker> {
+        log::warn!("🧵 Framed bridge session ended: {}", reason);
+        self.error = Some(reason);
+        self.suspended = false;
+        let mut wakers = std::mem::take(&mut self.write_wakers);
+        wakers.extend(
+            self.channels
+                .values_mut()
+                .filter_map(|ch| ch.read_waker.take()),
+        );
+        wakers
+    }
+
+    /// The socket dropped: hold the channels until the session resumes
+    fn suspend(&mut self) {
+        self.suspended = true;
+        // Whatever was queued went nowhere; DATA is replayed after resuming
+        self.outbound.clear();
+    }
+
+    /// How much of each open channel has arrived, for RESUME
+    fn received_offsets(&mut self) -> Vec<(u16, u64)> {
+        self.channels
+            .iter_mut()
+            .filter(|(_, ch)| ch.closed.is_none())
+            .map(|(&id, ch)| {
+                // RESUME acknowledges it too
+                ch.acked = ch.received;
+                (id, ch.received)
+            })
+            .collect()
+    }
+
+    /// Carry on over `stream` after the bridge's RESUME listed how much of
+    /// each channel it received: replay the rest and close the channels it
+    /// no longer has. Returns the wakers of everyone who was waiting.
+    fn resumed(
+        &mut self,
+        stream: WasmTcpStream,
+        received: &[(u16, u64)],
+    ) -> Result<Vec<Waker>, String> {
+        let received: HashMap<u16, u64> = received.iter().copied().collect();
+        self.stream = stream;
+        self.suspended = false;
+        self.resumes += 1;
+
+        let mut wakers = std::mem::take(&mut self.write_wakers);
+        for (&id, ch) in &mut self.channels {
+            match received.get(&id) {
+                Some(&from) if ch.closed.is_none() => {
+                    let missing = ch.log.replay_from(from).map_err(|e| e.to_string())?;
+                    Record::encode_data(id, &missing, &mut self.outbound);
+                }
+                Some(_) => {}
+                None => {
+                    ch.closed
+                        .get_or_insert_with(|| "Bridge lost the channel while reconnecting".into());
+                }
+            }
+            wakers.extend(ch.read_waker.take());
+        }
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        if let Poll::Ready(Err(e)) = self.drive_outbound(&mut cx) {
+            return Err(e.to_string());
+        }
+        Ok(wakers)
+    }
+}
+
+/// One framed WebSocket to the bridge, carrying many OR connections
+pub struct FramedSession {
+    state: Rc<LocalCell<SessionState>>,
+    version: u8,
+}
+
+impl FramedSession {
+    /// Connect to `bridge_url` and negotiate the framing version
+    pub async fn connect(bridge_url: &str) -> IoResult<Self> {
+        let mut stream = WasmTcpStream::connect(&session_url(bridge_url)).await?;
+
+        stream
+            .write_all(&Record::hello(&SUPPORTED_VERSIONS).to_bytes())
+            .await?;
+        stream.flush().await?;
+
+        let mut decoder = RecordDecoder::new();
+        let version = accept_hello(&read_record(&mut stream, &mut decoder).await?)?;
+        let ticket = if version >= RESUMABLE_VERSION {
+            Some(parse_session(
+                &read_record(&mut stream, &mut decoder).await?,
+            )?)
+        } else {
+            None
+        };
+        log::info!(
+            "🧵 Framed bridge session up (version {}{})",
+            version,
+            if ticket.is_some() { ", resumable" } else { "" }
+        );
+
+        let state = Rc::new(LocalCell::new(SessionState {
+            stream,
+            outbound: Vec::new(),
+            channels: HashMap::new(),
+            next_channel: 1,
+            error: None,
+            padding_received: 0,
+            ticket,
+            suspended: false,
+            write_wakers: Vec::new(),
+            resumes: 0,
+        }));
+        let reader = receive_loop(Rc::clone(&state), decoder, bridge_url.to_string());
+        TaskSupervisor::global().spawn("framed-receive", async move {
+            reader.await;
+            Ok(())
+        });
+        Ok(Self { state, version })
+    }
+
+    /// Negotiated framing version
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Whether new channels can be opened: the session hasn't failed and
+    /// isn't waiting to resume
+    pub fn is_open(&self) -> bool {
+        self.state.with(|s| s.error.is_none() && !s.suspended)
+    }
+
+    /// Whether the session survives its socket dropping
+    pub fn is_resumable(&self) -> bool {
+        self.state.with(|s| s.ticket.is_some())
+    }
+
+    /// Times the session was resumed on a new socket
+    pub fn resumes(&self) -> u64 {
+        self.state.with(|s| s.resumes)
+    }
+
+    /// Channels currently open
+    pub fn channel_count(&self) -> usize {
+        self.state.with(|s| s.channels.len())
+    }
+
+    /// Padding bytes the bridge has sent
+    pub fn padding_received(&self) -> u64 {
+        self.state.with(|s| s.padding_received)
+    }
+
+    /// Open a channel to `target` (`addr=HOST:PORT` or `dest=<blob>`)
+    ///
+    /// The bridge connects in the background; data written meanwhile is
+    /// held by the bridge, and a failed connect arrives as a CLOSE that
+    /// fails the channel's next read.
+    pub fn open_channel(&self, target: &str) -> IoResult<FramedChannel> {
+        let id = self.state.with(|s| {
+            if let Some(err) = &s.error {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, err.clone()));
+            }
+            if s.suspended {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Bridge session is reconnecting",
+                ));
+            }
+            let start = s.next_channel;
+            while s.channels.contains_key(&s.next_channel) {
+                s.next_channel = s.next_channel.checked_add(1).unwrap_or(1);
+                if s.next_channel == start {
+                    return Err(io::Error::other("No free framing channels"));
+                }
+            }
+            let id = s.next_channel;
+            s.next_channel = id.checked_add(1).unwrap_or(1);
+            s.channels.insert(id, ChannelState::default());
+            s.send(&Record::open(id, target));
+            Ok(id)
+        })?;
+        log::debug!("🧵 Opened framed channel {}", id);
+        Ok(FramedChannel {
+            id,
+            state: Rc::clone(&self.state),
+        })
+    }
+
+    /// Close the socket for good, failing any channels still open (no
+    /// resume is attempted)
+    pub fn close(&self) {
+        let wakers = self.state.with(|s| {
+            let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+            let _ = Pin::new(&mut s.stream).poll_close(&mut cx);
+            s.fail("Bridge session closed".into())
+        });
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Send `len` bytes of padding to the bridge
+    pub fn send_padding(&self, len: usize) {
+        self.state.with(|s| {
+            if s.error.is_none() {
+                s.send(&Record::padding(len));
+            }
+        });
+    }
+}
+
+fn session_url(bridge_url: &str) -> String {
+    format!("{}?framing=1", bridge_url)
+}
+
+/// Read from `stream` until `decoder` has a whole record
+async fn read_record(stream: &mut WasmTcpStream, decoder: &mut RecordDecoder) -> IoResult<Record> {
+    let mut buf = [0u8; 64];
+    loop {
+        if let Some(record) = decoder.next_record()? {
+            return Ok(record);
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Bridge closed during the framing handshake",
+            ));
+        }
+        decoder.push(&buf[..n]);
+    }
+}
+
+/// Open a new socket and ask the bridge to resume the session named by
+/// `token` on it, returning the socket, its decoder and the bridge's
+/// `(channel, received)` counts
+///
+/// A refusal or protocol error comes back as `InvalidData`; anything else
+/// is worth retrying.
+async fn reattach(
+    bridge_url: &str,
+    token: &[u8; TOKEN_LEN],
+    received: &[(u16, u64)],
+) -> IoResult<(WasmTcpStream, RecordDecoder, Vec<(u16, u64)>)> {
+    let attempt = async {
+        let mut stream = WasmTcpStream::connect(&session_url(bridge_url)).await?;
+        let mut handshake = Record::hello(&[RESUMABLE_VERSION]).to_bytes();
+        Record::resume(token, received).encode(&mut handshake);
+        stream.write_all(&handshake).await?;
+        stream.flush().await?;
+
+        let mut decoder = RecordDecoder::new();
+        accept_hello(&read_record(&mut stream, &mut decoder).await?)?;
+        // The new socket's own session, given up for the resumed one
+        parse_session(&read_record(&mut stream, &mut decoder).await?)?;
+        let reply = read_record(&mut stream, &mut decoder).await?;
+        let received = match reply.kind {
+            RecordType::Close if reply.channel == CONTROL_CHANNEL => {
+                return Err(FramingError::ResumeRefused(
+                    String::from_utf8_lossy(&reply.payload).into_owned(),
+                )
+                .into());
+            }
+            _ => parse_resume(&reply)?.received,
+        };
+        Ok((stream, decoder, received))
+    };
+    let timeout = gloo_timers::future::TimeoutFuture::new(RESUME_ATTEMPT_TIMEOUT_MS);
+    futures::pin_mut!(attempt);
+    match futures::future::select(attempt, timeout).await {
+        futures::future::Either::Left((result, _)) => result,
+        futures::future::Either::Right(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "Resume handshake timed out",
+        )),
+    }
+}
+
+/// The socket dropped with `reason`: resume the session on a new one within
+/// the bridge's grace window, returning the new socket's decoder, or why
+/// the session is over
+async fn resume(
+    state: &Rc<LocalCell<SessionState>>,
+    bridge_url: &str,
+    reason: String,
+) -> Result<RecordDecoder, String> {
+    // Closed on purpose, or a version 1 bridge
+    let Some(ticket) = state.with(|s| s.ticket.filter(|_| s.error.is_none())) else {
+        return Err(reason);
+    };
+    log::warn!(
+        "🧵 Bridge socket dropped ({}); resuming within {}s",
+        reason,
+        ticket.grace_secs
+    );
+    state.with(|s| s.suspend());
+    let deadline = now_ms() + u64::from(ticket.grace_secs) * 1000;
+    let mut backoff = RESUME_FIRST_BACKOFF_MS;
+    loop {
+        if let Some(error) = state.with(|s| s.error.clone()) {
+            return Err(error);
+        }
+        if now_ms() + u64::from(backoff) >= deadline {
+            return Err(format!(
+                "{}; not resumed within {}s",
+                reason, ticket.grace_secs
+            ));
+        }
+        gloo_timers::future::TimeoutFuture::new(backoff).await;
+        backoff = (backoff * 2).min(RESUME_MAX_BACKOFF_MS);
+
+        let received = state.with(|s| s.received_offsets());
+        match reattach(bridge_url, &ticket.token, &received).await {
+            Ok((stream, decoder, bridge_received)) => {
+                let wakers = state.with(|s| s.resumed(stream, &bridge_received))?;
+                log::info!(
+                    "🧵 Bridge session resumed with {} channels",
+                    bridge_received.len()
+                );
+                wakers.into_iter().for_each(Waker::wake);
+                return Ok(decoder);
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                return Err(format!("{}; {}", reason, e));
+            }
+            Err(e) => log::debug!("🧵 Resume attempt failed: {}", e),
+        }
+    }
+}
+
+/// Read the socket and route records until it closes for good
+async fn receive_loop(
+    state: Rc<LocalCell<SessionState>>,
+    mut decoder: RecordDecoder,
+    bridge_url: String,
+) {
+    let mut buf = vec![0u8; READ_CHUNK];
+    loop {
+        // Records left over from the handshake read go out first
+        let mut wakers = Vec::new();
+        let failure = state.with(|s| loop {
+            match decoder.next_record() {
+                Ok(Some(record)) => match s.dispatch(record) {
+                    Ok(waker) => wakers.extend(waker),
+                    Err(reason) => break Some(reason),
+                },
+                Ok(None) => break None,
+                Err(e) => break Some(e.to_string()),
+            }
+        });
+        if let Some(reason) = failure {
+            wakers.extend(state.with(|s| s.fail(reason)));
+        }
+        let failed = state.with(|s| s.error.is_some());
+        wakers.into_iter().for_each(Waker::wake);
+        if failed {
+            return;
+        }
+
+        let read = futures::future::poll_fn(|cx| {
+            state.with(|s| Pin::new(&mut s.stream).poll_read(cx, &mut buf))
+        })
+        .await;
+        let dropped = match read {
+            Ok(0) => "Bridge closed the session".to_string(),
+            Ok(n) => {
+                decoder.push(&buf[..n]);
+                continue;
+            }
+            Err(e) => e.to_string(),
+        };
+        match resume(&state, &bridge_url, dropped).await {
+            Ok(resumed) => decoder = resumed,
+            Err(reason) => {
+                let wakers = state.with(|s| s.fail(reason));
+                wakers.into_iter().for_each(Waker::wake);
+                return;
+            }
+        }
+    }
+}
+
+/// One OR connection inside a [`FramedSession`]
+pub struct FramedChannel {
+    id: u16,
+    state: Rc<LocalCell<SessionState>>,
+}
+
+impl FramedChannel {
+    /// Channel number within the session
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+}
+
+impl AsyncRead for FramedChannel {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        let id = self.id;
+        self.state.with(|s| {
+            let error = s.error.clone();
+            let Some(ch) = s.channels.get_mut(&id) else {
+                return Poll::Ready(Ok(0));
+            };
+            if !ch.recv.is_empty() {
+                let n = buf.len().min(ch.recv.len());
+                for (dst, src) in buf.iter_mut().zip(ch.recv.drain(..n)) {
+                    *dst = src;
+                }
+                return Poll::Ready(Ok(n));
+            }
+            match (&ch.closed, error) {
+                (Some(reason), _) if reason.is_empty() => Poll::Ready(Ok(0)),
+                (Some(reason), _) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    reason.clone(),
+                ))),
+                (None, Some(error)) => Poll::Ready(Err(io::Error::other(error))),
+                (None, None) => {
+                    ch.read_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+impl AsyncWrite for FramedChannel {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        let id = self.id;
+        self.state.with(|s| {
+            if let Some(error) = &s.error {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    error.clone(),
+                )));
+            }
+            if s.channels.get(&id).is_none_or(|ch| ch.closed.is_some()) {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Framed channel closed",
+                )));
+            }
+            if s.suspended {
+                s.write_wakers.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            // Wait for earlier records to go out before queueing more
+            match s.drive_outbound(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            if s.ticket.is_some() {
+                if let Some(ch) = s.channels.get_mut(&id) {
+                    ch.log.push(buf);
+                }
+            }
+            Record::encode_data(id, buf, &mut s.outbound);
+            if let Poll::Ready(Err(e)) = s.drive_outbound(cx) {
+                return Poll::Ready(Err(e));
+            }
+            Poll::Ready(Ok(buf.len()))
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        self.state.with(|s| {
+            if s.suspended {
+                s.write_wakers.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            match s.drive_outbound(cx) {
+                Poll::Ready(Ok(())) => Pin::new(&mut s.stream).poll_flush(cx),
+                other => other,
+            }
+        })
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let id = self.id;
+        self.state.with(|s| {
+            if s.channels.remove(&id).is_some() && s.error.is_none() {
+                // Left out of RESUME instead while suspended
+                s.send(&Record::close(id, ""));
+            }
+            if s.suspended {
+                return Poll::Ready(Ok(()));
+            }
+            match s.drive_outbound(cx) {
+                Poll::Ready(Ok(())) => Pin::new(&mut s.stream).poll_flush(cx),
+                other => other,
+            }
+        })
+    }
+}
+
+impl Drop for FramedChannel {
+    fn drop(&mut self) {
+        let id = self.id;
+        let _ = self.state.try_with(|s| {
+            if s.channels.remove(&id).is_some() && s.error.is_none() {
+                s.send(&Record::close(id, ""));
+            }
+        });
+    }
+}
+
+impl std::fmt::Debug for FramedChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FramedChannel")
+            .field("id", &self.id)
+            .finish()
+    }
+}
@@ -0,0 +1,583 @@
+//! Framing protocol between the client and the bridge
+//!
+//! Without framing, a bridge WebSocket carries one relay's raw TLS bytes and
+//! the relay address rides in the URL. With framing (`?framing=1`), the
+//! socket carries a stream of records instead, so that one WebSocket can
+//! hold several OR connections, either side can insert padding the other
+//! discards, and the protocol can change behind a version handshake.
+//!
+//! Every record is
+//!
+//! ```text
+//! type: u8 | channel: u16 BE | length: u16 BE | payload: [u8; length]
+//! ```
+//!
+//! As with unframed traffic, WebSocket message boundaries carry no meaning:
+//! a record may span messages and a message may hold several records.
+//!
+//! | type | record  | payload                                               |
+//! |------|---------|-------------------------------------------------------|
+//! | 1    | HELLO   | supported versions, one byte each (channel 0)         |
+//! | 2    | OPEN    | target, `addr=HOST:PORT` or `dest=<blinded blob>`     |
+//! | 3    | DATA    | bytes for the channel's OR connection                 |
+//! | 4    | CLOSE   | optional UTF-8 reason; either side may send it        |
+//! | 5    | PADDING | ignored (channel 0)                                   |
+//! | 6    | SESSION | resume token (16 bytes), grace seconds u16 (channel 0)|
+//! | 7    | RESUME  | resume token, then `channel u16, received u64` each   |
+//! | 8    | ACK     | bytes of the channel received so far, u64             |
+//!
+//! The client opens with HELLO listing the versions it speaks; the bridge
+//! answers with a HELLO naming the single version it picked, or closes the
+//! socket if there is none in common. Channels are numbered by the client,
+//! starting at 1. Records of unknown type are an error.
+//!
+//! # Resumption (version 2)
+//!
+//! A version 2 bridge follows its HELLO with a SESSION record: a token
+//! naming the session and how long the bridge keeps the session's relay
+//! connections open after the socket drops. Both sides count the DATA bytes
+//! of each channel, ACK what they have received every [`ACK_INTERVAL`]
+//! bytes, and keep what they sent until it is acknowledged ([`SendLog`]).
+//!
+//! When the socket drops (a mobile network blip), the client opens a new
+//! one within the grace window, sends HELLO offering version 2 and then
+//! RESUME with the token and how much of each open channel it received.
+//! After its HELLO and a fresh SESSION (which the client ignores), the
+//! bridge answers with a RESUME of its own counts, or CLOSE on channel 0 if
+//! the token is unknown or expired. Each side then replays the bytes
+//! the other is missing and carries on, so the TLS streams inside never
+//! see the gap. Channels one side no longer lists were closed meanwhile.
+
+use std::collections::VecDeque;
+
+/// Framing versions this client speaks, preferred first
+pub const SUPPORTED_VERSIONS: [u8; 2] = [2, 1];
+
+/// First version whose sessions can be resumed
+pub const RESUMABLE_VERSION: u8 = 2;
+
+/// Length of a resume token
+pub const TOKEN_LEN: usize = 16;
+
+/// Unacknowledged bytes a channel receives before sending an ACK
+pub const ACK_INTERVAL: u64 = 32 * 1024;
+
+/// Record header: type, channel, length
+pub const HEADER_LEN: usize = 5;
+
+/// Largest record payload
+pub const MAX_PAYLOAD: usize = u16::MAX as usize;
+
+/// Channel for session-level records (HELLO, PADDING)
+pub const CONTROL_CHANNEL: u16 = 0;
+
+/// Framing protocol violations
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FramingError {
+    /// A record type this version doesn't define
+    #[error("Unknown framing record type {0}")]
+    UnknownRecord(u8),
+
+    /// The bridge's HELLO names no version we speak
+    #[error("No common framing version (bridge offered {0:?})")]
+    NoCommonVersion(Vec<u8>),
+
+    /// A record arrived that is not valid here
+    #[error("Unexpected {0:?} record")]
+    Unexpected(RecordType),
+
+    /// A record's payload has the wrong length
+    #[error("Malformed {0:?} record")]
+    Malformed(RecordType),
+
+    /// The peer acknowledged more than was sent
+    #[error("Peer acknowledged {acked} bytes of {sent} sent")]
+    AckBeyondSent { acked: u64, sent: u64 },
+
+    /// The peer is missing bytes that were already acknowledged and dropped
+    #[error("Cannot replay from byte {from}, the send log starts at {start}")]
+    ReplayGap { from: u64, start: u64 },
+
+    /// The bridge won't resume the session
+    #[error("Bridge refused to resume the session: {0}")]
+    ResumeRefused(String),
+}
+
+impl From<FramingError> for std::io::Error {
+    fn from(e: FramingError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Record types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    Hello = 1,
+    Open = 2,
+    Data = 3,
+    Close = 4,
+    Padding = 5,
+    Session = 6,
+    Resume = 7,
+    Ack = 8,
+}
+
+impl RecordType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(RecordType::Hello),
+            2 => Some(RecordType::Open),
+            3 => Some(RecordType::Data),
+            4 => Some(RecordType::Close),
+            5 => Some(RecordType::Padding),
+            6 => Some(RecordType::Session),
+            7 => Some(RecordType::Resume),
+            8 => Some(RecordType::Ack),
+            _ => None,
+        }
+    }
+}
+
+/// One framing record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub kind: RecordType,
+    pub channel: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    /// HELLO offering `versions`
+    pub fn hello(versions: &[u8]) -> Self {
+        Self {
+            kind: RecordType::Hello,
+            channel: CONTROL_CHANNEL,
+            payload: versions.to_vec(),
+        }
+    }
+
+    /// OPEN of `channel` to `target` (`addr=...` or `dest=...`)
+    pub fn open(channel: u16, target: &str) -> Self {
+        Self {
+            kind: RecordType::Open,
+            channel,
+            payload: target.as_bytes().to_vec(),
+        }
+    }
+
+    /// CLOSE of `channel`
+    pub fn close(channel: u16, reason: &str) -> Self {
+        Self {
+            kind: RecordType::Close,
+            channel,
+            payload: reason.as_bytes().to_vec(),
+        }
+    }
+
+    /// PADDING with `len` zero bytes (the payload is covered by TLS, so its
+    /// content doesn't matter)
+    pub fn padding(len: usize) -> Self {
+        Self {
+            kind: RecordType::Padding,
+            channel: CONTROL_CHANNEL,
+            payload: vec![0; len.min(MAX_PAYLOAD)],
+        }
+    }
+
+    /// SESSION issuing `ticket` (sent by the bridge)
+    pub fn session(ticket: &ResumeTicket) -> Self {
+        let mut payload = ticket.token.to_vec();
+        payload.extend_from_slice(&ticket.grace_secs.to_be_bytes());
+        Self {
+            kind: RecordType::Session,
+            channel: CONTROL_CHANNEL,
+            payload,
+        }
+    }
+
+    /// RESUME of the session named by `token`, with how many bytes of each
+    /// channel were received
+    pub fn resume(token: &[u8; TOKEN_LEN], received: &[(u16, u64)]) -> Self {
+        let mut payload = token.to_vec();
+        for (channel, offset) in received {
+            payload.extend_from_slice(&channel.to_be_bytes());
+            payload.extend_from_slice(&offset.to_be_bytes());
+        }
+        Self {
+            kind: RecordType::Resume,
+            channel: CONTROL_CHANNEL,
+            payload,
+        }
+    }
+
+    /// ACK of the first `received` bytes of `channel`
+    pub fn ack(channel: u16, received: u64) -> Self {
+        Self {
+            kind: RecordType::Ack,
+            channel,
+            payload: received.to_be_bytes().to_vec(),
+        }
+    }
+
+    /// Append the wire form of `data` for `channel` to `out`, split into
+    /// DATA records of at most [`MAX_PAYLOAD`] bytes
+    pub fn encode_data(channel: u16, data: &[u8], out: &mut Vec<u8>) {
+        for chunk in data.chunks(MAX_PAYLOAD) {
+            encode_header(RecordType::Data, channel, chunk.len(), out);
+            out.extend_from_slice(chunk);
+        }
+    }
+
+    /// Append the wire form of the record to `out`
+    ///
+    /// Payloads longer than [`MAX_PAYLOAD`] are truncated; only DATA can be
+    /// that long, and [`Record::encode_data`] splits it instead.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let payload = &self.payload[..self.payload.len().min(MAX_PAYLOAD)];
+        encode_header(self.kind, self.channel, payload.len(), out);
+        out.extend_from_slice(payload);
+    }
+
+    /// The record's wire form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        self.encode(&mut out);
+        out
+    }
+}
+
+fn encode_header(kind: RecordType, channel: u16, len: usize, out: &mut Vec<u8>) {
+    out.push(kind as u8);
+    out.extend_from_slice(&channel.to_be_bytes());
+    out.extend_from_slice(&(len as u16).to_be_bytes());
+}
+
+/// Reassembles records from arbitrarily split input
+#[derive(Debug, Default)]
+pub struct RecordDecoder {
+    buf: VecDeque<u8>,
+}
+
+impl RecordDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add received bytes
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend(data);
+    }
+
+    /// Bytes received but not yet returned as records
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Next complete record, if one has arrived
+    pub fn next_record(&mut self) -> Result<Option<Record>, FramingError> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let kind =
+            RecordType::from_u8(self.buf[0]).ok_or(FramingError::UnknownRecord(self.buf[0]))?;
+        let channel = u16::from_be_bytes([self.buf[1], self.buf[2]]);
+        let len = u16::from_be_bytes([self.buf[3], self.buf[4]]) as usize;
+        if self.buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        self.buf.drain(..HEADER_LEN);
+        let payload = self.buf.drain(..len).collect();
+        Ok(Some(Record {
+            kind,
+            channel,
+            payload,
+        }))
+    }
+}
+
+/// Check the bridge's HELLO reply: the version it picked, if we speak it
+pub fn accept_hello(reply: &Record) -> Result<u8, FramingError> {
+    if reply.kind != RecordType::Hello {
+        return Err(FramingError::Unexpected(reply.kind));
+    }
+    match reply.payload[..] {
+        [version] if SUPPORTED_VERSIONS.contains(&version) => Ok(version),
+        _ => Err(FramingError::NoCommonVersion(reply.payload.clone())),
+    }
+}
+
+/// What a version 2 bridge hands out to resume a session
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ResumeTicket {
+    pub token: [u8; TOKEN_LEN],
+    /// How long the bridge holds the session after its socket drops
+    pub grace_secs: u16,
+}
+
+impl std::fmt::Debug for ResumeTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The token is all it takes to take over the session
+        f.debug_struct("ResumeTicket")
+            .field("token", &"<redacted>")
+            .field("grace_secs", &self.grace_secs)
+            .finish()
+    }
+}
+
+/// Parse the bridge's SESSION record
+pub fn parse_session(record: &Record) -> Result<ResumeTicket, FramingError> {
+    if record.kind != RecordType::Session {
+        return Err(FramingError::Unexpected(record.kind));
+    }
+    if record.payload.len() != TOKEN_LEN + 2 {
+        return Err(FramingError::Malformed(record.kind));
+    }
+    let (token, grace) = record.payload.split_at(TOKEN_LEN);
+    Ok(ResumeTicket {
+        token: token.try_into().expect("split at TOKEN_LEN"),
+        grace_secs: u16::from_be_bytes([grace[0], grace[1]]),
+    })
+}
+
+/// A parsed RESUME record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumeRecord {
+    pub token: [u8; TOKEN_LEN],
+    /// `(channel, bytes received)` of each channel the sender still has
+    pub received: Vec<(u16, u64)>,
+}
+
+/// Parse a RESUME record
+pub fn parse_resume(record: &Record) -> Result<ResumeRecord, FramingError> {
+    if record.kind != RecordType::Resume {
+        return Err(FramingError::Unexpected(record.kind));
+    }
+    if record.payload.len() < TOKEN_LEN || !(record.payload.len() - TOKEN_LEN).is_multiple_of(10) {
+        return Err(FramingError::Malformed(record.kind));
+    }
+    let (token, entries) = record.payload.split_at(TOKEN_LEN);
+    let received = entries
+        .chunks(10)
+        .map(|entry| {
+            let (channel, offset) = entry.split_at(2);
+            (
+                u16::from_be_bytes([channel[0], channel[1]]),
+                u64::from_be_bytes(offset.try_into().expect("8-byte offset")),
+            )
+        })
+        .collect();
+    Ok(ResumeRecord {
+        token: token.try_into().expect("split at TOKEN_LEN"),
+        received,
+    })
+}
+
+/// Parse an ACK record's offset
+pub fn parse_ack(record: &Record) -> Result<u64, FramingError> {
+    let offset: [u8; 8] = record
+        .payload
+        .as_slice()
+        .try_into()
+        .map_err(|_| FramingError::Malformed(record.kind))?;
+    Ok(u64::from_be_bytes(offset))
+}
+
+/// A channel's sent bytes that the peer hasn't acknowledged yet, kept to
+/// replay after a resume
+#[derive(Debug, Default)]
+pub struct SendLog {
+    /// Channel offset of the first byte held
+    start: u64,
+    bytes: VecDeque<u8>,
+}
+
+impl SendLog {
+    /// Record bytes as sent
+    pub fn push(&mut self, data: &[u8]) {
+        self.bytes.extend(data);
+    }
+
+    /// Bytes sent on the channel so far
+    pub fn sent(&self) -> u64 {
+        self.start + self.bytes.len() as u64
+    }
+
+    /// Bytes held for replay
+    pub fn held(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// The peer has the first `offset` bytes: stop holding them
+    pub fn ack(&mut self, offset: u64) -> Result<(), FramingError> {
+        if offset > self.sent() {
+            return Err(FramingError::AckBeyondSent {
+                acked: offset,
+                sent: self.sent(),
+            });
+        }
+        if offset > self.start {
+            self.bytes.drain(..(offset - self.start) as usize);
+            self.start = offset;
+        }
+        Ok(())
+    }
+
+    /// The bytes after the first `from`, which the peer says it has
+    pub fn replay_from(&mut self, from: u64) -> Result<Vec<u8>, FramingError> {
+        if from < self.start {
+            return Err(FramingError::ReplayGap {
+                from,
+                start: self.start,
+            });
+        }
+        self.ack(from)?;
+        Ok(self.bytes.iter().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_survive_arbitrary_splits() {
+        let mut wire = Vec::new();
+        Record::hello(&SUPPORTED_VERSIONS).encode(&mut wire);
+        Record::open(1, "addr=1.2.3.4:9001").encode(&mut wire);
+        Record::encode_data(1, &[7u8; 514], &mut wire);
+        Record::padding(100).encode(&mut wire);
+        Record::close(1, "done").encode(&mut wire);
+
+        // Feed one byte at a time: nothing is returned early
+        let mut decoder = RecordDecoder::new();
+        let mut records = Vec::new();
+        for byte in &wire {
+            decoder.push(std::slice::from_ref(byte));
+            while let Some(record) = decoder.next_record().unwrap() {
+                records.push(record);
+            }
+        }
+        assert_eq!(decoder.buffered(), 0);
+
+        let kinds: Vec<_> = records.iter().map(|r| (r.kind, r.channel)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (RecordType::Hello, 0),
+                (RecordType::Open, 1),
+                (RecordType::Data, 1),
+                (RecordType::Padding, 0),
+                (RecordType::Close, 1),
+            ]
+        );
+        assert_eq!(records[1].payload, b"addr=1.2.3.4:9001");
+        assert_eq!(records[2].payload, vec![7u8; 514]);
+        assert_eq!(records[3].payload.len(), 100);
+    }
+
+    #[test]
+    fn test_large_data_is_split() {
+        let data = vec![1u8; MAX_PAYLOAD + 10];
+        let mut wire = Vec::new();
+        Record::encode_data(3, &data, &mut wire);
+        assert_eq!(wire.len(), data.len() + 2 * HEADER_LEN);
+
+        let mut decoder = RecordDecoder::new();
+        decoder.push(&wire);
+        assert_eq!(
+            decoder.next_record().unwrap().unwrap().payload.len(),
+            MAX_PAYLOAD
+        );
+        assert_eq!(decoder.next_record().unwrap().unwrap().payload.len(), 10);
+        assert_eq!(decoder.next_record().unwrap(), None);
+
+        decoder.push(&[9, 0, 0, 0, 0]);
+        assert_eq!(decoder.next_record(), Err(FramingError::UnknownRecord(9)));
+    }
+
+    #[test]
+    fn test_version_negotiation() {
+        assert_eq!(accept_hello(&Record::hello(&[1])), Ok(1));
+        assert_eq!(accept_hello(&Record::hello(&[2])), Ok(2));
+        assert_eq!(
+            accept_hello(&Record::hello(&[3])),
+            Err(FramingError::NoCommonVersion(vec![3]))
+        );
+        // The bridge must pick exactly one
+        assert!(accept_hello(&Record::hello(&[1, 2])).is_err());
+        assert_eq!(
+            accept_hello(&Record::padding(4)),
+            Err(FramingError::Unexpected(RecordType::Padding))
+        );
+    }
+
+    #[test]
+    fn test_resume_records_round_trip() {
+        let ticket = ResumeTicket {
+            token: [0xab; TOKEN_LEN],
+            grace_secs: 30,
+        };
+        assert_eq!(parse_session(&Record::session(&ticket)), Ok(ticket));
+        assert!(!format!("{:?}", ticket).contains("171"));
+
+        let received = vec![(1, 0), (7, 1 << 40)];
+        let record = Record::resume(&ticket.token, &received);
+        let mut decoder = RecordDecoder::new();
+        decoder.push(&record.to_bytes());
+        let decoded = decoder.next_record().unwrap().unwrap();
+        assert_eq!(
+            parse_resume(&decoded),
+            Ok(ResumeRecord {
+                token: ticket.token,
+                received,
+            })
+        );
+        assert!(parse_resume(&Record::resume(&ticket.token, &[]))
+            .unwrap()
+            .received
+            .is_empty());
+
+        assert_eq!(parse_ack(&Record::ack(3, 70_000)), Ok(70_000));
+
+        let mut truncated = record.clone();
+        truncated.payload.pop();
+        assert_eq!(
+            parse_resume(&truncated),
+            Err(FramingError::Malformed(RecordType::Resume))
+        );
+        assert_eq!(
+            parse_session(&Record::ack(0, 1)),
+            Err(FramingError::Unexpected(RecordType::Ack))
+        );
+    }
+
+    #[test]
+    fn test_send_log_replays_what_the_peer_missed() {
+        let mut log = SendLog::default();
+        log.push(b"hello ");
+        log.push(b"world");
+        assert_eq!(log.sent(), 11);
+
+        log.ack(6).unwrap();
+        assert_eq!(log.held(), 5);
+        // Acknowledging less than before holds nothing back
+        log.ack(2).unwrap();
+        assert_eq!(log.held(), 5);
+        assert_eq!(
+            log.ack(12),
+            Err(FramingError::AckBeyondSent {
+                acked: 12,
+                sent: 11
+            })
+        );
+
+        // The peer got "hello wo" before the socket dropped
+        assert_eq!(log.replay_from(8).unwrap(), b"rld");
+        assert_eq!(log.held(), 3);
+        assert_eq!(log.replay_from(11).unwrap(), b"");
+        assert_eq!(
+            log.replay_from(5),
+            Err(FramingError::ReplayGap { from: 5, start: 11 })
+        );
+    }
+}
@@ -13,7 +13,6 @@
 //! standard HTTP request/response. Defeats WebSocket-based blocking.
 
 use futures::io::{AsyncRead, AsyncWrite};
-use std::cell::UnsafeCell;
 use std::collections::VecDeque;
 use std::io::{self, Result as IoResult};
 use std::pin::Pin;
@@ -24,6 +23,8 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{Request, RequestInit, RequestMode, Response};
 
+use crate::runtime::LocalCell;
+
 /// State of the meek connection
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum MeekState {
@@ -63,7 +64,7 @@ pub struct WasmMeekStream {
     bridge_url: String,
     session_id: String,
     target: String,
-    state: Rc<UnsafeCell<MeekStreamState>>,
+    state: Rc<LocalCell<MeekStreamState>>,
     poll_interval_ms: u32,
     _poll_closure: Option<Closure<dyn FnMut()>>,
     /// Interval ID from setInterval, needed for cleanup
@@ -84,7 +85,7 @@ impl WasmMeekStream {
     pub async fn connect(bridge_url: &str, target: &str) -> IoResult<Self> {
         let session_id = Self::generate_session_id();
 
-        let state = Rc::new(UnsafeCell::new(MeekStreamState::new()));
+        let state = Rc::new(LocalCell::new(MeekStreamState::new()));
 
         let mut stream = Self {
             bridge_url: bridge_url.to_string(),
@@ -99,19 +100,22 @@ impl WasmMeekStream {
         // Initial POST to establish session (empty body, target in header)
         match stream.do_exchange(&[]).await {
             Ok(data) => {
-                let s = unsafe { &mut *state.get() };
-                s.state = MeekState::Connected;
-                if !data.is_empty() {
-                    s.recv_buffer.extend(data.iter());
-                }
-                if let Some(w) = s.read_waker.take() {
+                let waker = state.with(|s| {
+                    s.state = MeekState::Connected;
+                    if !data.is_empty() {
+                        s.recv_buffer.extend(data.iter());
+                    }
+                    s.read_waker.take()
+                });
+                if let Some(w) = waker {
                     w.wake();
                 }
             }
             Err(e) => {
-                let s = unsafe { &mut *state.get() };
-                s.state = MeekState::Closed;
-                s.error = Some(format!("meek connect failed: {}", e));
+                state.with(|s| {
+                    s.state = MeekState::Closed;
+                    s.error = Some(format!("meek connect failed: {}", e));
+                });
                 return Err(io::Error::new(io::ErrorKind::ConnectionRefused, e));
             }
         }
@@ -197,13 +201,13 @@ impl WasmMeekStream {
         let target = self.target.clone();
 
         let closure = Closure::new(move || {
-            let s = unsafe { &mut *state.get() };
-            if s.state != MeekState::Connected {
-                return;
-            }
-
             // Drain send buffer
-            let send_data: Vec<u8> = s.send_buffer.drain(..).collect();
+            let send_data: Vec<u8> = match state.with(|s| {
+                (s.state == MeekState::Connected).then(|| s.send_buffer.drain(..).collect())
+            }) {
+                Some(data) => data,
+                None => return,
+            };
 
             if !send_data.is_empty() {
                 log::info!("meek poll: sending {} bytes", send_data.len());
@@ -224,11 +228,13 @@ impl WasmMeekStream {
                 };
                 match stream.do_exchange(&send_data).await {
                     Ok(data) => {
-                        let s = unsafe { &mut *state_inner.get() };
                         if !data.is_empty() {
                             log::info!("meek poll: received {} bytes from relay", data.len());
-                            s.recv_buffer.extend(data.iter());
-                            if let Some(w) = s.read_waker.take() {
+                            let waker = state_inner.with(|s| {
+                                s.recv_buffer.extend(data.iter());
+                                s.read_waker.take()
+                            });
+                            if let Some(w) = waker {
                                 w.wake();
                             }
                         } else if had_data {
@@ -239,11 +245,13 @@ impl WasmMeekStream {
                         }
                     }
                     Err(e) => {
-                        let s = unsafe { &mut *state_inner.get() };
                         log::warn!("meek poll error: {}", e);
-                        s.error = Some(e);
-                        s.state = MeekState::Closed;
-                        if let Some(w) = s.read_waker.take() {
+                        let waker = state_inner.with(|s| {
+                            s.error = Some(e);
+                            s.state = MeekState::Closed;
+                            s.read_waker.take()
+                        });
+                        if let Some(w) = waker {
                             w.wake();
                         }
                     }
@@ -329,32 +337,32 @@ impl AsyncRead for WasmMeekStream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<IoResult<usize>> {
-        let state = unsafe { &mut *self.state.get() };
-
-        if !state.recv_buffer.is_empty() {
-            let len = std::cmp::min(buf.len(), state.recv_buffer.len());
-            for i in 0..len {
-                buf[i] = state.recv_buffer.pop_front().unwrap();
+        self.state.with(|state| {
+            if !state.recv_buffer.is_empty() {
+                let len = std::cmp::min(buf.len(), state.recv_buffer.len());
+                for i in 0..len {
+                    buf[i] = state.recv_buffer.pop_front().unwrap();
+                }
+                return Poll::Ready(Ok(len));
             }
-            return Poll::Ready(Ok(len));
-        }
 
-        match state.state {
-            MeekState::Closed => {
-                if let Some(ref e) = state.error {
-                    Poll::Ready(Err(io::Error::new(
-                        io::ErrorKind::ConnectionReset,
-                        e.clone(),
-                    )))
-                } else {
-                    Poll::Ready(Ok(0)) // EOF
+            match state.state {
+                MeekState::Closed => {
+                    if let Some(ref e) = state.error {
+                        Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::ConnectionReset,
+                            e.clone(),
+                        )))
+                    } else {
+                        Poll::Ready(Ok(0)) // EOF
+                    }
+                }
+                _ => {
+                    state.read_waker = Some(cx.waker().clone());
+                    Poll::Pending
                 }
             }
-            _ => {
-                state.read_waker = Some(cx.waker().clone());
-                Poll::Pending
-            }
-        }
+        })
     }
 }
 
@@ -364,9 +372,7 @@ impl AsyncWrite for WasmMeekStream {
         _cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<IoResult<usize>> {
-        let state = unsafe { &mut *self.state.get() };
-
-        match state.state {
+        self.state.with(|state| match state.state {
             MeekState::Closed => Poll::Ready(Err(io::Error::new(
                 io::ErrorKind::BrokenPipe,
                 state.error.as_deref().unwrap_or("connection closed"),
@@ -375,7 +381,7 @@ impl AsyncWrite for WasmMeekStream {
                 state.send_buffer.extend(buf.iter());
                 Poll::Ready(Ok(buf.len()))
             }
-        }
+        })
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
@@ -384,16 +390,14 @@ impl AsyncWrite for WasmMeekStream {
     }
 
     fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
-        let state = unsafe { &mut *self.state.get() };
-        state.state = MeekState::Closed;
+        self.state.with(|state| state.state = MeekState::Closed);
         Poll::Ready(Ok(()))
     }
 }
 
 impl Drop for WasmMeekStream {
     fn drop(&mut self) {
-        let state = unsafe { &mut *self.state.get() };
-        state.state = MeekState::Closed;
+        self.state.with(|state| state.state = MeekState::Closed);
 
         // Clear the poll interval to prevent "closure invoked after being dropped" panics
         if let Some(interval_id) = self.poll_interval_id.take() {
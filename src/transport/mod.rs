@@ -7,25 +7,47 @@
 //! - **Direct mode:** WebSocket with `?addr=1.2.3.4:9001` to a single bridge (simple, legacy)
 //! - **Blinded mode:** WebSocket with encrypted relay address under Bridge B's public key.
 //!   Bridge A forwards the opaque blob to Bridge B. Neither bridge alone can
-//!   correlate client IP with guard relay IP.
+//!   correlate client IP with guard relay IP. Where Bridge B supports it, the
+//!   OR traffic is also sealed end to end between the client and Bridge B
+//!   ([`bridge_seal`]), so Bridge A can't tamper with or replay it.
 //! - **Peer bridge mode:** WebRTC DataChannel through a volunteer's browser tab.
 //!   Looks like a video call to DPI equipment. No installation required on either side.
 //! - **meek mode:** HTTP POST/response bodies through a CDN. Censor sees only
 //!   HTTPS to a CDN IP — indistinguishable from normal website traffic.
 //!   Fallback when WebSocket and ECH are both blocked.
+//!
+//! Direct and blinded WebSocket connections can also run **framed**
+//! ([`framing`]): many OR connections share one bridge WebSocket as
+//! channels, and padding records may be mixed in.
+//!
+//! With the `volunteer-proxy` feature, the [`volunteer`] module lets this same
+//! WASM bundle serve as the volunteer side of peer bridge mode.
 
 pub mod bridge_blind;
+pub mod bridge_seal;
+pub mod framed;
+pub mod framing;
 pub mod meek;
+pub mod shared_ring;
+pub mod stats;
 pub mod unified;
+#[cfg(feature = "volunteer-proxy")]
+pub mod volunteer;
 pub mod webrtc;
 pub mod websocket;
 pub mod webtunnel;
 
-pub use bridge_blind::blind_target_address;
+pub use bridge_blind::{blind_target, blind_target_address, BlindedTarget};
+pub use bridge_seal::{ChannelKeys, SealError, SealedStream};
+pub use framed::{FramedChannel, FramedSession};
+pub use framing::FramingError;
 pub use meek::WasmMeekStream;
+pub use stats::{ConnectionStats, ConnectionStatsRegistry, SharedConnectionStats};
 pub use unified::TransportStream;
+#[cfg(feature = "volunteer-proxy")]
+pub use volunteer::VolunteerProxy;
 pub use webrtc::WasmRtcStream;
-pub use websocket::WasmTcpStream;
+pub use websocket::{BridgeMux, FrameError, MuxStatus, WasmTcpStream, MAX_MESSAGE_SIZE};
 pub use webtunnel::WasmWebTunnelStream;
 
 /// Transport mode for connecting to the bridge
@@ -41,6 +63,18 @@ pub enum TransportMode {
     WebTunnel,
 }
 
+impl TransportMode {
+    /// Lowercase name, as used in bridge lines and JS results
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransportMode::WebSocket => "websocket",
+            TransportMode::WebRtc => "webrtc",
+            TransportMode::Meek => "meek",
+            TransportMode::WebTunnel => "webtunnel",
+        }
+    }
+}
+
 /// Configuration for bridge server
 #[derive(Debug, Clone)]
 pub struct BridgeConfig {
@@ -194,11 +228,46 @@ impl BridgeConfig {
                 match blind_target_address(&addr_str, pubkey) {
                     Ok(blob) => format!("{}?dest={}", self.bridge_url, blob),
                     Err(e) => {
-                        log::error!("Bridge blinding failed, falling back to direct: {}", e);
+                        crate::security_posture::report_downgrade(
+                            crate::security_posture::Downgrade::BlindingFallback,
+                            &format!("bridge blinding failed, connected directly: {}", e),
+                        );
                         format!("{}?addr={}", self.bridge_url, addr)
                     }
                 }
             }
         }
     }
+
+    /// Open a single connection to `addr` using this configuration's
+    /// preferred transport. No retries and no fallback to other transports.
+    pub async fn connect(&self, addr: &std::net::SocketAddr) -> std::io::Result<TransportStream> {
+        match self.transport {
+            TransportMode::WebSocket => {
+                let stream = WasmTcpStream::connect(&self.build_url(addr)).await?;
+                Ok(TransportStream::WebSocket(stream))
+            }
+            TransportMode::Meek => {
+                let url = self.meek_url.as_deref().unwrap_or(&self.bridge_url);
+                let stream = WasmMeekStream::connect(url, &addr.to_string()).await?;
+                Ok(TransportStream::Meek(stream))
+            }
+            TransportMode::WebTunnel => {
+                let url = self.webtunnel_url.as_deref().unwrap_or(&self.bridge_url);
+                let path = self.webtunnel_path.as_deref().unwrap_or("/");
+                let stream = WasmWebTunnelStream::connect(url, path).await?;
+                Ok(TransportStream::WebTunnel(stream))
+            }
+            TransportMode::WebRtc => {
+                let broker = self.broker_url.as_deref().ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "WebRTC transport needs a broker URL",
+                    )
+                })?;
+                let stream = WasmRtcStream::connect(broker, &self.build_url(addr)).await?;
+                Ok(TransportStream::WebRtc(stream))
+            }
+        }
+    }
 }
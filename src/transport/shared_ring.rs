@@ -0,0 +1,148 @@
+//! SharedArrayBuffer ring buffer for received bridge bytes
+//!
+//! Without it, every WebSocket message is copied from its `ArrayBuffer`
+//! into a `Vec`, appended to the stream's `VecDeque`, then drained byte by
+//! byte into the reader's buffer. When the page is cross-origin isolated
+//! (so `SharedArrayBuffer` exists), [`WasmTcpStream`] gives each connection
+//! a [`SharedRing`] instead: the receive callback copies the message
+//! straight into the ring on the JS side, and `poll_read` copies out of it
+//! into the caller's buffer in at most two block copies.
+//!
+//! The ring is single-producer single-consumer and lock-free: the write and
+//! read positions are free-running `u32` counters kept in an `Int32Array`
+//! over the same shared memory and updated with `Atomics`, so the producer
+//! could equally run in a worker. Data that doesn't fit spills into the
+//! stream's `VecDeque` (see [`WasmTcpStream`]), which keeps byte order.
+//!
+//! [`WasmTcpStream`]: super::WasmTcpStream
+
+/// Default ring size per connection
+pub const DEFAULT_RING_CAPACITY: usize = 256 * 1024;
+
+/// Index of the write (head) and read (tail) counters
+const HEAD: u32 = 0;
+const TAIL: u32 = 1;
+
+/// Whether the page can allocate shared memory (cross-origin isolated and
+/// `SharedArrayBuffer` defined)
+pub fn shared_memory_available() -> bool {
+    let global = js_sys::global();
+    js_sys::Reflect::get(&global, &"crossOriginIsolated".into())
+        .map(|v| v.is_truthy())
+        .unwrap_or(false)
+        && js_sys::Reflect::has(&global, &"SharedArrayBuffer".into()).unwrap_or(false)
+}
+
+/// Where `len` bytes starting at counter `pos` sit in a ring of `capacity`
+/// bytes: up to two `(offset, len)` runs, the second after wrapping
+fn segments(pos: u32, len: usize, capacity: usize) -> [(usize, usize); 2] {
+    let start = pos as usize % capacity;
+    let first = len.min(capacity - start);
+    [(start, first), (0, len - first)]
+}
+
+/// Lock-free SPSC byte ring in a `SharedArrayBuffer`
+pub struct SharedRing {
+    data: js_sys::Uint8Array,
+    counters: js_sys::Int32Array,
+    capacity: usize,
+}
+
+impl SharedRing {
+    /// A ring of `capacity` bytes, or `None` without shared memory
+    ///
+    /// `capacity` must be a power of two (up to 1 GiB) so ring positions
+    /// stay continuous when the counters wrap.
+    pub fn new(capacity: usize) -> Option<Self> {
+        if !capacity.is_power_of_two() || capacity > 1 << 30 || !shared_memory_available() {
+            return None;
+        }
+        let data = js_sys::Uint8Array::new(&js_sys::SharedArrayBuffer::new(capacity as u32));
+        let counters = js_sys::Int32Array::new(&js_sys::SharedArrayBuffer::new(8));
+        Some(Self {
+            data,
+            counters,
+            capacity,
+        })
+    }
+
+    fn counter(&self, index: u32) -> u32 {
+        js_sys::Atomics::load(&self.counters, index).unwrap_or(0) as u32
+    }
+
+    fn set_counter(&self, index: u32, value: u32) {
+        let _ = js_sys::Atomics::store(&self.counters, index, value as i32);
+    }
+
+    /// Bytes waiting to be read
+    pub fn len(&self) -> usize {
+        self.counter(HEAD).wrapping_sub(self.counter(TAIL)) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes that can be written before the reader catches up
+    pub fn free(&self) -> usize {
+        self.capacity - self.len()
+    }
+
+    /// Append all of `src`, or nothing if it doesn't fit
+    pub fn push(&self, src: &js_sys::Uint8Array) -> bool {
+        let len = src.length() as usize;
+        if len > self.free() {
+            return false;
+        }
+        let head = self.counter(HEAD);
+        let mut copied = 0;
+        for (offset, run) in segments(head, len, self.capacity) {
+            if run > 0 {
+                let part = src.subarray(copied as u32, (copied + run) as u32);
+                self.data.set(&part, offset as u32);
+                copied += run;
+            }
+        }
+        // Publish only once the bytes are in place
+        self.set_counter(HEAD, head.wrapping_add(len as u32));
+        true
+    }
+
+    /// Move up to `buf.len()` bytes into `buf`, returning how many
+    pub fn pop_into(&self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.len());
+        let tail = self.counter(TAIL);
+        let mut copied = 0;
+        for (offset, run) in segments(tail, len, self.capacity) {
+            if run > 0 {
+                self.data
+                    .subarray(offset as u32, (offset + run) as u32)
+                    .copy_to(&mut buf[copied..copied + run]);
+                copied += run;
+            }
+        }
+        self.set_counter(TAIL, tail.wrapping_add(len as u32));
+        len
+    }
+
+    /// Drop everything unread
+    pub fn clear(&self) {
+        self.set_counter(TAIL, self.counter(HEAD));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments_wrap_around() {
+        assert_eq!(segments(0, 10, 16), [(0, 10), (0, 0)]);
+        assert_eq!(segments(12, 10, 16), [(12, 4), (0, 6)]);
+        assert_eq!(segments(16, 16, 16), [(0, 16), (0, 0)]);
+        // Counters keep running past u32::MAX; capacities are powers of two
+        // so positions stay continuous across the wrap
+        assert_eq!(segments(u32::MAX, 2, 16), [(15, 1), (0, 1)]);
+        assert_eq!(segments(u32::MAX.wrapping_add(2), 1, 16), [(1, 1), (0, 0)]);
+    }
+}
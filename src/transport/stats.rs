@@ -0,0 +1,202 @@
+//! Per-connection transport statistics
+//!
+//! Each transport stream owns a [`SharedConnectionStats`] handle that its
+//! JS callbacks update in place. The network provider keeps a clone of every
+//! handle it hands out in a [`ConnectionStatsRegistry`], so totals survive
+//! after the stream itself is dropped. Useful for debugging flaky bridges.
+
+use crate::runtime::LocalCell;
+use std::rc::Rc;
+
+/// Counters for a single transport connection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Bytes handed to the transport for sending
+    pub bytes_sent: u64,
+
+    /// Bytes received from the transport
+    pub bytes_received: u64,
+
+    /// Frames/messages sent (WebSocket messages, DataChannel messages)
+    pub frames_sent: u64,
+
+    /// Frames/messages received
+    pub frames_received: u64,
+
+    /// Failed attempts before this connection was established
+    pub reconnects: u32,
+
+    /// Last observed `bufferedAmount` (bytes queued in the browser)
+    pub buffered_amount: u64,
+
+    /// Highest `bufferedAmount` seen over the connection lifetime
+    pub peak_buffered_amount: u64,
+
+    /// Number of ICE connection state changes (WebRTC only)
+    pub ice_state_transitions: u32,
+
+    /// Most recent ICE connection state (WebRTC only)
+    pub ice_state: Option<String>,
+}
+
+impl ConnectionStats {
+    /// Record an outgoing frame
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as u64;
+        self.frames_sent += 1;
+    }
+
+    /// Record an incoming frame
+    pub fn record_received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
+        self.frames_received += 1;
+    }
+
+    /// Record the browser's current send queue depth
+    pub fn record_buffered_amount(&mut self, amount: u64) {
+        self.buffered_amount = amount;
+        self.peak_buffered_amount = self.peak_buffered_amount.max(amount);
+    }
+
+    /// Record an ICE connection state change
+    pub fn record_ice_state(&mut self, state: String) {
+        if self.ice_state.as_deref() != Some(state.as_str()) {
+            self.ice_state_transitions += 1;
+            self.ice_state = Some(state);
+        }
+    }
+
+    /// Add another connection's cumulative counters into this one.
+    ///
+    /// Gauges (`buffered_amount`, `ice_state`) are not summed here; the
+    /// registry only counts them for live connections.
+    fn accumulate(&mut self, other: &ConnectionStats) {
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.frames_sent += other.frames_sent;
+        self.frames_received += other.frames_received;
+        self.reconnects += other.reconnects;
+        self.ice_state_transitions += other.ice_state_transitions;
+        self.peak_buffered_amount = self.peak_buffered_amount.max(other.peak_buffered_amount);
+    }
+}
+
+/// Stats handle shared between a stream and its observers
+pub type SharedConnectionStats = Rc<LocalCell<ConnectionStats>>;
+
+/// Create a fresh stats handle for a new connection
+pub fn new_shared_stats() -> SharedConnectionStats {
+    Rc::new(LocalCell::new(ConnectionStats::default()))
+}
+
+/// Aggregates stats across live and closed connections
+#[derive(Debug, Default)]
+pub struct ConnectionStatsRegistry {
+    /// Handles of connections that may still be alive
+    live: Vec<SharedConnectionStats>,
+
+    /// Totals folded in from connections that have been dropped
+    retired: ConnectionStats,
+}
+
+impl ConnectionStatsRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a connection
+    pub fn track(&mut self, handle: SharedConnectionStats) {
+        self.live.push(handle);
+    }
+
+    /// Number of tracked connections whose stream is still alive
+    pub fn live_count(&self) -> usize {
+        self.live.iter().filter(|h| Rc::strong_count(h) > 1).count()
+    }
+
+    /// Totals across all connections ever tracked.
+    ///
+    /// Connections whose stream has been dropped (we hold the last handle)
+    /// are folded into the retired totals and forgotten.
+    pub fn totals(&mut self) -> ConnectionStats {
+        let retired = &mut self.retired;
+        self.live.retain(|handle| {
+            if Rc::strong_count(handle) > 1 {
+                return true;
+            }
+            handle.with(|stats| retired.accumulate(stats));
+            false
+        });
+
+        let mut totals = self.retired.clone();
+        for handle in &self.live {
+            handle.with(|stats| {
+                totals.accumulate(stats);
+                totals.buffered_amount += stats.buffered_amount;
+            });
+        }
+        totals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_stats_counters() {
+        let mut stats = ConnectionStats::default();
+        stats.record_sent(514);
+        stats.record_sent(514);
+        stats.record_received(100);
+        stats.record_buffered_amount(2048);
+        stats.record_buffered_amount(0);
+
+        assert_eq!(stats.bytes_sent, 1028);
+        assert_eq!(stats.frames_sent, 2);
+        assert_eq!(stats.bytes_received, 100);
+        assert_eq!(stats.frames_received, 1);
+        assert_eq!(stats.buffered_amount, 0);
+        assert_eq!(stats.peak_buffered_amount, 2048);
+
+        stats.record_ice_state("checking".to_string());
+        stats.record_ice_state("checking".to_string());
+        stats.record_ice_state("connected".to_string());
+        assert_eq!(stats.ice_state_transitions, 2);
+        assert_eq!(stats.ice_state.as_deref(), Some("connected"));
+    }
+
+    #[test]
+    fn test_registry_keeps_totals_after_drop() {
+        let mut registry = ConnectionStatsRegistry::new();
+
+        let a = new_shared_stats();
+        let b = new_shared_stats();
+        registry.track(a.clone());
+        registry.track(b.clone());
+
+        a.with(|s| {
+            s.record_sent(10);
+            s.record_buffered_amount(5);
+        });
+        b.with(|s| {
+            s.record_received(20);
+            s.reconnects = 2;
+        });
+
+        let totals = registry.totals();
+        assert_eq!(registry.live_count(), 2);
+        assert_eq!(totals.bytes_sent, 10);
+        assert_eq!(totals.bytes_received, 20);
+        assert_eq!(totals.buffered_amount, 5);
+        assert_eq!(totals.reconnects, 2);
+
+        // Stream `a` goes away: counters are kept, the gauge is not
+        drop(a);
+        let totals = registry.totals();
+        assert_eq!(registry.live_count(), 1);
+        assert_eq!(totals.bytes_sent, 10);
+        assert_eq!(totals.buffered_amount, 0);
+    }
+}
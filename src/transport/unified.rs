@@ -5,7 +5,7 @@
 //! to return any transport type from its `connect()` method, enabling the
 //! transport fallback chain (WebSocket → meek → WebRTC).
 //!
-//! All three inner types are `!Send` (they use `Rc<UnsafeCell<_>>` and JS objects),
+//! All three inner types are `!Send` (they use `Rc<LocalCell<_>>` and JS objects),
 //! which is fine — WASM is single-threaded.
 
 use futures::io::{AsyncRead, AsyncWrite};
@@ -13,7 +13,10 @@ use std::io::Result as IoResult;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+use super::bridge_seal::SealedStream;
+use super::framed::FramedChannel;
 use super::meek::WasmMeekStream;
+use super::stats::{ConnectionStats, SharedConnectionStats};
 use super::webrtc::WasmRtcStream;
 use super::websocket::WasmTcpStream;
 use super::webtunnel::WasmWebTunnelStream;
@@ -38,6 +41,12 @@ pub enum TransportStream {
 
     /// WebTunnel — HTTPS WebSocket on a secret path, disguised as normal website
     WebTunnel(WasmWebTunnelStream),
+
+    /// One channel of a framed bridge WebSocket shared with other OR connections
+    Framed(FramedChannel),
+
+    /// Any of the above to a blinded target, sealed end to end with Bridge B
+    Sealed(Box<SealedStream<TransportStream>>),
 }
 
 impl TransportStream {
@@ -48,6 +57,33 @@ impl TransportStream {
             TransportStream::Meek(_) => "meek",
             TransportStream::WebRtc(_) => "webrtc",
             TransportStream::WebTunnel(_) => "webtunnel",
+            TransportStream::Framed(_) => "framed",
+            TransportStream::Sealed(stream) => stream.get_ref().transport_name(),
+        }
+    }
+
+    /// Connection-level counters, for transports that report them
+    /// (WebSocket and WebRTC)
+    pub fn stats(&self) -> Option<ConnectionStats> {
+        match self {
+            TransportStream::WebSocket(stream) => Some(stream.stats()),
+            TransportStream::WebRtc(stream) => Some(stream.stats()),
+            TransportStream::Sealed(stream) => stream.get_ref().stats(),
+            TransportStream::Meek(_)
+            | TransportStream::WebTunnel(_)
+            | TransportStream::Framed(_) => None,
+        }
+    }
+
+    /// Shared counter handle, for transports that report stats
+    pub fn stats_handle(&self) -> Option<SharedConnectionStats> {
+        match self {
+            TransportStream::WebSocket(stream) => Some(stream.stats_handle()),
+            TransportStream::WebRtc(stream) => Some(stream.stats_handle()),
+            TransportStream::Sealed(stream) => stream.get_ref().stats_handle(),
+            TransportStream::Meek(_)
+            | TransportStream::WebTunnel(_)
+            | TransportStream::Framed(_) => None,
         }
     }
 
@@ -79,12 +115,14 @@ impl AsyncRead for TransportStream {
         buf: &mut [u8],
     ) -> Poll<IoResult<usize>> {
         // SAFETY: We never move the inner value, only delegate to its poll_read.
-        // All inner types are Unpin (they use Rc<UnsafeCell<_>>), so this is safe.
+        // All inner types are Unpin (they use Rc<LocalCell<_>>), so this is safe.
         match self.get_mut() {
             TransportStream::WebSocket(stream) => Pin::new(stream).poll_read(cx, buf),
             TransportStream::Meek(stream) => Pin::new(stream).poll_read(cx, buf),
             TransportStream::WebRtc(stream) => Pin::new(stream).poll_read(cx, buf),
             TransportStream::WebTunnel(stream) => Pin::new(stream).poll_read(cx, buf),
+            TransportStream::Framed(stream) => Pin::new(stream).poll_read(cx, buf),
+            TransportStream::Sealed(stream) => Pin::new(stream).poll_read(cx, buf),
         }
     }
 }
@@ -96,6 +134,8 @@ impl AsyncWrite for TransportStream {
             TransportStream::Meek(stream) => Pin::new(stream).poll_write(cx, buf),
             TransportStream::WebRtc(stream) => Pin::new(stream).poll_write(cx, buf),
             TransportStream::WebTunnel(stream) => Pin::new(stream).poll_write(cx, buf),
+            TransportStream::Framed(stream) => Pin::new(stream).poll_write(cx, buf),
+            TransportStream::Sealed(stream) => Pin::new(stream).poll_write(cx, buf),
         }
     }
 
@@ -105,6 +145,8 @@ impl AsyncWrite for TransportStream {
             TransportStream::Meek(stream) => Pin::new(stream).poll_flush(cx),
             TransportStream::WebRtc(stream) => Pin::new(stream).poll_flush(cx),
             TransportStream::WebTunnel(stream) => Pin::new(stream).poll_flush(cx),
+            TransportStream::Framed(stream) => Pin::new(stream).poll_flush(cx),
+            TransportStream::Sealed(stream) => Pin::new(stream).poll_flush(cx),
         }
     }
 
@@ -114,6 +156,8 @@ impl AsyncWrite for TransportStream {
             TransportStream::Meek(stream) => Pin::new(stream).poll_close(cx),
             TransportStream::WebRtc(stream) => Pin::new(stream).poll_close(cx),
             TransportStream::WebTunnel(stream) => Pin::new(stream).poll_close(cx),
+            TransportStream::Framed(stream) => Pin::new(stream).poll_close(cx),
+            TransportStream::Sealed(stream) => Pin::new(stream).poll_close(cx),
         }
     }
 }
@@ -146,6 +190,12 @@ impl From<WasmWebTunnelStream> for TransportStream {
     }
 }
 
+impl From<FramedChannel> for TransportStream {
+    fn from(stream: FramedChannel) -> Self {
+        TransportStream::Framed(stream)
+    }
+}
+
 impl std::fmt::Debug for TransportStream {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "TransportStream::{}", self.transport_name())
@@ -0,0 +1,744 @@
+//! Volunteer proxy mode (Snowflake-style).
+//!
+//! Lets a visitor's browser tab act as a peer bridge for censored users,
+//! using the same WASM bundle as the client. This is the Rust counterpart of
+//! `proxy/proxy.js`:
+//!
+//! 1. Create an RTCPeerConnection + DataChannel and gather ICE candidates
+//! 2. Register the SDP offer with the broker (`{"type":"register"}`)
+//! 3. When the broker forwards a client's answer (`{"type":"connect"}`),
+//!    apply it and wait for the DataChannel to open
+//! 4. Relay bytes: DataChannel ↔ WebSocket ↔ bridge
+//! 5. When either side closes, tear down and re-register
+//!
+//! The first DataChannel message from a client (`WasmRtcStream::connect`) is
+//! the bridge URL it wants to reach. It is only honoured if it points at the
+//! configured bridge, so a volunteer can never be used as an open relay.
+//!
+//! ```javascript
+//! import init, { VolunteerProxy } from './pkg/tor_wasm.js';
+//! await init();
+//! const proxy = new VolunteerProxy('wss://broker.example', 'wss://bridge.example');
+//! await proxy.start();
+//! console.log(proxy.stats()); // { status, connections_served, bytes_relayed, ... }
+//! ```
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{
+    BinaryType, MessageEvent, RtcConfiguration, RtcDataChannel, RtcDataChannelEvent,
+    RtcDataChannelInit, RtcIceCandidate, RtcIceCandidateInit, RtcPeerConnection, RtcSdpType,
+    RtcSessionDescriptionInit, WebSocket,
+};
+
+use crate::runtime::LocalCell;
+
+/// STUN servers used for ICE gathering (same as `proxy/proxy.js`)
+const ICE_SERVERS: [&str; 2] = [
+    "stun:stun.l.google.com:19302",
+    "stun:stun1.l.google.com:19302",
+];
+
+/// Label of the DataChannel offered to clients
+const DATA_CHANNEL_LABEL: &str = "tor-transport";
+
+/// Delay before re-registering with the broker after a client leaves
+const REREGISTER_DELAY_MS: u32 = 2_000;
+
+/// Maximum bytes buffered for the bridge while its WebSocket is connecting
+const MAX_PENDING_BRIDGE_BYTES: usize = 256 * 1024;
+
+/// Lifecycle of the volunteer proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolunteerStatus {
+    /// Not started, or stopped
+    Idle,
+    /// Gathering ICE / registering with the broker
+    Connecting,
+    /// Registered, waiting for the broker to match a client
+    Waiting,
+    /// Client answer received, waiting for the DataChannel to open
+    ConnectingClient,
+    /// Relaying traffic between a client and the bridge
+    Relaying,
+    /// Broker connection lost
+    Disconnected,
+    /// Unrecoverable error during the current cycle
+    Error,
+}
+
+impl VolunteerStatus {
+    /// Status string exposed to JavaScript (matches `proxy/proxy.js`)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VolunteerStatus::Idle => "idle",
+            VolunteerStatus::Connecting => "connecting",
+            VolunteerStatus::Waiting => "waiting",
+            VolunteerStatus::ConnectingClient => "connecting-client",
+            VolunteerStatus::Relaying => "relaying",
+            VolunteerStatus::Disconnected => "disconnected",
+            VolunteerStatus::Error => "error",
+        }
+    }
+}
+
+/// Volunteer proxy statistics
+#[derive(Debug, Clone, Default)]
+pub struct VolunteerStats {
+    /// Number of clients that completed a DataChannel connection
+    pub connections_served: u64,
+    /// Bytes forwarded client → bridge
+    pub bytes_to_bridge: u64,
+    /// Bytes forwarded bridge → client
+    pub bytes_to_client: u64,
+    /// Number of times the proxy (re-)registered with the broker
+    pub registrations: u64,
+}
+
+/// Messages the broker sends to a registered proxy
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrokerMessage {
+    /// Registration accepted
+    Registered { proxy_id: String, pool_size: u64 },
+    /// A client was matched; apply its answer and ICE candidates
+    Connect {
+        sdp_answer: String,
+        ice_candidates: Vec<String>,
+    },
+    /// Broker-side error (e.g. pool full)
+    Error { message: String },
+}
+
+impl BrokerMessage {
+    /// Parse a broker text frame. Unknown message types return `None`.
+    ///
+    /// `sdp_answer` may be either a bare SDP string (what `WasmRtcStream`
+    /// sends) or an `RTCSessionDescriptionInit` object `{type, sdp}`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let msg: serde_json::Value = serde_json::from_str(text).ok()?;
+        match msg["type"].as_str()? {
+            "registered" => Some(BrokerMessage::Registered {
+                proxy_id: msg["proxy_id"].as_str()?.to_string(),
+                pool_size: msg["pool_size"].as_u64().unwrap_or(0),
+            }),
+            "connect" => {
+                let answer = &msg["sdp_answer"];
+                let sdp_answer = answer
+                    .as_str()
+                    .or_else(|| answer["sdp"].as_str())?
+                    .to_string();
+                let ice_candidates = msg["ice_candidates"]
+                    .as_array()
+                    .map(|arr| {
+                        arr.iter()
+                            .map(|c| serde_json::to_string(c).unwrap_or_default())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Some(BrokerMessage::Connect {
+                    sdp_answer,
+                    ice_candidates,
+                })
+            }
+            "error" => Some(BrokerMessage::Error {
+                message: msg["message"].as_str().unwrap_or("unknown").to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Decide which bridge URL to open for a client.
+///
+/// Clients send their full bridge URL (including `?addr=` or `?dest=`) as the
+/// first DataChannel message. It is accepted only when it targets the
+/// configured bridge; anything else falls back to the configured URL.
+pub fn resolve_bridge_target(configured: &str, requested: &[u8]) -> String {
+    let requested = match std::str::from_utf8(requested) {
+        Ok(s) => s.trim(),
+        Err(_) => return configured.to_string(),
+    };
+
+    let rest = match requested.strip_prefix(configured) {
+        Some(rest) => rest,
+        None => return configured.to_string(),
+    };
+
+    // Only a query string may follow; reject path/host extensions such as
+    // "wss://bridge.example.evil.com" or "wss://bridge.example/../other".
+    if rest.is_empty() || rest.starts_with('?') {
+        requested.to_string()
+    } else {
+        configured.to_string()
+    }
+}
+
+/// State shared between the proxy handle and its JS callbacks.
+struct ProxyState {
+    broker_url: String,
+    bridge_url: String,
+    running: bool,
+    status: VolunteerStatus,
+    proxy_id: Option<String>,
+    stats: VolunteerStats,
+    pc: Option<RtcPeerConnection>,
+    broker: Option<WebSocket>,
+    bridge: Option<WebSocket>,
+    client_dc: Option<RtcDataChannel>,
+    /// Client → bridge data received before the bridge WebSocket opened
+    pending_to_bridge: VecDeque<Vec<u8>>,
+    pending_bytes: usize,
+}
+
+type SharedState = Rc<LocalCell<ProxyState>>;
+
+/// A browser tab acting as a peer bridge for censored clients.
+#[wasm_bindgen]
+pub struct VolunteerProxy {
+    state: SharedState,
+}
+
+#[wasm_bindgen]
+impl VolunteerProxy {
+    /// Create a volunteer proxy for the given broker and bridge.
+    #[wasm_bindgen(constructor)]
+    pub fn new(broker_url: String, bridge_url: String) -> VolunteerProxy {
+        Self {
+            state: Rc::new(LocalCell::new(ProxyState {
+                broker_url,
+                bridge_url,
+                running: false,
+                status: VolunteerStatus::Idle,
+                proxy_id: None,
+                stats: VolunteerStats::default(),
+                pc: None,
+                broker: None,
+                bridge: None,
+                client_dc: None,
+                pending_to_bridge: VecDeque::new(),
+                pending_bytes: 0,
+            })),
+        }
+    }
+
+    /// Register with the broker and start serving clients.
+    ///
+    /// Resolves once the offer has been sent to the broker. The proxy keeps
+    /// re-registering after each client until `stop()` is called.
+    #[wasm_bindgen]
+    pub async fn start(&self) -> Result<(), JsValue> {
+        if self
+            .state
+            .with(|st| std::mem::replace(&mut st.running, true))
+        {
+            return Ok(());
+        }
+        log::info!("🤝 Volunteer proxy starting");
+        register(self.state.clone()).await
+    }
+
+    /// Stop serving: close the broker, bridge and peer connections.
+    #[wasm_bindgen]
+    pub fn stop(&self) {
+        self.state.with(|st| st.running = false);
+        teardown(&self.state);
+        set_status(&self.state, VolunteerStatus::Idle);
+        log::info!("🤝 Volunteer proxy stopped");
+    }
+
+    /// Current status string (`idle`, `waiting`, `relaying`, ...)
+    #[wasm_bindgen]
+    pub fn status(&self) -> String {
+        self.state.with(|st| st.status.as_str().to_string())
+    }
+
+    /// Relay statistics as a JS object
+    #[wasm_bindgen]
+    pub fn stats(&self) -> JsValue {
+        let snapshot = self.state.with(|st| {
+            serde_json::json!({
+                "status": st.status.as_str(),
+                "proxy_id": st.proxy_id,
+                "connections_served": st.stats.connections_served,
+                "bytes_to_bridge": st.stats.bytes_to_bridge,
+                "bytes_to_client": st.stats.bytes_to_client,
+                "bytes_relayed": st.stats.bytes_to_bridge + st.stats.bytes_to_client,
+                "registrations": st.stats.registrations,
+            })
+        });
+        serde_wasm_bindgen::to_value(&snapshot).unwrap_or(JsValue::NULL)
+    }
+}
+
+fn set_status(state: &SharedState, status: VolunteerStatus) {
+    state.with(|st| st.status = status);
+    log::debug!("Volunteer proxy status: {}", status.as_str());
+}
+
+/// Close every connection belonging to the current client cycle.
+fn teardown(state: &SharedState) {
+    let (client_dc, bridge, broker, pc) = state.with(|st| {
+        st.pending_to_bridge.clear();
+        st.pending_bytes = 0;
+        st.proxy_id = None;
+        (
+            st.client_dc.take(),
+            st.bridge.take(),
+            st.broker.take(),
+            st.pc.take(),
+        )
+    });
+    if let Some(dc) = client_dc {
+        dc.set_onmessage(None);
+        dc.set_onclose(None);
+        dc.close();
+    }
+    if let Some(ws) = bridge {
+        ws.set_onclose(None);
+        let _ = ws.close();
+    }
+    if let Some(ws) = broker {
+        ws.set_onclose(None);
+        let _ = ws.close();
+    }
+    if let Some(pc) = pc {
+        pc.close();
+    }
+}
+
+/// Tear down the current cycle and register again after a short delay.
+fn schedule_reregister(state: &SharedState) {
+    teardown(state);
+    if !state.with(|st| st.running) {
+        return;
+    }
+    set_status(state, VolunteerStatus::Waiting);
+
+    let state = state.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        gloo_timers::future::TimeoutFuture::new(REREGISTER_DELAY_MS).await;
+        if state.with(|st| st.running) {
+            log::info!("🤝 Re-registering with broker...");
+            if let Err(e) = register(state.clone()).await {
+                log::warn!("Volunteer re-registration failed: {:?}", e);
+                set_status(&state, VolunteerStatus::Error);
+            }
+        }
+    });
+}
+
+/// One registration cycle: offer → broker → wait for a client.
+async fn register(state: SharedState) -> Result<(), JsValue> {
+    set_status(&state, VolunteerStatus::Connecting);
+
+    let config = RtcConfiguration::new();
+    let ice_servers = js_sys::Array::new();
+    for url in ICE_SERVERS {
+        let server = js_sys::Object::new();
+        js_sys::Reflect::set(&server, &"urls".into(), &url.into())?;
+        ice_servers.push(&server);
+    }
+    config.set_ice_servers(&ice_servers);
+    let pc = RtcPeerConnection::new_with_configuration(&config)?;
+
+    let dc_init = RtcDataChannelInit::new();
+    dc_init.set_ordered(true);
+    dc_init.set_protocol("binary");
+    let dc = pc.create_data_channel_with_data_channel_dict(DATA_CHANNEL_LABEL, &dc_init);
+    dc.set_binary_type(web_sys::RtcDataChannelType::Arraybuffer);
+
+    // Collect ICE candidates until gathering completes
+    let candidates: Rc<LocalCell<Vec<serde_json::Value>>> = Rc::new(LocalCell::new(Vec::new()));
+    let gathered = {
+        let candidates = candidates.clone();
+        let pc_clone = pc.clone();
+        js_sys::Promise::new(&mut move |resolve, _reject| {
+            let candidates = candidates.clone();
+            let cb = Closure::wrap(Box::new(move |event: JsValue| {
+                let event: web_sys::RtcPeerConnectionIceEvent = event.unchecked_into();
+                match event.candidate() {
+                    Some(candidate) => {
+                        let json = js_sys::JSON::stringify(&candidate.to_json())
+                            .ok()
+                            .and_then(|s| s.as_string())
+                            .and_then(|s| serde_json::from_str(&s).ok());
+                        if let Some(json) = json {
+                            candidates.with(|c| c.push(json));
+                        }
+                    }
+                    None => {
+                        let _ = resolve.call0(&JsValue::NULL);
+                    }
+                }
+            }) as Box<dyn FnMut(JsValue)>);
+            pc_clone.set_onicecandidate(Some(cb.as_ref().unchecked_ref()));
+            cb.forget();
+        })
+    };
+
+    let offer = wasm_bindgen_futures::JsFuture::from(pc.create_offer()).await?;
+    let offer: RtcSessionDescriptionInit = offer.unchecked_into();
+    wasm_bindgen_futures::JsFuture::from(pc.set_local_description(&offer)).await?;
+    wasm_bindgen_futures::JsFuture::from(gathered).await?;
+
+    let local = pc
+        .local_description()
+        .ok_or_else(|| JsValue::from_str("No local description after createOffer"))?;
+    let register_msg = serde_json::json!({
+        "type": "register",
+        "sdp_offer": { "type": "offer", "sdp": local.sdp() },
+        "ice_candidates": candidates.get_cloned(),
+    })
+    .to_string();
+
+    // Client DataChannel: either ours opens, or the client creates one
+    {
+        let state_open = state.clone();
+        let dc_clone = dc.clone();
+        let on_open = Closure::wrap(Box::new(move |_: JsValue| {
+            setup_relay(&state_open, dc_clone.clone());
+        }) as Box<dyn FnMut(JsValue)>);
+        dc.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        on_open.forget();
+
+        let state_dc = state.clone();
+        let on_channel = Closure::wrap(Box::new(move |event: JsValue| {
+            let event: RtcDataChannelEvent = event.unchecked_into();
+            let channel = event.channel();
+            channel.set_binary_type(web_sys::RtcDataChannelType::Arraybuffer);
+            setup_relay(&state_dc, channel);
+        }) as Box<dyn FnMut(JsValue)>);
+        pc.set_ondatachannel(Some(on_channel.as_ref().unchecked_ref()));
+        on_channel.forget();
+    }
+
+    let broker_url = state.with(|st| st.broker_url.clone());
+    let broker = WebSocket::new(&broker_url)?;
+
+    {
+        let broker_clone = broker.clone();
+        let on_open = Closure::once_into_js(move || {
+            let _ = broker_clone.send_with_str(&register_msg);
+        });
+        broker.set_onopen(Some(on_open.unchecked_ref()));
+    }
+
+    {
+        let state_msg = state.clone();
+        let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+            if let Some(text) = event.data().as_string() {
+                handle_broker_message(&state_msg, &text);
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        broker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        on_message.forget();
+    }
+
+    {
+        let state_close = state.clone();
+        let on_close = Closure::wrap(Box::new(move |_: JsValue| {
+            log::info!("Broker connection closed");
+            let waiting = state_close.with(|st| {
+                st.broker = None;
+                st.status == VolunteerStatus::Waiting
+            });
+            if waiting {
+                set_status(&state_close, VolunteerStatus::Disconnected);
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+        broker.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        on_close.forget();
+    }
+
+    state.with(|st| {
+        st.pc = Some(pc);
+        st.broker = Some(broker);
+        st.stats.registrations += 1;
+    });
+
+    Ok(())
+}
+
+fn handle_broker_message(state: &SharedState, text: &str) {
+    match BrokerMessage::parse(text) {
+        Some(BrokerMessage::Registered {
+            proxy_id,
+            pool_size,
+        }) => {
+            log::info!("🤝 Registered with broker (pool size: {})", pool_size);
+            state.with(|st| st.proxy_id = Some(proxy_id));
+            set_status(state, VolunteerStatus::Waiting);
+        }
+        Some(BrokerMessage::Connect {
+            sdp_answer,
+            ice_candidates,
+        }) => {
+            log::info!("🤝 Client matched, applying answer");
+            set_status(state, VolunteerStatus::ConnectingClient);
+            let state = state.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(e) = apply_answer(&state, &sdp_answer, &ice_candidates).await {
+                    log::warn!("Failed to apply client answer: {:?}", e);
+                    set_status(&state, VolunteerStatus::Error);
+                    schedule_reregister(&state);
+                }
+            });
+        }
+        Some(BrokerMessage::Error { message }) => {
+            log::warn!("Broker error: {}", message);
+        }
+        None => log::debug!("Ignoring unknown broker message"),
+    }
+}
+
+async fn apply_answer(
+    state: &SharedState,
+    sdp_answer: &str,
+    ice_candidates: &[String],
+) -> Result<(), JsValue> {
+    let pc = state
+        .with(|st| st.pc.clone())
+        .ok_or_else(|| JsValue::from_str("No peer connection"))?;
+
+    let answer = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+    answer.set_sdp(sdp_answer);
+    wasm_bindgen_futures::JsFuture::from(pc.set_remote_description(&answer)).await?;
+
+    for candidate_json in ice_candidates {
+        let candidate_obj = match js_sys::JSON::parse(candidate_json) {
+            Ok(obj) => obj,
+            Err(_) => continue,
+        };
+        let init = RtcIceCandidateInit::new("");
+        if let Some(c) = js_sys::Reflect::get(&candidate_obj, &"candidate".into())
+            .ok()
+            .and_then(|v| v.as_string())
+        {
+            init.set_candidate(&c);
+        }
+        if let Some(mid) = js_sys::Reflect::get(&candidate_obj, &"sdpMid".into())
+            .ok()
+            .and_then(|v| v.as_string())
+        {
+            init.set_sdp_mid(Some(&mid));
+        }
+        if let Some(idx) = js_sys::Reflect::get(&candidate_obj, &"sdpMLineIndex".into())
+            .ok()
+            .and_then(|v| v.as_f64())
+        {
+            init.set_sdp_m_line_index(Some(idx as u16));
+        }
+        if let Ok(ice) = RtcIceCandidate::new(&init) {
+            let _ = pc.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&ice));
+        }
+    }
+
+    Ok(())
+}
+
+/// Wire a client DataChannel to a fresh bridge WebSocket.
+fn setup_relay(state: &SharedState, dc: RtcDataChannel) {
+    let first = state.with(|st| {
+        if st.client_dc.is_some() {
+            return false;
+        }
+        st.client_dc = Some(dc.clone());
+        st.stats.connections_served += 1;
+        true
+    });
+    if !first {
+        // Both our channel and the client's fired; serve only one
+        return;
+    }
+    log::info!("🤝 Client DataChannel open, relaying");
+    set_status(state, VolunteerStatus::Relaying);
+
+    // Client → bridge. The first message selects the bridge target.
+    {
+        let state_msg = state.clone();
+        let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+            let data = match event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                Ok(buf) => js_sys::Uint8Array::new(&buf).to_vec(),
+                Err(_) => match event.data().as_string() {
+                    Some(s) => s.into_bytes(),
+                    None => return,
+                },
+            };
+            let has_bridge = state_msg.with(|st| st.bridge.is_some());
+            if !has_bridge {
+                open_bridge(&state_msg, &data);
+            } else {
+                forward_to_bridge(&state_msg, data);
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        dc.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        on_message.forget();
+    }
+
+    {
+        let state_close = state.clone();
+        let on_close = Closure::wrap(Box::new(move |_: JsValue| {
+            log::info!("Client DataChannel closed");
+            schedule_reregister(&state_close);
+        }) as Box<dyn FnMut(JsValue)>);
+        dc.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        on_close.forget();
+    }
+}
+
+fn open_bridge(state: &SharedState, first_message: &[u8]) {
+    let target = state.with(|st| resolve_bridge_target(&st.bridge_url, first_message));
+    log::info!("🤝 Opening bridge WebSocket");
+
+    let ws = match WebSocket::new(&target) {
+        Ok(ws) => ws,
+        Err(e) => {
+            log::warn!("Failed to open bridge WebSocket: {:?}", e);
+            schedule_reregister(state);
+            return;
+        }
+    };
+    ws.set_binary_type(BinaryType::Arraybuffer);
+
+    {
+        let state_open = state.clone();
+        let ws_clone = ws.clone();
+        let on_open = Closure::once_into_js(move || {
+            log::info!("Bridge WebSocket connected");
+            let pending = state_open.with(|st| {
+                st.pending_bytes = 0;
+                std::mem::take(&mut st.pending_to_bridge)
+            });
+            for chunk in pending {
+                let array = js_sys::Uint8Array::from(&chunk[..]);
+                let _ = ws_clone.send_with_array_buffer(&array.buffer());
+            }
+        });
+        ws.set_onopen(Some(on_open.unchecked_ref()));
+    }
+
+    // Bridge → client
+    {
+        let state_msg = state.clone();
+        let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+            if let Ok(buf) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                if let Some(dc) = state_msg.with(|st| st.client_dc.clone()) {
+                    if dc.ready_state() == web_sys::RtcDataChannelState::Open
+                        && dc.send_with_array_buffer(&buf).is_ok()
+                    {
+                        let len = buf.byte_length() as u64;
+                        state_msg.with(|st| st.stats.bytes_to_client += len);
+                    }
+                }
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        on_message.forget();
+    }
+
+    {
+        let state_close = state.clone();
+        let on_close = Closure::wrap(Box::new(move |_: JsValue| {
+            log::info!("Bridge disconnected");
+            schedule_reregister(&state_close);
+        }) as Box<dyn FnMut(JsValue)>);
+        ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        on_close.forget();
+    }
+
+    state.with(|st| st.bridge = Some(ws));
+}
+
+fn forward_to_bridge(state: &SharedState, data: Vec<u8>) {
+    let ws = match state.with(|st| st.bridge.clone()) {
+        Some(ws) => ws,
+        None => return,
+    };
+
+    let overflow = if ws.ready_state() == WebSocket::OPEN {
+        let array = js_sys::Uint8Array::from(&data[..]);
+        if ws.send_with_array_buffer(&array.buffer()).is_ok() {
+            state.with(|st| st.stats.bytes_to_bridge += data.len() as u64);
+        }
+        false
+    } else {
+        state.with(|st| {
+            if st.pending_bytes + data.len() <= MAX_PENDING_BRIDGE_BYTES {
+                st.stats.bytes_to_bridge += data.len() as u64;
+                st.pending_bytes += data.len();
+                st.pending_to_bridge.push_back(data);
+                false
+            } else {
+                true
+            }
+        })
+    };
+
+    if overflow {
+        log::warn!("Bridge not open and pending buffer full, dropping client");
+        schedule_reregister(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_broker_messages() {
+        assert_eq!(
+            BrokerMessage::parse(r#"{"type":"registered","proxy_id":"abc","pool_size":3}"#),
+            Some(BrokerMessage::Registered {
+                proxy_id: "abc".to_string(),
+                pool_size: 3,
+            })
+        );
+
+        // Bare SDP string (WasmRtcStream) and RTCSessionDescriptionInit object
+        let bare = BrokerMessage::parse(
+            r#"{"type":"connect","sdp_answer":"v=0","ice_candidates":[{"candidate":"c"}]}"#,
+        );
+        let object = BrokerMessage::parse(
+            r#"{"type":"connect","sdp_answer":{"type":"answer","sdp":"v=0"},"ice_candidates":[]}"#,
+        );
+        match bare {
+            Some(BrokerMessage::Connect {
+                sdp_answer,
+                ice_candidates,
+            }) => {
+                assert_eq!(sdp_answer, "v=0");
+                assert_eq!(ice_candidates, vec![r#"{"candidate":"c"}"#.to_string()]);
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert!(
+            matches!(object, Some(BrokerMessage::Connect { ref sdp_answer, .. }) if sdp_answer == "v=0")
+        );
+
+        assert_eq!(BrokerMessage::parse(r#"{"type":"answer_sent"}"#), None);
+        assert_eq!(BrokerMessage::parse("not json"), None);
+    }
+
+    #[test]
+    fn test_resolve_bridge_target() {
+        let bridge = "wss://bridge.example";
+        assert_eq!(
+            resolve_bridge_target(bridge, b"wss://bridge.example?addr=1.2.3.4:9001"),
+            "wss://bridge.example?addr=1.2.3.4:9001"
+        );
+        assert_eq!(resolve_bridge_target(bridge, bridge.as_bytes()), bridge);
+
+        // Anything not pointing at the configured bridge is ignored
+        assert_eq!(
+            resolve_bridge_target(bridge, b"wss://bridge.example.evil.com?addr=x"),
+            bridge
+        );
+        assert_eq!(
+            resolve_bridge_target(bridge, b"wss://other.example"),
+            bridge
+        );
+        assert_eq!(resolve_bridge_target(bridge, &[0xff, 0xfe]), bridge);
+    }
+}
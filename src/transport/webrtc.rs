@@ -10,7 +10,6 @@
 //! The volunteer proxy sees only encrypted bytes (TLS end-to-end).
 
 use futures::io::{AsyncRead, AsyncWrite};
-use std::cell::UnsafeCell;
 use std::collections::VecDeque;
 use std::io::{self, Result as IoResult};
 use std::pin::Pin;
@@ -23,6 +22,9 @@ use web_sys::{
     RtcIceCandidateInit, RtcPeerConnection, RtcSdpType, RtcSessionDescriptionInit,
 };
 
+use super::stats::{new_shared_stats, ConnectionStats, SharedConnectionStats};
+use crate::runtime::LocalCell;
+
 /// Connection state for the WebRTC peer connection
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum RtcState {
@@ -37,7 +39,7 @@ enum RtcState {
 }
 
 /// Inner state shared between callbacks and async methods.
-/// UnsafeCell is safe because WASM is single-threaded.
+/// Accessed only through `LocalCell::with`; wakers are woken outside it.
 struct RtcStreamState {
     state: RtcState,
     recv_buffer: VecDeque<u8>,
@@ -73,7 +75,9 @@ impl RtcStreamState {
 pub struct WasmRtcStream {
     _pc: RtcPeerConnection,
     dc: RtcDataChannel,
-    state: Rc<UnsafeCell<RtcStreamState>>,
+    state: Rc<LocalCell<RtcStreamState>>,
+    /// Connection-level counters (bytes, frames, ICE transitions)
+    stats: SharedConnectionStats,
     // Store closures to prevent garbage collection
     _closures: Vec<Closure<dyn FnMut(JsValue)>>,
 }
@@ -109,26 +113,41 @@ impl WasmRtcStream {
         let pc = RtcPeerConnection::new_with_configuration(&config)
             .map_err(|e| io::Error::other(format!("RtcPeerConnection::new failed: {:?}", e)))?;
 
-        let state = Rc::new(UnsafeCell::new(RtcStreamState::new()));
+        let state = Rc::new(LocalCell::new(RtcStreamState::new()));
+        let stats = new_shared_stats();
         let mut closures: Vec<Closure<dyn FnMut(JsValue)>> = Vec::new();
 
+        // Track ICE connection state transitions for diagnostics
+        {
+            let stats_clone = stats.clone();
+            let pc_clone = pc.clone();
+            let cb = Closure::wrap(Box::new(move |_: JsValue| {
+                let ice_state = format!("{:?}", pc_clone.ice_connection_state()).to_lowercase();
+                log::debug!("ICE connection state: {}", ice_state);
+                stats_clone.with(|stats| stats.record_ice_state(ice_state));
+            }) as Box<dyn FnMut(JsValue)>);
+            pc.set_oniceconnectionstatechange(Some(cb.as_ref().unchecked_ref()));
+            closures.push(cb);
+        }
+
         // Set up ICE candidate handler
         {
             let state_clone = state.clone();
             let cb = Closure::wrap(Box::new(move |event: JsValue| {
                 let event: web_sys::RtcPeerConnectionIceEvent = event.unchecked_into();
-                unsafe {
-                    let st = &mut *state_clone.get();
-                    if let Some(candidate) = event.candidate() {
-                        if let Ok(json) = js_sys::JSON::stringify(&candidate) {
-                            st.ice_candidates.push(json.as_string().unwrap_or_default());
-                        }
-                    } else {
-                        // ICE gathering complete
+                if let Some(candidate) = event.candidate() {
+                    if let Ok(json) = js_sys::JSON::stringify(&candidate) {
+                        let json = json.as_string().unwrap_or_default();
+                        state_clone.with(|st| st.ice_candidates.push(json));
+                    }
+                } else {
+                    // ICE gathering complete
+                    let waker = state_clone.with(|st| {
                         st.ice_complete = true;
-                        if let Some(waker) = st.ice_waker.take() {
-                            waker.wake();
-                        }
+                        st.ice_waker.take()
+                    });
+                    if let Some(waker) = waker {
+                        waker.wake();
                     }
                 }
             }) as Box<dyn FnMut(JsValue)>);
@@ -187,13 +206,14 @@ impl WasmRtcStream {
         {
             let state_clone = state.clone();
             futures::future::poll_fn(|cx| {
-                let st = unsafe { &mut *state_clone.get() };
-                if st.ice_complete {
-                    Poll::Ready(())
-                } else {
-                    st.ice_waker = Some(cx.waker().clone());
-                    Poll::Pending
-                }
+                state_clone.with(|st| {
+                    if st.ice_complete {
+                        Poll::Ready(())
+                    } else {
+                        st.ice_waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                })
             })
             .await;
         }
@@ -203,18 +223,19 @@ impl WasmRtcStream {
             .local_description()
             .ok_or_else(|| io::Error::other("No local description after createAnswer"))?;
         let sdp_answer = local_desc.sdp();
-        let our_candidates: Vec<String> = unsafe { (*state.get()).ice_candidates.clone() };
+        let our_candidates: Vec<String> = state.with(|st| st.ice_candidates.clone());
 
         // Send answer back to broker
         Self::send_answer(broker_url, &proxy_id, &sdp_answer, &our_candidates).await?;
 
         // Set up DataChannel handler (we receive the proxy's data channel)
         let dc_state = state.clone();
-        let dc_ready = Rc::new(UnsafeCell::new(None::<RtcDataChannel>));
+        let dc_ready = Rc::new(LocalCell::new(None::<RtcDataChannel>));
         let dc_ready_clone = dc_ready.clone();
 
         {
             let state_clone = state.clone();
+            let stats_clone = stats.clone();
             let dc_ready_inner = dc_ready.clone();
             let cb = Closure::wrap(Box::new(move |event: JsValue| {
                 let event: RtcDataChannelEvent = event.unchecked_into();
@@ -223,17 +244,19 @@ impl WasmRtcStream {
 
                 // Set up data handlers on the received channel
                 let state_for_msg = state_clone.clone();
+                let stats_for_msg = stats_clone.clone();
                 let on_message = Closure::wrap(Box::new(move |event: JsValue| {
                     let event: MessageEvent = event.unchecked_into();
                     if let Ok(buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
                         let array = js_sys::Uint8Array::new(&buffer);
                         let data = array.to_vec();
-                        unsafe {
-                            let st = &mut *state_for_msg.get();
+                        stats_for_msg.with(|stats| stats.record_received(data.len()));
+                        let waker = state_for_msg.with(|st| {
                             st.recv_buffer.extend(data);
-                            if let Some(waker) = st.read_waker.take() {
-                                waker.wake();
-                            }
+                            st.read_waker.take()
+                        });
+                        if let Some(waker) = waker {
+                            waker.wake();
                         }
                     }
                 }) as Box<dyn FnMut(JsValue)>);
@@ -243,23 +266,21 @@ impl WasmRtcStream {
                 let state_for_open = dc_state.clone();
                 let on_open = Closure::wrap(Box::new(move |_: JsValue| {
                     log::info!("Peer DataChannel opened");
-                    unsafe {
-                        let st = &mut *state_for_open.get();
+                    let (write, read) = state_for_open.with(|st| {
                         st.state = RtcState::Connected;
-                        if let Some(waker) = st.write_waker.take() {
-                            waker.wake();
-                        }
-                        if let Some(waker) = st.read_waker.take() {
-                            waker.wake();
-                        }
+                        (st.write_waker.take(), st.read_waker.take())
+                    });
+                    if let Some(waker) = write {
+                        waker.wake();
+                    }
+                    if let Some(waker) = read {
+                        waker.wake();
                     }
                 }) as Box<dyn FnMut(JsValue)>);
                 channel.set_onopen(Some(on_open.as_ref().unchecked_ref()));
                 on_open.forget();
 
-                unsafe {
-                    *dc_ready_inner.get() = Some(channel);
-                }
+                dc_ready_inner.replace(Some(channel));
             }) as Box<dyn FnMut(JsValue)>);
             pc.set_ondatachannel(Some(cb.as_ref().unchecked_ref()));
             closures.push(cb);
@@ -271,7 +292,7 @@ impl WasmRtcStream {
             let state_clone = state.clone();
             async move {
                 loop {
-                    let current = unsafe { (*state_clone.get()).state };
+                    let current = state_clone.with(|st| st.state);
                     match current {
                         RtcState::Connected => return Ok(()),
                         RtcState::Closed | RtcState::Closing => {
@@ -304,11 +325,9 @@ impl WasmRtcStream {
         }
 
         // Get the data channel
-        let dc = unsafe {
-            (*dc_ready_clone.get())
-                .take()
-                .ok_or_else(|| io::Error::other("No DataChannel received"))?
-        };
+        let dc = dc_ready_clone
+            .replace(None)
+            .ok_or_else(|| io::Error::other("No DataChannel received"))?;
 
         // Send bridge URL as first message so proxy knows where to connect
         let bridge_msg = bridge_url.as_bytes();
@@ -316,6 +335,7 @@ impl WasmRtcStream {
         array.copy_from(bridge_msg);
         dc.send_with_array_buffer(&array.buffer())
             .map_err(|e| io::Error::other(format!("Failed to send bridge URL: {:?}", e)))?;
+        stats.with(|stats| stats.record_sent(bridge_msg.len()));
 
         log::info!("WebRTC peer bridge connected successfully");
 
@@ -323,10 +343,25 @@ impl WasmRtcStream {
             _pc: pc,
             dc,
             state,
+            stats,
             _closures: closures,
         })
     }
 
+    /// Snapshot of this connection's counters
+    pub fn stats(&self) -> ConnectionStats {
+        let buffered = self.dc.buffered_amount() as u64;
+        self.stats.with(|stats| {
+            stats.record_buffered_amount(buffered);
+            stats.clone()
+        })
+    }
+
+    /// Shared handle to this connection's counters (for aggregation)
+    pub fn stats_handle(&self) -> SharedConnectionStats {
+        self.stats.clone()
+    }
+
     /// Contact broker to request a volunteer proxy.
     /// Returns (sdp_offer, ice_candidates, proxy_id).
     async fn request_proxy(broker_url: &str) -> IoResult<(String, Vec<String>, String)> {
@@ -339,9 +374,9 @@ impl WasmRtcStream {
         })?;
         ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
 
-        let result: Rc<UnsafeCell<Option<IoResult<(String, Vec<String>, String)>>>> =
-            Rc::new(UnsafeCell::new(None));
-        let waker: Rc<UnsafeCell<Option<Waker>>> = Rc::new(UnsafeCell::new(None));
+        let result: Rc<LocalCell<Option<IoResult<(String, Vec<String>, String)>>>> =
+            Rc::new(LocalCell::new(None));
+        let waker: Rc<LocalCell<Option<Waker>>> = Rc::new(LocalCell::new(None));
 
         // On open: send request
         {
@@ -381,22 +416,20 @@ impl WasmRtcStream {
                                 let proxy_id =
                                     msg["proxy_id"].as_str().unwrap_or_default().to_string();
 
-                                unsafe {
-                                    *result_clone.get() = Some(Ok((offer, candidates, proxy_id)));
-                                    if let Some(w) = (*waker_clone.get()).take() {
-                                        w.wake();
-                                    }
+                                result_clone.replace(Some(Ok((offer, candidates, proxy_id))));
+                                if let Some(w) = waker_clone.replace(None) {
+                                    w.wake();
                                 }
                             }
-                            "no_proxies" => unsafe {
-                                *result_clone.get() = Some(Err(io::Error::new(
+                            "no_proxies" => {
+                                result_clone.replace(Some(Err(io::Error::new(
                                     io::ErrorKind::NotConnected,
                                     "No volunteer proxies available",
-                                )));
-                                if let Some(w) = (*waker_clone.get()).take() {
+                                ))));
+                                if let Some(w) = waker_clone.replace(None) {
                                     w.wake();
                                 }
-                            },
+                            }
                             _ => {}
                         }
                     }
@@ -411,12 +444,12 @@ impl WasmRtcStream {
         {
             let result_clone = result.clone();
             let waker_clone = waker.clone();
-            let cb = Closure::wrap(Box::new(move |_: JsValue| unsafe {
-                *result_clone.get() = Some(Err(io::Error::new(
+            let cb = Closure::wrap(Box::new(move |_: JsValue| {
+                result_clone.replace(Some(Err(io::Error::new(
                     io::ErrorKind::ConnectionRefused,
                     "Broker connection failed",
-                )));
-                if let Some(w) = (*waker_clone.get()).take() {
+                ))));
+                if let Some(w) = waker_clone.replace(None) {
                     w.wake();
                 }
             }) as Box<dyn FnMut(JsValue)>);
@@ -428,23 +461,18 @@ impl WasmRtcStream {
         let result_clone = result.clone();
         let waker_clone = waker.clone();
         futures::future::poll_fn(move |cx| {
-            let val = unsafe { &*result_clone.get() };
-            if val.is_some() {
+            if result_clone.with(|val| val.is_some()) {
                 Poll::Ready(())
             } else {
-                unsafe {
-                    *waker_clone.get() = Some(cx.waker().clone());
-                }
+                waker_clone.replace(Some(cx.waker().clone()));
                 Poll::Pending
             }
         })
         .await;
 
-        unsafe {
-            (*result.get())
-                .take()
-                .unwrap_or_else(|| Err(io::Error::other("No result from broker")))
-        }
+        result
+            .replace(None)
+            .unwrap_or_else(|| Err(io::Error::other("No result from broker")))
     }
 
     /// Send SDP answer back to broker for the matched proxy.
@@ -461,8 +489,8 @@ impl WasmRtcStream {
             )
         })?;
 
-        let done: Rc<UnsafeCell<bool>> = Rc::new(UnsafeCell::new(false));
-        let done_waker: Rc<UnsafeCell<Option<Waker>>> = Rc::new(UnsafeCell::new(None));
+        let done: Rc<LocalCell<bool>> = Rc::new(LocalCell::new(false));
+        let done_waker: Rc<LocalCell<Option<Waker>>> = Rc::new(LocalCell::new(None));
 
         let candidates_json: Vec<serde_json::Value> = ice_candidates
             .iter()
@@ -483,11 +511,9 @@ impl WasmRtcStream {
             let ws_clone = ws.clone();
             let cb = Closure::wrap(Box::new(move |_: JsValue| {
                 let _ = ws_clone.send_with_str(&msg);
-                unsafe {
-                    *done_clone.get() = true;
-                    if let Some(w) = (*done_waker_clone.get()).take() {
-                        w.wake();
-                    }
+                done_clone.replace(true);
+                if let Some(w) = done_waker_clone.replace(None) {
+                    w.wake();
                 }
                 let _ = ws_clone.close();
             }) as Box<dyn FnMut(JsValue)>);
@@ -498,12 +524,10 @@ impl WasmRtcStream {
         let done_clone = done.clone();
         let done_waker_clone = done_waker.clone();
         futures::future::poll_fn(move |cx| {
-            if unsafe { *done_clone.get() } {
+            if done_clone.get_cloned() {
                 Poll::Ready(Ok(()))
             } else {
-                unsafe {
-                    *done_waker_clone.get() = Some(cx.waker().clone());
-                }
+                done_waker_clone.replace(Some(cx.waker().clone()));
                 Poll::Pending
             }
         })
@@ -519,40 +543,47 @@ impl AsyncRead for WasmRtcStream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<IoResult<usize>> {
-        let st = unsafe { &mut *self.state.get() };
-
-        if let Some(ref err) = st.error {
-            return Poll::Ready(Err(io::Error::other(err.clone())));
-        }
+        self.state.with(|st| {
+            if let Some(ref err) = st.error {
+                return Poll::Ready(Err(io::Error::other(err.clone())));
+            }
 
-        if !st.recv_buffer.is_empty() {
-            let n = std::cmp::min(buf.len(), st.recv_buffer.len());
-            for (i, byte) in st.recv_buffer.drain(..n).enumerate() {
-                buf[i] = byte;
+            if !st.recv_buffer.is_empty() {
+                let n = std::cmp::min(buf.len(), st.recv_buffer.len());
+                for (i, byte) in st.recv_buffer.drain(..n).enumerate() {
+                    buf[i] = byte;
+                }
+                return Poll::Ready(Ok(n));
             }
-            return Poll::Ready(Ok(n));
-        }
 
-        if st.state == RtcState::Closed || st.state == RtcState::Closing {
-            return Poll::Ready(Ok(0)); // EOF
-        }
+            if st.state == RtcState::Closed || st.state == RtcState::Closing {
+                return Poll::Ready(Ok(0)); // EOF
+            }
 
-        st.read_waker = Some(cx.waker().clone());
-        Poll::Pending
+            st.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
     }
 }
 
 impl AsyncWrite for WasmRtcStream {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
-        let st = unsafe { &mut *self.state.get() };
+        let ready = self.state.with(|st| {
+            if let Some(ref err) = st.error {
+                return Poll::Ready(Err(io::Error::other(err.clone())));
+            }
 
-        if let Some(ref err) = st.error {
-            return Poll::Ready(Err(io::Error::other(err.clone())));
-        }
+            if st.state != RtcState::Connected {
+                st.write_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
 
-        if st.state != RtcState::Connected {
-            st.write_waker = Some(cx.waker().clone());
-            return Poll::Pending;
+            Poll::Ready(Ok(()))
+        });
+        match ready {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
         }
 
         // Send data through DataChannel
@@ -560,10 +591,17 @@ impl AsyncWrite for WasmRtcStream {
         array.copy_from(buf);
 
         match self.dc.send_with_array_buffer(&array.buffer()) {
-            Ok(_) => Poll::Ready(Ok(buf.len())),
+            Ok(_) => {
+                let buffered = self.dc.buffered_amount() as u64;
+                self.stats.with(|stats| {
+                    stats.record_sent(buf.len());
+                    stats.record_buffered_amount(buffered);
+                });
+                Poll::Ready(Ok(buf.len()))
+            }
             Err(e) => {
                 let msg = format!("DataChannel send failed: {:?}", e);
-                st.error = Some(msg.clone());
+                self.state.with(|st| st.error = Some(msg.clone()));
                 Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, msg)))
             }
         }
@@ -575,8 +613,7 @@ impl AsyncWrite for WasmRtcStream {
 
     fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
         self.dc.close();
-        let st = unsafe { &mut *self.state.get() };
-        st.state = RtcState::Closing;
+        self.state.with(|st| st.state = RtcState::Closing);
         Poll::Ready(Ok(()))
     }
 }
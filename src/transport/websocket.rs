@@ -2,9 +2,22 @@
 //!
 //! This implements AsyncRead and AsyncWrite over WebSocket connections,
 //! allowing WASM code to communicate with Tor relays through a bridge server.
+//!
+//! The bridge forwards the relay's TCP byte stream as binary messages whose
+//! boundaries carry no meaning: a cell may be split across messages or share
+//! one with its neighbours. Messages are appended to a single receive buffer
+//! so readers (`read_exact` of 514-byte cells) reassemble them transparently.
+//! Anything that would corrupt that byte stream — oversized messages, a
+//! receive buffer overflow, or text frames — fails the stream with a
+//! [`FrameError`] instead of being silently dropped.
+//!
+//! [`BridgeMux`] shares one bridge WebSocket between all relay connections,
+//! each a channel of a [`framed`](super::framed) session, instead of opening
+//! a socket per relay. Bridges that don't speak the framing protocol get one
+//! socket per connection as before.
 
+use futures::future::{FutureExt, LocalBoxFuture, Shared};
 use futures::io::{AsyncRead, AsyncWrite};
-use std::cell::UnsafeCell;
 use std::collections::VecDeque;
 use std::io::{self, Result as IoResult};
 use std::pin::Pin;
@@ -14,6 +27,93 @@ use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{BinaryType, MessageEvent, WebSocket};
 
+use super::framed::{FramedChannel, FramedSession};
+use super::shared_ring::{SharedRing, DEFAULT_RING_CAPACITY};
+use super::stats::{new_shared_stats, ConnectionStats, SharedConnectionStats};
+use crate::runtime::LocalCell;
+
+/// Largest single message accepted from the bridge
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+/// Largest amount of received-but-unread data we hold before failing
+pub const MAX_RECV_BUFFER: usize = 4 * 1024 * 1024;
+
+/// WebSocket close code 1003: received data it cannot accept
+const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+
+/// WebSocket close code 1009: message too big to process
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Protocol violations on the bridge WebSocket.
+///
+/// Surfaced as the inner error of an `io::ErrorKind::InvalidData` error;
+/// callers can recover it with `err.get_ref()` + `downcast_ref::<FrameError>()`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FrameError {
+    /// A single message exceeded [`MAX_MESSAGE_SIZE`]
+    #[error("WebSocket message of {size} bytes exceeds maximum of {max} bytes")]
+    MessageTooLarge { size: usize, max: usize },
+
+    /// Unread data would exceed [`MAX_RECV_BUFFER`]
+    #[error("Receive buffer overflow: {buffered} + {incoming} bytes exceeds {max} bytes")]
+    BufferOverflow {
+        buffered: usize,
+        incoming: usize,
+        max: usize,
+    },
+
+    /// The bridge sent a text (or otherwise non-binary) message
+    #[error("Unexpected non-binary WebSocket message")]
+    NonBinaryMessage,
+}
+
+impl FrameError {
+    /// WebSocket close code to send when failing the connection
+    fn close_code(&self) -> u16 {
+        match self {
+            FrameError::MessageTooLarge { .. } | FrameError::BufferOverflow { .. } => {
+                CLOSE_MESSAGE_TOO_BIG
+            }
+            FrameError::NonBinaryMessage => CLOSE_UNSUPPORTED_DATA,
+        }
+    }
+}
+
+impl From<FrameError> for io::Error {
+    fn from(e: FrameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Validate an incoming binary message against the size limits.
+fn check_incoming_frame(size: usize, buffered: usize) -> std::result::Result<(), FrameError> {
+    if size > MAX_MESSAGE_SIZE {
+        return Err(FrameError::MessageTooLarge {
+            size,
+            max: MAX_MESSAGE_SIZE,
+        });
+    }
+    if buffered + size > MAX_RECV_BUFFER {
+        return Err(FrameError::BufferOverflow {
+            buffered,
+            incoming: size,
+            max: MAX_RECV_BUFFER,
+        });
+    }
+    Ok(())
+}
+
+/// Split any frame longer than `max` bytes into consecutive pieces
+fn split_oversized(frames: Vec<Vec<u8>>, max: usize) -> Vec<Vec<u8>> {
+    if frames.iter().all(|frame| frame.len() <= max) {
+        return frames;
+    }
+    frames
+        .iter()
+        .flat_map(|frame| frame.chunks(max).map(<[u8]>::to_vec))
+        .collect()
+}
+
 /// State of the WebSocket connection
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum ConnectionState {
@@ -28,9 +128,14 @@ struct StreamState {
     /// Current connection state
     state: ConnectionState,
 
-    /// Buffer for received data
+    /// Buffer for received data (overflow from `recv_ring` when it has one;
+    /// always newer than what the ring holds)
     recv_buffer: VecDeque<u8>,
 
+    /// Shared-memory fast path for received data, when the page is
+    /// cross-origin isolated
+    recv_ring: Option<SharedRing>,
+
     /// Buffer for data to send
     send_buffer: VecDeque<u8>,
 
@@ -43,6 +148,9 @@ struct StreamState {
     /// Last error encountered
     error: Option<String>,
 
+    /// Protocol violation that failed the stream (takes precedence over `error`)
+    frame_error: Option<FrameError>,
+
     /// Traffic shaping profile for DPI resistance.
     /// When set to a non-None profile, outgoing data is fragmented into
     /// profile-matching frame sizes instead of the default 514-byte Tor cells.
@@ -56,9 +164,53 @@ struct StreamState {
     /// here and sent via setTimeout callbacks to match the profile's
     /// inter-frame timing distribution.
     pending_shaped_frames: VecDeque<Vec<u8>>,
+
+    /// Whether a drain check is scheduled while writes are back-pressured
+    drain_timer_armed: bool,
+
+    /// Largest message the bridge accepts; longer frames are split
+    max_send_message: usize,
 }
 
 impl StreamState {
+    /// Received bytes not yet read
+    fn buffered(&self) -> usize {
+        self.recv_buffer.len() + self.recv_ring.as_ref().map_or(0, SharedRing::len)
+    }
+
+    /// Append a received message: into the ring while it has room and
+    /// nothing has spilled, otherwise onto `recv_buffer`
+    fn push_received(&mut self, data: &js_sys::Uint8Array) {
+        if self.recv_buffer.is_empty() {
+            if let Some(ring) = &self.recv_ring {
+                if ring.push(data) {
+                    return;
+                }
+            }
+        }
+        self.recv_buffer.extend(data.to_vec());
+    }
+
+    /// Move received bytes into `buf`, oldest (the ring) first
+    fn read_received(&mut self, buf: &mut [u8]) -> usize {
+        if let Some(ring) = self.recv_ring.as_ref().filter(|r| !r.is_empty()) {
+            return ring.pop_into(buf);
+        }
+        let to_read = buf.len().min(self.recv_buffer.len());
+        for (slot, byte) in buf.iter_mut().zip(self.recv_buffer.drain(..to_read)) {
+            *slot = byte;
+        }
+        to_read
+    }
+
+    /// Drop all received bytes
+    fn clear_received(&mut self) {
+        self.recv_buffer.clear();
+        if let Some(ring) = &self.recv_ring {
+            ring.clear();
+        }
+    }
+
     fn new() -> Self {
         // Seed RNG from current time
         let seed = web_time::SystemTime::now()
@@ -69,13 +221,17 @@ impl StreamState {
         Self {
             state: ConnectionState::Connecting,
             recv_buffer: VecDeque::new(),
+            recv_ring: SharedRing::new(DEFAULT_RING_CAPACITY),
             send_buffer: VecDeque::new(),
             read_waker: None,
             write_waker: None,
             error: None,
+            frame_error: None,
             traffic_profile: crate::traffic_shaping::TrafficProfile::None,
             shaping_rng: seed,
             pending_shaped_frames: VecDeque::new(),
+            drain_timer_armed: false,
+            max_send_message: MAX_MESSAGE_SIZE,
         }
     }
 }
@@ -88,8 +244,32 @@ pub struct WasmTcpStream {
     /// The underlying WebSocket
     ws: WebSocket,
 
-    /// Shared state between callbacks and stream methods (UnsafeCell is safe in single-threaded WASM)
-    state: Rc<UnsafeCell<StreamState>>,
+    /// Shared state between callbacks and stream methods
+    state: Rc<LocalCell<StreamState>>,
+
+    /// Connection-level counters, shared with the network provider
+    stats: SharedConnectionStats,
+}
+
+/// Backpressure: stop accepting writes once the browser has this many bytes
+/// queued in `WebSocket.bufferedAmount` (slow bridge / congested uplink)
+const BUFFERED_HIGH_WATER_MARK: u32 = 1024 * 1024;
+
+/// Backpressure: resume writes once `bufferedAmount` drains below this
+const BUFFERED_LOW_WATER_MARK: u32 = 256 * 1024;
+
+/// How often to re-check `bufferedAmount` while back-pressured.
+/// WebSocket has no "drain" event, so we poll.
+const DRAIN_POLL_MS: u32 = 10;
+
+/// Returns true if writes should be paused for this `bufferedAmount`
+fn over_high_water(buffered_amount: u32) -> bool {
+    buffered_amount >= BUFFERED_HIGH_WATER_MARK
+}
+
+/// Returns true if paused writes may resume for this `bufferedAmount`
+fn drained_to_low_water(buffered_amount: u32) -> bool {
+    buffered_amount <= BUFFERED_LOW_WATER_MARK
 }
 
 /// Reconnection configuration
@@ -108,6 +288,7 @@ impl WasmTcpStream {
                     if attempt > 0 {
                         log::info!("WebSocket reconnected on attempt {}", attempt + 1);
                     }
+                    stream.record_reconnects(attempt);
                     return Ok(stream);
                 }
                 Err(e) => {
@@ -143,11 +324,12 @@ impl WasmTcpStream {
         // Set binary mode
         ws.set_binary_type(BinaryType::Arraybuffer);
 
-        // Create shared state (UnsafeCell is safe in single-threaded WASM)
-        let state = Rc::new(UnsafeCell::new(StreamState::new()));
+        // Create shared state
+        let state = Rc::new(LocalCell::new(StreamState::new()));
+        let stats = new_shared_stats();
 
         // Set up event handlers
-        Self::setup_handlers(&ws, state.clone())?;
+        Self::setup_handlers(&ws, state.clone(), stats.clone())?;
 
         // Wait for connection to open
         let connection_future = {
@@ -155,13 +337,11 @@ impl WasmTcpStream {
             async move {
                 // Poll until connected or error
                 loop {
-                    let current_state = unsafe {
-                        let st = &*state_clone.get();
-                        if let Some(err) = &st.error {
-                            return Err(io::Error::other(err.clone()));
-                        }
-                        st.state
-                    };
+                    let (current_state, error) =
+                        state_clone.with(|st| (st.state, st.error.clone()));
+                    if let Some(err) = error {
+                        return Err(io::Error::other(err));
+                    }
 
                     match current_state {
                         ConnectionState::Connected => return Ok(()),
@@ -186,22 +366,72 @@ impl WasmTcpStream {
 
         log::info!("WebSocket connected successfully");
 
-        Ok(Self { ws, state })
+        Ok(Self { ws, state, stats })
+    }
+
+    /// Snapshot of this connection's counters
+    pub fn stats(&self) -> ConnectionStats {
+        let buffered = self.ws.buffered_amount() as u64;
+        self.stats.with(|stats| {
+            stats.record_buffered_amount(buffered);
+            stats.clone()
+        })
+    }
+
+    /// Shared handle to this connection's counters (for aggregation)
+    pub fn stats_handle(&self) -> SharedConnectionStats {
+        self.stats.clone()
+    }
+
+    /// Record how many failed attempts preceded this connection
+    pub fn record_reconnects(&self, attempts: u32) {
+        self.stats.with(|stats| stats.reconnects += attempts);
+    }
+
+    /// Wake every pending reader and writer.
+    ///
+    /// Wakers are taken inside `with` but woken after it returns, so a waker
+    /// that polls synchronously can never re-enter the borrowed state.
+    fn wake_all(state: &LocalCell<StreamState>) {
+        let (read, write) = state.with(|st| (st.read_waker.take(), st.write_waker.take()));
+        if let Some(waker) = read {
+            waker.wake();
+        }
+        if let Some(waker) = write {
+            waker.wake();
+        }
+    }
+
+    /// Fail the stream with a protocol violation and close the WebSocket.
+    fn fail_with_frame_error(ws: &WebSocket, state: &LocalCell<StreamState>, err: FrameError) {
+        log::error!("WebSocket protocol violation: {}", err);
+        state.with(|st| {
+            st.clear_received();
+            st.error = Some(err.to_string());
+            st.frame_error = Some(err.clone());
+            st.state = ConnectionState::Closed;
+        });
+        let _ = ws.close_with_code_and_reason(err.close_code(), "protocol violation");
+        Self::wake_all(state);
     }
 
     /// Set up WebSocket event handlers
-    fn setup_handlers(ws: &WebSocket, state: Rc<UnsafeCell<StreamState>>) -> IoResult<()> {
+    fn setup_handlers(
+        ws: &WebSocket,
+        state: Rc<LocalCell<StreamState>>,
+        stats: SharedConnectionStats,
+    ) -> IoResult<()> {
         // onopen handler
         {
             let state_clone = state.clone();
             let onopen = Closure::wrap(Box::new(move |_event: JsValue| {
                 log::debug!("WebSocket opened");
-                unsafe {
-                    let st = &mut *state_clone.get();
+                let waker = state_clone.with(|st| {
                     st.state = ConnectionState::Connected;
-                    if let Some(waker) = st.read_waker.take() {
-                        waker.wake();
-                    }
+                    st.read_waker.take()
+                });
+                if let Some(waker) = waker {
+                    waker.wake();
                 }
             }) as Box<dyn FnMut(JsValue)>);
 
@@ -212,22 +442,47 @@ impl WasmTcpStream {
         // onmessage handler - receives data
         {
             let state_clone = state.clone();
+            let ws_clone = ws.clone();
             let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
-                if let Ok(array_buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
-                    let array = js_sys::Uint8Array::new(&array_buffer);
-                    let data = array.to_vec();
+                let (failed, buffered) =
+                    state_clone.with(|st| (st.frame_error.is_some(), st.buffered()));
+                if failed {
+                    // Stream already failed; ignore anything still in flight
+                    return;
+                }
 
-                    log::debug!("WebSocket received {} bytes", data.len());
+                let array_buffer = match event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    Ok(buf) => buf,
+                    Err(_) => {
+                        Self::fail_with_frame_error(
+                            &ws_clone,
+                            &state_clone,
+                            FrameError::NonBinaryMessage,
+                        );
+                        return;
+                    }
+                };
 
-                    unsafe {
-                        let st = &mut *state_clone.get();
-                        st.recv_buffer.extend(data);
+                let size = array_buffer.byte_length() as usize;
+                if let Err(e) = check_incoming_frame(size, buffered) {
+                    Self::fail_with_frame_error(&ws_clone, &state_clone, e);
+                    return;
+                }
 
-                        // Wake up any pending read
-                        if let Some(waker) = st.read_waker.take() {
-                            waker.wake();
-                        }
-                    }
+                let data = js_sys::Uint8Array::new(&array_buffer);
+                log::debug!("WebSocket received {} bytes", size);
+
+                stats.with(|stats| stats.record_received(size));
+
+                // Partial cells are fine: the buffer is a byte stream
+                let waker = state_clone.with(|st| {
+                    st.push_received(&data);
+                    st.read_waker.take()
+                });
+
+                // Wake up any pending read
+                if let Some(waker) = waker {
+                    waker.wake();
                 }
             }) as Box<dyn FnMut(MessageEvent)>);
 
@@ -242,19 +497,13 @@ impl WasmTcpStream {
             let state_clone = state.clone();
             let onerror = Closure::wrap(Box::new(move |_event: JsValue| {
                 log::error!("WebSocket error event");
-                unsafe {
-                    let st = &mut *state_clone.get();
+                state_clone.with(|st| {
                     st.error = Some("WebSocket error".to_string());
                     st.state = ConnectionState::Closed;
+                });
 
-                    // Wake up any pending operations
-                    if let Some(waker) = st.read_waker.take() {
-                        waker.wake();
-                    }
-                    if let Some(waker) = st.write_waker.take() {
-                        waker.wake();
-                    }
-                }
+                // Wake up any pending operations
+                Self::wake_all(&state_clone);
             }) as Box<dyn FnMut(JsValue)>);
 
             ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
@@ -266,18 +515,10 @@ impl WasmTcpStream {
             let state_clone = state.clone();
             let onclose = Closure::wrap(Box::new(move |_event: JsValue| {
                 log::debug!("WebSocket closed");
-                unsafe {
-                    let st = &mut *state_clone.get();
-                    st.state = ConnectionState::Closed;
+                state_clone.with(|st| st.state = ConnectionState::Closed);
 
-                    // Wake up any pending operations
-                    if let Some(waker) = st.read_waker.take() {
-                        waker.wake();
-                    }
-                    if let Some(waker) = st.write_waker.take() {
-                        waker.wake();
-                    }
-                }
+                // Wake up any pending operations
+                Self::wake_all(&state_clone);
             }) as Box<dyn FnMut(JsValue)>);
 
             ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
@@ -287,6 +528,11 @@ impl WasmTcpStream {
         Ok(())
     }
 
+    /// Split outgoing messages at `max` bytes, the most the bridge accepts
+    pub fn set_max_message_size(&self, max: usize) {
+        self.state.with(|state| state.max_send_message = max.max(1));
+    }
+
     /// Set the traffic shaping profile for DPI resistance.
     ///
     /// When a non-None profile is active, outgoing data is fragmented into
@@ -300,11 +546,8 @@ impl WasmTcpStream {
     ///   - `Ticker`: steady small frames (20-100 bytes)
     ///   - `Video`: sustained high-bandwidth (800-1200 bytes)
     pub fn set_traffic_profile(&self, profile: crate::traffic_shaping::TrafficProfile) {
-        unsafe {
-            let state = &mut *self.state.get();
-            log::info!("Traffic shaping profile set to: {:?}", profile);
-            state.traffic_profile = profile;
-        }
+        log::info!("Traffic shaping profile set to: {:?}", profile);
+        self.state.with(|state| state.traffic_profile = profile);
     }
 
     /// Flush the send buffer to the WebSocket.
@@ -313,11 +556,10 @@ impl WasmTcpStream {
     /// frame sizes. The first frame is sent immediately; remaining frames are
     /// queued and sent via setTimeout callbacks to match timing distribution.
     fn flush_send_buffer(&self) -> IoResult<()> {
-        unsafe {
-            let state = &mut *self.state.get();
-
+        // Take the buffered data and fragment it (brief borrow)
+        let prepared = self.state.with(|state| {
             if state.send_buffer.is_empty() {
-                return Ok(());
+                return Ok(None);
             }
 
             // Check if we can send
@@ -340,23 +582,14 @@ impl WasmTcpStream {
             // Drain all buffered data
             let data: Vec<u8> = state.send_buffer.drain(..).collect();
 
-            if data.is_empty() {
-                return Ok(());
-            }
-
             // Fragment data according to traffic profile
             let frames = crate::traffic_shaping::fragment_for_profile(
                 &data,
                 &state.traffic_profile,
                 &mut state.shaping_rng,
             );
+            let frames = split_oversized(frames, state.max_send_message);
 
-            if frames.is_empty() {
-                return Ok(());
-            }
-
-            // Send first frame immediately
-            let first = &frames[0];
             log::debug!(
                 "Sending {} bytes ({} frames, profile {:?})",
                 data.len(),
@@ -364,61 +597,120 @@ impl WasmTcpStream {
                 state.traffic_profile
             );
 
-            let array = js_sys::Uint8Array::from(&first[..]);
-            self.ws
-                .send_with_array_buffer(&array.buffer())
-                .map_err(|e| {
-                    log::error!("Failed to send data: {:?}", e);
-                    io::Error::other("Failed to send data over WebSocket")
-                })?;
-
-            // If there are more frames, schedule them with timing delays
-            if frames.len() > 1 {
-                let remaining: Vec<Vec<u8>> = frames[1..].to_vec();
-                self.schedule_deferred_frames(remaining, &mut state.shaping_rng);
-            }
+            Ok(Some(frames))
+        })?;
+
+        let mut frames = match prepared {
+            Some(frames) if !frames.is_empty() => frames,
+            _ => return Ok(()),
+        };
 
-            Ok(())
+        // Send first frame immediately
+        let remaining = frames.split_off(1);
+        let first = &frames[0];
+
+        let array = js_sys::Uint8Array::from(&first[..]);
+        self.ws
+            .send_with_array_buffer(&array.buffer())
+            .map_err(|e| {
+                log::error!("Failed to send data: {:?}", e);
+                io::Error::other("Failed to send data over WebSocket")
+            })?;
+
+        let buffered = self.ws.buffered_amount() as u64;
+        self.stats.with(|stats| {
+            stats.record_sent(first.len());
+            stats.record_buffered_amount(buffered);
+        });
+
+        // If there are more frames, schedule them with timing delays
+        if !remaining.is_empty() {
+            self.schedule_deferred_frames(remaining);
         }
+
+        Ok(())
+    }
+
+    /// Wake the pending writer once `bufferedAmount` drains to the low-water mark.
+    ///
+    /// Only one drain check runs at a time. It also fires if the connection
+    /// closes, so the writer observes the error instead of hanging.
+    fn arm_drain_timer(&self) {
+        let already_armed = self
+            .state
+            .with(|state| std::mem::replace(&mut state.drain_timer_armed, true));
+        if already_armed {
+            return;
+        }
+
+        let ws = self.ws.clone();
+        let state = self.state.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            loop {
+                gloo_timers::future::TimeoutFuture::new(DRAIN_POLL_MS).await;
+                let drained = drained_to_low_water(ws.buffered_amount());
+                let waker = state.with(|st| {
+                    if st.state != ConnectionState::Connected || drained {
+                        st.drain_timer_armed = false;
+                        Some(st.write_waker.take())
+                    } else {
+                        None
+                    }
+                });
+                if let Some(waker) = waker {
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                    return;
+                }
+            }
+        });
     }
 
     /// Schedule remaining shaped frames with profile-matching timing delays.
     ///
     /// Uses `setTimeout` to send each frame after the appropriate delay,
     /// simulating the inter-frame timing of the active traffic profile.
-    fn schedule_deferred_frames(&self, frames: Vec<Vec<u8>>, rng: &mut u64) {
+    fn schedule_deferred_frames(&self, frames: Vec<Vec<u8>>) {
         use crate::traffic_shaping::profile_delay;
 
         let ws = self.ws.clone();
         let state = self.state.clone();
-        let profile = unsafe { (*self.state.get()).traffic_profile };
+        let stats = self.stats.clone();
 
         // Calculate cumulative delays for each frame
-        let mut cumulative_ms: u32 = 0;
-        let mut scheduled_frames: Vec<(u32, Vec<u8>)> = Vec::with_capacity(frames.len());
-
-        for frame in frames {
-            let delay = profile_delay(&profile, rng);
-            cumulative_ms += delay.as_millis() as u32;
-            scheduled_frames.push((cumulative_ms, frame));
-        }
+        let scheduled_frames = self.state.with(|st| {
+            let mut cumulative_ms: u32 = 0;
+            let mut scheduled: Vec<(u32, Vec<u8>)> = Vec::with_capacity(frames.len());
+            for frame in frames {
+                let delay = profile_delay(&st.traffic_profile, &mut st.shaping_rng);
+                cumulative_ms += delay.as_millis() as u32;
+                scheduled.push((cumulative_ms, frame));
+            }
+            scheduled
+        });
 
         // Schedule each frame via setTimeout
         for (delay_ms, frame) in scheduled_frames {
             let ws_clone = ws.clone();
             let state_clone = state.clone();
+            let stats_clone = stats.clone();
 
             let closure = Closure::once(move || {
                 // Check connection is still alive
-                let connected = unsafe {
-                    let st = &*state_clone.get();
-                    st.state == ConnectionState::Connected
-                };
+                let connected = state_clone.with(|st| st.state == ConnectionState::Connected);
 
                 if connected {
                     let array = js_sys::Uint8Array::from(&frame[..]);
-                    if let Err(e) = ws_clone.send_with_array_buffer(&array.buffer()) {
-                        log::warn!("Deferred frame send failed: {:?}", e);
+                    match ws_clone.send_with_array_buffer(&array.buffer()) {
+                        Ok(()) => {
+                            let buffered = ws_clone.buffered_amount() as u64;
+                            stats_clone.with(|stats| {
+                                stats.record_sent(frame.len());
+                                stats.record_buffered_amount(buffered);
+                            });
+                        }
+                        Err(e) => log::warn!("Deferred frame send failed: {:?}", e),
                     }
                 }
             });
@@ -439,43 +731,43 @@ impl AsyncRead for WasmTcpStream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<IoResult<usize>> {
-        unsafe {
-            let state = &mut *self.state.get();
-
+        self.state.with(|state| {
             // Check for errors
+            if let Some(err) = &state.frame_error {
+                return Poll::Ready(Err(err.clone().into()));
+            }
             if let Some(err) = &state.error {
                 return Poll::Ready(Err(io::Error::other(err.clone())));
             }
 
             // Check if connection is closed
-            if state.state == ConnectionState::Closed && state.recv_buffer.is_empty() {
+            if state.state == ConnectionState::Closed && state.buffered() == 0 {
                 return Poll::Ready(Ok(0)); // EOF
             }
 
             // If we have data in the buffer, read it
-            if !state.recv_buffer.is_empty() {
-                let to_read = buf.len().min(state.recv_buffer.len());
-                for (i, byte) in state.recv_buffer.drain(..to_read).enumerate() {
-                    buf[i] = byte;
-                }
-                return Poll::Ready(Ok(to_read));
+            if state.buffered() > 0 {
+                return Poll::Ready(Ok(state.read_received(buf)));
             }
 
             // No data available, store waker and return pending
             state.read_waker = Some(cx.waker().clone());
             Poll::Pending
-        }
+        })
     }
 }
 
 impl AsyncWrite for WasmTcpStream {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
-        unsafe {
-            let state = &mut *self.state.get();
+        let buffered_amount = self.ws.buffered_amount();
 
+        let outcome = self.state.with(|state| {
             // Check for errors
+            if let Some(err) = &state.frame_error {
+                return Err(Poll::Ready(Err(err.clone().into())));
+            }
             if let Some(err) = &state.error {
-                return Poll::Ready(Err(io::Error::other(err.clone())));
+                return Err(Poll::Ready(Err(io::Error::other(err.clone()))));
             }
 
             // Check if connection is ready
@@ -484,20 +776,51 @@ impl AsyncWrite for WasmTcpStream {
                 ConnectionState::Connecting => {
                     // Store waker and return pending
                     state.write_waker = Some(cx.waker().clone());
-                    return Poll::Pending;
+                    return Err(Poll::Pending);
                 }
                 _ => {
-                    return Poll::Ready(Err(io::Error::new(
+                    return Err(Poll::Ready(Err(io::Error::new(
                         io::ErrorKind::NotConnected,
                         "Connection closed",
-                    )));
+                    ))));
                 }
             }
 
+            // Backpressure: if the browser is already holding too much unsent
+            // data, make the writer wait. The cell scheduler awaits each send,
+            // so this stalls it instead of growing bufferedAmount without bound.
+            if over_high_water(buffered_amount) {
+                log::debug!(
+                    "WebSocket backpressure: bufferedAmount={} bytes",
+                    buffered_amount
+                );
+                state.write_waker = Some(cx.waker().clone());
+                return Ok(None);
+            }
+
             // Buffer the data
             state.send_buffer.extend(buf);
 
-            Poll::Ready(Ok(buf.len()))
+            // Don't let unflushed data grow past the high-water mark either
+            Ok(Some(
+                state.send_buffer.len() >= BUFFERED_HIGH_WATER_MARK as usize,
+            ))
+        });
+
+        match outcome {
+            Err(poll) => poll,
+            Ok(None) => {
+                self.arm_drain_timer();
+                Poll::Pending
+            }
+            Ok(Some(needs_flush)) => {
+                if needs_flush {
+                    if let Err(e) = self.flush_send_buffer() {
+                        return Poll::Ready(Err(e));
+                    }
+                }
+                Poll::Ready(Ok(buf.len()))
+            }
         }
     }
 
@@ -507,10 +830,8 @@ impl AsyncWrite for WasmTcpStream {
             Ok(()) => Poll::Ready(Ok(())),
             Err(e) if e.kind() == io::ErrorKind::NotConnected => {
                 // Store waker for when connection is ready
-                unsafe {
-                    let state = &mut *self.state.get();
-                    state.write_waker = Some(_cx.waker().clone());
-                }
+                self.state
+                    .with(|state| state.write_waker = Some(_cx.waker().clone()));
                 Poll::Pending
             }
             Err(e) => Poll::Ready(Err(e)),
@@ -524,12 +845,16 @@ impl AsyncWrite for WasmTcpStream {
         }
 
         // Close the WebSocket
-        unsafe {
-            let state = &mut *self.state.get();
+        let should_close = self.state.with(|state| {
             if state.state != ConnectionState::Closed {
                 state.state = ConnectionState::Closing;
-                let _ = self.ws.close();
+                true
+            } else {
+                false
             }
+        });
+        if should_close {
+            let _ = self.ws.close();
         }
 
         Poll::Ready(Ok(()))
@@ -539,14 +864,183 @@ impl AsyncWrite for WasmTcpStream {
 // Implement Debug
 impl std::fmt::Debug for WasmTcpStream {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        unsafe {
-            let state = &*self.state.get();
-            f.debug_struct("WasmTcpStream")
-                .field("state", &state.state)
-                .field("recv_buffer_len", &state.recv_buffer.len())
-                .field("send_buffer_len", &state.send_buffer.len())
-                .finish()
+        let (state, recv_len, send_len) = match self
+            .state
+            .try_with(|st| (st.state, st.buffered(), st.send_buffer.len()))
+        {
+            Ok(snapshot) => snapshot,
+            Err(_) => return f.write_str("WasmTcpStream { <borrowed> }"),
+        };
+        f.debug_struct("WasmTcpStream")
+            .field("state", &state)
+            .field("recv_buffer_len", &recv_len)
+            .field("send_buffer_len", &send_len)
+            .finish()
+    }
+}
+
+/// How long to wait for the bridge's framing HELLO
+const MUX_HELLO_TIMEOUT_MS: u32 = 10_000;
+
+/// After a failed framing handshake, how long to use one socket per
+/// connection before trying to multiplex again
+pub const MUX_RETRY_MS: u64 = 10 * 60 * 1000;
+
+type SessionFuture = Shared<LocalBoxFuture<'static, Result<Rc<FramedSession>, String>>>;
+
+#[derive(Default)]
+struct MuxState {
+    /// The shared session, connected or still connecting
+    session: Option<SessionFuture>,
+    /// Until when (ms) the bridge is treated as not supporting framing
+    fallback_until: u64,
+    sessions_opened: u64,
+    channels_opened: u64,
+}
+
+impl MuxState {
+    fn multiplexing(&self, now_ms: u64) -> bool {
+        now_ms >= self.fallback_until
+    }
+
+    /// The session to open a channel on, starting a new one if there is
+    /// none or the last one failed or closed
+    fn session(&mut self, bridge_url: &str) -> SessionFuture {
+        let usable = match self.session.as_ref().map(|s| s.peek()) {
+            Some(None) => true,
+            Some(Some(Ok(session))) => session.is_open(),
+            Some(Some(Err(_))) | None => false,
+        };
+        if !usable {
+            self.sessions_opened += 1;
+            self.session = Some(
+                connect_session(bridge_url.to_string())
+                    .boxed_local()
+                    .shared(),
+            );
         }
+        self.session.clone().expect("session just set")
+    }
+
+    fn handshake_failed(&mut self, now_ms: u64) {
+        self.session = None;
+        self.fallback_until = now_ms + MUX_RETRY_MS;
+    }
+}
+
+async fn connect_session(bridge_url: String) -> Result<Rc<FramedSession>, String> {
+    let connect = FramedSession::connect(&bridge_url);
+    let timeout = gloo_timers::future::TimeoutFuture::new(MUX_HELLO_TIMEOUT_MS);
+    futures::pin_mut!(connect);
+    match futures::future::select(connect, timeout).await {
+        futures::future::Either::Left((result, _)) => {
+            result.map(Rc::new).map_err(|e| e.to_string())
+        }
+        futures::future::Either::Right(_) => Err("framing HELLO timed out".into()),
+    }
+}
+
+/// Counters for [`BridgeMux`]
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct MuxStatus {
+    /// Whether new connections are multiplexed (false while falling back)
+    pub multiplexing: bool,
+    /// Channels open on the current session
+    pub open_channels: usize,
+    pub sessions_opened: u64,
+    pub channels_opened: u64,
+    /// Times the current session was resumed after its socket dropped
+    pub session_resumes: u64,
+}
+
+/// One shared, framed WebSocket per bridge for all relay connections
+///
+/// Concurrent callers wait on the same handshake rather than each opening a
+/// socket. If the handshake fails (an older bridge closes a `?framing=1`
+/// socket for lack of an address), [`BridgeMux::channel`] returns `None` for
+/// [`MUX_RETRY_MS`] and callers fall back to [`WasmTcpStream`] per relay.
+pub struct BridgeMux {
+    bridge_url: String,
+    state: Rc<LocalCell<MuxState>>,
+}
+
+impl BridgeMux {
+    pub fn new(bridge_url: impl Into<String>) -> Self {
+        Self {
+            bridge_url: bridge_url.into(),
+            state: Rc::new(LocalCell::new(MuxState::default())),
+        }
+    }
+
+    /// Whether the next connection will be tried as a channel
+    pub fn multiplexing(&self) -> bool {
+        let now = crate::runtime::timer::now_ms();
+        self.state.with(|s| s.multiplexing(now))
+    }
+
+    /// Open a channel to `target` (`addr=HOST:PORT` or `dest=<blob>`), or
+    /// `None` if the bridge doesn't support framing
+    pub async fn channel(&self, target: &str) -> IoResult<Option<FramedChannel>> {
+        let now = crate::runtime::timer::now_ms();
+        let session = self
+            .state
+            .with(|s| s.multiplexing(now).then(|| s.session(&self.bridge_url)));
+        let Some(session) = session else {
+            return Ok(None);
+        };
+        match session.await {
+            Ok(session) => {
+                let channel = session.open_channel(target)?;
+                self.state.with(|s| s.channels_opened += 1);
+                Ok(Some(channel))
+            }
+            Err(e) => {
+                log::warn!(
+                    "🧵 Bridge framing unavailable ({}); one socket per relay for {} min",
+                    e,
+                    MUX_RETRY_MS / 60_000
+                );
+                let now = crate::runtime::timer::now_ms();
+                self.state.with(|s| s.handshake_failed(now));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Drop the shared session; open channels keep it alive until they close
+    pub fn reset(&self) {
+        self.state.with(|s| *s = MuxState::default());
+    }
+
+    /// Close the shared socket now, failing any channels still on it; the
+    /// next channel opens a new session
+    pub fn close(&self) {
+        let session = self
+            .state
+            .with(|s| match s.session.take().and_then(|f| f.peek().cloned()) {
+                Some(Ok(session)) => Some(session),
+                _ => None,
+            });
+        if let Some(session) = session {
+            session.close();
+        }
+    }
+
+    pub fn status(&self) -> MuxStatus {
+        let now = crate::runtime::timer::now_ms();
+        self.state.with(|s| {
+            let session = match s.session.as_ref().and_then(|f| f.peek()) {
+                Some(Ok(session)) => Some(Rc::clone(session)),
+                _ => None,
+            };
+            MuxStatus {
+                multiplexing: s.multiplexing(now),
+                open_channels: session.as_ref().map_or(0, |s| s.channel_count()),
+                sessions_opened: s.sessions_opened,
+                channels_opened: s.channels_opened,
+                session_resumes: session.as_ref().map_or(0, |s| s.resumes()),
+            }
+        })
     }
 }
 
@@ -561,4 +1055,70 @@ mod tests {
         // For now, just test that the structure compiles
         assert!(true);
     }
+
+    #[test]
+    fn test_incoming_frame_limits() {
+        assert_eq!(check_incoming_frame(514, 0), Ok(()));
+        assert_eq!(check_incoming_frame(MAX_MESSAGE_SIZE, 0), Ok(()));
+        assert_eq!(
+            check_incoming_frame(MAX_MESSAGE_SIZE + 1, 0),
+            Err(FrameError::MessageTooLarge {
+                size: MAX_MESSAGE_SIZE + 1,
+                max: MAX_MESSAGE_SIZE,
+            })
+        );
+        assert!(matches!(
+            check_incoming_frame(1024, MAX_RECV_BUFFER - 1000),
+            Err(FrameError::BufferOverflow { .. })
+        ));
+    }
+
+    #[test]
+    fn test_split_oversized_frames() {
+        let frames = vec![vec![1u8; 10], vec![2u8; 3]];
+        assert_eq!(split_oversized(frames.clone(), 10), frames);
+
+        let split = split_oversized(frames, 4);
+        let lens: Vec<usize> = split.iter().map(Vec::len).collect();
+        assert_eq!(lens, vec![4, 4, 2, 3]);
+        assert_eq!(split.concat(), [vec![1u8; 10], vec![2u8; 3]].concat());
+    }
+
+    #[test]
+    fn test_frame_error_is_typed_io_error() {
+        let err: io::Error = FrameError::NonBinaryMessage.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let inner = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<FrameError>())
+            .expect("FrameError should be recoverable");
+        assert_eq!(inner, &FrameError::NonBinaryMessage);
+        assert_eq!(inner.close_code(), CLOSE_UNSUPPORTED_DATA);
+    }
+
+    #[test]
+    fn test_mux_falls_back_after_failed_handshake() {
+        let mut state = MuxState::default();
+        assert!(state.multiplexing(1_000));
+
+        state.handshake_failed(1_000);
+        assert!(state.session.is_none());
+        assert!(!state.multiplexing(1_000 + MUX_RETRY_MS - 1));
+        assert!(state.multiplexing(1_000 + MUX_RETRY_MS));
+    }
+
+    #[test]
+    fn test_backpressure_hysteresis() {
+        assert!(!over_high_water(0));
+        assert!(!over_high_water(BUFFERED_HIGH_WATER_MARK - 1));
+        assert!(over_high_water(BUFFERED_HIGH_WATER_MARK));
+
+        // Between the marks: still paused, not yet drained
+        let between = (BUFFERED_LOW_WATER_MARK + BUFFERED_HIGH_WATER_MARK) / 2;
+        assert!(!over_high_water(between));
+        assert!(!drained_to_low_water(between));
+
+        assert!(drained_to_low_water(BUFFERED_LOW_WATER_MARK));
+        assert!(drained_to_low_water(0));
+    }
 }
@@ -20,7 +20,6 @@
 use futures::io::{AsyncRead, AsyncWrite};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
-use std::cell::UnsafeCell;
 use std::collections::VecDeque;
 use std::fmt::Write as FmtWrite;
 use std::io::{self, Result as IoResult};
@@ -28,6 +27,8 @@ use std::pin::Pin;
 use std::rc::Rc;
 use std::task::{Context, Poll, Waker};
 use wasm_bindgen::prelude::*;
+
+use crate::runtime::LocalCell;
 use wasm_bindgen::JsCast;
 use web_sys::{BinaryType, MessageEvent, WebSocket};
 
@@ -102,7 +103,7 @@ impl TunnelStreamState {
 /// Implements AsyncRead/AsyncWrite for transparent use by Arti.
 pub struct WasmWebTunnelStream {
     ws: WebSocket,
-    state: Rc<UnsafeCell<TunnelStreamState>>,
+    state: Rc<LocalCell<TunnelStreamState>>,
     // Prevent closures from being dropped while WS events can still fire
     _on_open: Closure<dyn FnMut()>,
     _on_message: Closure<dyn FnMut(MessageEvent)>,
@@ -144,14 +145,16 @@ impl WasmWebTunnelStream {
 
         ws.set_binary_type(BinaryType::Arraybuffer);
 
-        let state = Rc::new(UnsafeCell::new(TunnelStreamState::new()));
+        let state = Rc::new(LocalCell::new(TunnelStreamState::new()));
 
         // onopen
         let state_open = state.clone();
         let on_open = Closure::once(move || {
-            let s = unsafe { &mut *state_open.get() };
-            s.state = TunnelState::Connected;
-            if let Some(w) = s.write_waker.take() {
+            let waker = state_open.with(|s| {
+                s.state = TunnelState::Connected;
+                s.write_waker.take()
+            });
+            if let Some(w) = waker {
                 w.wake();
             }
             log::debug!("WebTunnel: connected");
@@ -161,16 +164,17 @@ impl WasmWebTunnelStream {
         // onmessage
         let state_msg = state.clone();
         let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
-            let s = unsafe { &mut *state_msg.get() };
-
             if let Ok(buf) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
                 let array = js_sys::Uint8Array::new(&buf);
                 let len = array.length() as usize;
                 let mut data = vec![0u8; len];
                 array.copy_to(&mut data);
-                s.recv_buffer.extend(data.iter());
+                let waker = state_msg.with(|s| {
+                    s.recv_buffer.extend(data.iter());
+                    s.read_waker.take()
+                });
 
-                if let Some(w) = s.read_waker.take() {
+                if let Some(w) = waker {
                     w.wake();
                 }
             }
@@ -180,14 +184,16 @@ impl WasmWebTunnelStream {
         // onerror
         let state_err = state.clone();
         let on_error = Closure::wrap(Box::new(move |_event: JsValue| {
-            let s = unsafe { &mut *state_err.get() };
-            s.error = Some("WebTunnel: connection error".to_string());
-            s.state = TunnelState::Closed;
+            let (read, write) = state_err.with(|s| {
+                s.error = Some("WebTunnel: connection error".to_string());
+                s.state = TunnelState::Closed;
+                (s.read_waker.take(), s.write_waker.take())
+            });
 
-            if let Some(w) = s.read_waker.take() {
+            if let Some(w) = read {
                 w.wake();
             }
-            if let Some(w) = s.write_waker.take() {
+            if let Some(w) = write {
                 w.wake();
             }
             log::error!("WebTunnel: connection error");
@@ -197,13 +203,15 @@ impl WasmWebTunnelStream {
         // onclose
         let state_close = state.clone();
         let on_close = Closure::wrap(Box::new(move || {
-            let s = unsafe { &mut *state_close.get() };
-            s.state = TunnelState::Closed;
+            let (read, write) = state_close.with(|s| {
+                s.state = TunnelState::Closed;
+                (s.read_waker.take(), s.write_waker.take())
+            });
 
-            if let Some(w) = s.read_waker.take() {
+            if let Some(w) = read {
                 w.wake();
             }
-            if let Some(w) = s.write_waker.take() {
+            if let Some(w) = write {
                 w.wake();
             }
             log::debug!("WebTunnel: closed");
@@ -217,8 +225,7 @@ impl WasmWebTunnelStream {
             let state_inner = state_wait.clone();
 
             let check = Closure::wrap(Box::new(move || {
-                let s = unsafe { &*state_inner.get() };
-                match s.state {
+                match state_inner.with(|s| s.state) {
                     TunnelState::Connected => {
                         let _ = resolve.call0(&JsValue::NULL);
                     }
@@ -228,10 +235,8 @@ impl WasmWebTunnelStream {
                     _ => {
                         // Check again in 50ms
                         let window = web_sys::window().unwrap();
-                        let state_retry = state_inner.clone();
                         let resolve_retry = resolve.clone();
                         let timeout_cb = Closure::once(move || {
-                            let s = unsafe { &*state_retry.get() };
                             let _ = resolve_retry.call0(&JsValue::NULL);
                         });
                         let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
@@ -255,11 +260,11 @@ impl WasmWebTunnelStream {
         .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "WebTunnel connect timeout"))?;
 
         // Check final state
-        let s = unsafe { &*state.get() };
-        if s.state == TunnelState::Closed {
+        let (final_state, error) = state.with(|s| (s.state, s.error.clone()));
+        if final_state == TunnelState::Closed {
             return Err(io::Error::new(
                 io::ErrorKind::ConnectionRefused,
-                s.error.as_deref().unwrap_or("WebTunnel connection failed"),
+                error.as_deref().unwrap_or("WebTunnel connection failed"),
             ));
         }
 
@@ -275,20 +280,24 @@ impl WasmWebTunnelStream {
 
     /// Flush the send buffer through the WebSocket
     fn flush_send_buffer(&self) -> IoResult<()> {
-        let state = unsafe { &mut *self.state.get() };
+        let data: Vec<u8> = self.state.with(|state| {
+            if state.send_buffer.is_empty() {
+                return Ok(Vec::new());
+            }
 
-        if state.send_buffer.is_empty() {
-            return Ok(());
-        }
+            if state.state != TunnelState::Connected {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "WebTunnel not connected",
+                ));
+            }
 
-        if state.state != TunnelState::Connected {
-            return Err(io::Error::new(
-                io::ErrorKind::NotConnected,
-                "WebTunnel not connected",
-            ));
+            Ok(state.send_buffer.drain(..).collect())
+        })?;
+        if data.is_empty() {
+            return Ok(());
         }
 
-        let data: Vec<u8> = state.send_buffer.drain(..).collect();
         let array = js_sys::Uint8Array::from(&data[..]);
 
         self.ws
@@ -308,32 +317,32 @@ impl AsyncRead for WasmWebTunnelStream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<IoResult<usize>> {
-        let state = unsafe { &mut *self.state.get() };
-
-        if !state.recv_buffer.is_empty() {
-            let len = std::cmp::min(buf.len(), state.recv_buffer.len());
-            for i in 0..len {
-                buf[i] = state.recv_buffer.pop_front().unwrap();
+        self.state.with(|state| {
+            if !state.recv_buffer.is_empty() {
+                let len = std::cmp::min(buf.len(), state.recv_buffer.len());
+                for i in 0..len {
+                    buf[i] = state.recv_buffer.pop_front().unwrap();
+                }
+                return Poll::Ready(Ok(len));
             }
-            return Poll::Ready(Ok(len));
-        }
 
-        match state.state {
-            TunnelState::Closed | TunnelState::Closing => {
-                if let Some(ref e) = state.error {
-                    Poll::Ready(Err(io::Error::new(
-                        io::ErrorKind::ConnectionReset,
-                        e.clone(),
-                    )))
-                } else {
-                    Poll::Ready(Ok(0)) // EOF
+            match state.state {
+                TunnelState::Closed | TunnelState::Closing => {
+                    if let Some(ref e) = state.error {
+                        Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::ConnectionReset,
+                            e.clone(),
+                        )))
+                    } else {
+                        Poll::Ready(Ok(0)) // EOF
+                    }
+                }
+                _ => {
+                    state.read_waker = Some(cx.waker().clone());
+                    Poll::Pending
                 }
             }
-            _ => {
-                state.read_waker = Some(cx.waker().clone());
-                Poll::Pending
-            }
-        }
+        })
     }
 }
 
@@ -343,12 +352,9 @@ impl AsyncWrite for WasmWebTunnelStream {
         _cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<IoResult<usize>> {
-        let state = unsafe { &mut *self.state.get() };
-
-        match state.state {
+        let result = self.state.with(|state| match state.state {
             TunnelState::Connected => {
                 state.send_buffer.extend(buf.iter());
-                let _ = self.flush_send_buffer();
                 Poll::Ready(Ok(buf.len()))
             }
             TunnelState::Connecting => {
@@ -361,7 +367,13 @@ impl AsyncWrite for WasmWebTunnelStream {
                 io::ErrorKind::BrokenPipe,
                 state.error.as_deref().unwrap_or("WebTunnel closed"),
             ))),
+        });
+
+        // Flush outside the borrow: sending can re-enter the stream state
+        if let Poll::Ready(Ok(_)) = result {
+            let _ = self.flush_send_buffer();
         }
+        result
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
@@ -372,10 +384,9 @@ impl AsyncWrite for WasmWebTunnelStream {
     }
 
     fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
-        let state = unsafe { &mut *self.state.get() };
-        state.state = TunnelState::Closing;
+        self.state.with(|state| state.state = TunnelState::Closing);
         let _ = self.ws.close();
-        state.state = TunnelState::Closed;
+        self.state.with(|state| state.state = TunnelState::Closed);
         Poll::Ready(Ok(()))
     }
 }
@@ -384,10 +395,16 @@ impl Unpin for WasmWebTunnelStream {}
 
 impl Drop for WasmWebTunnelStream {
     fn drop(&mut self) {
-        let state = unsafe { &mut *self.state.get() };
-        if state.state == TunnelState::Connected || state.state == TunnelState::Connecting {
+        let open = self.state.with(|state| {
+            let open =
+                state.state == TunnelState::Connected || state.state == TunnelState::Connecting;
+            if open {
+                state.state = TunnelState::Closed;
+            }
+            open
+        });
+        if open {
             let _ = self.ws.close();
-            state.state = TunnelState::Closed;
         }
     }
 }
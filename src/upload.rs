@@ -0,0 +1,158 @@
+//! Streaming request bodies for `fetch_upload()`
+//!
+//! A JS `Blob` (or `File`) or `ReadableStream` is read one chunk at a time
+//! through a stream reader, so an upload never holds the whole body in
+//! memory. A `Blob` has a known size and is sent with `Content-Length`; a
+//! `ReadableStream` is not, so its chunks are sent with chunked transfer
+//! encoding. Splitting into DATA cells and waiting on flow control happen
+//! in [`TorStream::write_all`](crate::protocol::TorStream::write_all).
+
+use js_sys::{Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+use crate::error::{Result, TorError};
+
+/// Terminator of a chunked body
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/// The method `name` of a JS object, if it has one
+fn method(target: &JsValue, name: &str) -> Option<Function> {
+    Reflect::get(target, &JsValue::from_str(name))
+        .ok()?
+        .dyn_into::<Function>()
+        .ok()
+}
+
+fn read_error(e: JsValue) -> TorError {
+    TorError::Stream(format!("Failed to read upload body: {:?}", e))
+}
+
+/// A request body read from JavaScript a chunk at a time
+pub struct BodySource {
+    /// The `ReadableStreamDefaultReader` chunks come from
+    reader: JsValue,
+    /// Size in bytes, when known up front (a `Blob`)
+    length: Option<u64>,
+    /// Bytes read so far
+    read: u64,
+}
+
+impl BodySource {
+    /// Start reading `body`, a `Blob` or a `ReadableStream`
+    pub fn from_js(body: &JsValue) -> Result<Self> {
+        let (stream, length) = match body.dyn_ref::<web_sys::Blob>() {
+            Some(blob) => {
+                let stream = method(body, "stream")
+                    .ok_or_else(|| TorError::InvalidState("Blob.stream() is unavailable".into()))?
+                    .call0(body)
+                    .map_err(read_error)?;
+                (stream, Some(blob.size() as u64))
+            }
+            None => (body.clone(), None),
+        };
+        let reader = method(&stream, "getReader")
+            .ok_or_else(|| {
+                TorError::InvalidState("Upload body must be a Blob or ReadableStream".into())
+            })?
+            .call0(&stream)
+            .map_err(read_error)?;
+        Ok(Self {
+            reader,
+            length,
+            read: 0,
+        })
+    }
+
+    /// Body size, if known before reading it
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Bytes read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    /// The next chunk, or `None` at the end of the body
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let promise = method(&self.reader, "read")
+            .ok_or_else(|| TorError::InvalidState("Body reader has no read()".into()))?
+            .call0(&self.reader)
+            .map_err(read_error)?
+            .dyn_into::<Promise>()
+            .map_err(read_error)?;
+        let result = JsFuture::from(promise).await.map_err(read_error)?;
+
+        let done = Reflect::get(&result, &JsValue::from_str("done"))
+            .map(|d| d.is_truthy())
+            .unwrap_or(true);
+        if done {
+            return match self.length {
+                Some(length) if length != self.read => Err(TorError::Stream(format!(
+                    "Upload body ended after {} of {} bytes",
+                    self.read, length
+                ))),
+                _ => Ok(None),
+            };
+        }
+
+        let value = Reflect::get(&result, &JsValue::from_str("value")).map_err(read_error)?;
+        let chunk = if value.is_instance_of::<Uint8Array>() {
+            value.unchecked_into::<Uint8Array>().to_vec()
+        } else if value.is_instance_of::<js_sys::ArrayBuffer>() {
+            Uint8Array::new(&value).to_vec()
+        } else if let Some(text) = value.as_string() {
+            text.into_bytes()
+        } else {
+            return Err(TorError::InvalidState(
+                "ReadableStream chunks must be Uint8Array, ArrayBuffer or string".into(),
+            ));
+        };
+        self.read += chunk.len() as u64;
+        if self.length.is_some_and(|length| self.read > length) {
+            return Err(TorError::Stream(
+                "Upload body is longer than its size".into(),
+            ));
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Stop reading, releasing the JS stream
+    pub fn cancel(&self) {
+        if let Some(cancel) = method(&self.reader, "cancel") {
+            let _ = cancel.call0(&self.reader);
+        }
+    }
+}
+
+/// `chunk` as sent on the wire: as is, or framed for chunked encoding
+pub fn frame(chunk: &[u8], chunked: bool) -> Vec<u8> {
+    if !chunked {
+        return chunk.to_vec();
+    }
+    let mut framed = format!("{:x}\r\n", chunk.len()).into_bytes();
+    framed.extend_from_slice(chunk);
+    framed.extend_from_slice(b"\r\n");
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_framing() {
+        assert_eq!(frame(b"hello", false), b"hello");
+        assert_eq!(frame(&[b'a'; 26], true)[..4], *b"1a\r\n");
+        assert!(frame(b"hello", true).ends_with(b"hello\r\n"));
+
+        // Round-trips through the response parser's chunked decoding
+        let mut raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        raw.extend(frame(b"hello ", true));
+        raw.extend(frame(b"world", true));
+        raw.extend_from_slice(LAST_CHUNK);
+        let response = crate::response::HttpResponse::parse(&raw).unwrap();
+        assert_eq!(response.body, b"hello world");
+    }
+}
@@ -0,0 +1,67 @@
+//! Interop tests against a chutney-style local Tor network
+//!
+//! Builds real circuits through real tor relays, with the consensus and
+//! relay connections going through a local test bridge. Needs the
+//! `test-interop` feature and a running network; `tests/interop/run.sh`
+//! starts one and runs these tests.
+//!
+//! Configured at compile time:
+//! - `TOR_WASM_INTEROP_BRIDGE`: bridge URL (tests are skipped when unset)
+//! - `TOR_WASM_INTEROP_TARGET`: URL an exit of the test network can reach
+//!   (default `http://127.0.0.1:8000/`)
+//!
+//! Run with: wasm-pack test --headless --chrome --features test-interop
+
+#![cfg(all(target_arch = "wasm32", feature = "test-interop"))]
+
+use tor_wasm::interop::set_local_network;
+use tor_wasm::TorClient;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+const DEFAULT_TARGET: &str = "http://127.0.0.1:8000/";
+
+/// A bootstrapped client on the test network, or `None` to skip
+async fn local_client() -> Option<TorClient> {
+    let Some(bridge) = option_env!("TOR_WASM_INTEROP_BRIDGE") else {
+        web_sys::console::log_1(&"TOR_WASM_INTEROP_BRIDGE not set, skipping".into());
+        return None;
+    };
+    set_local_network(true);
+    let mut client = TorClient::new(Some(bridge.to_string()))
+        .await
+        .expect("client creation failed");
+    client.bootstrap().await.expect("bootstrap failed");
+    Some(client)
+}
+
+#[wasm_bindgen_test]
+async fn interop_build_circuit() {
+    let Some(mut client) = local_client().await else {
+        return;
+    };
+    let circuit_id = client.build_circuit().await.expect("circuit build failed");
+    assert!(circuit_id > 0);
+}
+
+/// Fetch options allowing the plain-HTTP test target
+fn insecure_http_options() -> JsValue {
+    js_sys::JSON::parse(r#"{ "allow_insecure_http": true }"#).expect("valid JSON")
+}
+
+#[wasm_bindgen_test]
+async fn interop_fetch() {
+    let Some(mut client) = local_client().await else {
+        return;
+    };
+    let target = option_env!("TOR_WASM_INTEROP_TARGET").unwrap_or(DEFAULT_TARGET);
+    let body = client
+        .fetch(target.to_string(), None, insecure_http_options())
+        .await
+        .expect("fetch through the test network failed");
+    assert!(!body.is_empty());
+}
+
+// Onion service tests go here once the client can reach onion services
@@ -0,0 +1,78 @@
+#!/bin/bash
+#
+# Interop tests against a chutney local Tor network
+#
+# Starts a chutney network, a test bridge serving its consensus, and a
+# local HTTP server for exits to reach, then runs tests/interop.rs in
+# headless Chrome with the `test-interop` feature.
+#
+# Requirements:
+#   - tor on PATH, Python 3, git
+#   - Node.js 18+ (bridge server)
+#   - wasm-pack and Chrome
+#
+# Usage:
+#   ./tests/interop/run.sh
+#
+# Environment:
+#   CHUTNEY_DIR      chutney checkout (cloned if missing; default /tmp/chutney)
+#   CHUTNEY_NETWORK  network to start (default networks/basic)
+#   TOR_DIRPORT      DirPort of a test authority (default 127.0.0.1:7000)
+#   BRIDGE_PORT      test bridge port (default 8080)
+#   TARGET_PORT      local HTTP server port (default 8000)
+#
+
+set -euo pipefail
+
+ROOT="$(cd "$(dirname "$0")/../.." && pwd)"
+CHUTNEY_DIR="${CHUTNEY_DIR:-/tmp/chutney}"
+CHUTNEY_NETWORK="${CHUTNEY_NETWORK:-networks/basic}"
+TOR_DIRPORT="${TOR_DIRPORT:-127.0.0.1:7000}"
+BRIDGE_PORT="${BRIDGE_PORT:-8080}"
+TARGET_PORT="${TARGET_PORT:-8000}"
+PIDS=()
+
+cleanup() {
+  for pid in "${PIDS[@]}"; do
+    kill "$pid" 2>/dev/null || true
+  done
+  (cd "$CHUTNEY_DIR" && ./chutney stop "$CHUTNEY_NETWORK") >/dev/null 2>&1 || true
+}
+trap cleanup EXIT
+
+if [ ! -d "$CHUTNEY_DIR" ]; then
+  git clone --depth 1 https://gitlab.torproject.org/tpo/core/chutney.git "$CHUTNEY_DIR"
+fi
+
+echo "🧅 Starting chutney network $CHUTNEY_NETWORK..."
+(
+  cd "$CHUTNEY_DIR"
+  ./chutney configure "$CHUTNEY_NETWORK"
+  ./chutney start "$CHUTNEY_NETWORK"
+  ./chutney wait_for_bootstrap "$CHUTNEY_NETWORK"
+)
+
+echo "🌐 Starting exit target on 127.0.0.1:$TARGET_PORT..."
+python3 -m http.server "$TARGET_PORT" --bind 127.0.0.1 --directory "$ROOT/tests/interop" >/dev/null 2>&1 &
+PIDS+=($!)
+
+echo "🌉 Starting test bridge on port $BRIDGE_PORT (DirPort $TOR_DIRPORT)..."
+(cd "$ROOT/bridge-server" && TOR_DIRPORT="$TOR_DIRPORT" PORT="$BRIDGE_PORT" node server-collector.js) &
+PIDS+=($!)
+
+for _ in $(seq 1 60); do
+  if curl -sf "http://127.0.0.1:$BRIDGE_PORT/tor/consensus" >/dev/null; then
+    break
+  fi
+  sleep 1
+done
+curl -sf "http://127.0.0.1:$BRIDGE_PORT/tor/consensus" >/dev/null || {
+  echo "❌ Test bridge has no consensus"
+  exit 1
+}
+
+echo "🧪 Running interop tests..."
+cd "$ROOT"
+TOR_WASM_INTEROP_BRIDGE="ws://127.0.0.1:$BRIDGE_PORT" \
+TOR_WASM_INTEROP_TARGET="http://127.0.0.1:$TARGET_PORT/" \
+  wasm-pack test --headless --chrome -- --features test-interop --test interop
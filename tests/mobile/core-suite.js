@@ -21,7 +21,7 @@
     'use strict';
 
     const BRIDGE_URL = window.__TEST_BRIDGE_URL || 'wss://bridge.example.com';
-    const TEST_FETCH_URL = 'http://check.torproject.org/api/ip';
+    const TEST_FETCH_URL = 'https://check.torproject.org/api/ip';
     const MEMORY_LIMIT_MB = 150;
     const CIRCUIT_TIMEOUT_MS = 30000;
     const FETCH_TIMEOUT_MS = 45000;
@@ -7,7 +7,9 @@
 use futures::io::{AsyncReadExt, AsyncWriteExt};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tor_wasm::network::{ConnectionManager, NetworkConfig, WasmTcpProvider, WasmTlsConnector};
+use tor_wasm::network::{
+    BridgeCapabilities, ConnectionManager, NetworkConfig, WasmTcpProvider, WasmTlsConnector,
+};
 use tor_wasm::WasmTcpStream;
 use wasm_bindgen_test::*;
 
@@ -36,7 +38,7 @@ async fn test_network_config() {
     assert_eq!(config.bridge_url, "ws://localhost:8080");
 
     let addr: SocketAddr = "1.2.3.4:9001".parse().unwrap();
-    let url = config.build_url(&addr);
+    let (url, _) = config.build_url(&addr, &BridgeCapabilities::default());
     assert!(url.contains("1.2.3.4:9001"));
 }
 
@@ -0,0 +1,345 @@
+//! Crypto and cell path benchmarks
+//!
+//! Times the hot paths of a circuit in the browser: the ntor handshake
+//! (client side, against a relay computed in-test), per-cell onion crypto
+//! through `Circuit` (three AES-128-CTR layers plus the SHA-1 digest),
+//! consensus parsing, and a fetch pipeline from request cells to a parsed
+//! `HttpResponse`.
+//!
+//! There is no mock relay in the tree, so the exit's cells are prepared
+//! locally before the clock starts; the benchmarks measure everything on
+//! the client's side except the network and the TLS layer.
+//!
+//! Each benchmark logs one result, and the suite ends with a single
+//! `BENCH_JSON {...}` line for regression tracking. Run with:
+//!
+//! ```text
+//! wasm-pack test --release --headless --chrome --test wasm_bench
+//! ```
+
+#![cfg(target_arch = "wasm32")]
+
+use aes::Aes128;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use futures::executor::block_on;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use tor_wasm::protocol::{
+    Cell, CellCommand, Circuit, CircuitKeys, ConsensusParser, NtorHandshake, Relay, RelayCell,
+    RelayCommand,
+};
+use tor_wasm::HttpResponse;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::*;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+/// Bumped when result fields change meaning
+const BENCH_FORMAT: u32 = 1;
+
+/// Relays in the synthetic consensus (about the size of the live one)
+const CONSENSUS_RELAYS: usize = 7_000;
+
+/// Response body size for the fetch pipeline
+const FETCH_BYTES: usize = 256 * 1024;
+
+/// One benchmark result
+#[derive(Debug, Serialize)]
+struct BenchResult {
+    name: &'static str,
+    iterations: u32,
+    total_ms: f64,
+    per_iter_us: f64,
+    /// Payload throughput, for benchmarks that move bytes
+    mb_per_s: Option<f64>,
+}
+
+/// High-resolution clock: `performance.now()` in both windows and workers
+fn now() -> f64 {
+    let performance = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("performance"))
+        .expect("performance is available");
+    let now = js_sys::Reflect::get(&performance, &JsValue::from_str("now"))
+        .expect("performance.now is available");
+    js_sys::Function::from(now)
+        .call0(&performance)
+        .ok()
+        .and_then(|v| v.as_f64())
+        .unwrap_or_else(js_sys::Date::now)
+}
+
+/// Run `f` a few times to warm up, then `iterations` times under the clock
+fn bench(
+    name: &'static str,
+    iterations: u32,
+    bytes_per_iter: Option<usize>,
+    mut f: impl FnMut(),
+) -> BenchResult {
+    for _ in 0..iterations.div_ceil(10) {
+        f();
+    }
+    let start = now();
+    for _ in 0..iterations {
+        f();
+    }
+    let total_ms = now() - start;
+    let result = BenchResult {
+        name,
+        iterations,
+        total_ms,
+        per_iter_us: total_ms * 1000.0 / iterations as f64,
+        mb_per_s: bytes_per_iter.filter(|_| total_ms > 0.0).map(|bytes| {
+            (bytes as f64 * iterations as f64) / (1024.0 * 1024.0) / (total_ms / 1000.0)
+        }),
+    };
+    console_log!(
+        "{:<24} {:>6} iters {:>10.1} us/iter",
+        result.name,
+        result.iterations,
+        result.per_iter_us
+    );
+    result
+}
+
+/// Relay side of ntor: returns (Y, AUTH) for the client's X
+fn relay_reply(
+    identity: &[u8; 20],
+    onion_secret: &StaticSecret,
+    client_public: &PublicKey,
+) -> (PublicKey, [u8; 32]) {
+    const PROTOID: &[u8] = b"ntor-curve25519-sha256-1";
+    type HmacSha256 = Hmac<Sha256>;
+
+    let onion_public = PublicKey::from(onion_secret);
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    // secret_input = EXP(X,y) | EXP(X,b) | ID | B | X | Y | PROTOID
+    let mut secret_input = Vec::new();
+    secret_input.extend_from_slice(ephemeral.diffie_hellman(client_public).as_bytes());
+    secret_input.extend_from_slice(onion_secret.diffie_hellman(client_public).as_bytes());
+    secret_input.extend_from_slice(identity);
+    secret_input.extend_from_slice(onion_public.as_bytes());
+    secret_input.extend_from_slice(client_public.as_bytes());
+    secret_input.extend_from_slice(ephemeral_public.as_bytes());
+    secret_input.extend_from_slice(PROTOID);
+
+    let mut verify = HmacSha256::new_from_slice(b"ntor-curve25519-sha256-1:verify").unwrap();
+    verify.update(&secret_input);
+    let verify = verify.finalize().into_bytes();
+
+    // auth_input = verify | ID | B | Y | X | PROTOID | "Server"
+    let mut auth = HmacSha256::new_from_slice(b"ntor-curve25519-sha256-1:mac").unwrap();
+    auth.update(&verify);
+    auth.update(identity);
+    auth.update(onion_public.as_bytes());
+    auth.update(ephemeral_public.as_bytes());
+    auth.update(client_public.as_bytes());
+    auth.update(PROTOID);
+    auth.update(b"Server");
+
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&auth.finalize().into_bytes());
+    (ephemeral_public, out)
+}
+
+fn circuit_keys() -> Vec<CircuitKeys> {
+    (1u8..=3)
+        .map(|hop| CircuitKeys::derive_from_secret(&[hop; 32]).unwrap())
+        .collect()
+}
+
+/// A three-hop circuit with `keys`, assembled without a network
+fn circuit(keys: &[CircuitKeys]) -> Circuit {
+    let mut circuit = Circuit::new(7, vec![Relay::default()], keys[0].clone());
+    for hop in &keys[1..] {
+        circuit.add_hop(Relay::default(), hop.clone());
+    }
+    circuit
+}
+
+/// The relays' side of the backward direction: the exit's running digest
+/// and every hop's layer
+struct Relays {
+    ciphers: Vec<Aes128Ctr>,
+    digest: Sha1,
+}
+
+impl Relays {
+    fn new(keys: &[CircuitKeys]) -> Self {
+        Self {
+            ciphers: keys
+                .iter()
+                .map(|k| Aes128Ctr::new((&k.backward_key).into(), (&k.backward_iv).into()))
+                .collect(),
+            digest: Sha1::new_with_prefix(keys.last().unwrap().backward_digest),
+        }
+    }
+
+    fn seal(&mut self, payload: &mut [u8]) {
+        self.digest.update(&*payload);
+        let digest = self.digest.clone().finalize();
+        payload[5..9].copy_from_slice(&digest[..4]);
+        for cipher in self.ciphers.iter_mut().rev() {
+            cipher.apply_keystream(payload);
+        }
+    }
+}
+
+/// `count` DATA cells as the exit would send them
+fn backward_cells(keys: &[CircuitKeys], data: &[u8], count: usize) -> Vec<Vec<u8>> {
+    let mut relays = Relays::new(keys);
+    data.chunks(RelayCell::MAX_DATA_SIZE)
+        .cycle()
+        .take(count)
+        .map(|chunk| {
+            let mut payload = RelayCell::new(RelayCommand::Data, 1, chunk.to_vec())
+                .to_bytes()
+                .unwrap();
+            relays.seal(&mut payload);
+            Cell::new(7, CellCommand::Relay, payload)
+                .to_bytes()
+                .unwrap()
+        })
+        .collect()
+}
+
+fn synthetic_consensus(relays: usize) -> String {
+    let mut text = String::from(
+        "network-status-version 3\n\
+         valid-after 2026-01-01 00:00:00\n\
+         fresh-until 2026-01-01 01:00:00\n\
+         valid-until 2026-01-01 03:00:00\n",
+    );
+    for i in 0..relays {
+        text.push_str(&format!(
+            "r relay{i} AAAAAAAAAAAAAAAAAAAAAAAAAA{:04x} 2026-01-01 10.{}.{}.{} 9001 0\n\
+             s Fast Guard Running Stable Valid{}\n\
+             v Tor 0.4.8.10\n\
+             w Bandwidth={}\n\
+             p accept 80,443\n",
+            i,
+            (i >> 16) & 0xff,
+            (i >> 8) & 0xff,
+            i & 0xff,
+            if i % 4 == 0 { " Exit" } else { "" },
+            1000 + i * 7,
+        ));
+    }
+    text
+}
+
+#[wasm_bindgen_test]
+fn benchmarks() {
+    let mut results = Vec::new();
+
+    // ntor: client keypair, CREATE2 body, AUTH check and key expansion
+    let identity = [7u8; 20];
+    let onion_secret = StaticSecret::random_from_rng(OsRng);
+    let onion_public = PublicKey::from(&onion_secret);
+    let replies: Vec<_> = (0..64)
+        .map(|_| {
+            let handshake = NtorHandshake::new();
+            let reply = relay_reply(&identity, &onion_secret, handshake.client_public_key());
+            (handshake, reply)
+        })
+        .collect();
+    let mut replies = replies.into_iter();
+    results.push(bench("ntor_handshake", 50, None, || {
+        let (handshake, (y, auth)) = replies.next().unwrap();
+        let _ = NtorHandshake::create_handshake_data(
+            handshake.client_public_key(),
+            &identity,
+            &onion_public,
+        );
+        let (seed, _) = handshake
+            .complete(&identity, &onion_public, &y, &auth)
+            .unwrap();
+        tor_wasm::protocol::derive_circuit_keys(&seed).unwrap();
+    }));
+
+    // Per cell: running SHA-1 digest and three AES-128-CTR layers
+    let keys = circuit_keys();
+    let data = vec![0x42; RelayCell::MAX_DATA_SIZE];
+    let relay = RelayCell::new(RelayCommand::Data, 1, data.clone());
+    let mut client = circuit(&keys);
+    results.push(bench(
+        "cell_encrypt_forward",
+        5_000,
+        Some(Cell::PAYLOAD_SIZE),
+        || {
+            block_on(client.seal_relay_cell(&relay)).unwrap();
+        },
+    ));
+    let inbound = backward_cells(&keys, &data, 5_500);
+    let mut inbound = inbound.into_iter();
+    let mut client = circuit(&keys);
+    results.push(bench(
+        "cell_decrypt_backward",
+        5_000,
+        Some(Cell::PAYLOAD_SIZE),
+        || {
+            let cell = Cell::from_bytes(&inbound.next().unwrap()).unwrap();
+            let (hop, _) = block_on(client.open_relay_cell(&cell)).unwrap();
+            assert_eq!(hop, 2);
+        },
+    ));
+
+    // Consensus parse
+    let consensus = synthetic_consensus(CONSENSUS_RELAYS);
+    results.push(bench("consensus_parse", 5, Some(consensus.len()), || {
+        let parsed = ConsensusParser::parse(consensus.as_bytes()).unwrap();
+        assert_eq!(parsed.relays.len(), CONSENSUS_RELAYS);
+    }));
+
+    // Fetch: GET in a BEGIN + DATA cell, response as DATA cells from the
+    // exit, opened by the circuit and parsed into an `HttpResponse`
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        FETCH_BYTES
+    )
+    .into_bytes();
+    response.resize(response.len() + FETCH_BYTES, 0x61);
+    let response_cells = backward_cells(
+        &keys,
+        &response,
+        response.len().div_ceil(RelayCell::MAX_DATA_SIZE),
+    );
+    results.push(bench("fetch_pipeline", 20, Some(FETCH_BYTES), || {
+        let mut client = circuit(&keys);
+        let begin = RelayCell::new(RelayCommand::Begin, 1, b"example.com:80\0".to_vec());
+        let get = RelayCell::new(
+            RelayCommand::Data,
+            1,
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n".to_vec(),
+        );
+        for relay in [begin, get] {
+            block_on(client.seal_relay_cell(&relay))
+                .unwrap()
+                .to_bytes()
+                .unwrap();
+        }
+
+        let mut raw = Vec::with_capacity(response.len());
+        for bytes in &response_cells {
+            let cell = Cell::from_bytes(bytes).unwrap();
+            let (_, relay) = block_on(client.open_relay_cell(&cell)).unwrap();
+            raw.extend_from_slice(&relay.data);
+        }
+        let parsed = HttpResponse::parse(&raw).unwrap();
+        assert_eq!(parsed.status, 200);
+        assert_eq!(parsed.body.len(), FETCH_BYTES);
+    }));
+
+    let report = serde_json::json!({
+        "format": BENCH_FORMAT,
+        "version": env!("CARGO_PKG_VERSION"),
+        "results": results,
+    });
+    console_log!("BENCH_JSON {}", report);
+}
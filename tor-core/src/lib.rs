@@ -23,7 +23,7 @@ pub mod error;
 // Re-export everything for easy access
 pub use error::TorError;
 pub use crypto::NtorHandshake;
-pub use protocol::{Cell, CellCommand, RelayCommand, create_versions_cell, create_netinfo_cell, create_create2_cell};
+pub use protocol::{Cell, CellCommand, LinkVersion, RelayCommand, create_versions_cell, create_netinfo_cell, create_create2_cell};
 
 // ============================================================================
 // FFI exports for embedded WASM runtimes
@@ -173,7 +173,9 @@ pub extern "C" fn tor_create_create2_cell(
     unsafe {
         let hs_slice = core::slice::from_raw_parts(handshake_data, 84);
         let cell = create_create2_cell(circuit_id, hs_slice);
-        let bytes = cell.to_bytes();
+        let Ok(bytes) = cell.to_bytes() else {
+            return 0;
+        };
         
         let out_slice = core::slice::from_raw_parts_mut(out, bytes.len());
         out_slice.copy_from_slice(&bytes);
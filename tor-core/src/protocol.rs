@@ -19,9 +19,24 @@ pub enum CellCommand {
     RelayEarly = 9,
     Create2 = 10,
     Created2 = 11,
+    Vpadding = 128,
     Certs = 129,
     AuthChallenge = 130,
     Authenticate = 131,
+    Authorize = 132,
+}
+
+impl CellCommand {
+    /// Whether cells with this command carry a 2-byte length and a
+    /// payload of that length, rather than a fixed 509-byte payload
+    pub fn is_variable_length(self) -> bool {
+        is_variable_length(self as u8)
+    }
+}
+
+/// VERSIONS (7) and every command from 128 up are variable-length
+fn is_variable_length(command: u8) -> bool {
+    command == CellCommand::Versions as u8 || command >= 128
 }
 
 impl TryFrom<u8> for CellCommand {
@@ -41,9 +56,11 @@ impl TryFrom<u8> for CellCommand {
             9 => Ok(CellCommand::RelayEarly),
             10 => Ok(CellCommand::Create2),
             11 => Ok(CellCommand::Created2),
+            128 => Ok(CellCommand::Vpadding),
             129 => Ok(CellCommand::Certs),
             130 => Ok(CellCommand::AuthChallenge),
             131 => Ok(CellCommand::Authenticate),
+            132 => Ok(CellCommand::Authorize),
             _ => Err(TorError::Protocol(alloc::format!("Unknown command: {}", value))),
         }
     }
@@ -75,7 +92,64 @@ pub const CELL_SIZE: usize = 514;
 pub const CELL_HEADER_SIZE: usize = 5; // 4 bytes circuit ID + 1 byte command
 pub const CELL_PAYLOAD_SIZE: usize = 509;
 
+/// Largest variable-length cell payload
+pub const MAX_VAR_PAYLOAD_SIZE: usize = u16::MAX as usize;
+
+/// Negotiated link protocol version, which fixes how cells are framed
+///
+/// Versions 1-3 use 2-byte circuit IDs (512-byte fixed cells); 4 and later
+/// use 4-byte circuit IDs (514-byte fixed cells). VERSIONS cells are always
+/// framed with a 2-byte circuit ID, since they are sent before a version
+/// is agreed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LinkVersion(pub u16);
+
+impl LinkVersion {
+    /// Framing used before VERSIONS has been exchanged
+    pub const INITIAL: LinkVersion = LinkVersion(3);
+
+    /// First version with 4-byte circuit IDs
+    pub const V4: LinkVersion = LinkVersion(4);
+
+    /// Versions we speak, lowest first
+    pub const SUPPORTED: [u16; 2] = [4, 5];
+
+    /// Circuit ID width in bytes
+    pub fn circ_id_len(self) -> usize {
+        if self.0 < 4 {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// Size of a fixed-length cell
+    pub fn cell_size(self) -> usize {
+        self.circ_id_len() + 1 + CELL_PAYLOAD_SIZE
+    }
+
+    /// Highest version in both our list and the peer's VERSIONS payload
+    pub fn negotiate(ours: &[u16], versions_payload: &[u8]) -> Option<LinkVersion> {
+        versions_payload
+            .chunks_exact(2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+            .filter(|v| ours.contains(v))
+            .max()
+            .map(LinkVersion)
+    }
+
+    /// Framing for a cell with `command` on a link at this version
+    fn framing_for(self, command: u8) -> LinkVersion {
+        if command == CellCommand::Versions as u8 {
+            LinkVersion::INITIAL
+        } else {
+            self
+        }
+    }
+}
+
 /// A Tor cell
+#[derive(Debug, Clone, PartialEq)]
 pub struct Cell {
     pub circuit_id: u32,
     pub command: CellCommand,
@@ -91,61 +165,137 @@ impl Cell {
             payload,
         }
     }
-    
-    /// Serialize cell to bytes (514 bytes for fixed-size cells)
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(CELL_SIZE);
-        
-        // Circuit ID (4 bytes, big-endian)
-        bytes.extend_from_slice(&self.circuit_id.to_be_bytes());
-        
-        // Command (1 byte)
-        bytes.push(self.command as u8);
-        
-        // Payload (509 bytes, zero-padded)
-        bytes.extend_from_slice(&self.payload);
-        bytes.resize(CELL_SIZE, 0);
-        
-        bytes
+
+    /// Serialize cell to bytes for link protocol v4+ (514 bytes for
+    /// fixed-size cells; longer payloads are truncated)
+    ///
+    /// Fails if a variable-length payload exceeds [`MAX_VAR_PAYLOAD_SIZE`].
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut cell = self.clone();
+        if !self.command.is_variable_length() {
+            cell.payload.truncate(CELL_PAYLOAD_SIZE);
+        }
+        cell.encode(LinkVersion::V4)
     }
-    
-    /// Parse cell from bytes
+
+    /// Parse a cell for link protocol v4+
+    ///
+    /// A fixed-length cell must be complete: fewer than 514 bytes is an
+    /// error rather than a cell with a short payload.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < CELL_HEADER_SIZE {
-            return Err(TorError::Protocol("Cell too short".into()));
+        match Self::decode(bytes, LinkVersion::V4)? {
+            Some((cell, _)) => Ok(cell),
+            None => Err(TorError::Protocol("Cell too short".into())),
         }
-        
-        let circuit_id = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-        let command = CellCommand::try_from(bytes[4])?;
-        
-        let payload = if bytes.len() > CELL_HEADER_SIZE {
-            bytes[CELL_HEADER_SIZE..].to_vec()
+    }
+
+    /// Serialize the cell for a link at `version`
+    ///
+    /// Fixed-length payloads are zero-padded to 509 bytes. Fails if the
+    /// circuit ID doesn't fit the version's width or the payload is too long.
+    pub fn encode(&self, version: LinkVersion) -> Result<Vec<u8>> {
+        let command = self.command as u8;
+        let version = version.framing_for(command);
+        let variable = is_variable_length(command);
+
+        let max_payload = if variable {
+            MAX_VAR_PAYLOAD_SIZE
         } else {
-            Vec::new()
+            CELL_PAYLOAD_SIZE
         };
-        
-        Ok(Self {
-            circuit_id,
-            command,
-            payload,
-        })
+        if self.payload.len() > max_payload {
+            return Err(TorError::Protocol(alloc::format!(
+                "{:?} payload of {} bytes exceeds {}",
+                self.command,
+                self.payload.len(),
+                max_payload
+            )));
+        }
+
+        let mut bytes = Vec::with_capacity(version.cell_size());
+        match version.circ_id_len() {
+            2 => {
+                let id = u16::try_from(self.circuit_id).map_err(|_| {
+                    TorError::Protocol(alloc::format!(
+                        "Circuit ID {} too wide for link version {}",
+                        self.circuit_id,
+                        version.0
+                    ))
+                })?;
+                bytes.extend_from_slice(&id.to_be_bytes());
+            }
+            _ => bytes.extend_from_slice(&self.circuit_id.to_be_bytes()),
+        }
+        bytes.push(command);
+
+        if variable {
+            bytes.extend_from_slice(&(self.payload.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&self.payload);
+        } else {
+            bytes.extend_from_slice(&self.payload);
+            bytes.resize(version.cell_size(), 0);
+        }
+        Ok(bytes)
+    }
+
+    /// Parse the first cell in `bytes` on a link at `version`
+    ///
+    /// Returns the cell and the number of bytes it took, or `None` if
+    /// `bytes` doesn't hold a complete cell yet. Until VERSIONS has been
+    /// exchanged, pass [`LinkVersion::INITIAL`]: the peer's VERSIONS cell
+    /// can't be told apart from a 4-byte-ID cell by its bytes alone.
+    pub fn decode(bytes: &[u8], version: LinkVersion) -> Result<Option<(Self, usize)>> {
+        let id_len = version.circ_id_len();
+        let Some(&command) = bytes.get(id_len) else {
+            return Ok(None);
+        };
+
+        let circuit_id = match id_len {
+            2 => u16::from_be_bytes([bytes[0], bytes[1]]) as u32,
+            _ => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        };
+        let command = CellCommand::try_from(command)?;
+
+        let (start, len) = if command.is_variable_length() {
+            let Some(len) = bytes.get(id_len + 1..id_len + 3) else {
+                return Ok(None);
+            };
+            (id_len + 3, u16::from_be_bytes([len[0], len[1]]) as usize)
+        } else {
+            (id_len + 1, CELL_PAYLOAD_SIZE)
+        };
+        let Some(payload) = bytes.get(start..start + len) else {
+            return Ok(None);
+        };
+
+        Ok(Some((
+            Self {
+                circuit_id,
+                command,
+                payload: payload.to_vec(),
+            },
+            start + len,
+        )))
     }
 }
 
 /// Create a VERSIONS cell
+///
+/// It is always framed with a 2-byte circuit ID (tor-spec §3), so the
+/// cell is 5 bytes plus 2 per version, whatever is negotiated afterwards.
 pub fn create_versions_cell(versions: &[u16]) -> Vec<u8> {
-    let mut cell = Vec::with_capacity(7 + versions.len() * 2);
-    
+    let mut cell = Vec::with_capacity(5 + versions.len() * 2);
+
     // Variable-length cell header
-    cell.extend_from_slice(&0u32.to_be_bytes()); // Circuit ID = 0
+    cell.extend_from_slice(&0u16.to_be_bytes()); // Circuit ID = 0
     cell.push(CellCommand::Versions as u8);
     cell.extend_from_slice(&((versions.len() * 2) as u16).to_be_bytes()); // Length
-    
+
     // Payload: version numbers
     for v in versions {
         cell.extend_from_slice(&v.to_be_bytes());
     }
-    
+
     cell
 }
 
@@ -243,3 +393,120 @@ impl From<u8> for DestroyReason {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const ALL_COMMANDS: [u8; 17] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 128, 129, 130, 131, 132];
+
+    #[test]
+    fn test_roundtrip_every_command_and_version() {
+        for version in 1..=5 {
+            let version = LinkVersion(version);
+            for &command in &ALL_COMMANDS {
+                let command = CellCommand::try_from(command).unwrap();
+                // VERSIONS is framed, and so must be read, as before negotiation
+                let framing = version.framing_for(command as u8);
+                let max_id = if framing.circ_id_len() == 2 {
+                    u16::MAX as u32
+                } else {
+                    u32::MAX
+                };
+                let payload_lens: &[usize] = if command.is_variable_length() {
+                    &[0, 1, 509, 1000, MAX_VAR_PAYLOAD_SIZE]
+                } else {
+                    &[0, 1, CELL_PAYLOAD_SIZE]
+                };
+
+                for circuit_id in [0, 1, max_id] {
+                    for &len in payload_lens {
+                        let cell = Cell::new(circuit_id, command, vec![0xa5; len]);
+                        let mut wire = cell.encode(version).unwrap();
+                        if !command.is_variable_length() {
+                            assert_eq!(wire.len(), framing.cell_size());
+                        }
+
+                        // Every proper prefix is incomplete, not an error
+                        for cut in [0, 1, 2, 3, wire.len() - 1] {
+                            assert_eq!(Cell::decode(&wire[..cut], framing).unwrap(), None);
+                        }
+
+                        // Trailing bytes belong to the next cell
+                        let cell_len = wire.len();
+                        wire.extend_from_slice(&[9, 9, 9]);
+                        let (decoded, used) = Cell::decode(&wire, framing).unwrap().unwrap();
+                        assert_eq!(used, cell_len);
+                        assert_eq!(decoded.circuit_id, circuit_id);
+                        assert_eq!(decoded.command, command);
+                        if command.is_variable_length() {
+                            assert_eq!(decoded.payload, cell.payload);
+                        } else {
+                            assert_eq!(&decoded.payload[..len], &cell.payload[..]);
+                            assert!(decoded.payload[len..].iter().all(|&b| b == 0));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_framing_limits() {
+        // 2-byte circuit IDs reject wider values; VERSIONS always uses them
+        let wide = Cell::new(0x1_0000, CellCommand::Relay, Vec::new());
+        assert!(wide.encode(LinkVersion(3)).is_err());
+        assert_eq!(wide.encode(LinkVersion::V4).unwrap().len(), CELL_SIZE);
+        let versions = Cell::new(0, CellCommand::Versions, vec![0, 4, 0, 5]);
+        assert_eq!(versions.encode(LinkVersion(5)).unwrap(), create_versions_cell(&[4, 5]));
+
+        let long = Cell::new(1, CellCommand::Relay, vec![0; CELL_PAYLOAD_SIZE + 1]);
+        assert!(long.encode(LinkVersion::V4).is_err());
+        assert_eq!(long.to_bytes().unwrap().len(), CELL_SIZE);
+
+        assert!(Cell::decode(&[0, 0, 0, 1, 200], LinkVersion::V4).is_err());
+        assert!(Cell::from_bytes(&[0, 0, 0, 1, 3]).is_err());
+    }
+
+    #[test]
+    fn test_to_bytes_reports_encoding_errors() {
+        let cell = Cell::new(7, CellCommand::Create2, vec![1; 84]);
+        let bytes = cell.to_bytes().unwrap();
+        assert_eq!(bytes.len(), CELL_SIZE);
+        assert_eq!(Cell::from_bytes(&bytes).unwrap().payload[..84], [1; 84]);
+
+        // Too long for the 2-byte length field: an error, not an empty cell
+        let huge = Cell::new(0, CellCommand::Certs, vec![0; MAX_VAR_PAYLOAD_SIZE + 1]);
+        assert!(huge.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_needs_a_whole_fixed_cell() {
+        let bytes = Cell::new(1, CellCommand::Destroy, vec![3]).to_bytes().unwrap();
+        assert!(Cell::from_bytes(&bytes).is_ok());
+        // A truncated cell used to parse with a short payload
+        assert!(Cell::from_bytes(&bytes[..CELL_HEADER_SIZE + 1]).is_err());
+        assert!(Cell::from_bytes(&bytes[..CELL_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_versions_cell_has_two_byte_circuit_id() {
+        // Used to carry a 4-byte circuit ID, which relays misread
+        assert_eq!(create_versions_cell(&[4, 5]), [0, 0, 7, 0, 4, 0, 4, 0, 5]);
+        let (cell, used) = Cell::decode(&create_versions_cell(&[4]), LinkVersion::INITIAL)
+            .unwrap()
+            .unwrap();
+        assert_eq!((cell.command, cell.payload, used), (CellCommand::Versions, vec![0, 4], 7));
+    }
+
+    #[test]
+    fn test_version_negotiation() {
+        let theirs = [0, 3, 0, 4, 0, 5, 0, 6];
+        assert_eq!(LinkVersion::negotiate(&LinkVersion::SUPPORTED, &theirs), Some(LinkVersion(5)));
+        assert_eq!(LinkVersion::negotiate(&[4], &theirs), Some(LinkVersion::V4));
+        assert_eq!(LinkVersion::negotiate(&[4, 5], &[0, 3]), None);
+        assert_eq!(LinkVersion(3).cell_size(), 512);
+        assert_eq!(LinkVersion(5).cell_size(), CELL_SIZE);
+    }
+}